@@ -395,6 +395,27 @@ define_callback_attributes!(
         as a prefix (\"sample_do_foo\" rather than \"do_foo\").
     "
     (monitor, panda_cb_type_PANDA_CB_MONITOR, (monitor: &mut Monitor, cmd: *const u8)),
+    "Called when a QMP command is received, allowing plugins to implement
+    their own QMP commands.
+
+    Callback ID: PANDA_CB_QMP
+
+       Arguments:
+        char *command:  the name of the command
+        char *args:     the arguments of the command, as a JSON string
+        char **result:  where to store the result of the command, as a JSON
+                        string allocated with malloc
+
+       Helper call location: monitor.c
+
+       Return value:
+        true if the plugin handled the command, false otherwise
+
+       Notes:
+        Every loaded plugin is given the chance to handle each command, so
+        plugin commands should be uniquely named.
+    "
+    (qmp, panda_cb_type_PANDA_CB_QMP, (command: *mut std::os::raw::c_char, args: *mut std::os::raw::c_char, result: *mut *mut std::os::raw::c_char) -> bool),
     "Called inside of cpu_restore_state(), when there is a CPU
         fault/exception.
 
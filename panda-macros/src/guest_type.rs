@@ -5,24 +5,40 @@ use proc_macro2::TokenStream;
 use quote::quote;
 
 #[derive(FromDeriveInput)]
+#[darling(attributes(guest))]
 pub(crate) struct GuestTypeInput {
     ident: syn::Ident,
     data: Data<GuestTypeVariant, GuestTypeField>,
 
     #[darling(default)]
     guest_repr: String,
+
+    /// The integer type of the tag selecting the variant of a tagged union
+    #[darling(default)]
+    tag: Option<syn::Path>,
+
+    /// Whether the tag precedes the payload, rather than being its first field
+    #[darling(default)]
+    prefix: bool,
 }
 
-#[allow(dead_code)]
 #[derive(FromVariant)]
+#[darling(attributes(guest))]
 struct GuestTypeVariant {
     ident: syn::Ident,
-    discriminant: Option<syn::Expr>,
     fields: darling::ast::Fields<GuestTypeVariantField>,
+
+    #[darling(default)]
+    tag: Option<syn::Lit>,
+
+    #[darling(default)]
+    other: bool,
 }
 
 #[derive(FromField)]
-struct GuestTypeVariantField {}
+struct GuestTypeVariantField {
+    ty: syn::Type,
+}
 
 #[derive(FromField)]
 struct GuestTypeField {
@@ -77,6 +93,7 @@ fn todo() -> TokenStream {
     quote! { todo!() }
 }
 
+mod enum_impl;
 mod struct_impl;
 
 impl GuestTypeInput {
@@ -85,6 +102,8 @@ impl GuestTypeInput {
             ident,
             data,
             guest_repr,
+            tag,
+            prefix,
         } = self;
 
         let ty = ident;
@@ -94,7 +113,14 @@ impl GuestTypeInput {
             panic!("guest_repr = \"{}\" is only allowed on enums", guest_repr);
         }
 
+        if data.is_struct() && (tag.is_some() || prefix) {
+            panic!("tag and prefix are only allowed on enums");
+        }
+
         let impls = match data {
+            Data::Enum(variants) if tag.is_some() => {
+                enum_impl::tagged_union(&variants, tag.as_ref().unwrap(), prefix)
+            }
             Data::Enum(_en) => Impls {
                 guest_layout: todo(),
                 read_from_guest: todo(),
@@ -103,6 +129,10 @@ impl GuestTypeInput {
                 write_to_guest_phys: todo(),
             },
             Data::Struct(st) => {
+                if let Some(error) = struct_impl::misplaced_dynamic_field(&st.fields) {
+                    return error;
+                }
+
                 let guest_layout =
                     struct_impl::struct_layout(st.fields.iter().map(|field| &field.ty));
 
@@ -134,7 +164,7 @@ impl GuestTypeInput {
         let write_ret = quote!( Result<(), ::panda::GuestWriteFail> );
 
         quote! {
-            const _: fn() = || {
+            const _: () = {
                 use panda::prelude::*;
 
                 impl ::panda::GuestType for #ty {
@@ -0,0 +1,214 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use super::{GuestTypeVariant, Impls};
+
+/// How a variant of a tagged union is selected and what it holds
+enum VariantKind<'a> {
+    /// `#[guest(tag = N)] Variant(Payload)`
+    Payload(&'a syn::Lit, &'a syn::Type),
+
+    /// `#[guest(tag = N)] Variant`
+    Unit(&'a syn::Lit),
+
+    /// `#[guest(other)] Variant(Tag)`, for any unrecognized tag
+    Other,
+}
+
+struct Variant<'a> {
+    ident: &'a syn::Ident,
+    kind: VariantKind<'a>,
+}
+
+fn variant_kind(variant: &GuestTypeVariant) -> VariantKind<'_> {
+    let fields = &variant.fields.fields;
+
+    match (&variant.tag, variant.other) {
+        (Some(_), true) => panic!(
+            "variant {} can't have both a tag and #[guest(other)]",
+            variant.ident
+        ),
+        (None, false) => panic!(
+            "variant {} of a tagged union needs #[guest(tag = ...)] or #[guest(other)]",
+            variant.ident
+        ),
+        (None, true) if fields.len() == 1 && variant.fields.style.is_tuple() => VariantKind::Other,
+        (None, true) => panic!(
+            "#[guest(other)] variant {} must hold a single field of the tag type",
+            variant.ident
+        ),
+        (Some(tag), false) if fields.is_empty() => VariantKind::Unit(tag),
+        (Some(tag), false) if fields.len() == 1 && variant.fields.style.is_tuple() => {
+            VariantKind::Payload(tag, &fields[0].ty)
+        }
+        (Some(_), false) => panic!(
+            "variant {} of a tagged union must hold a single unnamed payload field",
+            variant.ident
+        ),
+    }
+}
+
+/// Generate the `GuestType` methods for a tagged union, an enum where the value of an
+/// integer tag selects which payload layout is in use
+pub(super) fn tagged_union(
+    variants: &[GuestTypeVariant],
+    tag_ty: &syn::Path,
+    prefix: bool,
+) -> Impls {
+    let variants: Vec<_> = variants
+        .iter()
+        .map(|variant| Variant {
+            ident: &variant.ident,
+            kind: variant_kind(variant),
+        })
+        .collect();
+
+    if variants
+        .iter()
+        .filter(|variant| matches!(variant.kind, VariantKind::Other))
+        .count()
+        > 1
+    {
+        panic!("only one variant of a tagged union can be #[guest(other)]");
+    }
+
+    let payload_ty = variants
+        .iter()
+        .filter_map(|variant| match variant.kind {
+            VariantKind::Payload(_, ty) => Some(ty),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let layout = quote! {
+        ::panda::__tagged_union_layout::<#tag_ty>(
+            &[ #( <#payload_ty as ::panda::GuestType>::guest_layout() ),* ],
+            #prefix,
+        )
+    };
+
+    let guest_layout = quote! {
+        #layout.map(|(layout, _)| layout)
+    };
+
+    Impls {
+        guest_layout,
+        read_from_guest: read(true, &variants, tag_ty, &layout),
+        read_from_guest_phys: read(false, &variants, tag_ty, &layout),
+        write_to_guest: write(true, &variants, tag_ty, &layout, prefix),
+        write_to_guest_phys: write(false, &variants, tag_ty, &layout, prefix),
+    }
+}
+
+fn read(
+    is_virt: bool,
+    variants: &[Variant],
+    tag_ty: &syn::Path,
+    layout: &TokenStream,
+) -> TokenStream {
+    let read_method = if is_virt {
+        quote!(read_from_guest)
+    } else {
+        quote!(read_from_guest_phys)
+    };
+
+    let cpu = is_virt.then(|| quote! { __cpu, });
+
+    let arms = variants.iter().map(|Variant { ident, kind }| match kind {
+        VariantKind::Payload(tag, ty) => quote! {
+            #tag => Ok(Self::#ident(
+                <#ty as ::panda::GuestType>::#read_method(#cpu __payload)?
+            )),
+        },
+        VariantKind::Unit(tag) => quote! {
+            #tag => Ok(Self::#ident),
+        },
+        VariantKind::Other => quote! {
+            __tag => Ok(Self::#ident(__tag)),
+        },
+    });
+
+    let fallback = (!variants
+        .iter()
+        .any(|variant| matches!(variant.kind, VariantKind::Other)))
+    .then(|| quote! { _ => Err(::panda::GuestReadFail), });
+
+    quote! {
+        let (_, __offset) = #layout.ok_or(::panda::GuestReadFail)?;
+        #[allow(unused_variables)]
+        let __payload = __ptr + (__offset as ::panda::prelude::target_ptr_t);
+        let __tag = <#tag_ty as ::panda::GuestType>::#read_method(#cpu __ptr)?;
+
+        #[allow(unreachable_patterns)]
+        match __tag {
+            #( #arms )*
+            #fallback
+        }
+    }
+}
+
+fn write(
+    is_virt: bool,
+    variants: &[Variant],
+    tag_ty: &syn::Path,
+    layout: &TokenStream,
+    prefix: bool,
+) -> TokenStream {
+    let write_method = if is_virt {
+        quote!(write_to_guest)
+    } else {
+        quote!(write_to_guest_phys)
+    };
+
+    let cpu = is_virt.then(|| quote! { __cpu, });
+
+    let write_tag = |tag: TokenStream| {
+        quote! {
+            <#tag_ty as ::panda::GuestType>::#write_method(&#tag, #cpu __ptr)?;
+        }
+    };
+
+    let arms = variants.iter().map(|Variant { ident, kind }| match kind {
+        VariantKind::Payload(tag, ty) => {
+            // without a prefix, the tag is a field of the payload
+            let write_tag = prefix.then(|| write_tag(quote! { (#tag as #tag_ty) }));
+
+            quote! {
+                Self::#ident(__value) => {
+                    #write_tag
+                    <#ty as ::panda::GuestType>::#write_method(__value, #cpu __payload)?;
+                }
+            }
+        }
+        VariantKind::Unit(tag) => {
+            let write_tag = write_tag(quote! { (#tag as #tag_ty) });
+
+            quote! {
+                Self::#ident => {
+                    #write_tag
+                }
+            }
+        }
+        VariantKind::Other => {
+            let write_tag = write_tag(quote! { *__tag });
+
+            quote! {
+                Self::#ident(__tag) => {
+                    #write_tag
+                }
+            }
+        }
+    });
+
+    quote! {
+        let (_, __offset) = #layout.ok_or(::panda::GuestWriteFail)?;
+        #[allow(unused_variables)]
+        let __payload = __ptr + (__offset as ::panda::prelude::target_ptr_t);
+
+        match self {
+            #( #arms )*
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
 
 use super::GuestTypeField;
 
@@ -15,12 +16,64 @@ pub fn struct_layout<'a>(fields: impl Iterator<Item = &'a syn::Type> + 'a) -> To
     }
 }
 
+/// The dynamically sized guest types provided by panda-rs
+const DYNAMIC_TYPES: &[&str] = &["CStrGuest"];
+
+/// A compile error for a dynamically sized field which isn't the last field, if there
+/// is one. Only the types in [`DYNAMIC_TYPES`] can be recognized when deriving, other
+/// dynamically sized fields panic when the struct is first read or written instead.
+pub(super) fn misplaced_dynamic_field(fields: &[GuestTypeField]) -> Option<TokenStream> {
+    let (_last, rest) = fields.split_last()?;
+    let field = rest.iter().find(|field| match &field.ty {
+        syn::Type::Path(path) => path.path.segments.last().map_or(false, |segment| {
+            DYNAMIC_TYPES.iter().any(|ty| segment.ident == ty)
+        }),
+        _ => false,
+    })?;
+
+    Some(quote_spanned! { field.ty.span() =>
+        compile_error!("only the last field of a GuestType can be dynamically sized");
+    })
+}
+
+/// The layout of each field. Only the last field may be dynamically sized, in which
+/// case it is placed at the end of the fields before it, like a flexible array member.
+fn field_layouts(fields: &[GuestTypeField]) -> Vec<TokenStream> {
+    let last = fields.len().saturating_sub(1);
+
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let ty = &field.ty;
+
+            if i == last {
+                quote! {
+                    <#ty as ::panda::GuestType>::guest_layout().unwrap_or_else(|| {
+                        ::std::alloc::Layout::from_size_align(
+                            0,
+                            <#ty as ::panda::GuestType>::guest_align(),
+                        )
+                        .unwrap()
+                    })
+                }
+            } else {
+                quote! {
+                    <#ty as ::panda::GuestType>::guest_layout()
+                        .expect("only the last field of a GuestType can be dynamically sized")
+                }
+            }
+        })
+        .collect()
+}
+
 fn read(is_virt: bool, fields: &[GuestTypeField]) -> TokenStream {
     let field_name = fields
         .iter()
         .map(|field| field.ident.as_ref().unwrap())
         .collect::<Vec<_>>();
     let field_ty = fields.iter().map(|field| &field.ty);
+    let field_layout = field_layouts(fields);
 
     let read_method = if is_virt {
         quote!(read_from_guest)
@@ -34,9 +87,7 @@ fn read(is_virt: bool, fields: &[GuestTypeField]) -> TokenStream {
             let #layout = ::std::alloc::Layout::from_size_align(0, 1).unwrap();
 
             #(
-                let (#layout, offset) = #layout.extend(
-                    <#field_ty as ::panda::GuestType>::guest_layout().unwrap()
-                ).unwrap();
+                let (#layout, offset) = #layout.extend(#field_layout).unwrap();
 
                 let #field_name = <#field_ty as ::panda::GuestType>::#read_method(
                     #cpu __ptr + (offset as ::panda::prelude::target_ptr_t)
@@ -61,6 +112,7 @@ fn write(is_virt: bool, fields: &[GuestTypeField]) -> TokenStream {
         .map(|field| field.ident.as_ref().unwrap())
         .collect::<Vec<_>>();
     let field_ty = fields.iter().map(|field| &field.ty);
+    let field_layout = field_layouts(fields);
 
     let write_method = if is_virt {
         quote!(write_to_guest)
@@ -74,9 +126,7 @@ fn write(is_virt: bool, fields: &[GuestTypeField]) -> TokenStream {
             let #layout = ::std::alloc::Layout::from_size_align(0, 1).unwrap();
 
             #(
-                let (#layout, offset) = #layout.extend(
-                    <#field_ty as ::panda::GuestType>::guest_layout().unwrap()
-                ).unwrap();
+                let (#layout, offset) = #layout.extend(#field_layout).unwrap();
 
                 <#field_ty as ::panda::GuestType>::#write_method(
                     &self.#field_name,
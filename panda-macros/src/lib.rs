@@ -42,13 +42,8 @@ pub fn init(_: TokenStream, function: TokenStream) -> TokenStream {
             pub unsafe extern "C" fn init_plugin(plugin: *mut ::panda::PluginHandle) -> bool {
                 ::panda::set_plugin_ref(plugin);
 
-                for cb in ::panda::inventory::iter::<::panda::InternalCallback> {
-                    ::panda::sys::panda_register_callback(plugin as _, cb.cb_type, ::core::mem::transmute(cb.fn_pointer));
-                }
-
-                for cb in ::panda::inventory::iter::<::panda::PPPCallbackSetup> {
-                    cb.0();
-                }
+                ::panda::__internal_register_callbacks(plugin as _);
+                ::panda::__internal_setup_ppp_callbacks();
 
                 ::panda::InitReturn::into_init_bool(#func_name(#args))
             }
@@ -62,7 +57,8 @@ pub fn init(_: TokenStream, function: TokenStream) -> TokenStream {
         }
 
         #func
-    ).into()
+    )
+    .into()
 }
 
 /// (Callback) Called when the plugin is being uninitialized
@@ -106,7 +102,7 @@ pub fn uninit(_: TokenStream, function: TokenStream) -> TokenStream {
 ///     // when a process starts, hook the entrypoint
 ///     entry_hook::hook()
 ///         .after_block_exec()
-///         .at_addr(auxv.entry)
+///         .at_addr(auxv.entry);
 /// }
 ///
 /// Panda::new()
@@ -115,6 +111,26 @@ pub fn uninit(_: TokenStream, function: TokenStream) -> TokenStream {
 ///     .run();
 /// ```
 ///
+/// ## Hooking Symbols
+///
+/// Hooks can also be placed at a symbol of a shared library, in every process which
+/// loads it, using `at_symbol`:
+///
+/// ```
+/// use panda::plugins::hooks::Hook;
+/// use panda::prelude::*;
+///
+/// #[panda::hook]
+/// fn malloc_hook(_: &mut CPUState, _: &mut TranslationBlock, _: &mut Hook) {
+///     println!("malloc called");
+/// }
+///
+/// #[panda::init]
+/// fn init(_: &mut PluginHandle) {
+///     malloc_hook::hook().at_symbol("libc", "malloc");
+/// }
+/// ```
+///
 /// ## Supported Callback Types
 ///
 /// ### Standard callbacks
@@ -143,8 +159,36 @@ pub fn uninit(_: TokenStream, function: TokenStream) -> TokenStream {
 /// | `before_block_translate` | `fn(cpu: &mut CPUState, pc: target_ptr_t, hook: &mut Hook)` | Callback that runs before the block the hooked instruction is translated to tcg |
 /// | `after_block_exec`       | `fn(cpu: &mut CPUState, tb: &mut TranslationBlock, exitCode: u8, hook: &mut Hook)` | Callback that runs after the given block is executed |
 /// | `before_block_exec_invalidate_opt` | `fn(env: &mut CPUState, tb: &mut TranslationBlock, hook: &mut Hook) -> bool` | Callback on translate to provide the option to invalidate the block the hooked instruction is generated in |
+///
+/// ## Hook State
+///
+/// Hooks can keep state without the need for global statics by passing a type implementing
+/// `Default` using `state`. Each installed hook gets its own instance of the state, which
+/// can be accessed from the hook using the generated `state` function:
+///
+/// ```
+/// use panda::plugins::hooks::Hook;
+/// use panda::prelude::*;
+///
+/// #[derive(Default)]
+/// struct Hits {
+///     count: usize,
+/// }
+///
+/// #[panda::hook(state = Hits)]
+/// fn count_hits(_: &mut CPUState, _: &mut TranslationBlock, hook: &mut Hook) {
+///     let hits = count_hits::state(hook);
+///     hits.count += 1;
+///
+///     // disable the hook after it has been hit 10 times
+///     if hits.count == 10 {
+///         hook.enabled = false;
+///     }
+/// }
+/// ```
 #[proc_macro_attribute]
-pub fn hook(_: TokenStream, func: TokenStream) -> TokenStream {
+pub fn hook(args: TokenStream, func: TokenStream) -> TokenStream {
+    let hook_args = syn::parse_macro_input!(args as HookArgs);
     let mut function = syn::parse_macro_input!(func as syn::ItemFn);
     function.sig.abi = Some(syn::parse_quote!(extern "C"));
     let vis = &function.vis;
@@ -155,14 +199,34 @@ pub fn hook(_: TokenStream, func: TokenStream) -> TokenStream {
     let ret = &function.sig.output;
     let ty: syn::Type = syn::parse_quote! { extern "C" fn(  #args ) #ret };
 
+    let state = match &hook_args.state {
+        Some(state) => quote!(
+            pub type State = #state;
+
+            pub fn hook() -> <#ty as ::panda::plugins::hooks::IntoHookBuilder>::BuilderType {
+                <#ty as ::panda::plugins::hooks::IntoHookBuilder>::hook(#func)
+                    .state(<State as ::core::default::Default>::default())
+            }
+
+            /// Get the state of the hook being run
+            pub fn state(hook: &mut ::panda::plugins::hooks::Hook) -> &mut State {
+                hook.state::<State>()
+                    .expect("Hook was not installed with its state")
+            }
+        ),
+        None => quote!(
+            pub fn hook() -> <#ty as ::panda::plugins::hooks::IntoHookBuilder>::BuilderType {
+                <#ty as ::panda::plugins::hooks::IntoHookBuilder>::hook(#func)
+            }
+        ),
+    };
+
     quote!(
         #( #cfgs )*
         #vis mod #func {
             use super::*;
 
-            pub fn hook() -> <#ty as ::panda::plugins::hooks::IntoHookBuilder>::BuilderType {
-                <#ty as ::panda::plugins::hooks::IntoHookBuilder>::hook(#func)
-            }
+            #state
         }
 
         #function
@@ -170,10 +234,82 @@ pub fn hook(_: TokenStream, func: TokenStream) -> TokenStream {
     .into()
 }
 
+/// Arguments to `#[panda::hook]`, of the form `state = Type`
+struct HookArgs {
+    state: Option<syn::Type>,
+}
+
+impl syn::parse::Parse for HookArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        if input.is_empty() {
+            return Ok(Self { state: None });
+        }
+
+        let name: syn::Ident = input.parse()?;
+        if name != "state" {
+            return Err(syn::Error::new(name.span(), "expected `state = Type`"));
+        }
+
+        input.parse::<syn::Token![=]>()?;
+
+        Ok(Self {
+            state: Some(input.parse()?),
+        })
+    }
+}
+
+/// Arguments to callback attributes such as `#[panda::before_block_exec]` and
+/// `#[panda::on_sys::read_enter]`, of the form `priority = expr`
+struct CallbackArgs {
+    priority: Option<syn::Expr>,
+}
+
+impl syn::parse::Parse for CallbackArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        if input.is_empty() {
+            return Ok(Self { priority: None });
+        }
+
+        let name: syn::Ident = input.parse()?;
+        if name != "priority" {
+            return Err(syn::Error::new(name.span(), "expected `priority = i32`"));
+        }
+
+        input.parse::<syn::Token![=]>()?;
+
+        Ok(Self {
+            priority: Some(input.parse()?),
+        })
+    }
+}
+
+/// Arguments to `#[panda::on_sys_return_with_args]`, of the form
+/// `callno` or `callno, priority = expr`
+#[cfg(not(feature = "ppc"))]
+struct SysReturnArgs {
+    callno: syn::Expr,
+    priority: Option<syn::Expr>,
+}
+
+#[cfg(not(feature = "ppc"))]
+impl syn::parse::Parse for SysReturnArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let callno = input.parse()?;
+        let priority = if input.is_empty() {
+            None
+        } else {
+            input.parse::<syn::Token![,]>()?;
+            input.parse::<CallbackArgs>()?.priority
+        };
+
+        Ok(Self { callno, priority })
+    }
+}
+
 mod guest_type;
 use guest_type::GuestTypeInput;
 
-#[proc_macro_derive(GuestType)]
+#[proc_macro_derive(GuestType, attributes(guest))]
 pub fn derive_guest_type(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
     match GuestTypeInput::from_derive_input(&input) {
@@ -194,6 +330,18 @@ pub fn derive_osi_type(input: TokenStream) -> TokenStream {
     }
 }
 
+mod osi_flags;
+use osi_flags::OsiFlagsInput;
+
+#[proc_macro_derive(OsiFlags, attributes(flag))]
+pub fn derive_osi_flags(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
+    match OsiFlagsInput::from_derive_input(&input) {
+        Ok(input) => input.to_tokens().into(),
+        Err(err) => err.write_errors().into(),
+    }
+}
+
 mod osi_static;
 use osi_static::OsiStatics;
 
@@ -272,14 +420,19 @@ macro_rules! define_callback_attributes {
                     "]\nfn callback(",
                     $("_: ", stringify!($arg), ", ", )* ")",
                     $(" -> ", stringify!($ret),)?
-                    " {\n    // do stuff\n}\n```"),
+                    " {\n    // do stuff\n}\n```",
+                    "\n\nThe callback's order relative to other callbacks for the same event can be ",
+                    "set with `#[panda::", stringify!($attr_name), "(priority = 10)]`, see ",
+                    "`Callback::with_priority`."),
                 #[proc_macro_attribute]
-                pub fn $attr_name(_: TokenStream, function: TokenStream) -> TokenStream {
+                pub fn $attr_name(args: TokenStream, function: TokenStream) -> TokenStream {
+                    let args = syn::parse_macro_input!(args as crate::CallbackArgs);
                     let mut function = syn::parse_macro_input!(function as syn::ItemFn);
                     function.sig.abi = Some(syn::parse_quote!(extern "C"));
                     let vis = &function.vis;
                     let func = &function.sig.ident;
                     let cfgs = crate::get_cfg_attrs(&function);
+                    let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
 
                     quote!(
                         #(
@@ -294,9 +447,10 @@ macro_rules! define_callback_attributes {
 
                         ::panda::inventory::submit! {
                             #![crate = ::panda]
-                            ::panda::InternalCallback::new(
+                            ::panda::InternalCallback::with_priority(
                                 ::panda::sys::$const_name,
-                                #func as *const ()
+                                #func as *const (),
+                                #priority
                             )
                         }
 
@@ -338,6 +492,21 @@ macro_rules! define_callback_attributes {
             }
         )*
 
+        /// For internal use only. Lists the name and type of every callback which has
+        /// bindings, so they can be checked against the callback types PANDA defines.
+        #[proc_macro]
+        #[doc(hidden)]
+        pub fn define_callback_types(_: TokenStream) -> TokenStream {
+            quote!(
+                #[doc(hidden)]
+                pub const CALLBACK_TYPES: &[(&str, crate::sys::panda_cb_type)] = &[
+                    $(
+                        (stringify!($attr_name), crate::sys::$const_name),
+                    )*
+                ];
+            ).into()
+        }
+
         #[proc_macro]
         pub fn define_closure_callbacks(_: TokenStream) -> TokenStream {
             quote!(
@@ -353,30 +522,41 @@ macro_rules! define_callback_attributes {
                         pub fn $attr_name<F>(self, callback: F)
                             where F: FnMut($($arg),*) $(-> $ret)? + 'static
                         {
-                            unsafe extern "C" fn trampoline(context: *mut c_void, $($arg_name: $arg),*) $(-> $ret)? {
-                                let closure: &mut &mut (
-                                    dyn FnMut($($arg),*) $(-> $ret)?
-                                ) = unsafe { std::mem::transmute(
-                                    context as *mut *mut c_void
-                                )};
+                            unsafe extern "C" fn trampoline(position: *mut c_void, $($arg_name: $arg),*) $(-> $ret)? {
+                                let context = unsafe { crate::callbacks::closure_at(position) };
+                                if context.is_null() {
+                                    return crate::reentrancy::SkippedReturn::skipped_return(
+                                        ($($arg_name,)*)
+                                    );
+                                }
+
+                                let _running = match unsafe { crate::reentrancy::__enter(context) } {
+                                    Some(running) => running,
+                                    None => return crate::reentrancy::SkippedReturn::skipped_return(
+                                        ($($arg_name,)*)
+                                    ),
+                                };
+
+                                let context = context as *mut crate::reentrancy::ClosureContext<
+                                    Box<dyn FnMut($($arg),*) $(-> $ret)?>
+                                >;
+                                let closure = unsafe { &mut (*context).closure };
                                 closure($($arg_name),*)
                             }
 
                             unsafe fn drop_fn(this: *mut *mut c_void) {
-                                let _: Box<Box<dyn FnMut($($arg),*) $(-> $ret)?>> = unsafe {
-                                    std::mem::transmute(this)
+                                let _ = unsafe {
+                                    Box::from_raw(this as *mut crate::reentrancy::ClosureContext<
+                                        Box<dyn FnMut($($arg),*) $(-> $ret)?>
+                                    >)
                                 };
                             }
 
-                            let closure_ref: *mut *mut c_void = unsafe {
-                                let x: Box<Box<
-                                    dyn FnMut($($arg),*) $(-> $ret)?
-                                >> = Box::new(
-                                    Box::new(callback) as Box<_>
-                                );
-
-                                std::mem::transmute(x)
-                            };
+                            let closure_ref = Box::into_raw(Box::new(
+                                crate::reentrancy::ClosureContext::new(
+                                    Box::new(callback) as Box<dyn FnMut($($arg),*) $(-> $ret)?>
+                                )
+                            )) as *mut *mut c_void;
 
                             install_closure_callback(self.0, ClosureCallback {
                                 closure_ref,
@@ -387,6 +567,7 @@ macro_rules! define_callback_attributes {
                                     })
                                 },
                                 cb_kind: sys::$const_name,
+                                installed: 0,
                             });
                         }
                     )*
@@ -422,14 +603,19 @@ macro_rules! define_syscalls_callbacks {
                     "_enter",
                     "]\nfn callback(",
                     $("_: ", stringify!($arg), ", ",)*
-                    ") {\n    // do stuff\n}\n```"
+                    ") {\n    // do stuff\n}\n```",
+                    "\n\nThe callback's order relative to this plugin's other callbacks for the ",
+                    "same syscall can be set with a priority, such as ",
+                    "`#[panda::on_sys::read_enter(priority = 10)]`, see `PppCallback::with_priority`."
                 ),
                 #[proc_macro_attribute]
-                pub fn $attr_name(_: TokenStream, function: TokenStream) -> TokenStream {
+                pub fn $attr_name(args: TokenStream, function: TokenStream) -> TokenStream {
+                    let args = syn::parse_macro_input!(args as crate::CallbackArgs);
                     let mut function = syn::parse_macro_input!(function as syn::ItemFn);
                     function.sig.abi = Some(syn::parse_quote!(extern "C"));
                     let func = &function.sig.ident;
                     let cfgs = crate::get_cfg_attrs(&function);
+                    let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
 
                     quote!(
                         #(
@@ -440,7 +626,8 @@ macro_rules! define_syscalls_callbacks {
                             ::panda::PPPCallbackSetup(
                                 || {
                                     ::panda::plugins::syscalls2::SYSCALLS.$cb_name(#func);
-                                }
+                                },
+                                #priority
                             )
                         }
 
@@ -492,11 +679,13 @@ macro_rules! define_syscalls_callbacks {
         ///
         /// [`CPUState`]: https://docs.rs/panda-re/*/panda/prelude/struct.CPUState.html
         #[proc_macro_attribute]
-        pub fn on_all_sys_enter(_: TokenStream, function: TokenStream) -> TokenStream {
+        pub fn on_all_sys_enter(args: TokenStream, function: TokenStream) -> TokenStream {
+            let args = syn::parse_macro_input!(args as crate::CallbackArgs);
             let mut function = syn::parse_macro_input!(function as syn::ItemFn);
             function.sig.abi = Some(syn::parse_quote!(extern "C"));
             let func = &function.sig.ident;
             let cfgs = crate::get_cfg_attrs(&function);
+            let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
 
             quote!(
                 #(
@@ -507,7 +696,8 @@ macro_rules! define_syscalls_callbacks {
                     ::panda::PPPCallbackSetup(
                         || {
                             ::panda::plugins::syscalls2::SYSCALLS.add_callback_on_all_sys_enter(#func);
-                        }
+                        },
+                        #priority
                     )
                 }
 
@@ -537,11 +727,13 @@ macro_rules! define_syscalls_callbacks {
         ///
         /// [`CPUState`]: https://docs.rs/panda-re/*/panda/prelude/struct.CPUState.html
         #[proc_macro_attribute]
-        pub fn on_all_sys_return(_: TokenStream, function: TokenStream) -> TokenStream {
+        pub fn on_all_sys_return(args: TokenStream, function: TokenStream) -> TokenStream {
+            let args = syn::parse_macro_input!(args as crate::CallbackArgs);
             let mut function = syn::parse_macro_input!(function as syn::ItemFn);
             function.sig.abi = Some(syn::parse_quote!(extern "C"));
             let func = &function.sig.ident;
             let cfgs = crate::get_cfg_attrs(&function);
+            let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
 
             quote!(
                 #(
@@ -552,7 +744,111 @@ macro_rules! define_syscalls_callbacks {
                     ::panda::PPPCallbackSetup(
                         || {
                             ::panda::plugins::syscalls2::SYSCALLS.add_callback_on_all_sys_return(#func);
-                        }
+                        },
+                        #priority
+                    )
+                }
+
+                #function
+            ).into()
+        }
+
+        /// Callback that runs when any syscall returns, with the arguments the syscall
+        /// was entered with.
+        ///
+        /// Unlike other syscall callbacks, the function is a regular Rust function.
+        ///
+        /// ### Args
+        ///
+        /// * `cpu` - a reference to the currently executing [`CPUState`] object
+        /// * `ctx` - the [`SyscallContext`] of the syscall, including the syscall number,
+        /// arguments and return value
+        ///
+        /// ### Example
+        /// ```rust
+        /// use panda::prelude::*;
+        /// use panda::plugins::syscalls2::SyscallContext;
+        ///
+        /// #[panda::on_all_sys_return_with_args]
+        /// fn callback(cpu: &mut CPUState, ctx: &SyscallContext) {
+        ///     println!("{:x?} -> {:#x}", ctx.args(), ctx.retval());
+        /// }
+        /// ```
+        ///
+        /// [`CPUState`]: https://docs.rs/panda-re/*/panda/prelude/struct.CPUState.html
+        /// [`SyscallContext`]: https://docs.rs/panda-re/*/panda/plugins/syscalls2/struct.SyscallContext.html
+        #[proc_macro_attribute]
+        pub fn on_all_sys_return_with_args(args: TokenStream, function: TokenStream) -> TokenStream {
+            let args = syn::parse_macro_input!(args as crate::CallbackArgs);
+            let function = syn::parse_macro_input!(function as syn::ItemFn);
+            let func = &function.sig.ident;
+            let cfgs = crate::get_cfg_attrs(&function);
+            let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
+
+            quote!(
+                #(
+                    #cfgs
+                 )*
+                ::panda::inventory::submit! {
+                    #![crate = ::panda]
+                    ::panda::PPPCallbackSetup(
+                        || {
+                            ::panda::plugins::syscalls2::on_all_sys_return_with_args(#func);
+                        },
+                        #priority
+                    )
+                }
+
+                #function
+            ).into()
+        }
+
+        /// Callback that runs when a given syscall returns, with the arguments the syscall
+        /// was entered with. The attribute takes the number of the syscall, optionally
+        /// followed by a priority, such as `#[panda::on_sys_return_with_args(0, priority = 10)]`.
+        ///
+        /// Unlike other syscall callbacks, the function is a regular Rust function.
+        ///
+        /// ### Args
+        ///
+        /// * `cpu` - a reference to the currently executing [`CPUState`] object
+        /// * `ctx` - the [`SyscallContext`] of the syscall, including the syscall number,
+        /// arguments and return value
+        ///
+        /// ### Example
+        /// ```rust
+        /// use panda::prelude::*;
+        /// use panda::plugins::syscalls2::SyscallContext;
+        ///
+        /// // read on x86_64
+        /// #[panda::on_sys_return_with_args(0)]
+        /// fn callback(cpu: &mut CPUState, ctx: &SyscallContext) {
+        ///     println!("read{:x?} -> {:#x}", ctx.args(), ctx.retval());
+        /// }
+        /// ```
+        ///
+        /// [`CPUState`]: https://docs.rs/panda-re/*/panda/prelude/struct.CPUState.html
+        /// [`SyscallContext`]: https://docs.rs/panda-re/*/panda/plugins/syscalls2/struct.SyscallContext.html
+        #[proc_macro_attribute]
+        pub fn on_sys_return_with_args(args: TokenStream, function: TokenStream) -> TokenStream {
+            let args = syn::parse_macro_input!(args as crate::SysReturnArgs);
+            let function = syn::parse_macro_input!(function as syn::ItemFn);
+            let func = &function.sig.ident;
+            let cfgs = crate::get_cfg_attrs(&function);
+            let callno = args.callno;
+            let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
+
+            quote!(
+                #(
+                    #cfgs
+                 )*
+                ::panda::inventory::submit! {
+                    #![crate = ::panda]
+                    ::panda::PPPCallbackSetup(
+                        || {
+                            ::panda::plugins::syscalls2::on_sys_return_with_args(#callno, #func);
+                        },
+                        #priority
                     )
                 }
 
@@ -592,11 +888,13 @@ macro_rules! define_syscalls_callbacks {
 /// [`TranslationBlock`]: https://docs.rs/panda-re/*/panda/prelude/struct.TranslationBlock.html
 /// [`AuxvValues`]: https://docs.rs/panda-re/*/panda/plugins/proc_start_linux/struct.AuxvValues.html
 #[proc_macro_attribute]
-pub fn on_rec_auxv(_: TokenStream, function: TokenStream) -> TokenStream {
+pub fn on_rec_auxv(args: TokenStream, function: TokenStream) -> TokenStream {
+    let args = syn::parse_macro_input!(args as crate::CallbackArgs);
     let mut function = syn::parse_macro_input!(function as syn::ItemFn);
     function.sig.abi = Some(syn::parse_quote!(extern "C"));
     let func = &function.sig.ident;
     let cfgs = crate::get_cfg_attrs(&function);
+    let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
 
     quote!(
         #(
@@ -607,7 +905,8 @@ pub fn on_rec_auxv(_: TokenStream, function: TokenStream) -> TokenStream {
             ::panda::PPPCallbackSetup(
                 || {
                     ::panda::plugins::proc_start_linux::PROC_START_LINUX.add_callback_on_rec_auxv(#func);
-                }
+                },
+                #priority
             )
         }
 
@@ -615,6 +914,119 @@ pub fn on_rec_auxv(_: TokenStream, function: TokenStream) -> TokenStream {
     ).into()
 }
 
+macro_rules! define_module_callbacks {
+    (mod $module:ident; $(
+        $($doc:literal)*
+        fn $attr_name:ident ($($arg:ty),*);
+    )*) => {
+        $(
+            doc_comment::doc_comment!{
+                concat!(
+                    "(Callback) ",
+                    $($doc, "\n",)*
+                    "\n\nSee [`",
+                    stringify!($module),
+                    "`](https://docs.rs/panda-re/*/panda/",
+                    stringify!($module),
+                    "/index.html) for details.",
+                    "\n\nCallback arguments: (",
+                    $("`", stringify!($arg), "`, ",)*
+                    ")\n### Example\n```rust\nuse panda::prelude::*;\n\n#[panda::",
+                    stringify!($attr_name),
+                    "]\nfn callback(",
+                    $("_: ", stringify!($arg), ", ", )*
+                    ") {\n    // do stuff\n}\n```"
+                ),
+                #[proc_macro_attribute]
+                pub fn $attr_name(args: TokenStream, function: TokenStream) -> TokenStream {
+                    let args = syn::parse_macro_input!(args as crate::CallbackArgs);
+                    let function = syn::parse_macro_input!(function as syn::ItemFn);
+                    let func = &function.sig.ident;
+                    let cfgs = crate::get_cfg_attrs(&function);
+                    let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
+
+                    quote!(
+                        #(
+                            #cfgs
+                         )*
+                        ::panda::inventory::submit! {
+                            #![crate = ::panda]
+                            ::panda::PPPCallbackSetup(
+                                || {
+                                    ::panda::$module::$attr_name(#func);
+                                },
+                                #priority
+                            )
+                        }
+
+                        #function
+                    ).into()
+                }
+            }
+        )*
+    };
+}
+
+define_module_callbacks! {
+    mod insn_callbacks;
+
+    "Called after each call instruction, direct or indirect, is executed, with the
+    address of the call and the address of the function called. Requires the `disas`
+    feature."
+    fn on_call(&mut CPUState, target_ulong, target_ulong);
+
+    "Called after each indirect call instruction is executed, with the address of the
+    call and the address of the function called. Requires the `disas` feature."
+    fn on_indirect_call(&mut CPUState, target_ulong, target_ulong);
+
+    "Called after each return instruction is executed, with the address of the return
+    and the address returned to. Requires the `disas` feature."
+    fn on_ret(&mut CPUState, target_ulong, target_ulong);
+
+    "Called after each indirect jump instruction is executed, with the address of the
+    jump and the address jumped to. Requires the `disas` feature."
+    fn on_indirect_jump(&mut CPUState, target_ulong, target_ulong);
+
+    "Called before each privileged instruction is executed, with the address of the
+    instruction. Requires the `disas` feature."
+    fn on_privileged(&mut CPUState, target_ulong);
+}
+
+define_module_callbacks! {
+    mod disk_activity;
+
+    "Called when the guest reads part of a file from disk while replaying, with the
+    path of the file and the part read. Requires the `guestfs` feature and a disk image
+    to be loaded with `disk_activity::load`."
+    fn on_file_block_read(&mut CPUState, &FileBlock);
+
+    "Called when the guest writes part of a file to disk while replaying, with the
+    path of the file and the part written. Requires the `guestfs` feature and a disk
+    image to be loaded with `disk_activity::load`."
+    fn on_file_block_write(&mut CPUState, &FileBlock);
+}
+
+define_module_callbacks! {
+    mod progress;
+
+    "Called at regular intervals while replaying with how far through the replay PANDA
+    is, the rate instructions are being replayed at and the estimated time remaining.
+    How often it is called is set with `progress::set_interval`."
+    fn on_replay_progress(&ReplayProgress);
+}
+
+define_module_callbacks! {
+    mod trustzone;
+
+    "Called each time the CPU takes a secure monitor call (`SMC`), with the decoded
+    function ID, arguments and a snapshot of the registers. ARM only."
+    fn on_smc(&mut CPUState, &SmcCall);
+
+    "Called each time the CPU switches between the secure and non-secure worlds, with a
+    snapshot of the registers at the start of the new world. ARM only."
+    fn on_world_switch(&mut CPUState, &WorldSwitch);
+}
+
 macro_rules! define_hooks2_callbacks {
     ($(
         $($doc:literal)*
@@ -624,11 +1036,13 @@ macro_rules! define_hooks2_callbacks {
             doc_comment::doc_comment!{
                 concat!("(Callback) ", $($doc, "\n",)* "\n\nCallback arguments: ("$(, "`", stringify!($arg), "`")*, ")\n### Example\n```rust\nuse panda::prelude::*;\n\n#[panda::", stringify!($attr_name),"]\nfn callback(", $(", _: ", stringify!($arg), )* ") {\n    // do stuff\n}\n```"),
                 #[proc_macro_attribute]
-                pub fn $attr_name(_: TokenStream, function: TokenStream) -> TokenStream {
+                pub fn $attr_name(args: TokenStream, function: TokenStream) -> TokenStream {
+                    let args = syn::parse_macro_input!(args as crate::CallbackArgs);
                     let mut function = syn::parse_macro_input!(function as syn::ItemFn);
                     function.sig.abi = Some(syn::parse_quote!(extern "C"));
                     let func = &function.sig.ident;
                     let cfgs = crate::get_cfg_attrs(&function);
+                    let priority = args.priority.unwrap_or_else(|| syn::parse_quote!(0));
 
                     quote!(
                         #(
@@ -639,7 +1053,8 @@ macro_rules! define_hooks2_callbacks {
                             ::panda::PPPCallbackSetup(
                                 || {
                                     ::panda::plugins::hooks2::HOOKS.$cb_name(#func);
-                                }
+                                },
+                                #priority
                             )
                         }
 
@@ -0,0 +1,53 @@
+use darling::ast::Data;
+use darling::util::Ignored;
+use darling::{FromDeriveInput, FromField};
+
+use proc_macro2::TokenStream;
+use quote::quote;
+
+#[derive(FromDeriveInput)]
+#[darling(supports(struct_named))]
+pub(crate) struct OsiFlagsInput {
+    ident: syn::Ident,
+    data: Data<Ignored, OsiFlagsField>,
+}
+
+#[derive(FromField)]
+#[darling(attributes(flag))]
+struct OsiFlagsField {
+    ident: Option<syn::Ident>,
+
+    #[darling(default)]
+    bit: Option<u32>,
+
+    #[darling(default)]
+    mask: Option<u64>,
+}
+
+impl OsiFlagsInput {
+    pub(crate) fn to_tokens(self) -> TokenStream {
+        let self_ident = &self.ident;
+        let fields = self.data.take_struct().unwrap().fields;
+
+        let field_names = fields.iter().map(|field| &field.ident);
+        let masks = fields.iter().map(|field| match (field.bit, field.mask) {
+            (Some(bit), None) if bit < 64 => 1u64 << bit,
+            (None, Some(mask)) => mask,
+            _ => panic!(
+                "Each flag requires exactly one of #[flag(bit = ...)] or #[flag(mask = ...)]"
+            ),
+        });
+
+        quote! {
+            impl ::panda::plugins::cosi::FromBits for #self_ident {
+                fn from_bits(__bits: u64) -> Self {
+                    Self {
+                        #(
+                            #field_names: __bits & #masks != 0,
+                        )*
+                    }
+                }
+            }
+        }
+    }
+}
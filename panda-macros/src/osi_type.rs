@@ -35,6 +35,87 @@ struct OsiTypeField {
 
     #[darling(default)]
     osi_type: bool,
+
+    #[darling(default)]
+    bits: Option<String>,
+
+    #[darling(default)]
+    flags: bool,
+
+    #[darling(default)]
+    list: Option<String>,
+}
+
+impl OsiTypeField {
+    fn name(&self) -> String {
+        self.rename
+            .clone()
+            .or_else(|| self.ident.as_ref().map(ToString::to_string))
+            .unwrap()
+    }
+
+    /// Generate an expression reading this field, given the address of the field
+    fn read(&self, field_addr: TokenStream) -> TokenStream {
+        let ty = &self.ty;
+        let field_name = self.name();
+
+        let bits = match (&self.bits, self.flags) {
+            (Some(_), true) => panic!("`bits` and `flags` cannot be used on the same field"),
+            (Some(bits), false) => {
+                let (start, end) = parse_bit_range(bits);
+                Some(quote! { Some(#start..#end) })
+            }
+            (None, true) => Some(quote! { None }),
+            (None, false) => None,
+        };
+
+        if let Some(member) = &self.list {
+            if self.osi_type || bits.is_some() {
+                panic!("`list` cannot be used alongside `osi_type`, `bits` or `flags`");
+            }
+
+            quote! {
+                <#ty>::read_at(__cpu, #field_addr, #member)
+            }
+        } else if let Some(bits) = bits {
+            if self.osi_type {
+                panic!("`osi_type` cannot be used alongside `bits` or `flags`");
+            }
+
+            quote! {
+                ::panda::plugins::cosi::read_field_bits(
+                    __cpu, __osi_type, #field_name, #field_addr, #bits
+                ).map(<#ty as ::panda::plugins::cosi::FromBits>::from_bits)
+            }
+        } else if self.osi_type {
+            quote! {
+                <#ty as ::panda::plugins::cosi::OsiType>::osi_read(__cpu, #field_addr)
+            }
+        } else {
+            quote! {
+                ::panda::mem::read_guest_type::<#ty>(__cpu, #field_addr)
+            }
+        }
+    }
+}
+
+/// Parse a bit range of the form `start..end`, where `end` is exclusive
+fn parse_bit_range(bits: &str) -> (u32, u32) {
+    let range = bits.split("..").map(str::trim).collect::<Vec<_>>();
+
+    match range[..] {
+        [start, end] => match (start.parse::<u32>(), end.parse::<u32>()) {
+            (Ok(start), Ok(end)) if start < end && end <= 64 => (start, end),
+            _ => panic!(
+                "Invalid bit range \"{}\": must be `start..end` within 0..64",
+                bits
+            ),
+        },
+        _ => panic!(
+            "Invalid bit range \"{}\": must be of the form `start..end`",
+            bits
+        ),
+    }
 }
 
 impl OsiTypeInput {
@@ -47,80 +128,54 @@ impl OsiTypeInput {
         let self_struct = self.data.clone().take_struct().unwrap();
         let read_fields = self_struct.fields.iter().map(|field| {
             let ident = &field.ident;
-            let ty = &field.ty;
-
-            let field_name = field.rename
-                .clone()
-                .or_else(|| ident.as_ref().map(ToString::to_string))
-                .unwrap();
-
-            let read_func = if field.osi_type {
-                quote! {
-                    <#ty as ::panda::plugins::cosi::OsiType>::osi_read
-                }
-            } else {
-                quote! {
-                    ::panda::mem::read_guest_type::<#ty>
-                }
-            };
+            let field_name = field.name();
+            let read = field.read(quote! {
+                __base_ptr + (__field_offset as ::panda::prelude::target_ptr_t)
+            });
 
             quote! {
                 let __field_offset = {
-                    static FIELD_OFFSET: ::panda::once_cell::sync::OnceCell<::panda::prelude::target_long>
-                        = ::panda::once_cell::sync::OnceCell::new();
+                    static FIELD_OFFSET: ::panda::plugins::cosi::ProfileCache<::panda::prelude::target_long>
+                        = ::panda::plugins::cosi::ProfileCache::new();
 
-                    *FIELD_OFFSET.get_or_init(|| {
+                    FIELD_OFFSET.get_or_init(|| {
                         __osi_type.offset_of(#field_name)
                     })
                 };
 
-                let #ident = #read_func (
-                    __cpu, __base_ptr + (__field_offset as ::panda::prelude::target_ptr_t)
-                )?;
+                let #ident = #read?;
             }
         });
 
         let read_field_methods = self_struct.fields.iter().map(|field| {
             let ident = &field.ident;
             let ty = &field.ty;
-
-            let field_name = field.rename
-                .clone()
-                .or_else(|| ident.as_ref().map(ToString::to_string))
-                .unwrap();
-
-            let read_func = if field.osi_type {
-                quote! {
-                    <#ty as ::panda::plugins::cosi::OsiType>::osi_read
-                }
-            } else {
-                quote! {
-                    ::panda::mem::read_guest_type::<#ty>
-                }
-            };
+            let field_name = field.name();
+            let read = field.read(quote! {
+                __base_ptr + (__osi_type.offset_of(#field_name) as ::panda::prelude::target_ptr_t)
+            });
 
             quote! {
                 pub(crate) fn #ident(&self, __cpu: &mut CPUState) -> Result<#ty, ::panda::GuestReadFail> {
-                    let __osi_type = ::panda::plugins::cosi::type_from_name(#type_name)
+                    let __osi_type_ref = ::panda::plugins::cosi::type_from_name(#type_name)
                         .ok_or(::panda::GuestReadFail)?;
+                    let __osi_type = &*__osi_type_ref;
 
                     let is_per_cpu = self.1;
                     let __base_ptr = if is_per_cpu {
                         ::panda::plugins::cosi::find_per_cpu_address(__cpu, self.0)?
                     } else {
-                        static SYMBOL_ADDR: ::panda::once_cell::sync::OnceCell<::panda::prelude::target_ptr_t>
-                            = ::panda::once_cell::sync::OnceCell::new();
+                        static SYMBOL_ADDR: ::panda::plugins::cosi::ProfileCache<::panda::prelude::target_ptr_t>
+                            = ::panda::plugins::cosi::ProfileCache::new();
 
-                        *SYMBOL_ADDR.get_or_init(|| {
+                        SYMBOL_ADDR.get_or_init(|| {
                             ::panda::plugins::cosi::symbol_addr_from_name(
                                 self.0
                             )
                         })
                     };
 
-                    #read_func (
-                        __cpu, __base_ptr + (__osi_type.offset_of(#field_name) as ::panda::prelude::target_ptr_t)
-                    )
+                    #read
                 }
             }
         });
@@ -144,12 +199,17 @@ impl OsiTypeInput {
             impl ::panda::plugins::cosi::OsiType for #self_ident {
                 type MethodDispatcher = #method_dispatcher;
 
+                fn osi_type_name() -> Option<&'static str> {
+                    Some(#type_name)
+                }
+
                 fn osi_read(
                     __cpu: &mut ::panda::prelude::CPUState,
                     __base_ptr: ::panda::prelude::target_ptr_t,
                 ) -> Result<Self, ::panda::GuestReadFail> {
-                    let __osi_type = ::panda::plugins::cosi::type_from_name(#type_name)
+                    let __osi_type_ref = ::panda::plugins::cosi::type_from_name(#type_name)
                         .ok_or(::panda::GuestReadFail)?;
+                    let __osi_type = &*__osi_type_ref;
 
 
                     #(
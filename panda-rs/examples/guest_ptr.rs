@@ -1,4 +1,4 @@
-use panda::mem::{map_memory, physical_memory_write, PAGE_SIZE};
+use panda::mem::{map_memory, physical_memory_write};
 use panda::prelude::*;
 use panda::{GuestPtr, GuestType};
 
@@ -13,7 +13,7 @@ const ADDRESS: target_ptr_t = 0x1000;
 #[panda::after_machine_init]
 fn setup(_: &mut CPUState) {
     // Map 2MB memory for this emulation
-    map_memory("mymem", 2 * 1024 * PAGE_SIZE, ADDRESS).unwrap();
+    map_memory("mymem", 2 * 1024 * 1024, ADDRESS).unwrap();
 
     // Write code into memory
     physical_memory_write(ADDRESS, b"\x34\x12\x00\x00\x20\x00\x00\x00");
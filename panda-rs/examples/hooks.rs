@@ -26,7 +26,7 @@ fn on_proc_start(_cpu: &mut CPUState, _tb: &mut TranslationBlock, auxv: &AuxvVal
     })
     .at_addr(auxv.entry);
 
-    entry_hook::hook().after_block_exec().at_addr(auxv.entry)
+    entry_hook::hook().after_block_exec().at_addr(auxv.entry);
 }
 
 fn main() {
@@ -1,7 +1,6 @@
-use std::ffi::CStr;
 use std::sync::atomic::{AtomicU64, Ordering};
 
-use panda::plugins::osi::OSI;
+use panda::plugins::osi;
 use panda::prelude::*;
 
 static NUM_BB: AtomicU64 = AtomicU64::new(0);
@@ -18,17 +17,19 @@ fn every_basic_block(cpu: &mut CPUState, tb: &mut TranslationBlock, exit_code: u
         return;
     }
 
-    let curr_proc = OSI.get_current_process(cpu);
-    let curr_proc_name_c_str = unsafe { CStr::from_ptr(curr_proc.as_ref().unwrap().name) };
+    let curr_proc = match osi::current_process(cpu) {
+        Some(curr_proc) => curr_proc,
+        None => return,
+    };
 
     let curr_bb = NUM_BB.fetch_add(1, Ordering::SeqCst);
     if (curr_bb % 1000 == 0) && (curr_bb != 0) {
         println!(
             "{:?} @ 0x{:016x}, {} BBs in - in shared lib? {}",
-            curr_proc_name_c_str,
+            curr_proc.name,
             tb.pc,
             NUM_BB.load(Ordering::SeqCst) - 1,
-            OSI.in_shared_object(cpu, &**curr_proc.as_ref().unwrap()),
+            curr_proc.in_shared_object(cpu),
         );
     }
 }
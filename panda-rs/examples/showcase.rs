@@ -18,7 +18,7 @@ struct Args {
 fn sys_write_test(cpu: &mut CPUState, _pc: SyscallPc, _fd: u32, buf: target_ulong, count: u32) {
     println!(
         "sys_write buf = \"{}\"",
-        String::from_utf8_lossy(&cpu.mem_read(buf, count as usize))
+        String::from_utf8_lossy(&cpu.read_mem(buf, count as usize).unwrap_or_default())
     );
 }
 
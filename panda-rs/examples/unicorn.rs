@@ -1,6 +1,6 @@
 use panda::prelude::*;
 use panda::regs::{get_reg, set_reg, set_pc, get_pc, Reg};
-use panda::mem::{map_memory, physical_memory_write, PAGE_SIZE};
+use panda::mem::{map_memory, physical_memory_write};
 
 // inc rax
 // add rbx, rax
@@ -13,7 +13,7 @@ const STOP_ADDR: target_ulong = ADDRESS + (X86_CODE.len() as target_ulong);
 #[panda::after_machine_init]
 fn setup(cpu: &mut CPUState) {
     // Map 2MB memory for this emulation
-    map_memory("mymem", 2 * 1024 * PAGE_SIZE, ADDRESS).unwrap();
+    map_memory("mymem", 2 * 1024 * 1024, ADDRESS).unwrap();
 
     // Write code into memory
     physical_memory_write(ADDRESS, X86_CODE);
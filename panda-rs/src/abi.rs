@@ -142,8 +142,8 @@ impl From<Stack> for StorageLocation {
 
 const REG_SIZE: usize = std::mem::size_of::<target_ulong>();
 
-fn is_little_endian() -> bool {
-    crate::ARCH_ENDIAN == crate::enums::Endian::Little
+fn is_little_endian(cpu: &CPUState) -> bool {
+    crate::check_endian(cpu) == crate::enums::Endian::Little
 }
 
 impl StorageLocation {
@@ -170,7 +170,7 @@ impl StorageLocation {
                     .try_into()
                     .unwrap();
 
-                if is_little_endian() {
+                if is_little_endian(cpu) {
                     target_ulong::from_le_bytes(bytes)
                 } else {
                     target_ulong::from_be_bytes(bytes)
@@ -198,7 +198,7 @@ impl StorageLocation {
             Self::StackOffset(offset) => {
                 let sp = regs::get_reg(cpu, regs::reg_sp());
 
-                let bytes = if is_little_endian() {
+                let bytes = if is_little_endian(cpu) {
                     val.to_le_bytes()
                 } else {
                     val.to_be_bytes()
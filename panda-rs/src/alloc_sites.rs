@@ -0,0 +1,200 @@
+//! Attributing heap allocations to the code which made them
+//!
+//! There is no heap tracker in panda-rs, so allocations are reported by the plugin using
+//! this module, typically from hooks on the return of the guest's `malloc` and the
+//! entry of its `free`. Each reported allocation can be sampled with the top frames of
+//! the shadow stack kept by [`callstack_instr`](crate::plugins::callstack_instr),
+//! allowing any address to later be traced back to where the buffer containing it was
+//! allocated, such as when triaging an out-of-bounds access.
+//!
+//! Sampling the shadow stack costs a call into `callstack_instr` per allocation, so the
+//! overhead can be tuned with [`set_depth`], which limits the number of frames recorded,
+//! and [`set_sample_rate`], which only samples a fraction of allocations. Allocations
+//! which aren't sampled are still tracked, but without any frames.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::alloc_sites;
+//! use panda::prelude::*;
+//!
+//! // called from the plugin's hooks on the guest's allocator
+//! fn on_malloc_return(cpu: &mut CPUState, ptr: target_ptr_t, size: target_ulong) {
+//!     alloc_sites::record_alloc(cpu, ptr, size);
+//! }
+//!
+//! fn on_free(cpu: &mut CPUState, ptr: target_ptr_t) {
+//!     alloc_sites::record_free(cpu, ptr);
+//! }
+//!
+//! fn on_bad_access(cpu: &mut CPUState, addr: target_ptr_t) {
+//!     if let Some(site) = alloc_sites::allocated_at(cpu, addr) {
+//!         println!("{:#x} is in {}", addr, site);
+//!     }
+//! }
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     // record the 4 innermost callers of every 10th allocation
+//!     alloc_sites::set_depth(4);
+//!     alloc_sites::set_sample_rate(10);
+//!     alloc_sites::start();
+//! }
+//! ```
+use crate::plugins::callstack_instr;
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// The default number of frames recorded for each sampled allocation
+pub const DEFAULT_DEPTH: usize = 8;
+
+/// A live allocation and, if it was sampled, the calls which led to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllocSite {
+    pub addr: target_ptr_t,
+    pub size: target_ulong,
+
+    /// The address space the allocation was made in
+    pub asid: target_ulong,
+
+    /// The guest instruction count when the allocation was recorded
+    pub instr_count: u64,
+
+    /// The return addresses on the shadow stack when the allocation was recorded,
+    /// starting with the innermost. Empty if the allocation wasn't sampled.
+    pub callers: Vec<target_ulong>,
+}
+
+impl AllocSite {
+    /// Check whether the given address is within the allocation
+    pub fn contains(&self, addr: target_ptr_t) -> bool {
+        addr >= self.addr && addr - self.addr < self.size.max(1)
+    }
+
+    /// Whether the shadow stack was sampled for this allocation
+    pub fn is_sampled(&self) -> bool {
+        !self.callers.is_empty()
+    }
+
+    /// The innermost return address, which is usually in the code that called the
+    /// allocator
+    pub fn call_site(&self) -> Option<target_ulong> {
+        self.callers.first().copied()
+    }
+}
+
+impl fmt::Display for AllocSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x} ({} bytes) allocated at instruction {}",
+            self.addr, self.size, self.instr_count
+        )?;
+
+        if self.is_sampled() {
+            f.write_str(" from")?;
+            for caller in &self.callers {
+                write!(f, " {:#x}", caller)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct State {
+    depth: usize,
+    sample_rate: u64,
+    count: u64,
+
+    /// Live allocations, keyed by address space and address
+    allocs: BTreeMap<(target_ulong, target_ptr_t), AllocSite>,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        depth: DEFAULT_DEPTH,
+        sample_rate: 1,
+        count: 0,
+        allocs: BTreeMap::new(),
+    });
+}
+
+/// Load `callstack_instr`, so that the shadow stack includes calls made from now on.
+/// Should be called during initialization if any allocations are to be sampled.
+pub fn start() {
+    callstack_instr::ensure_loaded();
+}
+
+/// Set the number of frames recorded for each sampled allocation. A depth of 0 disables
+/// sampling entirely.
+pub fn set_depth(depth: usize) {
+    STATE.lock().unwrap().depth = depth;
+}
+
+/// Only sample one out of every `rate` allocations. A rate of 1 (the default) samples
+/// every allocation.
+pub fn set_sample_rate(rate: u64) {
+    STATE.lock().unwrap().sample_rate = rate.max(1);
+}
+
+/// Record an allocation of `size` bytes at `addr` in the current address space,
+/// sampling the shadow stack if the allocation is selected for sampling
+pub fn record_alloc(cpu: &mut CPUState, addr: target_ptr_t, size: target_ulong) {
+    let asid = crate::current_asid(cpu);
+    let mut state = STATE.lock().unwrap();
+
+    state.count += 1;
+    let callers = if state.depth > 0 && state.count % state.sample_rate == 0 {
+        callstack_instr::callers(cpu, state.depth)
+    } else {
+        Vec::new()
+    };
+
+    state.allocs.insert(
+        (asid, addr),
+        AllocSite {
+            addr,
+            size,
+            asid,
+            instr_count: rr_get_guest_instr_count(),
+            callers,
+        },
+    );
+}
+
+/// Record that the allocation at `addr` in the current address space was freed,
+/// returning it if it was being tracked
+pub fn record_free(cpu: &mut CPUState, addr: target_ptr_t) -> Option<AllocSite> {
+    let asid = crate::current_asid(cpu);
+
+    STATE.lock().unwrap().allocs.remove(&(asid, addr))
+}
+
+/// Find the live allocation containing the given address in the current address space
+pub fn allocated_at(cpu: &mut CPUState, addr: target_ptr_t) -> Option<AllocSite> {
+    let asid = crate::current_asid(cpu);
+    let state = STATE.lock().unwrap();
+
+    state
+        .allocs
+        .range((asid, 0)..=(asid, addr))
+        .next_back()
+        .map(|(_, site)| site)
+        .filter(|site| site.contains(addr))
+        .cloned()
+}
+
+/// Get every live allocation, ordered by address space and address
+pub fn live_allocations() -> Vec<AllocSite> {
+    STATE.lock().unwrap().allocs.values().cloned().collect()
+}
+
+/// Stop tracking all allocations, such as after reverting to a snapshot
+pub fn clear() {
+    STATE.lock().unwrap().allocs.clear();
+}
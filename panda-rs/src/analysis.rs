@@ -0,0 +1,622 @@
+//! Analysis passes, run together over a single execution
+//!
+//! Tools which combine several analyses over one replay tend to grow a global static
+//! and a callback per analysis, wired together by hand in an order which only works by
+//! accident. An [`AnalysisPass`] instead declares what it needs: its name, the passes
+//! it depends on and which events it wants to see. A [`Runner`] then orders the passes
+//! so each runs after its dependencies, registers a single set of callbacks which
+//! dispatch to every pass in order, and shares a [`Context`] between them holding
+//! caches which would otherwise be duplicated by each pass:
+//!
+//! * the current process, looked up through OSI once per address space switch
+//! * symbolization of addresses to the module they lie in, cached per address space
+//! * results published by passes for the passes which depend on them
+//!
+//! Once the execution is done (such as in an `uninit` callback), [`finish`] lets
+//! every pass finish in the same order and collects their reports.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::analysis::{self, AnalysisPass, Context, Needs, Runner};
+//! use panda::prelude::*;
+//!
+//! use std::collections::HashMap;
+//!
+//! /// Counts the blocks executed in each module
+//! #[derive(Default)]
+//! struct ModuleBlocks(HashMap<String, u64>);
+//!
+//! impl AnalysisPass for ModuleBlocks {
+//!     fn name(&self) -> &'static str {
+//!         "module_blocks"
+//!     }
+//!
+//!     fn needs(&self) -> Needs {
+//!         Needs { blocks: true, ..Needs::default() }
+//!     }
+//!
+//!     fn on_block(&mut self, cpu: &mut CPUState, tb: &mut TranslationBlock, ctx: &mut Context) {
+//!         if let Some(location) = ctx.symbolize(cpu, tb.pc as target_ptr_t) {
+//!             *self.0.entry(location.module).or_default() += 1;
+//!         }
+//!     }
+//!
+//!     fn finish(&mut self, ctx: &mut Context) {
+//!         // make the counts available to passes depending on this one
+//!         ctx.publish(self.0.clone());
+//!     }
+//!
+//!     fn report(&self, _: &Context) -> Option<String> {
+//!         Some(format!("{:#?}", self.0))
+//!     }
+//! }
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     Runner::new()
+//!         .pass(ModuleBlocks::default())
+//!         .start()
+//!         .unwrap();
+//! }
+//!
+//! #[panda::uninit]
+//! fn uninit(_: &mut PluginHandle) {
+//!     for report in analysis::finish() {
+//!         println!("{}", report);
+//!     }
+//! }
+//! ```
+use crate::mem::page_size;
+use crate::plugins::osi::{self, Mapping, Process};
+use crate::prelude::*;
+use crate::Callback;
+
+#[cfg(not(feature = "ppc"))]
+use crate::{plugins::syscalls2::Syscalls2Callbacks, PppCallback};
+
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+
+/// The events a pass wants to be called for. Callbacks for an event are only
+/// registered if at least one pass needs it.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Needs {
+    /// [`AnalysisPass::on_block`], before each basic block executes
+    pub blocks: bool,
+
+    /// [`AnalysisPass::on_process_change`], when the address space changes
+    pub process_changes: bool,
+
+    /// [`AnalysisPass::on_syscall`], on each syscall. Requires syscalls2.
+    #[cfg(not(feature = "ppc"))]
+    pub syscalls: bool,
+}
+
+impl Needs {
+    fn union(self, other: Needs) -> Needs {
+        Needs {
+            blocks: self.blocks || other.blocks,
+            process_changes: self.process_changes || other.process_changes,
+            #[cfg(not(feature = "ppc"))]
+            syscalls: self.syscalls || other.syscalls,
+        }
+    }
+}
+
+/// A single analysis, run alongside others by a [`Runner`]
+///
+/// Every method other than [`name`](Self::name) has a default which does nothing, so
+/// passes only implement the events they need. The callbacks are run while the
+/// runner is locked, so must not start a runner or call [`finish`].
+pub trait AnalysisPass: Send + 'static {
+    /// A name identifying the pass, unique among the passes of a runner
+    fn name(&self) -> &'static str;
+
+    /// The names of the passes which must run before this one, for example because
+    /// this pass reads what they [`publish`](Context::publish)
+    fn dependencies(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// The events the pass wants to be called for
+    fn needs(&self) -> Needs {
+        Needs::default()
+    }
+
+    /// Called once when the runner starts, after the passes this depends on
+    fn init(&mut self, _ctx: &mut Context) {}
+
+    /// Called before each basic block executes, if [`Needs::blocks`] is set
+    fn on_block(&mut self, _cpu: &mut CPUState, _tb: &mut TranslationBlock, _ctx: &mut Context) {}
+
+    /// Called after the address space changes, with the process now running if OSI
+    /// could find one, if [`Needs::process_changes`] is set
+    fn on_process_change(
+        &mut self,
+        _cpu: &mut CPUState,
+        _process: Option<&Process>,
+        _ctx: &mut Context,
+    ) {
+    }
+
+    /// Called on entry to each syscall, if [`Needs::syscalls`] is set
+    #[cfg(not(feature = "ppc"))]
+    fn on_syscall(
+        &mut self,
+        _cpu: &mut CPUState,
+        _pc: SyscallPc,
+        _syscall_num: target_ulong,
+        _ctx: &mut Context,
+    ) {
+    }
+
+    /// Called once by [`finish`], after the passes this depends on have finished
+    fn finish(&mut self, _ctx: &mut Context) {}
+
+    /// The report of the pass, collected by [`finish`] after every pass has finished
+    fn report(&self, _ctx: &Context) -> Option<String> {
+        None
+    }
+}
+
+/// Where an address lies within the memory mappings of its process
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    /// The name of the mapping (or the path of the file backing it if it has no name)
+    pub module: String,
+
+    /// The offset of the address from the start of the mapping
+    pub offset: target_ptr_t,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.module, self.offset)
+    }
+}
+
+/// The most address spaces to cache the mappings of, after which the least recently
+/// used is evicted
+const MAX_CACHED_ADDRESS_SPACES: usize = 64;
+
+/// The mappings of an address space, along with pages which weren't in any mapping the
+/// last time the mappings were read
+#[derive(Default)]
+struct MappingCache {
+    mappings: Vec<Mapping>,
+    unmapped_pages: HashSet<target_ptr_t>,
+
+    /// The value of [`Context::uses`] when the cache was last used
+    last_used: u64,
+}
+
+/// State shared between the passes of a runner
+#[derive(Default)]
+pub struct Context {
+    /// The current process, `None` if it hasn't been looked up since the last address
+    /// space change
+    process: Option<Option<Process>>,
+    mappings: HashMap<target_ulong, MappingCache>,
+
+    /// The number of times the mapping cache has been used, for evicting the least
+    /// recently used address space
+    uses: u64,
+    published: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl Context {
+    /// Get the process currently running, looked up through OSI at most once per
+    /// address space change
+    pub fn current_process(&mut self, cpu: &mut CPUState) -> Option<&Process> {
+        self.process
+            .get_or_insert_with(|| osi::current_process(cpu))
+            .as_ref()
+    }
+
+    /// Find which mapping of the current process an address lies within
+    ///
+    /// Mappings are cached per address space, and only read again when an address
+    /// isn't within any of the cached mappings (such as after a library is loaded).
+    /// The mappings of at most 64 address spaces are cached at once.
+    pub fn symbolize(&mut self, cpu: &mut CPUState, addr: target_ptr_t) -> Option<Location> {
+        let asid = crate::current_asid(cpu);
+        let page = addr & !(page_size() as target_ptr_t - 1);
+
+        self.uses += 1;
+        let uses = self.uses;
+
+        let find = |cache: &MappingCache| {
+            cache
+                .mappings
+                .iter()
+                .find(|mapping| mapping.contains(addr))
+                .map(|mapping| Location {
+                    module: mapping
+                        .name
+                        .clone()
+                        .or_else(|| mapping.file.clone())
+                        .unwrap_or_default(),
+                    offset: addr - mapping.base,
+                })
+        };
+
+        if let Some(cache) = self.mappings.get_mut(&asid) {
+            cache.last_used = uses;
+
+            if let Some(location) = find(cache) {
+                return Some(location);
+            }
+
+            if cache.unmapped_pages.contains(&page) {
+                return None;
+            }
+        }
+
+        let mappings = self
+            .current_process(cpu)
+            .cloned()
+            .map(|process| process.mappings(cpu))
+            .unwrap_or_default();
+
+        if !self.mappings.contains_key(&asid) && self.mappings.len() >= MAX_CACHED_ADDRESS_SPACES {
+            let oldest = self
+                .mappings
+                .iter()
+                .min_by_key(|(_, cache)| cache.last_used)
+                .map(|(&asid, _)| asid);
+
+            if let Some(oldest) = oldest {
+                self.mappings.remove(&oldest);
+            }
+        }
+
+        let cache = self.mappings.entry(asid).or_default();
+        cache.last_used = uses;
+        if cache.mappings != mappings {
+            cache.mappings = mappings;
+            cache.unmapped_pages.clear();
+        }
+
+        let location = find(cache);
+        if location.is_none() {
+            cache.unmapped_pages.insert(page);
+        }
+
+        location
+    }
+
+    /// Make a value available to other passes, replacing any value of the same type
+    pub fn publish<T: Any + Send>(&mut self, value: T) {
+        self.published.insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    /// Get a value published by another pass
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.published.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Get a mutable reference to a value published by another pass
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.published.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Forget the cached process and mappings, such as after reverting to a snapshot
+    pub fn clear_caches(&mut self) {
+        self.process = None;
+        self.mappings.clear();
+    }
+}
+
+/// An error in the passes given to a [`Runner`]
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum AnalysisError {
+    #[error("more than one pass is named {0:?}")]
+    DuplicatePass(&'static str),
+
+    #[error("pass {pass:?} depends on {dependency:?}, which was not added")]
+    MissingDependency {
+        pass: &'static str,
+        dependency: &'static str,
+    },
+
+    #[error("passes {0:?} depend on each other")]
+    Cycle(Vec<&'static str>),
+
+    #[error("an analysis runner has already been started")]
+    AlreadyRunning,
+}
+
+/// The report of a single pass, returned by [`finish`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassReport {
+    pub pass: &'static str,
+    pub report: String,
+}
+
+impl fmt::Display for PassReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "== {} ==", self.pass)?;
+        write!(f, "{}", self.report)
+    }
+}
+
+struct Running {
+    /// Passes in dependency order
+    passes: Vec<Box<dyn AnalysisPass>>,
+    ctx: Context,
+}
+
+lazy_static::lazy_static! {
+    static ref RUNNING: Mutex<Option<Running>> = Mutex::new(None);
+    static ref CALLBACKS: (Callback, Callback) = install_callbacks();
+}
+
+#[cfg(not(feature = "ppc"))]
+lazy_static::lazy_static! {
+    // only installed once a pass needs it, as it loads syscalls2
+    static ref SYSCALL_CALLBACK: PppCallback = install_syscall_callback();
+}
+
+fn install_callbacks() -> (Callback, Callback) {
+    let block = Callback::new();
+    let asid = Callback::new();
+
+    block.before_block_exec(|cpu, tb| {
+        if let Some(running) = RUNNING.lock().unwrap().as_mut() {
+            for pass in running.passes.iter_mut().filter(|pass| pass.needs().blocks) {
+                pass.on_block(cpu, tb, &mut running.ctx);
+            }
+        }
+    });
+
+    asid.asid_changed(|cpu, _, _| {
+        if let Some(running) = RUNNING.lock().unwrap().as_mut() {
+            running.ctx.process = None;
+
+            let wants_changes = |pass: &Box<dyn AnalysisPass>| pass.needs().process_changes;
+            if running.passes.iter().any(wants_changes) {
+                let process = running.ctx.current_process(cpu).cloned();
+
+                for pass in running.passes.iter_mut().filter(|pass| wants_changes(pass)) {
+                    pass.on_process_change(cpu, process.as_ref(), &mut running.ctx);
+                }
+            }
+        }
+
+        false
+    });
+
+    block.disable();
+
+    (block, asid)
+}
+
+#[cfg(not(feature = "ppc"))]
+fn install_syscall_callback() -> PppCallback {
+    let syscall = PppCallback::new();
+
+    syscall.on_all_sys_enter(|cpu, pc, syscall_num| {
+        if let Some(running) = RUNNING.lock().unwrap().as_mut() {
+            for pass in running
+                .passes
+                .iter_mut()
+                .filter(|pass| pass.needs().syscalls)
+            {
+                pass.on_syscall(cpu, pc, syscall_num, &mut running.ctx);
+            }
+        }
+    });
+
+    syscall
+}
+
+/// Order passes so that each comes after its dependencies, keeping the order they were
+/// added in otherwise
+fn order_passes(
+    mut passes: Vec<Box<dyn AnalysisPass>>,
+) -> Result<Vec<Box<dyn AnalysisPass>>, AnalysisError> {
+    let mut names = HashSet::new();
+    for pass in &passes {
+        if !names.insert(pass.name()) {
+            return Err(AnalysisError::DuplicatePass(pass.name()));
+        }
+    }
+
+    for pass in &passes {
+        if let Some(&dependency) = pass.dependencies().iter().find(|dep| !names.contains(*dep)) {
+            return Err(AnalysisError::MissingDependency {
+                pass: pass.name(),
+                dependency,
+            });
+        }
+    }
+
+    let mut ordered: Vec<Box<dyn AnalysisPass>> = Vec::with_capacity(passes.len());
+    let mut placed = HashSet::new();
+
+    while !passes.is_empty() {
+        let ready = passes
+            .iter()
+            .position(|pass| pass.dependencies().iter().all(|dep| placed.contains(dep)));
+
+        match ready {
+            Some(i) => {
+                let pass = passes.remove(i);
+                placed.insert(pass.name());
+                ordered.push(pass);
+            }
+            None => {
+                return Err(AnalysisError::Cycle(
+                    passes.iter().map(|pass| pass.name()).collect(),
+                ))
+            }
+        }
+    }
+
+    Ok(ordered)
+}
+
+/// Runs a set of analysis passes over the current execution
+#[derive(Default)]
+pub struct Runner {
+    passes: Vec<Box<dyn AnalysisPass>>,
+}
+
+impl Runner {
+    /// A runner with no passes, to be added with [`pass`](Self::pass)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a pass to the runner
+    pub fn pass(mut self, pass: impl AnalysisPass) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Order the passes by their dependencies, initialize them and start calling them
+    /// for the events they need. Only one runner can be running at a time, until
+    /// [`finish`] is called.
+    pub fn start(self) -> Result<(), AnalysisError> {
+        let mut running = RUNNING.lock().unwrap();
+        if running.is_some() {
+            return Err(AnalysisError::AlreadyRunning);
+        }
+
+        let mut passes = order_passes(self.passes)?;
+        let mut ctx = Context::default();
+        let needs = passes
+            .iter()
+            .fold(Needs::default(), |needs, pass| needs.union(pass.needs()));
+
+        for pass in &mut passes {
+            pass.init(&mut ctx);
+        }
+
+        *running = Some(Running { passes, ctx });
+        drop(running);
+
+        let (block, asid) = &*CALLBACKS;
+        asid.enable();
+        if needs.blocks {
+            block.enable();
+        }
+
+        #[cfg(not(feature = "ppc"))]
+        if needs.syscalls {
+            SYSCALL_CALLBACK.enable();
+        }
+
+        Ok(())
+    }
+}
+
+/// Stop the running passes, finishing each in dependency order, and collect their
+/// reports. Returns no reports if no runner is running.
+pub fn finish() -> Vec<PassReport> {
+    let running = RUNNING.lock().unwrap().take();
+    let Running {
+        mut passes,
+        mut ctx,
+    } = match running {
+        Some(running) => running,
+        None => return Vec::new(),
+    };
+
+    let (block, asid) = &*CALLBACKS;
+    block.disable();
+    asid.disable();
+
+    #[cfg(not(feature = "ppc"))]
+    if passes.iter().any(|pass| pass.needs().syscalls) {
+        SYSCALL_CALLBACK.disable();
+    }
+
+    for pass in &mut passes {
+        pass.finish(&mut ctx);
+    }
+
+    passes
+        .iter()
+        .filter_map(|pass| {
+            pass.report(&ctx).map(|report| PassReport {
+                pass: pass.name(),
+                report,
+            })
+        })
+        .collect()
+}
+
+/// Check whether a runner has been started and not yet finished
+pub fn is_running() -> bool {
+    RUNNING.lock().unwrap().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Pass(&'static str, &'static [&'static str]);
+
+    impl AnalysisPass for Pass {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn dependencies(&self) -> &[&'static str] {
+            self.1
+        }
+    }
+
+    fn order(passes: Vec<Pass>) -> Result<Vec<&'static str>, AnalysisError> {
+        let passes = passes
+            .into_iter()
+            .map(|pass| Box::new(pass) as Box<dyn AnalysisPass>)
+            .collect();
+
+        order_passes(passes).map(|passes| passes.iter().map(|pass| pass.name()).collect())
+    }
+
+    #[test]
+    fn test_order_passes_keeps_independent_order() {
+        assert_eq!(
+            order(vec![Pass("a", &[]), Pass("b", &[]), Pass("c", &[])]),
+            Ok(vec!["a", "b", "c"])
+        );
+    }
+
+    #[test]
+    fn test_order_passes_after_dependencies() {
+        assert_eq!(
+            order(vec![
+                Pass("report", &["calls", "blocks"]),
+                Pass("calls", &["blocks"]),
+                Pass("blocks", &[]),
+                Pass("other", &[]),
+            ]),
+            Ok(vec!["blocks", "calls", "report", "other"])
+        );
+    }
+
+    #[test]
+    fn test_order_passes_errors() {
+        assert_eq!(
+            order(vec![Pass("a", &[]), Pass("a", &[])]),
+            Err(AnalysisError::DuplicatePass("a"))
+        );
+        assert_eq!(
+            order(vec![Pass("a", &["b"])]),
+            Err(AnalysisError::MissingDependency {
+                pass: "a",
+                dependency: "b"
+            })
+        );
+        assert_eq!(
+            order(vec![Pass("a", &[]), Pass("b", &["c"]), Pass("c", &["b"])]),
+            Err(AnalysisError::Cycle(vec!["b", "c"]))
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Translation block execution counting without per-block callbacks
+//!
+//! Counting block executions with [`before_block_exec`](crate::before_block_exec) calls
+//! into Rust for every block executed, which dominates the cost of profiling a whole
+//! replay. Instead, this module injects a counter increment into the TCG ops of each
+//! translation block as it is generated, so counting happens entirely in translated
+//! code. Counters live in host memory owned by this module and are only read when
+//! [`counts`] is called.
+//!
+//! Counters are keyed by the guest pc of the block. Blocks at the same virtual address
+//! in different address spaces share a counter, and blocks which are retranslated keep
+//! their counter. Increments from different vCPUs are not atomic, so counts may be
+//! slightly low for guests with several vCPUs.
+//!
+//! Only code executed by TCG is counted: blocks executed in LLVM mode are not
+//! instrumented, and a translation block which is exited early (for example due to an
+//! exception in its first instruction) may still be counted.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::block_count;
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     block_count::enable();
+//! }
+//!
+//! #[panda::uninit]
+//! fn uninit(_: &mut PluginHandle) {
+//!     for (pc, count) in block_count::hottest(10) {
+//!         println!("{:#x}: {}", pc, count);
+//!     }
+//! }
+//! ```
+use crate::prelude::*;
+use crate::tb_invalidation::flush_tb;
+use crate::Callback;
+
+use panda_sys::{
+    tcg_ctx, tcg_op_insert_after, tcg_temp_free_i64, tcg_temp_new_internal_i64, TCGArg, TCGContext,
+    TCGOp, TCGOpcode, TCGOpcode_INDEX_op_add_i64, TCGOpcode_INDEX_op_insn_start,
+    TCGOpcode_INDEX_op_ld_i64, TCGOpcode_INDEX_op_movi_i64, TCGOpcode_INDEX_op_st_i64,
+    TranslationBlock,
+};
+
+use std::collections::HashMap;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+const CHUNK_SIZE: usize = 4096;
+
+/// The number of ops and op parameters injected into each block
+const INJECTED_OPS: usize = 5;
+const INJECTED_PARAMS: usize = 13;
+
+/// Counters, allocated in fixed-size chunks so that their addresses never change once
+/// they have been baked into translated code
+#[derive(Default)]
+struct Counters {
+    chunks: Vec<Box<[u64; CHUNK_SIZE]>>,
+    slots: HashMap<target_ulong, usize>,
+}
+
+impl Counters {
+    fn counter(&mut self, pc: target_ulong) -> *mut u64 {
+        let next_slot = self.slots.len();
+        let slot = *self.slots.entry(pc).or_insert(next_slot);
+
+        if slot / CHUNK_SIZE >= self.chunks.len() {
+            self.chunks.push(Box::new([0; CHUNK_SIZE]));
+        }
+
+        &mut self.chunks[slot / CHUNK_SIZE][slot % CHUNK_SIZE]
+    }
+
+    fn read(&self, slot: usize) -> u64 {
+        // Translated code increments counters without holding the lock
+        unsafe { ptr::read_volatile(&self.chunks[slot / CHUNK_SIZE][slot % CHUNK_SIZE]) }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref COUNTERS: Mutex<Counters> = Mutex::new(Counters::default());
+    static ref CALLBACK: Callback = install_callback();
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn install_callback() -> Callback {
+    let callback = Callback::new();
+
+    callback.before_tcg_codegen(|_, tb| {
+        if ENABLED.load(Ordering::SeqCst) {
+            instrument(tb);
+        }
+    });
+
+    callback
+}
+
+fn instrument(tb: &mut TranslationBlock) {
+    let s = unsafe { &mut *ptr::addr_of_mut!(tcg_ctx) };
+
+    let ops_full = s.gen_next_op_idx as usize + INJECTED_OPS >= s.gen_op_buf.len();
+    let params_full = s.gen_next_parm_idx as usize + INJECTED_PARAMS >= s.gen_opparam_buf.len();
+    if ops_full || params_full {
+        return;
+    }
+
+    // Insert after the first instruction's start marker, so blocks exited by the
+    // interrupt check at the start of the block aren't counted
+    let mut op_idx = s.gen_op_buf[0].next() as usize;
+    while op_idx != 0 && s.gen_op_buf[op_idx].opc() != TCGOpcode_INDEX_op_insn_start {
+        op_idx = s.gen_op_buf[op_idx].next() as usize;
+    }
+
+    if op_idx == 0 {
+        return;
+    }
+
+    let counter = COUNTERS.lock().unwrap().counter(tb.pc);
+
+    unsafe {
+        // The argument for a temp is its index, which is what TCGv_i64 encodes
+        let addr = tcg_temp_new_internal_i64(0);
+        let value = tcg_temp_new_internal_i64(0);
+        let one = tcg_temp_new_internal_i64(0);
+        let (addr_arg, value_arg, one_arg) = (addr as TCGArg, value as TCGArg, one as TCGArg);
+
+        let mut op: *mut TCGOp = &mut s.gen_op_buf[op_idx];
+        for (opc, args) in [
+            (
+                TCGOpcode_INDEX_op_movi_i64,
+                &[addr_arg, counter as TCGArg][..],
+            ),
+            (TCGOpcode_INDEX_op_ld_i64, &[value_arg, addr_arg, 0][..]),
+            (TCGOpcode_INDEX_op_movi_i64, &[one_arg, 1][..]),
+            (
+                TCGOpcode_INDEX_op_add_i64,
+                &[value_arg, value_arg, one_arg][..],
+            ),
+            (TCGOpcode_INDEX_op_st_i64, &[value_arg, addr_arg, 0][..]),
+        ]
+        .iter()
+        {
+            op = insert_op(s, op, *opc, args);
+        }
+
+        tcg_temp_free_i64(one);
+        tcg_temp_free_i64(value);
+        tcg_temp_free_i64(addr);
+    }
+}
+
+unsafe fn insert_op(
+    s: &mut TCGContext,
+    after: *mut TCGOp,
+    opc: TCGOpcode,
+    args: &[TCGArg],
+) -> *mut TCGOp {
+    let op = tcg_op_insert_after(s, after, opc, args.len() as _);
+    let first_arg = (*op).args() as usize;
+
+    s.gen_opparam_buf[first_arg..first_arg + args.len()].copy_from_slice(args);
+
+    op
+}
+
+/// Start counting translation block executions. The translation cache is flushed so
+/// that blocks translated before counting was enabled are instrumented as well.
+pub fn enable() {
+    lazy_static::initialize(&CALLBACK);
+
+    if !ENABLED.swap(true, Ordering::SeqCst) {
+        flush_tb();
+    }
+}
+
+/// Stop counting translation block executions. The translation cache is flushed so
+/// that instrumented blocks are discarded. Counts gathered so far are kept until
+/// [`reset`] is called.
+pub fn disable() {
+    if ENABLED.swap(false, Ordering::SeqCst) {
+        flush_tb();
+    }
+}
+
+/// Check whether translation block executions are currently being counted
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Get the number of times each translation block has been executed, keyed by the
+/// guest pc of the block
+pub fn counts() -> HashMap<target_ulong, u64> {
+    let counters = COUNTERS.lock().unwrap();
+
+    counters
+        .slots
+        .iter()
+        .map(|(&pc, &slot)| (pc, counters.read(slot)))
+        .collect()
+}
+
+/// Get the number of times the translation block at the given pc has been executed
+pub fn count(pc: target_ulong) -> u64 {
+    let counters = COUNTERS.lock().unwrap();
+
+    counters
+        .slots
+        .get(&pc)
+        .map_or(0, |&slot| counters.read(slot))
+}
+
+/// Get the `n` most executed translation blocks, most executed first
+pub fn hottest(n: usize) -> Vec<(target_ulong, u64)> {
+    let mut counts: Vec<_> = counts().into_iter().collect();
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(n);
+
+    counts
+}
+
+/// Reset all counts to zero
+pub fn reset() {
+    let mut counters = COUNTERS.lock().unwrap();
+
+    for chunk in &mut counters.chunks {
+        for counter in chunk.iter_mut() {
+            unsafe { ptr::write_volatile(counter, 0) };
+        }
+    }
+}
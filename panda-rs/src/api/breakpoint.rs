@@ -0,0 +1,279 @@
+//! Breakpoints at exact points in the execution of the guest
+//!
+//! [`at_instruction_count`] runs a callback right before the guest executes its `n`th
+//! instruction, as counted by [`rr_get_guest_instr_count`]. This allows reproducing a
+//! finding reported by another tool as an instruction count into a replay.
+//!
+//! Execution is only checked at the start of each basic block until a block containing
+//! a breakpoint is reached. That block is then retranslated with every instruction
+//! instrumented, so the guest is stepped one instruction at a time for the final
+//! stretch, while the rest of execution runs at full speed.
+//!
+//! Instruction counts are only deterministic while recording or replaying, or when
+//! running live in icount mode (see [`Panda::icount`](crate::Panda::icount)).
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::{breakpoint, prelude::*};
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     breakpoint::at_instruction_count(1_234_567, |cpu| {
+//!         println!("pc at instruction 1234567: {:#x}", panda::current_pc(cpu));
+//!     });
+//! }
+//! ```
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+use crate::Callback;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+type BreakpointCallback = Box<dyn FnOnce(&mut CPUState) + Send + 'static>;
+
+/// An identifier for a breakpoint, used to remove it before it has been hit
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Breakpoint {
+    id: u64,
+    instr_count: u64,
+}
+
+impl Breakpoint {
+    /// The instruction count the breakpoint is set at
+    pub fn instr_count(&self) -> u64 {
+        self.instr_count
+    }
+
+    /// Remove the breakpoint, returning `false` if it has already been hit
+    pub fn remove(self) -> bool {
+        let mut state = STATE.lock().unwrap();
+        let breakpoints = match state.breakpoints.get_mut(&self.instr_count) {
+            Some(breakpoints) => breakpoints,
+            None => return false,
+        };
+
+        let len = breakpoints.len();
+        breakpoints.retain(|(id, _)| *id != self.id);
+        let removed = breakpoints.len() != len;
+
+        if breakpoints.is_empty() {
+            state.breakpoints.remove(&self.instr_count);
+        }
+
+        removed
+    }
+}
+
+/// The block currently being stepped through
+struct Step {
+    /// The guest code covered by the block
+    start: target_ulong,
+    end: target_ulong,
+
+    /// The instruction count of the next instruction to execute
+    next: u64,
+}
+
+#[derive(Default)]
+struct State {
+    breakpoints: BTreeMap<u64, Vec<(u64, BreakpointCallback)>>,
+    step: Option<Step>,
+
+    /// The address and pc of the last block translated with every instruction
+    /// instrumented, so it isn't retranslated again
+    instrumented: Option<(usize, target_ulong)>,
+}
+
+impl State {
+    fn add(&mut self, id: u64, instr_count: u64, callback: BreakpointCallback) {
+        self.breakpoints
+            .entry(instr_count)
+            .or_default()
+            .push((id, callback));
+    }
+
+    /// Start executing a block of `icount` instructions covering `start..end`, with
+    /// `count` instructions executed before it. Returns whether the block contains a
+    /// breakpoint, in which case it is stepped through one instruction at a time.
+    fn enter_block(
+        &mut self,
+        count: u64,
+        start: target_ulong,
+        end: target_ulong,
+        icount: u64,
+    ) -> bool {
+        // breakpoints which were set in the past will never be hit
+        while let Some(&first) = self.breakpoints.keys().next() {
+            if first >= count {
+                break;
+            }
+
+            self.breakpoints.remove(&first);
+        }
+
+        let in_block = match self.breakpoints.keys().next() {
+            Some(&next) => next < count + icount,
+            None => false,
+        };
+
+        self.step = if in_block {
+            Some(Step {
+                start,
+                end,
+                next: count,
+            })
+        } else {
+            None
+        };
+
+        in_block
+    }
+
+    /// Step over the next instruction of the block being stepped through, returning
+    /// the callbacks of the breakpoints set at it
+    fn step_insn(&mut self) -> Option<Vec<(u64, BreakpointCallback)>> {
+        let step = self.step.as_mut()?;
+        let count = step.next;
+        step.next += 1;
+
+        self.breakpoints.remove(&count)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+    static ref CALLBACKS: [Callback; 4] = install_callbacks();
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn install_callbacks() -> [Callback; 4] {
+    let block = Callback::new();
+    let after_translate = Callback::new();
+    let translate = Callback::new();
+    let exec = Callback::new();
+
+    block.before_block_exec_invalidate_opt(|_, tb| {
+        let count = rr_get_guest_instr_count();
+        let end = tb.pc + tb.size as target_ulong;
+
+        let mut state = STATE.lock().unwrap();
+        if !state.enter_block(count, tb.pc, end, tb.icount as u64) {
+            return false;
+        }
+
+        // retranslate the block with each instruction instrumented, unless it already is
+        let pc = tb.pc;
+        state.instrumented != Some((tb as *mut TranslationBlock as usize, pc))
+    });
+
+    after_translate.after_block_translate(|_, tb| {
+        let mut state = STATE.lock().unwrap();
+        let pc = tb.pc;
+        if matches!(&state.step, Some(step) if step.start == pc) {
+            state.instrumented = Some((tb as *mut TranslationBlock as usize, pc));
+        }
+    });
+
+    translate.insn_translate(|_, pc| match &STATE.lock().unwrap().step {
+        Some(step) => (step.start..step.end).contains(&pc),
+        None => false,
+    });
+
+    exec.insn_exec(|cpu, _| {
+        let callbacks = match STATE.lock().unwrap().step_insn() {
+            Some(callbacks) => callbacks,
+            None => return,
+        };
+
+        // run without the lock held so callbacks can set further breakpoints
+        for (_, callback) in callbacks {
+            callback(cpu);
+        }
+    });
+
+    [block, after_translate, translate, exec]
+}
+
+/// Run a callback right before the guest executes the instruction at the given
+/// instruction count, that is, the instruction executed when
+/// [`rr_get_guest_instr_count`] reaches `instr_count`. Breakpoints at counts which
+/// have already passed are never hit.
+///
+/// The callback must not end the replay or change the control flow of the guest, as it
+/// is run from within a basic block.
+pub fn at_instruction_count(
+    instr_count: u64,
+    callback: impl FnOnce(&mut CPUState) + Send + 'static,
+) -> Breakpoint {
+    lazy_static::initialize(&CALLBACKS);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    STATE
+        .lock()
+        .unwrap()
+        .add(id, instr_count, Box::new(callback));
+
+    Breakpoint { id, instr_count }
+}
+
+/// Get the instruction counts of every breakpoint which hasn't been hit yet, in order
+pub fn pending() -> Vec<u64> {
+    STATE.lock().unwrap().breakpoints.keys().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_with(counts: &[u64]) -> State {
+        let mut state = State::default();
+        for (id, &count) in counts.iter().enumerate() {
+            state.add(id as u64, count, Box::new(|_| ()));
+        }
+
+        state
+    }
+
+    fn hit_ids(state: &mut State) -> Option<Vec<u64>> {
+        state
+            .step_insn()
+            .map(|callbacks| callbacks.into_iter().map(|(id, _)| id).collect())
+    }
+
+    #[test]
+    fn test_past_breakpoints_dropped() {
+        let mut state = state_with(&[5, 20]);
+
+        assert!(!state.enter_block(10, 0x1000, 0x1010, 4));
+        assert_eq!(state.breakpoints.keys().copied().collect::<Vec<_>>(), [20]);
+        assert!(hit_ids(&mut state).is_none());
+    }
+
+    #[test]
+    fn test_breakpoint_after_block() {
+        let mut state = state_with(&[14]);
+
+        // the block runs instructions 10 to 13
+        assert!(!state.enter_block(10, 0x1000, 0x1010, 4));
+        assert!(state.step.is_none());
+        assert!(state.enter_block(14, 0x1010, 0x1020, 4));
+    }
+
+    #[test]
+    fn test_breakpoint_hit_at_count() {
+        let mut state = state_with(&[10, 12, 13, 13]);
+
+        assert!(state.enter_block(10, 0x1000, 0x1010, 4));
+
+        // the first instruction of the block is the one at the count the block starts at
+        assert_eq!(hit_ids(&mut state), Some(vec![0]));
+        assert_eq!(hit_ids(&mut state), None);
+        assert_eq!(hit_ids(&mut state), Some(vec![1]));
+        assert_eq!(hit_ids(&mut state), Some(vec![2, 3]));
+        assert!(state.breakpoints.is_empty());
+    }
+}
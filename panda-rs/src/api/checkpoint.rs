@@ -0,0 +1,230 @@
+//! Checkpointing and restoring within a replay
+//!
+//! PANDA can save in-memory checkpoints of the guest while replaying, and later restore
+//! one to rewind the replay to the point it was taken, allowing a plugin to travel back
+//! in time (for example to re-execute a region with heavier instrumentation once it
+//! turns out to be interesting).
+//!
+//! Checkpoints can't be taken or restored in the middle of executing a block, so
+//! [`Checkpoint::take`] and [`Checkpoint::restore`] queue the operation to be run from
+//! PANDA's main loop as an [`idle`](crate::idle) task, in the order they were
+//! requested, making them safe to call from any callback. A checkpoint handle is returned immediately and can
+//! be restored straight away, as the restore is queued after the checkpoint is taken.
+//! Callbacks registered with [`on_restore`] are run after each restore.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::checkpoint::Checkpoint;
+//! use panda::prelude::*;
+//!
+//! use std::sync::atomic::{AtomicBool, Ordering};
+//! use std::sync::Mutex;
+//!
+//! lazy_static::lazy_static! {
+//!     static ref START: Mutex<Option<Checkpoint>> = Mutex::new(None);
+//! }
+//!
+//! static REWOUND: AtomicBool = AtomicBool::new(false);
+//!
+//! #[panda::before_block_exec]
+//! fn before_block(_: &mut CPUState, tb: &mut TranslationBlock) {
+//!     if REWOUND.load(Ordering::SeqCst) {
+//!         return;
+//!     }
+//!
+//!     let mut start = START.lock().unwrap();
+//!
+//!     if tb.pc == 0x401000 && start.is_none() {
+//!         *start = Checkpoint::take().ok();
+//!     } else if tb.pc == 0x402000 {
+//!         // go back and run the region again, only once
+//!         if let Some(start) = start.take() {
+//!             REWOUND.store(true, Ordering::SeqCst);
+//!             start.restore();
+//!         }
+//!     }
+//! }
+//! ```
+use crate::idle::{self, TaskStatus};
+use crate::rr::{in_replay, rr_get_guest_instr_count};
+
+use crate::sys::{get_num_checkpoints, panda_checkpoint, panda_restore};
+
+use std::os::raw::c_void;
+use std::sync::Mutex;
+
+/// An error in taking a checkpoint
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointError {
+    #[error("checkpoints can only be taken while replaying")]
+    NotReplaying,
+}
+
+/// A handle to a checkpoint of the replay, which may not have been taken yet
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Checkpoint(usize);
+
+struct Slot {
+    /// PANDA's checkpoint, once it has been taken
+    checkpoint: Option<*mut c_void>,
+
+    /// The instruction count the checkpoint was taken at, once it has been taken
+    instr_count: Option<u64>,
+}
+
+enum Op {
+    Take(Checkpoint),
+    Restore(Checkpoint),
+}
+
+type RestoreCallback = Box<dyn FnMut(Checkpoint) + Send + 'static>;
+
+#[derive(Default)]
+struct State {
+    slots: Vec<Slot>,
+    pending: Vec<Op>,
+    on_restore: Vec<RestoreCallback>,
+
+    /// Whether an idle task has been spawned to run the pending operations
+    scheduled: bool,
+}
+
+// checkpoints are only ever used from the main loop
+unsafe impl Send for State {}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+/// Run the pending operations, returning whether more have been queued since
+fn run_pending() -> TaskStatus {
+    let pending = std::mem::take(&mut STATE.lock().unwrap().pending);
+
+    for op in pending {
+        match op {
+            Op::Take(Checkpoint(id)) => {
+                // the replay may have ended since the checkpoint was requested
+                if in_replay() {
+                    let mut state = STATE.lock().unwrap();
+                    let slot = &mut state.slots[id];
+                    slot.checkpoint = Some(unsafe { panda_checkpoint() });
+                    slot.instr_count = Some(rr_get_guest_instr_count());
+                }
+            }
+            Op::Restore(checkpoint) => {
+                let ptr = match STATE.lock().unwrap().slots[checkpoint.0].checkpoint {
+                    Some(ptr) if in_replay() => ptr,
+                    _ => continue,
+                };
+
+                unsafe {
+                    panda_restore(ptr);
+                }
+
+                // run without the lock held, so the callbacks can take or restore
+                // checkpoints
+                let mut callbacks = std::mem::take(&mut STATE.lock().unwrap().on_restore);
+                for callback in &mut callbacks {
+                    callback(checkpoint);
+                }
+
+                let mut state = STATE.lock().unwrap();
+                callbacks.append(&mut state.on_restore);
+                state.on_restore = callbacks;
+            }
+        }
+    }
+
+    let mut state = STATE.lock().unwrap();
+    if state.pending.is_empty() {
+        state.scheduled = false;
+        TaskStatus::Done
+    } else {
+        TaskStatus::Pending
+    }
+}
+
+fn queue(op: Op) {
+    let mut state = STATE.lock().unwrap();
+    state.pending.push(op);
+
+    if !std::mem::replace(&mut state.scheduled, true) {
+        idle::spawn(|_| run_pending());
+    }
+}
+
+impl Checkpoint {
+    /// Take a checkpoint of the replay from the main loop, once the current block has
+    /// finished executing
+    pub fn take() -> Result<Self, CheckpointError> {
+        if !in_replay() {
+            return Err(CheckpointError::NotReplaying);
+        }
+
+        let checkpoint = {
+            let mut state = STATE.lock().unwrap();
+            state.slots.push(Slot {
+                checkpoint: None,
+                instr_count: None,
+            });
+
+            Checkpoint(state.slots.len() - 1)
+        };
+
+        queue(Op::Take(checkpoint));
+
+        Ok(checkpoint)
+    }
+
+    /// Rewind the replay to this checkpoint from the main loop. Has no effect if the
+    /// replay has ended by then.
+    pub fn restore(self) {
+        queue(Op::Restore(self));
+    }
+
+    /// Check whether the checkpoint has been taken yet
+    pub fn is_taken(self) -> bool {
+        self.instr_count().is_some()
+    }
+
+    /// The guest instruction count the checkpoint was taken at, or `None` if it hasn't
+    /// been taken yet
+    pub fn instr_count(self) -> Option<u64> {
+        STATE.lock().unwrap().slots[self.0].instr_count
+    }
+
+    /// Get the latest checkpoint taken at or before the given instruction count, the
+    /// one to restore to in order to rewind to that point
+    pub fn latest_before(instr_count: u64) -> Option<Self> {
+        STATE
+            .lock()
+            .unwrap()
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| Some((id, slot.instr_count?)))
+            .filter(|&(_, taken_at)| taken_at <= instr_count)
+            .max_by_key(|&(_, taken_at)| taken_at)
+            .map(|(id, _)| Checkpoint(id))
+    }
+}
+
+/// Get every checkpoint requested so far, in the order they were requested
+pub fn checkpoints() -> Vec<Checkpoint> {
+    (0..STATE.lock().unwrap().slots.len())
+        .map(Checkpoint)
+        .collect()
+}
+
+/// Get the number of checkpoints PANDA holds, including any not taken through this
+/// module
+pub fn num_checkpoints() -> usize {
+    unsafe { get_num_checkpoints() as usize }
+}
+
+/// Register a callback to be run from the main loop after the replay is rewound to a
+/// checkpoint, such as to reset analysis state which is now ahead of the guest
+pub fn on_restore(callback: impl FnMut(Checkpoint) + Send + 'static) {
+    STATE.lock().unwrap().on_restore.push(Box::new(callback));
+}
@@ -0,0 +1,182 @@
+//! Pausing, resuming and stopping the guest
+//!
+//! The guest can't be paused in the middle of executing a block, so [`pause`] only
+//! requests a pause, which takes effect once the current block has finished. Resuming
+//! has to happen from PANDA's main loop, so [`resume`] and [`while_paused`] queue their
+//! work as an [`idle`](crate::idle) task, run once the pause has taken effect.
+//! This makes every function in this module safe to call from any callback, from init
+//! and from other threads, unlike the raw `panda_stop`/`panda_cont` which depend on the
+//! context they're called from.
+//!
+//! | Function          | Effect |
+//! |:-----------------:|:-------|
+//! | [`pause`]         | Pause the guest once the current block finishes |
+//! | [`resume`]        | Resume a paused guest |
+//! | [`while_paused`]  | Pause, run a closure from the main loop, then resume |
+//! | [`stop`]          | Leave PANDA's main loop, returning from [`Panda::run`](crate::Panda::run) in libpanda mode |
+//! | [`break_exec`]    | Stop executing the current block, such as after changing the PC |
+//!
+//! To quit PANDA entirely, see [`vm_quit`](crate::rr::vm_quit).
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::control;
+//! use panda::prelude::*;
+//!
+//! #[panda::before_block_exec]
+//! fn before_block(_: &mut CPUState, tb: &mut TranslationBlock) {
+//!     if tb.pc == 0x401000 {
+//!         control::while_paused(|| {
+//!             println!("reached main, press enter to continue");
+//!             std::io::stdin().read_line(&mut String::new()).unwrap();
+//!         });
+//!     }
+//! }
+//! ```
+use crate::idle::{self, TaskStatus};
+use crate::sys::{panda_break_exec, panda_break_main_loop, panda_cont, panda_stop};
+
+use std::os::raw::c_int;
+use std::sync::Mutex;
+
+enum Action {
+    Run(Box<dyn FnOnce() + Send>),
+    Resume,
+}
+
+#[derive(Default)]
+struct State {
+    /// Whether a pause has been requested which may not have taken effect yet
+    pause_pending: bool,
+
+    /// Whether the guest has been paused and not resumed since
+    paused: bool,
+
+    /// Actions to run from the main loop once any pending pause has taken effect
+    queued: Vec<Action>,
+
+    /// Whether an idle task has been spawned to run the queued actions
+    scheduled: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+/// Run the queued actions, returning whether more have been queued since
+fn run_queued() -> TaskStatus {
+    let queued = {
+        let mut state = STATE.lock().unwrap();
+
+        // QEMU handles the pause request between main loop iterations, so wait a
+        // round for it to take effect before resuming
+        if std::mem::take(&mut state.pause_pending) {
+            return TaskStatus::Pending;
+        }
+
+        std::mem::take(&mut state.queued)
+    };
+
+    // run without the lock held, so the actions can pause or resume again
+    for action in queued {
+        match action {
+            Action::Run(func) => func(),
+            Action::Resume => {
+                let mut state = STATE.lock().unwrap();
+                if state.pause_pending {
+                    // paused again by an earlier action
+                    state.queued.push(Action::Resume);
+                } else if state.paused {
+                    state.paused = false;
+                    unsafe {
+                        panda_cont();
+                    }
+                }
+            }
+        }
+    }
+
+    let mut state = STATE.lock().unwrap();
+    if state.queued.is_empty() {
+        state.scheduled = false;
+        TaskStatus::Done
+    } else {
+        TaskStatus::Pending
+    }
+}
+
+fn queue(action: Action) {
+    let mut state = STATE.lock().unwrap();
+    state.queued.push(action);
+
+    if !std::mem::replace(&mut state.scheduled, true) {
+        idle::spawn(|_| run_queued());
+    }
+}
+
+/// Pause the guest once the current block has finished executing. Has no effect if
+/// the guest is already paused.
+///
+/// PANDA's main loop keeps running while the guest is paused, so `main_loop_wait`
+/// callbacks, the monitor and [`while_paused`] work are still run.
+pub fn pause() {
+    let mut state = STATE.lock().unwrap();
+    if !state.paused {
+        state.paused = true;
+        state.pause_pending = true;
+
+        unsafe {
+            panda_stop(panda_sys::RunState_RUN_STATE_PAUSED as c_int);
+        }
+    }
+}
+
+/// Resume the guest after a [`pause`], from the main loop once the pause has taken
+/// effect. Has no effect if the guest isn't paused.
+pub fn resume() {
+    queue(Action::Resume);
+}
+
+/// Check whether the guest has been paused with [`pause`] and not yet resumed
+pub fn is_paused() -> bool {
+    STATE.lock().unwrap().paused
+}
+
+/// Pause the guest, run `func` from the main loop once the pause has taken effect,
+/// then resume the guest
+///
+/// This allows a callback to do work which would otherwise stall the guest in the
+/// middle of a block, such as heavy host-side processing or waiting for user input.
+/// If the guest was already paused it is left paused afterwards.
+pub fn while_paused(func: impl FnOnce() + Send + 'static) {
+    let was_paused = is_paused();
+
+    pause();
+    queue(Action::Run(Box::new(func)));
+
+    if !was_paused {
+        queue(Action::Resume);
+    }
+}
+
+/// Leave PANDA's main loop once the current block has finished executing
+///
+/// In libpanda mode this returns from [`Panda::run`](crate::Panda::run). The guest
+/// can't be continued afterwards, as `run` initializes PANDA from scratch each time it
+/// is called.
+pub fn stop() {
+    unsafe {
+        panda_break_main_loop();
+    }
+}
+
+/// Stop executing the current block and return to the CPU loop, so that changes to
+/// the CPU state (such as the PC) take effect immediately. Only has an effect when
+/// called from a callback run while the CPU is executing, such as
+/// [`before_block_exec`](crate::Callback::before_block_exec) or an instruction hook.
+///
+/// Returns whether execution was interrupted.
+pub fn break_exec() -> bool {
+    unsafe { panda_break_exec() }
+}
@@ -0,0 +1,390 @@
+//! Debugger-like control of the guest
+//!
+//! This module provides the building blocks of a debugger without going through the
+//! hooks plugin: breakpoints on guest addresses, watchpoints on ranges of guest memory
+//! and single-stepping, each surfacing as a Rust callback. It can also start QEMU's gdb
+//! server, so that an external debugger can be attached alongside a plugin.
+//!
+//! Breakpoints and stepping work by instrumenting the instructions involved, so adding
+//! a breakpoint or starting to step flushes the translation cache. Watchpoints turn on
+//! memory callbacks for the pages they cover (see [`memcb`](crate::memcb)), which
+//! slows down execution while any watchpoint is set.
+//!
+//! Callbacks are run from within a basic block, so they must not change the control
+//! flow of the guest or end the replay.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::{debug, prelude::*};
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     debug::set_breakpoint(0x401000, |cpu| {
+//!         println!("hit breakpoint, stepping");
+//!
+//!         debug::step(|cpu| println!("stepped to {:#x}", panda::current_pc(cpu)));
+//!     });
+//!
+//!     debug::set_watchpoint(0x601040, 8, debug::Access::Write, |_, hit| {
+//!         println!("{:#x} wrote {:02x?}", hit.pc, hit.data);
+//!     });
+//! }
+//! ```
+use crate::memcb::{self, MemcbDemand};
+use crate::prelude::*;
+use crate::tb_invalidation::flush_tb;
+use crate::Callback;
+
+use panda_sys::gdbserver_start;
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum DebugError {
+    #[error("Failed to start the gdb server on {0}")]
+    GdbServer(String),
+}
+
+/// Start QEMU's gdb server, listening for a debugger on the given TCP port.
+/// Equivalent to the `gdbserver tcp::[port]` monitor command.
+///
+/// In libpanda mode, [`Panda::gdb_server`](crate::Panda::gdb_server) starts the server
+/// before the guest starts running instead.
+pub fn start_gdb_server(port: u16) -> Result<(), DebugError> {
+    let device = format!("tcp::{}", port);
+    let device_c = CString::new(device.clone()).unwrap();
+
+    if unsafe { gdbserver_start(device_c.as_ptr()) } < 0 {
+        Err(DebugError::GdbServer(device))
+    } else {
+        Ok(())
+    }
+}
+
+/// The kinds of memory access which trigger a watchpoint
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+
+    /// Either a read or a write
+    ReadWrite,
+}
+
+impl Access {
+    fn matches(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+/// An access to guest memory which hit a watchpoint
+#[derive(Debug)]
+pub struct WatchHit<'a> {
+    /// The address of the instruction making the access
+    pub pc: target_ptr_t,
+
+    /// The virtual address accessed, which may start before the watched range
+    pub addr: target_ptr_t,
+
+    /// Whether the access was a read or a write
+    pub access: Access,
+
+    /// The data read or written
+    pub data: &'a [u8],
+}
+
+type BreakpointCallback = Arc<Mutex<dyn FnMut(&mut CPUState) + Send + 'static>>;
+type WatchpointCallback = Arc<Mutex<dyn FnMut(&mut CPUState, &WatchHit) + Send + 'static>>;
+type StepCallback = Box<dyn FnOnce(&mut CPUState) + Send + 'static>;
+
+/// A breakpoint on a guest address, set by [`set_breakpoint`]. Not to be confused with
+/// [`breakpoint::Breakpoint`](crate::breakpoint::Breakpoint), a breakpoint on an
+/// instruction count.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AddressBreakpoint {
+    id: u64,
+    addr: target_ulong,
+}
+
+impl AddressBreakpoint {
+    /// The address the breakpoint is set at
+    pub fn addr(&self) -> target_ulong {
+        self.addr
+    }
+
+    /// Remove the breakpoint, returning `false` if it was already removed
+    pub fn remove(self) -> bool {
+        let mut state = STATE.lock().unwrap();
+        let breakpoints = match state.breakpoints.get_mut(&self.addr) {
+            Some(breakpoints) => breakpoints,
+            None => return false,
+        };
+
+        let len = breakpoints.len();
+        breakpoints.retain(|(id, _)| *id != self.id);
+        let removed = breakpoints.len() != len;
+
+        // the instruction stays instrumented until it is next translated, which is
+        // harmless as it no longer has any callbacks
+        if breakpoints.is_empty() {
+            state.breakpoints.remove(&self.addr);
+        }
+
+        removed
+    }
+}
+
+struct Watched {
+    id: u64,
+    range: std::ops::Range<target_ptr_t>,
+    access: Access,
+    callback: WatchpointCallback,
+
+    /// Keeps memory callbacks enabled until the watchpoint is removed
+    _demand: MemcbDemand,
+}
+
+/// A watchpoint on a range of guest memory, set by [`set_watchpoint`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Watchpoint {
+    id: u64,
+}
+
+impl Watchpoint {
+    /// Remove the watchpoint, returning `false` if it was already removed
+    pub fn remove(self) -> bool {
+        let (removed, none_left) = {
+            let mut state = STATE.lock().unwrap();
+            let len = state.watchpoints.len();
+            state.watchpoints.retain(|watched| watched.id != self.id);
+
+            (state.watchpoints.len() != len, state.watchpoints.is_empty())
+        };
+
+        // stop checking memory accesses until another watchpoint is set
+        if removed && none_left {
+            for callback in MEM_CALLBACKS.iter() {
+                callback.disable();
+            }
+        }
+
+        removed
+    }
+}
+
+#[derive(Default)]
+struct State {
+    breakpoints: HashMap<target_ulong, Vec<(u64, BreakpointCallback)>>,
+    watchpoints: Vec<Watched>,
+    steps: Vec<StepCallback>,
+
+    /// Whether every instruction is being instrumented, as the guest is being stepped
+    stepping: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+    static ref CALLBACKS: [Callback; 2] = install_callbacks();
+    static ref MEM_CALLBACKS: [Callback; 2] = install_mem_callbacks();
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn install_callbacks() -> [Callback; 2] {
+    let translate = Callback::new();
+    let exec = Callback::new();
+
+    translate.insn_translate(|_, pc| {
+        let state = STATE.lock().unwrap();
+
+        state.stepping || state.breakpoints.contains_key(&pc)
+    });
+
+    exec.insn_exec(|cpu, pc| {
+        let (steps, breakpoints) = {
+            let mut state = STATE.lock().unwrap();
+            let steps = std::mem::take(&mut state.steps);
+            let breakpoints: Vec<_> = state
+                .breakpoints
+                .get(&pc)
+                .map(|breakpoints| breakpoints.iter().map(|(_, cb)| cb.clone()).collect())
+                .unwrap_or_default();
+
+            (steps, breakpoints)
+        };
+
+        // run without the lock held so callbacks can set breakpoints and step again
+        for callback in breakpoints {
+            (callback.lock().unwrap())(cpu);
+        }
+
+        if steps.is_empty() {
+            return;
+        }
+
+        for step in steps {
+            step(cpu);
+        }
+
+        // stop instrumenting every instruction once the callbacks stop stepping
+        let done = {
+            let mut state = STATE.lock().unwrap();
+            let done = state.stepping && state.steps.is_empty();
+            if done {
+                state.stepping = false;
+            }
+
+            done
+        };
+
+        if done {
+            flush_tb();
+        }
+    });
+
+    [translate, exec]
+}
+
+fn install_mem_callbacks() -> [Callback; 2] {
+    // the watchpoint callbacks may access guest memory themselves
+    let read = Callback::new().skip_reentrant();
+    let write = Callback::new().skip_reentrant();
+
+    read.virt_mem_after_read(|cpu, pc, addr, size, buf| {
+        watch_access(cpu, Access::Read, pc, addr, size, buf);
+    });
+
+    write.virt_mem_after_write(|cpu, pc, addr, size, buf| {
+        watch_access(cpu, Access::Write, pc, addr, size, buf);
+    });
+
+    [read, write]
+}
+
+fn watch_access(
+    cpu: &mut CPUState,
+    access: Access,
+    pc: target_ptr_t,
+    addr: target_ptr_t,
+    size: usize,
+    buf: *mut u8,
+) {
+    let end = addr.saturating_add(size as target_ptr_t);
+    let callbacks: Vec<_> = STATE
+        .lock()
+        .unwrap()
+        .watchpoints
+        .iter()
+        .filter(|watched| watched.access.matches(access))
+        .filter(|watched| addr < watched.range.end && end > watched.range.start)
+        .map(|watched| watched.callback.clone())
+        .collect();
+
+    if callbacks.is_empty() {
+        return;
+    }
+
+    let hit = WatchHit {
+        pc,
+        addr,
+        access,
+        data: unsafe { std::slice::from_raw_parts(buf, size) },
+    };
+
+    for callback in callbacks {
+        (callback.lock().unwrap())(cpu, &hit);
+    }
+}
+
+/// Run a callback right before the guest executes the instruction at `addr`, every
+/// time it is executed, until the returned [`AddressBreakpoint`] is removed. Breakpoints are
+/// on virtual addresses, and are hit in every address space.
+pub fn set_breakpoint(
+    addr: target_ulong,
+    callback: impl FnMut(&mut CPUState) + Send + 'static,
+) -> AddressBreakpoint {
+    lazy_static::initialize(&CALLBACKS);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let is_new = {
+        let mut state = STATE.lock().unwrap();
+        let breakpoints = state.breakpoints.entry(addr).or_default();
+        breakpoints.push((id, Arc::new(Mutex::new(callback))));
+
+        breakpoints.len() == 1
+    };
+
+    // retranslate the code at the address so that it is instrumented
+    if is_new {
+        flush_tb();
+    }
+
+    AddressBreakpoint { id, addr }
+}
+
+/// Run a callback whenever the guest accesses memory overlapping the `size` bytes at
+/// virtual address `addr`, until the returned [`Watchpoint`] is removed. The callback
+/// is run after the access has been made.
+pub fn set_watchpoint(
+    addr: target_ptr_t,
+    size: usize,
+    access: Access,
+    callback: impl FnMut(&mut CPUState, &WatchHit) + Send + 'static,
+) -> Watchpoint {
+    lazy_static::initialize(&MEM_CALLBACKS);
+
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let range = addr..addr.saturating_add(size as target_ptr_t);
+    let demand = memcb::demand_range(range.clone());
+
+    let is_first = {
+        let mut state = STATE.lock().unwrap();
+        state.watchpoints.push(Watched {
+            id,
+            range,
+            access,
+            callback: Arc::new(Mutex::new(callback)),
+            _demand: demand,
+        });
+
+        state.watchpoints.len() == 1
+    };
+
+    if is_first {
+        for callback in MEM_CALLBACKS.iter() {
+            callback.enable();
+        }
+    }
+
+    Watchpoint { id }
+}
+
+/// Single-step the guest, running a callback right before it executes its next
+/// instruction. Calling `step` again from the callback steps another instruction.
+///
+/// Stepping begins once the translation cache has been flushed to instrument every
+/// instruction, so when called from outside of a step callback the first instruction
+/// stepped to is the start of the next block to execute.
+pub fn step(callback: impl FnOnce(&mut CPUState) + Send + 'static) {
+    lazy_static::initialize(&CALLBACKS);
+
+    let start_stepping = {
+        let mut state = STATE.lock().unwrap();
+        state.steps.push(Box::new(callback));
+
+        !std::mem::replace(&mut state.stepping, true)
+    };
+
+    if start_stepping {
+        flush_tb();
+    }
+}
+
+/// Get the addresses of every breakpoint currently set
+pub fn breakpoints() -> Vec<target_ulong> {
+    STATE.lock().unwrap().breakpoints.keys().copied().collect()
+}
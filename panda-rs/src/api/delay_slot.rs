@@ -0,0 +1,163 @@
+//! Handling of MIPS branch delay slots
+//!
+//! On MIPS, the instruction following a branch or jump (its delay slot) is executed
+//! before the branch takes effect. QEMU translates the delay slot into the same block
+//! as the branch, and tracks a branch which is still pending in `hflags` when execution
+//! stops in a delay slot (for example, due to an exception). This trips up code written
+//! with other architectures in mind:
+//!
+//! * a hook placed on a delay slot is never hit at the start of a block, as the delay
+//! slot is in the middle of the branch's block
+//! * setting the pc while a branch is pending doesn't redirect execution, as the branch
+//! still takes effect once the instruction at the new pc has run
+//! * skipping an instruction in a delay slot by adding 4 to the pc skips the branch
+//!
+//! [`HookBuilder::try_at_addr`](crate::plugins::hooks::HookBuilder::try_at_addr)
+//! rejects hooks on delay slots, [`hook_addr`] gives the address to hook instead, and
+//! [`redirect`] and [`skip_instruction`] change control flow correctly in and out of
+//! delay slots.
+//!
+//! Only branches with delay slots in MIPS32/MIPS64 before release 6 are recognized.
+//! Compact branches, microMIPS and MIPS16 code are not supported.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::{delay_slot, prelude::*};
+//!
+//! #[panda::before_block_exec_invalidate_opt]
+//! fn skip_faulting_load(cpu: &mut CPUState, tb: &mut TranslationBlock) -> bool {
+//!     if tb.pc != 0x400a10 {
+//!         return false;
+//!     }
+//!
+//!     // continues at the branch target if the load is in a delay slot
+//!     delay_slot::skip_instruction(cpu);
+//!
+//!     // leave the block, so that execution continues at the new pc
+//!     true
+//! }
+//! ```
+use crate::mem::virtual_memory_read;
+use crate::prelude::*;
+use crate::regs;
+use crate::scan::arch::insn_word;
+use crate::sys;
+use crate::{cpu_arch_state, CPUArchPtr};
+
+/// The size of a MIPS instruction, and of a delay slot
+pub const INSN_SIZE: target_ulong = 4;
+
+/// An error in placing a hook
+#[derive(thiserror::Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DelaySlotError {
+    #[error("{addr:#x} is in the delay slot of the branch at {branch:#x}, so would never be hit")]
+    InDelaySlot {
+        addr: target_ulong,
+        branch: target_ulong,
+    },
+}
+
+/// Check whether an instruction is a branch or jump with a delay slot
+pub fn has_delay_slot(word: u32) -> bool {
+    let opcode = word >> 26;
+    let rs = (word >> 21) & 0x1f;
+    let rt = (word >> 16) & 0x1f;
+    let funct = word & 0x3f;
+
+    match opcode {
+        // SPECIAL: jr, jalr
+        0x00 => funct == 0x08 || funct == 0x09,
+
+        // REGIMM: bltz, bgez, bltzl, bgezl, bltzal, bgezal, bltzall, bgezall
+        0x01 => matches!(rt, 0x00..=0x03 | 0x10..=0x13),
+
+        // j, jal, beq, bne, blez, bgtz
+        0x02..=0x07 => true,
+
+        // COP1/COP2: bc1*, bc2*
+        0x11 | 0x12 => rs == 0x08,
+
+        // beql, bnel, blezl, bgtzl
+        0x14..=0x17 => true,
+
+        // jalx
+        0x1d => true,
+
+        _ => false,
+    }
+}
+
+/// Check whether the instruction at `addr` is in the delay slot of a branch, by reading
+/// the instruction before it. Returns `false` if it can't be read.
+pub fn is_delay_slot(cpu: &mut CPUState, addr: target_ulong) -> bool {
+    let branch = match addr.checked_sub(INSN_SIZE) {
+        Some(branch) => branch,
+        None => return false,
+    };
+
+    virtual_memory_read(cpu, branch, INSN_SIZE as usize)
+        .ok()
+        .and_then(|bytes| insn_word(&bytes))
+        .map_or(false, has_delay_slot)
+}
+
+/// Check whether the CPU stopped in a delay slot with its branch still pending, such as
+/// when the delay slot raised an exception or starts a block of its own
+pub fn in_delay_slot(cpu: &mut CPUState) -> bool {
+    let env = cpu_arch_state!(cpu);
+
+    unsafe { (*env).hflags & sys::MIPS_HFLAG_BMASK != 0 }
+}
+
+/// Get the address to hook in place of `addr`. Hooks are only checked at the start of
+/// a block, and delay slots are in the same block as their branch, so a hook on a delay
+/// slot is placed on its branch instead, running before the branch.
+pub fn hook_addr(cpu: &mut CPUState, addr: target_ulong) -> target_ulong {
+    if is_delay_slot(cpu, addr) {
+        addr - INSN_SIZE
+    } else {
+        addr
+    }
+}
+
+/// Get the address execution will continue at after the current instruction, taking
+/// into account a pending branch if the CPU is in a delay slot
+pub fn next_pc(cpu: &mut CPUState) -> target_ulong {
+    let env = cpu_arch_state!(cpu);
+    let (hflags, btarget, bcond) = unsafe { ((*env).hflags, (*env).btarget, (*env).bcond) };
+    let fallthrough = regs::get_pc(cpu) + INSN_SIZE;
+
+    match hflags & sys::MIPS_HFLAG_BMASK_BASE {
+        sys::MIPS_HFLAG_B | sys::MIPS_HFLAG_BR => btarget,
+        sys::MIPS_HFLAG_BC | sys::MIPS_HFLAG_BL if bcond != 0 => btarget,
+        _ => fallthrough,
+    }
+}
+
+/// Cancel the branch pending while in a delay slot, if any
+pub fn cancel_branch(cpu: &mut CPUState) {
+    let env = cpu_arch_state!(cpu);
+
+    unsafe {
+        (*env).hflags &= !sys::MIPS_HFLAG_BMASK;
+    }
+}
+
+/// Continue execution at `pc`, cancelling the pending branch if the CPU is in a delay
+/// slot so that execution doesn't return to the branch target afterwards.
+///
+/// Like setting the pc directly, this only takes effect once the current block is left,
+/// such as by returning `true` from `before_block_exec_invalidate_opt`.
+pub fn redirect(cpu: &mut CPUState, pc: target_ulong) {
+    cancel_branch(cpu);
+    regs::set_pc(cpu, pc);
+}
+
+/// Skip the current instruction without executing it. If it is in a delay slot, this
+/// continues at the branch target if the branch is taken, rather than at the next
+/// instruction. See [`redirect`] for when this takes effect.
+pub fn skip_instruction(cpu: &mut CPUState) {
+    let next = next_pc(cpu);
+    redirect(cpu, next);
+}
@@ -0,0 +1,485 @@
+//! Disassembly of guest code using [capstone](https://www.capstone-engine.org)
+//!
+//! The disassembler is configured from the state of the guest CPU, so the instruction
+//! set in use is picked up automatically: Thumb vs ARM on ARM, AArch32 vs AArch64 on
+//! aarch64, 16/32/64-bit code segments on x86 and the current byte order on
+//! bi-endian architectures. ARM code is always read as little-endian, as big-endian
+//! ARMv6+ guests (BE8) only swap the byte order of data.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//!
+//! #[panda::insn_translate]
+//! fn translate(cpu: &mut CPUState, pc: target_ptr_t) -> bool {
+//!     if let Ok(insn) = panda::disas::disassemble(cpu, pc) {
+//!         println!("{}", insn);
+//!     }
+//!
+//!     false
+//! }
+//! ```
+use crate::enums::{Endian, MemRWStatus};
+use crate::guest_endian;
+use crate::mem::virtual_memory_read;
+use crate::prelude::*;
+
+#[cfg(any(
+    feature = "i386",
+    feature = "x86_64",
+    feature = "arm",
+    feature = "aarch64"
+))]
+use crate::{cpu_arch_state, CPUArchPtr};
+
+use capstone::Capstone;
+
+use std::cell::RefCell;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+
+/// Maximum length of an instruction on any supported architecture
+const MAX_INSN_LEN: usize = 16;
+
+/// An error encountered while disassembling guest code
+#[derive(thiserror::Error, Debug)]
+pub enum DisasError {
+    #[error("failed to read guest code at {addr:#x} ({status:?})")]
+    Read {
+        addr: target_ulong,
+        status: MemRWStatus,
+    },
+
+    #[error("invalid instruction at {0:#x}")]
+    InvalidInstruction(target_ulong),
+
+    #[error("capstone error: {0}")]
+    Capstone(capstone::Error),
+}
+
+/// The instruction set to disassemble as
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DisasMode {
+    /// 16-bit x86 code (real mode or a 16-bit code segment)
+    X86Real,
+
+    /// 32-bit x86 code (protected mode or compatibility mode)
+    X86Protected,
+
+    /// 64-bit x86 code (long mode)
+    X86Long,
+
+    /// 32-bit ARM (A32)
+    Arm,
+
+    /// Thumb and Thumb-2 (T32)
+    Thumb,
+
+    /// 64-bit ARM (A64)
+    Aarch64,
+
+    Mips32,
+    Mips64,
+    Ppc32,
+}
+
+impl DisasMode {
+    /// Get the instruction set the guest CPU is currently executing
+    pub fn current(cpu: &CPUState) -> Self {
+        current_mode(cpu)
+    }
+
+    fn capstone(self, endian: Endian) -> Result<Capstone, DisasError> {
+        use capstone::{Arch, Mode};
+
+        let (arch, mode) = match self {
+            DisasMode::X86Real => (Arch::X86, Mode::Mode16),
+            DisasMode::X86Protected => (Arch::X86, Mode::Mode32),
+            DisasMode::X86Long => (Arch::X86, Mode::Mode64),
+            DisasMode::Arm => (Arch::ARM, Mode::Arm),
+            DisasMode::Thumb => (Arch::ARM, Mode::Thumb),
+            DisasMode::Aarch64 => (Arch::ARM64, Mode::Arm),
+            DisasMode::Mips32 => (Arch::MIPS, Mode::Mips32),
+            DisasMode::Mips64 => (Arch::MIPS, Mode::Mips64),
+            DisasMode::Ppc32 => (Arch::PPC, Mode::Mode32),
+        };
+
+        // x86 has no byte order option
+        let endian = match (arch, endian) {
+            (Arch::X86, _) => None,
+            (_, Endian::Big) => Some(capstone::Endian::Big),
+            (_, Endian::Little) => Some(capstone::Endian::Little),
+        };
+
+        let mut cs = Capstone::new_raw(arch, mode, std::iter::empty(), endian)
+            .map_err(DisasError::Capstone)?;
+
+        // instruction groups are only reported in detail mode
+        cs.set_detail(true).map_err(DisasError::Capstone)?;
+
+        Ok(cs)
+    }
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+fn current_mode(cpu: &CPUState) -> DisasMode {
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    if env.hflags & panda_sys::HF_CS64_MASK != 0 {
+        DisasMode::X86Long
+    } else if env.hflags & panda_sys::HF_CS32_MASK != 0 {
+        DisasMode::X86Protected
+    } else {
+        DisasMode::X86Real
+    }
+}
+
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+fn current_mode(cpu: &CPUState) -> DisasMode {
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    if env.aarch64 != 0 {
+        DisasMode::Aarch64
+    } else if env.thumb != 0 {
+        DisasMode::Thumb
+    } else {
+        DisasMode::Arm
+    }
+}
+
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+fn current_mode(_: &CPUState) -> DisasMode {
+    DisasMode::Mips32
+}
+
+#[cfg(any(feature = "mips64", feature = "mips64el"))]
+fn current_mode(_: &CPUState) -> DisasMode {
+    DisasMode::Mips64
+}
+
+#[cfg(feature = "ppc")]
+fn current_mode(_: &CPUState) -> DisasMode {
+    DisasMode::Ppc32
+}
+
+/// A disassembled guest instruction
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Instruction {
+    /// The guest virtual address of the instruction
+    pub address: target_ulong,
+
+    /// The encoded instruction
+    pub bytes: Vec<u8>,
+
+    /// The instruction mnemonic, such as `mov`
+    pub mnemonic: String,
+
+    /// The operands of the instruction, formatted as text
+    pub op_str: String,
+}
+
+impl Instruction {
+    /// The length of the instruction in bytes
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// The address of the instruction following this one
+    pub fn next_address(&self) -> target_ulong {
+        self.address + self.bytes.len() as target_ulong
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.op_str.is_empty() {
+            write!(f, "{:#x}: {}", self.address, self.mnemonic)
+        } else {
+            write!(f, "{:#x}: {} {}", self.address, self.mnemonic, self.op_str)
+        }
+    }
+}
+
+thread_local! {
+    /// Capstone handles are expensive to open, so they're kept for reuse
+    static HANDLES: RefCell<HashMap<(DisasMode, Endian), Capstone>> = RefCell::new(HashMap::new());
+}
+
+/// Disassemble a single instruction at the given guest virtual address
+pub fn disassemble(cpu: &mut CPUState, pc: target_ulong) -> Result<Instruction, DisasError> {
+    disassemble_count(cpu, pc, 1)?
+        .pop()
+        .ok_or(DisasError::InvalidInstruction(pc))
+}
+
+/// Disassemble up to `count` consecutive instructions starting at the given guest
+/// virtual address, stopping early at an invalid instruction or unreadable memory.
+/// Fails if the first instruction can't be disassembled.
+pub fn disassemble_count(
+    cpu: &mut CPUState,
+    pc: target_ulong,
+    count: usize,
+) -> Result<Vec<Instruction>, DisasError> {
+    let code = read_code(cpu, pc, count.max(1) * MAX_INSN_LEN)?;
+    let mode = DisasMode::current(cpu);
+    let insns = disassemble_bytes(&code, pc, mode, code_endian(cpu, mode), count)?;
+
+    if insns.is_empty() {
+        Err(DisasError::InvalidInstruction(pc))
+    } else {
+        Ok(insns)
+    }
+}
+
+/// The byte order of the guest's instructions, which on ARM is little-endian even when
+/// data is big-endian
+pub(crate) fn code_endian(cpu: &CPUState, mode: DisasMode) -> Endian {
+    match mode {
+        DisasMode::Arm | DisasMode::Thumb | DisasMode::Aarch64 => Endian::Little,
+        _ => guest_endian(cpu),
+    }
+}
+
+/// Disassemble up to `count` instructions from a buffer of code located at `address`,
+/// using the given instruction set and byte order rather than those of the guest CPU.
+/// Disassembly stops early at an invalid instruction or the end of the buffer.
+pub fn disassemble_bytes(
+    code: &[u8],
+    address: target_ulong,
+    mode: DisasMode,
+    endian: Endian,
+    count: usize,
+) -> Result<Vec<Instruction>, DisasError> {
+    if count == 0 || code.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    with_handle(mode, endian, |cs| {
+        let insns = cs
+            .disasm_count(code, address as u64, count)
+            .map_err(DisasError::Capstone)?;
+
+        Ok(insns
+            .iter()
+            .map(|insn| Instruction {
+                address: insn.address() as target_ulong,
+                bytes: insn.bytes().to_vec(),
+                mnemonic: insn.mnemonic().unwrap_or_default().to_owned(),
+                op_str: insn.op_str().unwrap_or_default().to_owned(),
+            })
+            .collect())
+    })
+}
+
+/// The groups capstone places an instruction in, describing how it affects control
+/// flow and whether it is privileged
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct InsnGroups {
+    /// The instruction is a call
+    pub call: bool,
+
+    /// The instruction returns from a function or an interrupt
+    pub ret: bool,
+
+    /// The instruction is a jump or branch, conditional or not
+    pub jump: bool,
+
+    /// The instruction can only be executed by the kernel
+    pub privileged: bool,
+
+    /// The instruction is a call or jump whose target is taken from a register or
+    /// memory rather than encoded in the instruction
+    pub indirect: bool,
+}
+
+/// Get the groups of the instruction at the given guest virtual address
+pub fn groups(cpu: &mut CPUState, pc: target_ulong) -> Result<InsnGroups, DisasError> {
+    let code = read_code(cpu, pc, MAX_INSN_LEN)?;
+    let mode = DisasMode::current(cpu);
+
+    groups_bytes(&code, pc, mode, code_endian(cpu, mode))
+}
+
+/// Get the groups of the first instruction in a buffer of code located at `address`,
+/// using the given instruction set and byte order rather than those of the guest CPU
+pub fn groups_bytes(
+    code: &[u8],
+    address: target_ulong,
+    mode: DisasMode,
+    endian: Endian,
+) -> Result<InsnGroups, DisasError> {
+    use capstone::arch::{arm, arm64, mips, ppc, x86, ArchOperand};
+    use capstone::InsnGroupType::*;
+
+    with_handle(mode, endian, |cs| {
+        let insns = cs
+            .disasm_count(code, address as u64, 1)
+            .map_err(DisasError::Capstone)?;
+        let insn = insns
+            .iter()
+            .next()
+            .ok_or(DisasError::InvalidInstruction(address))?;
+        let detail = cs.insn_detail(&insn).map_err(DisasError::Capstone)?;
+
+        let in_group = |group| detail.groups().any(|id| id.0 as u32 == group);
+        let call = in_group(CS_GRP_CALL);
+        let jump = in_group(CS_GRP_JUMP);
+
+        // direct branches encode their target as an immediate operand
+        let direct = detail
+            .arch_detail()
+            .operands()
+            .iter()
+            .any(|operand| match operand {
+                ArchOperand::X86Operand(op) => matches!(op.op_type, x86::X86OperandType::Imm(_)),
+                ArchOperand::ArmOperand(op) => matches!(op.op_type, arm::ArmOperandType::Imm(_)),
+                ArchOperand::Arm64Operand(op) => {
+                    matches!(op.op_type, arm64::Arm64OperandType::Imm(_))
+                }
+                ArchOperand::MipsOperand(op) => matches!(op, mips::MipsOperand::Imm(_)),
+                ArchOperand::PpcOperand(op) => matches!(op, ppc::PpcOperand::Imm(_)),
+                _ => false,
+            });
+
+        let mut groups = InsnGroups {
+            call,
+            ret: in_group(CS_GRP_RET) || in_group(CS_GRP_IRET),
+            jump,
+            privileged: in_group(CS_GRP_PRIVILEGE),
+            indirect: (call || jump) && !direct,
+        };
+        add_missing_groups(&mut groups, &insn, mode, endian);
+
+        Ok(groups)
+    })
+}
+
+/// Add groups capstone leaves off some instructions, such as returns through `lr` on
+/// ARM, link branches on MIPS and PowerPC and port I/O on x86
+fn add_missing_groups(
+    groups: &mut InsnGroups,
+    insn: &capstone::Insn,
+    mode: DisasMode,
+    endian: Endian,
+) {
+    let mnemonic = insn.mnemonic().unwrap_or_default();
+    let op_str = insn.op_str().unwrap_or_default();
+    let one_of = |mnemonics: &[&str]| mnemonics.contains(&mnemonic);
+
+    match mode {
+        DisasMode::X86Real | DisasMode::X86Protected | DisasMode::X86Long => {
+            groups.privileged |= one_of(&[
+                "in", "out", "insb", "insw", "insd", "outsb", "outsw", "outsd",
+            ]);
+        }
+        DisasMode::Arm | DisasMode::Thumb => {
+            groups.ret |= (mnemonic == "bx" && op_str == "lr")
+                || (mnemonic == "mov" && op_str == "pc, lr")
+                || (mnemonic == "pop" && op_str.contains("pc"))
+                || (mnemonic == "ldr" && op_str.starts_with("pc, [sp]"));
+            groups.privileged |= one_of(&["cps", "cpsid", "cpsie"]);
+        }
+        DisasMode::Aarch64 => {
+            groups.ret |= mnemonic == "eret";
+            groups.privileged |= one_of(&["eret", "hvc", "smc"]);
+        }
+        DisasMode::Mips32 | DisasMode::Mips64 => {
+            groups.call |= one_of(&["jal", "jalx", "bal", "bgezal", "bltzal"]);
+            groups.ret |= (mnemonic == "jr" && op_str == "$ra") || mnemonic == "eret";
+            groups.privileged |= one_of(&[
+                "mfc0", "mtc0", "dmfc0", "dmtc0", "eret", "tlbp", "tlbr", "tlbwi", "tlbwr", "wait",
+            ]);
+        }
+        DisasMode::Ppc32 => {
+            // branches which set the link register have the low bit of the word set
+            let low_byte = match endian {
+                Endian::Big => insn.bytes().last(),
+                Endian::Little => insn.bytes().first(),
+            };
+
+            groups.call |= groups.jump && low_byte.map_or(false, |byte| byte & 1 != 0);
+            groups.ret |= one_of(&["blr", "rfi", "rfid"]);
+            groups.privileged |= one_of(&[
+                "mfmsr", "mtmsr", "mtmsrd", "mfsr", "mtsr", "mfsrin", "mtsrin", "tlbie", "tlbia",
+                "rfi", "rfid",
+            ]);
+        }
+    }
+}
+
+/// Run `f` with the cached capstone handle for the given instruction set and byte order
+fn with_handle<T>(
+    mode: DisasMode,
+    endian: Endian,
+    f: impl FnOnce(&mut Capstone) -> Result<T, DisasError>,
+) -> Result<T, DisasError> {
+    HANDLES.with(|handles| {
+        let mut handles = handles.borrow_mut();
+        let cs = match handles.entry((mode, endian)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(mode.capstone(endian)?),
+        };
+
+        f(cs)
+    })
+}
+
+/// Read up to `len` bytes of code, stopping at the end of the first page if the
+/// following page isn't mapped
+fn read_code(cpu: &mut CPUState, pc: target_ulong, len: usize) -> Result<Vec<u8>, DisasError> {
+    if let Ok(code) = virtual_memory_read(cpu, pc, len) {
+        return Ok(code);
+    }
+
+    let page_size = crate::mem::page_size();
+    let to_page_end = (page_size - (pc % page_size)) as usize;
+
+    virtual_memory_read(cpu, pc, len.min(to_page_end))
+        .map_err(|status| DisasError::Read { addr: pc, status })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups_of(code: &[u8], mode: DisasMode, endian: Endian) -> InsnGroups {
+        groups_bytes(code, 0x1000, mode, endian).unwrap()
+    }
+
+    #[test]
+    fn test_groups_bytes() {
+        let x86 = |code: &[u8]| groups_of(code, DisasMode::X86Long, Endian::Little);
+
+        // call rel32, call rax, jmp qword ptr [rax], ret, out dx, al
+        assert!(x86(&[0xe8, 0, 0, 0, 0]).call && !x86(&[0xe8, 0, 0, 0, 0]).indirect);
+        assert!(x86(&[0xff, 0xd0]).call && x86(&[0xff, 0xd0]).indirect);
+        assert!(x86(&[0xff, 0x20]).jump && x86(&[0xff, 0x20]).indirect);
+        assert!(x86(&[0xc3]).ret);
+        assert!(x86(&[0xee]).privileged);
+
+        let arm = |word: u32| groups_of(&word.to_le_bytes(), DisasMode::Arm, Endian::Little);
+
+        // bl, bx lr, pop {r4, pc}, blx r3
+        assert!(arm(0xeb00_0000).call);
+        assert!(arm(0xe12f_ff1e).ret);
+        assert!(arm(0xe8bd_8010).ret);
+        assert!(arm(0xe12f_ff33).call && arm(0xe12f_ff33).indirect);
+
+        let mips = |word: u32| groups_of(&word.to_be_bytes(), DisasMode::Mips32, Endian::Big);
+
+        // jal, jr $ra, mtc0
+        assert!(mips(0x0c00_0000).call && !mips(0x0c00_0000).indirect);
+        assert!(mips(0x03e0_0008).ret);
+        assert!(mips(0x4088_6000).privileged);
+
+        let ppc = |word: u32| groups_of(&word.to_be_bytes(), DisasMode::Ppc32, Endian::Big);
+
+        // bl, b, bctrl, blr, mtmsr
+        assert!(ppc(0x4800_0001).call);
+        assert!(!ppc(0x4800_0000).call);
+        assert!(ppc(0x4e80_0421).call && ppc(0x4e80_0421).indirect);
+        assert!(ppc(0x4e80_0020).ret);
+        assert!(ppc(0x7c00_0124).privileged);
+    }
+}
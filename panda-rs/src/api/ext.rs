@@ -0,0 +1,340 @@
+//! Safe methods on the raw PANDA types passed to callbacks
+//!
+//! Types such as [`CPUState`] and [`TranslationBlock`] are the raw bindings PANDA
+//! passes to callbacks, so working with them directly means knowing which fields are
+//! meaningful and which require `unsafe` FFI calls. These extension traits provide
+//! safe methods for the common cases, and are brought into scope by the
+//! [`prelude`](crate::prelude). They should be preferred over field access or the
+//! [`sys`](crate::sys) functions, as the prelude will move away from exposing the raw
+//! types directly. The panicking memory accessors `panda-sys` adds to [`CPUState`]
+//! (such as `mem_read`) are deprecated in favour of the fallible methods of
+//! [`CpuExt`].
+//!
+//! Guest physical addresses, which are passed to callbacks as a raw [`hwaddr`], can be
+//! wrapped in a [`PhysAddr`] to access the memory at them.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::sys::Monitor;
+//!
+//! #[panda::before_block_exec]
+//! fn every_block(cpu: &mut CPUState, tb: &mut TranslationBlock) {
+//!     if !cpu.in_kernel_mode() {
+//!         println!("cpu {}: block {:#x}-{:#x}", cpu.index(), tb.start(), tb.end());
+//!
+//!         if let Ok(code) = tb.bytes(cpu) {
+//!             println!("{:02x?}", code);
+//!         }
+//!     }
+//! }
+//!
+//! #[panda::monitor]
+//! fn monitor(mon: &mut Monitor, _cmd: *const u8) {
+//!     mon.print("hello from the plugin\n");
+//! }
+//! ```
+use crate::enums::MemRWStatus;
+use crate::mem::{
+    physical_memory_read, physical_memory_write, read_guest_string, virtual_memory_read,
+    virtual_memory_write,
+};
+use crate::prelude::*;
+use crate::sys::{self, hwaddr, MachineState, Monitor, Object};
+use crate::tb_insns::{self, TbInsn};
+use crate::GuestReadFail;
+
+#[cfg(feature = "disas")]
+use crate::disas::{self, DisasError, DisasMode, Instruction};
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::sync::Arc;
+
+fn status_result(status: MemRWStatus) -> Result<(), MemRWStatus> {
+    match status {
+        MemRWStatus::MemTxOk => Ok(()),
+        status => Err(status),
+    }
+}
+
+/// Safe methods for [`CPUState`]
+pub trait CpuExt {
+    /// The index of the CPU, starting at 0
+    fn index(&self) -> i32;
+
+    /// The current program counter
+    fn pc(&mut self) -> target_ulong;
+
+    /// The current architecture-independent address space ID
+    fn asid(&mut self) -> target_ulong;
+
+    /// The current userspace stack pointer
+    fn sp(&mut self) -> target_ulong;
+
+    /// The current kernel stack pointer
+    fn kernel_sp(&mut self) -> target_ulong;
+
+    /// Whether the CPU is currently in kernel mode
+    fn in_kernel_mode(&mut self) -> bool;
+
+    /// The return value of the function which just returned, using the calling
+    /// convention of the guest
+    fn ret_val(&mut self) -> target_ulong;
+
+    /// Read `len` bytes of guest virtual memory
+    fn read_mem(&mut self, addr: target_ulong, len: usize) -> Result<Vec<u8>, MemRWStatus>;
+
+    /// Write to guest virtual memory
+    fn write_mem(&mut self, addr: target_ulong, data: &[u8]) -> Result<(), MemRWStatus>;
+
+    /// Read a nul-terminated UTF-8 string from guest virtual memory
+    fn read_string(&mut self, addr: target_ptr_t) -> Result<String, GuestReadFail>;
+}
+
+impl CpuExt for CPUState {
+    fn index(&self) -> i32 {
+        self.cpu_index
+    }
+
+    fn pc(&mut self) -> target_ulong {
+        crate::current_pc(self)
+    }
+
+    fn asid(&mut self) -> target_ulong {
+        crate::current_asid(self)
+    }
+
+    fn sp(&mut self) -> target_ulong {
+        crate::current_sp(self)
+    }
+
+    fn kernel_sp(&mut self) -> target_ulong {
+        crate::current_ksp(self)
+    }
+
+    fn in_kernel_mode(&mut self) -> bool {
+        crate::in_kernel_mode(self)
+    }
+
+    fn ret_val(&mut self) -> target_ulong {
+        crate::get_ret_val(self)
+    }
+
+    fn read_mem(&mut self, addr: target_ulong, len: usize) -> Result<Vec<u8>, MemRWStatus> {
+        virtual_memory_read(self, addr, len)
+    }
+
+    fn write_mem(&mut self, addr: target_ulong, data: &[u8]) -> Result<(), MemRWStatus> {
+        status_result(virtual_memory_write(self, addr, data))
+    }
+
+    fn read_string(&mut self, addr: target_ptr_t) -> Result<String, GuestReadFail> {
+        read_guest_string(self, addr)
+    }
+}
+
+/// Safe methods for [`TranslationBlock`]
+pub trait BlockExt {
+    /// The address of the first instruction in the block
+    fn start(&self) -> target_ulong;
+
+    /// The address just past the end of the block
+    fn end(&self) -> target_ulong;
+
+    /// The size of the block's guest code in bytes
+    fn code_size(&self) -> usize;
+
+    /// The number of guest instructions in the block
+    fn instr_count(&self) -> usize;
+
+    /// Check whether `addr` is within the block's guest code
+    fn contains(&self, addr: target_ulong) -> bool;
+
+    /// The guest instructions in the block, if it was translated while
+    /// [`tb_insns`](crate::tb_insns) was enabled
+    fn instructions(&self) -> Option<Arc<[TbInsn]>>;
+
+    /// The guest instruction in the block which contains `addr`, if it was translated
+    /// while [`tb_insns`](crate::tb_insns) was enabled
+    fn instruction_at(&self, addr: target_ulong) -> Option<TbInsn>;
+
+    /// Read the block's guest code from memory
+    fn bytes(&self, cpu: &mut CPUState) -> Result<Vec<u8>, MemRWStatus>;
+
+    /// Disassemble the block's guest code, using the instruction set the CPU is
+    /// currently executing
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "disas")))]
+    #[cfg(feature = "disas")]
+    fn disassemble(&self, cpu: &mut CPUState) -> Result<Vec<Instruction>, DisasError>;
+}
+
+impl BlockExt for TranslationBlock {
+    fn start(&self) -> target_ulong {
+        self.pc
+    }
+
+    fn end(&self) -> target_ulong {
+        self.pc + self.size as target_ulong
+    }
+
+    fn code_size(&self) -> usize {
+        self.size as usize
+    }
+
+    fn instr_count(&self) -> usize {
+        self.icount as usize
+    }
+
+    fn contains(&self, addr: target_ulong) -> bool {
+        (self.start()..self.end()).contains(&addr)
+    }
+
+    fn instructions(&self) -> Option<Arc<[TbInsn]>> {
+        tb_insns::instructions(self)
+    }
+
+    fn instruction_at(&self, addr: target_ulong) -> Option<TbInsn> {
+        tb_insns::instruction_at(self, addr)
+    }
+
+    fn bytes(&self, cpu: &mut CPUState) -> Result<Vec<u8>, MemRWStatus> {
+        virtual_memory_read(cpu, self.start(), self.code_size())
+    }
+
+    #[cfg(feature = "disas")]
+    fn disassemble(&self, cpu: &mut CPUState) -> Result<Vec<Instruction>, DisasError> {
+        let code = self.bytes(cpu).map_err(|status| DisasError::Read {
+            addr: self.start(),
+            status,
+        })?;
+
+        let mode = DisasMode::current(cpu);
+        disas::disassemble_bytes(
+            &code,
+            self.start(),
+            mode,
+            disas::code_endian(cpu, mode),
+            self.instr_count(),
+        )
+    }
+}
+
+/// Safe methods for the QEMU [`Monitor`] passed to `monitor` callbacks
+pub trait MonitorExt {
+    /// Print a message to the monitor. Any nul bytes in the message are dropped.
+    fn print(&mut self, msg: &str);
+}
+
+impl MonitorExt for Monitor {
+    fn print(&mut self, msg: &str) {
+        let msg = CString::new(msg.replace('\0', "")).unwrap();
+
+        unsafe {
+            sys::monitor_printf(self, b"%s\0".as_ptr() as _, msg.as_ptr());
+        }
+    }
+}
+
+/// Safe methods for the QEMU [`MachineState`] passed to `during_machine_init`
+/// callbacks
+pub trait MachineExt {
+    /// The QOM type name of the machine, such as `pc-i440fx-2.8-machine`
+    fn type_name(&self) -> String;
+
+    /// Get a string property of the machine, such as `kernel-cmdline`, if it exists and
+    /// is set
+    fn property(&self, name: &str) -> Option<String>;
+
+    /// The command line passed to the kernel given with `-append`, if any
+    fn kernel_cmdline(&self) -> Option<String>;
+
+    /// The amount of guest RAM in bytes
+    fn ram_size(&self) -> u64;
+}
+
+impl MachineExt for MachineState {
+    fn type_name(&self) -> String {
+        // a MachineState is a QOM object, which it starts with
+        let object = self as *const MachineState as *mut Object;
+
+        unsafe {
+            CStr::from_ptr(sys::object_get_typename(object))
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    fn property(&self, name: &str) -> Option<String> {
+        let object = self as *const MachineState as *mut Object;
+        let name = CString::new(name).ok()?;
+
+        unsafe {
+            // errors are ignored when no error pointer is passed, leaving it to return NULL
+            let value = sys::object_property_get_str(object, name.as_ptr(), std::ptr::null_mut());
+            if value.is_null() {
+                return None;
+            }
+
+            let string = CStr::from_ptr(value).to_string_lossy().into_owned();
+            glib_sys::g_free(value as _);
+
+            Some(string)
+        }
+    }
+
+    fn kernel_cmdline(&self) -> Option<String> {
+        self.property("kernel-cmdline")
+            .filter(|cmdline| !cmdline.is_empty())
+    }
+
+    fn ram_size(&self) -> u64 {
+        unsafe { sys::ram_size as u64 }
+    }
+}
+
+/// A guest physical address, as passed to callbacks such as
+/// [`replay_before_dma`](crate::replay_before_dma) as a raw [`hwaddr`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct PhysAddr(pub hwaddr);
+
+impl PhysAddr {
+    /// The raw address
+    pub fn get(self) -> hwaddr {
+        self.0
+    }
+
+    /// Read `len` bytes of guest physical memory starting at this address
+    pub fn read(self, len: usize) -> Result<Vec<u8>, MemRWStatus> {
+        physical_memory_read(self.0 as target_ulong, len)
+    }
+
+    /// Write to guest physical memory starting at this address
+    pub fn write(self, data: &[u8]) -> Result<(), MemRWStatus> {
+        status_result(physical_memory_write(self.0 as target_ulong, data))
+    }
+
+    /// The address `offset` bytes after this one
+    pub fn offset(self, offset: hwaddr) -> Self {
+        PhysAddr(self.0.wrapping_add(offset))
+    }
+}
+
+impl From<hwaddr> for PhysAddr {
+    fn from(addr: hwaddr) -> Self {
+        PhysAddr(addr)
+    }
+}
+
+impl From<PhysAddr> for hwaddr {
+    fn from(addr: PhysAddr) -> Self {
+        addr.0
+    }
+}
+
+impl fmt::LowerHex for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
@@ -0,0 +1,183 @@
+//! Deferred host-side work run from PANDA's main loop
+//!
+//! Heavy work done from a guest callback stalls the vCPU for as long as it takes.
+//! Instead, work which doesn't need to happen at a particular point in guest execution
+//! (flushing logs, recomputing indexes, writing reports) can be deferred to the main
+//! loop, which runs between periods of guest execution:
+//!
+//! * [`on_idle`] runs a callback from the main loop at most once per interval
+//! * [`spawn`] queues a task which is resumed from the main loop until it finishes
+//!
+//! To keep the stalls this causes predictable, the work done each time the main loop
+//! runs is limited by a time budget (see [`set_budget`]). Callbacks and tasks are
+//! passed the [`Budget`] for the current iteration and are expected to stop, returning
+//! [`TaskStatus::Pending`] to be resumed later, once it is exhausted. The budget is
+//! cooperative: work which doesn't check it can still overrun it.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::idle::{self, TaskStatus};
+//! use panda::PluginHandle;
+//!
+//! use std::time::Duration;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     idle::on_idle(Duration::from_secs(1), |_| println!("flushing logs"));
+//!
+//!     let mut remaining = 1_000_000;
+//!     idle::spawn(move |budget| {
+//!         while remaining > 0 && !budget.is_exhausted() {
+//!             remaining -= 1; // do a unit of work
+//!         }
+//!
+//!         if remaining == 0 {
+//!             TaskStatus::Done
+//!         } else {
+//!             TaskStatus::Pending
+//!         }
+//!     });
+//! }
+//! ```
+use crate::Callback;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The default time spent on deferred work each time the main loop runs
+pub const DEFAULT_BUDGET: Duration = Duration::from_millis(5);
+
+/// The time remaining for deferred work in the current iteration of the main loop
+#[derive(Debug, Copy, Clone)]
+pub struct Budget {
+    deadline: Instant,
+}
+
+impl Budget {
+    /// The time left before the budget is exhausted
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether work should stop until the next iteration of the main loop
+    pub fn is_exhausted(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
+
+/// Whether a task has finished or should be resumed in a later iteration
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    Done,
+    Pending,
+}
+
+type IdleCallback = Box<dyn FnMut(&Budget) + Send + 'static>;
+type Task = Box<dyn FnMut(&Budget) -> TaskStatus + Send + 'static>;
+
+struct IdleState {
+    budget: Duration,
+    callbacks: Vec<(Duration, Option<Instant>, IdleCallback)>,
+    tasks: VecDeque<Task>,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<IdleState> = Mutex::new(IdleState {
+        budget: DEFAULT_BUDGET,
+        callbacks: Vec::new(),
+        tasks: VecDeque::new(),
+    });
+
+    static ref CALLBACK: Callback = install_callback();
+}
+
+fn install_callback() -> Callback {
+    let callback = Callback::new();
+
+    // callbacks and tasks are run without the state locked, so that they can spawn
+    // further work
+    callback.main_loop_wait(|| {
+        let (budget, mut callbacks, task_count) = {
+            let mut state = STATE.lock().unwrap();
+            let budget = Budget {
+                deadline: Instant::now() + state.budget,
+            };
+
+            (
+                budget,
+                std::mem::take(&mut state.callbacks),
+                state.tasks.len(),
+            )
+        };
+
+        let now = Instant::now();
+        for (interval, last_run, callback) in &mut callbacks {
+            if budget.is_exhausted() {
+                break;
+            }
+
+            if last_run.map_or(true, |last_run| now - last_run >= *interval) {
+                *last_run = Some(now);
+                callback(&budget);
+            }
+        }
+
+        {
+            let mut state = STATE.lock().unwrap();
+            callbacks.append(&mut state.callbacks);
+            state.callbacks = callbacks;
+        }
+
+        // each task runs at most once per iteration, in the order they were spawned
+        for _ in 0..task_count {
+            if budget.is_exhausted() {
+                break;
+            }
+
+            let mut task = match STATE.lock().unwrap().tasks.pop_front() {
+                Some(task) => task,
+                None => break,
+            };
+
+            if task(&budget) == TaskStatus::Pending {
+                STATE.lock().unwrap().tasks.push_back(task);
+            }
+        }
+    });
+
+    callback
+}
+
+/// Set the time spent on deferred work each time the main loop runs, shared between
+/// all idle callbacks and tasks. Defaults to [`DEFAULT_BUDGET`].
+pub fn set_budget(budget: Duration) {
+    STATE.lock().unwrap().budget = budget;
+}
+
+/// Run a callback from the main loop, at most once per `min_interval`. Callbacks are
+/// skipped when the budget has already been used up, and run again on the next
+/// iteration of the main loop.
+pub fn on_idle(min_interval: Duration, callback: impl FnMut(&Budget) + Send + 'static) {
+    lazy_static::initialize(&CALLBACK);
+
+    STATE
+        .lock()
+        .unwrap()
+        .callbacks
+        .push((min_interval, None, Box::new(callback)));
+}
+
+/// Queue a task to run from the main loop. The task is called once per iteration of the
+/// main loop until it returns [`TaskStatus::Done`].
+pub fn spawn(task: impl FnMut(&Budget) -> TaskStatus + Send + 'static) {
+    lazy_static::initialize(&CALLBACK);
+
+    STATE.lock().unwrap().tasks.push_back(Box::new(task));
+}
+
+/// The number of tasks which haven't finished yet
+pub fn pending_tasks() -> usize {
+    STATE.lock().unwrap().tasks.len()
+}
@@ -0,0 +1,299 @@
+//! Callbacks for classes of instructions rather than specific addresses
+//!
+//! Calls, returns, indirect calls and jumps, and privileged instructions can each be
+//! hooked with a single callback, either using the functions in this module or the
+//! matching attribute macros ([`#[panda::on_call]`](macro@crate::on_call) and so on).
+//! Instructions are classified by capstone (see [`disas::groups`]) when they are
+//! translated, and only instructions of a class with callbacks registered are
+//! instrumented. Requires the `disas` feature.
+//!
+//! Branch callbacks are passed the address of the branch and its resolved target, the
+//! address execution continued at. As the target is only known once the branch has
+//! executed, they are run just before the target is executed, or before an interrupt
+//! or exception is handled if one arrives first. Privileged instruction callbacks are
+//! run just before the instruction executes.
+//!
+//! Registering a callback for a class without callbacks flushes the translation cache
+//! so that already-translated code is instrumented.
+//!
+//! [`disas::groups`]: crate::disas::groups
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//!
+//! #[panda::on_indirect_call]
+//! fn indirect_call(_: &mut CPUState, pc: target_ulong, target: target_ulong) {
+//!     println!("{:#x}: indirect call to {:#x}", pc, target);
+//! }
+//!
+//! #[panda::on_privileged]
+//! fn privileged(_: &mut CPUState, pc: target_ulong) {
+//!     println!("{:#x}: privileged instruction", pc);
+//! }
+//! ```
+use crate::disas::{self, InsnGroups};
+use crate::prelude::*;
+use crate::tb_invalidation::flush_tb;
+use crate::{current_asid, current_pc, Callback};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+type BranchCallback = Box<dyn FnMut(&mut CPUState, target_ulong, target_ulong) + Send + 'static>;
+type PrivilegedCallback = Box<dyn FnMut(&mut CPUState, target_ulong) + Send + 'static>;
+
+/// The class of a marked instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Marked {
+    Call { indirect: bool },
+    Ret,
+    IndirectJump,
+    Privileged,
+}
+
+#[derive(Default)]
+struct Dispatcher {
+    calls: Vec<BranchCallback>,
+    indirect_calls: Vec<BranchCallback>,
+    rets: Vec<BranchCallback>,
+    indirect_jumps: Vec<BranchCallback>,
+    privileged: Vec<PrivilegedCallback>,
+
+    /// The class of each marked instruction, by address space and address
+    marked: HashMap<(target_ulong, target_ulong), Marked>,
+
+    /// The class and address of the branch each address space has executed but whose
+    /// target hasn't been reached yet
+    pending: HashMap<target_ulong, (Marked, target_ulong)>,
+}
+
+impl Dispatcher {
+    fn mark(&self, groups: InsnGroups) -> Option<Marked> {
+        let marked = if groups.ret {
+            Marked::Ret
+        } else if groups.call {
+            Marked::Call {
+                indirect: groups.indirect,
+            }
+        } else if groups.privileged {
+            Marked::Privileged
+        } else if groups.jump && groups.indirect {
+            Marked::IndirectJump
+        } else {
+            return None;
+        };
+
+        self.wants(marked).then(|| marked)
+    }
+
+    /// Get the class of the instruction at `pc` in the current address space, marking
+    /// it if it was translated in another one
+    fn marked(&mut self, cpu: &mut CPUState, pc: target_ulong) -> Option<Marked> {
+        let key = (current_asid(cpu), pc);
+        if let Some(&marked) = self.marked.get(&key) {
+            return Some(marked);
+        }
+
+        let marked = self.mark(disas::groups(cpu, pc).ok()?)?;
+        self.marked.insert(key, marked);
+
+        Some(marked)
+    }
+
+    fn wants(&self, marked: Marked) -> bool {
+        match marked {
+            Marked::Call { indirect } => {
+                !self.calls.is_empty() || (indirect && !self.indirect_calls.is_empty())
+            }
+            Marked::Ret => !self.rets.is_empty(),
+            Marked::IndirectJump => !self.indirect_jumps.is_empty(),
+            Marked::Privileged => !self.privileged.is_empty(),
+        }
+    }
+}
+
+/// Run each callback in the list selected by `list`, without the dispatcher locked so
+/// that the callbacks can register further callbacks
+fn run_callbacks<C>(list: impl Fn(&mut Dispatcher) -> &mut Vec<C>, mut run: impl FnMut(&mut C)) {
+    let mut callbacks = std::mem::take(list(&mut DISPATCHER.lock().unwrap()));
+    for callback in &mut callbacks {
+        run(callback);
+    }
+
+    let mut dispatcher = DISPATCHER.lock().unwrap();
+    let list = list(&mut dispatcher);
+    callbacks.append(list);
+    *list = callbacks;
+}
+
+/// Run the callbacks for a branch which has been taken, if there is one
+fn branch_taken(cpu: &mut CPUState, target: target_ulong) {
+    let pending = DISPATCHER
+        .lock()
+        .unwrap()
+        .pending
+        .remove(&current_asid(cpu));
+
+    let (marked, pc) = match pending {
+        Some(pending) => pending,
+        None => return,
+    };
+
+    let mut run = |callback: &mut BranchCallback| callback(cpu, pc, target);
+    match marked {
+        Marked::Call { indirect } => {
+            run_callbacks(|dispatcher| &mut dispatcher.calls, &mut run);
+
+            if indirect {
+                run_callbacks(|dispatcher| &mut dispatcher.indirect_calls, &mut run);
+            }
+        }
+        Marked::Ret => run_callbacks(|dispatcher| &mut dispatcher.rets, run),
+        Marked::IndirectJump => run_callbacks(|dispatcher| &mut dispatcher.indirect_jumps, run),
+        Marked::Privileged => (),
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref DISPATCHER: Mutex<Dispatcher> = Mutex::new(Dispatcher::default());
+    static ref CALLBACKS: [Callback; 5] = install_callbacks();
+}
+
+fn install_callbacks() -> [Callback; 5] {
+    let translate = Callback::new();
+    let exec = Callback::new();
+    let block = Callback::new();
+    let exception = Callback::new();
+    let interrupt = Callback::new();
+
+    translate.insn_translate(|cpu, pc| {
+        let groups = match disas::groups(cpu, pc) {
+            Ok(groups) => groups,
+            Err(_) => return false,
+        };
+
+        let key = (current_asid(cpu), pc);
+        let mut dispatcher = DISPATCHER.lock().unwrap();
+
+        match dispatcher.mark(groups) {
+            Some(marked) => {
+                dispatcher.marked.insert(key, marked);
+                true
+            }
+            None => {
+                dispatcher.marked.remove(&key);
+                false
+            }
+        }
+    });
+
+    // translated code can be shared between address spaces, so instructions may be
+    // executed in one they weren't marked in
+    exec.insn_exec(|cpu, pc| {
+        let mut dispatcher = DISPATCHER.lock().unwrap();
+        match dispatcher.marked(cpu, pc) {
+            Some(Marked::Privileged) => {
+                drop(dispatcher);
+                run_callbacks(
+                    |dispatcher| &mut dispatcher.privileged,
+                    |callback| callback(cpu, pc),
+                );
+            }
+            Some(marked) => {
+                let asid = current_asid(cpu);
+                dispatcher.pending.insert(asid, (marked, pc));
+            }
+            None => (),
+        }
+    });
+
+    block.before_block_exec(|cpu, tb| {
+        branch_taken(cpu, tb.pc);
+    });
+
+    // the branch has completed by the time an interrupt is handled, so the pc is its
+    // target rather than the start of the handler
+    exception.before_handle_exception(|cpu, exception_index| {
+        let pc = current_pc(cpu);
+        branch_taken(cpu, pc);
+
+        exception_index
+    });
+
+    interrupt.before_handle_interrupt(|cpu, interrupt_index| {
+        let pc = current_pc(cpu);
+        branch_taken(cpu, pc);
+
+        interrupt_index
+    });
+
+    [translate, exec, block, exception, interrupt]
+}
+
+/// Add a callback to the list selected by `list`, flushing the translation cache if
+/// instructions it needs weren't being instrumented before
+fn register<C>(callback: C, list: impl FnOnce(&mut Dispatcher) -> &mut Vec<C>) {
+    lazy_static::initialize(&CALLBACKS);
+
+    let was_empty = {
+        let mut dispatcher = DISPATCHER.lock().unwrap();
+        let callbacks = list(&mut dispatcher);
+        callbacks.push(callback);
+
+        callbacks.len() == 1
+    };
+
+    if was_empty {
+        flush_tb();
+    }
+}
+
+/// Register a callback to be run after each call instruction, direct or indirect, is
+/// executed. The callback is passed the address of the call and the address of the
+/// function called.
+pub fn on_call(callback: impl FnMut(&mut CPUState, target_ulong, target_ulong) + Send + 'static) {
+    register(Box::new(callback) as BranchCallback, |dispatcher| {
+        &mut dispatcher.calls
+    });
+}
+
+/// Register a callback to be run after each indirect call (through a register or
+/// memory) is executed. The callback is passed the address of the call and the address
+/// of the function called.
+pub fn on_indirect_call(
+    callback: impl FnMut(&mut CPUState, target_ulong, target_ulong) + Send + 'static,
+) {
+    register(Box::new(callback) as BranchCallback, |dispatcher| {
+        &mut dispatcher.indirect_calls
+    });
+}
+
+/// Register a callback to be run after each return instruction is executed. The
+/// callback is passed the address of the return and the address returned to.
+pub fn on_ret(callback: impl FnMut(&mut CPUState, target_ulong, target_ulong) + Send + 'static) {
+    register(Box::new(callback) as BranchCallback, |dispatcher| {
+        &mut dispatcher.rets
+    });
+}
+
+/// Register a callback to be run after each indirect jump (through a register or
+/// memory, other than calls and returns) is executed. The callback is passed the
+/// address of the jump and the address jumped to.
+pub fn on_indirect_jump(
+    callback: impl FnMut(&mut CPUState, target_ulong, target_ulong) + Send + 'static,
+) {
+    register(Box::new(callback) as BranchCallback, |dispatcher| {
+        &mut dispatcher.indirect_jumps
+    });
+}
+
+/// Register a callback to be run before each privileged instruction (as classified by
+/// [`InsnGroups::privileged`]) is executed. The callback is passed the address of the
+/// instruction.
+pub fn on_privileged(callback: impl FnMut(&mut CPUState, target_ulong) + Send + 'static) {
+    register(Box::new(callback) as PrivilegedCallback, |dispatcher| {
+        &mut dispatcher.privileged
+    });
+}
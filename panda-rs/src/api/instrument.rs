@@ -0,0 +1,358 @@
+//! Declarative selection of which instructions to instrument
+//!
+//! Rather than writing [`insn_translate`] and [`insn_exec`] callbacks by hand, a
+//! [`Policy`] describes which instructions are of interest (by address range, process
+//! and instruction class) and this module only instruments instructions matching it.
+//! Address ranges and instruction classes are checked once at translation time, while
+//! process filters are checked at execution time as translated code can be shared
+//! between processes. Instructions are classified by capstone (see [`disas::groups`]),
+//! so this requires the `disas` feature.
+//!
+//! Changing the policy flushes the translation cache so that code translated under the
+//! previous policy is retranslated.
+//!
+//! [`insn_translate`]: crate::insn_translate
+//! [`insn_exec`]: crate::insn_exec
+//! [`disas::groups`]: crate::disas::groups
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::instrument::{self, InsnClass, Policy};
+//!
+//! instrument::set_policy(
+//!     Policy::new()
+//!         .range(0x400000..0x500000)
+//!         .process_name("target")
+//!         .class(InsnClass::Call)
+//!         .class(InsnClass::Ret),
+//! );
+//!
+//! instrument::on_insn(|_, pc, class| {
+//!     println!("{:?} @ {:#x}", class, pc);
+//! });
+//! ```
+use crate::disas::{self, InsnGroups};
+use crate::plugins::osi::OSI;
+use crate::prelude::*;
+use crate::tb_invalidation::flush_tb;
+use crate::{current_asid, Callback};
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Mutex;
+
+/// The maximum number of selected instructions whose class is remembered. Once this
+/// many have been selected the classes are forgotten, and are looked up again as the
+/// instructions are executed.
+const MAX_SELECTED: usize = 1 << 20;
+
+/// A class of instruction which can be selected by a [`Policy`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum InsnClass {
+    /// Function calls, both direct and indirect
+    Call,
+
+    /// Function returns
+    Ret,
+
+    /// Instructions which can only be executed by the kernel (control register, MSR,
+    /// port I/O, etc)
+    Privileged,
+
+    /// Any other instruction
+    Other,
+}
+
+impl From<InsnGroups> for InsnClass {
+    fn from(groups: InsnGroups) -> Self {
+        if groups.ret {
+            InsnClass::Ret
+        } else if groups.call {
+            InsnClass::Call
+        } else if groups.privileged {
+            InsnClass::Privileged
+        } else {
+            InsnClass::Other
+        }
+    }
+}
+
+/// A filter for which processes' instructions are instrumented
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessFilter {
+    /// Processes with the given address space
+    Asid(target_ulong),
+
+    /// Processes with the given name, as reported by OSI
+    Name(String),
+}
+
+/// A description of which instructions should be instrumented. Each kind of filter
+/// which is left empty matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    ranges: Vec<Range<target_ulong>>,
+    processes: Vec<ProcessFilter>,
+    classes: Vec<InsnClass>,
+}
+
+impl Policy {
+    /// Create a policy which instruments every instruction
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only instrument instructions within the given address range
+    pub fn range(mut self, range: Range<target_ulong>) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Only instrument instructions executed by the process with the given address space
+    pub fn asid(mut self, asid: target_ulong) -> Self {
+        self.processes.push(ProcessFilter::Asid(asid));
+        self
+    }
+
+    /// Only instrument instructions executed by processes with the given name. Requires
+    /// the OSI plugin.
+    pub fn process_name(mut self, name: impl Into<String>) -> Self {
+        self.processes.push(ProcessFilter::Name(name.into()));
+        self
+    }
+
+    /// Only instrument instructions of the given class
+    pub fn class(mut self, class: InsnClass) -> Self {
+        self.classes.push(class);
+        self
+    }
+
+    fn in_range(&self, pc: target_ulong) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc))
+    }
+
+    fn wants_class(&self, class: InsnClass) -> bool {
+        self.classes.is_empty() || self.classes.contains(&class)
+    }
+
+    /// Get the class of the instruction at `pc` if the policy selects it
+    fn select(&self, cpu: &mut CPUState, pc: target_ulong) -> Option<InsnClass> {
+        if !self.in_range(pc) {
+            return None;
+        }
+
+        let class = classify_at(cpu, pc);
+
+        self.wants_class(class).then(|| class)
+    }
+}
+
+type InsnCallback = Box<dyn FnMut(&mut CPUState, target_ulong, InsnClass) + Send + 'static>;
+
+#[derive(Default)]
+struct Instrumenter {
+    policy: Option<Policy>,
+    callbacks: Vec<InsnCallback>,
+
+    /// The class of each instruction selected, by address space and address
+    selected: HashMap<(target_ulong, target_ulong), InsnClass>,
+
+    /// Whether each address space passes the process filter, cached to avoid querying
+    /// OSI on every instruction
+    process_matches: HashMap<target_ulong, bool>,
+}
+
+/// Remember the class of a selected instruction, forgetting every class remembered so
+/// far if there are too many
+fn remember(
+    selected: &mut HashMap<(target_ulong, target_ulong), InsnClass>,
+    key: (target_ulong, target_ulong),
+    class: InsnClass,
+) {
+    if selected.len() >= MAX_SELECTED && !selected.contains_key(&key) {
+        selected.clear();
+    }
+
+    selected.insert(key, class);
+}
+
+/// Run the callbacks without the instrumentation state locked, so that they can change
+/// the policy or register further callbacks
+fn run_callbacks(cpu: &mut CPUState, pc: target_ulong, class: InsnClass) {
+    let mut callbacks = std::mem::take(&mut INSTRUMENTER.lock().unwrap().callbacks);
+    for callback in &mut callbacks {
+        callback(cpu, pc, class);
+    }
+
+    let mut instrumenter = INSTRUMENTER.lock().unwrap();
+    callbacks.append(&mut instrumenter.callbacks);
+    instrumenter.callbacks = callbacks;
+}
+
+/// Check whether the current process passes the policy's process filter, caching the
+/// result per address space
+fn process_matches(
+    cache: &mut HashMap<target_ulong, bool>,
+    cpu: &mut CPUState,
+    policy: &Policy,
+) -> bool {
+    if policy.processes.is_empty() {
+        return true;
+    }
+
+    let asid = current_asid(cpu);
+    if let Some(&matches) = cache.get(&asid) {
+        return matches;
+    }
+
+    let needs_name = policy
+        .processes
+        .iter()
+        .any(|filter| matches!(filter, ProcessFilter::Name(_)));
+
+    let name = if needs_name {
+        OSI.get_current_process(cpu)
+            .map(|process| process.get_name().into_owned())
+    } else {
+        None
+    };
+
+    let matches = policy.processes.iter().any(|filter| match filter {
+        ProcessFilter::Asid(filter_asid) => *filter_asid == asid,
+        ProcessFilter::Name(filter_name) => name.as_deref() == Some(filter_name.as_str()),
+    });
+
+    // Only cache once OSI knows the process, as names aren't available early on
+    if !needs_name || name.is_some() {
+        cache.insert(asid, matches);
+    }
+
+    matches
+}
+
+lazy_static::lazy_static! {
+    static ref INSTRUMENTER: Mutex<Instrumenter> = Mutex::new(Instrumenter::default());
+    static ref CALLBACKS: [Callback; 3] = install_callbacks();
+}
+
+fn install_callbacks() -> [Callback; 3] {
+    let translate = Callback::new();
+    let exec = Callback::new();
+    let asid_changed = Callback::new();
+
+    translate.insn_translate(|cpu, pc| {
+        let mut instrumenter = INSTRUMENTER.lock().unwrap();
+        let class = match &instrumenter.policy {
+            Some(policy) => policy.select(cpu, pc),
+            None => return false,
+        };
+
+        let key = (current_asid(cpu), pc);
+        match class {
+            Some(class) => {
+                remember(&mut instrumenter.selected, key, class);
+                true
+            }
+            None => {
+                instrumenter.selected.remove(&key);
+                false
+            }
+        }
+    });
+
+    // translated code can be shared between address spaces, so instructions may be
+    // executed in one they weren't selected in
+    exec.insn_exec(|cpu, pc| {
+        let mut guard = INSTRUMENTER.lock().unwrap();
+        let instrumenter = &mut *guard;
+
+        let policy = match &instrumenter.policy {
+            Some(policy) => policy,
+            None => return,
+        };
+
+        let key = (current_asid(cpu), pc);
+        let class = match instrumenter.selected.get(&key) {
+            Some(&class) => class,
+            None => match policy.select(cpu, pc) {
+                Some(class) => {
+                    remember(&mut instrumenter.selected, key, class);
+                    class
+                }
+                None => return,
+            },
+        };
+
+        if process_matches(&mut instrumenter.process_matches, cpu, policy) {
+            drop(guard);
+            run_callbacks(cpu, pc, class);
+        }
+    });
+
+    // Process names can change on exec, so don't trust cached matches across switches
+    // into a different process
+    asid_changed.asid_changed(|_, old_asid, _| {
+        INSTRUMENTER
+            .lock()
+            .unwrap()
+            .process_matches
+            .remove(&old_asid);
+
+        false
+    });
+
+    [translate, exec, asid_changed]
+}
+
+/// Set the instrumentation policy, replacing any previous policy and retranslating
+/// code so the new policy takes effect immediately
+pub fn set_policy(policy: Policy) {
+    lazy_static::initialize(&CALLBACKS);
+
+    {
+        let mut instrumenter = INSTRUMENTER.lock().unwrap();
+        instrumenter.policy = Some(policy);
+        instrumenter.selected.clear();
+        instrumenter.process_matches.clear();
+    }
+
+    flush_tb();
+}
+
+/// Remove the instrumentation policy, disabling instrumentation
+pub fn clear_policy() {
+    {
+        let mut instrumenter = INSTRUMENTER.lock().unwrap();
+        instrumenter.policy = None;
+        instrumenter.selected.clear();
+        instrumenter.process_matches.clear();
+    }
+
+    flush_tb();
+}
+
+/// Get the current instrumentation policy
+pub fn policy() -> Option<Policy> {
+    INSTRUMENTER.lock().unwrap().policy.clone()
+}
+
+/// Register a callback to be run before each instruction selected by the policy is
+/// executed, along with the class of the instruction.
+pub fn on_insn(callback: impl FnMut(&mut CPUState, target_ulong, InsnClass) + Send + 'static) {
+    lazy_static::initialize(&CALLBACKS);
+
+    INSTRUMENTER
+        .lock()
+        .unwrap()
+        .callbacks
+        .push(Box::new(callback));
+}
+
+/// Classify the instruction at `pc` in guest memory. Instructions which can't be read
+/// or disassembled are classed as [`InsnClass::Other`].
+pub fn classify_at(cpu: &mut CPUState, pc: target_ulong) -> InsnClass {
+    disas::groups(cpu, pc)
+        .map(InsnClass::from)
+        .unwrap_or(InsnClass::Other)
+}
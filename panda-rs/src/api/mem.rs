@@ -1,11 +1,27 @@
+use crate::audit;
 use crate::enums::MemRWStatus;
 use crate::prelude::*;
 use crate::GuestType;
 use crate::{sys, Error};
 use crate::{GuestReadFail, GuestWriteFail};
 
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+use crate::segment::SegAddr;
+
 use std::ffi::CString;
 use std::os::raw::c_char;
+use std::sync::Mutex;
+
+mod batch;
+mod encoding;
+mod pages;
+mod ram;
+mod regions;
+pub use batch::*;
+pub use encoding::*;
+pub use pages::*;
+pub use ram::*;
+pub use regions::*;
 
 // Public API ----------------------------------------------------------------------------------------------------------
 
@@ -178,7 +194,7 @@ pub fn physical_memory_read_into(addr: target_ulong, buf: &mut [u8]) -> Result<(
 /// Write to guest virtual memory
 pub fn virtual_memory_write(cpu: &mut CPUState, addr: target_ulong, data: &[u8]) -> MemRWStatus {
     let mut c_data = data.to_vec(); // Alloc b/c C API wants mut
-    unsafe {
+    let status: MemRWStatus = unsafe {
         panda_sys::panda_virtual_memory_write_external(
             cpu,
             addr,
@@ -186,20 +202,38 @@ pub fn virtual_memory_write(cpu: &mut CPUState, addr: target_ulong, data: &[u8])
             c_data.len() as i32,
         )
         .into()
+    };
+
+    if status == MemRWStatus::MemTxOk {
+        audit::record(Some(cpu), || audit::Mutation::VirtualMemory {
+            addr,
+            data: data.to_vec(),
+        });
     }
+
+    status
 }
 
 /// Write to guest physical memory
 pub fn physical_memory_write(addr: target_ulong, data: &[u8]) -> MemRWStatus {
     let mut c_data = data.to_vec(); // Alloc b/c C API wants mut
-    unsafe {
+    let status: MemRWStatus = unsafe {
         panda_sys::panda_physical_memory_write_external(
             addr as _,
             c_data.as_mut_ptr(),
             c_data.len() as i32,
         )
         .into()
+    };
+
+    if status == MemRWStatus::MemTxOk {
+        audit::record(None, || audit::Mutation::PhysicalMemory {
+            addr,
+            data: data.to_vec(),
+        });
     }
+
+    status
 }
 
 /// Translate guest virtual address to physical address, returning `None` if no mapping
@@ -211,23 +245,119 @@ pub fn virt_to_phys(cpu: &mut CPUState, addr: target_ulong) -> Option<target_ulo
     }
 }
 
-pub const PAGE_SIZE: target_ulong = 1024;
+/// Read from guest virtual memory at a segment-qualified address
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub fn virtual_memory_read_seg(
+    cpu: &mut CPUState,
+    addr: SegAddr,
+    len: usize,
+) -> Result<Vec<u8>, MemRWStatus> {
+    let linear = addr.linear(cpu)?;
+
+    virtual_memory_read(cpu, linear, len)
+}
+
+/// Read from guest virtual memory at a segment-qualified address into a buffer
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub fn virtual_memory_read_into_seg(
+    cpu: &mut CPUState,
+    addr: SegAddr,
+    buf: &mut [u8],
+) -> Result<(), MemRWStatus> {
+    let linear = addr.linear(cpu)?;
+
+    virtual_memory_read_into(cpu, linear, buf)
+}
+
+/// Write to guest virtual memory at a segment-qualified address
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub fn virtual_memory_write_seg(cpu: &mut CPUState, addr: SegAddr, data: &[u8]) -> MemRWStatus {
+    match addr.linear(cpu) {
+        Ok(linear) => virtual_memory_write(cpu, linear, data),
+        Err(err) => err,
+    }
+}
+
+/// Translate a segment-qualified guest address to a physical address, returning `None`
+/// if the segment can't be resolved or no mapping can be found.
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub fn virt_to_phys_seg(cpu: &mut CPUState, addr: SegAddr) -> Option<target_ulong> {
+    let linear = addr.linear(cpu).ok()?;
+
+    virt_to_phys(cpu, linear)
+}
 
-/// Map RAM into the system at a given physical address
+/// A region of RAM mapped into the system with [`map_memory`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedRegion {
+    pub name: String,
+
+    /// The physical address the region starts at
+    pub addr: target_ptr_t,
+
+    /// The size of the region in bytes
+    pub size: target_ulong,
+}
+
+impl MappedRegion {
+    /// Check whether the region contains the given physical address
+    pub fn contains(&self, addr: target_ptr_t) -> bool {
+        addr >= self.addr && addr - self.addr < self.size as target_ptr_t
+    }
+
+    fn overlaps(&self, addr: target_ptr_t, size: target_ulong) -> bool {
+        addr < self.addr + self.size as target_ptr_t && self.addr < addr + size as target_ptr_t
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref MAPPED_REGIONS: Mutex<Vec<MappedRegion>> = Mutex::new(Vec::new());
+}
+
+/// Map RAM into the system at a given physical address. Both the address and the size
+/// must be aligned to the guest [`page_size`] (see [`page_align_up`]), and the region
+/// must not overlap a region previously mapped with this function.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::mem::{map_memory, page_align_up};
+///
+/// // Map 2MB of memory at 0x1000
+/// map_memory("mymem", page_align_up(2 * 1024 * 1024), 0x1000).unwrap();
+/// ```
 pub fn map_memory(name: &str, size: target_ulong, addr: target_ptr_t) -> Result<(), Error> {
-    let name = CString::new(name)?;
+    let c_name = CString::new(name)?;
 
-    if size % PAGE_SIZE != 0 {
-        Err(Error::UnalignedPageSize)
-    } else {
-        unsafe {
-            sys::map_memory(name.as_ptr() as _, size as _, addr as _);
-        }
+    if size == 0 || size % page_size() != 0 {
+        return Err(Error::UnalignedPageSize);
+    }
 
-        drop(name);
+    if addr as target_ulong % page_size() != 0 {
+        return Err(Error::UnalignedAddress);
+    }
 
-        Ok(())
+    let mut regions = MAPPED_REGIONS.lock().unwrap();
+    if let Some(region) = regions.iter().find(|region| region.overlaps(addr, size)) {
+        return Err(Error::OverlappingRegion(region.name.clone()));
     }
+
+    unsafe {
+        sys::map_memory(c_name.as_ptr() as _, size as _, addr as _);
+    }
+
+    regions.push(MappedRegion {
+        name: name.to_owned(),
+        addr,
+        size,
+    });
+
+    Ok(())
+}
+
+/// Get the regions of RAM mapped with [`map_memory`], in the order they were mapped
+pub fn mapped_regions() -> Vec<MappedRegion> {
+    MAPPED_REGIONS.lock().unwrap().clone()
 }
 
 const IS_32_BIT: bool = std::mem::size_of::<target_ptr_t>() == 4;
@@ -0,0 +1,163 @@
+use super::{
+    page_size, physical_memory_read_into, virt_to_phys, virtual_memory_read_into,
+    virtual_memory_write,
+};
+use crate::audit;
+use crate::enums::MemRWStatus;
+use crate::prelude::*;
+
+use std::collections::HashMap;
+
+/// Caches virtual to physical translations for the duration of a batch, at the
+/// granularity of the smallest guest page size
+struct Translator<'a> {
+    cpu: &'a mut CPUState,
+    page_size: target_ulong,
+    pages: HashMap<target_ulong, Option<target_ulong>>,
+}
+
+impl<'a> Translator<'a> {
+    fn new(cpu: &'a mut CPUState) -> Self {
+        Self {
+            cpu,
+            page_size: page_size(),
+            pages: HashMap::new(),
+        }
+    }
+
+    fn translate(&mut self, addr: target_ulong) -> Option<target_ulong> {
+        let page = addr & !(self.page_size - 1);
+        let cpu = &mut *self.cpu;
+        let phys_page = *self
+            .pages
+            .entry(page)
+            .or_insert_with(|| virt_to_phys(cpu, page));
+
+        phys_page.map(|phys_page| phys_page + (addr - page))
+    }
+
+    /// Split `addr..addr + len` into runs which are contiguous in physical memory, as
+    /// `(offset, phys_addr, len)`. Returns `None` if any page is unmapped.
+    fn phys_runs(
+        &mut self,
+        addr: target_ulong,
+        len: usize,
+    ) -> Option<Vec<(usize, target_ulong, usize)>> {
+        let mut runs: Vec<(usize, target_ulong, usize)> = Vec::new();
+        let mut offset = 0;
+
+        while offset < len {
+            let chunk_addr = addr.wrapping_add(offset as target_ulong);
+            let page_left = (self.page_size - (chunk_addr & (self.page_size - 1))) as usize;
+            let chunk_len = page_left.min(len - offset);
+            let phys_addr = self.translate(chunk_addr)?;
+
+            match runs.last_mut() {
+                Some((_, run_phys, run_len))
+                    if run_phys.wrapping_add(*run_len as target_ulong) == phys_addr =>
+                {
+                    *run_len += chunk_len;
+                }
+                _ => runs.push((offset, phys_addr, chunk_len)),
+            }
+
+            offset += chunk_len;
+        }
+
+        Some(runs)
+    }
+}
+
+/// Read several ranges of guest virtual memory, given as `(addr, len)` pairs, returning
+/// the result of each read in order.
+///
+/// Address translations are shared between all of the reads, and reads which are
+/// contiguous in physical memory are performed together, making this much faster than
+/// calling [`virtual_memory_read`](super::virtual_memory_read) for each range when
+/// sampling many small structures. Ranges which can't be read through physical memory
+/// (such as those backed by MMIO) fall back to a regular virtual memory read.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::mem::read_many;
+/// use panda::prelude::*;
+///
+/// # let cpu: &mut CPUState = todo!();
+/// # let (task, mm) = (0, 0);
+/// let results = read_many(cpu, &[(task, 0x10), (mm, 0x40)]);
+/// for result in results {
+///     if let Ok(bytes) = result {
+///         println!("{:02x?}", bytes);
+///     }
+/// }
+/// ```
+pub fn read_many(
+    cpu: &mut CPUState,
+    ranges: &[(target_ulong, usize)],
+) -> Vec<Result<Vec<u8>, MemRWStatus>> {
+    let mut translator = Translator::new(cpu);
+
+    ranges
+        .iter()
+        .map(|&(addr, len)| {
+            let mut buf = vec![0; len];
+
+            let read_phys = translator.phys_runs(addr, len).map_or(false, |runs| {
+                runs.into_iter().all(|(offset, phys_addr, len)| {
+                    physical_memory_read_into(phys_addr, &mut buf[offset..offset + len]).is_ok()
+                })
+            });
+
+            if read_phys {
+                Ok(buf)
+            } else {
+                virtual_memory_read_into(translator.cpu, addr, &mut buf).map(|_| buf)
+            }
+        })
+        .collect()
+}
+
+/// Write several ranges of guest virtual memory, given as `(addr, data)` pairs,
+/// returning the status of each write in order.
+///
+/// Like [`read_many`], address translations are shared between all of the writes.
+/// Writes to unmapped pages fall back to a regular virtual memory write. Each write is
+/// recorded in the [`audit`](crate::audit) log as a single virtual memory write.
+pub fn write_many(cpu: &mut CPUState, writes: &[(target_ulong, &[u8])]) -> Vec<MemRWStatus> {
+    let mut translator = Translator::new(cpu);
+
+    writes
+        .iter()
+        .map(|&(addr, data)| {
+            let runs = match translator.phys_runs(addr, data.len()) {
+                Some(runs) => runs,
+                None => return virtual_memory_write(translator.cpu, addr, data),
+            };
+
+            // The write is audited as a whole below, so bypass physical_memory_write
+            let mut c_data = data.to_vec(); // Alloc b/c C API wants mut
+            for (offset, phys_addr, len) in runs {
+                let status: MemRWStatus = unsafe {
+                    panda_sys::panda_physical_memory_write_external(
+                        phys_addr as _,
+                        c_data[offset..].as_mut_ptr(),
+                        len as i32,
+                    )
+                    .into()
+                };
+
+                if status != MemRWStatus::MemTxOk {
+                    return status;
+                }
+            }
+
+            audit::record(Some(&mut *translator.cpu), || audit::Mutation::VirtualMemory {
+                addr,
+                data: data.to_vec(),
+            });
+
+            MemRWStatus::MemTxOk
+        })
+        .collect()
+}
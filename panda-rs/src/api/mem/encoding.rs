@@ -0,0 +1,279 @@
+use super::{page_size, physical_memory_read_into, virtual_memory_read_into};
+use crate::os::{self, OsFamily};
+use crate::prelude::*;
+use crate::GuestReadFail;
+
+/// The maximum number of bytes read for a single guest string
+pub const MAX_GUEST_STRING_LEN: usize = 0x1000;
+
+/// The number of bytes examined when detecting the encoding of a guest string
+const DETECT_LEN: usize = 64;
+
+/// A text encoding used by guest strings
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Utf8,
+
+    /// UTF-16, little endian, as used by Windows (`wchar_t`, `UNICODE_STRING`)
+    Utf16Le,
+
+    /// UTF-16, big endian
+    Utf16Be,
+
+    /// ISO-8859-1, where each byte is the corresponding Unicode code point
+    Latin1,
+
+    /// The Windows "ANSI" codepage used by western-language Windows installs
+    Windows1252,
+
+    /// Guess the encoding from the string's contents (see [`detect_encoding`])
+    Detect,
+}
+
+impl Encoding {
+    /// The size of a single code unit in bytes
+    fn unit_size(self) -> usize {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be | Encoding::Detect => 2,
+            _ => 1,
+        }
+    }
+
+    /// Decode a string which does not include a terminator, replacing invalid
+    /// sequences with U+FFFD
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|unit| {
+                        let unit = [unit[0], unit[1]];
+                        if self == Encoding::Utf16Le {
+                            u16::from_le_bytes(unit)
+                        } else {
+                            u16::from_be_bytes(unit)
+                        }
+                    })
+                    .collect();
+
+                String::from_utf16_lossy(&units)
+            }
+            Encoding::Latin1 => bytes.iter().map(|&byte| byte as char).collect(),
+            Encoding::Windows1252 => bytes.iter().map(|&byte| windows_1252(byte)).collect(),
+            Encoding::Detect => detect_encoding(bytes).decode(bytes),
+        }
+    }
+}
+
+/// Characters for bytes 0x80..=0x9f in Windows-1252. Unassigned bytes map to the C1
+/// control character of the same value, matching Windows' behavior.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{0081}', '\u{201a}', '\u{0192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02c6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008d}', '\u{017d}', '\u{008f}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02dc}', '\u{2122}', '\u{0161}', '\u{203a}', '\u{0153}', '\u{009d}', '\u{017e}', '\u{0178}',
+];
+
+fn windows_1252(byte: u8) -> char {
+    match byte {
+        0x80..=0x9f => WINDOWS_1252_HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+/// Guess the encoding of a guest string from its raw bytes.
+///
+/// Strings where every other byte is zero are treated as UTF-16 (as is the case for
+/// text in Latin scripts), otherwise the string is treated as UTF-8 if it is valid
+/// UTF-8. If not, the legacy codepage of the guest OS family is assumed: Windows-1252
+/// for Windows guests and Latin-1 otherwise.
+pub fn detect_encoding(bytes: &[u8]) -> Encoding {
+    let pairs = &bytes[..bytes.len() & !1];
+    let pair_count = pairs.len() / 2;
+
+    if pair_count > 0 {
+        let even_zeros = pairs.iter().step_by(2).filter(|&&byte| byte == 0).count();
+        let odd_zeros = pairs
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .filter(|&&byte| byte == 0)
+            .count();
+
+        if pairs[0] != 0 && pairs[1] == 0 && odd_zeros * 2 >= pair_count && even_zeros <= 1 {
+            return Encoding::Utf16Le;
+        }
+
+        if pairs[0] == 0 && pairs[1] != 0 && even_zeros * 2 >= pair_count && odd_zeros <= 1 {
+            return Encoding::Utf16Be;
+        }
+    }
+
+    let narrow = match bytes.iter().position(|&byte| byte == 0) {
+        Some(end) => &bytes[..end],
+        None => bytes,
+    };
+
+    if std::str::from_utf8(narrow).is_ok() {
+        Encoding::Utf8
+    } else if os::family() == OsFamily::Windows {
+        Encoding::Windows1252
+    } else {
+        Encoding::Latin1
+    }
+}
+
+/// Read raw bytes from the guest until a NUL code unit (aligned to `unit` bytes from
+/// the start of the string) or `max_len` bytes have been read, excluding the terminator.
+fn read_until_nul(
+    cpu: &mut CPUState,
+    addr: target_ptr_t,
+    unit: usize,
+    max_len: usize,
+) -> Result<Vec<u8>, GuestReadFail> {
+    read_until_nul_with(addr, unit, max_len, |addr, buf| {
+        virtual_memory_read_into(cpu, addr, buf).is_ok()
+    })
+}
+
+/// [`read_until_nul`], reading memory a page at a time using `read`
+fn read_until_nul_with(
+    addr: target_ptr_t,
+    unit: usize,
+    max_len: usize,
+    mut read: impl FnMut(target_ptr_t, &mut [u8]) -> bool,
+) -> Result<Vec<u8>, GuestReadFail> {
+    let mut bytes = Vec::new();
+    let mut checked = 0;
+    let page_size = page_size();
+    let mut page = vec![0u8; page_size as usize];
+
+    while bytes.len() < max_len {
+        let current = addr.wrapping_add(bytes.len() as target_ptr_t);
+        let to_page_end = (page_size - (current % page_size)) as usize;
+        let len = to_page_end.min(max_len - bytes.len());
+
+        if !read(current, &mut page[..len]) {
+            if bytes.is_empty() {
+                return Err(GuestReadFail);
+            }
+
+            // Unterminated string running into unmapped memory
+            break;
+        }
+
+        bytes.extend_from_slice(&page[..len]);
+
+        while checked + unit <= bytes.len() {
+            if bytes[checked..checked + unit].iter().all(|&byte| byte == 0) {
+                bytes.truncate(checked);
+                return Ok(bytes);
+            }
+
+            checked += unit;
+        }
+    }
+
+    bytes.truncate(max_len - (max_len % unit));
+
+    Ok(bytes)
+}
+
+/// Read a NUL-terminated UTF-8 string from guest memory, replacing invalid UTF-8 with
+/// U+FFFD.
+///
+/// At most [`MAX_GUEST_STRING_LEN`] bytes are read. For other encodings, see
+/// [`read_guest_string_with`].
+pub fn read_guest_string(cpu: &mut CPUState, addr: target_ptr_t) -> Result<String, GuestReadFail> {
+    read_guest_string_with(cpu, addr, Encoding::Utf8)
+}
+
+/// Read a NUL-terminated UTF-8 string of at most `max_len` bytes from guest memory,
+/// replacing invalid UTF-8 with U+FFFD. Strings longer than `max_len` are truncated.
+pub fn read_guest_string_bounded(
+    cpu: &mut CPUState,
+    addr: target_ptr_t,
+    max_len: usize,
+) -> Result<String, GuestReadFail> {
+    let bytes = read_until_nul(cpu, addr, 1, max_len)?;
+
+    Ok(Encoding::Utf8.decode(&bytes))
+}
+
+/// Read a NUL-terminated UTF-16 string (a Windows `wchar_t *`) from guest memory,
+/// replacing invalid UTF-16 with U+FFFD.
+///
+/// At most [`MAX_GUEST_STRING_LEN`] bytes are read.
+pub fn read_guest_wstring(cpu: &mut CPUState, addr: target_ptr_t) -> Result<String, GuestReadFail> {
+    read_guest_string_with(cpu, addr, Encoding::Utf16Le)
+}
+
+/// Read a NUL-terminated string from guest memory as raw bytes, without the NUL. This
+/// is useful for strings such as Linux paths which aren't guaranteed to be UTF-8.
+///
+/// At most [`MAX_GUEST_STRING_LEN`] bytes are read.
+pub fn read_guest_bytes_until_nul(
+    cpu: &mut CPUState,
+    addr: target_ptr_t,
+) -> Result<Vec<u8>, GuestReadFail> {
+    read_until_nul(cpu, addr, 1, MAX_GUEST_STRING_LEN)
+}
+
+/// Read a NUL-terminated string from guest physical memory as raw bytes, without the
+/// NUL.
+///
+/// At most [`MAX_GUEST_STRING_LEN`] bytes are read.
+pub fn read_guest_bytes_until_nul_phys(addr: target_ptr_t) -> Result<Vec<u8>, GuestReadFail> {
+    read_until_nul_with(addr, 1, MAX_GUEST_STRING_LEN, |addr, buf| {
+        physical_memory_read_into(addr, buf).is_ok()
+    })
+}
+
+/// Read a NUL-terminated string in the given encoding from guest memory, replacing
+/// invalid sequences with U+FFFD.
+///
+/// At most [`MAX_GUEST_STRING_LEN`] bytes are read.
+///
+/// ## Example
+///
+/// ```
+/// use panda::mem::{read_guest_string_with, Encoding};
+/// use panda::prelude::*;
+///
+/// # let cpu: &mut CPUState = todo!();
+/// # let buffer = 0;
+/// let path = read_guest_string_with(cpu, buffer, Encoding::Utf16Le).unwrap();
+/// ```
+pub fn read_guest_string_with(
+    cpu: &mut CPUState,
+    addr: target_ptr_t,
+    encoding: Encoding,
+) -> Result<String, GuestReadFail> {
+    let encoding = match encoding {
+        Encoding::Detect => {
+            let start = read_until_nul(cpu, addr, 2, DETECT_LEN)?;
+
+            detect_encoding(&start)
+        }
+        encoding => encoding,
+    };
+
+    let bytes = read_until_nul(cpu, addr, encoding.unit_size(), MAX_GUEST_STRING_LEN)?;
+
+    Ok(encoding.decode(&bytes))
+}
+
+/// Read a string of a known length in bytes (such as a Windows `UNICODE_STRING`, or
+/// a buffer passed to a syscall) in the given encoding from guest memory.
+pub fn read_guest_string_len(
+    cpu: &mut CPUState,
+    addr: target_ptr_t,
+    len: usize,
+    encoding: Encoding,
+) -> Result<String, GuestReadFail> {
+    let mut bytes = vec![0u8; len];
+    virtual_memory_read_into(cpu, addr, &mut bytes).map_err(|_| GuestReadFail)?;
+
+    Ok(encoding.decode(&bytes))
+}
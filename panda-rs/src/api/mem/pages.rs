@@ -0,0 +1,142 @@
+use crate::prelude::*;
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+use {
+    super::physical_memory_read_into,
+    crate::{cpu_arch_state, CPUArchPtr},
+};
+
+#[cfg(not(any(feature = "i386", feature = "x86_64")))]
+use super::virt_to_phys;
+
+/// Get the number of bits in a guest page offset, as used by QEMU's TLB and memory map.
+///
+/// On ARM and AArch64 this depends on the CPU being emulated, as ARMv5 and earlier
+/// cores support 1 KiB pages.
+pub fn page_bits() -> u32 {
+    #[cfg(any(feature = "arm", feature = "aarch64"))]
+    let bits = unsafe { crate::sys::target_page_bits as u32 };
+
+    #[cfg(not(any(feature = "arm", feature = "aarch64")))]
+    let bits = 12;
+
+    bits
+}
+
+/// Get the size of the smallest guest page, which memory mappings and regions must be
+/// aligned to
+pub fn page_size() -> target_ulong {
+    1 << page_bits()
+}
+
+/// Round an address or size up to the next multiple of the page size
+pub fn page_align_up(value: target_ulong) -> target_ulong {
+    let mask = page_size() - 1;
+
+    value.wrapping_add(mask) & !mask
+}
+
+/// Round an address or size down to a multiple of the page size
+pub fn page_align_down(value: target_ulong) -> target_ulong {
+    value & !(page_size() - 1)
+}
+
+/// Get the size of the page the given virtual address is mapped by, accounting for
+/// huge pages, or `None` if the address isn't mapped.
+///
+/// Huge pages are only detected on x86, by walking the guest's page tables. On other
+/// architectures, mapped addresses are reported as being in a page of [`page_size`].
+pub fn mapping_page_size(cpu: &mut CPUState, addr: target_ptr_t) -> Option<target_ulong> {
+    #[cfg(any(feature = "i386", feature = "x86_64"))]
+    {
+        x86_mapping_page_size(cpu, addr)
+    }
+
+    #[cfg(not(any(feature = "i386", feature = "x86_64")))]
+    {
+        virt_to_phys(cpu, addr).map(|_| page_size())
+    }
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+fn x86_mapping_page_size(cpu: &mut CPUState, addr: target_ptr_t) -> Option<target_ulong> {
+    const PRESENT: u64 = 1 << 0;
+    const PAGE_SIZE_BIT: u64 = 1 << 7;
+    const EFER_LMA: u64 = 1 << 10;
+    const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+    let env = cpu_arch_state!(cpu);
+    let (cr0, cr3, cr4, efer) = unsafe {
+        let env = &*env;
+
+        (
+            env.cr[0] as u64,
+            env.cr[3] as u64,
+            env.cr[4] as u64,
+            env.efer,
+        )
+    };
+
+    if cr0 & panda_sys::CR0_PG_MASK as u64 == 0 {
+        return Some(page_size());
+    }
+
+    let addr = addr as u64;
+    let read_entry = |table: u64, index: u64, size: usize| {
+        let mut bytes = [0u8; 8];
+        physical_memory_read_into(
+            (table + index * size as u64) as target_ulong,
+            &mut bytes[..size],
+        )
+        .ok()?;
+
+        Some(u64::from_le_bytes(bytes))
+    };
+
+    // (shift of the first level, entry size, index bits per level)
+    let (mut table, mut shift, entry_size, index_bits) = if efer & EFER_LMA != 0 {
+        let top = if cr4 & panda_sys::CR4_LA57_MASK as u64 != 0 {
+            48
+        } else {
+            39
+        };
+
+        (cr3 & ADDR_MASK, top, 8, 9)
+    } else if cr4 & panda_sys::CR4_PAE_MASK as u64 != 0 {
+        (cr3 & !0x1f, 30, 8, 9)
+    } else {
+        (cr3 & 0xffff_f000, 22, 4, 10)
+    };
+
+    loop {
+        let index = (addr >> shift) & ((1 << index_bits) - 1);
+        let entry = read_entry(table, index, entry_size)?;
+
+        if entry & PRESENT == 0 {
+            return None;
+        }
+
+        if shift == 12 {
+            return Some(page_size());
+        }
+
+        // PS is reserved in PAE PDPT entries and only honored with PSE in 32-bit paging
+        let large_page_allowed = match (entry_size, shift) {
+            (4, _) => cr4 & panda_sys::CR4_PSE_MASK as u64 != 0,
+            (_, 30) => efer & EFER_LMA != 0,
+            (_, 21) => true,
+            _ => false,
+        };
+
+        if large_page_allowed && entry & PAGE_SIZE_BIT != 0 {
+            return Some((1 as target_ulong) << shift);
+        }
+
+        table = if entry_size == 4 {
+            entry & 0xffff_f000
+        } else {
+            entry & ADDR_MASK
+        };
+        shift -= index_bits;
+    }
+}
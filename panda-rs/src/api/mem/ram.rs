@@ -0,0 +1,108 @@
+use panda_sys::{get_system_memory, MemoryRegion};
+
+use std::ops::Range;
+
+/// Get the ranges of guest physical addresses which are backed by RAM, in order.
+///
+/// RAM doesn't necessarily start at 0 or form a single range: ARM boards map it at a
+/// board-specific base, and x86 machines split it around the PCI hole, with the rest
+/// mapped above 4 GiB. This walks the machine's memory map to find where it actually
+/// is. Device memory overlapping RAM with a higher priority (such as VGA memory on x86)
+/// isn't excluded.
+pub fn ram_ranges() -> Vec<Range<u64>> {
+    let mut ranges = Vec::new();
+
+    unsafe {
+        let root = get_system_memory();
+        if !root.is_null() {
+            walk_region(root, 0, 0..u64::MAX, &mut ranges);
+        }
+    }
+
+    merge_ranges(ranges)
+}
+
+/// Add the RAM within `region` to `ranges`, where `origin` is the physical address
+/// offset 0 of the region is mapped at, and only addresses within `clip` are visible
+unsafe fn walk_region(
+    region: *const MemoryRegion,
+    origin: u64,
+    clip: Range<u64>,
+    ranges: &mut Vec<Range<u64>>,
+) {
+    let region = unsafe { &*region };
+    if !region.enabled {
+        return;
+    }
+
+    let size = region.size.clamp(0, u64::MAX as i128) as u64;
+    let visible = intersect(&clip, &(origin..origin.saturating_add(size)));
+    if visible.start >= visible.end {
+        return;
+    }
+
+    if !region.alias.is_null() {
+        let alias_origin = origin.wrapping_sub(region.alias_offset);
+        unsafe { walk_region(region.alias, alias_origin, visible, ranges) };
+        return;
+    }
+
+    if region.ram && !region.ram_device {
+        ranges.push(visible);
+        return;
+    }
+
+    let mut subregion = region.subregions.tqh_first;
+    while !subregion.is_null() {
+        let sub = unsafe { &*subregion };
+        let sub_origin = origin.wrapping_add(sub.addr);
+        unsafe { walk_region(subregion, sub_origin, visible.clone(), ranges) };
+
+        subregion = sub.subregions_link.tqe_next;
+    }
+}
+
+fn intersect(a: &Range<u64>, b: &Range<u64>) -> Range<u64> {
+    a.start.max(b.start)..a.end.min(b.end)
+}
+
+/// Sort ranges and merge those which overlap or touch
+fn merge_ranges(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_ranges() {
+        let ranges = vec![
+            0x1_0000_0000..0x1_4000_0000,
+            0..0xa_0000,
+            0x10_0000..0xc000_0000,
+            0x8_0000..0x10_0000,
+        ];
+
+        assert_eq!(
+            merge_ranges(ranges),
+            [0..0xc000_0000, 0x1_0000_0000..0x1_4000_0000]
+        );
+    }
+
+    #[test]
+    fn test_intersect() {
+        assert_eq!(intersect(&(0..0x1000), &(0x800..0x2000)), 0x800..0x1000);
+        assert!(intersect(&(0..0x1000), &(0x2000..0x3000)).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::plugins::cosi;
+use crate::plugins::osi::{self, Mapping};
+use crate::prelude::*;
+use crate::sys::panda_get_plugin_by_name;
+
+use std::fmt;
+
+use super::read_guest_type;
+
+/// The `vm_flags` bits of a Linux `vm_area_struct` giving its permissions
+const VM_READ: target_ulong = 0x1;
+const VM_WRITE: target_ulong = 0x2;
+const VM_EXEC: target_ulong = 0x4;
+
+/// The permissions a region of guest memory is mapped with
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Perms {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+}
+
+impl fmt::Display for Perms {
+    /// Formats the permissions the way `/proc/<pid>/maps` does, such as `r-x`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+
+        write!(
+            f,
+            "{}{}{}",
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.exec, 'x')
+        )
+    }
+}
+
+/// A region of the current process's address space, as reported by OSI
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MemoryRegion {
+    pub base: target_ptr_t,
+    pub size: target_ptr_t,
+
+    /// The permissions of the region. Only available on Linux guests when cosi is
+    /// loaded with a volatility profile, as OSI doesn't report them.
+    pub perms: Option<Perms>,
+
+    /// The path of the file backing the region, if any
+    pub file: Option<String>,
+    pub name: Option<String>,
+}
+
+impl MemoryRegion {
+    /// The address just past the end of the region
+    pub fn end(&self) -> target_ptr_t {
+        self.base + self.size
+    }
+
+    /// Check whether `addr` lies within the region
+    pub fn contains(&self, addr: target_ptr_t) -> bool {
+        (self.base..self.end()).contains(&addr)
+    }
+
+    /// Whether the region is backed by a file rather than being anonymous memory
+    pub fn is_file_backed(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}-{:#x}", self.base, self.end())?;
+
+        match self.perms {
+            Some(perms) => write!(f, " {}", perms)?,
+            None => f.write_str(" ???")?,
+        }
+
+        if let Some(file) = self.file.as_ref().or(self.name.as_ref()) {
+            write!(f, " {}", file)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the offset of `vm_flags` in `vm_area_struct`, if cosi is loaded and its
+/// profile includes it. cosi is never loaded here, as it can't be loaded without a
+/// profile.
+fn vm_flags_offset() -> Option<target_ptr_t> {
+    if unsafe { panda_get_plugin_by_name(b"cosi\0".as_ptr() as _) }.is_null() {
+        return None;
+    }
+
+    cosi::type_from_name("vm_area_struct")?
+        .fields()
+        .find(|(field, _)| field == "vm_flags")
+        .map(|(_, offset)| offset)
+}
+
+fn region_from_mapping(
+    cpu: &mut CPUState,
+    mapping: Mapping,
+    vm_flags: Option<target_ptr_t>,
+) -> MemoryRegion {
+    // on Linux, the descriptor of each mapping is its vm_area_struct
+    let perms = vm_flags
+        .filter(|_| mapping.modd != 0)
+        .and_then(|offset| read_guest_type::<target_ulong>(cpu, mapping.modd + offset).ok())
+        .map(|flags| Perms {
+            read: flags & VM_READ != 0,
+            write: flags & VM_WRITE != 0,
+            exec: flags & VM_EXEC != 0,
+        });
+
+    MemoryRegion {
+        base: mapping.base,
+        size: mapping.size,
+        perms,
+        file: mapping.file,
+        name: mapping.name,
+    }
+}
+
+/// Get the memory regions of the current process, ordered by address
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::mem;
+/// use panda::prelude::*;
+///
+/// # let cpu: &mut CPUState = todo!();
+/// for region in mem::mappings(cpu) {
+///     println!("{}", region);
+/// }
+/// ```
+pub fn mappings(cpu: &mut CPUState) -> impl Iterator<Item = MemoryRegion> {
+    let mut mappings = osi::current_process(cpu)
+        .map(|process| process.mappings(cpu))
+        .unwrap_or_default();
+    mappings.sort_by_key(|mapping| mapping.base);
+
+    let vm_flags = vm_flags_offset();
+    let regions: Vec<_> = mappings
+        .into_iter()
+        .map(|mapping| region_from_mapping(cpu, mapping, vm_flags))
+        .collect();
+
+    regions.into_iter()
+}
+
+/// Find the memory region of the current process containing `addr`, if any
+pub fn find_mapping_containing(cpu: &mut CPUState, addr: target_ptr_t) -> Option<MemoryRegion> {
+    let mapping = osi::current_process(cpu)?.mapping_containing(cpu, addr)?;
+
+    Some(region_from_mapping(cpu, mapping, vm_flags_offset()))
+}
+
+/// Check whether `addr` is within a memory region of the current process. The page
+/// containing it may still not be resident, see [`virt_to_phys`](super::virt_to_phys)
+/// for checking whether it can currently be read.
+pub fn is_mapped(cpu: &mut CPUState, addr: target_ptr_t) -> bool {
+    osi::current_process(cpu)
+        .and_then(|process| process.mapping_containing(cpu, addr))
+        .is_some()
+}
@@ -0,0 +1,240 @@
+//! Memory callbacks which are only enabled while something needs them
+//!
+//! PANDA's memory callbacks (`virt_mem_*` and `phys_mem_*`) only run while memory
+//! callbacks are enabled globally, which slows down every memory access the guest
+//! makes. Rather than enabling them for the whole run, analyses can register a
+//! [`MemcbDemand`] for as long as they need them: memory callbacks are turned on when
+//! the first demand is made and turned back off once the last one is dropped, restoring
+//! full speed.
+//!
+//! Demands can cover all of memory ([`demand_all`]) or only a range of virtual
+//! addresses ([`demand_range`]), such as for a watchpoint. Accesses are trapped through
+//! QEMU's TLB, so when only ranges are demanded, only the TLB entries of the pages
+//! they cover are invalidated when memory callbacks are enabled, and accesses to other
+//! pages keep using the TLB entries they already had. Invalidation is deferred until
+//! the next basic block, as it requires a CPU.
+//!
+//! Memory callbacks which were already enabled by someone else (such as with
+//! [`runtime::enable_memcb`]) are left enabled.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::{memcb, Callback};
+//!
+//! let watched = 0x601040..0x601048;
+//! let demand = memcb::demand_range(watched.clone());
+//!
+//! Callback::new().virt_mem_after_write(move |_, pc, addr, _, _| {
+//!     if watched.contains(&addr) {
+//!         println!("{:#x} wrote {:#x}", pc, addr);
+//!     }
+//! });
+//!
+//! // ...
+//!
+//! // turns memory callbacks back off if nothing else needs them
+//! drop(demand);
+//! ```
+use crate::mem::{page_align_down, page_size};
+use crate::prelude::*;
+use crate::runtime::{self, RuntimeGuard};
+use crate::Callback;
+
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Demands covering more pages than this invalidate the whole TLB instead
+const MAX_PAGE_FLUSHES: usize = 64;
+
+/// TLB entries waiting to be invalidated
+enum Flush {
+    All,
+    Pages(BTreeSet<target_ptr_t>),
+}
+
+impl Flush {
+    fn merge(self, other: Flush) -> Flush {
+        match (self, other) {
+            (Flush::Pages(mut pages), Flush::Pages(more)) => {
+                pages.extend(more);
+                if pages.len() > MAX_PAGE_FLUSHES {
+                    Flush::All
+                } else {
+                    Flush::Pages(pages)
+                }
+            }
+            _ => Flush::All,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Engine {
+    demands: HashMap<u64, Option<Range<target_ptr_t>>>,
+
+    /// Holds memory callbacks on while there are demands
+    memcb: Option<RuntimeGuard>,
+
+    /// Whether memory callbacks were off before the first demand, so accesses need to
+    /// be trapped by flushing the TLB
+    enabled_memcb: bool,
+    pending_flush: Option<Flush>,
+}
+
+impl Engine {
+    fn request_flush(&mut self, flush: Flush) {
+        self.pending_flush = Some(match self.pending_flush.take() {
+            Some(pending) => pending.merge(flush),
+            None => flush,
+        });
+
+        FLUSH_CALLBACK.enable();
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref ENGINE: Mutex<Engine> = Mutex::new(Engine::default());
+    static ref FLUSH_CALLBACK: Callback = install_flush_callback();
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn install_flush_callback() -> Callback {
+    let flush = Callback::new();
+
+    flush.before_block_exec(move |cpu, _| {
+        let pending = ENGINE.lock().unwrap().pending_flush.take();
+
+        unsafe {
+            match pending {
+                Some(Flush::All) => panda_sys::tlb_flush_all_cpus(cpu),
+                Some(Flush::Pages(pages)) => {
+                    for page in pages {
+                        panda_sys::tlb_flush_page_all_cpus(cpu, page);
+                    }
+                }
+                None => (),
+            }
+        }
+
+        flush.disable();
+    });
+
+    flush
+}
+
+/// A demand for memory callbacks to be enabled, released when dropped. Created by
+/// [`demand_all`] or [`demand_range`].
+#[must_use = "memory callbacks are released as soon as the demand is dropped"]
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct MemcbDemand {
+    id: u64,
+}
+
+impl MemcbDemand {
+    /// Release the demand now rather than when it is dropped
+    pub fn release(self) {
+        drop(self)
+    }
+
+    /// Consume the demand without releasing it, keeping memory callbacks enabled for
+    /// the rest of the run
+    pub fn forget(self) {
+        std::mem::forget(self)
+    }
+}
+
+impl Drop for MemcbDemand {
+    fn drop(&mut self) {
+        let mut engine = ENGINE.lock().unwrap();
+        engine.demands.remove(&self.id);
+
+        if !engine.demands.is_empty() {
+            return;
+        }
+
+        drop(engine.memcb.take());
+
+        // drop the entries which trap accesses to get back to full speed
+        if engine.enabled_memcb && !runtime::is_memcb_enabled() {
+            engine.request_flush(Flush::All);
+        }
+    }
+}
+
+fn add_demand(range: Option<Range<target_ptr_t>>) -> MemcbDemand {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let mut engine = ENGINE.lock().unwrap();
+
+    let flush = match &range {
+        Some(range) => pages(range).map(Flush::Pages).unwrap_or(Flush::All),
+        None => Flush::All,
+    };
+
+    engine.demands.insert(id, range);
+
+    if engine.memcb.is_none() {
+        engine.enabled_memcb = !runtime::is_memcb_enabled();
+        engine.memcb = Some(runtime::enable_memcb());
+    }
+
+    if engine.enabled_memcb {
+        engine.request_flush(flush);
+    }
+
+    MemcbDemand { id }
+}
+
+/// The pages overlapping a range, or `None` if there are too many to flush one by one
+fn pages(range: &Range<target_ptr_t>) -> Option<BTreeSet<target_ptr_t>> {
+    let size = page_size();
+    let mut pages = BTreeSet::new();
+    let mut page = page_align_down(range.start);
+
+    while page < range.end {
+        if pages.len() == MAX_PAGE_FLUSHES {
+            return None;
+        }
+
+        pages.insert(page);
+        page = page.checked_add(size)?;
+    }
+
+    Some(pages)
+}
+
+/// Enable memory callbacks for all of memory until the returned demand is dropped
+pub fn demand_all() -> MemcbDemand {
+    add_demand(None)
+}
+
+/// Enable memory callbacks for accesses to the given range of virtual addresses until
+/// the returned demand is dropped. Accesses outside of the range may still run memory
+/// callbacks, so callbacks should check the address they are given.
+pub fn demand_range(range: Range<target_ptr_t>) -> MemcbDemand {
+    add_demand(Some(range))
+}
+
+/// Check whether any demand covers the given access
+pub fn is_demanded(addr: target_ptr_t, size: usize) -> bool {
+    let end = addr.saturating_add(size as target_ptr_t);
+
+    ENGINE
+        .lock()
+        .unwrap()
+        .demands
+        .values()
+        .any(|range| match range {
+            Some(range) => addr < range.end && end > range.start,
+            None => true,
+        })
+}
+
+/// Get the number of demands currently held
+pub fn demand_count() -> usize {
+    ENGINE.lock().unwrap().demands.len()
+}
@@ -7,6 +7,82 @@ pub mod regs;
 /// Functions for record and replay
 pub mod rr;
 
+pub mod block_count;
+pub mod breakpoint;
+pub mod checkpoint;
+pub mod control;
+pub mod debug;
+
+#[cfg_attr(
+    doc_cfg,
+    doc(cfg(any(
+        feature = "mips",
+        feature = "mipsel",
+        feature = "mips64",
+        feature = "mips64el"
+    )))
+)]
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+pub mod delay_slot;
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "disas")))]
+#[cfg(feature = "disas")]
+pub mod disas;
+
+pub mod ext;
+pub mod idle;
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "disas")))]
+#[cfg(feature = "disas")]
+pub mod insn_callbacks;
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "disas")))]
+#[cfg(feature = "disas")]
+pub mod instrument;
+pub mod memcb;
+pub mod net;
+pub mod page_table;
+pub mod progress;
+pub mod replay;
+pub mod runtime;
+pub mod scan;
+pub mod schedule;
+pub mod serial;
+
+#[cfg_attr(
+    doc_cfg,
+    doc(cfg(any(
+        feature = "i386",
+        feature = "x86_64",
+        feature = "arm",
+        feature = "aarch64"
+    )))
+)]
+#[cfg(any(
+    feature = "i386",
+    feature = "x86_64",
+    feature = "arm",
+    feature = "aarch64"
+))]
+pub mod tables;
+pub mod tb_insns;
+pub mod tb_invalidation;
+pub mod time;
+
+#[cfg_attr(doc_cfg, doc(cfg(any(feature = "arm", feature = "aarch64"))))]
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+pub mod trustzone;
+
+/// Helpers for working with x86 segmented (`segment:offset`) addresses
+#[cfg_attr(doc_cfg, doc(cfg(any(feature = "i386", feature = "x86_64"))))]
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub mod segment;
+
 /// Utilities for working with the PANDA OS API
 ///
 /// For OS introspection, see [the `osi` plugin](crate::plugins::osi).
@@ -0,0 +1,204 @@
+//! Guest network configuration
+//!
+//! Networking for a libpanda instance is configured through the [`Panda`] builder:
+//! user-mode networking with port forwarding ([`Panda::forward_port`]), a host tap
+//! device ([`Panda::tap`]) or no networking at all ([`Panda::no_network`]), along with
+//! packet capture ([`Panda::capture_network`]). Once the instance is running, the
+//! active configuration can be queried with [`config`], for example to find the host
+//! port to SSH into the guest on.
+//!
+//! The configuration is only known when PANDA was started through [`Panda::run`]. When
+//! running as a plugin, networking is configured by the PANDA command line and
+//! [`config`] returns `None`.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::net;
+//!
+//! Panda::run_after_init(|| {
+//!     let port = net::forwarded_port(22).unwrap();
+//!     println!("Guest SSH available at localhost:{}", port);
+//! });
+//!
+//! Panda::new()
+//!     .generic("x86_64")
+//!     .forward_port(2222, 22)
+//!     .run();
+//! ```
+//!
+//! [`Panda`]: crate::Panda
+//! [`Panda::forward_port`]: crate::Panda::forward_port
+//! [`Panda::tap`]: crate::Panda::tap
+//! [`Panda::no_network`]: crate::Panda::no_network
+//! [`Panda::capture_network`]: crate::Panda::capture_network
+//! [`Panda::run`]: crate::Panda::run
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// The transport protocol of a forwarded port
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp"),
+            Self::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// A rule forwarding connections to a host port to a port in the guest
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PortForward {
+    pub protocol: Protocol,
+
+    /// The host address to listen on, or all addresses if `None`
+    pub host_addr: Option<Ipv4Addr>,
+    pub host_port: u16,
+    pub guest_port: u16,
+}
+
+impl fmt::Display for PortForward {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.protocol)?;
+
+        if let Some(host_addr) = self.host_addr {
+            write!(f, "{}", host_addr)?;
+        }
+
+        write!(f, ":{}-:{}", self.host_port, self.guest_port)
+    }
+}
+
+/// The host side of the guest's network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Netdev {
+    /// The guest has no network card
+    Disabled,
+
+    /// User-mode (SLIRP) networking, with the given port forwarding rules
+    User { forwards: Vec<PortForward> },
+
+    /// The host tap device with the given name, which is created if it doesn't exist.
+    /// The device is not configured by any script, so it must be brought up on the host.
+    Tap { ifname: String },
+}
+
+/// The network configuration of a PANDA instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetConfig {
+    pub netdev: Netdev,
+
+    /// The model of the guest network card, or the machine's default if `None`
+    pub nic_model: Option<String>,
+
+    /// A pcap file all guest traffic is written to
+    pub capture: Option<PathBuf>,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            netdev: Netdev::User {
+                forwards: Vec::new(),
+            },
+            nic_model: None,
+            capture: None,
+        }
+    }
+}
+
+impl NetConfig {
+    pub(crate) fn forward(&mut self, forward: PortForward) {
+        match &mut self.netdev {
+            Netdev::User { forwards } => forwards.push(forward),
+            netdev => {
+                *netdev = Netdev::User {
+                    forwards: vec![forward],
+                }
+            }
+        }
+    }
+
+    /// Get the arguments to pass to QEMU for this configuration
+    #[cfg(feature = "libpanda")]
+    pub(crate) fn to_args(&self) -> Vec<String> {
+        if self.netdev == Netdev::Disabled {
+            return vec!["-net".into(), "none".into()];
+        }
+
+        let mut nic = String::from("nic");
+        if let Some(model) = &self.nic_model {
+            nic += &format!(",model={}", model);
+        }
+
+        let mut netdev = String::new();
+        match &self.netdev {
+            Netdev::User { forwards } => {
+                netdev += "user";
+                for forward in forwards {
+                    netdev += &format!(",hostfwd={}", forward);
+                }
+            }
+            Netdev::Tap { ifname } => {
+                netdev += &format!("tap,ifname={},script=no,downscript=no", ifname);
+            }
+            Netdev::Disabled => unreachable!(),
+        }
+
+        let mut args = vec!["-net".into(), nic, "-net".into(), netdev];
+
+        if let Some(capture) = &self.capture {
+            args.push("-net".into());
+            args.push(format!("dump,file={}", capture.display()));
+        }
+
+        args
+    }
+
+    /// Get the host port forwarded to the given guest TCP port, if any
+    pub fn forwarded_port(&self, guest_port: u16) -> Option<u16> {
+        match &self.netdev {
+            Netdev::User { forwards } => forwards
+                .iter()
+                .find(|forward| {
+                    forward.protocol == Protocol::Tcp && forward.guest_port == guest_port
+                })
+                .map(|forward| forward.host_port),
+            _ => None,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref ACTIVE_CONFIG: Mutex<Option<NetConfig>> = Mutex::new(None);
+}
+
+#[cfg(feature = "libpanda")]
+pub(crate) fn set_active_config(config: NetConfig) {
+    *ACTIVE_CONFIG.lock().unwrap() = Some(config);
+}
+
+/// Get the network configuration of the running instance. Returns `None` if PANDA
+/// wasn't started with [`Panda::run`](crate::Panda::run).
+pub fn config() -> Option<NetConfig> {
+    ACTIVE_CONFIG.lock().unwrap().clone()
+}
+
+/// Get the host port forwarded to the given guest TCP port in the running instance, if
+/// any
+pub fn forwarded_port(guest_port: u16) -> Option<u16> {
+    ACTIVE_CONFIG
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|config| config.forwarded_port(guest_port))
+}
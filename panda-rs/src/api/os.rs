@@ -1,3 +1,5 @@
+pub mod users;
+
 use crate::sys::{
     panda_os_bits, panda_os_family, panda_os_familyno, panda_os_name, panda_os_variant,
 };
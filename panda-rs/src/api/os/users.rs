@@ -0,0 +1,351 @@
+//! Credentials of Linux guest processes and the names of their users and groups
+//!
+//! [`credentials`] reads the real, effective, saved and filesystem uid/gid of a task
+//! from its `struct cred`, using the volatility profile loaded by cosi. The uids and
+//! gids can then be turned into names using the guest's `/etc/passwd` and
+//! `/etc/group`, which are loaded once with [`load_from_image`] (reading them out of
+//! the guest's disk image) or [`set_user_db`] (for contents fetched some other way,
+//! such as by running `cat /etc/passwd` with
+//! [`Panda::run_serial_cmd`](crate::Panda::run_serial_cmd)). Until one of these is
+//! called, ids are shown without names.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::os::users::{self, Credentials};
+//! use panda::plugins::osi;
+//! use panda::prelude::*;
+//!
+//! use std::collections::HashMap;
+//! use std::sync::Mutex;
+//!
+//! lazy_static::lazy_static! {
+//!     static ref LAST_CREDS: Mutex<HashMap<target_pid_t, Credentials>> =
+//!         Mutex::new(HashMap::new());
+//! }
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     let passwd = std::fs::read_to_string("guest/etc/passwd").unwrap();
+//!     let group = std::fs::read_to_string("guest/etc/group").unwrap();
+//!     users::set_user_db(users::UserDb::parse(&passwd, &group));
+//! }
+//!
+//! #[panda::asid_changed]
+//! fn asid_changed(cpu: &mut CPUState, _old: target_ulong, _new: target_ulong) -> bool {
+//!     if let Some(process) = osi::current_process(cpu) {
+//!         if let Ok(creds) = users::credentials(cpu, process.taskd) {
+//!             let mut last_creds = LAST_CREDS.lock().unwrap();
+//!             if let Some(previous) = last_creds.insert(process.pid, creds) {
+//!                 if creds.elevated_from(&previous) {
+//!                     // "bash" (pid 1234) escalated from user (uid 1000) to root (uid 0)
+//!                     println!(
+//!                         "{:?} (pid {}) escalated from {} to {}",
+//!                         process.name,
+//!                         process.pid,
+//!                         users::format_uid(previous.effective.uid),
+//!                         users::format_uid(creds.effective.uid),
+//!                     );
+//!                 }
+//!             }
+//!         }
+//!     }
+//!
+//!     false
+//! }
+//! ```
+use crate::mem::read_guest_type;
+use crate::plugins::cosi::{self, VolatilityStruct};
+use crate::prelude::*;
+use crate::GuestReadFail;
+
+use lazy_static::lazy_static;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+/// An error encountered while reading the credentials of a task
+#[derive(thiserror::Error, Debug)]
+pub enum CredentialsError {
+    #[error("{0} not found, is cosi loaded with a volatility profile?")]
+    MissingType(&'static str),
+
+    #[error("{0} not found in the volatility profile")]
+    MissingField(&'static str),
+
+    #[error("failed to read credentials from guest memory")]
+    ReadFailed,
+}
+
+impl From<GuestReadFail> for CredentialsError {
+    fn from(_: GuestReadFail) -> Self {
+        Self::ReadFailed
+    }
+}
+
+/// A uid along with its matching gid
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Ids {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Ids {
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+}
+
+impl fmt::Display for Ids {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}", format_uid(self.uid), format_gid(self.gid))
+    }
+}
+
+/// The credentials of a task, as stored in its `struct cred`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Credentials {
+    /// The user that started the task
+    pub real: Ids,
+
+    /// The ids used for permission checks
+    pub effective: Ids,
+
+    /// The ids saved by the last `execve` of a setuid/setgid binary, which the task can
+    /// switch back to
+    pub saved: Ids,
+
+    /// The ids used for filesystem access checks
+    pub fs: Ids,
+}
+
+impl Credentials {
+    /// Whether the task is running with root privileges
+    pub fn is_root(&self) -> bool {
+        self.effective.is_root()
+    }
+
+    /// Whether the task has gained root privileges since `previous` was read, such as
+    /// through a setuid binary or a kernel exploit
+    pub fn elevated_from(&self, previous: &Credentials) -> bool {
+        !previous.is_root() && self.is_root()
+    }
+
+    /// Whether any of the ids differ from the real ids, such as while running a setuid
+    /// binary
+    pub fn is_mixed(&self) -> bool {
+        [self.effective, self.saved, self.fs]
+            .iter()
+            .any(|ids| *ids != self.real)
+    }
+}
+
+impl fmt::Display for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_mixed() {
+            write!(
+                f,
+                "real: {}; effective: {}; saved: {}; fs: {}",
+                self.real, self.effective, self.saved, self.fs
+            )
+        } else {
+            self.real.fmt(f)
+        }
+    }
+}
+
+fn field_offset(vol_struct: &VolatilityStruct, name: &str) -> Option<target_ptr_t> {
+    vol_struct
+        .fields()
+        .find(|(field, _)| field == name)
+        .map(|(_, offset)| offset)
+}
+
+struct CredLayout {
+    real_cred: target_ptr_t,
+    ids: [target_ptr_t; 8],
+}
+
+/// The fields of `struct cred` in the order they are stored in [`Credentials`]
+const CRED_FIELDS: [&str; 8] = [
+    "uid", "gid", "euid", "egid", "suid", "sgid", "fsuid", "fsgid",
+];
+
+impl CredLayout {
+    fn load() -> Result<Self, CredentialsError> {
+        let task_struct = cosi::type_from_name("task_struct")
+            .ok_or(CredentialsError::MissingType("task_struct"))?;
+        let cred = cosi::type_from_name("cred").ok_or(CredentialsError::MissingType("cred"))?;
+
+        let mut ids = [0; 8];
+        for (offset, name) in ids.iter_mut().zip(CRED_FIELDS) {
+            *offset = field_offset(&cred, name).ok_or(CredentialsError::MissingField(name))?;
+        }
+
+        Ok(Self {
+            real_cred: field_offset(&task_struct, "real_cred")
+                .ok_or(CredentialsError::MissingField("real_cred"))?,
+            ids,
+        })
+    }
+}
+
+/// Read the credentials of the task with the given `task_struct`, such as
+/// [`Process::taskd`](crate::plugins::osi::Process::taskd). Requires cosi to be loaded
+/// with a volatility profile for the guest kernel.
+pub fn credentials(
+    cpu: &mut CPUState,
+    task: target_ptr_t,
+) -> Result<Credentials, CredentialsError> {
+    let layout = CredLayout::load()?;
+    let cred: target_ptr_t = read_guest_type(cpu, task + layout.real_cred)?;
+
+    // kuid_t and kgid_t are each a struct wrapping a u32
+    let mut ids = [0u32; 8];
+    for (id, offset) in ids.iter_mut().zip(layout.ids) {
+        *id = read_guest_type(cpu, cred + offset)?;
+    }
+
+    let ids = |i: usize| Ids {
+        uid: ids[i],
+        gid: ids[i + 1],
+    };
+
+    Ok(Credentials {
+        real: ids(0),
+        effective: ids(2),
+        saved: ids(4),
+        fs: ids(6),
+    })
+}
+
+/// The users and groups of the guest, from its `/etc/passwd` and `/etc/group`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserDb {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+/// Parse the name and id from each line of a passwd or group file, which both start
+/// with `name:password:id:`
+fn parse_ids(contents: &str) -> HashMap<u32, String> {
+    let mut ids = HashMap::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split(':');
+        if let (Some(name), Some(id)) = (fields.next(), fields.nth(1)) {
+            if let Ok(id) = id.parse() {
+                // the first entry for an id is the one shown by tools such as `ls`
+                ids.entry(id).or_insert_with(|| name.to_owned());
+            }
+        }
+    }
+
+    ids
+}
+
+impl UserDb {
+    /// Parse the contents of `/etc/passwd` and `/etc/group`. Malformed lines are
+    /// skipped.
+    pub fn parse(passwd: &str, group: &str) -> Self {
+        Self {
+            users: parse_ids(passwd),
+            groups: parse_ids(group),
+        }
+    }
+
+    /// Get the name of the user with the given uid
+    pub fn user_name(&self, uid: u32) -> Option<&str> {
+        self.users.get(&uid).map(String::as_str)
+    }
+
+    /// Get the name of the group with the given gid
+    pub fn group_name(&self, gid: u32) -> Option<&str> {
+        self.groups.get(&gid).map(String::as_str)
+    }
+
+    /// Find the uid of the user with the given name
+    pub fn uid(&self, name: &str) -> Option<u32> {
+        self.users
+            .iter()
+            .find(|(_, user)| *user == name)
+            .map(|(&uid, _)| uid)
+    }
+
+    /// Find the gid of the group with the given name
+    pub fn gid(&self, name: &str) -> Option<u32> {
+        self.groups
+            .iter()
+            .find(|(_, group)| *group == name)
+            .map(|(&gid, _)| gid)
+    }
+}
+
+lazy_static! {
+    static ref USER_DB: RwLock<Option<UserDb>> = RwLock::new(None);
+}
+
+/// Set the users and groups used to name uids and gids
+pub fn set_user_db(db: UserDb) {
+    *USER_DB.write().unwrap() = Some(db);
+}
+
+/// Load the users and groups of the guest from `/etc/passwd` and `/etc/group` in its
+/// disk image. See [`guestfs::open`](crate::guestfs::open) for the supported images.
+#[cfg_attr(doc_cfg, doc(cfg(feature = "guestfs")))]
+#[cfg(feature = "guestfs")]
+pub fn load_from_image(
+    image: impl AsRef<std::path::Path>,
+) -> Result<(), crate::guestfs::GuestFsError> {
+    let mut fs = crate::guestfs::open(image)?;
+    let passwd = fs.read_to_string("/etc/passwd")?;
+    let group = fs.read_to_string("/etc/group").unwrap_or_default();
+
+    set_user_db(UserDb::parse(&passwd, &group));
+
+    Ok(())
+}
+
+/// Get the name of the user with the given uid, if the guest's users have been loaded
+pub fn user_name(uid: u32) -> Option<String> {
+    USER_DB
+        .read()
+        .unwrap()
+        .as_ref()?
+        .user_name(uid)
+        .map(String::from)
+}
+
+/// Get the name of the group with the given gid, if the guest's groups have been loaded
+pub fn group_name(gid: u32) -> Option<String> {
+    USER_DB
+        .read()
+        .unwrap()
+        .as_ref()?
+        .group_name(gid)
+        .map(String::from)
+}
+
+/// Format a uid for display, such as `root (uid 0)`, or just `uid 1000` if its name
+/// isn't known
+pub fn format_uid(uid: u32) -> String {
+    match user_name(uid) {
+        Some(name) => format!("{} (uid {})", name, uid),
+        None => format!("uid {}", uid),
+    }
+}
+
+/// Format a gid for display, such as `wheel (gid 10)`, or just `gid 1000` if its name
+/// isn't known
+pub fn format_gid(gid: u32) -> String {
+    match group_name(gid) {
+        Some(name) => format!("{} (gid {})", name, gid),
+        None => format!("gid {}", gid),
+    }
+}
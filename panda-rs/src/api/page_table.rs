@@ -0,0 +1,151 @@
+//! Notifications for when the guest switches page tables
+//!
+//! PANDA's `asid_changed` callback is only fired by some architectures, and at a point
+//! where the new page table isn't yet in effect. This module instead compares the
+//! address space of each CPU (as returned by [`current_asid`], which is the page table
+//! base on x86 and ARM) before every block, giving a uniform signal on every
+//! architecture. Changes are delivered right before the first block executed in the new
+//! address space, so OSI reports the process being switched to.
+//!
+//! Each change is classified as either a switch to an address space that has been seen
+//! before or the first use of a new one, which happens when a process is created or
+//! calls `execve`. Page tables freed by exiting processes can be reused by the kernel,
+//! so a new address space reusing one will be reported as a context switch.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::page_table::{self, ChangeKind};
+//!
+//! page_table::on_change(|cpu, change| {
+//!     if change.kind == ChangeKind::NewAddressSpace {
+//!         if let Some(process) = change.process(cpu) {
+//!             println!("{} is now using page table {:#x}", process.name, change.new);
+//!         }
+//!     }
+//! });
+//! ```
+use crate::plugins::osi::{self, Process};
+use crate::prelude::*;
+use crate::{current_asid, current_pc, Callback};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Whether the address space being switched to has been seen before
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// A switch to an existing address space, such as when the scheduler switches
+    /// processes
+    ContextSwitch,
+
+    /// The first use of an address space, such as after `fork` or `execve`
+    NewAddressSpace,
+}
+
+/// A change of the page table base of a CPU
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PageTableChange {
+    /// The index of the CPU which switched page tables
+    pub cpu_index: i32,
+
+    /// The address space before the change
+    pub old: target_ulong,
+
+    /// The address space after the change
+    pub new: target_ulong,
+
+    pub kind: ChangeKind,
+
+    /// The program counter of the first block executed in the new address space
+    pub pc: target_ulong,
+}
+
+impl PageTableChange {
+    pub fn is_context_switch(&self) -> bool {
+        self.kind == ChangeKind::ContextSwitch
+    }
+
+    /// Get the process now running, as reported by OSI. Must be called with the CPU
+    /// passed to the callback.
+    pub fn process(&self, cpu: &mut CPUState) -> Option<Process> {
+        osi::current_process(cpu)
+    }
+}
+
+type ChangeCallback = Box<dyn FnMut(&mut CPUState, &PageTableChange) + Send + 'static>;
+
+#[derive(Default)]
+struct Tracker {
+    callbacks: Vec<ChangeCallback>,
+    current: HashMap<i32, target_ulong>,
+    seen: HashSet<target_ulong>,
+}
+
+lazy_static::lazy_static! {
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Tracker::default());
+    static ref CALLBACKS: [Callback; 2] = install_callbacks();
+}
+
+fn install_callbacks() -> [Callback; 2] {
+    let before_block = Callback::new();
+    let after_loadvm = Callback::new();
+
+    before_block.before_block_exec(|cpu, _| {
+        let asid = current_asid(cpu);
+        let mut tracker = TRACKER.lock().unwrap();
+
+        let old = match tracker.current.insert(cpu.cpu_index, asid) {
+            Some(old) if old != asid => old,
+            Some(_) => return,
+
+            // the first block seen on this CPU, there's nothing to compare against
+            None => {
+                tracker.seen.insert(asid);
+                return;
+            }
+        };
+
+        let kind = if tracker.seen.insert(asid) {
+            ChangeKind::NewAddressSpace
+        } else {
+            ChangeKind::ContextSwitch
+        };
+
+        let change = PageTableChange {
+            cpu_index: cpu.cpu_index,
+            old,
+            new: asid,
+            kind,
+            pc: current_pc(cpu),
+        };
+
+        // run without the lock held, so the callbacks can register further callbacks
+        let mut callbacks = std::mem::take(&mut tracker.callbacks);
+        drop(tracker);
+        for callback in &mut callbacks {
+            callback(cpu, &change);
+        }
+
+        let mut tracker = TRACKER.lock().unwrap();
+        callbacks.append(&mut tracker.callbacks);
+        tracker.callbacks = callbacks;
+    });
+
+    // the address spaces of a snapshot are unrelated to those seen before it was loaded
+    after_loadvm.after_loadvm(|_| {
+        let mut tracker = TRACKER.lock().unwrap();
+        tracker.current.clear();
+        tracker.seen.clear();
+    });
+
+    [before_block, after_loadvm]
+}
+
+/// Register a callback to be run whenever a CPU switches page tables. Callbacks
+/// registered from within a callback are first run on the next change.
+pub fn on_change(callback: impl FnMut(&mut CPUState, &PageTableChange) + Send + 'static) {
+    lazy_static::initialize(&CALLBACKS);
+
+    TRACKER.lock().unwrap().callbacks.push(Box::new(callback));
+}
@@ -0,0 +1,285 @@
+//! Progress reporting for long replays
+//!
+//! Replaying a large recording with heavy instrumentation can take hours, so this
+//! module reports how far through the replay PANDA is at regular intervals, along with
+//! the rate instructions are being replayed at and an estimate of the time remaining.
+//! Reports are passed to callbacks registered with [`on_replay_progress`] (or the
+//! [`#[panda::on_replay_progress]`](macro@crate::on_replay_progress) attribute), and
+//! [`print_progress`] enables a built-in reporter which prints a line to stderr for
+//! each, similar to a `--progress` flag.
+//!
+//! How often reports are made is set with [`set_interval`], defaulting to every 1% of
+//! the replay. Progress is checked at the start of each basic block, and only while
+//! replaying. The latest progress can also be polled at any point with
+//! [`ReplayProgress::current`], such as to hand off to a job scheduler.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::progress::{self, Interval, ReplayProgress};
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     progress::set_interval(Interval::Percent(5.0));
+//!     progress::print_progress();
+//! }
+//!
+//! #[panda::on_replay_progress]
+//! fn progress(progress: &ReplayProgress) {
+//!     if let Some(eta) = progress.eta() {
+//!         println!("done in {}s", eta.as_secs());
+//!     }
+//! }
+//! ```
+use crate::rr::{in_replay, replay_length, replay_percentage, rr_get_guest_instr_count};
+use crate::Callback;
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often progress is reported
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interval {
+    /// Every time the replay advances by the given percentage
+    Percent(f64),
+
+    /// Every time the given number of guest instructions have been replayed
+    Instructions(u64),
+
+    /// Every time the given amount of wall-clock time has passed
+    Time(Duration),
+}
+
+impl Default for Interval {
+    fn default() -> Self {
+        Interval::Percent(1.0)
+    }
+}
+
+/// How far through the replay PANDA is
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReplayProgress {
+    /// The number of guest instructions replayed so far
+    pub instr_count: u64,
+
+    /// The number of guest instructions in the recording, if known
+    pub total_instr: Option<u64>,
+
+    /// How much of the replay has been completed, from 0 to 100
+    pub percent: f64,
+
+    /// The wall-clock time since progress tracking started
+    pub elapsed: Duration,
+
+    /// The instruction count progress tracking started at
+    start_instr: u64,
+}
+
+impl ReplayProgress {
+    /// Get the progress of the running replay, or `None` if not replaying. Elapsed time
+    /// is measured from the first time progress is checked, so starts at zero if
+    /// neither this nor [`on_replay_progress`] have been used before.
+    pub fn current() -> Option<Self> {
+        if !in_replay() {
+            return None;
+        }
+
+        let mut tracker = TRACKER.lock().unwrap();
+        let (start, start_instr) = tracker.start();
+
+        Some(measure(start, start_instr))
+    }
+
+    /// The average number of guest instructions replayed per second of wall-clock time
+    pub fn instr_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+
+        if secs > 0.0 {
+            self.instr_count.saturating_sub(self.start_instr) as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Estimate the wall-clock time until the replay completes, based on the rate
+    /// seen so far
+    pub fn eta(&self) -> Option<Duration> {
+        let secs = match self.total_instr {
+            Some(total) => {
+                let rate = self.instr_per_sec();
+                if rate <= 0.0 {
+                    return None;
+                }
+
+                total.saturating_sub(self.instr_count) as f64 / rate
+            }
+            None => {
+                if self.percent <= 0.0 {
+                    return None;
+                }
+
+                self.elapsed.as_secs_f64() * (100.0 - self.percent).max(0.0) / self.percent
+            }
+        };
+
+        Some(Duration::from_secs_f64(secs))
+    }
+}
+
+struct HumanDuration(Duration);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+
+        write!(
+            f,
+            "{}:{:02}:{:02}",
+            secs / 3600,
+            (secs / 60) % 60,
+            secs % 60
+        )
+    }
+}
+
+impl fmt::Display for ReplayProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "replay {:6.2}% ", self.percent)?;
+
+        match self.total_instr {
+            Some(total) => write!(f, "{}/{} instrs", self.instr_count, total)?,
+            None => write!(f, "{} instrs", self.instr_count)?,
+        }
+
+        write!(
+            f,
+            ", {:.2}M instrs/s, elapsed {}",
+            self.instr_per_sec() / 1_000_000.0,
+            HumanDuration(self.elapsed)
+        )?;
+
+        match self.eta() {
+            Some(eta) => write!(f, ", ETA {}", HumanDuration(eta)),
+            None => write!(f, ", ETA unknown"),
+        }
+    }
+}
+
+type ProgressCallback = Box<dyn FnMut(&ReplayProgress) + Send + 'static>;
+
+#[derive(Default)]
+struct Tracker {
+    interval: Interval,
+    callbacks: Vec<ProgressCallback>,
+
+    /// When tracking started, and the instruction count at the time
+    start: Option<(Instant, u64)>,
+
+    /// The progress at the time of the last report
+    last: Option<(Instant, u64, f64)>,
+}
+
+impl Tracker {
+    fn start(&mut self) -> (Instant, u64) {
+        *self
+            .start
+            .get_or_insert_with(|| (Instant::now(), rr_get_guest_instr_count()))
+    }
+
+    fn is_due(&self, progress: &ReplayProgress, now: Instant) -> bool {
+        let (last_time, last_instr, last_percent) = match self.last {
+            Some(last) => last,
+            None => return true,
+        };
+
+        match self.interval {
+            Interval::Percent(step) => progress.percent >= last_percent + step,
+            Interval::Instructions(step) => progress.instr_count >= last_instr + step,
+            Interval::Time(step) => now >= last_time + step,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Tracker::default());
+    static ref CHECK_CALLBACK: Callback = install_check_callback();
+}
+
+static PRINTING: AtomicBool = AtomicBool::new(false);
+
+fn measure(start: Instant, start_instr: u64) -> ReplayProgress {
+    let instr_count = rr_get_guest_instr_count();
+    let total_instr = replay_length();
+    let percent = replay_percentage().unwrap_or_default();
+
+    ReplayProgress {
+        instr_count,
+        total_instr,
+        percent,
+        elapsed: start.elapsed(),
+        start_instr,
+    }
+}
+
+fn install_check_callback() -> Callback {
+    let check = Callback::new();
+
+    check.before_block_exec(|_, _| {
+        if !in_replay() {
+            return;
+        }
+
+        let mut tracker = TRACKER.lock().unwrap();
+        let (start, start_instr) = tracker.start();
+        let progress = measure(start, start_instr);
+        let now = Instant::now();
+
+        let is_due = tracker.last.is_some() && tracker.is_due(&progress, now);
+
+        // start counting intervals from when tracking began rather than reporting 0%
+        if tracker.last.is_none() || is_due {
+            tracker.last = Some((now, progress.instr_count, progress.percent));
+        }
+
+        if !is_due {
+            return;
+        }
+
+        // run the callbacks unlocked so that they can register further callbacks
+        let mut callbacks = std::mem::take(&mut tracker.callbacks);
+        drop(tracker);
+
+        for callback in &mut callbacks {
+            callback(&progress);
+        }
+
+        let mut tracker = TRACKER.lock().unwrap();
+        callbacks.append(&mut tracker.callbacks);
+        tracker.callbacks = callbacks;
+    });
+
+    check
+}
+
+/// Set how often progress is reported, taking effect from the next report
+pub fn set_interval(interval: Interval) {
+    TRACKER.lock().unwrap().interval = interval;
+}
+
+/// Register a callback to be run each time progress is reported while replaying
+pub fn on_replay_progress(callback: impl FnMut(&ReplayProgress) + Send + 'static) {
+    TRACKER.lock().unwrap().callbacks.push(Box::new(callback));
+    lazy_static::initialize(&CHECK_CALLBACK);
+}
+
+/// Print a line to stderr each time progress is reported while replaying, with the
+/// percentage completed, the replay rate and the estimated time remaining. Only
+/// enables the printer once, no matter how many times it's called.
+pub fn print_progress() {
+    if !PRINTING.swap(true, Ordering::SeqCst) {
+        on_replay_progress(|progress| eprintln!("{}", progress));
+    }
+}
@@ -1,9 +1,16 @@
+use crate::audit;
 use crate::prelude::*;
 use crate::{cpu_arch_state, CPUArchPtr};
 
 use strum::IntoEnumIterator;
 use strum_macros::{EnumIter, EnumString, ToString};
 
+pub mod flags;
+
+#[cfg_attr(doc_cfg, doc(cfg(any(feature = "i386", feature = "x86_64"))))]
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub mod x86;
+
 /// Type-safe API to allow APIs to accept only program counters coming from
 /// syscall callbacks. To convert to integer of the width of your target, use the
 /// `.pc()` method.
@@ -309,12 +316,13 @@ pub fn get_reg<T: Into<Reg>>(cpu: &CPUState, reg: T) -> target_ulong {
 }
 
 /// Set the value for a register
-pub fn set_reg<T: Into<Reg>>(cpu: &CPUState, reg: T, val: target_ulong) {
+pub fn set_reg<T: Into<Reg>>(cpu: &mut CPUState, reg: T, val: target_ulong) {
     let cpu_arch = cpu_arch_state!(cpu);
+    let reg = reg.into();
 
     #[cfg(any(feature = "i386", feature = "x86_64", feature = "arm"))]
     unsafe {
-        (*cpu_arch).regs[reg.into() as usize] = val;
+        (*cpu_arch).regs[reg as usize] = val;
     }
 
     #[cfg(any(
@@ -324,23 +332,24 @@ pub fn set_reg<T: Into<Reg>>(cpu: &CPUState, reg: T, val: target_ulong) {
         feature = "mips64el"
     ))]
     unsafe {
-        (*cpu_arch).active_tc.gpr[reg.into() as usize] = val;
+        (*cpu_arch).active_tc.gpr[reg as usize] = val;
     }
 
     #[cfg(any(feature = "ppc"))]
     unsafe {
-        let reg_enum = reg.into();
-        if reg_enum == Reg::LR {
+        if reg == Reg::LR {
             (*cpu_arch).lr = val;
         } else {
-            (*cpu_arch).gpr[reg_enum as usize] = val;
+            (*cpu_arch).gpr[reg as usize] = val;
         }
     }
 
     #[cfg(feature = "aarch64")]
     unsafe {
-        (*cpu_arch).xregs[reg.into() as usize] = val;
+        (*cpu_arch).xregs[reg as usize] = val;
     }
+
+    audit::record(Some(cpu), || audit::Mutation::Register { reg, value: val });
 }
 
 pub fn get_pc(cpu: &CPUState) -> target_ulong {
@@ -412,6 +421,8 @@ pub fn set_pc(cpu: &mut CPUState, pc: target_ulong) {
     unsafe {
         (*cpu_arch).active_tc.PC = pc;
     }
+
+    audit::record(Some(cpu), || audit::Mutation::Pc { value: pc });
 }
 
 // Printing ------------------------------------------------------------------------------------------------------------
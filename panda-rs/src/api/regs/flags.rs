@@ -0,0 +1,423 @@
+//! Reading and modifying the guest's condition flags
+//!
+//! Each architecture stores its condition state differently, and QEMU doesn't always
+//! keep it in the form the guest sees (x86 flags are computed lazily from the last
+//! flag-setting operation, ARM flags are kept in separate fields). This module hides
+//! that behind a [`Flag`] for each condition flag of the architecture, along with
+//! [`carry`], [`zero`], [`negative`] and [`overflow`] functions for the flags shared by
+//! most architectures:
+//!
+//! | Function     | x86    | ARM/AArch64 | PowerPC        |
+//! |:-------------|:-------|:------------|:---------------|
+//! | [`carry`]    | `CF`   | `C`         | `XER[CA]`      |
+//! | [`zero`]     | `ZF`   | `Z`         | `CR0[EQ]`      |
+//! | [`negative`] | `SF`   | `N`         | `CR0[LT]`      |
+//! | [`overflow`] | `OF`   | `V`         | `XER[OV]`      |
+//!
+//! MIPS has no integer condition flags, so only its FPU condition codes are exposed
+//! (as [`Flag::FpCondition`]).
+//!
+//! Flags are only guaranteed to be up to date at block boundaries, such as in
+//! [`before_block_exec`](crate::before_block_exec) or a hook, as translated code may
+//! defer updating them until the end of a block.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::regs::flags;
+//!
+//! #[panda::before_block_exec]
+//! fn force_branch(cpu: &mut CPUState, tb: &mut TranslationBlock) {
+//!     // the block at 0x4010a0 starts with a `jz`, force it to be taken
+//!     if tb.pc == 0x4010a0 {
+//!         flags::set_zero(cpu, true);
+//!     }
+//! }
+//! ```
+use crate::audit;
+use crate::prelude::*;
+use crate::{cpu_arch_state, CPUArchPtr};
+
+/// A condition flag of the guest architecture
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// `CF`
+    Carry,
+    /// `PF`
+    Parity,
+    /// `AF`
+    Adjust,
+    /// `ZF`
+    Zero,
+    /// `SF`
+    Sign,
+    /// `DF`
+    Direction,
+    /// `OF`
+    Overflow,
+}
+
+/// A condition flag of the guest architecture
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// `N`
+    Negative,
+    /// `Z`
+    Zero,
+    /// `C`
+    Carry,
+    /// `V`
+    Overflow,
+}
+
+/// A condition flag of the guest architecture
+#[cfg(feature = "ppc")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// The `LT` bit of a condition register field (0 to 7)
+    Lt(u8),
+    /// The `GT` bit of a condition register field (0 to 7)
+    Gt(u8),
+    /// The `EQ` bit of a condition register field (0 to 7)
+    Eq(u8),
+    /// The `SO` bit of a condition register field (0 to 7)
+    So(u8),
+    /// `XER[CA]`
+    Carry,
+    /// `XER[OV]`
+    Overflow,
+    /// `XER[SO]`
+    SummaryOverflow,
+}
+
+/// A condition flag of the guest architecture
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Flag {
+    /// An FPU condition code (0 to 7), as set by `c.cond.fmt` and tested by `bc1t`
+    FpCondition(u8),
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+mod arch {
+    use super::*;
+    use crate::sys::{cpu_cc_compute_all, CCOp_CC_OP_EFLAGS};
+
+    const CC_C: u32 = 0x0001;
+    const CC_P: u32 = 0x0004;
+    const CC_A: u32 = 0x0010;
+    const CC_Z: u32 = 0x0040;
+    const CC_S: u32 = 0x0080;
+    const DF_MASK: target_ulong = 0x0400;
+    const CC_O: u32 = 0x0800;
+
+    fn mask(flag: Flag) -> u32 {
+        match flag {
+            Flag::Carry => CC_C,
+            Flag::Parity => CC_P,
+            Flag::Adjust => CC_A,
+            Flag::Zero => CC_Z,
+            Flag::Sign => CC_S,
+            Flag::Overflow => CC_O,
+            Flag::Direction => unreachable!(),
+        }
+    }
+
+    /// Compute the arithmetic flags from the lazily-evaluated condition codes
+    fn compute_flags(cpu: &CPUState) -> u32 {
+        let env = cpu_arch_state!(cpu);
+
+        unsafe { cpu_cc_compute_all(env, (*env).cc_op as _) }
+    }
+
+    pub(super) fn get(cpu: &CPUState, flag: Flag) -> bool {
+        match flag {
+            Flag::Direction => unsafe { (*cpu_arch_state!(cpu)).df == -1 },
+            _ => compute_flags(cpu) & mask(flag) != 0,
+        }
+    }
+
+    pub(super) fn set(cpu: &mut CPUState, flag: Flag, value: bool) {
+        if flag == Flag::Direction {
+            unsafe {
+                (*cpu_arch_state!(cpu)).df = if value { -1 } else { 1 };
+            }
+            return;
+        }
+
+        let mut flags = compute_flags(cpu);
+        if value {
+            flags |= mask(flag);
+        } else {
+            flags &= !mask(flag);
+        }
+
+        // store the flags directly rather than as the result of an operation, as
+        // `cpu_load_eflags` does
+        let env = cpu_arch_state!(cpu);
+        unsafe {
+            (*env).cc_src = flags as target_ulong;
+            (*env).cc_op = CCOp_CC_OP_EFLAGS;
+        }
+    }
+
+    pub(super) fn raw(cpu: &CPUState) -> target_ulong {
+        let env = cpu_arch_state!(cpu);
+        let direction = if get(cpu, Flag::Direction) {
+            DF_MASK
+        } else {
+            0
+        };
+
+        unsafe { (*env).eflags | compute_flags(cpu) as target_ulong | direction }
+    }
+
+    pub(super) const ZERO: Flag = Flag::Zero;
+    pub(super) const NEGATIVE: Flag = Flag::Sign;
+}
+
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+mod arch {
+    use super::*;
+
+    // QEMU keeps N and V in bit 31 of their fields, C as 0 or 1, and Z inverted (the
+    // flag is set when the field is zero)
+    const SIGN_BIT: u32 = 1 << 31;
+
+    pub(super) fn get(cpu: &CPUState, flag: Flag) -> bool {
+        let env = unsafe { &*cpu_arch_state!(cpu) };
+
+        match flag {
+            Flag::Negative => env.NF & SIGN_BIT != 0,
+            Flag::Zero => env.ZF == 0,
+            Flag::Carry => env.CF != 0,
+            Flag::Overflow => env.VF & SIGN_BIT != 0,
+        }
+    }
+
+    pub(super) fn set(cpu: &mut CPUState, flag: Flag, value: bool) {
+        let env = unsafe { &mut *cpu_arch_state!(cpu) };
+
+        match flag {
+            Flag::Negative => env.NF = if value { SIGN_BIT } else { 0 },
+            Flag::Zero => env.ZF = if value { 0 } else { 1 },
+            Flag::Carry => env.CF = value as u32,
+            Flag::Overflow => env.VF = if value { SIGN_BIT } else { 0 },
+        }
+    }
+
+    pub(super) fn raw(cpu: &CPUState) -> target_ulong {
+        [Flag::Negative, Flag::Zero, Flag::Carry, Flag::Overflow]
+            .iter()
+            .enumerate()
+            .filter(|&(_, &flag)| get(cpu, flag))
+            .fold(0, |nzcv, (i, _)| nzcv | (1 << (31 - i)))
+    }
+
+    pub(super) const ZERO: Flag = Flag::Zero;
+    pub(super) const NEGATIVE: Flag = Flag::Negative;
+}
+
+#[cfg(feature = "ppc")]
+mod arch {
+    use super::*;
+
+    const CR_LT: u32 = 0b1000;
+    const CR_GT: u32 = 0b0100;
+    const CR_EQ: u32 = 0b0010;
+    const CR_SO: u32 = 0b0001;
+
+    /// The condition register field and bit within it of a flag, if it's in the CR
+    fn cr_bit(flag: Flag) -> Option<(usize, u32)> {
+        let (field, bit) = match flag {
+            Flag::Lt(field) => (field, CR_LT),
+            Flag::Gt(field) => (field, CR_GT),
+            Flag::Eq(field) => (field, CR_EQ),
+            Flag::So(field) => (field, CR_SO),
+            _ => return None,
+        };
+
+        assert!(field < 8, "condition register field out of range");
+
+        Some((field as usize, bit))
+    }
+
+    pub(super) fn get(cpu: &CPUState, flag: Flag) -> bool {
+        let env = unsafe { &*cpu_arch_state!(cpu) };
+
+        match flag {
+            Flag::Carry => env.ca != 0,
+            Flag::Overflow => env.ov != 0,
+            Flag::SummaryOverflow => env.so != 0,
+            _ => {
+                let (field, bit) = cr_bit(flag).unwrap();
+                env.crf[field] & bit != 0
+            }
+        }
+    }
+
+    pub(super) fn set(cpu: &mut CPUState, flag: Flag, value: bool) {
+        let env = unsafe { &mut *cpu_arch_state!(cpu) };
+
+        match flag {
+            Flag::Carry => env.ca = value as target_ulong,
+            Flag::Overflow => env.ov = value as target_ulong,
+            Flag::SummaryOverflow => env.so = value as target_ulong,
+            _ => {
+                let (field, bit) = cr_bit(flag).unwrap();
+                if value {
+                    env.crf[field] |= bit;
+                } else {
+                    env.crf[field] &= !bit;
+                }
+            }
+        }
+    }
+
+    pub(super) fn raw(cpu: &CPUState) -> target_ulong {
+        let env = unsafe { &*cpu_arch_state!(cpu) };
+
+        env.crf
+            .iter()
+            .fold(0, |cr, &field| (cr << 4) | (field & 0xf) as target_ulong)
+    }
+
+    pub(super) const ZERO: Flag = Flag::Eq(0);
+    pub(super) const NEGATIVE: Flag = Flag::Lt(0);
+}
+
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+mod arch {
+    use super::*;
+
+    /// The bit of `FCSR` holding an FPU condition code
+    fn fcc_bit(flag: Flag) -> u32 {
+        let Flag::FpCondition(cc) = flag;
+        assert!(cc < 8, "FPU condition code out of range");
+
+        match cc {
+            0 => 1 << 23,
+            cc => 1 << (24 + cc),
+        }
+    }
+
+    pub(super) fn get(cpu: &CPUState, flag: Flag) -> bool {
+        let env = unsafe { &*cpu_arch_state!(cpu) };
+
+        env.active_fpu.fcr31 & fcc_bit(flag) != 0
+    }
+
+    pub(super) fn set(cpu: &mut CPUState, flag: Flag, value: bool) {
+        let env = unsafe { &mut *cpu_arch_state!(cpu) };
+
+        if value {
+            env.active_fpu.fcr31 |= fcc_bit(flag);
+        } else {
+            env.active_fpu.fcr31 &= !fcc_bit(flag);
+        }
+    }
+
+    pub(super) fn raw(cpu: &CPUState) -> target_ulong {
+        let env = unsafe { &*cpu_arch_state!(cpu) };
+
+        env.active_fpu.fcr31 as target_ulong
+    }
+}
+
+/// Read a condition flag
+///
+/// ## Panics
+///
+/// On PowerPC and MIPS, this panics if the condition register field or condition code
+/// of `flag` is greater than 7.
+pub fn get(cpu: &CPUState, flag: Flag) -> bool {
+    arch::get(cpu, flag)
+}
+
+/// Set or clear a condition flag
+///
+/// ## Panics
+///
+/// On PowerPC and MIPS, this panics if the condition register field or condition code
+/// of `flag` is greater than 7.
+pub fn set(cpu: &mut CPUState, flag: Flag, value: bool) {
+    arch::set(cpu, flag, value);
+
+    audit::record(Some(cpu), || audit::Mutation::Flag { flag, value });
+}
+
+/// Read all the condition flags packed the way the guest sees them:
+///
+/// * x86: `EFLAGS`
+/// * ARM/AArch64: `NZCV` in bits 31 to 28
+/// * PowerPC: `CR`
+/// * MIPS: `FCSR`
+pub fn raw(cpu: &CPUState) -> target_ulong {
+    arch::raw(cpu)
+}
+
+macro_rules! common_flags {
+    ($($name:ident, $set_name:ident => $flag:expr, $desc:literal;)*) => {
+        $(
+            #[doc = concat!("Read the ", $desc)]
+            #[cfg_attr(
+                doc_cfg,
+                doc(cfg(not(any(
+                    feature = "mips",
+                    feature = "mipsel",
+                    feature = "mips64",
+                    feature = "mips64el"
+                ))))
+            )]
+            #[cfg(not(any(
+                feature = "mips",
+                feature = "mipsel",
+                feature = "mips64",
+                feature = "mips64el"
+            )))]
+            pub fn $name(cpu: &CPUState) -> bool {
+                get(cpu, $flag)
+            }
+
+            #[doc = concat!("Set or clear the ", $desc)]
+            #[cfg_attr(
+                doc_cfg,
+                doc(cfg(not(any(
+                    feature = "mips",
+                    feature = "mipsel",
+                    feature = "mips64",
+                    feature = "mips64el"
+                ))))
+            )]
+            #[cfg(not(any(
+                feature = "mips",
+                feature = "mipsel",
+                feature = "mips64",
+                feature = "mips64el"
+            )))]
+            pub fn $set_name(cpu: &mut CPUState, value: bool) {
+                set(cpu, $flag, value)
+            }
+        )*
+    };
+}
+
+common_flags! {
+    carry, set_carry => Flag::Carry, "carry flag (`CF` on x86, `C` on ARM, `XER[CA]` on PowerPC)";
+    zero, set_zero => arch::ZERO, "zero flag (`ZF` on x86, `Z` on ARM, `CR0[EQ]` on PowerPC)";
+    negative, set_negative => arch::NEGATIVE, "negative flag (`SF` on x86, `N` on ARM, `CR0[LT]` on PowerPC)";
+    overflow, set_overflow => Flag::Overflow, "overflow flag (`OF` on x86, `V` on ARM, `XER[OV]` on PowerPC)";
+}
@@ -0,0 +1,164 @@
+//! x86 control registers and model-specific registers
+//!
+//! Setting a control register or MSR goes through the same paths QEMU uses for the
+//! guest's own `mov cr`/`wrmsr`, so the TLB and CPU mode are updated to match. Only
+//! the MSRs with state stored by QEMU's CPU model are supported (see [`Msr`]).
+//!
+//! Segment register bases can be found in the [`segment`](crate::segment) module, and
+//! are re-exported here.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::regs::x86::{self, ControlReg, Msr};
+//!
+//! #[panda::asid_changed]
+//! fn asid_changed(cpu: &mut CPUState, _old: target_ulong, _new: target_ulong) -> bool {
+//!     let cr3 = x86::get_cr(cpu, ControlReg::CR3);
+//!     let syscall_entry = x86::read_msr(cpu, Msr::LSTAR);
+//!     println!("cr3 = {:#x}, syscall entry = {:#x?}", cr3, syscall_entry);
+//!
+//!     false
+//! }
+//! ```
+use crate::audit;
+use crate::prelude::*;
+use crate::{cpu_arch_state, CPUArchPtr};
+
+use strum_macros::{EnumIter, EnumString, ToString};
+
+pub use crate::segment::{
+    get_efer, get_gs_base, get_segment_base, get_selector, set_gs_base, SegReg,
+};
+
+#[cfg(feature = "x86_64")]
+pub use crate::segment::{get_kernel_gs_base, set_kernel_gs_base};
+
+/// x86 control registers
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumString, EnumIter, ToString)]
+pub enum ControlReg {
+    CR0 = 0,
+    CR2 = 2,
+    CR3 = 3,
+    CR4 = 4,
+}
+
+/// Read the current value of a control register
+pub fn get_cr(cpu: &CPUState, reg: ControlReg) -> target_ulong {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe { (*cpu_arch).cr[reg as usize] }
+}
+
+/// Set the value of a control register. Changes to CR0, CR3 and CR4 flush the TLB and
+/// update the CPU mode as the guest writing them would.
+pub fn set_cr(cpu: &mut CPUState, reg: ControlReg, value: target_ulong) {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe {
+        match reg {
+            ControlReg::CR0 => panda_sys::cpu_x86_update_cr0(cpu_arch, value as u32),
+            ControlReg::CR2 => (*cpu_arch).cr[2] = value,
+            ControlReg::CR3 => panda_sys::cpu_x86_update_cr3(cpu_arch, value),
+            ControlReg::CR4 => panda_sys::cpu_x86_update_cr4(cpu_arch, value as u32),
+        }
+    }
+
+    audit::record(Some(cpu), || audit::Mutation::ControlRegister {
+        reg,
+        value,
+    });
+}
+
+/// A model-specific register, identified by its index as used by `rdmsr`/`wrmsr`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Msr(pub u32);
+
+impl Msr {
+    pub const SYSENTER_CS: Msr = Msr(0x174);
+    pub const SYSENTER_ESP: Msr = Msr(0x175);
+    pub const SYSENTER_EIP: Msr = Msr(0x176);
+    pub const PAT: Msr = Msr(0x277);
+    pub const EFER: Msr = Msr(0xc000_0080);
+    pub const STAR: Msr = Msr(0xc000_0081);
+    pub const LSTAR: Msr = Msr(0xc000_0082);
+    pub const CSTAR: Msr = Msr(0xc000_0083);
+    pub const FMASK: Msr = Msr(0xc000_0084);
+    pub const FS_BASE: Msr = Msr(0xc000_0100);
+    pub const GS_BASE: Msr = Msr(0xc000_0101);
+    pub const KERNEL_GS_BASE: Msr = Msr(0xc000_0102);
+    pub const TSC_AUX: Msr = Msr(0xc000_0103);
+}
+
+/// Read a model-specific register. Returns `None` for MSRs which aren't supported,
+/// including the 64-bit only MSRs (`LSTAR`, `CSTAR`, `FMASK` and `KERNEL_GS_BASE`) on
+/// i386.
+pub fn read_msr(cpu: &CPUState, msr: Msr) -> Option<u64> {
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    Some(match msr {
+        Msr::SYSENTER_CS => env.sysenter_cs as u64,
+        Msr::SYSENTER_ESP => env.sysenter_esp as u64,
+        Msr::SYSENTER_EIP => env.sysenter_eip as u64,
+        Msr::PAT => env.pat,
+        Msr::EFER => env.efer,
+        Msr::STAR => env.star,
+        #[cfg(feature = "x86_64")]
+        Msr::LSTAR => env.lstar as u64,
+        #[cfg(feature = "x86_64")]
+        Msr::CSTAR => env.cstar as u64,
+        #[cfg(feature = "x86_64")]
+        Msr::FMASK => env.fmask as u64,
+        Msr::FS_BASE => env.segs[SegReg::FS as usize].base as u64,
+        Msr::GS_BASE => env.segs[SegReg::GS as usize].base as u64,
+        #[cfg(feature = "x86_64")]
+        Msr::KERNEL_GS_BASE => env.kernelgsbase as u64,
+        Msr::TSC_AUX => env.tsc_aux,
+        _ => return None,
+    })
+}
+
+/// Write a model-specific register, returning `false` without modifying the CPU for
+/// MSRs which aren't supported (see [`read_msr`])
+pub fn write_msr(cpu: &mut CPUState, msr: Msr, value: u64) -> bool {
+    const EFER_LMA: u64 = 1 << 10;
+    const EFER_SVME: u64 = 1 << 12;
+
+    let env = unsafe { &mut *cpu_arch_state!(cpu) };
+
+    match msr {
+        Msr::SYSENTER_CS => env.sysenter_cs = value as u32,
+        Msr::SYSENTER_ESP => env.sysenter_esp = value as target_ulong,
+        Msr::SYSENTER_EIP => env.sysenter_eip = value as target_ulong,
+        Msr::PAT => env.pat = value,
+        Msr::EFER => {
+            // the mode bits of EFER are mirrored in hflags, as QEMU's cpu_load_efer does
+            env.efer = value;
+            env.hflags &= !(panda_sys::HF_LMA_MASK | panda_sys::HF_SVME_MASK);
+            if value & EFER_LMA != 0 {
+                env.hflags |= panda_sys::HF_LMA_MASK;
+            }
+            if value & EFER_SVME != 0 {
+                env.hflags |= panda_sys::HF_SVME_MASK;
+            }
+        }
+        Msr::STAR => env.star = value,
+        #[cfg(feature = "x86_64")]
+        Msr::LSTAR => env.lstar = value as target_ulong,
+        #[cfg(feature = "x86_64")]
+        Msr::CSTAR => env.cstar = value as target_ulong,
+        #[cfg(feature = "x86_64")]
+        Msr::FMASK => env.fmask = value as target_ulong,
+        Msr::FS_BASE => env.segs[SegReg::FS as usize].base = value as target_ulong,
+        Msr::GS_BASE => env.segs[SegReg::GS as usize].base = value as target_ulong,
+        #[cfg(feature = "x86_64")]
+        Msr::KERNEL_GS_BASE => env.kernelgsbase = value as target_ulong,
+        Msr::TSC_AUX => env.tsc_aux = value,
+        _ => return false,
+    }
+
+    audit::record(Some(cpu), || audit::Mutation::Msr { msr, value });
+
+    true
+}
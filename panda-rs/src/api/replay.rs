@@ -0,0 +1,211 @@
+//! Instrumenting only a slice of a replay
+//!
+//! For long recordings where only a window of execution matters, [`instrument_between`]
+//! keeps this plugin's callbacks disabled and PANDA's expensive runtime modes (memory
+//! callbacks, precise PC and LLVM) off until the start of the window is reached, then
+//! enables them for the duration of the window and ends the replay once the window is
+//! over, so that the rest of the replay is skipped.
+//!
+//! The start and end of the window can be given either as guest instruction counts or
+//! as trigger events (see [`ReplayPoint`]). Both are checked at the start of each basic
+//! block, so the window begins and ends on block boundaries.
+//!
+//! Only callbacks registered by this plugin through PANDA (both attribute-based and
+//! [`Callback`](crate::Callback)s) are disabled outside the window. Callbacks provided
+//! by other plugins, such as PPP callbacks and hooks, will still run and can use
+//! [`in_window`] to check whether they should do any work.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::replay::{self, ReplayPoint};
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     // Instrument from instruction 1,000,000 until the guest reaches 0x401000
+//!     replay::instrument_between(1_000_000, ReplayPoint::Pc(0x401000));
+//! }
+//! ```
+use crate::callbacks::{disable_all, set_attribute_callbacks_enabled};
+use crate::prelude::*;
+use crate::rr::{replay_end, rr_get_guest_instr_count};
+use crate::runtime::{self, RuntimeGuard};
+use crate::sys::{self, panda_cb};
+use crate::tb_invalidation::flush_tb;
+use crate::Callback;
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// A point in the execution of a replay
+pub enum ReplayPoint {
+    /// The point at which the guest has executed the given number of instructions
+    Instr(u64),
+
+    /// The first time the guest executes the block containing the given address
+    Pc(target_ulong),
+
+    /// The first block for which the given function returns `true`
+    Trigger(Box<dyn FnMut(&mut CPUState, &mut TranslationBlock) -> bool + Send>),
+}
+
+impl ReplayPoint {
+    /// Create a point from a function which is run before each block executes,
+    /// returning `true` once the point has been reached
+    pub fn trigger(
+        trigger: impl FnMut(&mut CPUState, &mut TranslationBlock) -> bool + Send + 'static,
+    ) -> Self {
+        ReplayPoint::Trigger(Box::new(trigger))
+    }
+
+    fn reached(&mut self, cpu: &mut CPUState, tb: &mut TranslationBlock) -> bool {
+        match self {
+            ReplayPoint::Instr(count) => rr_get_guest_instr_count() >= *count,
+            // a block at the top of the address space can wrap around its end
+            ReplayPoint::Pc(pc) => pc.wrapping_sub(tb.pc) < tb.size as target_ulong,
+            ReplayPoint::Trigger(trigger) => trigger(cpu, tb),
+        }
+    }
+}
+
+impl From<u64> for ReplayPoint {
+    fn from(count: u64) -> Self {
+        ReplayPoint::Instr(count)
+    }
+}
+
+enum WindowState {
+    Before,
+    Inside,
+    After,
+}
+
+struct Window {
+    start: ReplayPoint,
+    end: ReplayPoint,
+    state: WindowState,
+
+    /// Guards keeping runtime modes disabled until the start of the window
+    guards: Vec<RuntimeGuard>,
+}
+
+// RuntimeGuard restore closures only touch PANDA's global state
+unsafe impl Send for Window {}
+
+lazy_static::lazy_static! {
+    static ref WINDOW: Mutex<Option<Window>> = Mutex::new(None);
+
+    /// The closure callbacks disabled outside of the window, to be enabled again once
+    /// it starts
+    static ref SUSPENDED: Mutex<Vec<Callback>> = Mutex::new(Vec::new());
+}
+
+static IN_WINDOW: AtomicBool = AtomicBool::new(true);
+static WATCHER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// The owner of the callback watching for the window, distinct from this plugin's
+/// handle so that it keeps running while the plugin's callbacks are disabled
+static WATCHER_OWNER: u8 = 0;
+
+/// Enable or disable this plugin's callbacks. Rather than `panda_enable_plugin`, which
+/// would also enable callbacks which were disabled beforehand, only the closure callbacks
+/// which were disabled here and are still installed are enabled again.
+fn set_plugin_enabled(enabled: bool) {
+    set_attribute_callbacks_enabled(enabled);
+
+    let mut suspended = SUSPENDED.lock().unwrap();
+    if enabled {
+        for callback in suspended.drain(..) {
+            callback.enable();
+        }
+    } else {
+        suspended.extend(disable_all());
+    }
+}
+
+extern "C" fn watch(cpu: *mut CPUState, tb: *mut TranslationBlock) {
+    let (cpu, tb) = unsafe { (&mut *cpu, &mut *tb) };
+    let mut window = WINDOW.lock().unwrap();
+    let window = match &mut *window {
+        Some(window) => window,
+        None => return,
+    };
+
+    match window.state {
+        WindowState::Before if window.start.reached(cpu, tb) => {
+            window.state = WindowState::Inside;
+            window.guards.clear();
+            IN_WINDOW.store(true, Ordering::SeqCst);
+            set_plugin_enabled(true);
+
+            // Retranslate code translated without this plugin's instrumentation
+            flush_tb();
+        }
+        WindowState::Inside if window.end.reached(cpu, tb) => {
+            window.state = WindowState::After;
+            IN_WINDOW.store(false, Ordering::SeqCst);
+            set_plugin_enabled(false);
+
+            let _ = replay_end();
+        }
+        _ => (),
+    }
+}
+
+/// Only instrument the replay between the `start` and `end` points, skipping the
+/// rest of the replay as cheaply as possible. Replaces any previously set window.
+///
+/// Until `start` is reached, this plugin's callbacks are disabled and memory callbacks,
+/// precise PC and LLVM are turned off. Once `start` is reached, these are restored to
+/// their previous state and the translation cache is flushed. When `end` is reached,
+/// the plugin's callbacks are disabled again and the replay is ended.
+///
+/// Instruction counts can be passed directly as either point:
+///
+/// ```no_run
+/// panda::replay::instrument_between(500_000, 2_000_000);
+/// ```
+pub fn instrument_between(start: impl Into<ReplayPoint>, end: impl Into<ReplayPoint>) {
+    let mut window = WINDOW.lock().unwrap();
+
+    // Drop the guards of any previous window before taking new ones
+    *window = None;
+
+    let guards = vec![
+        runtime::disable_memcb(),
+        runtime::disable_precise_pc(),
+        runtime::disable_llvm(),
+    ];
+
+    *window = Some(Window {
+        start: start.into(),
+        end: end.into(),
+        state: WindowState::Before,
+        guards,
+    });
+
+    IN_WINDOW.store(false, Ordering::SeqCst);
+    set_plugin_enabled(false);
+
+    if !WATCHER_INSTALLED.swap(true, Ordering::SeqCst) {
+        unsafe {
+            sys::panda_register_callback(
+                &WATCHER_OWNER as *const u8 as *mut c_void,
+                sys::panda_cb_type_PANDA_CB_BEFORE_BLOCK_EXEC,
+                panda_cb {
+                    before_block_exec: Some(std::mem::transmute(
+                        watch as extern "C" fn(*mut CPUState, *mut TranslationBlock),
+                    )),
+                },
+            );
+        }
+    }
+}
+
+/// Check whether execution is currently within the window set by
+/// [`instrument_between`]. Always `true` if no window has been set.
+pub fn in_window() -> bool {
+    IN_WINDOW.load(Ordering::SeqCst)
+}
@@ -1,13 +1,43 @@
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::ptr;
+use std::sync::Mutex;
 
-use crate::{Error, RrError};
+use crate::{Callback, Error, RrError};
 
 /// RR point-in-time: get current count of instructions replayed
 pub fn rr_get_guest_instr_count() -> u64 {
     unsafe { panda_sys::rr_get_guest_instr_count_external() as _ }
 }
 
+/// The record/replay mode PANDA is currently in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RrMode {
+    Off,
+    Record,
+    Replay,
+}
+
+/// Get whether PANDA is currently recording, replaying or neither
+pub fn rr_mode() -> RrMode {
+    let mode = unsafe { ptr::read_volatile(ptr::addr_of!(panda_sys::rr_control.mode)) };
+
+    match mode {
+        panda_sys::RR_mode_RR_RECORD => RrMode::Record,
+        panda_sys::RR_mode_RR_REPLAY => RrMode::Replay,
+        _ => RrMode::Off,
+    }
+}
+
+/// Check whether a recording is currently being made
+pub fn in_record() -> bool {
+    rr_mode() == RrMode::Record
+}
+
+/// Check whether a replay is currently running
+pub fn in_replay() -> bool {
+    rr_mode() == RrMode::Replay
+}
+
 /// Stop and quit, wraps QMP functions.
 pub fn vm_quit() {
     let rr_ctrl_ret = unsafe { panda_sys::panda_vm_quit() };
@@ -16,7 +46,8 @@ pub fn vm_quit() {
     assert_eq!(rr_ctrl_ret, panda_sys::RRCTRL_ret_RRCTRL_OK);
 }
 
-/// Start recording.
+/// Start recording, saving the recording under the given name. The recording begins
+/// once the current block has finished executing.
 /// If `snapshot.is_some()` restore the named snapshot prior to recording.
 pub fn record_begin(name: &str, snapshot: Option<&str>) -> Result<(), Error> {
     match CString::new(name) {
@@ -63,3 +94,110 @@ pub fn replay_end() -> Result<(), Error> {
     RrError::translate_err_code(rr_ctrl_ret)
 }
 
+/// The replay log of the replay currently running, if any
+fn replay_log() -> Option<&'static panda_sys::RR_log> {
+    if !in_replay() {
+        return None;
+    }
+
+    unsafe { panda_sys::rr_nondet_log.as_ref() }
+}
+
+/// Information about the recording being replayed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayInfo {
+    /// The name the recording was opened with
+    pub name: String,
+
+    /// Whether the recording is in the rr2 archive format, rather than a separate
+    /// snapshot and log file
+    pub rr2: bool,
+
+    /// The number of guest instructions in the recording, if known
+    pub total_instr: Option<u64>,
+
+    /// The size of the nondeterminism log in bytes
+    pub log_size: u64,
+
+    /// How much of the nondeterminism log has been read, in bytes
+    pub log_read: u64,
+}
+
+/// Get information about the recording being replayed, or `None` if no replay is
+/// running
+pub fn replay_info() -> Option<ReplayInfo> {
+    let log = replay_log()?;
+    let name = if log.name.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(log.name) }
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    Some(ReplayInfo {
+        name,
+        rr2: log.rr2,
+        total_instr: replay_length(),
+        log_size: log.size,
+        log_read: log.bytes_read,
+    })
+}
+
+/// Get the number of guest instructions in the recording being replayed, or `None` if
+/// no replay is running or the recording doesn't say
+pub fn replay_length() -> Option<u64> {
+    Some(replay_log()?.last_prog_point.guest_instr_count).filter(|&total| total != 0)
+}
+
+/// Get how far through the replay PANDA is, as a percentage
+///
+/// This is by instruction count when the [length of the replay](replay_length) is
+/// known, otherwise by how much of the nondeterminism log has been read.
+pub fn replay_percentage() -> Option<f64> {
+    if !in_replay() {
+        return None;
+    }
+
+    Some(match replay_length() {
+        Some(total) => (rr_get_guest_instr_count() as f64 * 100.0 / total as f64).min(100.0),
+        None => unsafe { panda_sys::rr_get_percentage() },
+    })
+}
+
+lazy_static::lazy_static! {
+    /// The instruction count to end the replay at, if any
+    static ref END_AT: Mutex<Option<u64>> = Mutex::new(None);
+    static ref END_CALLBACK: Callback = install_end_callback();
+}
+
+fn install_end_callback() -> Callback {
+    let callback = Callback::new();
+
+    callback.before_block_exec(|_, _| {
+        let mut end_at = END_AT.lock().unwrap();
+        if matches!(*end_at, Some(end) if rr_get_guest_instr_count() >= end) {
+            *end_at = None;
+            if let Err(err) = replay_end() {
+                eprintln!("Warning: failed to end replay early: {}", err);
+            }
+        }
+    });
+
+    callback
+}
+
+/// End the replay once the guest has executed the given number of instructions,
+/// skipping the rest of it. The replay ends at the start of the first block at or after
+/// that count. If an end has already been set, the earlier of the two is used.
+pub fn end_replay_at(instr_count: u64) {
+    lazy_static::initialize(&END_CALLBACK);
+
+    let mut end_at = END_AT.lock().unwrap();
+    *end_at = Some(end_at.map_or(instr_count, |end| end.min(instr_count)));
+}
+
+/// Cancel ending the replay early set by [`end_replay_at`]
+pub fn cancel_replay_end() {
+    END_AT.lock().unwrap().take();
+}
@@ -0,0 +1,321 @@
+//! Safe togglers for PANDA's global runtime modes.
+//!
+//! Each toggle returns a guard which holds the mode for as long as it is alive, allowing
+//! analyses which require a specific mode to set it for as long as they need it without
+//! clobbering the configuration of other plugins.
+//!
+//! Guards are reference counted per mode, so they can be dropped in any order. While
+//! only guards for one setting of a mode are held, the mode is set to it. Once the last
+//! guard is dropped, the mode goes back to what it was before the first guard was
+//! taken. While guards for different settings are held at once, the mode is left as it
+//! is until only one setting is left.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::runtime;
+//!
+//! // Make sure every block returns to the main loop while `_guard` is alive
+//! let _guard = runtime::disable_tb_chaining();
+//! ```
+//!
+//! To change a mode permanently, [`RuntimeGuard::forget`] can be used.
+use panda_sys::{execute_llvm, generate_llvm, panda_tb_chaining, panda_update_pc, panda_use_memcb};
+
+use std::sync::Mutex;
+
+/// The state of PANDA's LLVM translation
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LlvmMode {
+    /// Blocks are not translated to LLVM
+    Disabled,
+
+    /// Blocks are translated to LLVM, but TCG is still used for execution
+    NoExec,
+
+    /// Blocks are translated to and executed as LLVM
+    Exec,
+}
+
+/// A guard which holds a PANDA runtime mode until it is dropped.
+#[must_use = "the runtime mode is released as soon as the guard is dropped"]
+pub struct RuntimeGuard {
+    restore: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl RuntimeGuard {
+    pub(crate) fn new(restore: impl FnOnce() + Send + 'static) -> Self {
+        Self {
+            restore: Some(Box::new(restore)),
+        }
+    }
+
+    /// Consume the guard without releasing the mode, holding it for the rest of the
+    /// run.
+    pub fn forget(mut self) {
+        self.restore.take();
+    }
+}
+
+impl Drop for RuntimeGuard {
+    fn drop(&mut self) {
+        if let Some(restore) = self.restore.take() {
+            restore();
+        }
+    }
+}
+
+/// Check whether precise PC mode is currently enabled
+pub fn is_precise_pc_enabled() -> bool {
+    unsafe { panda_update_pc }
+}
+
+/// Check whether memory callbacks are currently enabled
+pub fn is_memcb_enabled() -> bool {
+    unsafe { panda_use_memcb }
+}
+
+/// Check whether translation block chaining is currently enabled
+pub fn is_tb_chaining_enabled() -> bool {
+    unsafe { panda_tb_chaining }
+}
+
+/// Get the current LLVM translation mode
+pub fn llvm_mode() -> LlvmMode {
+    unsafe {
+        match (generate_llvm != 0, execute_llvm != 0) {
+            (false, _) => LlvmMode::Disabled,
+            (true, false) => LlvmMode::NoExec,
+            (true, true) => LlvmMode::Exec,
+        }
+    }
+}
+
+fn set_precise_pc(enabled: bool) {
+    unsafe {
+        if enabled {
+            panda_sys::panda_enable_precise_pc();
+        } else {
+            panda_sys::panda_disable_precise_pc();
+        }
+    }
+}
+
+fn set_memcb(enabled: bool) {
+    unsafe {
+        if enabled {
+            panda_sys::panda_enable_memcb();
+        } else {
+            panda_sys::panda_disable_memcb();
+        }
+    }
+}
+
+fn set_tb_chaining(enabled: bool) {
+    unsafe {
+        if enabled {
+            panda_sys::panda_enable_tb_chaining();
+        } else {
+            panda_sys::panda_disable_tb_chaining();
+        }
+    }
+}
+
+/// Set the LLVM translation mode without a guard
+pub fn set_llvm_mode(mode: LlvmMode) {
+    if llvm_mode() == mode {
+        return;
+    }
+
+    unsafe {
+        match mode {
+            LlvmMode::Disabled => panda_sys::panda_disable_llvm(),
+            LlvmMode::NoExec => panda_sys::panda_enable_llvm_no_exec(),
+            LlvmMode::Exec => panda_sys::panda_enable_llvm(),
+        }
+    }
+}
+
+/// The guards held for each setting of a mode
+struct ModeCounts<T> {
+    held: Vec<(T, usize)>,
+
+    /// The setting of the mode before the first guard was taken
+    baseline: Option<T>,
+}
+
+impl<T: Copy + PartialEq> ModeCounts<T> {
+    fn new() -> Self {
+        Self {
+            held: Vec::new(),
+            baseline: None,
+        }
+    }
+
+    /// Take a guard for `value`, returning the setting the mode should now have
+    fn acquire(&mut self, value: T, current: T) -> T {
+        if self.held.is_empty() {
+            self.baseline = Some(current);
+        }
+
+        match self.held.iter_mut().find(|(held, _)| *held == value) {
+            Some((_, count)) => *count += 1,
+            None => self.held.push((value, 1)),
+        }
+
+        self.wanted(current)
+    }
+
+    /// Drop a guard for `value`, returning the setting the mode should now have
+    fn release(&mut self, value: T, current: T) -> T {
+        if let Some(i) = self.held.iter().position(|(held, _)| *held == value) {
+            self.held[i].1 -= 1;
+            if self.held[i].1 == 0 {
+                self.held.remove(i);
+            }
+        }
+
+        self.wanted(current)
+    }
+
+    fn wanted(&mut self, current: T) -> T {
+        match self.held.as_slice() {
+            [] => self.baseline.take().unwrap_or(current),
+            [(value, _)] => *value,
+            _ => current,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PRECISE_PC: Mutex<ModeCounts<bool>> = Mutex::new(ModeCounts::new());
+    static ref MEMCB: Mutex<ModeCounts<bool>> = Mutex::new(ModeCounts::new());
+    static ref TB_CHAINING: Mutex<ModeCounts<bool>> = Mutex::new(ModeCounts::new());
+    static ref LLVM: Mutex<ModeCounts<LlvmMode>> = Mutex::new(ModeCounts::new());
+}
+
+/// Take a guard holding a mode at `value`
+fn guard<T: Copy + PartialEq + Send + 'static>(
+    counts: &'static Mutex<ModeCounts<T>>,
+    get: fn() -> T,
+    set: fn(T),
+    value: T,
+) -> RuntimeGuard {
+    let apply = move |wanted: T| {
+        if get() != wanted {
+            set(wanted);
+        }
+    };
+
+    apply(counts.lock().unwrap().acquire(value, get()));
+
+    RuntimeGuard::new(move || apply(counts.lock().unwrap().release(value, get())))
+}
+
+macro_rules! toggles {
+    ($(
+        $(#[$meta:meta])*
+        fn $name:ident() => ($counts:ident, $getter:ident, $setter:ident, $value:literal);
+    )*) => {
+        $(
+            $(#[$meta])*
+            pub fn $name() -> RuntimeGuard {
+                guard(&$counts, $getter, $setter, $value)
+            }
+        )*
+    };
+}
+
+toggles! {
+    /// Turn on precise PC mode, in which PANDA keeps an accurate shadow program
+    /// counter at the instruction level. Restored when the guard is dropped.
+    fn enable_precise_pc() => (PRECISE_PC, is_precise_pc_enabled, set_precise_pc, true);
+
+    /// Turn off precise PC mode. Restored when the guard is dropped.
+    fn disable_precise_pc() => (PRECISE_PC, is_precise_pc_enabled, set_precise_pc, false);
+
+    /// Turn on memory callbacks (required for the `virt_mem_*`/`phys_mem_*`
+    /// callbacks to fire). Restored when the guard is dropped.
+    fn enable_memcb() => (MEMCB, is_memcb_enabled, set_memcb, true);
+
+    /// Turn off memory callbacks. Restored when the guard is dropped.
+    fn disable_memcb() => (MEMCB, is_memcb_enabled, set_memcb, false);
+
+    /// Turn on translation block chaining. Restored when the guard is dropped.
+    fn enable_tb_chaining() => (TB_CHAINING, is_tb_chaining_enabled, set_tb_chaining, true);
+
+    /// Turn off translation block chaining, forcing every block to return to the
+    /// main emulation loop after it executes. Restored when the guard is dropped.
+    fn disable_tb_chaining() => (TB_CHAINING, is_tb_chaining_enabled, set_tb_chaining, false);
+}
+
+/// Turn on LLVM translation and execution. Restored when the guard is dropped.
+pub fn enable_llvm() -> RuntimeGuard {
+    llvm_guard(LlvmMode::Exec)
+}
+
+/// Turn on LLVM translation, while still executing TCG. Restored when the guard is
+/// dropped.
+pub fn enable_llvm_no_exec() -> RuntimeGuard {
+    llvm_guard(LlvmMode::NoExec)
+}
+
+/// Turn off LLVM translation and execution. Restored when the guard is dropped.
+pub fn disable_llvm() -> RuntimeGuard {
+    llvm_guard(LlvmMode::Disabled)
+}
+
+fn llvm_guard(mode: LlvmMode) -> RuntimeGuard {
+    guard(&LLVM, llvm_mode, set_llvm_mode, mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guards_dropped_out_of_order() {
+        let mut counts = ModeCounts::new();
+
+        assert_eq!(counts.acquire(true, false), true);
+        assert_eq!(counts.acquire(true, true), true);
+
+        // dropping the first guard leaves the mode held by the second
+        assert_eq!(counts.release(true, true), true);
+        assert_eq!(counts.release(true, true), false);
+    }
+
+    #[test]
+    fn test_conflicting_guards() {
+        let mut counts = ModeCounts::new();
+
+        assert_eq!(counts.acquire(false, true), false);
+        assert_eq!(counts.acquire(true, false), false);
+        assert_eq!(counts.release(false, false), true);
+        assert_eq!(counts.release(true, true), true);
+    }
+
+    #[test]
+    fn test_baseline_restored() {
+        let mut counts = ModeCounts::new();
+
+        assert_eq!(
+            counts.acquire(LlvmMode::Exec, LlvmMode::NoExec),
+            LlvmMode::Exec
+        );
+        assert_eq!(
+            counts.release(LlvmMode::Exec, LlvmMode::Exec),
+            LlvmMode::NoExec
+        );
+
+        // the baseline is taken again by the next guard
+        assert_eq!(
+            counts.acquire(LlvmMode::Exec, LlvmMode::Disabled),
+            LlvmMode::Exec
+        );
+        assert_eq!(
+            counts.release(LlvmMode::Exec, LlvmMode::Exec),
+            LlvmMode::Disabled
+        );
+    }
+}
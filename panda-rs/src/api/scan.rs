@@ -0,0 +1,431 @@
+//! Scanning of executable guest memory for byte patterns and ROP gadgets
+//!
+//! Executable regions are found using the mappings provided by OSI, filtered down to
+//! those marked executable in the guest kernel's `vm_area_struct`. This requires a
+//! Linux guest, with cosi loaded alongside a volatility profile for the guest kernel.
+//!
+//! Memory can only be read from the current address space, so the regions of a
+//! process can only be scanned while that process is running.
+//!
+//! ## Example
+//!
+//! ```
+//! use panda::prelude::*;
+//! use panda::scan::{self, Pattern};
+//!
+//! fn find_syscall_gadgets(cpu: &mut CPUState, pid: target_pid_t) {
+//!     let pattern: Pattern = "0f 05 c3".parse().unwrap();
+//!
+//!     for region in scan::exec_regions(cpu, pid).unwrap() {
+//!         for addr in scan::find_pattern(cpu, &region, &pattern).unwrap() {
+//!             println!("syscall; ret @ {:#x} ({})", addr, region.name);
+//!         }
+//!     }
+//! }
+//! ```
+use crate::current_asid;
+use crate::mem::{page_size, read_guest_type, virtual_memory_read_into};
+use crate::plugins::{cosi, osi::OSI};
+use crate::prelude::*;
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::str::FromStr;
+
+/// Linux `vm_area_struct::vm_flags` bit for executable mappings
+const VM_EXEC: target_ulong = 0x4;
+
+/// An error encountered while scanning guest memory
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScanError {
+    #[error("no process with pid {0} was found")]
+    NoSuchProcess(target_pid_t),
+
+    #[error("vm_area_struct.vm_flags not found, is cosi loaded with a volatility profile?")]
+    NoVmFlags,
+
+    #[error("the region belongs to asid {region:#x}, but the current asid is {current:#x}")]
+    WrongAddressSpace {
+        region: target_ulong,
+        current: target_ulong,
+    },
+
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(String),
+}
+
+/// An executable mapping within a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecRegion {
+    /// The first address of the mapping
+    pub start: target_ptr_t,
+
+    /// The address just past the end of the mapping
+    pub end: target_ptr_t,
+
+    /// The address space the mapping belongs to
+    pub asid: target_ulong,
+
+    /// The name of the mapping, typically the file name of the mapped file
+    pub name: String,
+
+    /// The path of the mapped file, if any
+    pub file: String,
+}
+
+impl ExecRegion {
+    /// The length of the region in bytes
+    pub fn len(&self) -> target_ptr_t {
+        self.end - self.start
+    }
+
+    /// Check whether the region is empty
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Check whether an address falls within the region
+    pub fn contains(&self, addr: target_ptr_t) -> bool {
+        (self.start..self.end).contains(&addr)
+    }
+
+    /// Read the parts of the region which are currently paged in, as a list of
+    /// contiguous `(address, bytes)` runs.
+    pub fn read_resident(
+        &self,
+        cpu: &mut CPUState,
+    ) -> Result<Vec<(target_ptr_t, Vec<u8>)>, ScanError> {
+        let current = current_asid(cpu);
+        if current != self.asid {
+            return Err(ScanError::WrongAddressSpace {
+                region: self.asid,
+                current,
+            });
+        }
+
+        let mut runs: Vec<(target_ptr_t, Vec<u8>)> = Vec::new();
+        let page_size = page_size();
+        let mut page = vec![0u8; page_size as usize];
+        let mut addr = self.start;
+
+        while addr < self.end {
+            let len = (page_size - (addr % page_size)).min(self.end - addr) as usize;
+
+            if virtual_memory_read_into(cpu, addr, &mut page[..len]).is_ok() {
+                match runs.last_mut() {
+                    Some((start, bytes)) if *start + bytes.len() as target_ptr_t == addr => {
+                        bytes.extend_from_slice(&page[..len]);
+                    }
+                    _ => runs.push((addr, page[..len].to_vec())),
+                }
+            }
+
+            addr += len as target_ptr_t;
+        }
+
+        Ok(runs)
+    }
+}
+
+fn c_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Enumerate the executable mappings of the process with the given pid
+pub fn exec_regions(cpu: &mut CPUState, pid: target_pid_t) -> Result<Vec<ExecRegion>, ScanError> {
+    let vm_flags_offset = cosi::type_from_name("vm_area_struct")
+        .map(|vma| vma.offset_of("vm_flags"))
+        .ok_or(ScanError::NoVmFlags)?;
+
+    let processes = OSI.get_processes(cpu);
+    if processes.is_null() {
+        return Err(ScanError::NoSuchProcess(pid));
+    }
+
+    let mut process = *processes
+        .iter()
+        .find(|process| process.pid == pid)
+        .ok_or(ScanError::NoSuchProcess(pid))?;
+
+    let mappings = OSI.get_mappings(cpu, &mut process);
+    if mappings.is_null() {
+        return Ok(Vec::new());
+    }
+
+    let regions = mappings
+        .iter()
+        .filter(|mapping| {
+            let flags_addr = mapping.modd.wrapping_add(vm_flags_offset as target_ptr_t);
+
+            read_guest_type::<target_ulong>(cpu, flags_addr)
+                .map(|flags| flags & VM_EXEC != 0)
+                .unwrap_or(false)
+        })
+        .map(|mapping| ExecRegion {
+            start: mapping.base,
+            end: mapping.base + mapping.size,
+            asid: process.asid,
+            name: c_string(mapping.name),
+            file: c_string(mapping.file),
+        })
+        .collect();
+
+    Ok(regions)
+}
+
+/// A byte pattern which may contain wildcard bytes
+///
+/// Patterns can be parsed from a string of space-separated hex bytes, where `??`
+/// matches any byte, such as `"48 8b ?? c3"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern(Vec<Option<u8>>);
+
+impl Pattern {
+    /// Create a pattern which matches the given bytes exactly
+    pub fn exact(bytes: &[u8]) -> Self {
+        Self(bytes.iter().copied().map(Some).collect())
+    }
+
+    /// The length of the pattern in bytes
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check whether the pattern is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Check whether the pattern matches the start of `bytes`
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        bytes.len() >= self.0.len()
+            && self
+                .0
+                .iter()
+                .zip(bytes)
+                .all(|(expected, byte)| expected.map_or(true, |expected| expected == *byte))
+    }
+
+    /// Find the offsets of all matches of the pattern within `bytes`
+    pub fn find_all<'a>(&'a self, bytes: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let count = if self.is_empty() {
+            0
+        } else {
+            (bytes.len() + 1).saturating_sub(self.len())
+        };
+
+        (0..count).filter(move |&offset| self.matches(&bytes[offset..]))
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = ScanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_whitespace()
+            .map(|byte| match byte {
+                "?" | "??" => Ok(None),
+                _ => u8::from_str_radix(byte, 16)
+                    .map(Some)
+                    .map_err(|_| ScanError::InvalidPattern(byte.to_owned())),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Find the addresses of every match of `pattern` within the resident parts of `region`
+pub fn find_pattern(
+    cpu: &mut CPUState,
+    region: &ExecRegion,
+    pattern: &Pattern,
+) -> Result<Vec<target_ptr_t>, ScanError> {
+    let matches = region
+        .read_resident(cpu)?
+        .iter()
+        .flat_map(|(start, bytes)| {
+            pattern
+                .find_all(bytes)
+                .map(move |offset| start + offset as target_ptr_t)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    Ok(matches)
+}
+
+/// A sequence of instructions ending in a return
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gadget {
+    /// The address of the first byte of the gadget
+    pub addr: target_ptr_t,
+
+    /// The bytes of the gadget, including the return instruction
+    pub bytes: Vec<u8>,
+}
+
+/// Find gadgets within a block of code which starts at `start`, with up to `max_len`
+/// bytes of instructions before the return.
+///
+/// On x86, where instructions have variable lengths, a gadget is produced for every
+/// byte offset before a return, so not every gadget is guaranteed to decode cleanly.
+/// On other architectures, gadgets are aligned to [`arch::INSN_ALIGN`].
+pub fn find_gadgets_in(start: target_ptr_t, bytes: &[u8], max_len: usize) -> Vec<Gadget> {
+    let align = arch::INSN_ALIGN;
+    let mut gadgets = Vec::new();
+
+    for ret in (0..bytes.len()).step_by(align) {
+        let ret_len = match arch::return_len(&bytes[ret..]) {
+            Some(len) => len,
+            None => continue,
+        };
+
+        let mut gadget_start = ret;
+        while ret - gadget_start <= max_len {
+            let body = &bytes[gadget_start..ret];
+
+            // Gadgets which return partway through are covered by the earlier return
+            let returns_early = (0..body.len())
+                .step_by(align)
+                .any(|i| arch::return_len(&bytes[gadget_start + i..]).is_some());
+
+            if returns_early {
+                break;
+            }
+
+            gadgets.push(Gadget {
+                addr: start + gadget_start as target_ptr_t,
+                bytes: bytes[gadget_start..ret + ret_len].to_vec(),
+            });
+
+            match gadget_start.checked_sub(align) {
+                Some(next) => gadget_start = next,
+                None => break,
+            }
+        }
+    }
+
+    gadgets
+}
+
+/// Find gadgets within the resident parts of `region`, with up to `max_len` bytes of
+/// instructions before the return. See [`find_gadgets_in`] for details.
+pub fn find_gadgets(
+    cpu: &mut CPUState,
+    region: &ExecRegion,
+    max_len: usize,
+) -> Result<Vec<Gadget>, ScanError> {
+    let gadgets = region
+        .read_resident(cpu)?
+        .iter()
+        .flat_map(|(start, bytes)| find_gadgets_in(*start, bytes, max_len))
+        .collect();
+
+    Ok(gadgets)
+}
+
+/// Architecture-specific instruction decoding helpers for the current guest architecture
+pub mod arch {
+    /// The alignment of instructions, in bytes
+    #[cfg(any(feature = "i386", feature = "x86_64"))]
+    pub const INSN_ALIGN: usize = 1;
+
+    /// The alignment of instructions, in bytes
+    #[cfg(not(any(feature = "i386", feature = "x86_64")))]
+    pub const INSN_ALIGN: usize = 4;
+
+    #[cfg(not(any(feature = "i386", feature = "x86_64")))]
+    use std::convert::TryInto;
+
+    /// Read a fixed-width instruction word in guest byte order. This is always the
+    /// architecture's default byte order, except on big endian ARM, which keeps
+    /// instructions little endian in BE8 mode.
+    #[cfg(not(any(feature = "i386", feature = "x86_64")))]
+    pub fn insn_word(bytes: &[u8]) -> Option<u32> {
+        let word: [u8; 4] = bytes.get(..4)?.try_into().ok()?;
+
+        #[cfg(feature = "arm")]
+        let endian = crate::enums::Endian::Little;
+
+        #[cfg(not(feature = "arm"))]
+        let endian = crate::ARCH_ENDIAN;
+
+        match endian {
+            crate::enums::Endian::Big => Some(u32::from_be_bytes(word)),
+            crate::enums::Endian::Little => Some(u32::from_le_bytes(word)),
+        }
+    }
+
+    /// If `bytes` starts with a return instruction, get the length of the return
+    /// (including any delay slot).
+    ///
+    /// Recognizes `ret`/`retf` (with or without an immediate).
+    #[cfg(any(feature = "i386", feature = "x86_64"))]
+    pub fn return_len(bytes: &[u8]) -> Option<usize> {
+        match bytes {
+            [0xc3, ..] | [0xcb, ..] => Some(1),
+            [0xc2, _, _, ..] | [0xca, _, _, ..] => Some(3),
+            _ => None,
+        }
+    }
+
+    /// If `bytes` starts with a return instruction, get the length of the return
+    /// (including any delay slot).
+    ///
+    /// Recognizes `bx lr`, `pop {..., pc}` and `ldr pc, [sp], #4` in ARM mode. Thumb
+    /// code is not supported.
+    #[cfg(feature = "arm")]
+    pub fn return_len(bytes: &[u8]) -> Option<usize> {
+        let word = insn_word(bytes)?;
+
+        let is_return =
+            word == 0xe12f_ff1e || (word & 0xffff_8000) == 0xe8bd_8000 || word == 0xe49d_f004;
+
+        is_return.then_some(4)
+    }
+
+    /// If `bytes` starts with a return instruction, get the length of the return
+    /// (including any delay slot).
+    ///
+    /// Recognizes `ret` with any register.
+    #[cfg(feature = "aarch64")]
+    pub fn return_len(bytes: &[u8]) -> Option<usize> {
+        let word = insn_word(bytes)?;
+
+        ((word & 0xffff_fc1f) == 0xd65f_0000).then_some(4)
+    }
+
+    /// If `bytes` starts with a return instruction, get the length of the return
+    /// (including any delay slot).
+    ///
+    /// Recognizes `jr $ra`, which is only considered a return if its delay slot is
+    /// present.
+    #[cfg(any(
+        feature = "mips",
+        feature = "mipsel",
+        feature = "mips64",
+        feature = "mips64el"
+    ))]
+    pub fn return_len(bytes: &[u8]) -> Option<usize> {
+        let word = insn_word(bytes)?;
+
+        (word == 0x03e0_0008 && bytes.len() >= 8).then_some(8)
+    }
+
+    /// If `bytes` starts with a return instruction, get the length of the return
+    /// (including any delay slot).
+    ///
+    /// Recognizes `blr`.
+    #[cfg(feature = "ppc")]
+    pub fn return_len(bytes: &[u8]) -> Option<usize> {
+        let word = insn_word(bytes)?;
+
+        (word == 0x4e80_0020).then_some(4)
+    }
+}
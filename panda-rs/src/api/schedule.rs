@@ -0,0 +1,292 @@
+//! Running callbacks after a number of guest instructions or an amount of guest time
+//!
+//! Rather than counting blocks by hand in a `before_block_exec` callback, analyses
+//! such as sampling profilers and timeouts can ask to be called once after a delay
+//! ([`after_insns`], [`after`]) or periodically ([`every_insns`], [`every`]).
+//! Scheduled callbacks run at the start of the first basic block at or after their
+//! deadline, so may run up to a block late.
+//!
+//! Instructions are counted with the record/replay instruction count while replaying,
+//! and by counting the instructions of each completed block otherwise, starting from
+//! when this module is first used. See [`insn_count`].
+//!
+//! Guest time is QEMU's virtual clock when running live, which follows the instruction
+//! count when icount is enabled (see [`icount_enabled`](crate::time::icount_enabled))
+//! and host time (excluding time the guest is paused) otherwise. The virtual clock of a
+//! replay isn't deterministic, so while replaying guest time is instead derived from
+//! the instruction count at a fixed rate, set with [`set_replay_insns_per_sec`]. See
+//! [`guest_time`].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::schedule;
+//!
+//! use std::time::Duration;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     // sample the pc every million instructions
+//!     schedule::every_insns(1_000_000, |cpu| {
+//!         println!("pc: {:#x}", panda::current_pc(cpu));
+//!     });
+//!
+//!     // give up after 30 seconds of guest time
+//!     schedule::after(Duration::from_secs(30), |_| {
+//!         panda::control::stop();
+//!     });
+//! }
+//! ```
+use crate::prelude::*;
+use crate::rr::{in_replay, rr_get_guest_instr_count};
+use crate::Callback;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The default rate guest time advances at while replaying, one instruction per
+/// nanosecond as with `-icount shift=0`
+pub const DEFAULT_REPLAY_INSNS_PER_SEC: u64 = 1_000_000_000;
+
+/// Identifies a scheduled callback, allowing it to be cancelled with [`cancel`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+enum TaskFn {
+    Once(Box<dyn FnOnce(&mut CPUState) + Send + 'static>),
+    Repeat(Box<dyn FnMut(&mut CPUState) + Send + 'static>),
+}
+
+struct Task {
+    func: TaskFn,
+
+    /// The period of a repeating task, in instructions or nanoseconds
+    period: u64,
+}
+
+#[derive(Clone, Copy)]
+enum Clock {
+    Insns,
+    Time,
+}
+
+struct State {
+    /// Tasks keyed by the instruction count they're due at
+    by_insns: BTreeMap<(u64, TaskId), Task>,
+
+    /// Tasks keyed by the guest time (in nanoseconds) they're due at
+    by_time: BTreeMap<(u64, TaskId), Task>,
+
+    /// The repeating task currently running, and whether it has been cancelled
+    running: Option<(TaskId, bool)>,
+
+    replay_insns_per_sec: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        by_insns: BTreeMap::new(),
+        by_time: BTreeMap::new(),
+        running: None,
+        replay_insns_per_sec: DEFAULT_REPLAY_INSNS_PER_SEC,
+    });
+    static ref CALLBACKS: (Callback, Callback) = install_callbacks();
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Instructions executed live, in blocks which ran to completion
+static LIVE_INSNS: AtomicU64 = AtomicU64::new(0);
+
+fn install_callbacks() -> (Callback, Callback) {
+    let count = Callback::new();
+    let block = Callback::new();
+
+    count.after_block_exec(|_, tb, exit_code| {
+        if !in_replay() && exit_code as u32 <= panda_sys::TB_EXIT_IDX1 {
+            LIVE_INSNS.fetch_add(tb.icount as u64, Ordering::Relaxed);
+        }
+    });
+
+    block.before_block_exec(|cpu, _| run_due(cpu));
+
+    (count, block)
+}
+
+/// Get the number of guest instructions executed, from the record/replay instruction
+/// count while replaying. Otherwise, this is the number of instructions executed since
+/// this module was first used, not counting blocks which were exited early.
+pub fn insn_count() -> u64 {
+    lazy_static::initialize(&CALLBACKS);
+
+    if in_replay() {
+        rr_get_guest_instr_count()
+    } else {
+        LIVE_INSNS.load(Ordering::Relaxed)
+    }
+}
+
+/// Get the current guest time, from the instruction count while replaying or QEMU's
+/// virtual clock otherwise
+pub fn guest_time() -> Duration {
+    Duration::from_nanos(now(Clock::Time))
+}
+
+/// Set how many instructions make up a second of guest time while replaying, defaulting
+/// to [`DEFAULT_REPLAY_INSNS_PER_SEC`]
+pub fn set_replay_insns_per_sec(rate: u64) {
+    assert_ne!(
+        rate, 0,
+        "guest time can't advance at a rate of 0 instructions"
+    );
+
+    STATE.lock().unwrap().replay_insns_per_sec = rate;
+}
+
+fn now(clock: Clock) -> u64 {
+    match clock {
+        Clock::Insns => insn_count(),
+        Clock::Time if in_replay() => {
+            let rate = STATE.lock().unwrap().replay_insns_per_sec;
+
+            (insn_count() as u128 * 1_000_000_000 / rate as u128) as u64
+        }
+        Clock::Time => unsafe {
+            panda_sys::qemu_clock_get_ns(panda_sys::QEMUClockType_QEMU_CLOCK_VIRTUAL) as u64
+        },
+    }
+}
+
+impl State {
+    fn queue(&mut self, clock: Clock) -> &mut BTreeMap<(u64, TaskId), Task> {
+        match clock {
+            Clock::Insns => &mut self.by_insns,
+            Clock::Time => &mut self.by_time,
+        }
+    }
+}
+
+fn schedule(clock: Clock, delay: u64, period: u64, func: TaskFn) -> TaskId {
+    let id = TaskId(NEXT_ID.fetch_add(1, Ordering::SeqCst));
+    let due = now(clock).saturating_add(delay);
+
+    STATE
+        .lock()
+        .unwrap()
+        .queue(clock)
+        .insert((due, id), Task { func, period });
+
+    id
+}
+
+/// Take the first task of a queue if it's due
+fn pop_due(queue: &mut BTreeMap<(u64, TaskId), Task>, now: u64) -> Option<((u64, TaskId), Task)> {
+    let &key = queue.keys().next()?;
+
+    (key.0 <= now).then(|| (key, queue.remove(&key).unwrap()))
+}
+
+fn run_due(cpu: &mut CPUState) {
+    for &clock in &[Clock::Insns, Clock::Time] {
+        if STATE.lock().unwrap().queue(clock).is_empty() {
+            continue;
+        }
+
+        let now = now(clock);
+        loop {
+            // run each task without the lock held, so it can schedule or cancel tasks
+            let due = pop_due(STATE.lock().unwrap().queue(clock), now);
+
+            let ((deadline, id), mut task) = match due {
+                Some(due) => due,
+                None => break,
+            };
+
+            match task.func {
+                TaskFn::Once(func) => func(cpu),
+                TaskFn::Repeat(ref mut func) => {
+                    STATE.lock().unwrap().running = Some((id, false));
+                    func(cpu);
+
+                    let mut state = STATE.lock().unwrap();
+                    if let Some((_, true)) = state.running.take() {
+                        continue;
+                    }
+
+                    // keep to the original cadence, unless it has fallen a whole period
+                    // behind
+                    let mut next = deadline.saturating_add(task.period);
+                    if next <= now {
+                        next = now.saturating_add(task.period);
+                    }
+
+                    state.queue(clock).insert((next, id), task);
+                }
+            }
+        }
+    }
+}
+
+/// Run a callback once at least `n` guest instructions have executed
+pub fn after_insns(n: u64, func: impl FnOnce(&mut CPUState) + Send + 'static) -> TaskId {
+    schedule(Clock::Insns, n, 0, TaskFn::Once(Box::new(func)))
+}
+
+/// Run a callback every `n` guest instructions, first after `n` instructions
+pub fn every_insns(n: u64, func: impl FnMut(&mut CPUState) + Send + 'static) -> TaskId {
+    assert_ne!(n, 0, "tasks can't repeat every 0 instructions");
+
+    schedule(Clock::Insns, n, n, TaskFn::Repeat(Box::new(func)))
+}
+
+/// Run a callback once the given amount of guest time has passed
+pub fn after(delay: Duration, func: impl FnOnce(&mut CPUState) + Send + 'static) -> TaskId {
+    let delay = delay.as_nanos().min(u64::MAX as u128) as u64;
+
+    schedule(Clock::Time, delay, 0, TaskFn::Once(Box::new(func)))
+}
+
+/// Run a callback each time the given amount of guest time has passed
+pub fn every(period: Duration, func: impl FnMut(&mut CPUState) + Send + 'static) -> TaskId {
+    let period = period.as_nanos().min(u64::MAX as u128) as u64;
+    assert_ne!(period, 0, "tasks can't repeat with a period of 0");
+
+    schedule(Clock::Time, period, period, TaskFn::Repeat(Box::new(func)))
+}
+
+/// Cancel a scheduled callback, returning whether it was still scheduled. A repeating
+/// callback may cancel itself while running, in which case it won't be run again.
+pub fn cancel(id: TaskId) -> bool {
+    let mut state = STATE.lock().unwrap();
+
+    if let Some((running, cancelled)) = &mut state.running {
+        if *running == id {
+            *cancelled = true;
+            return true;
+        }
+    }
+
+    for &clock in &[Clock::Insns, Clock::Time] {
+        let queue = state.queue(clock);
+        if let Some(&key) = queue.keys().find(|(_, task_id)| *task_id == id) {
+            queue.remove(&key);
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Check whether a callback is still scheduled to run
+pub fn is_scheduled(id: TaskId) -> bool {
+    let state = STATE.lock().unwrap();
+
+    state
+        .by_insns
+        .keys()
+        .chain(state.by_time.keys())
+        .any(|&(_, task_id)| task_id == id)
+}
@@ -0,0 +1,305 @@
+use crate::enums::MemRWStatus;
+use crate::mem::{
+    virt_to_phys_seg, virtual_memory_read_into, virtual_memory_read_seg, virtual_memory_write_seg,
+};
+use crate::prelude::*;
+use crate::{cpu_arch_state, CPUArchPtr};
+
+use strum_macros::{EnumIter, EnumString, ToString};
+
+/// x86 segment registers, in the order QEMU stores them
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, EnumString, EnumIter, ToString)]
+pub enum SegReg {
+    ES = panda_sys::R_ES as isize,
+    CS = panda_sys::R_CS as isize,
+    SS = panda_sys::R_SS as isize,
+    DS = panda_sys::R_DS as isize,
+    FS = panda_sys::R_FS as isize,
+    GS = panda_sys::R_GS as isize,
+}
+
+/// A segment-qualified (`segment:offset`) guest address. Accepted by the `_seg`
+/// variants of the memory functions, such as
+/// [`virtual_memory_read_seg`](crate::mem::virtual_memory_read_seg).
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::segment::SegAddr;
+/// use panda::prelude::*;
+///
+/// # let cpu: &mut CPUState = todo!();
+/// // The BIOS boot sector load address
+/// let boot_sector = SegAddr::new(0x0000, 0x7c00);
+/// let mbr = boot_sector.read(cpu, 512).unwrap();
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SegAddr {
+    /// The segment selector (in protected mode) or paragraph (in real mode)
+    pub segment: u16,
+
+    /// The offset within the segment
+    pub offset: target_ulong,
+
+    /// The segment register the selector is loaded into, if known. In 64-bit mode only
+    /// FS and GS have a base, so without a register the base is 0.
+    pub reg: Option<SegReg>,
+}
+
+impl SegAddr {
+    pub fn new(segment: u16, offset: target_ulong) -> Self {
+        Self {
+            segment,
+            offset,
+            reg: None,
+        }
+    }
+
+    /// An offset relative to the selector currently loaded into a segment register
+    pub fn from_reg(cpu: &CPUState, reg: SegReg, offset: target_ulong) -> Self {
+        Self {
+            segment: get_selector(cpu, reg),
+            offset,
+            reg: Some(reg),
+        }
+    }
+
+    /// Compute the linear address using real mode rules (`segment * 16 + offset`),
+    /// regardless of the current CPU mode.
+    pub fn real_mode_linear(self) -> target_ulong {
+        ((self.segment as target_ulong) << 4).wrapping_add(self.offset)
+    }
+
+    /// Compute the linear address given the current CPU mode. In real mode and
+    /// virtual-8086 mode the segment is treated as a paragraph. In 64-bit mode the base
+    /// is 0, other than for FS and GS, which use the base from the `IA32_FS_BASE` and
+    /// `IA32_GS_BASE` MSRs. Otherwise the segment is treated as a selector and its base
+    /// is looked up in the GDT/LDT.
+    pub fn linear(self, cpu: &mut CPUState) -> Result<target_ulong, MemRWStatus> {
+        if is_64bit_mode(cpu) {
+            let base = match self.reg {
+                Some(reg @ SegReg::FS) | Some(reg @ SegReg::GS) => get_segment_base(cpu, reg),
+                _ => 0,
+            };
+
+            Ok(base.wrapping_add(self.offset))
+        } else if is_protected_mode(cpu) && !is_vm86_mode(cpu) {
+            let descriptor = read_descriptor(cpu, self.segment)?;
+
+            Ok(descriptor.base.wrapping_add(self.offset))
+        } else {
+            Ok(self.real_mode_linear())
+        }
+    }
+
+    /// Translate to a physical address, returning `None` if no mapping can be found
+    pub fn to_phys(self, cpu: &mut CPUState) -> Option<target_ulong> {
+        virt_to_phys_seg(cpu, self)
+    }
+
+    /// Read `len` bytes of guest memory starting at this address
+    pub fn read(self, cpu: &mut CPUState, len: usize) -> Result<Vec<u8>, MemRWStatus> {
+        virtual_memory_read_seg(cpu, self, len)
+    }
+
+    /// Write to guest memory starting at this address
+    pub fn write(self, cpu: &mut CPUState, data: &[u8]) -> MemRWStatus {
+        virtual_memory_write_seg(cpu, self, data)
+    }
+}
+
+/// A decoded GDT/LDT segment descriptor
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SegmentDescriptor {
+    /// Linear address the segment starts at
+    pub base: target_ulong,
+
+    /// Segment limit in bytes, with granularity already applied
+    pub limit: u32,
+
+    /// The access byte (present, DPL, type, etc)
+    pub access: u8,
+
+    /// The flags nibble (granularity, size, long mode)
+    pub flags: u8,
+}
+
+impl SegmentDescriptor {
+    /// Decode a raw 8-byte descriptor as stored in the GDT/LDT
+    pub fn from_bytes(bytes: [u8; 8]) -> Self {
+        let raw = u64::from_le_bytes(bytes);
+
+        let base = ((raw >> 16) & 0xff_ffff) | (((raw >> 56) & 0xff) << 24);
+        let limit = ((raw & 0xffff) | (((raw >> 48) & 0xf) << 16)) as u32;
+        let access = ((raw >> 40) & 0xff) as u8;
+        let flags = ((raw >> 52) & 0xf) as u8;
+
+        let limit = if flags & 0x8 != 0 {
+            (limit << 12) | 0xfff
+        } else {
+            limit
+        };
+
+        Self {
+            base: base as target_ulong,
+            limit,
+            access,
+            flags,
+        }
+    }
+
+    /// Whether the present bit is set
+    pub fn is_present(&self) -> bool {
+        self.access & 0x80 != 0
+    }
+
+    /// The privilege level of the descriptor
+    pub fn dpl(&self) -> u8 {
+        (self.access >> 5) & 0b11
+    }
+}
+
+/// Whether the guest has protected mode enabled (CR0.PE)
+pub fn is_protected_mode(cpu: &CPUState) -> bool {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe { (*cpu_arch).cr[0] & (panda_sys::CR0_PE_MASK as target_ulong) != 0 }
+}
+
+/// Whether the guest is in virtual-8086 mode (EFLAGS.VM)
+pub fn is_vm86_mode(cpu: &CPUState) -> bool {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe { (*cpu_arch).eflags & (panda_sys::VM_MASK as target_ulong) != 0 }
+}
+
+/// Get the selector currently loaded into a segment register
+pub fn get_selector(cpu: &CPUState, seg: SegReg) -> u16 {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe { (*cpu_arch).segs[seg as usize].selector as u16 }
+}
+
+/// Get the base of a segment register as cached by the CPU
+pub fn get_segment_base(cpu: &CPUState, seg: SegReg) -> target_ulong {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe { (*cpu_arch).segs[seg as usize].base }
+}
+
+/// Compute a linear address from an offset relative to a segment register, using the
+/// base the CPU currently has cached for it.
+pub fn linear_from_seg_reg(cpu: &CPUState, seg: SegReg, offset: target_ulong) -> target_ulong {
+    get_segment_base(cpu, seg).wrapping_add(offset)
+}
+
+/// Read and decode the descriptor for a given selector from the GDT or LDT (depending
+/// on the table indicator bit of the selector).
+pub fn read_descriptor(
+    cpu: &mut CPUState,
+    selector: u16,
+) -> Result<SegmentDescriptor, MemRWStatus> {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    let table = unsafe {
+        if selector & 0x4 != 0 {
+            (*cpu_arch).ldt
+        } else {
+            (*cpu_arch).gdt
+        }
+    };
+
+    let offset = ((selector >> 3) as target_ulong).wrapping_mul(8);
+    if offset.wrapping_add(7) > table.limit as target_ulong {
+        return Err(MemRWStatus::GenericErrorRet);
+    }
+
+    let mut bytes = [0u8; 8];
+    virtual_memory_read_into(cpu, table.base.wrapping_add(offset), &mut bytes)?;
+
+    Ok(SegmentDescriptor::from_bytes(bytes))
+}
+
+/// Get the current privilege level (CPL) of the CPU, where 0 is kernel mode and 3 is
+/// user mode
+pub fn current_privilege_level(cpu: &CPUState) -> u8 {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe { ((*cpu_arch).hflags & panda_sys::HF_CPL_MASK) as u8 }
+}
+
+/// Get the value of the `IA32_EFER` MSR
+pub fn get_efer(cpu: &CPUState) -> u64 {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe { (*cpu_arch).efer }
+}
+
+/// Whether the CPU is in long mode (EFER.LMA)
+pub fn is_long_mode(cpu: &CPUState) -> bool {
+    const EFER_LMA: u64 = 1 << 10;
+
+    get_efer(cpu) & EFER_LMA != 0
+}
+
+/// Whether the CPU is running 64-bit code, that is, it is in long mode and the current
+/// code segment is a 64-bit one rather than a compatibility mode one
+pub fn is_64bit_mode(cpu: &CPUState) -> bool {
+    let cpu_arch = cpu_arch_state!(cpu);
+    let cs64 = unsafe { (*cpu_arch).hflags & panda_sys::HF_CS64_MASK != 0 };
+
+    is_long_mode(cpu) && cs64
+}
+
+/// Get the value of the `IA32_GS_BASE` MSR, the base of the currently active GS segment
+pub fn get_gs_base(cpu: &CPUState) -> target_ulong {
+    get_segment_base(cpu, SegReg::GS)
+}
+
+/// Set the value of the `IA32_GS_BASE` MSR, the base of the currently active GS segment
+pub fn set_gs_base(cpu: &mut CPUState, base: target_ulong) {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe {
+        (*cpu_arch).segs[SegReg::GS as usize].base = base;
+    }
+}
+
+/// Get the value of the `IA32_KERNEL_GS_BASE` MSR, the GS base which will be swapped in
+/// by the next `swapgs`
+#[cfg(feature = "x86_64")]
+pub fn get_kernel_gs_base(cpu: &CPUState) -> target_ulong {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe { (*cpu_arch).kernelgsbase }
+}
+
+/// Set the value of the `IA32_KERNEL_GS_BASE` MSR, the GS base which will be swapped in
+/// by the next `swapgs`
+#[cfg(feature = "x86_64")]
+pub fn set_kernel_gs_base(cpu: &mut CPUState, base: target_ulong) {
+    let cpu_arch = cpu_arch_state!(cpu);
+
+    unsafe {
+        (*cpu_arch).kernelgsbase = base;
+    }
+}
+
+/// Get the GS base used by the kernel, regardless of whether the kernel's GS base is
+/// currently swapped in.
+///
+/// In user mode the kernel's GS base is held in `IA32_KERNEL_GS_BASE`, and the same is
+/// true in kernel mode between kernel entry and the kernel's `swapgs`. In that window
+/// the active GS base still belongs to user space, which is detected by it not being
+/// a kernel (upper half) address.
+#[cfg(feature = "x86_64")]
+pub fn kernel_gs_base(cpu: &CPUState) -> target_ulong {
+    let gs_base = get_gs_base(cpu);
+    let is_kernel_addr = gs_base >> 63 != 0;
+
+    if current_privilege_level(cpu) == 0 && is_kernel_addr {
+        gs_base
+    } else {
+        get_kernel_gs_base(cpu)
+    }
+}
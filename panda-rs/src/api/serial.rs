@@ -0,0 +1,286 @@
+//! Per-port capture of guest serial (UART) traffic
+//!
+//! Guests with several UARTs (common for embedded targets) multiplex all of their
+//! serial traffic through the same PANDA callbacks. This module demultiplexes it,
+//! identifying each device by the address of its FIFO, so callbacks and streams can be
+//! attached to individual ports. Devices are discovered as they are used, so
+//! [`ports`] only lists ports which have sent or received data so far.
+//!
+//! Serial traffic is only reported by PANDA while replaying, as it is derived from the
+//! serial events in the recording.
+//!
+//! Extra UARTs can be attached to the guest in libpanda mode using
+//! [`Panda::serial`](crate::Panda::serial).
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::serial::{self, Direction};
+//!
+//! serial::on_data(|_, port, direction, byte| {
+//!     if direction == Direction::Output {
+//!         println!("uart{} <- {:?}", port.index, byte as char);
+//!     }
+//! });
+//! ```
+use crate::prelude::*;
+use crate::Callback;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// The direction serial data is travelling in
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Data received by the guest
+    Input,
+
+    /// Data sent by the guest
+    Output,
+}
+
+/// A serial device which has been seen by PANDA
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SerialPort {
+    /// The order in which the port was discovered, starting from 0
+    pub index: usize,
+
+    /// The address of the device's FIFO, which uniquely identifies the device
+    pub fifo_addr: target_ptr_t,
+
+    /// The I/O port or MMIO address of the device, once the guest has accessed it
+    pub port_addr: Option<u32>,
+}
+
+type PortCallback = Box<dyn FnMut(&SerialPort) + Send + 'static>;
+type DataCallback = Box<dyn FnMut(&mut CPUState, &SerialPort, Direction, u8) + Send + 'static>;
+
+#[derive(Default)]
+struct Mux {
+    ports: BTreeMap<target_ptr_t, SerialPort>,
+    port_callbacks: Vec<PortCallback>,
+    data_callbacks: Vec<(Option<target_ptr_t>, DataCallback)>,
+}
+
+impl Mux {
+    /// Look up the port with the given FIFO address, adding it if it hasn't been seen
+    /// before. Returns the port and whether it is new.
+    fn port(&mut self, fifo_addr: target_ptr_t, port_addr: Option<u32>) -> (SerialPort, bool) {
+        let index = self.ports.len();
+        let mut is_new = false;
+        let port = self.ports.entry(fifo_addr).or_insert_with(|| {
+            is_new = true;
+            SerialPort {
+                index,
+                fifo_addr,
+                port_addr: None,
+            }
+        });
+
+        if port_addr.is_some() {
+            port.port_addr = port_addr;
+        }
+
+        (*port, is_new)
+    }
+}
+
+/// Run each callback in the list selected by `list`, without the multiplexer locked so
+/// that the callbacks can register further callbacks
+fn run_callbacks<C>(list: impl Fn(&mut Mux) -> &mut Vec<C>, mut run: impl FnMut(&mut C)) {
+    let mut callbacks = std::mem::take(list(&mut MUX.lock().unwrap()));
+    for callback in &mut callbacks {
+        run(callback);
+    }
+
+    let mut mux = MUX.lock().unwrap();
+    let list = list(&mut mux);
+    callbacks.append(list);
+    *list = callbacks;
+}
+
+fn port(fifo_addr: target_ptr_t, port_addr: Option<u32>) -> SerialPort {
+    let (port, is_new) = MUX.lock().unwrap().port(fifo_addr, port_addr);
+    if is_new {
+        run_callbacks(|mux| &mut mux.port_callbacks, |callback| callback(&port));
+    }
+
+    port
+}
+
+fn data(cpu: &mut CPUState, fifo_addr: target_ptr_t, direction: Direction, byte: u8) {
+    let port = port(fifo_addr, None);
+
+    run_callbacks(
+        |mux| &mut mux.data_callbacks,
+        |(filter, callback)| {
+            if filter.map_or(true, |filter| filter == fifo_addr) {
+                callback(cpu, &port, direction, byte);
+            }
+        },
+    );
+}
+
+lazy_static::lazy_static! {
+    static ref MUX: Mutex<Mux> = Mutex::new(Mux::default());
+    static ref CALLBACKS: [Callback; 4] = install_callbacks();
+}
+
+fn install_callbacks() -> [Callback; 4] {
+    let receive = Callback::new();
+    let read = Callback::new();
+    let send = Callback::new();
+    let write = Callback::new();
+
+    // Bytes are reported as they arrive in the RX FIFO and as they leave on the wire,
+    // while FIFO reads and writes by the guest are only used to learn device addresses,
+    // so that each byte is reported once.
+    receive.replay_serial_receive(|cpu, fifo_addr, value| {
+        data(cpu, fifo_addr, Direction::Input, value);
+    });
+
+    send.replay_serial_send(|cpu, fifo_addr, value| {
+        data(cpu, fifo_addr, Direction::Output, value);
+    });
+
+    read.replay_serial_read(|_, fifo_addr, port_addr, _| {
+        port(fifo_addr, Some(port_addr));
+    });
+
+    write.replay_serial_write(|_, fifo_addr, port_addr, _| {
+        port(fifo_addr, Some(port_addr));
+    });
+
+    [receive, read, send, write]
+}
+
+/// Get the serial ports which have been seen so far, in the order they were discovered
+pub fn ports() -> Vec<SerialPort> {
+    let mut ports: Vec<_> = MUX.lock().unwrap().ports.values().copied().collect();
+    ports.sort_by_key(|port| port.index);
+
+    ports
+}
+
+/// Register a callback to be run whenever a new serial port is discovered
+pub fn on_new_port(callback: impl FnMut(&SerialPort) + Send + 'static) {
+    lazy_static::initialize(&CALLBACKS);
+
+    MUX.lock().unwrap().port_callbacks.push(Box::new(callback));
+}
+
+/// Register a callback to be run for each byte of serial data on any port
+pub fn on_data(callback: impl FnMut(&mut CPUState, &SerialPort, Direction, u8) + Send + 'static) {
+    lazy_static::initialize(&CALLBACKS);
+
+    MUX.lock()
+        .unwrap()
+        .data_callbacks
+        .push((None, Box::new(callback)));
+}
+
+/// Register a callback to be run for each byte of serial data on the port with the
+/// given FIFO address
+pub fn on_port_data(
+    fifo_addr: target_ptr_t,
+    mut callback: impl FnMut(&mut CPUState, Direction, u8) + Send + 'static,
+) {
+    lazy_static::initialize(&CALLBACKS);
+
+    MUX.lock().unwrap().data_callbacks.push((
+        Some(fifo_addr),
+        Box::new(move |cpu, _, direction, byte| callback(cpu, direction, byte)),
+    ));
+}
+
+/// A buffered stream of the data travelling in one direction on a serial port
+///
+/// Data is buffered from when the stream is created until it is read. Reading never
+/// blocks: once the buffered data has been consumed, reads return 0 bytes until more
+/// data arrives.
+#[derive(Debug, Clone)]
+pub struct SerialStream(Arc<Mutex<VecDeque<u8>>>);
+
+impl SerialStream {
+    /// Take all of the data buffered so far
+    pub fn take(&self) -> Vec<u8> {
+        self.0.lock().unwrap().drain(..).collect()
+    }
+
+    /// Get the number of bytes currently buffered
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Check whether any data is currently buffered
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
+impl Read for SerialStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.0.lock().unwrap();
+        let len = buf.len().min(buffer.len());
+
+        for (dst, src) in buf.iter_mut().zip(buffer.drain(..len)) {
+            *dst = src;
+        }
+
+        Ok(len)
+    }
+}
+
+/// Create a stream of the data travelling in the given direction on the port with the
+/// given FIFO address
+pub fn stream(fifo_addr: target_ptr_t, direction: Direction) -> SerialStream {
+    let buffer = Arc::new(Mutex::new(VecDeque::new()));
+    let stream = SerialStream(Arc::clone(&buffer));
+
+    on_port_data(fifo_addr, move |_, data_direction, byte| {
+        if data_direction == direction {
+            buffer.lock().unwrap().push_back(byte);
+        }
+    });
+
+    stream
+}
+
+/// A host-side backend for a serial port added with
+/// [`Panda::serial`](crate::Panda::serial), equivalent to QEMU's `-serial` argument
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialBackend {
+    /// A new pseudo-terminal, whose path is printed by QEMU on startup
+    Pty,
+
+    /// The standard input/output of the PANDA process
+    Stdio,
+
+    /// Output is written to the given file, no input is received
+    File(PathBuf),
+
+    /// A TCP server listening on the given `host:port`
+    Tcp(String),
+
+    /// A port which is present in the guest but not connected to anything
+    Null,
+
+    /// Any other QEMU character device specification
+    Other(String),
+}
+
+impl fmt::Display for SerialBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pty => write!(f, "pty"),
+            Self::Stdio => write!(f, "stdio"),
+            Self::File(path) => write!(f, "file:{}", path.display()),
+            Self::Tcp(addr) => write!(f, "tcp:{},server,nowait", addr),
+            Self::Null => write!(f, "null"),
+            Self::Other(spec) => write!(f, "{}", spec),
+        }
+    }
+}
@@ -0,0 +1,503 @@
+//! Inspection of the guest's interrupt and exception tables
+//!
+//! On x86 this decodes the interrupt descriptor table ([`idt`]) and global descriptor
+//! table ([`gdt`]), while on ARM and AArch64 it decodes the exception vector table
+//! located by the vector base address ([`exception_vectors`]). These are useful for
+//! detecting rootkits which hook interrupt handlers, or for following an OS as it
+//! brings up its exception handling.
+//!
+//! Changes to the table can be watched for with [`on_table_change`]. Moving the table
+//! (such as by `lidt` or writing VBAR) is detected before the next block executes,
+//! while modifications of the entries in place are detected by comparing the table
+//! every [`CONTENT_CHECK_INTERVAL`] blocks.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::tables;
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     tables::on_table_change(|_, change| {
+//!         for entry in &change.entries {
+//!             println!("entry {} changed: {:x?} -> {:x?}", entry.index, entry.old, entry.new);
+//!         }
+//!     });
+//! }
+//! ```
+use crate::enums::MemRWStatus;
+use crate::mem::virtual_memory_read;
+use crate::prelude::*;
+use crate::{cpu_arch_state, CPUArchPtr, Callback};
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+use crate::segment::{is_long_mode, is_protected_mode, SegmentDescriptor};
+
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+use crate::{data_endian, enums::Endian};
+
+use std::sync::Mutex;
+
+/// The number of blocks executed between comparisons of the table's entries
+pub const CONTENT_CHECK_INTERVAL: u64 = 0x1000;
+
+/// The kind of an x86 interrupt descriptor
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GateType {
+    /// A task gate, which switches to the task given by the selector
+    Task,
+
+    /// An interrupt gate, which disables interrupts while the handler runs
+    Interrupt,
+
+    /// A trap gate, which leaves interrupts enabled while the handler runs
+    Trap,
+
+    /// A real mode interrupt vector table entry
+    RealMode,
+
+    /// A reserved or invalid gate type
+    Other(u8),
+}
+
+/// A decoded entry of the x86 interrupt descriptor table
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct IdtEntry {
+    /// The interrupt vector the entry handles
+    pub vector: u8,
+
+    /// The offset of the handler within the segment given by `selector`. For real mode
+    /// entries this is the linear address of the handler.
+    pub handler: target_ulong,
+
+    /// The code segment selector of the handler, or the segment of a real mode entry
+    pub selector: u16,
+
+    pub gate_type: GateType,
+
+    /// The privilege level required to raise the interrupt with `int`
+    pub dpl: u8,
+
+    pub present: bool,
+
+    /// The interrupt stack table index to switch stacks to (64-bit only, 0 if unused)
+    pub ist: u8,
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+impl IdtEntry {
+    /// Decode a protected mode gate descriptor, which is 8 bytes outside of long mode
+    /// and 16 bytes in long mode
+    fn from_gate(vector: u8, bytes: &[u8]) -> Self {
+        let mut low = [0u8; 8];
+        low.copy_from_slice(&bytes[..8]);
+        let raw = u64::from_le_bytes(low);
+
+        let mut handler = (raw & 0xffff) | (((raw >> 48) & 0xffff) << 16);
+        if bytes.len() >= 12 {
+            let mut high = [0u8; 4];
+            high.copy_from_slice(&bytes[8..12]);
+            handler |= (u32::from_le_bytes(high) as u64) << 32;
+        }
+
+        let ist = if bytes.len() == 16 {
+            ((raw >> 32) & 0x7) as u8
+        } else {
+            0
+        };
+
+        let attrs = (raw >> 40) as u8;
+        let gate_type = match attrs & 0xf {
+            0x5 => GateType::Task,
+            0x6 | 0xe => GateType::Interrupt,
+            0x7 | 0xf => GateType::Trap,
+            other => GateType::Other(other),
+        };
+
+        Self {
+            vector,
+            handler: handler as target_ulong,
+            selector: (raw >> 16) as u16,
+            gate_type,
+            dpl: (attrs >> 5) & 0b11,
+            present: attrs & 0x80 != 0,
+            ist,
+        }
+    }
+
+    /// Decode a real mode interrupt vector table entry (`offset:segment`)
+    fn from_ivt(vector: u8, bytes: &[u8]) -> Self {
+        let offset = u16::from_le_bytes([bytes[0], bytes[1]]) as target_ulong;
+        let segment = u16::from_le_bytes([bytes[2], bytes[3]]);
+
+        Self {
+            vector,
+            handler: ((segment as target_ulong) << 4).wrapping_add(offset),
+            selector: segment,
+            gate_type: GateType::RealMode,
+            dpl: 0,
+            present: true,
+            ist: 0,
+        }
+    }
+}
+
+/// Get the linear address and limit of the interrupt descriptor table, as loaded in
+/// the IDTR
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub fn idt_location(cpu: &CPUState) -> (target_ulong, u32) {
+    let cpu_arch = cpu_arch_state!(cpu);
+    let idt = unsafe { (*cpu_arch).idt };
+
+    (idt.base, idt.limit)
+}
+
+/// Read and decode the interrupt descriptor table (or the interrupt vector table in
+/// real mode)
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub fn idt(cpu: &mut CPUState) -> Result<Vec<IdtEntry>, MemRWStatus> {
+    let (base, limit) = idt_location(cpu);
+    let protected = is_protected_mode(cpu);
+    let entry_size = match (protected, is_long_mode(cpu)) {
+        (false, _) => 4,
+        (true, false) => 8,
+        (true, true) => 16,
+    };
+
+    let count = ((limit as usize + 1) / entry_size).min(256);
+    let bytes = virtual_memory_read(cpu, base, count * entry_size)?;
+
+    Ok(bytes
+        .chunks_exact(entry_size)
+        .enumerate()
+        .map(|(vector, entry)| {
+            if protected {
+                IdtEntry::from_gate(vector as u8, entry)
+            } else {
+                IdtEntry::from_ivt(vector as u8, entry)
+            }
+        })
+        .collect())
+}
+
+/// Read and decode the global descriptor table. In long mode, system descriptors (such
+/// as the TSS) take up two slots, the second of which is not meaningful when decoded.
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub fn gdt(cpu: &mut CPUState) -> Result<Vec<SegmentDescriptor>, MemRWStatus> {
+    let cpu_arch = cpu_arch_state!(cpu);
+    let gdt = unsafe { (*cpu_arch).gdt };
+
+    let count = (gdt.limit as usize + 1) / 8;
+    let bytes = virtual_memory_read(cpu, gdt.base, count * 8)?;
+
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|descriptor| {
+            let mut raw = [0u8; 8];
+            raw.copy_from_slice(descriptor);
+
+            SegmentDescriptor::from_bytes(raw)
+        })
+        .collect())
+}
+
+/// An entry of the ARM exception vector table
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ExceptionVector {
+    /// The name of the exception, such as `irq` on AArch32 or `lower_a64_sync` on
+    /// AArch64
+    pub name: &'static str,
+
+    /// The address of the vector
+    pub addr: target_ulong,
+
+    /// The first instruction of the vector, typically a branch to the handler
+    pub instruction: u32,
+}
+
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+const AARCH32_VECTORS: &[&str] = &[
+    "reset",
+    "undefined",
+    "svc",
+    "prefetch_abort",
+    "data_abort",
+    "hyp_trap",
+    "irq",
+    "fiq",
+];
+
+#[cfg(feature = "aarch64")]
+const AARCH64_VECTORS: &[&str] = &[
+    "current_sp0_sync",
+    "current_sp0_irq",
+    "current_sp0_fiq",
+    "current_sp0_serror",
+    "current_spx_sync",
+    "current_spx_irq",
+    "current_spx_fiq",
+    "current_spx_serror",
+    "lower_a64_sync",
+    "lower_a64_irq",
+    "lower_a64_fiq",
+    "lower_a64_serror",
+    "lower_a32_sync",
+    "lower_a32_irq",
+    "lower_a32_fiq",
+    "lower_a32_serror",
+];
+
+#[cfg(feature = "aarch64")]
+fn is_aarch64(cpu: &CPUState) -> bool {
+    unsafe { (*cpu_arch_state!(cpu)).aarch64 != 0 }
+}
+
+/// Get the address of the exception vector table exceptions are currently taken to.
+/// On AArch32 this is the high vectors address (`0xffff0000`) if `SCTLR.V` is set, and
+/// `VBAR` otherwise. On AArch64 this is the `VBAR` of the current exception level (or
+/// EL1 when running at EL0).
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+pub fn vector_base(cpu: &CPUState) -> target_ulong {
+    const SCTLR_V: u64 = 1 << 13;
+    const HIGH_VECTORS: target_ulong = 0xffff_0000;
+
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+    let (sctlr_el, vbar_el) = unsafe {
+        (
+            env.cp15.__bindgen_anon_2.sctlr_el,
+            env.cp15.__bindgen_anon_11.vbar_el,
+        )
+    };
+
+    #[cfg(feature = "aarch64")]
+    {
+        if env.aarch64 != 0 {
+            let el = ((env.pstate >> 2) & 3).max(1) as usize;
+
+            return vbar_el[el] as target_ulong;
+        }
+    }
+
+    if sctlr_el[1] & SCTLR_V != 0 {
+        HIGH_VECTORS
+    } else {
+        vbar_el[1] as target_ulong
+    }
+}
+
+/// Read the exception vector table exceptions are currently taken to, see
+/// [`vector_base`]
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+pub fn exception_vectors(cpu: &mut CPUState) -> Result<Vec<ExceptionVector>, MemRWStatus> {
+    let base = vector_base(cpu);
+
+    #[cfg(feature = "aarch64")]
+    let (names, stride) = if is_aarch64(cpu) {
+        (AARCH64_VECTORS, 0x80)
+    } else {
+        (AARCH32_VECTORS, 4)
+    };
+
+    #[cfg(feature = "arm")]
+    let (names, stride) = (AARCH32_VECTORS, 4);
+
+    let bytes = virtual_memory_read(cpu, base, names.len() * stride)?;
+    let endian = data_endian();
+
+    Ok(names
+        .iter()
+        .enumerate()
+        .map(|(i, &name)| {
+            let offset = i * stride;
+            let mut raw = [0u8; 4];
+            raw.copy_from_slice(&bytes[offset..offset + 4]);
+
+            ExceptionVector {
+                name,
+                addr: base + offset as target_ulong,
+                instruction: match endian {
+                    Endian::Big => u32::from_be_bytes(raw),
+                    Endian::Little => u32::from_le_bytes(raw),
+                },
+            }
+        })
+        .collect())
+}
+
+/// An entry of the table being watched by [`on_table_change`]
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub type TableEntry = IdtEntry;
+
+/// An entry of the table being watched by [`on_table_change`]
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+pub type TableEntry = ExceptionVector;
+
+/// An entry which changed between two reads of the table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryChange {
+    pub index: usize,
+    pub old: TableEntry,
+    pub new: TableEntry,
+}
+
+/// A change to the interrupt or exception table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableChange {
+    /// The previous address of the table
+    pub old_base: target_ulong,
+
+    /// The current address of the table, which is the same as `old_base` if the table
+    /// was modified in place
+    pub new_base: target_ulong,
+
+    /// The entries which changed, including entries added or removed by resizing the
+    /// table (which are compared against a default entry)
+    pub entries: Vec<EntryChange>,
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+fn table_base(cpu: &CPUState) -> target_ulong {
+    idt_location(cpu).0
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+fn read_table(cpu: &mut CPUState) -> Result<Vec<TableEntry>, MemRWStatus> {
+    idt(cpu)
+}
+
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+fn table_base(cpu: &CPUState) -> target_ulong {
+    vector_base(cpu)
+}
+
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+fn read_table(cpu: &mut CPUState) -> Result<Vec<TableEntry>, MemRWStatus> {
+    exception_vectors(cpu)
+}
+
+type ChangeCallback = Box<dyn FnMut(&mut CPUState, &TableChange) + Send + 'static>;
+
+#[derive(Default)]
+struct Watcher {
+    base: Option<target_ulong>,
+    entries: Vec<TableEntry>,
+    blocks: u64,
+    callbacks: Vec<ChangeCallback>,
+}
+
+impl Watcher {
+    /// Check whether the table has moved or changed since it was last read
+    fn check(&mut self, cpu: &mut CPUState) -> Option<TableChange> {
+        self.blocks += 1;
+
+        let base = table_base(cpu);
+        let moved = self.base != Some(base);
+        if !moved && self.blocks % CONTENT_CHECK_INTERVAL != 0 {
+            return None;
+        }
+
+        // the table may not be mapped in the current address space
+        let entries = read_table(cpu).ok()?;
+
+        let old_base = match self.base.replace(base) {
+            Some(old_base) => old_base,
+            None => {
+                self.entries = entries;
+                return None;
+            }
+        };
+
+        let len = self.entries.len().max(entries.len());
+        let changed: Vec<_> = (0..len)
+            .filter_map(|index| {
+                let old = self.entries.get(index).copied();
+                let new = entries.get(index).copied();
+
+                if old == new {
+                    return None;
+                }
+
+                Some(EntryChange {
+                    index,
+                    old: old.unwrap_or_else(|| empty_entry(index)),
+                    new: new.unwrap_or_else(|| empty_entry(index)),
+                })
+            })
+            .collect();
+
+        self.entries = entries;
+
+        if changed.is_empty() && !moved {
+            return None;
+        }
+
+        Some(TableChange {
+            old_base,
+            new_base: base,
+            entries: changed,
+        })
+    }
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+fn empty_entry(index: usize) -> TableEntry {
+    IdtEntry {
+        vector: index as u8,
+        handler: 0,
+        selector: 0,
+        gate_type: GateType::Other(0),
+        dpl: 0,
+        present: false,
+        ist: 0,
+    }
+}
+
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+fn empty_entry(_: usize) -> TableEntry {
+    ExceptionVector {
+        name: "",
+        addr: 0,
+        instruction: 0,
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref WATCHER: Mutex<Watcher> = Mutex::new(Watcher::default());
+    static ref CALLBACK: Callback = install_callback();
+}
+
+fn install_callback() -> Callback {
+    let callback = Callback::new();
+
+    callback.before_block_exec(|cpu, _| {
+        let change = match WATCHER.lock().unwrap().check(cpu) {
+            Some(change) => change,
+            None => return,
+        };
+
+        // run the callbacks unlocked so that they can register further callbacks
+        let mut callbacks = std::mem::take(&mut WATCHER.lock().unwrap().callbacks);
+        for callback in &mut callbacks {
+            callback(cpu, &change);
+        }
+
+        let mut watcher = WATCHER.lock().unwrap();
+        callbacks.append(&mut watcher.callbacks);
+        watcher.callbacks = callbacks;
+    });
+
+    callback
+}
+
+/// Register a callback to be run when the interrupt descriptor table (on x86) or
+/// exception vector table (on ARM) is moved or modified. The first read of the table
+/// is used as a baseline and is not reported.
+pub fn on_table_change(callback: impl FnMut(&mut CPUState, &TableChange) + Send + 'static) {
+    WATCHER.lock().unwrap().callbacks.push(Box::new(callback));
+
+    lazy_static::initialize(&CALLBACK);
+}
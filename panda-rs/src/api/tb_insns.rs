@@ -0,0 +1,189 @@
+//! Instruction boundaries of translation blocks
+//!
+//! A [`TranslationBlock`] only records the address range and number of the guest
+//! instructions it contains, so mapping an address in the middle of a block back to the
+//! instruction it belongs to would otherwise mean disassembling the block again. Once
+//! [`enable`] has been called, this module records the address and size of each
+//! instruction as blocks are translated, so they can be looked up from exec-time
+//! callbacks with [`instructions`] and [`instruction_at`], or the
+//! [`BlockExt::instructions`](crate::prelude::BlockExt::instructions) method.
+//!
+//! Boundaries are only known for blocks translated after tracking is enabled, so
+//! enabling it flushes the translation cache.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::tb_insns;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     tb_insns::enable();
+//! }
+//!
+//! #[panda::before_block_exec]
+//! fn every_block(_: &mut CPUState, tb: &mut TranslationBlock) {
+//!     if let Some(insns) = tb.instructions() {
+//!         for insn in insns.iter() {
+//!             println!("{:#x} ({} bytes)", insn.pc, insn.size);
+//!         }
+//!     }
+//! }
+//! ```
+use crate::prelude::*;
+use crate::tb_invalidation::{self, flush_tb, TbInvalidation};
+use crate::Callback;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A guest instruction within a translation block
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TbInsn {
+    /// The address of the instruction
+    pub pc: target_ulong,
+
+    /// The size of the instruction in bytes
+    pub size: usize,
+}
+
+impl TbInsn {
+    /// The address just past the end of the instruction
+    pub fn end(&self) -> target_ulong {
+        self.pc + self.size as target_ulong
+    }
+
+    /// Check whether `addr` is within the instruction
+    pub fn contains(&self, addr: target_ulong) -> bool {
+        (self.pc..self.end()).contains(&addr)
+    }
+}
+
+struct Block {
+    pc: target_ulong,
+    size: u16,
+    insns: Arc<[TbInsn]>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Instruction addresses of the block currently being translated
+    pending: Vec<target_ulong>,
+
+    /// Translated blocks, keyed by the address of their `TranslationBlock`
+    blocks: HashMap<usize, Block>,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+    static ref CALLBACKS: [Callback; 3] = install_callbacks();
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn install_callbacks() -> [Callback; 3] {
+    let before_translate = Callback::new();
+    let insn_translate = Callback::new();
+    let after_translate = Callback::new();
+
+    before_translate.before_block_translate(|_, _| {
+        STATE.lock().unwrap().pending.clear();
+    });
+
+    insn_translate.insn_translate(|_, pc| {
+        STATE.lock().unwrap().pending.push(pc);
+
+        false
+    });
+
+    after_translate.after_block_translate(|_, tb| {
+        let mut state = STATE.lock().unwrap();
+        let end = tb.pc + tb.size as target_ulong;
+
+        let insns: Arc<[TbInsn]> = state
+            .pending
+            .iter()
+            .enumerate()
+            .map(|(i, &pc)| {
+                let next = state.pending.get(i + 1).copied().unwrap_or(end);
+
+                TbInsn {
+                    pc,
+                    size: next.wrapping_sub(pc) as usize,
+                }
+            })
+            .collect();
+
+        state.pending.clear();
+        state.blocks.insert(
+            tb as *mut TranslationBlock as usize,
+            Block {
+                pc: tb.pc,
+                size: tb.size,
+                insns,
+            },
+        );
+    });
+
+    // freed blocks can be reused at the same address, so forget them on a flush
+    tb_invalidation::on_invalidate(|event| {
+        if let TbInvalidation::Flush { .. } = event {
+            STATE.lock().unwrap().blocks.clear();
+        }
+    });
+
+    [before_translate, insn_translate, after_translate]
+}
+
+/// Start recording the instruction boundaries of translated blocks, flushing the
+/// translation cache so that every block is retranslated
+pub fn enable() {
+    if !ENABLED.swap(true, Ordering::SeqCst) {
+        CALLBACKS.iter().for_each(Callback::enable);
+        flush_tb();
+    }
+}
+
+/// Stop recording instruction boundaries and forget those recorded so far
+pub fn disable() {
+    if ENABLED.swap(false, Ordering::SeqCst) {
+        CALLBACKS.iter().for_each(Callback::disable);
+
+        let mut state = STATE.lock().unwrap();
+        state.pending.clear();
+        state.blocks.clear();
+    }
+}
+
+/// Whether instruction boundaries are being recorded
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Get the instructions of a translation block in order, or `None` if the block was
+/// translated before [`enable`] was called
+pub fn instructions(tb: &TranslationBlock) -> Option<Arc<[TbInsn]>> {
+    let state = STATE.lock().unwrap();
+    let block = state
+        .blocks
+        .get(&(tb as *const TranslationBlock as usize))?;
+
+    // guard against a stale entry for a block freed without a flush
+    if block.pc == tb.pc && block.size == tb.size {
+        Some(Arc::clone(&block.insns))
+    } else {
+        None
+    }
+}
+
+/// Get the instruction of a translation block which contains `addr`
+pub fn instruction_at(tb: &TranslationBlock, addr: target_ulong) -> Option<TbInsn> {
+    let insns = instructions(tb)?;
+    let index = insns
+        .partition_point(|insn| insn.pc <= addr)
+        .checked_sub(1)?;
+
+    Some(insns[index]).filter(|insn| insn.contains(addr))
+}
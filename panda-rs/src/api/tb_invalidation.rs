@@ -0,0 +1,204 @@
+//! Notifications for when translated code is invalidated
+//!
+//! PANDA has no callback for translation block invalidation, so this module derives
+//! invalidation events from QEMU's translation cache statistics and from blocks being
+//! retranslated. This allows analyses which cache data per translation block (coverage,
+//! hooks, etc.) to drop stale entries.
+//!
+//! Events are delivered lazily: a flush is reported right before the next block is
+//! translated, and an invalidated range is reported when the code it covered is
+//! translated again.
+//!
+//! ## Limitations
+//!
+//! Invalidation is inferred rather than observed, so the events are a heuristic:
+//!
+//! * Invalidated code which is never executed again is never reported.
+//! * Any retranslation of a block is reported as an invalidation, including ones QEMU
+//! performs without the old block being invalidated, such as retranslating a block to
+//! restore the CPU state on an exception.
+//! * Blocks are matched by address space and start address, so an invalidated block
+//! whose code is retranslated starting at a different address is missed.
+//! * Several flushes between two translations are reported as a single flush, and a
+//! flush performed by QEMU right after [`flush_tb`] is called or a snapshot is loaded
+//! is given that reason.
+//! * At most [`MAX_TRACKED_BLOCKS`] blocks are tracked between flushes. Once that many
+//! have been translated the tracked blocks are forgotten, so retranslations of them
+//! are missed.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::tb_invalidation::{self, TbInvalidation};
+//!
+//! tb_invalidation::on_invalidate(|event| match event {
+//!     TbInvalidation::Flush { reason } => println!("Translation cache flushed ({:?})", reason),
+//!     TbInvalidation::Range { start, end, .. } => {
+//!         println!("Code at {:#x}..{:#x} was invalidated", start, end)
+//!     }
+//! });
+//! ```
+use crate::prelude::*;
+use crate::{current_asid, Callback};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// The cause of a full translation cache flush
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FlushReason {
+    /// The flush was requested via [`flush_tb`]
+    Requested,
+
+    /// A snapshot was loaded
+    LoadVm,
+
+    /// The flush was performed by QEMU or another plugin (e.g. the code buffer filled up)
+    Other,
+}
+
+/// An invalidation of translated code
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TbInvalidation {
+    /// Every translation block was discarded
+    Flush { reason: FlushReason },
+
+    /// The translation block covering guest code `start..end` in the address space
+    /// `asid` was discarded (for example, due to the guest writing to the code) and
+    /// has now been retranslated.
+    Range {
+        start: target_ulong,
+        end: target_ulong,
+        asid: target_ulong,
+    },
+}
+
+/// The maximum number of translated blocks remembered between flushes
+pub const MAX_TRACKED_BLOCKS: usize = 1 << 20;
+
+type InvalidationCallback = Box<dyn FnMut(&TbInvalidation) + Send + 'static>;
+
+#[derive(Default)]
+struct Tracker {
+    callbacks: Vec<InvalidationCallback>,
+    translated: HashMap<(target_ulong, target_ulong), target_ulong>,
+    flush_count: u32,
+    pending_reason: Option<FlushReason>,
+}
+
+impl Tracker {
+    /// Check whether the translation cache has been flushed since the last check
+    fn check_flush(&mut self) -> Option<TbInvalidation> {
+        let flush_count = flush_count();
+        if flush_count == self.flush_count {
+            return None;
+        }
+
+        self.flush_count = flush_count;
+        self.translated.clear();
+
+        let reason = self.pending_reason.take().unwrap_or(FlushReason::Other);
+        Some(TbInvalidation::Flush { reason })
+    }
+
+    /// Record a block being translated, returning the invalidation of the block it
+    /// replaces, if any
+    fn translated(
+        &mut self,
+        asid: target_ulong,
+        start: target_ulong,
+        end: target_ulong,
+    ) -> Option<TbInvalidation> {
+        if self.translated.len() >= MAX_TRACKED_BLOCKS
+            && !self.translated.contains_key(&(asid, start))
+        {
+            self.translated.clear();
+        }
+
+        let old_end = self.translated.insert((asid, start), end)?;
+        Some(TbInvalidation::Range {
+            start,
+            end: old_end,
+            asid,
+        })
+    }
+}
+
+/// Run the invalidation callbacks without the tracker locked, so that they can register
+/// further callbacks or request flushes
+fn emit(event: TbInvalidation) {
+    let mut callbacks = std::mem::take(&mut TRACKER.lock().unwrap().callbacks);
+    for callback in &mut callbacks {
+        callback(&event);
+    }
+
+    let mut tracker = TRACKER.lock().unwrap();
+    callbacks.append(&mut tracker.callbacks);
+    tracker.callbacks = callbacks;
+}
+
+lazy_static::lazy_static! {
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Tracker {
+        flush_count: flush_count(),
+        ..Default::default()
+    });
+
+    static ref CALLBACKS: [Callback; 3] = install_callbacks();
+}
+
+fn install_callbacks() -> [Callback; 3] {
+    let before_translate = Callback::new();
+    let after_translate = Callback::new();
+    let after_loadvm = Callback::new();
+
+    before_translate.before_block_translate(|_, _| {
+        let flush = TRACKER.lock().unwrap().check_flush();
+        if let Some(event) = flush {
+            emit(event);
+        }
+    });
+
+    after_translate.after_block_translate(|cpu, tb| {
+        let asid = current_asid(cpu);
+        let start = tb.pc;
+        let end = tb.pc.wrapping_add(tb.size as target_ulong);
+
+        let invalidation = TRACKER.lock().unwrap().translated(asid, start, end);
+        if let Some(event) = invalidation {
+            emit(event);
+        }
+    });
+
+    after_loadvm.after_loadvm(|_| {
+        TRACKER.lock().unwrap().pending_reason = Some(FlushReason::LoadVm);
+    });
+
+    [before_translate, after_translate, after_loadvm]
+}
+
+/// Register a callback to be run whenever translated code is invalidated.
+pub fn on_invalidate(callback: impl FnMut(&TbInvalidation) + Send + 'static) {
+    lazy_static::initialize(&CALLBACKS);
+
+    TRACKER.lock().unwrap().callbacks.push(Box::new(callback));
+}
+
+/// Request that PANDA flush the translation cache. The flush will be reported to
+/// [`on_invalidate`] callbacks with [`FlushReason::Requested`].
+pub fn flush_tb() {
+    TRACKER.lock().unwrap().pending_reason = Some(FlushReason::Requested);
+
+    unsafe {
+        panda_sys::panda_do_flush_tb();
+    }
+}
+
+/// Get the number of times QEMU has flushed the translation cache
+pub fn flush_count() -> u32 {
+    unsafe { panda_sys::tcg_ctx.tb_ctx.tb_flush_count }
+}
+
+/// Get the number of translation blocks QEMU has invalidated due to writes to guest code
+pub fn invalidate_count() -> i32 {
+    unsafe { panda_sys::tcg_ctx.tb_ctx.tb_phys_invalidate_count }
+}
@@ -0,0 +1,368 @@
+//! Control over the guest's perception of time
+//!
+//! The guest's virtual clock (which drives guest timers and the TSC) can be frozen,
+//! and on x86 the real time clock (RTC) can be read, set and moved forward, allowing
+//! time-triggered guest behavior to be explored deterministically. QEMU has no way to
+//! move the virtual clock forward, so only the RTC can be advanced.
+//!
+//! Whether the virtual clock is driven by the instruction counter (QEMU's `-icount`,
+//! configured with [`Panda::icount`](crate::Panda::icount) in libpanda mode) can be
+//! checked with [`icount_enabled`].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::time;
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! // Pretend it's the year 2038
+//! time::set_rtc(UNIX_EPOCH + Duration::from_secs(0x8000_0000)).unwrap();
+//!
+//! // Stop guest timers from firing while `_guard` is alive
+//! let _guard = time::freeze();
+//! ```
+use crate::runtime::RuntimeGuard;
+use crate::sys::{
+    self, cpu_disable_ticks, cpu_enable_ticks, qemu_mutex_iothread_locked,
+    qemu_mutex_lock_iothread, qemu_mutex_unlock_iothread,
+};
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static FROZEN: AtomicBool = AtomicBool::new(false);
+
+/// Run a function with the QEMU global lock held, as is required for touching clocks
+/// or devices from a callback.
+fn with_iothread_lock<T>(func: impl FnOnce() -> T) -> T {
+    if unsafe { qemu_mutex_iothread_locked() } {
+        func()
+    } else {
+        unsafe { qemu_mutex_lock_iothread() };
+        let ret = func();
+        unsafe { qemu_mutex_unlock_iothread() };
+
+        ret
+    }
+}
+
+fn set_frozen(frozen: bool) {
+    if FROZEN.swap(frozen, Ordering::SeqCst) != frozen {
+        with_iothread_lock(|| unsafe {
+            if frozen {
+                cpu_disable_ticks();
+            } else {
+                cpu_enable_ticks();
+            }
+        });
+    }
+}
+
+/// Check whether the guest's virtual clock is currently frozen by [`freeze`]
+pub fn is_frozen() -> bool {
+    FROZEN.load(Ordering::SeqCst)
+}
+
+/// Freeze the guest's virtual clock, stopping guest timers and the TSC from advancing
+/// while the guest continues to execute. The clock resumes from where it was frozen
+/// once the guard is dropped.
+///
+/// The RTC is driven by the host clock unless PANDA is run with `-rtc clock=vm`, so it
+/// will only be frozen in that configuration.
+pub fn freeze() -> RuntimeGuard {
+    let previous = is_frozen();
+    set_frozen(true);
+
+    RuntimeGuard::new(move || set_frozen(previous))
+}
+
+/// How far the virtual clock advances per instruction executed in icount mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IcountShift {
+    /// Each instruction advances the virtual clock by 2<sup>N</sup> ns
+    Fixed(u8),
+
+    /// The shift is adjusted at runtime to keep the virtual clock close to real time.
+    /// Not deterministic.
+    Auto,
+}
+
+impl From<u8> for IcountShift {
+    fn from(shift: u8) -> Self {
+        Self::Fixed(shift)
+    }
+}
+
+/// The largest fixed shift QEMU accepts
+pub const MAX_ICOUNT_SHIFT: u8 = 10;
+
+/// Configuration for icount mode, where the guest's virtual clock is derived from the
+/// number of instructions executed rather than the host clock. Equivalent to QEMU's
+/// `-icount` argument.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IcountConfig {
+    pub shift: IcountShift,
+
+    /// Whether the virtual CPU sleeps when idle until the next timer deadline in real
+    /// time, rather than skipping ahead to it. Disabling sleep makes the virtual clock
+    /// independent of the host.
+    pub sleep: bool,
+
+    /// Whether to delay execution to keep the virtual clock from running ahead of real
+    /// time. Requires `sleep` and a fixed shift.
+    pub align: bool,
+}
+
+impl IcountConfig {
+    /// Create a configuration with the given shift and sleep mode, without alignment
+    pub fn new(shift: impl Into<IcountShift>, sleep: bool) -> Self {
+        Self {
+            shift: shift.into(),
+            sleep,
+            align: false,
+        }
+    }
+}
+
+impl fmt::Display for IcountConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let on_off = |enabled| if enabled { "on" } else { "off" };
+
+        match self.shift {
+            IcountShift::Fixed(shift) => write!(f, "shift={}", shift)?,
+            IcountShift::Auto => write!(f, "shift=auto")?,
+        }
+
+        write!(
+            f,
+            ",sleep={},align={}",
+            on_off(self.sleep),
+            on_off(self.align)
+        )
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref ICOUNT_CONFIG: Mutex<Option<IcountConfig>> = Mutex::new(None);
+}
+
+#[cfg(feature = "libpanda")]
+pub(crate) fn set_icount_config(config: Option<IcountConfig>) {
+    *ICOUNT_CONFIG.lock().unwrap() = config;
+}
+
+/// Check whether the guest's virtual clock is driven by the instruction counter
+pub fn icount_enabled() -> bool {
+    unsafe { sys::use_icount != 0 }
+}
+
+/// Check whether icount mode is enabled with an adaptive shift, in which case the
+/// virtual clock depends on the host and is not deterministic
+pub fn icount_adaptive() -> bool {
+    unsafe { sys::use_icount == 2 }
+}
+
+/// Get the icount configuration the running instance was started with. Returns `None`
+/// if icount mode is disabled or PANDA wasn't started with
+/// [`Panda::run`](crate::Panda::run).
+pub fn icount_config() -> Option<IcountConfig> {
+    *ICOUNT_CONFIG.lock().unwrap()
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub use rtc::*;
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+mod rtc {
+    use super::*;
+    use crate::sys::{cpu_inb, cpu_outb};
+
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// An error in changing the guest's RTC
+    #[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum RtcError {
+        #[error("the RTC can't be changed while replaying, as the replay would diverge")]
+        Replaying,
+    }
+
+    const CMOS_INDEX_PORT: u32 = 0x70;
+    const CMOS_DATA_PORT: u32 = 0x71;
+
+    const RTC_SECONDS: u8 = 0x00;
+    const RTC_MINUTES: u8 = 0x02;
+    const RTC_HOURS: u8 = 0x04;
+    const RTC_DAY_OF_WEEK: u8 = 0x06;
+    const RTC_DAY_OF_MONTH: u8 = 0x07;
+    const RTC_MONTH: u8 = 0x08;
+    const RTC_YEAR: u8 = 0x09;
+    const RTC_REG_B: u8 = 0x0b;
+    const RTC_CENTURY: u8 = 0x32;
+
+    const REG_B_SET: u8 = 0x80;
+    const REG_B_DM: u8 = 0x04;
+    const REG_B_24H: u8 = 0x02;
+    const HOURS_PM: u8 = 0x80;
+
+    fn cmos_read(reg: u8) -> u8 {
+        unsafe {
+            cpu_outb(CMOS_INDEX_PORT, reg);
+            cpu_inb(CMOS_DATA_PORT)
+        }
+    }
+
+    fn cmos_write(reg: u8, val: u8) {
+        unsafe {
+            cpu_outb(CMOS_INDEX_PORT, reg);
+            cpu_outb(CMOS_DATA_PORT, val);
+        }
+    }
+
+    /// Days since the unix epoch to (year, month, day)
+    fn civil_from_days(days: i64) -> (i64, u32, u32) {
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        (year, month, day)
+    }
+
+    /// (year, month, day) to days since the unix epoch
+    fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+        let year = year - (month <= 2) as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month = month as i64;
+        let day_of_year =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        era * 146097 + day_of_era - 719468
+    }
+
+    /// Read the current time from the guest's RTC.
+    ///
+    /// The RTC only has a resolution of one second.
+    pub fn rtc() -> SystemTime {
+        with_iothread_lock(|| {
+            let reg_b = cmos_read(RTC_REG_B);
+            let decode = |val: u8| {
+                if reg_b & REG_B_DM != 0 {
+                    val as i64
+                } else {
+                    ((val >> 4) * 10 + (val & 0xf)) as i64
+                }
+            };
+
+            let seconds = decode(cmos_read(RTC_SECONDS));
+            let minutes = decode(cmos_read(RTC_MINUTES));
+            let raw_hours = cmos_read(RTC_HOURS);
+            let day = decode(cmos_read(RTC_DAY_OF_MONTH)) as u32;
+            let month = decode(cmos_read(RTC_MONTH)) as u32;
+            let year = decode(cmos_read(RTC_CENTURY)) * 100 + decode(cmos_read(RTC_YEAR));
+
+            let hours = if reg_b & REG_B_24H != 0 {
+                decode(raw_hours)
+            } else {
+                // 12 hour mode, where 12 AM is midnight and 12 PM is noon
+                (decode(raw_hours & !HOURS_PM) % 12)
+                    + if raw_hours & HOURS_PM != 0 { 12 } else { 0 }
+            };
+
+            let days = days_from_civil(year, month, day);
+            let secs = (days * 86400) + (hours * 3600) + (minutes * 60) + seconds;
+
+            if secs >= 0 {
+                UNIX_EPOCH + Duration::from_secs(secs as u64)
+            } else {
+                UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+            }
+        })
+    }
+
+    /// Set the guest's RTC to a given time, rounded down to the second.
+    ///
+    /// Guest operating systems typically only read the RTC at boot, so this will
+    /// usually only affect the guest's wall clock if set before the guest reads it (or
+    /// if the guest is asked to resync, e.g. with `hwclock --hctosys`).
+    ///
+    /// **Note:** this is performed via the CMOS I/O ports, so it clobbers the CMOS index
+    /// register. If the guest is in the middle of a CMOS access the access may fail.
+    ///
+    /// Fails while replaying, where the guest's reads of the RTC come from the recording.
+    pub fn set_rtc(time: SystemTime) -> Result<(), RtcError> {
+        if crate::rr::in_replay() {
+            return Err(RtcError::Replaying);
+        }
+
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        };
+
+        let days = secs.div_euclid(86400);
+        let secs_of_day = secs.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        let day_of_week = (days + 4).rem_euclid(7) + 1;
+
+        let hours = (secs_of_day / 3600) as u8;
+        let minutes = ((secs_of_day / 60) % 60) as u8;
+        let seconds = (secs_of_day % 60) as u8;
+
+        with_iothread_lock(|| {
+            let reg_b = cmos_read(RTC_REG_B);
+            let encode = |val: u8| {
+                if reg_b & REG_B_DM != 0 {
+                    val
+                } else {
+                    ((val / 10) << 4) | (val % 10)
+                }
+            };
+
+            let hours = if reg_b & REG_B_24H != 0 {
+                encode(hours)
+            } else {
+                let pm = if hours >= 12 { HOURS_PM } else { 0 };
+                let hours = match hours % 12 {
+                    0 => 12,
+                    hours => hours,
+                };
+
+                encode(hours) | pm
+            };
+
+            // Halt updates while the time is being written
+            cmos_write(RTC_REG_B, reg_b | REG_B_SET);
+
+            cmos_write(RTC_SECONDS, encode(seconds));
+            cmos_write(RTC_MINUTES, encode(minutes));
+            cmos_write(RTC_HOURS, hours);
+            cmos_write(RTC_DAY_OF_WEEK, encode(day_of_week as u8));
+            cmos_write(RTC_DAY_OF_MONTH, encode(day as u8));
+            cmos_write(RTC_MONTH, encode(month as u8));
+            cmos_write(RTC_YEAR, encode(year.rem_euclid(100) as u8));
+            cmos_write(RTC_CENTURY, encode(year.div_euclid(100) as u8));
+
+            cmos_write(RTC_REG_B, reg_b & !REG_B_SET);
+        });
+
+        Ok(())
+    }
+
+    /// Move the guest's RTC forward by a given number of milliseconds (at a resolution
+    /// of one second). Fails while replaying, see [`set_rtc`].
+    ///
+    /// Only the RTC is moved: the virtual clock driving guest timers and the TSC can be
+    /// frozen with [`freeze`], but not moved forward.
+    pub fn advance_rtc(ms: u64) -> Result<(), RtcError> {
+        set_rtc(rtc() + Duration::from_millis(ms))
+    }
+}
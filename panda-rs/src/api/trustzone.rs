@@ -0,0 +1,320 @@
+//! TrustZone secure monitor calls and world switches on ARM
+//!
+//! Firmware on TrustZone-capable ARM systems splits execution between the normal
+//! (non-secure) world and the secure world, which runs the trusted execution
+//! environment (TEE). The normal world requests services from the secure world using
+//! the `SMC` instruction, which traps to the secure monitor at EL3 (or Monitor mode on
+//! AArch32), and the monitor switches worlds by changing the `SCR_EL3.NS` bit before
+//! returning.
+//!
+//! This module reports both halves of these interactions where QEMU models them:
+//!
+//! * [`on_smc`] callbacks are run for each `SMC` taken, with the function ID and
+//!   arguments decoded using the SMC Calling Convention
+//! * [`on_world_switch`] callbacks are run the first time a basic block executes in a
+//!   different security state than the previous one
+//!
+//! Both are passed a [`RegSnapshot`] of the general purpose registers at the time.
+//!
+//! Secure state is only modeled by QEMU for machines with EL3 enabled (for example
+//! `-machine virt,secure=on`). On other machines the CPU is always in the non-secure
+//! world, and `SMC`s are either handled by QEMU itself (such as for PSCI, in which case
+//! they are still reported by [`on_smc`]) or are undefined instructions.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::trustzone::{SmcCall, WorldSwitch};
+//!
+//! #[panda::on_smc]
+//! fn on_smc(_: &mut CPUState, call: &SmcCall) {
+//!     println!(
+//!         "SMC {:#x} (owner {}) from {:?} world, args {:x?}",
+//!         call.function_id,
+//!         call.owner(),
+//!         call.world,
+//!         call.args
+//!     );
+//! }
+//!
+//! #[panda::on_world_switch]
+//! fn on_world_switch(_: &mut CPUState, switch: &WorldSwitch) {
+//!     println!("{:?} -> {:?} at {:#x}", switch.from, switch.to, switch.regs.pc);
+//! }
+//! ```
+use crate::prelude::*;
+use crate::{cpu_arch_state, CPUArchPtr, Callback};
+
+use std::sync::Mutex;
+
+/// `SCR_EL3.NS`, set while EL2 and below are non-secure
+const SCR_NS: u64 = 1 << 0;
+
+/// The AArch32 Monitor mode, which is always secure
+const ARM_CPU_MODE_MON: u32 = 0x16;
+
+/// The security state the CPU is executing in
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum World {
+    /// The normal world, running the rich OS
+    NonSecure,
+
+    /// The secure world, running the secure monitor and TEE
+    Secure,
+}
+
+/// The general purpose registers and processor state at a point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegSnapshot {
+    pub pc: target_ulong,
+
+    /// `x0`-`x30` followed by `sp` in AArch64 state, or `r0`-`r15` (zero-extended) in
+    /// AArch32 state
+    pub regs: Vec<u64>,
+
+    /// `PSTATE` in AArch64 state, or the `CPSR` in AArch32 state
+    pub cpsr: u32,
+
+    /// The current exception level
+    pub el: u8,
+
+    /// Whether the CPU is in AArch64 state
+    pub aarch64: bool,
+}
+
+impl RegSnapshot {
+    /// Take a snapshot of the current registers
+    pub fn capture(cpu: &CPUState) -> Self {
+        let env = unsafe { &*cpu_arch_state!(cpu) };
+
+        #[cfg(feature = "aarch64")]
+        {
+            if env.aarch64 != 0 {
+                return Self {
+                    pc: env.pc as target_ulong,
+                    regs: env.xregs.to_vec(),
+                    cpsr: env.pstate,
+                    el: ((env.pstate >> 2) & 3) as u8,
+                    aarch64: true,
+                };
+            }
+        }
+
+        Self {
+            pc: env.regs[15] as target_ulong,
+            regs: env.regs.iter().map(|&reg| reg as u64).collect(),
+            cpsr: env.uncached_cpsr,
+            el: aarch32_el(env.uncached_cpsr),
+            aarch64: false,
+        }
+    }
+}
+
+/// The exception level of an AArch32 mode, assuming EL3 is AArch32 when present
+fn aarch32_el(cpsr: u32) -> u8 {
+    match cpsr & 0x1f {
+        // User
+        0x10 => 0,
+        // Hyp
+        0x1a => 2,
+        ARM_CPU_MODE_MON => 3,
+        _ => 1,
+    }
+}
+
+/// A secure monitor call (`SMC`) taken by the CPU, decoded following the SMC Calling
+/// Convention
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmcCall {
+    /// The function identifier, passed in `w0`/`r0`
+    pub function_id: u32,
+
+    /// The arguments, passed in `x1`-`x6`/`r1`-`r6`
+    pub args: [u64; 6],
+
+    /// The world the call was made from
+    pub world: World,
+
+    /// The registers at the time of the call. The program counter is the address
+    /// execution returns to once the call completes.
+    pub regs: RegSnapshot,
+}
+
+impl SmcCall {
+    /// Whether this is a fast call, which runs atomically in the secure world, rather
+    /// than a yielding call
+    pub fn is_fast(&self) -> bool {
+        self.function_id & (1 << 31) != 0
+    }
+
+    /// Whether the call uses the 64-bit calling convention
+    pub fn is_smc64(&self) -> bool {
+        self.function_id & (1 << 30) != 0
+    }
+
+    /// The service the call is for, such as 4 for standard secure services like PSCI or
+    /// 50-63 for trusted OS calls
+    pub fn owner(&self) -> u8 {
+        ((self.function_id >> 24) & 0x3f) as u8
+    }
+
+    /// The function number within the owning service
+    pub fn function_number(&self) -> u16 {
+        self.function_id as u16
+    }
+}
+
+/// A change in the security state the CPU is executing in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorldSwitch {
+    pub from: World,
+    pub to: World,
+
+    /// The registers at the start of the first block executed in the new world
+    pub regs: RegSnapshot,
+}
+
+/// Check whether the CPU implements EL3, without which it is always in the non-secure
+/// world
+pub fn has_el3(cpu: &CPUState) -> bool {
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    env.features & (1 << panda_sys::arm_features_ARM_FEATURE_EL3) != 0
+}
+
+/// Get the security state the CPU is currently executing in, following QEMU's
+/// `arm_is_secure`
+pub fn current_world(cpu: &CPUState) -> World {
+    if !has_el3(cpu) {
+        return World::NonSecure;
+    }
+
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    #[cfg(feature = "aarch64")]
+    let in_monitor = if env.aarch64 != 0 {
+        (env.pstate >> 2) & 3 == 3
+    } else {
+        env.uncached_cpsr & 0x1f == ARM_CPU_MODE_MON
+    };
+
+    #[cfg(feature = "arm")]
+    let in_monitor = env.uncached_cpsr & 0x1f == ARM_CPU_MODE_MON;
+
+    if in_monitor || env.cp15.scr_el3 & SCR_NS == 0 {
+        World::Secure
+    } else {
+        World::NonSecure
+    }
+}
+
+/// Check whether the CPU is currently executing in the secure world
+pub fn is_secure(cpu: &CPUState) -> bool {
+    current_world(cpu) == World::Secure
+}
+
+type SmcCallback = Box<dyn FnMut(&mut CPUState, &SmcCall) + Send + 'static>;
+type WorldSwitchCallback = Box<dyn FnMut(&mut CPUState, &WorldSwitch) + Send + 'static>;
+
+#[derive(Default)]
+struct State {
+    smc_callbacks: Vec<SmcCallback>,
+    switch_callbacks: Vec<WorldSwitchCallback>,
+
+    /// The world the last basic block executed in
+    last_world: Option<World>,
+}
+
+/// Run each callback in the list selected by `list`, without the state locked so that
+/// the callbacks can register further callbacks
+fn run_callbacks<C>(list: impl Fn(&mut State) -> &mut Vec<C>, mut run: impl FnMut(&mut C)) {
+    let mut callbacks = std::mem::take(list(&mut STATE.lock().unwrap()));
+    for callback in &mut callbacks {
+        run(callback);
+    }
+
+    let mut state = STATE.lock().unwrap();
+    let list = list(&mut state);
+    callbacks.append(list);
+    *list = callbacks;
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+    static ref SMC_CALLBACK: Callback = install_smc_callback();
+    static ref SWITCH_CALLBACK: Callback = install_switch_callback();
+}
+
+fn install_smc_callback() -> Callback {
+    let smc = Callback::new();
+
+    smc.before_handle_exception(|cpu, index| {
+        if index == panda_sys::EXCP_SMC as i32 {
+            let regs = RegSnapshot::capture(cpu);
+            let mut args = [0; 6];
+            args.copy_from_slice(&regs.regs[1..7]);
+
+            let call = SmcCall {
+                function_id: regs.regs[0] as u32,
+                args,
+                world: current_world(cpu),
+                regs,
+            };
+
+            run_callbacks(
+                |state| &mut state.smc_callbacks,
+                |callback| callback(cpu, &call),
+            );
+        }
+
+        index
+    });
+
+    smc
+}
+
+fn install_switch_callback() -> Callback {
+    let switch = Callback::new();
+
+    switch.before_block_exec(|cpu, _| {
+        let world = current_world(cpu);
+        let last_world = STATE.lock().unwrap().last_world.replace(world);
+
+        match last_world {
+            Some(from) if from != world => {
+                let switch = WorldSwitch {
+                    from,
+                    to: world,
+                    regs: RegSnapshot::capture(cpu),
+                };
+
+                run_callbacks(
+                    |state| &mut state.switch_callbacks,
+                    |callback| callback(cpu, &switch),
+                );
+            }
+            _ => (),
+        }
+    });
+
+    switch
+}
+
+/// Register a callback to be run each time the CPU takes a secure monitor call
+pub fn on_smc(callback: impl FnMut(&mut CPUState, &SmcCall) + Send + 'static) {
+    STATE.lock().unwrap().smc_callbacks.push(Box::new(callback));
+    lazy_static::initialize(&SMC_CALLBACK);
+}
+
+/// Register a callback to be run each time the CPU switches between the secure and
+/// non-secure worlds. The security state is checked at the start of every basic block
+/// while any of these callbacks are registered.
+pub fn on_world_switch(callback: impl FnMut(&mut CPUState, &WorldSwitch) + Send + 'static) {
+    STATE
+        .lock()
+        .unwrap()
+        .switch_callbacks
+        .push(Box::new(callback));
+    lazy_static::initialize(&SWITCH_CALLBACK);
+}
@@ -1,4 +1,10 @@
 use crate::enums::Endian;
+use crate::prelude::*;
+
+#[cfg(not(any(feature = "i386", feature = "x86_64")))]
+use crate::{cpu_arch_state, CPUArchPtr};
+
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
 // ================ ARCH_NAME ================
 
@@ -8,11 +14,12 @@ use crate::enums::Endian;
 ///
 /// * x86_64
 /// * i386
-/// * arm
+/// * arm (including big endian ARM, see [`ARCH_ENDIAN`])
 /// * ppc
 /// * mips
 /// * mipsel
 /// * mips64
+/// * mips64el
 /// * aarch64
 pub const ARCH_NAME: &str = ARCH;
 
@@ -45,7 +52,11 @@ const ARCH: &str = "mips64el";
 
 // ================ ARCH_ENDIAN ================
 
-/// The byte order of the guest architecture being targetted by PANDA
+/// The default byte order of the guest architecture being targetted by PANDA
+///
+/// Some architectures can switch byte order at runtime (such as ARM in BE8 mode), so
+/// the byte order actually used for data accesses is checked against this at runtime.
+/// See [`guest_endian`] and [`data_endian`].
 pub const ARCH_ENDIAN: Endian = ENDIAN;
 
 #[cfg(feature = "x86_64")]
@@ -54,9 +65,13 @@ const ENDIAN: Endian = Endian::Little;
 #[cfg(feature = "i386")]
 const ENDIAN: Endian = Endian::Little;
 
-#[cfg(feature = "arm")]
+#[cfg(all(feature = "arm", not(feature = "armeb")))]
 const ENDIAN: Endian = Endian::Little;
 
+// big endian ARM guests run on arm-softmmu, so everything but the byte order is shared
+#[cfg(feature = "armeb")]
+const ENDIAN: Endian = Endian::Big;
+
 #[cfg(feature = "ppc")]
 const ENDIAN: Endian = Endian::Big;
 
@@ -74,3 +89,110 @@ const ENDIAN: Endian = Endian::Big;
 
 #[cfg(feature = "mips64el")]
 const ENDIAN: Endian = Endian::Little;
+
+/// The byte order used for multi-byte guest data, as last observed from the CPU
+static DATA_ENDIAN: AtomicU8 = AtomicU8::new(ENDIAN as u8);
+static WARNED_ENDIAN: AtomicBool = AtomicBool::new(false);
+
+/// Get the byte order the guest CPU is currently configured to use for data accesses,
+/// as opposed to the compile-time [`ARCH_ENDIAN`].
+///
+/// * ARM: the `CPSR.E` bit (set in BE8 mode), or `SCTLR_ELx.EE`/`SCTLR_EL1.E0E` for
+/// AArch64 state
+/// * MIPS: the `Config0.BE` bit
+/// * PowerPC: the `MSR.LE` bit
+/// * x86: always little endian
+pub fn guest_endian(cpu: &CPUState) -> Endian {
+    if cpu_is_big_endian(cpu) {
+        Endian::Big
+    } else {
+        Endian::Little
+    }
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+fn cpu_is_big_endian(_: &CPUState) -> bool {
+    false
+}
+
+#[cfg(feature = "arm")]
+fn cpu_is_big_endian(cpu: &CPUState) -> bool {
+    const CPSR_E: u32 = 1 << 9;
+
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    env.uncached_cpsr & CPSR_E != 0
+}
+
+#[cfg(feature = "aarch64")]
+fn cpu_is_big_endian(cpu: &CPUState) -> bool {
+    const CPSR_E: u32 = 1 << 9;
+    const SCTLR_E0E: u64 = 1 << 24;
+    const SCTLR_EE: u64 = 1 << 25;
+
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    if env.aarch64 == 0 {
+        return env.uncached_cpsr & CPSR_E != 0;
+    }
+
+    let sctlr_el = unsafe { env.cp15.__bindgen_anon_2.sctlr_el };
+
+    match (env.pstate >> 2) & 3 {
+        0 => sctlr_el[1] & SCTLR_E0E != 0,
+        el => sctlr_el[el as usize] & SCTLR_EE != 0,
+    }
+}
+
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+fn cpu_is_big_endian(cpu: &CPUState) -> bool {
+    const CP0C0_BE: i32 = 1 << 15;
+
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    env.CP0_Config0 & CP0C0_BE != 0
+}
+
+#[cfg(feature = "ppc")]
+fn cpu_is_big_endian(cpu: &CPUState) -> bool {
+    const MSR_LE: target_ulong = 1;
+
+    let env = unsafe { &*cpu_arch_state!(cpu) };
+
+    env.msr & MSR_LE == 0
+}
+
+/// Check the byte order of the guest CPU against [`ARCH_ENDIAN`], warning (once) on a
+/// mismatch, and return the byte order in use.
+///
+/// The result is remembered by [`data_endian`], so accesses which don't have access to
+/// the CPU (such as physical memory reads) use the byte order last observed.
+pub fn check_endian(cpu: &CPUState) -> Endian {
+    let endian = guest_endian(cpu);
+    DATA_ENDIAN.store(endian as u8, Ordering::Relaxed);
+
+    if endian != ARCH_ENDIAN && !WARNED_ENDIAN.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "Warning: guest CPU is {:?} endian but {} is {:?} endian, using {:?} endian for guest data",
+            endian, ARCH_NAME, ARCH_ENDIAN, endian
+        );
+    }
+
+    endian
+}
+
+/// The byte order used for multi-byte guest data when no CPU is available: the byte
+/// order last observed by [`check_endian`], or [`ARCH_ENDIAN`] if it has not been
+/// called.
+pub fn data_endian() -> Endian {
+    if DATA_ENDIAN.load(Ordering::Relaxed) == Endian::Big as u8 {
+        Endian::Big
+    } else {
+        Endian::Little
+    }
+}
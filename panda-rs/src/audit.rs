@@ -0,0 +1,367 @@
+//! An audit log of the guest state modified through panda-rs
+//!
+//! When enabled, every register write, flag write, memory write and injected syscall
+//! made through the safe APIs of panda-rs ([`regs`](crate::regs), [`mem`](crate::mem),
+//! [`GuestPtr`](crate::GuestPtr) and [`syscall_injection`](crate::syscall_injection))
+//! is recorded along with the point in execution it happened at. The log can be
+//! exported as JSON lines with [`write_json`] to document an experiment, and register,
+//! flag and memory writes can be re-applied to another run with [`AuditEntry::apply`].
+//!
+//! Only the most recent entries are kept, up to [`DEFAULT_CAPACITY`] unless changed
+//! with [`set_capacity`]. The number of older entries discarded is reported by
+//! [`dropped`].
+//!
+//! Writes made directly through [`sys`](crate::sys) are not recorded. Injected syscalls
+//! are recorded alongside the register writes used to set them up.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::audit;
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     audit::enable();
+//! }
+//!
+//! #[panda::uninit]
+//! fn uninit(_: &mut PluginHandle) {
+//!     let file = std::fs::File::create("audit.jsonl").unwrap();
+//!     audit::write_json(file).unwrap();
+//! }
+//! ```
+
+use crate::current_asid;
+use crate::enums::MemRWStatus;
+use crate::mem;
+use crate::prelude::*;
+use crate::regs::flags::{self, Flag};
+use crate::regs::{self, Reg};
+use crate::rr::rr_get_guest_instr_count;
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+use crate::regs::x86::{self, ControlReg, Msr};
+
+use serde_json::{json, Map, Value};
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The maximum number of entries kept by default
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+/// A modification of guest state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+    /// A general purpose register was set
+    Register { reg: Reg, value: target_ulong },
+
+    /// The program counter was set
+    Pc { value: target_ulong },
+
+    /// A condition flag was set or cleared
+    Flag { flag: Flag, value: bool },
+
+    /// An x86 control register was set
+    #[cfg(any(feature = "i386", feature = "x86_64"))]
+    ControlRegister {
+        reg: ControlReg,
+        value: target_ulong,
+    },
+
+    /// An x86 model-specific register was written
+    #[cfg(any(feature = "i386", feature = "x86_64"))]
+    Msr { msr: Msr, value: u64 },
+
+    /// Guest virtual memory was written to
+    VirtualMemory { addr: target_ulong, data: Vec<u8> },
+
+    /// Guest physical memory was written to
+    PhysicalMemory { addr: target_ulong, data: Vec<u8> },
+
+    /// A syscall was injected. `ret` is `None` if the injector did not wait for the
+    /// syscall to return.
+    Syscall {
+        num: target_ulong,
+        args: Vec<target_ulong>,
+        ret: Option<target_ulong>,
+    },
+}
+
+/// A single recorded modification, along with when it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// The guest instruction count at the time of the modification. Only meaningful
+    /// while recording or replaying.
+    pub instr_count: u64,
+
+    /// The program counter at the time of the modification, if a CPU was available
+    pub pc: Option<target_ulong>,
+
+    /// The address space at the time of the modification, if a CPU was available
+    pub asid: Option<target_ulong>,
+
+    pub mutation: Mutation,
+}
+
+impl AuditEntry {
+    /// Re-apply this modification to the guest. Returns `false` without modifying the
+    /// guest for injected syscalls, which can only be made from within an injector, and
+    /// for memory writes which fail.
+    ///
+    /// Re-applied modifications are themselves recorded if the audit log is enabled.
+    pub fn apply(&self, cpu: &mut CPUState) -> bool {
+        match &self.mutation {
+            Mutation::Register { reg, value } => {
+                regs::set_reg(cpu, *reg, *value);
+                true
+            }
+            Mutation::Pc { value } => {
+                regs::set_pc(cpu, *value);
+                true
+            }
+            Mutation::Flag { flag, value } => {
+                flags::set(cpu, *flag, *value);
+                true
+            }
+            #[cfg(any(feature = "i386", feature = "x86_64"))]
+            Mutation::ControlRegister { reg, value } => {
+                x86::set_cr(cpu, *reg, *value);
+                true
+            }
+            #[cfg(any(feature = "i386", feature = "x86_64"))]
+            Mutation::Msr { msr, value } => x86::write_msr(cpu, *msr, *value),
+            Mutation::VirtualMemory { addr, data } => {
+                mem::virtual_memory_write(cpu, *addr, data) == MemRWStatus::MemTxOk
+            }
+            Mutation::PhysicalMemory { addr, data } => {
+                mem::physical_memory_write(*addr, data) == MemRWStatus::MemTxOk
+            }
+            Mutation::Syscall { .. } => false,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("instr_count".into(), self.instr_count.into());
+
+        if let Some(pc) = self.pc {
+            object.insert("pc".into(), pc.into());
+        }
+
+        if let Some(asid) = self.asid {
+            object.insert("asid".into(), asid.into());
+        }
+
+        let mutation = match &self.mutation {
+            Mutation::Register { reg, value } => json!({
+                "type": "register",
+                "reg": format!("{:?}", reg),
+                "value": value,
+            }),
+            Mutation::Pc { value } => json!({ "type": "pc", "value": value }),
+            Mutation::Flag { flag, value } => json!({
+                "type": "flag",
+                "flag": format!("{:?}", flag),
+                "value": value,
+            }),
+            #[cfg(any(feature = "i386", feature = "x86_64"))]
+            Mutation::ControlRegister { reg, value } => json!({
+                "type": "control_register",
+                "reg": format!("{:?}", reg),
+                "value": value,
+            }),
+            #[cfg(any(feature = "i386", feature = "x86_64"))]
+            Mutation::Msr { msr, value } => json!({
+                "type": "msr",
+                "msr": msr.0,
+                "value": value,
+            }),
+            Mutation::VirtualMemory { addr, data } => json!({
+                "type": "virtual_memory",
+                "addr": addr,
+                "data": hex(data),
+            }),
+            Mutation::PhysicalMemory { addr, data } => json!({
+                "type": "physical_memory",
+                "addr": addr,
+                "data": hex(data),
+            }),
+            Mutation::Syscall { num, args, ret } => {
+                let mut syscall = json!({ "type": "syscall", "num": num, "args": args });
+                if let Some(ret) = ret {
+                    syscall["ret"] = (*ret).into();
+                }
+
+                syscall
+            }
+        };
+
+        if let Value::Object(fields) = mutation {
+            object.extend(fields);
+        }
+
+        Value::Object(object)
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+struct Log {
+    entries: VecDeque<AuditEntry>,
+    capacity: usize,
+
+    /// The number of entries discarded to stay within the capacity
+    dropped: u64,
+}
+
+impl Log {
+    fn push(&mut self, entry: AuditEntry) {
+        self.entries.push_back(entry);
+        self.truncate();
+    }
+
+    /// Discard the oldest entries until there are at most `capacity`
+    fn truncate(&mut self) {
+        let excess = self.entries.len().saturating_sub(self.capacity);
+        self.entries.drain(..excess);
+        self.dropped += excess as u64;
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref LOG: Mutex<Log> = Mutex::new(Log {
+        entries: VecDeque::new(),
+        capacity: DEFAULT_CAPACITY,
+        dropped: 0,
+    });
+}
+
+/// Record a modification of guest state, if the audit log is enabled
+pub(crate) fn record(cpu: Option<&mut CPUState>, mutation: impl FnOnce() -> Mutation) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let (pc, asid) = match cpu {
+        Some(cpu) => (Some(regs::get_pc(cpu)), Some(current_asid(cpu))),
+        None => (None, None),
+    };
+
+    let entry = AuditEntry {
+        instr_count: rr_get_guest_instr_count(),
+        pc,
+        asid,
+        mutation: mutation(),
+    };
+
+    LOG.lock().unwrap().push(entry);
+}
+
+/// Start recording modifications of guest state
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Stop recording modifications of guest state. Entries recorded so far are kept
+/// until [`clear`] is called.
+pub fn disable() {
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+/// Check whether modifications of guest state are currently being recorded
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Set the maximum number of entries kept, discarding the oldest entries once there
+/// are more. Defaults to [`DEFAULT_CAPACITY`].
+pub fn set_capacity(capacity: usize) {
+    let mut log = LOG.lock().unwrap();
+    log.capacity = capacity;
+    log.truncate();
+}
+
+/// Get the number of entries discarded so far to stay within the capacity
+pub fn dropped() -> u64 {
+    LOG.lock().unwrap().dropped
+}
+
+/// Get the entries kept so far, oldest first
+pub fn entries() -> Vec<AuditEntry> {
+    LOG.lock().unwrap().entries.iter().cloned().collect()
+}
+
+/// Clear all entries recorded so far, along with the count of dropped entries
+pub fn clear() {
+    let mut log = LOG.lock().unwrap();
+    log.entries.clear();
+    log.dropped = 0;
+}
+
+/// Write the entries kept so far as JSON lines, one object per entry. Memory contents
+/// are written as hex strings.
+pub fn write_json(mut writer: impl Write) -> io::Result<()> {
+    for entry in entries() {
+        serde_json::to_writer(&mut writer, &entry.to_json())?;
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(instr_count: u64) -> AuditEntry {
+        AuditEntry {
+            instr_count,
+            pc: Some(0x1000),
+            asid: None,
+            mutation: Mutation::Syscall {
+                num: 1,
+                args: vec![2, 3],
+                ret: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        assert_eq!(
+            entry(5).to_json(),
+            json!({
+                "instr_count": 5,
+                "pc": 0x1000,
+                "type": "syscall",
+                "num": 1,
+                "args": [2, 3],
+            })
+        );
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut log = Log {
+            entries: VecDeque::new(),
+            capacity: 2,
+            dropped: 0,
+        };
+
+        for instr_count in 0..5 {
+            log.push(entry(instr_count));
+        }
+
+        let kept: Vec<_> = log.entries.iter().map(|entry| entry.instr_count).collect();
+        assert_eq!(kept, [3, 4]);
+        assert_eq!(log.dropped, 3);
+    }
+}
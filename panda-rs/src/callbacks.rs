@@ -2,7 +2,8 @@ use crate::sys::panda_cb_type;
 
 mod closure;
 mod export;
-pub use closure::{set_plugin_ref, Callback};
+pub use closure::{set_plugin_ref, Callback, CallbackHandle};
+pub(crate) use closure::{disable_all, get_plugin_ref};
 pub use export::CallbackReturn;
 
 mod ppp_closures;
@@ -10,6 +11,10 @@ pub use ppp_closures::{
     InternalPppClosureCallback, PppCallback, __internal_install_ppp_closure_callback,
 };
 
+mod priority;
+pub(crate) use priority::closure_at;
+pub use priority::{__internal_register_callbacks, __internal_setup_ppp_callbacks};
+
 /// An opaque type used to register/unregister callbacks with PANDA. Passed into init/unit
 /// callbacks
 pub struct PluginHandle;
@@ -20,13 +25,33 @@ pub struct PluginHandle;
 pub struct InternalCallback {
     pub cb_type: panda_cb_type,
     pub fn_pointer: *const (),
+    pub priority: i32,
 }
 
 impl InternalCallback {
     pub fn new(cb_type: panda_cb_type, fn_pointer: *const ()) -> Self {
+        Self::with_priority(cb_type, fn_pointer, 0)
+    }
+
+    pub fn with_priority(cb_type: panda_cb_type, fn_pointer: *const (), priority: i32) -> Self {
         Self {
             cb_type,
             fn_pointer,
+            priority,
+        }
+    }
+}
+
+/// Enable or disable every callback registered through a callback attribute
+pub(crate) fn set_attribute_callbacks_enabled(enabled: bool) {
+    for cb in inventory::iter::<InternalCallback> {
+        unsafe {
+            let func = std::mem::transmute(cb.fn_pointer);
+            if enabled {
+                crate::sys::panda_enable_callback(get_plugin_ref(), cb.cb_type, func);
+            } else {
+                crate::sys::panda_disable_callback(get_plugin_ref(), cb.cb_type, func);
+            }
         }
     }
 }
@@ -46,9 +71,40 @@ impl InternalCallback {
 /// ```
 pub struct UninitCallback(pub fn(&mut PluginHandle));
 
+/// Adds a PPP callback declared with a callback attribute, along with the callback's
+/// priority
 #[doc(hidden)]
-pub struct PPPCallbackSetup(pub fn());
+pub struct PPPCallbackSetup(pub fn(), pub i32);
 
 inventory::collect!(InternalCallback);
 inventory::collect!(UninitCallback);
 inventory::collect!(PPPCallbackSetup);
+
+panda_macros::define_callback_types!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_callback_type_has_bindings() {
+        let mut covered: Vec<_> = CALLBACK_TYPES.iter().map(|&(_, cb_type)| cb_type).collect();
+        covered.sort_unstable();
+        covered.dedup();
+        assert_eq!(
+            covered.len(),
+            CALLBACK_TYPES.len(),
+            "callback type bound twice"
+        );
+
+        let missing: Vec<_> = (0..crate::sys::panda_cb_type_PANDA_CB_LAST)
+            .filter(|cb_type| !covered.contains(cb_type))
+            .collect();
+
+        assert!(
+            missing.is_empty(),
+            "callback types without bindings: {:?}",
+            missing
+        );
+    }
+}
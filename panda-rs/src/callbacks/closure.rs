@@ -1,9 +1,9 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::c_void,
     sync::{
         atomic::{AtomicU64, Ordering},
-        RwLock,
+        Mutex, RwLock,
     },
 };
 
@@ -71,15 +71,84 @@ impl Callback {
     pub fn enable(&self) {
         let callbacks = CALLBACKS.read().unwrap();
         if let Some(callback) = callbacks.get(&self.0) {
+            DISABLED.lock().unwrap().remove(&self.0);
+            arrange(&callbacks, callback);
+        }
+    }
+
+    /// Set the priority of the callback in this slot, relative to the other closure
+    /// callbacks installed by this plugin for the same event. Callbacks with a higher
+    /// priority run first, and the default priority is 0. Can be called either before
+    /// or after installing the callback, but must not be called from within a callback
+    /// for the same event.
+    ///
+    /// PANDA runs callbacks in the order they were registered, so this doesn't order
+    /// the callback relative to other plugins' callbacks or to callback attributes.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use panda::prelude::*;
+    /// use panda::Callback;
+    ///
+    /// // runs before any other before_block_exec_invalidate_opt callbacks
+    /// Callback::new()
+    ///     .with_priority(100)
+    ///     .before_block_exec_invalidate_opt(|_, _| false);
+    /// ```
+    pub fn with_priority(self, priority: i32) -> Self {
+        SLOT_PRIORITIES.lock().unwrap().insert(self.0, priority);
+
+        let callbacks = CALLBACKS.read().unwrap();
+        if let Some(callback) = callbacks.get(&self.0) {
+            arrange(&callbacks, callback);
+        }
+
+        self
+    }
+
+    /// Skip runs of the callback in this slot which would start while it is already
+    /// running, such as when it calls an API which triggers the same callback. Can be
+    /// called either before or after installing the callback.
+    ///
+    /// Skipped runs return a neutral value, see [`reentrancy`](crate::reentrancy).
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use panda::prelude::*;
+    /// use panda::{mem, Callback};
+    ///
+    /// // reading memory from a memory callback can run the callback again
+    /// Callback::new()
+    ///     .skip_reentrant()
+    ///     .virt_mem_after_read(|cpu, _pc, addr, _size, _buf| {
+    ///         let _ = mem::virtual_memory_read(cpu, addr, 8);
+    ///     });
+    /// ```
+    pub fn skip_reentrant(self) -> Self {
+        SLOT_SKIP_REENTRANT.lock().unwrap().insert(self.0);
+
+        if let Some(callback) = CALLBACKS.read().unwrap().get(&self.0) {
             unsafe {
-                sys::panda_enable_callback_with_context(
-                    get_plugin_ref(),
-                    callback.cb_kind,
-                    callback.trampoline,
-                    callback.closure_ref as *mut c_void,
-                );
+                crate::reentrancy::set_skip_reentrant(callback.closure_ref as _, true);
             }
         }
+
+        self
+    }
+
+    /// Get the number of runs of the callback in this slot which were skipped, either
+    /// because it was masked by [`suppress`](crate::reentrancy::suppress) or because it
+    /// was already running and [`skip_reentrant`](Self::skip_reentrant) was set
+    pub fn skipped_calls(&self) -> u64 {
+        CALLBACKS
+            .read()
+            .unwrap()
+            .get(&self.0)
+            .map_or(0, |callback| unsafe {
+                crate::reentrancy::skipped(callback.closure_ref as _)
+            })
     }
 
     /// Disable the callback assigned to the given slot, if any.
@@ -87,16 +156,101 @@ impl Callback {
         let callbacks = CALLBACKS.read().unwrap();
 
         if let Some(callback) = callbacks.get(&self.0) {
-            unsafe {
-                sys::panda_disable_callback_with_context(
-                    get_plugin_ref(),
-                    callback.cb_kind,
-                    callback.trampoline,
-                    callback.closure_ref as *mut c_void,
-                );
-            }
+            DISABLED.lock().unwrap().insert(self.0);
+            arrange(&callbacks, callback);
         }
     }
+
+    /// Remove the callback assigned to the given slot, if any, freeing its closure. The
+    /// slot can be reused to install another callback.
+    ///
+    /// A callback can remove itself, in which case its closure is freed once it
+    /// returns.
+    pub fn remove(&self) {
+        SLOT_PRIORITIES.lock().unwrap().remove(&self.0);
+        SLOT_SKIP_REENTRANT.lock().unwrap().remove(&self.0);
+        DISABLED.lock().unwrap().remove(&self.0);
+
+        let mut callbacks = CALLBACKS.write().unwrap();
+        if let Some(callback) = callbacks.remove(&self.0) {
+            arrange(&callbacks, &callback);
+            drop(callbacks);
+
+            retire(callback);
+        }
+    }
+
+    /// Take ownership of this slot, so that its callback is removed when the returned
+    /// handle is dropped
+    pub fn into_handle(self) -> CallbackHandle {
+        CallbackHandle(self)
+    }
+}
+
+/// An owned [`Callback`] slot, which removes its callback and frees the closure when
+/// dropped. Useful for instrumentation which should only live as long as some other
+/// object, such as in long-running libpanda applications which install and retire
+/// callbacks dynamically.
+///
+/// Callbacks are installed through the handle the same way as with [`Callback`].
+///
+/// ## Example
+///
+/// ```
+/// use panda::prelude::*;
+/// use panda::CallbackHandle;
+///
+/// let handle = CallbackHandle::new();
+/// handle.before_block_exec(|_, tb| {
+///     println!("block {:#x}", tb.pc);
+/// });
+///
+/// // ...
+///
+/// // removes the callback
+/// drop(handle);
+/// ```
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct CallbackHandle(Callback);
+
+impl CallbackHandle {
+    /// Create a new owned callback slot
+    pub fn new() -> Self {
+        Callback::new().into_handle()
+    }
+
+    /// Remove the callback now rather than when the handle is dropped
+    pub fn remove(self) {
+        drop(self)
+    }
+
+    /// Give up ownership of the slot, keeping the callback installed
+    pub fn into_inner(self) -> Callback {
+        let callback = self.0;
+        std::mem::forget(self);
+
+        callback
+    }
+}
+
+impl Default for CallbackHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::ops::Deref for CallbackHandle {
+    type Target = Callback;
+
+    fn deref(&self) -> &Callback {
+        &self.0
+    }
+}
+
+impl Drop for CallbackHandle {
+    fn drop(&mut self) {
+        self.0.remove();
+    }
 }
 
 struct ClosureCallback {
@@ -104,13 +258,85 @@ struct ClosureCallback {
     cb_kind: sys::panda_cb_type,
     trampoline: sys::panda_cb_with_context,
     drop_fn: unsafe fn(*mut *mut c_void),
+
+    /// When the callback was installed, relative to other callbacks
+    installed: u64,
 }
 
 unsafe impl Sync for ClosureCallback {}
 unsafe impl Send for ClosureCallback {}
 
+static INSTALL_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Assign the closure callbacks for the same event as `callback` to the positions
+/// registered with PANDA, in priority order (see `callbacks::priority`)
+fn arrange(callbacks: &HashMap<u64, ClosureCallback>, callback: &ClosureCallback) {
+    let priorities = SLOT_PRIORITIES.lock().unwrap();
+    let disabled = DISABLED.lock().unwrap();
+
+    let mut slots: Vec<_> = callbacks
+        .iter()
+        .filter(|(_, other)| other.cb_kind == callback.cb_kind)
+        .collect();
+
+    slots.sort_by_key(|(id, other)| {
+        let priority = priorities.get(id).copied().unwrap_or(0);
+        (std::cmp::Reverse(priority), other.installed)
+    });
+
+    let closures: Vec<_> = slots
+        .into_iter()
+        .map(|(id, other)| (other.closure_ref as *mut c_void, !disabled.contains(id)))
+        .collect();
+
+    crate::callbacks::priority::assign(callback.cb_kind, callback.trampoline, &closures);
+}
+
+/// Free the closure of a callback which is no longer in a slot, once it is no longer
+/// running. The callback must already have been unassigned from its position with
+/// [`arrange`], so that it is never run again.
+fn retire(callback: ClosureCallback) {
+    let context = callback.closure_ref as *mut c_void;
+    unsafe {
+        crate::reentrancy::after_return(context, move || drop(callback));
+    }
+}
+
 lazy_static::lazy_static! {
     static ref CALLBACKS: RwLock<HashMap<u64, ClosureCallback>> = RwLock::new(HashMap::new());
+    static ref SLOT_PRIORITIES: Mutex<HashMap<u64, i32>> = Mutex::new(HashMap::new());
+
+    /// Slots whose callback skips nested runs
+    static ref SLOT_SKIP_REENTRANT: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+
+    /// Slots whose callback has been disabled
+    static ref DISABLED: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+}
+
+/// Disable every enabled closure callback, returning the slots which were disabled so
+/// they can be enabled again later. Unlike `panda_disable_plugin` and
+/// `panda_enable_plugin`, this leaves callbacks which were already disabled alone.
+pub(crate) fn disable_all() -> Vec<Callback> {
+    let callbacks = CALLBACKS.read().unwrap();
+
+    let newly_disabled: Vec<u64> = {
+        let mut disabled = DISABLED.lock().unwrap();
+        callbacks
+            .keys()
+            .copied()
+            .filter(|&id| disabled.insert(id))
+            .collect()
+    };
+
+    let mut kinds = HashSet::new();
+    for id in &newly_disabled {
+        let callback = &callbacks[id];
+        if kinds.insert(callback.cb_kind) {
+            arrange(&callbacks, callback);
+        }
+    }
+
+    newly_disabled.into_iter().map(Callback).collect()
 }
 
 static PLUGIN_REF: OnceCell<u64> = OnceCell::new();
@@ -120,21 +346,37 @@ pub fn set_plugin_ref(plugin: *mut PluginHandle) {
     let _ = PLUGIN_REF.set(plugin as u64);
 }
 
-fn get_plugin_ref() -> *mut c_void {
+/// The handle callbacks registered by this plugin are owned by
+pub(crate) fn get_plugin_ref() -> *mut c_void {
     *PLUGIN_REF.get_or_init(|| &PLUGIN_REF as *const _ as u64) as _
 }
 
-fn install_closure_callback(id: u64, callback: ClosureCallback) {
-    unsafe {
-        sys::panda_register_callback_with_context(
-            get_plugin_ref(),
-            callback.cb_kind,
-            callback.trampoline,
-            callback.closure_ref as *mut c_void,
-        );
+fn install_closure_callback(id: u64, mut callback: ClosureCallback) {
+    callback.installed = INSTALL_COUNT.fetch_add(1, Ordering::SeqCst);
+
+    if SLOT_SKIP_REENTRANT.lock().unwrap().contains(&id) {
+        unsafe {
+            crate::reentrancy::set_skip_reentrant(callback.closure_ref as _, true);
+        }
+    }
+
+    DISABLED.lock().unwrap().remove(&id);
+
+    let mut callbacks = CALLBACKS.write().unwrap();
+    let kind = callback.cb_kind;
+    let replaced = callbacks.insert(id, callback);
+
+    arrange(&callbacks, &callbacks[&id]);
+    if let Some(replaced) = &replaced {
+        if replaced.cb_kind != kind {
+            arrange(&callbacks, replaced);
+        }
     }
+    drop(callbacks);
 
-    CALLBACKS.write().unwrap().insert(id, callback);
+    if let Some(replaced) = replaced {
+        retire(replaced);
+    }
 }
 
 impl std::ops::Drop for ClosureCallback {
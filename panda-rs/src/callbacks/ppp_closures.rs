@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::c_void,
     sync::{
         atomic::{AtomicU64, Ordering},
@@ -134,8 +134,67 @@ impl PppCallback {
                     (callback.enable)(callback.closure_ref);
                 }
                 callback.is_enabled = true;
+
+                enabled(self.0);
+                reorder(&callbacks, self.0, false);
+            }
+        }
+    }
+
+    /// Set the priority of the callback in this slot, relative to the other PPP
+    /// callbacks installed by this plugin for the same event. Callbacks with a higher
+    /// priority run first, and the default priority is 0. Can be called either before
+    /// or after installing the callback, but must not be called from within a callback
+    /// for the same event.
+    ///
+    /// The order of PPP callbacks is kept by the plugin providing them, which runs them
+    /// in the order they were added. Callbacks are put in order by removing and
+    /// re-adding those which should run after them, so this only orders the callbacks
+    /// of this plugin relative to each other. Callbacks declared with attributes, such
+    /// as `#[panda::on_sys::read_enter(priority = 10)]`, are added in priority order
+    /// when the plugin is loaded.
+    pub fn with_priority(self, priority: i32) -> Self {
+        PRIORITIES.lock().unwrap().insert(self.0, priority);
+
+        let callbacks = CALLBACKS.lock().unwrap();
+        if callbacks
+            .get(&self.0)
+            .map_or(false, |callback| callback.is_enabled)
+        {
+            reorder(&callbacks, self.0, true);
+        }
+
+        self
+    }
+
+    /// Skip runs of the callback in this slot which would start while it is already
+    /// running, such as when it calls an API which triggers the same callback. Can be
+    /// called either before or after installing the callback.
+    ///
+    /// Skipped runs return a neutral value, see [`reentrancy`](crate::reentrancy).
+    pub fn skip_reentrant(self) -> Self {
+        SKIP_REENTRANT.lock().unwrap().insert(self.0);
+
+        if let Some(callback) = CALLBACKS.lock().unwrap().get(&self.0) {
+            unsafe {
+                crate::reentrancy::set_skip_reentrant(callback.closure_ref, true);
             }
         }
+
+        self
+    }
+
+    /// Get the number of runs of the callback in this slot which were skipped, either
+    /// because it was masked by [`suppress`](crate::reentrancy::suppress) or because it
+    /// was already running and [`skip_reentrant`](Self::skip_reentrant) was set
+    pub fn skipped_calls(&self) -> u64 {
+        CALLBACKS
+            .lock()
+            .unwrap()
+            .get(&self.0)
+            .map_or(0, |callback| unsafe {
+                crate::reentrancy::skipped(callback.closure_ref)
+            })
     }
 
     /// Disable the callback assigned to the given slot, if any.
@@ -155,6 +214,60 @@ impl PppCallback {
 
 lazy_static::lazy_static! {
     static ref CALLBACKS: Mutex<HashMap<u64, InternalPppClosureCallback>> = Mutex::new(HashMap::new());
+    static ref PRIORITIES: Mutex<HashMap<u64, i32>> = Mutex::new(HashMap::new());
+
+    /// The order slots' callbacks were last enabled in, which orders callbacks of equal
+    /// priority
+    static ref ENABLED_ORDER: Mutex<HashMap<u64, u64>> = Mutex::new(HashMap::new());
+
+    /// Slots whose callback skips nested runs
+    static ref SKIP_REENTRANT: Mutex<HashSet<u64>> = Mutex::new(HashSet::new());
+}
+
+static ENABLE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Record that the callback in the given slot was just enabled, putting it after the
+/// enabled callbacks of equal priority
+fn enabled(id: u64) {
+    let order = ENABLE_COUNT.fetch_add(1, Ordering::SeqCst);
+    ENABLED_ORDER.lock().unwrap().insert(id, order);
+}
+
+/// Move the enabled callbacks which should run after the callback in slot `id` (and
+/// the callback itself, if `include_self` is set) to the end of the callbacks for its
+/// event, in priority order.
+///
+/// The plugin providing the callbacks runs them in the order they were added, so
+/// removing and re-adding a callback moves it to the end. The other callbacks are
+/// already in order, so only those ranked after `id` need to move.
+fn reorder(callbacks: &HashMap<u64, InternalPppClosureCallback>, id: u64, include_self: bool) {
+    let kind = callbacks[&id].enable as usize;
+
+    let priorities = PRIORITIES.lock().unwrap();
+    let enabled_order = ENABLED_ORDER.lock().unwrap();
+    let rank = |id: &u64| {
+        let priority = priorities.get(id).copied().unwrap_or(0);
+        (std::cmp::Reverse(priority), enabled_order.get(id).copied())
+    };
+
+    let mut ids: Vec<u64> = callbacks
+        .iter()
+        .filter(|(_, callback)| callback.is_enabled && callback.enable as usize == kind)
+        .map(|(&id, _)| id)
+        .collect();
+
+    ids.sort_by_key(rank);
+
+    let position = ids.iter().position(|other| *other == id).unwrap();
+    let start = if include_self { position } else { position + 1 };
+
+    for id in &ids[start..] {
+        let callback = &callbacks[id];
+        unsafe {
+            (callback.disable)(callback.closure_ref);
+            (callback.enable)(callback.closure_ref);
+        }
+    }
 }
 
 #[doc(hidden)]
@@ -174,9 +287,15 @@ pub unsafe fn __internal_install_ppp_closure_callback(
     PppCallback(id): PppCallback,
     mut callback: InternalPppClosureCallback,
 ) {
+    if SKIP_REENTRANT.lock().unwrap().contains(&id) {
+        crate::reentrancy::set_skip_reentrant(callback.closure_ref, true);
+    }
+
     (callback.enable)(callback.closure_ref);
     callback.is_enabled = true;
+    enabled(id);
 
     let mut callbacks = CALLBACKS.lock().unwrap();
     callbacks.insert(id, callback);
+    reorder(&callbacks, id, false);
 }
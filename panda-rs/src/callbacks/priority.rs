@@ -0,0 +1,193 @@
+//! Ordering of callbacks registered for the same event
+//!
+//! PANDA runs the callbacks for an event in the order they were registered, and can't
+//! reorder them afterwards. Some callbacks are sensitive to this order, such as
+//! `before_block_exec_invalidate_opt` callbacks which veto each other's invalidation,
+//! so this plugin's callbacks can be given a priority: callbacks with a higher priority
+//! run before those with a lower one, and callbacks with the same priority run in the
+//! order they were installed. Callbacks without a priority have a priority of 0.
+//!
+//! Priorities only order the callbacks of this plugin, and callback attributes and
+//! closure callbacks are ordered separately:
+//!
+//! * Callback attributes are registered in priority order when the plugin is loaded.
+//! * Closure callbacks are run through positions, contexts which are registered with
+//! PANDA once and then reused. Whenever a closure callback is installed, removed or
+//! given a priority, the closures for its event are reassigned to the positions in
+//! priority order.
+//! * PPP callbacks are run by the plugin providing them, see
+//! [`PppCallback::with_priority`](crate::PppCallback::with_priority).
+use crate::sys::{self, panda_cb_type, panda_cb_with_context};
+use crate::{InternalCallback, PPPCallbackSetup};
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
+
+/// A context registered with PANDA for closure callbacks, which runs whichever closure is
+/// assigned to it
+struct Position {
+    /// The context of the closure, or null if no closure is assigned
+    closure: AtomicPtr<c_void>,
+}
+
+/// The positions registered for an event
+struct Positions {
+    trampoline: panda_cb_with_context,
+
+    /// Each position in the order PANDA runs them, along with whether it is enabled
+    registered: Vec<(&'static Position, bool)>,
+}
+
+impl Positions {
+    /// Register a new, unassigned position with PANDA, after the existing ones
+    fn register(&mut self, kind: panda_cb_type) {
+        let position: &'static Position = Box::leak(Box::new(Position {
+            closure: AtomicPtr::new(ptr::null_mut()),
+        }));
+
+        unsafe {
+            sys::panda_register_callback_with_context(
+                crate::callbacks::get_plugin_ref(),
+                kind,
+                self.trampoline,
+                position as *const Position as *mut c_void,
+            );
+        }
+
+        self.registered.push((position, true));
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref POSITIONS: Mutex<HashMap<panda_cb_type, Positions>> = Mutex::new(HashMap::new());
+}
+
+/// Get the context of the closure assigned to the position PANDA ran, or null if none is
+/// assigned
+///
+/// ## Safety
+///
+/// `position` must be a context registered by [`assign`]
+pub(crate) unsafe fn closure_at(position: *mut c_void) -> *mut c_void {
+    unsafe { &*(position as *const Position) }
+        .closure
+        .load(Ordering::Acquire)
+}
+
+/// Assign the closure callbacks for `kind` to positions in the order they should run,
+/// registering more positions with PANDA if needed. Each closure is given by its context
+/// and whether it is enabled, and `trampoline` must run the closure assigned to a
+/// position.
+///
+/// Closures keep their position unless it is out of order, so that installing or removing
+/// a callback from within a callback for the same event doesn't shift the callbacks PANDA
+/// has yet to run for it. Positions left over are unassigned and disabled, so closures
+/// which are no longer passed in are never run again.
+pub(crate) fn assign(
+    kind: panda_cb_type,
+    trampoline: panda_cb_with_context,
+    closures: &[(*mut c_void, bool)],
+) {
+    let mut positions = POSITIONS.lock().unwrap();
+    let positions = positions.entry(kind).or_insert_with(|| Positions {
+        trampoline,
+        registered: Vec::new(),
+    });
+
+    let current: HashMap<usize, usize> = positions
+        .registered
+        .iter()
+        .enumerate()
+        .map(|(i, (position, _))| (position.closure.load(Ordering::Relaxed) as usize, i))
+        .filter(|(closure, _)| *closure != 0)
+        .collect();
+
+    let live: HashSet<usize> = closures
+        .iter()
+        .map(|(closure, _)| *closure as usize)
+        .collect();
+    let is_free = |positions: &Positions, i: usize| {
+        let closure = positions.registered[i].0.closure.load(Ordering::Relaxed);
+        !live.contains(&(closure as usize))
+    };
+
+    let mut assigned: Vec<Option<(*mut c_void, bool)>> = vec![None; positions.registered.len()];
+    let mut next = 0;
+    for &(closure, enabled) in closures {
+        let index = match current.get(&(closure as usize)) {
+            Some(&i) if i >= next => i,
+            _ => match (next..positions.registered.len())
+                .find(|&i| assigned[i].is_none() && is_free(positions, i))
+            {
+                Some(i) => i,
+                None => {
+                    positions.register(kind);
+                    assigned.push(None);
+                    positions.registered.len() - 1
+                }
+            },
+        };
+
+        assigned[index] = Some((closure, enabled));
+        next = index + 1;
+    }
+
+    let trampoline = positions.trampoline;
+    for ((position, enabled), assigned) in positions.registered.iter_mut().zip(assigned) {
+        let (closure, enable) = assigned.unwrap_or((ptr::null_mut(), false));
+
+        position.closure.store(closure, Ordering::Release);
+
+        if enable != *enabled {
+            let context = *position as *const Position as *mut c_void;
+            let plugin = crate::callbacks::get_plugin_ref();
+            unsafe {
+                if enable {
+                    sys::panda_enable_callback_with_context(plugin, kind, trampoline, context);
+                } else {
+                    sys::panda_disable_callback_with_context(plugin, kind, trampoline, context);
+                }
+            }
+
+            *enabled = enable;
+        }
+    }
+}
+
+/// Register the callbacks declared with callback attributes, in priority order
+///
+/// ## Safety
+///
+/// `plugin` must be the handle of this plugin
+#[doc(hidden)]
+pub unsafe fn __internal_register_callbacks(plugin: *mut c_void) {
+    let mut callbacks: Vec<&InternalCallback> =
+        inventory::iter::<InternalCallback>.into_iter().collect();
+
+    // stable, so callbacks of equal priority keep their order
+    callbacks.sort_by_key(|cb| Reverse(cb.priority));
+
+    for cb in callbacks {
+        unsafe {
+            sys::panda_register_callback(plugin, cb.cb_type, std::mem::transmute(cb.fn_pointer));
+        }
+    }
+}
+
+/// Add the PPP callbacks declared with callback attributes to the plugins providing them,
+/// in priority order
+#[doc(hidden)]
+pub fn __internal_setup_ppp_callbacks() {
+    let mut setups: Vec<&PPPCallbackSetup> =
+        inventory::iter::<PPPCallbackSetup>.into_iter().collect();
+
+    setups.sort_by_key(|setup| Reverse(setup.1));
+
+    for setup in setups {
+        setup.0();
+    }
+}
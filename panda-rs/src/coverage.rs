@@ -0,0 +1,602 @@
+//! Basic block and edge coverage of the guest
+//!
+//! Once [`start`] is called, every basic block executed (and optionally every edge
+//! between consecutive blocks) is recorded per address space, along with the module
+//! (executable or shared library) containing it as reported by OSI. Coverage can be
+//! paused and resumed with [`disable`] and [`enable`], retrieved at any time with
+//! [`report`], and written out in one of several [`Format`]s with
+//! [`CoverageReport::save`] or, when the guest shuts down, with [`save_on_exit`].
+//!
+//! Only the first execution of each block or edge is recorded, so the cost of coverage
+//! is a set lookup per block executed. [`CoverageConfig::rate_limit`] caps how many new
+//! entries are recorded per number of guest instructions, for bounding the overhead on
+//! guests which execute large amounts of new code, such as JITs. The limit is measured
+//! in guest instructions rather than host time so that replays record the same
+//! coverage each time.
+//!
+//! Requires the `osi` plugin for modules and process filtering. Writing SQLite
+//! databases requires the `coverage-sqlite` feature.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::coverage::{self, CoverageConfig, Format, Mode};
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     coverage::start(
+//!         CoverageConfig::new(Mode::Edges)
+//!             .process_name("nginx")
+//!             .user_only(),
+//!     );
+//!
+//!     coverage::save_on_exit("nginx.drcov", Format::Drcov);
+//! }
+//! ```
+use crate::plugins::osi::{self, Mapping};
+use crate::prelude::*;
+use crate::schedule::insn_count;
+use crate::{current_asid, sanitize, Callback};
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What to record coverage of
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Each basic block executed
+    Blocks,
+
+    /// Each basic block executed, along with each pair of blocks executed one after
+    /// the other
+    Edges,
+}
+
+/// A format for writing coverage to disk
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// The drcov format used by DynamoRIO, which can be loaded by tools such as
+    /// Lighthouse. Only blocks inside a module are written, and edges are not
+    /// included.
+    Drcov,
+
+    /// Comma-separated values, with one row per block followed by one row per edge
+    Csv,
+
+    /// A SQLite database, with `modules`, `blocks` and `edges` tables
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "coverage-sqlite")))]
+    #[cfg(feature = "coverage-sqlite")]
+    Sqlite,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CoverageError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "coverage-sqlite")))]
+    #[cfg(feature = "coverage-sqlite")]
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+/// Settings for collecting coverage, passed to [`start`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageConfig {
+    mode: Mode,
+    process_name: Option<String>,
+    user_only: bool,
+    rate_limit: Option<(u32, u64)>,
+}
+
+impl CoverageConfig {
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            process_name: None,
+            user_only: false,
+            rate_limit: None,
+        }
+    }
+
+    /// Only record coverage while a process with the given name is running
+    pub fn process_name(mut self, name: impl Into<String>) -> Self {
+        self.process_name = Some(name.into());
+        self
+    }
+
+    /// Only record coverage of user mode code
+    pub fn user_only(mut self) -> Self {
+        self.user_only = true;
+        self
+    }
+
+    /// Record at most `entries` new blocks and edges per `insns` guest instructions.
+    /// Entries over the limit are dropped, and counted in [`CoverageReport::dropped`].
+    pub fn rate_limit(mut self, entries: u32, insns: u64) -> Self {
+        assert_ne!(
+            insns, 0,
+            "coverage can't be rate limited per 0 instructions"
+        );
+
+        self.rate_limit = Some((entries, insns));
+        self
+    }
+}
+
+/// A module which covered code was found in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoveredModule {
+    /// The path of the file backing the module, or its name if it has no file
+    pub path: String,
+    pub asid: target_ulong,
+    pub base: target_ptr_t,
+    pub end: target_ptr_t,
+}
+
+/// A basic block which was executed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CoveredBlock {
+    pub asid: target_ulong,
+    pub pc: target_ulong,
+
+    /// The size of the block in bytes
+    pub size: u32,
+
+    /// The index of the module containing the block in [`CoverageReport::modules`]
+    pub module: Option<usize>,
+}
+
+/// A pair of basic blocks which were executed one after the other
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CoveredEdge {
+    pub asid: target_ulong,
+    pub from: target_ulong,
+    pub to: target_ulong,
+}
+
+/// The coverage collected so far, returned by [`report`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoverageReport {
+    pub modules: Vec<CoveredModule>,
+
+    /// Blocks in the order they were first executed
+    pub blocks: Vec<CoveredBlock>,
+
+    /// Edges in the order they were first executed, empty unless collecting
+    /// [`Mode::Edges`]
+    pub edges: Vec<CoveredEdge>,
+
+    /// The number of new blocks and edges dropped due to the rate limit
+    pub dropped: u64,
+}
+
+impl CoverageReport {
+    /// Get the offset of a block from the base of its module, if it is in one
+    pub fn module_offset(&self, block: &CoveredBlock) -> Option<target_ptr_t> {
+        block
+            .module
+            .map(|module| block.pc as target_ptr_t - self.modules[module].base)
+    }
+
+    /// Write the coverage to the given file in the given format
+    pub fn save(&self, path: impl AsRef<Path>, format: Format) -> Result<(), CoverageError> {
+        match format {
+            Format::Drcov => self.write_drcov(BufWriter::new(File::create(path)?))?,
+            Format::Csv => self.write_csv(BufWriter::new(File::create(path)?))?,
+
+            #[cfg(feature = "coverage-sqlite")]
+            Format::Sqlite => self.write_sqlite(path.as_ref())?,
+        }
+
+        Ok(())
+    }
+
+    /// Write the coverage in the drcov format. See [`Format::Drcov`].
+    pub fn write_drcov(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "DRCOV VERSION: 2")?;
+        writeln!(writer, "DRCOV FLAVOR: panda")?;
+        writeln!(
+            writer,
+            "Module Table: version 2, count {}",
+            self.modules.len()
+        )?;
+        writeln!(
+            writer,
+            "Columns: id, base, end, entry, checksum, timestamp, path"
+        )?;
+
+        for (id, module) in self.modules.iter().enumerate() {
+            writeln!(
+                writer,
+                "{:3}, {:#018x}, {:#018x}, {:#018x}, {:#010x}, {:#010x}, {}",
+                id,
+                module.base as u64,
+                module.end as u64,
+                0,
+                0,
+                0,
+                sanitize::text(&module.path)
+            )?;
+        }
+
+        let blocks: Vec<_> = self
+            .blocks
+            .iter()
+            .filter_map(|block| Some((block, block.module?, self.module_offset(block)?)))
+            .collect();
+
+        writeln!(writer, "BB Table: {} bbs", blocks.len())?;
+        for (block, module, offset) in blocks {
+            // struct bb_entry_t { uint32_t start; uint16_t size; uint16_t mod_id; }
+            writer.write_all(&(offset as u32).to_le_bytes())?;
+            writer.write_all(&(block.size.min(u16::MAX as u32) as u16).to_le_bytes())?;
+            writer.write_all(&(module as u16).to_le_bytes())?;
+        }
+
+        writer.flush()
+    }
+
+    /// Write the coverage as comma-separated values. See [`Format::Csv`].
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "kind,asid,pc,size,module,offset")?;
+        for block in &self.blocks {
+            let module = block
+                .module
+                .map(|module| sanitize::text(&self.modules[module].path));
+            write!(
+                writer,
+                "block,{:#x},{:#x},{},{},",
+                block.asid,
+                block.pc,
+                block.size,
+                csv_field(module.as_deref().unwrap_or_default())
+            )?;
+
+            match self.module_offset(block) {
+                Some(offset) => writeln!(writer, "{:#x}", offset)?,
+                None => writeln!(writer)?,
+            }
+        }
+
+        writeln!(writer, "kind,asid,from,to")?;
+        for edge in &self.edges {
+            writeln!(
+                writer,
+                "edge,{:#x},{:#x},{:#x}",
+                edge.asid, edge.from, edge.to
+            )?;
+        }
+
+        writer.flush()
+    }
+
+    /// Write the coverage to a SQLite database. See [`Format::Sqlite`].
+    #[cfg_attr(doc_cfg, doc(cfg(feature = "coverage-sqlite")))]
+    #[cfg(feature = "coverage-sqlite")]
+    pub fn write_sqlite(&self, path: &Path) -> rusqlite::Result<()> {
+        let mut db = rusqlite::Connection::open(path)?;
+        let tx = db.transaction()?;
+
+        tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS modules (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                path TEXT, asid INTEGER, base INTEGER, end INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS blocks (
+                asid INTEGER, pc INTEGER, size INTEGER, module INTEGER REFERENCES modules(id)
+            );
+            CREATE TABLE IF NOT EXISTS edges (asid INTEGER, src INTEGER, dst INTEGER);",
+        )?;
+
+        {
+            // the database may already hold modules from an earlier report, so let SQLite
+            // pick the ids and map each module to the id it was given
+            let mut module_ids = Vec::with_capacity(self.modules.len());
+            let mut insert =
+                tx.prepare("INSERT INTO modules (path, asid, base, end) VALUES (?1, ?2, ?3, ?4)")?;
+            for module in &self.modules {
+                module_ids.push(insert.insert(rusqlite::params![
+                    module.path,
+                    module.asid as i64,
+                    module.base as i64,
+                    module.end as i64
+                ])?);
+            }
+
+            let mut insert = tx.prepare("INSERT INTO blocks VALUES (?1, ?2, ?3, ?4)")?;
+            for block in &self.blocks {
+                insert.execute(rusqlite::params![
+                    block.asid as i64,
+                    block.pc as i64,
+                    block.size,
+                    block.module.map(|module| module_ids[module])
+                ])?;
+            }
+
+            let mut insert = tx.prepare("INSERT INTO edges VALUES (?1, ?2, ?3)")?;
+            for edge in &self.edges {
+                insert.execute(rusqlite::params![
+                    edge.asid as i64,
+                    edge.from as i64,
+                    edge.to as i64
+                ])?;
+            }
+        }
+
+        tx.commit()
+    }
+}
+
+/// Quote a field for CSV output if it contains a delimiter, quote or newline
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// A count of the new entries recorded since the instruction count the current rate
+/// limiting window started at
+struct RateWindow {
+    start: u64,
+    count: u32,
+}
+
+struct Collector {
+    config: CoverageConfig,
+    report: CoverageReport,
+    blocks: HashSet<(target_ulong, target_ulong)>,
+    edges: HashSet<CoveredEdge>,
+
+    /// The last block executed in each address space, for edges
+    last_block: HashMap<target_ulong, target_ulong>,
+
+    /// The mappings of each address space as of the last time a block outside of them
+    /// was found
+    mappings: HashMap<target_ulong, Vec<Mapping>>,
+
+    /// The index into the report of each module, by address space and base
+    modules: HashMap<(target_ulong, target_ptr_t), usize>,
+    window: RateWindow,
+}
+
+impl Collector {
+    fn new(config: CoverageConfig) -> Self {
+        Self {
+            config,
+            report: CoverageReport::default(),
+            blocks: HashSet::new(),
+            edges: HashSet::new(),
+            last_block: HashMap::new(),
+            mappings: HashMap::new(),
+            modules: HashMap::new(),
+            window: RateWindow { start: 0, count: 0 },
+        }
+    }
+
+    /// Check whether another new entry can be recorded under the rate limit
+    fn allow_new(&mut self) -> bool {
+        let (limit, insns) = match self.config.rate_limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        let now = insn_count();
+        if now.saturating_sub(self.window.start) >= insns {
+            self.window = RateWindow {
+                start: now,
+                count: 0,
+            };
+        }
+
+        if self.window.count < limit {
+            self.window.count += 1;
+            true
+        } else {
+            self.report.dropped += 1;
+            false
+        }
+    }
+
+    /// Find the module containing `pc`, fetching the mappings of the address space
+    /// again if it isn't in any of them, as a library may have been loaded since
+    fn module(
+        &mut self,
+        cpu: &mut CPUState,
+        asid: target_ulong,
+        pc: target_ulong,
+    ) -> Option<usize> {
+        let pc = pc as target_ptr_t;
+        let find = |mappings: &Vec<Mapping>| mappings.iter().find(|m| m.contains(pc)).cloned();
+
+        let mapping = match self.mappings.get(&asid).and_then(find) {
+            Some(mapping) => mapping,
+            None => {
+                let mappings = osi::current_process(cpu)
+                    .map(|process| process.mappings(cpu))
+                    .unwrap_or_default();
+                let mapping = find(&mappings);
+                self.mappings.insert(asid, mappings);
+
+                mapping?
+            }
+        };
+
+        let end = mapping.end();
+        let report = &mut self.report;
+        let index = *self.modules.entry((asid, mapping.base)).or_insert_with(|| {
+            report.modules.push(CoveredModule {
+                path: mapping.file.or(mapping.name).unwrap_or_default(),
+                asid,
+                base: mapping.base,
+                end,
+            });
+
+            report.modules.len() - 1
+        });
+
+        Some(index)
+    }
+
+    fn block(&mut self, cpu: &mut CPUState, tb: &TranslationBlock) {
+        if self.config.user_only && cpu.in_kernel_mode() {
+            return;
+        }
+
+        if let Some(name) = &self.config.process_name {
+            match osi::current_process(cpu) {
+                Some(process) if &process.name == name => (),
+                _ => return,
+            }
+        }
+
+        let asid = current_asid(cpu);
+        let pc = tb.pc;
+
+        if !self.blocks.contains(&(asid, pc)) && self.allow_new() {
+            self.blocks.insert((asid, pc));
+
+            let module = self.module(cpu, asid, pc);
+            self.report.blocks.push(CoveredBlock {
+                asid,
+                pc,
+                size: tb.size as u32,
+                module,
+            });
+        }
+
+        if self.config.mode == Mode::Edges {
+            if let Some(from) = self.last_block.insert(asid, pc) {
+                let edge = CoveredEdge { asid, from, to: pc };
+                if !self.edges.contains(&edge) && self.allow_new() {
+                    self.edges.insert(edge);
+                    self.report.edges.push(edge);
+                }
+            }
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref COLLECTOR: Mutex<Option<Collector>> = Mutex::new(None);
+    static ref CALLBACK: Callback = install_callback();
+    static ref SAVE_CALLBACKS: Mutex<Vec<Callback>> = Mutex::new(Vec::new());
+}
+
+fn install_callback() -> Callback {
+    let block = Callback::new();
+
+    block.before_block_exec(|cpu, tb| {
+        if let Some(collector) = &mut *COLLECTOR.lock().unwrap() {
+            collector.block(cpu, tb);
+        }
+    });
+
+    block
+}
+
+/// Start collecting coverage with the given settings, discarding any coverage
+/// collected so far
+pub fn start(config: CoverageConfig) {
+    *COLLECTOR.lock().unwrap() = Some(Collector::new(config));
+
+    enable();
+}
+
+/// Resume collecting coverage after [`disable`]. Has no effect before [`start`].
+pub fn enable() {
+    CALLBACK.enable();
+}
+
+/// Pause collecting coverage, keeping the coverage collected so far. Edges between the
+/// last block before pausing and the first block after resuming are not recorded.
+pub fn disable() {
+    CALLBACK.disable();
+
+    if let Some(collector) = &mut *COLLECTOR.lock().unwrap() {
+        collector.last_block.clear();
+    }
+}
+
+/// Get the coverage collected so far
+pub fn report() -> CoverageReport {
+    COLLECTOR
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|collector| collector.report.clone())
+        .unwrap_or_default()
+}
+
+/// Write the coverage to the given file in the given format when the guest shuts
+/// down, such as at the end of a replay
+pub fn save_on_exit(path: impl Into<PathBuf>, format: Format) {
+    let path = path.into();
+    let callback = Callback::new();
+
+    callback.pre_shutdown(move || {
+        if let Err(err) = report().save(&path, format) {
+            eprintln!(
+                "Warning: failed to write coverage to {}: {}",
+                path.display(),
+                err
+            );
+        }
+    });
+
+    SAVE_CALLBACKS.lock().unwrap().push(callback);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(path: &str) -> CoverageReport {
+        CoverageReport {
+            modules: vec![CoveredModule {
+                path: path.to_owned(),
+                asid: 1,
+                base: 0x1000,
+                end: 0x2000,
+            }],
+            blocks: vec![CoveredBlock {
+                asid: 1,
+                pc: 0x1010,
+                size: 4,
+                module: Some(0),
+            }],
+            edges: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    #[test]
+    fn test_csv_escapes_module_paths() {
+        let mut csv = Vec::new();
+        report("/lib/a,b\n\x1b[2J.so").write_csv(&mut csv).unwrap();
+
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv.lines().nth(1),
+            Some(r#"block,0x1,0x1010,4,"/lib/a,b\n\u{1b}[2J.so",0x10"#)
+        );
+    }
+
+    #[test]
+    fn test_drcov_escapes_module_paths() {
+        let mut drcov = Vec::new();
+        report("/lib/a.so\nBB Table: 0 bbs")
+            .write_drcov(&mut drcov)
+            .unwrap();
+
+        let header = String::from_utf8_lossy(&drcov);
+        assert!(header.contains("/lib/a.so\\nBB Table: 0 bbs\nBB Table: 1 bbs\n"));
+    }
+}
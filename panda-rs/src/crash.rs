@@ -0,0 +1,483 @@
+//! Bundles of analysis state for processes killed by a fatal signal
+//!
+//! Once [`enable`] is called, whenever the guest kernel starts a core dump for a process
+//! (such as one killed by `SIGSEGV` or `SIGABRT`), a bundle describing why it died is
+//! written to the `crashes` subdirectory of the [output directory](crate::output) while
+//! the process is still intact. Each bundle is written to its own directory,
+//! `<name>-<pid>-<instr count>`, containing:
+//!
+//! * `info.txt` - the name, pid, ppid and asid of the process, the signal it was killed
+//! by and the pc at which it last entered the kernel
+//! * `backtrace.txt` - the callstack of the process when it last entered the kernel
+//! (the faulting instruction for `SIGSEGV`, the `kill` syscall for `SIGABRT`), one frame
+//! per line with the mapping and offset it is in
+//! * `syscalls.txt` - the last syscalls made by the process, oldest first
+//! * `coverage.txt` - `pc count` for each block executed within the mappings of the
+//! process, if [`Triage::coverage`] is set
+//! * a [`procdump`] of the memory of the process, if [`Triage::memory`] is set
+//!
+//! Core dumps are caught by hooking the kernel's `do_coredump` using
+//! [`hook::kernel_symbol`](crate::hook::kernel_symbol), so a volatility profile must be
+//! loaded for OSI2 (see [`cosi`](crate::plugins::cosi)). Backtraces come from the
+//! `callstack_instr` plugin and coverage from [`block_count`](crate::block_count), with
+//! blocks at the same address in different processes (such as within shared libraries)
+//! sharing a count.
+//!
+//! Requires the `osi`, `syscalls2`, `hooks2` and `callstack_instr` plugins.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::crash::{self, Triage};
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     crash::enable(Triage::new().syscalls(64).coverage());
+//! }
+//! ```
+use crate::mem::{find_mapping_containing, read_guest_type, MemoryRegion};
+use crate::plugins::callstack_instr;
+use crate::plugins::hooks2::Hooks2Callbacks;
+use crate::plugins::osi::{self, OSI};
+use crate::prelude::*;
+use crate::procdump::{self, ProcessDump};
+use crate::regs::{self, Reg};
+use crate::rr::rr_get_guest_instr_count;
+use crate::syscalls::decode::{on_sys_enter_decoded, Syscall};
+use crate::{
+    block_count, current_asid, hook, in_kernel_mode, output, sanitize, Callback, PppCallback,
+};
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The subdirectory of the [output directory](crate::output) bundles are written to
+const OUTPUT_SUBDIR: &str = "crashes";
+
+/// The number of syscalls kept per process by default
+pub const DEFAULT_SYSCALLS: usize = 32;
+
+/// The number of callers included in backtraces by default
+pub const DEFAULT_BACKTRACE_DEPTH: usize = 16;
+
+/// The register holding the first argument of a kernel function
+#[cfg(feature = "x86_64")]
+const FIRST_ARG: Reg = Reg::RDI;
+
+// 32-bit x86 kernels are built with -mregparm=3
+#[cfg(feature = "i386")]
+const FIRST_ARG: Reg = Reg::EAX;
+
+#[cfg(feature = "arm")]
+const FIRST_ARG: Reg = Reg::R0;
+
+#[cfg(feature = "aarch64")]
+const FIRST_ARG: Reg = Reg::X0;
+
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+const FIRST_ARG: Reg = Reg::A0;
+
+/// What to collect for each crashed process, built in the same way as
+/// [`diff::Collect`](crate::diff::Collect)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Triage {
+    syscalls: usize,
+    backtrace_depth: usize,
+    coverage: bool,
+    memory: bool,
+}
+
+impl Default for Triage {
+    fn default() -> Self {
+        Self {
+            syscalls: DEFAULT_SYSCALLS,
+            backtrace_depth: DEFAULT_BACKTRACE_DEPTH,
+            coverage: false,
+            memory: false,
+        }
+    }
+}
+
+impl Triage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of syscalls kept for each process. Defaults to
+    /// [`DEFAULT_SYSCALLS`].
+    pub fn syscalls(mut self, count: usize) -> Self {
+        self.syscalls = count;
+        self
+    }
+
+    /// Set the number of callers included in backtraces. Defaults to
+    /// [`DEFAULT_BACKTRACE_DEPTH`].
+    pub fn backtrace_depth(mut self, depth: usize) -> Self {
+        self.backtrace_depth = depth;
+        self
+    }
+
+    /// Include the blocks executed by the process, enabling
+    /// [`block_count`](crate::block_count)
+    pub fn coverage(mut self) -> Self {
+        self.coverage = true;
+        self
+    }
+
+    /// Include a dump of the memory of the process, see [`procdump`]
+    pub fn memory(mut self) -> Self {
+        self.memory = true;
+        self
+    }
+}
+
+/// A syscall made by a crashed process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallRecord {
+    /// The guest instruction count when the syscall was made
+    pub instr_count: u64,
+    pub syscall: Syscall,
+}
+
+/// A frame of the backtrace of a crashed process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub addr: target_ulong,
+
+    /// The mapping containing the address, if any
+    pub mapping: Option<String>,
+
+    /// The offset of the address from the start of its mapping
+    pub offset: target_ulong,
+}
+
+/// A bundle of analysis state written for a crashed process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashBundle {
+    pub name: String,
+    pub pid: target_pid_t,
+    pub ppid: target_pid_t,
+    pub asid: target_ptr_t,
+
+    /// The signal the process was killed by, if it could be read
+    pub signal: Option<i32>,
+
+    /// The guest instruction count at the time of the crash. Only meaningful while
+    /// recording or replaying.
+    pub instr_count: u64,
+
+    /// The directory the bundle was written to
+    pub dir: PathBuf,
+
+    /// The callstack of the process when it last entered the kernel, starting with the
+    /// pc it entered the kernel at. Empty if it wasn't seen entering the kernel.
+    pub backtrace: Vec<Frame>,
+    pub syscalls: Vec<SyscallRecord>,
+
+    /// Execution counts of the blocks within the mappings of the process, keyed by pc
+    pub coverage: BTreeMap<target_ulong, u64>,
+
+    /// The dump of the memory of the process, if requested
+    pub memory: Option<ProcessDump>,
+}
+
+/// What has been seen of a process before it crashed
+#[derive(Default)]
+struct History {
+    syscalls: VecDeque<SyscallRecord>,
+
+    /// The pc and callers of the process when it last entered the kernel
+    last_entry: Option<(target_ulong, Vec<target_ulong>)>,
+}
+
+struct CrashState {
+    config: Triage,
+    history: HashMap<target_ulong, History>,
+    bundles: Vec<CrashBundle>,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<CrashState> = Mutex::new(CrashState {
+        config: Triage::default(),
+        history: HashMap::new(),
+        bundles: Vec::new(),
+    });
+    static ref CALLBACKS: () = install_callbacks();
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Get the name of a signal which causes a core dump
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    #[cfg(any(
+        feature = "mips",
+        feature = "mipsel",
+        feature = "mips64",
+        feature = "mips64el"
+    ))]
+    const SIGNALS: &[(i32, &str)] = &[(10, "SIGBUS"), (12, "SIGSYS")];
+
+    #[cfg(not(any(
+        feature = "mips",
+        feature = "mipsel",
+        feature = "mips64",
+        feature = "mips64el"
+    )))]
+    const SIGNALS: &[(i32, &str)] = &[(7, "SIGBUS"), (31, "SIGSYS")];
+
+    let name = match signal {
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        8 => "SIGFPE",
+        11 => "SIGSEGV",
+        _ => SIGNALS.iter().find(|(num, _)| *num == signal)?.1,
+    };
+
+    Some(name)
+}
+
+fn record_kernel_entry(cpu: &mut CPUState, depth: usize) -> target_ulong {
+    let asid = current_asid(cpu);
+    let entry = (crate::current_pc(cpu), callstack_instr::callers(cpu, depth));
+
+    STATE
+        .lock()
+        .unwrap()
+        .history
+        .entry(asid)
+        .or_default()
+        .last_entry = Some(entry);
+
+    asid
+}
+
+fn install_callbacks() {
+    on_sys_enter_decoded(|cpu, syscall| {
+        if !ENABLED.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let (max, depth) = {
+            let state = STATE.lock().unwrap();
+            (state.config.syscalls, state.config.backtrace_depth)
+        };
+
+        let asid = record_kernel_entry(cpu, depth);
+        let mut state = STATE.lock().unwrap();
+        let syscalls = &mut state.history.entry(asid).or_default().syscalls;
+
+        syscalls.push_back(SyscallRecord {
+            instr_count: rr_get_guest_instr_count(),
+            syscall: syscall.clone(),
+        });
+        while syscalls.len() > max {
+            syscalls.pop_front();
+        }
+    });
+
+    // faults are handled as exceptions taken in user mode
+    Callback::new().before_handle_exception(|cpu, index| {
+        if ENABLED.load(Ordering::SeqCst) && !in_kernel_mode(cpu) {
+            let depth = STATE.lock().unwrap().config.backtrace_depth;
+            record_kernel_entry(cpu, depth);
+        }
+
+        index
+    });
+
+    PppCallback::new().on_process_end(|_, _, asid, _| {
+        STATE.lock().unwrap().history.remove(&asid);
+    });
+
+    hook::kernel_symbol("do_coredump", |cpu, _, _| {
+        if ENABLED.load(Ordering::SeqCst) {
+            on_coredump(cpu);
+        }
+    });
+}
+
+fn on_coredump(cpu: &mut CPUState) {
+    let process = match osi::current_process(cpu) {
+        Some(process) => process,
+        None => return,
+    };
+
+    // the first argument of do_coredump is the siginfo, starting with the signal number
+    let siginfo = regs::get_reg(cpu, FIRST_ARG);
+    let signal = read_guest_type::<i32>(cpu, siginfo as target_ptr_t).ok();
+
+    let (config, history) = {
+        let mut state = STATE.lock().unwrap();
+        let history = state.history.remove(&process.asid).unwrap_or_default();
+        (state.config.clone(), history)
+    };
+
+    let mut bundle = CrashBundle {
+        name: process.name.clone(),
+        pid: process.pid,
+        ppid: process.ppid,
+        asid: process.asid,
+        signal,
+        instr_count: rr_get_guest_instr_count(),
+        dir: PathBuf::new(),
+        backtrace: Vec::new(),
+        syscalls: history.syscalls.into_iter().collect(),
+        coverage: BTreeMap::new(),
+        memory: None,
+    };
+
+    if let Some((pc, callers)) = history.last_entry {
+        bundle.backtrace = std::iter::once(pc)
+            .chain(callers)
+            .map(|addr| frame(cpu, addr))
+            .collect();
+    }
+
+    if config.coverage {
+        let mappings: Vec<_> = crate::mem::mappings(cpu).collect();
+        bundle.coverage = block_count::counts()
+            .into_iter()
+            .filter(|(pc, _)| {
+                mappings
+                    .iter()
+                    .any(|mapping| mapping.contains(*pc as target_ptr_t))
+            })
+            .collect();
+    }
+
+    let name = sanitize::file_name(&bundle.name);
+    bundle.dir = output::subdir(OUTPUT_SUBDIR)
+        .join(format!("{}-{}-{}", name, bundle.pid, bundle.instr_count));
+
+    let written = fs::create_dir_all(&bundle.dir).and_then(|_| write_bundle(&bundle));
+    if let Err(err) = written {
+        eprintln!(
+            "Warning: failed to write crash bundle for {} ({}): {}",
+            sanitize::text(&bundle.name),
+            bundle.pid,
+            err
+        );
+        return;
+    }
+
+    if config.memory {
+        if let Some(process) = OSI.get_current_process(cpu) {
+            match procdump::write_dump(cpu, &*process, &bundle.dir) {
+                Ok(dump) => bundle.memory = Some(dump),
+                Err(err) => eprintln!(
+                    "Warning: failed to dump memory of {} ({}): {}",
+                    sanitize::text(&bundle.name),
+                    bundle.pid,
+                    err
+                ),
+            }
+        }
+    }
+
+    STATE.lock().unwrap().bundles.push(bundle);
+}
+
+fn frame(cpu: &mut CPUState, addr: target_ulong) -> Frame {
+    match find_mapping_containing(cpu, addr as target_ptr_t) {
+        Some(MemoryRegion { base, name, .. }) => Frame {
+            addr,
+            mapping: name,
+            offset: addr - base as target_ulong,
+        },
+        None => Frame {
+            addr,
+            mapping: None,
+            offset: 0,
+        },
+    }
+}
+
+fn write_bundle(bundle: &CrashBundle) -> io::Result<()> {
+    let mut info = File::create(bundle.dir.join("info.txt"))?;
+    writeln!(info, "name: {}", sanitize::text(&bundle.name))?;
+    writeln!(info, "pid: {}", bundle.pid)?;
+    writeln!(info, "ppid: {}", bundle.ppid)?;
+    writeln!(info, "asid: {:#x}", bundle.asid)?;
+    match bundle.signal {
+        Some(signal) => writeln!(
+            info,
+            "signal: {} ({})",
+            signal,
+            signal_name(signal).unwrap_or("unknown")
+        )?,
+        None => writeln!(info, "signal: unknown")?,
+    }
+    if let Some(frame) = bundle.backtrace.first() {
+        writeln!(info, "pc: {:#x}", frame.addr)?;
+    }
+    writeln!(info, "instr_count: {}", bundle.instr_count)?;
+
+    let mut backtrace = File::create(bundle.dir.join("backtrace.txt"))?;
+    for (i, frame) in bundle.backtrace.iter().enumerate() {
+        match &frame.mapping {
+            Some(mapping) => writeln!(
+                backtrace,
+                "#{} {:#x} {}+{:#x}",
+                i,
+                frame.addr,
+                sanitize::text(mapping),
+                frame.offset
+            )?,
+            None => writeln!(backtrace, "#{} {:#x}", i, frame.addr)?,
+        }
+    }
+
+    let mut syscalls = BufWriter::new(File::create(bundle.dir.join("syscalls.txt"))?);
+    for record in &bundle.syscalls {
+        writeln!(syscalls, "[{}] {:?}", record.instr_count, record.syscall)?;
+    }
+    syscalls.flush()?;
+
+    if !bundle.coverage.is_empty() {
+        let mut coverage = BufWriter::new(File::create(bundle.dir.join("coverage.txt"))?);
+        for (pc, count) in &bundle.coverage {
+            writeln!(coverage, "{:#x} {}", pc, count)?;
+        }
+        coverage.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Start writing a bundle for each process killed by a signal which causes a core
+/// dump, collecting what the given config asks for. Calling this again replaces the
+/// config.
+pub fn enable(config: Triage) {
+    callstack_instr::ensure_loaded();
+    if config.coverage {
+        block_count::enable();
+    }
+
+    STATE.lock().unwrap().config = config;
+    lazy_static::initialize(&CALLBACKS);
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Stop writing bundles and forget the history of every process. Bundles written so
+/// far are kept.
+pub fn disable() {
+    ENABLED.store(false, Ordering::SeqCst);
+    STATE.lock().unwrap().history.clear();
+}
+
+/// Get the bundles written so far
+pub fn bundles() -> Vec<CrashBundle> {
+    STATE.lock().unwrap().bundles.clone()
+}
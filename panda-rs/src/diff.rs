@@ -0,0 +1,734 @@
+//! Differential analysis of replays
+//!
+//! Comparing two runs of a program, such as before and after a patch or two variants of
+//! a sample, is done in two steps. Each replay is run with artifacts being collected
+//! (see [`collect`]) and the artifacts are saved to a file with [`Artifacts::save`].
+//! The two sets of artifacts are then compared with [`compare`], producing a
+//! [`DiffReport`] which can be printed or written to a file.
+//!
+//! Three kinds of artifacts can be collected:
+//!
+//! * Coverage: the number of times each basic block was executed
+//! * Syscalls: the sequence of system calls made
+//! * Memory writes: the bytes written to a range of virtual memory
+//!
+//! As the runs being compared generally don't execute in lockstep, syscalls and memory
+//! writes are aligned according to an [`Alignment`]: either by guest instruction count,
+//! for replays of the same recording with different plugin settings, or by syscall
+//! index, for replays of different recordings.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::diff::{self, Alignment, Artifacts, Collect};
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     diff::collect(Collect::new().coverage().syscalls());
+//! }
+//!
+//! #[panda::uninit]
+//! fn uninit(_: &mut PluginHandle) {
+//!     let artifacts = diff::artifacts();
+//!
+//!     // the first run saves a baseline, later runs are compared against it
+//!     match Artifacts::load("baseline.diff") {
+//!         Ok(baseline) => print!("{}", diff::compare(&baseline, &artifacts, Alignment::SyscallIndex)),
+//!         Err(_) => artifacts.save("baseline.diff").unwrap(),
+//!     }
+//! }
+//! ```
+use crate::block_count;
+use crate::plugins::syscalls2::Syscalls2Callbacks;
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+use crate::{current_asid, memcb, Callback, PppCallback};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// A system call made during a replay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallEvent {
+    pub instr_count: u64,
+    pub asid: target_ulong,
+    pub num: target_ulong,
+}
+
+/// A write to guest virtual memory made during a replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub instr_count: u64,
+    pub pc: target_ulong,
+    pub addr: target_ulong,
+    pub data: Vec<u8>,
+}
+
+/// The artifacts collected from a single replay
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Artifacts {
+    /// The number of times each basic block was executed, keyed by the block's pc
+    pub coverage: BTreeMap<target_ulong, u64>,
+
+    /// The syscalls made, in order
+    pub syscalls: Vec<SyscallEvent>,
+
+    /// The memory writes made, in order
+    pub writes: Vec<MemoryWrite>,
+}
+
+fn invalid_data(line: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line + 1, msg),
+    )
+}
+
+fn parse_hex(field: Option<&str>) -> Option<u64> {
+    u64::from_str_radix(field?.trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_bytes(field: &str) -> Option<Vec<u8>> {
+    (0..field.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(field.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl Artifacts {
+    /// Write the artifacts in a line-based text format, one artifact per line
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        for (pc, count) in &self.coverage {
+            writeln!(writer, "block {:#x} {:#x}", pc, count)?;
+        }
+
+        for syscall in &self.syscalls {
+            writeln!(
+                writer,
+                "syscall {:#x} {:#x} {:#x}",
+                syscall.instr_count, syscall.asid, syscall.num
+            )?;
+        }
+
+        for write in &self.writes {
+            write!(
+                writer,
+                "write {:#x} {:#x} {:#x} ",
+                write.instr_count, write.pc, write.addr
+            )?;
+
+            for byte in &write.data {
+                write!(writer, "{:02x}", byte)?;
+            }
+
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read artifacts written by [`Artifacts::write`]
+    pub fn read(reader: impl BufRead) -> io::Result<Self> {
+        let mut artifacts = Self::default();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+
+            let parsed = match fields.next() {
+                Some("block") => (|| {
+                    let pc = parse_hex(fields.next())?;
+                    let count = parse_hex(fields.next())?;
+                    artifacts.coverage.insert(pc as target_ulong, count);
+
+                    Some(())
+                })(),
+                Some("syscall") => (|| {
+                    artifacts.syscalls.push(SyscallEvent {
+                        instr_count: parse_hex(fields.next())?,
+                        asid: parse_hex(fields.next())? as target_ulong,
+                        num: parse_hex(fields.next())? as target_ulong,
+                    });
+
+                    Some(())
+                })(),
+                Some("write") => (|| {
+                    artifacts.writes.push(MemoryWrite {
+                        instr_count: parse_hex(fields.next())?,
+                        pc: parse_hex(fields.next())? as target_ulong,
+                        addr: parse_hex(fields.next())? as target_ulong,
+                        data: parse_bytes(fields.next().unwrap_or(""))?,
+                    });
+
+                    Some(())
+                })(),
+                None => Some(()),
+                Some(_) => return Err(invalid_data(i, "unknown artifact kind")),
+            };
+
+            if parsed.is_none() {
+                return Err(invalid_data(i, "malformed artifact"));
+            }
+        }
+
+        Ok(artifacts)
+    }
+
+    /// Save the artifacts to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+
+        writer.flush()
+    }
+
+    /// Load artifacts saved by [`Artifacts::save`]
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// Which artifacts to collect during a replay
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Collect {
+    coverage: bool,
+    syscalls: bool,
+    writes: Vec<Range<target_ulong>>,
+}
+
+impl Collect {
+    /// Collect nothing, to be added to using the other methods
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect the number of times each basic block is executed
+    pub fn coverage(mut self) -> Self {
+        self.coverage = true;
+        self
+    }
+
+    /// Collect the sequence of syscalls made. Requires the syscalls2 plugin.
+    pub fn syscalls(mut self) -> Self {
+        self.syscalls = true;
+        self
+    }
+
+    /// Collect writes to the given range of virtual memory. Enables memory callbacks,
+    /// which slows down execution considerably.
+    pub fn memory_writes(mut self, range: Range<target_ulong>) -> Self {
+        self.writes.push(range);
+        self
+    }
+}
+
+#[derive(Default)]
+struct Collector {
+    config: Collect,
+    syscalls: Vec<SyscallEvent>,
+    writes: Vec<MemoryWrite>,
+
+    /// The callbacks collecting artifacts, once installed
+    syscall_callback: Option<PppCallback>,
+    write_callback: Option<Callback>,
+}
+
+lazy_static::lazy_static! {
+    static ref COLLECTOR: Mutex<Collector> = Mutex::new(Collector::default());
+}
+
+fn install_syscall_callback() -> PppCallback {
+    let callback = PppCallback::new();
+
+    callback.on_all_sys_enter(|cpu, _, num| {
+        let syscall = SyscallEvent {
+            instr_count: rr_get_guest_instr_count(),
+            asid: current_asid(cpu),
+            num,
+        };
+
+        COLLECTOR.lock().unwrap().syscalls.push(syscall);
+    });
+
+    callback
+}
+
+fn install_write_callback() -> Callback {
+    // memory callbacks are needed until the end of the replay
+    memcb::demand_all().forget();
+
+    let callback = Callback::new();
+    callback.virt_mem_after_write(|_, pc, addr, size, buf| {
+        let mut collector = COLLECTOR.lock().unwrap();
+        let end = addr + size as target_ulong;
+        if !collector
+            .config
+            .writes
+            .iter()
+            .any(|range| range.start < end && addr < range.end)
+        {
+            return;
+        }
+
+        let data = unsafe { std::slice::from_raw_parts(buf, size) }.to_vec();
+        collector.writes.push(MemoryWrite {
+            instr_count: rr_get_guest_instr_count(),
+            pc,
+            addr,
+            data,
+        });
+    });
+
+    callback
+}
+
+/// Start collecting artifacts. Calling this again replaces the artifacts being
+/// collected, keeping those collected so far.
+pub fn collect(config: Collect) {
+    if config.coverage {
+        block_count::enable();
+    }
+
+    let mut collector = COLLECTOR.lock().unwrap();
+
+    match (config.syscalls, collector.syscall_callback) {
+        (true, None) => collector.syscall_callback = Some(install_syscall_callback()),
+        (true, Some(callback)) => callback.enable(),
+        (false, Some(callback)) => callback.disable(),
+        (false, None) => (),
+    }
+
+    match (!config.writes.is_empty(), collector.write_callback) {
+        (true, None) => collector.write_callback = Some(install_write_callback()),
+        (true, Some(callback)) => callback.enable(),
+        (false, Some(callback)) => callback.disable(),
+        (false, None) => (),
+    }
+
+    collector.config = config;
+}
+
+/// Get the artifacts collected so far
+pub fn artifacts() -> Artifacts {
+    let collector = COLLECTOR.lock().unwrap();
+    let coverage = if collector.config.coverage {
+        block_count::counts().into_iter().collect()
+    } else {
+        BTreeMap::new()
+    };
+
+    Artifacts {
+        coverage,
+        syscalls: collector.syscalls.clone(),
+        writes: collector.writes.clone(),
+    }
+}
+
+/// How events of two replays are matched up with each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// Events at the same guest instruction count are compared. Suited to replays of the
+    /// same recording.
+    InstrCount,
+
+    /// The nth syscall of each replay is compared, and memory writes are compared
+    /// between the same pair of syscalls. Suited to replays of different recordings.
+    SyscallIndex,
+}
+
+/// A difference in how often a basic block was executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoverageDiff {
+    pub pc: target_ulong,
+    pub count_a: u64,
+    pub count_b: u64,
+}
+
+/// A syscall made by only one replay, or a pair of aligned syscalls which differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallDiff {
+    /// The position of the syscalls, an instruction count or syscall index depending on
+    /// the alignment used
+    pub position: u64,
+    pub a: Option<SyscallEvent>,
+    pub b: Option<SyscallEvent>,
+}
+
+/// A range of memory whose contents differ after being written by either replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteDiff {
+    /// The position of the writes, an instruction count or the number of syscalls made
+    /// before the writes depending on the alignment used
+    pub position: u64,
+    pub addr: target_ulong,
+
+    /// The bytes written by each replay, with `None` for bytes it didn't write
+    pub a: Vec<Option<u8>>,
+    pub b: Vec<Option<u8>>,
+}
+
+/// The differences between the artifacts of two replays
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffReport {
+    pub alignment: Alignment,
+
+    /// Blocks executed a different number of times (including blocks only executed by
+    /// one replay, which have a count of zero in the other)
+    pub coverage: Vec<CoverageDiff>,
+    pub syscalls: Vec<SyscallDiff>,
+    pub writes: Vec<WriteDiff>,
+}
+
+impl DiffReport {
+    /// Check whether no differences were found
+    pub fn is_empty(&self) -> bool {
+        self.coverage.is_empty() && self.syscalls.is_empty() && self.writes.is_empty()
+    }
+
+    /// Blocks only executed by the first replay
+    pub fn only_in_a(&self) -> impl Iterator<Item = target_ulong> + '_ {
+        self.coverage
+            .iter()
+            .filter(|diff| diff.count_b == 0)
+            .map(|diff| diff.pc)
+    }
+
+    /// Blocks only executed by the second replay
+    pub fn only_in_b(&self) -> impl Iterator<Item = target_ulong> + '_ {
+        self.coverage
+            .iter()
+            .filter(|diff| diff.count_a == 0)
+            .map(|diff| diff.pc)
+    }
+
+    /// The position of the first aligned syscall which differs between the replays, if
+    /// any
+    pub fn first_syscall_divergence(&self) -> Option<u64> {
+        self.syscalls.first().map(|diff| diff.position)
+    }
+
+    /// Write the report as text
+    pub fn write_report(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "{}", self)
+    }
+}
+
+fn fmt_syscall(syscall: &Option<SyscallEvent>) -> String {
+    match syscall {
+        Some(syscall) => format!("{} (asid {:#x})", syscall.num, syscall.asid),
+        None => String::from("-"),
+    }
+}
+
+fn fmt_bytes(bytes: &[Option<u8>]) -> String {
+    bytes
+        .iter()
+        .map(|byte| match byte {
+            Some(byte) => format!("{:02x}", byte),
+            None => String::from("??"),
+        })
+        .collect()
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let position = match self.alignment {
+            Alignment::InstrCount => "instr",
+            Alignment::SyscallIndex => "syscall",
+        };
+
+        writeln!(
+            f,
+            "coverage: {} blocks only in a, {} blocks only in b, {} blocks with different counts",
+            self.only_in_a().count(),
+            self.only_in_b().count(),
+            self.coverage
+                .iter()
+                .filter(|diff| diff.count_a != 0 && diff.count_b != 0)
+                .count(),
+        )?;
+
+        for diff in &self.coverage {
+            writeln!(f, "  {:#x}: {} -> {}", diff.pc, diff.count_a, diff.count_b)?;
+        }
+
+        writeln!(f, "syscalls: {} differences", self.syscalls.len())?;
+        for diff in &self.syscalls {
+            writeln!(
+                f,
+                "  {} {}: {} -> {}",
+                position,
+                diff.position,
+                fmt_syscall(&diff.a),
+                fmt_syscall(&diff.b)
+            )?;
+        }
+
+        writeln!(f, "memory writes: {} differences", self.writes.len())?;
+        for diff in &self.writes {
+            writeln!(
+                f,
+                "  {} {}: {:#x}: {} -> {}",
+                position,
+                diff.position,
+                diff.addr,
+                fmt_bytes(&diff.a),
+                fmt_bytes(&diff.b)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn diff_coverage(a: &Artifacts, b: &Artifacts) -> Vec<CoverageDiff> {
+    let pcs: BTreeSet<_> = a.coverage.keys().chain(b.coverage.keys()).collect();
+
+    pcs.into_iter()
+        .map(|&pc| CoverageDiff {
+            pc,
+            count_a: a.coverage.get(&pc).copied().unwrap_or(0),
+            count_b: b.coverage.get(&pc).copied().unwrap_or(0),
+        })
+        .filter(|diff| diff.count_a != diff.count_b)
+        .collect()
+}
+
+/// Key each syscall by its position under the given alignment. Syscalls at the same
+/// instruction count are made by different CPUs, so are further ordered by index.
+fn syscall_positions(
+    artifacts: &Artifacts,
+    alignment: Alignment,
+) -> BTreeMap<(u64, usize), SyscallEvent> {
+    let mut positions = BTreeMap::new();
+    for (i, &syscall) in artifacts.syscalls.iter().enumerate() {
+        let key = match alignment {
+            Alignment::InstrCount => {
+                let same_instr = positions
+                    .range((syscall.instr_count, 0)..=(syscall.instr_count, usize::MAX))
+                    .count();
+
+                (syscall.instr_count, same_instr)
+            }
+            Alignment::SyscallIndex => (i as u64, 0),
+        };
+
+        positions.insert(key, syscall);
+    }
+
+    positions
+}
+
+fn diff_syscalls(a: &Artifacts, b: &Artifacts, alignment: Alignment) -> Vec<SyscallDiff> {
+    let a = syscall_positions(a, alignment);
+    let b = syscall_positions(b, alignment);
+    let keys: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let (a, b) = (a.get(key).copied(), b.get(key).copied());
+
+            // syscalls are matched by number, as asids differ between recordings
+            let same = match (a, b) {
+                (Some(a), Some(b)) => alignment == Alignment::SyscallIndex || a.asid == b.asid,
+                _ => false,
+            } && a.map(|a| a.num) == b.map(|b| b.num);
+
+            (!same).then(|| SyscallDiff {
+                position: key.0,
+                a,
+                b,
+            })
+        })
+        .collect()
+}
+
+/// The final value of each byte written, keyed by position and address
+fn memory_state(artifacts: &Artifacts, alignment: Alignment) -> BTreeMap<(u64, target_ulong), u8> {
+    let mut syscall_counts: Vec<_> = artifacts.syscalls.iter().map(|s| s.instr_count).collect();
+    syscall_counts.sort_unstable();
+
+    let mut state = BTreeMap::new();
+    for write in &artifacts.writes {
+        let position = match alignment {
+            Alignment::InstrCount => write.instr_count,
+            Alignment::SyscallIndex => {
+                syscall_counts.partition_point(|&count| count <= write.instr_count) as u64
+            }
+        };
+
+        for (i, &byte) in write.data.iter().enumerate() {
+            state.insert((position, write.addr + i as target_ulong), byte);
+        }
+    }
+
+    state
+}
+
+fn diff_writes(a: &Artifacts, b: &Artifacts, alignment: Alignment) -> Vec<WriteDiff> {
+    let a = memory_state(a, alignment);
+    let b = memory_state(b, alignment);
+    let keys: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+
+    let mut diffs: Vec<WriteDiff> = Vec::new();
+    for &(position, addr) in keys {
+        let (byte_a, byte_b) = (a.get(&(position, addr)), b.get(&(position, addr)));
+        if byte_a == byte_b {
+            continue;
+        }
+
+        // merge adjacent differing bytes into a single range
+        match diffs.last_mut() {
+            Some(last)
+                if last.position == position
+                    && last.addr + last.a.len() as target_ulong == addr =>
+            {
+                last.a.push(byte_a.copied());
+                last.b.push(byte_b.copied());
+            }
+            _ => diffs.push(WriteDiff {
+                position,
+                addr,
+                a: vec![byte_a.copied()],
+                b: vec![byte_b.copied()],
+            }),
+        }
+    }
+
+    diffs
+}
+
+/// Compare the artifacts of two replays. Only artifacts collected for both replays are
+/// meaningful, as a kind of artifact collected for only one replay will show up as
+/// entirely different.
+pub fn compare(a: &Artifacts, b: &Artifacts, alignment: Alignment) -> DiffReport {
+    DiffReport {
+        alignment,
+        coverage: diff_coverage(a, b),
+        syscalls: diff_syscalls(a, b, alignment),
+        writes: diff_writes(a, b, alignment),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syscall(instr_count: u64, num: target_ulong) -> SyscallEvent {
+        SyscallEvent {
+            instr_count,
+            asid: 0x1000,
+            num,
+        }
+    }
+
+    fn write(instr_count: u64, addr: target_ulong, data: &[u8]) -> MemoryWrite {
+        MemoryWrite {
+            instr_count,
+            pc: 0x400000,
+            addr,
+            data: data.to_vec(),
+        }
+    }
+
+    fn artifacts() -> Artifacts {
+        Artifacts {
+            coverage: vec![(0x400000, 1), (0x400010, 3)].into_iter().collect(),
+            syscalls: vec![syscall(10, 0), syscall(20, 1)],
+            writes: vec![write(15, 0x8000, &[1, 2, 3, 4])],
+        }
+    }
+
+    #[test]
+    fn test_write_read_roundtrip() {
+        let artifacts = artifacts();
+
+        let mut buf = Vec::new();
+        artifacts.write(&mut buf).unwrap();
+
+        assert_eq!(Artifacts::read(&buf[..]).unwrap(), artifacts);
+    }
+
+    #[test]
+    fn test_read_rejects_malformed() {
+        assert!(Artifacts::read(&b"block 0x400000\n"[..]).is_err());
+        assert!(Artifacts::read(&b"branch 0x400000 0x1\n"[..]).is_err());
+        assert!(Artifacts::read(&b"write 0x1 0x2 0x3 0g\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_compare_identical() {
+        let report = compare(&artifacts(), &artifacts(), Alignment::InstrCount);
+
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_compare_coverage() {
+        let a = artifacts();
+        let mut b = artifacts();
+        b.coverage.remove(&0x400000);
+        b.coverage.insert(0x400010, 4);
+        b.coverage.insert(0x400020, 1);
+
+        let report = compare(&a, &b, Alignment::InstrCount);
+
+        assert_eq!(report.only_in_a().collect::<Vec<_>>(), vec![0x400000]);
+        assert_eq!(report.only_in_b().collect::<Vec<_>>(), vec![0x400020]);
+        assert_eq!(report.coverage.len(), 3);
+    }
+
+    #[test]
+    fn test_compare_syscalls_by_alignment() {
+        let a = artifacts();
+        let mut b = artifacts();
+
+        // the same syscalls, made later
+        for syscall in &mut b.syscalls {
+            syscall.instr_count += 5;
+        }
+
+        assert!(compare(&a, &b, Alignment::SyscallIndex).syscalls.is_empty());
+
+        let report = compare(&a, &b, Alignment::InstrCount);
+        assert_eq!(report.syscalls.len(), 4);
+        assert_eq!(report.first_syscall_divergence(), Some(10));
+    }
+
+    #[test]
+    fn test_compare_writes_merges_adjacent_bytes() {
+        let a = artifacts();
+        let mut b = artifacts();
+        b.writes = vec![write(15, 0x8000, &[1, 9, 9, 4])];
+
+        let report = compare(&a, &b, Alignment::InstrCount);
+
+        assert_eq!(
+            report.writes,
+            vec![WriteDiff {
+                position: 15,
+                addr: 0x8001,
+                a: vec![Some(2), Some(3)],
+                b: vec![Some(9), Some(9)],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_compare_writes_by_syscall_index() {
+        let a = artifacts();
+        let mut b = artifacts();
+        b.syscalls = vec![syscall(100, 0), syscall(200, 1)];
+        b.writes = vec![write(150, 0x8000, &[1, 2, 3, 4])];
+
+        // written between the same pair of syscalls in both
+        assert!(compare(&a, &b, Alignment::SyscallIndex).writes.is_empty());
+        assert!(!compare(&a, &b, Alignment::InstrCount).writes.is_empty());
+    }
+}
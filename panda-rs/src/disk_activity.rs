@@ -0,0 +1,149 @@
+//! Disk accesses in a replay, reported as accesses to files
+//!
+//! PANDA reports transfers to and from the guest's disk as raw disk offsets. This
+//! module maps those offsets back to the files stored there by parsing the filesystem
+//! of the guest's disk image with [`guestfs`](crate::guestfs), so that low-level disk
+//! activity can be linked to the files being read and written.
+//!
+//! Accesses are reported to callbacks registered with [`on_file_block_read`] and
+//! [`on_file_block_write`], or with the matching attribute macros
+//! ([`#[panda::on_file_block_read]`](macro@crate::on_file_block_read) and
+//! [`#[panda::on_file_block_write]`](macro@crate::on_file_block_write)), once a disk
+//! image has been loaded with [`load`]. An access spanning several files is reported
+//! once per file, and accesses to filesystem metadata or free space are not reported.
+//!
+//! Disk transfers are only reported by PANDA while replaying. The image should be in
+//! the state the recording was made from, as files the guest creates or moves during
+//! the recording aren't in the map.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::guestfs::FileBlock;
+//! use panda::prelude::*;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     panda::disk_activity::load("bionic-server-cloudimg-amd64-noaslr-nokaslr.qcow2")
+//!         .unwrap();
+//! }
+//!
+//! #[panda::on_file_block_read]
+//! fn file_read(_: &mut CPUState, block: &FileBlock) {
+//!     println!(
+//!         "read {} bytes of {} at offset {:#x}",
+//!         block.len, block.path, block.file_offset
+//!     );
+//! }
+//! ```
+use crate::guestfs::{self, BlockMap, FileBlock, GuestFsError};
+use crate::prelude::*;
+use crate::Callback;
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+type FileBlockCallback = Box<dyn FnMut(&mut CPUState, &FileBlock) + Send + 'static>;
+
+#[derive(Default)]
+struct State {
+    map: Option<Arc<BlockMap>>,
+    reads: Vec<FileBlockCallback>,
+    writes: Vec<FileBlockCallback>,
+}
+
+impl State {
+    fn callbacks(&mut self, is_write: bool) -> &mut Vec<FileBlockCallback> {
+        if is_write {
+            &mut self.writes
+        } else {
+            &mut self.reads
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+    static ref CALLBACK: Callback = install_callback();
+}
+
+fn install_callback() -> Callback {
+    let transfer = Callback::new();
+
+    transfer.replay_hd_transfer(|cpu, kind, src, dest, num_bytes| {
+        let (is_write, disk_offset) = match kind {
+            panda_sys::Hd_transfer_type_HD_TRANSFER_HD_TO_IOB
+            | panda_sys::Hd_transfer_type_HD_TRANSFER_HD_TO_RAM => (false, src),
+            panda_sys::Hd_transfer_type_HD_TRANSFER_IOB_TO_HD
+            | panda_sys::Hd_transfer_type_HD_TRANSFER_RAM_TO_HD => (true, dest),
+
+            // transfers between the I/O buffer and ports don't touch the disk
+            _ => return,
+        };
+
+        // run the callbacks unlocked so that they can register further callbacks or
+        // load an image
+        let (map, mut callbacks) = {
+            let mut state = STATE.lock().unwrap();
+            let map = match &state.map {
+                Some(map) => Arc::clone(map),
+                None => return,
+            };
+
+            if state.callbacks(is_write).is_empty() {
+                return;
+            }
+
+            (map, std::mem::take(state.callbacks(is_write)))
+        };
+
+        for block in &map.lookup(disk_offset as u64, num_bytes as u64) {
+            for callback in &mut callbacks {
+                callback(cpu, block);
+            }
+        }
+
+        let mut state = STATE.lock().unwrap();
+        let list = state.callbacks(is_write);
+        callbacks.append(list);
+        *list = callbacks;
+    });
+
+    transfer
+}
+
+/// Map disk accesses using the first supported filesystem in the given disk image,
+/// replacing any image loaded previously. See [`guestfs::open`].
+pub fn load(image: impl AsRef<Path>) -> Result<(), GuestFsError> {
+    set_block_map(guestfs::open(image)?.block_map()?);
+
+    Ok(())
+}
+
+/// Map disk accesses using the filesystem in the given partition of a disk image,
+/// replacing any image loaded previously. See [`guestfs::open_partition`].
+pub fn load_partition(image: impl AsRef<Path>, index: usize) -> Result<(), GuestFsError> {
+    set_block_map(guestfs::open_partition(image, index)?.block_map()?);
+
+    Ok(())
+}
+
+/// Map disk accesses using an already built [`BlockMap`], replacing any image loaded
+/// previously
+pub fn set_block_map(map: BlockMap) {
+    STATE.lock().unwrap().map = Some(Arc::new(map));
+}
+
+/// Register a callback to be run whenever the guest reads part of a file from disk.
+pub fn on_file_block_read(callback: impl FnMut(&mut CPUState, &FileBlock) + Send + 'static) {
+    lazy_static::initialize(&CALLBACK);
+
+    STATE.lock().unwrap().reads.push(Box::new(callback));
+}
+
+/// Register a callback to be run whenever the guest writes part of a file to disk.
+pub fn on_file_block_write(callback: impl FnMut(&mut CPUState, &FileBlock) + Send + 'static) {
+    lazy_static::initialize(&CALLBACK);
+
+    STATE.lock().unwrap().writes.push(Box::new(callback));
+}
@@ -0,0 +1,232 @@
+//! Looking up the dynamic symbols of an ELF shared object loaded in guest memory
+use crate::mem::virtual_memory_read;
+use crate::prelude::*;
+
+use std::convert::TryInto;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+
+const DT_NULL: u64 = 0;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_SYMENT: u64 = 11;
+
+const MAX_DYNAMIC_ENTRIES: usize = 512;
+const MAX_SYMBOLS: u64 = 1 << 20;
+
+/// The most bytes of program headers to read, well above what any real object has
+const MAX_PHDRS_SIZE: u64 = 0x10000;
+
+/// The size of a symbol table entry
+const SYM_SIZE_32: u64 = 0x10;
+const SYM_SIZE_64: u64 = 0x18;
+
+/// The dynamic symbol table of an ELF shared object loaded in guest memory
+struct Elf {
+    is_64: bool,
+    big_endian: bool,
+    bias: target_ptr_t,
+    symtab: target_ptr_t,
+    strtab: target_ptr_t,
+    syment: u64,
+    symbol_count: u64,
+}
+
+impl Elf {
+    fn load(cpu: &mut CPUState, base: target_ptr_t) -> Option<Self> {
+        let header = virtual_memory_read(cpu, base as target_ulong, 64).ok()?;
+        if header.get(..4)? != b"\x7fELF" {
+            return None;
+        }
+
+        let mut elf = Elf {
+            is_64: *header.get(4)? == 2,
+            big_endian: *header.get(5)? == 2,
+            bias: 0,
+            symtab: 0,
+            strtab: 0,
+            syment: 0,
+            symbol_count: 0,
+        };
+
+        let (phoff, phentsize, phnum) = if elf.is_64 {
+            (
+                elf.int(header.get(0x20..0x28)?),
+                elf.int(header.get(0x36..0x38)?),
+                elf.int(header.get(0x38..0x3a)?),
+            )
+        } else {
+            (
+                elf.int(header.get(0x1c..0x20)?),
+                elf.int(header.get(0x2a..0x2c)?),
+                elf.int(header.get(0x2c..0x2e)?),
+            )
+        };
+
+        if phentsize < if elf.is_64 { 0x38 } else { 0x20 } {
+            return None;
+        }
+
+        let phdrs_size = phentsize.checked_mul(phnum)?;
+        if phdrs_size > MAX_PHDRS_SIZE {
+            return None;
+        }
+
+        let phdrs = virtual_memory_read(
+            cpu,
+            base.checked_add(phoff.try_into().ok()?)? as target_ulong,
+            phdrs_size as usize,
+        )
+        .ok()?;
+
+        let mut first_load = None;
+        let mut dynamic = None;
+        for phdr in phdrs.chunks_exact(phentsize as usize) {
+            let p_type = elf.int(phdr.get(..4)?) as u32;
+            let p_vaddr = if elf.is_64 {
+                elf.int(phdr.get(0x10..0x18)?)
+            } else {
+                elf.int(phdr.get(8..0xc)?)
+            };
+
+            match p_type {
+                PT_LOAD if first_load.is_none() => first_load = Some(p_vaddr),
+                PT_DYNAMIC => dynamic = Some(p_vaddr),
+                _ => (),
+            }
+        }
+
+        elf.bias = base.wrapping_sub((first_load? & !0xfff) as target_ptr_t);
+        let dynamic = elf.bias.wrapping_add(dynamic? as target_ptr_t);
+
+        let entry_size = if elf.is_64 { 16 } else { 8 };
+        let mut hash = None;
+        for i in 0..MAX_DYNAMIC_ENTRIES {
+            let addr = dynamic.checked_add((i * entry_size) as target_ptr_t)?;
+            let entry = virtual_memory_read(cpu, addr as target_ulong, entry_size).ok()?;
+            let (tag, value) = entry.split_at(entry_size / 2);
+            let (tag, value) = (elf.int(tag), elf.int(value));
+
+            match tag {
+                DT_NULL => break,
+                DT_HASH => hash = Some(elf.ptr(base, value)),
+                DT_STRTAB => elf.strtab = elf.ptr(base, value),
+                DT_SYMTAB => elf.symtab = elf.ptr(base, value),
+                DT_SYMENT => elf.syment = value,
+                _ => (),
+            }
+        }
+
+        let min_syment = if elf.is_64 { SYM_SIZE_64 } else { SYM_SIZE_32 };
+        if elf.symtab == 0 || elf.strtab == 0 || elf.syment < min_syment {
+            return None;
+        }
+
+        // The number of symbols is given by the number of chains in the hash table.
+        // Objects with only a GNU hash table almost always place the string table
+        // directly after the symbol table, so fall back to the space between them.
+        elf.symbol_count = match hash {
+            Some(hash) => {
+                let nchain =
+                    virtual_memory_read(cpu, hash.checked_add(4)? as target_ulong, 4).ok()?;
+                elf.int(&nchain)
+            }
+            None if elf.strtab > elf.symtab => (elf.strtab - elf.symtab) as u64 / elf.syment,
+            None => return None,
+        };
+
+        Some(elf)
+    }
+
+    /// Read an integer of the object's byte order, of at most 8 bytes
+    fn int(&self, bytes: &[u8]) -> u64 {
+        let mut buf = [0; 8];
+        if self.big_endian {
+            buf[8 - bytes.len()..].copy_from_slice(bytes);
+            u64::from_be_bytes(buf)
+        } else {
+            buf[..bytes.len()].copy_from_slice(bytes);
+            u64::from_le_bytes(buf)
+        }
+    }
+
+    /// Pointers in the dynamic section are relocated by some loaders but not others
+    fn ptr(&self, base: target_ptr_t, value: u64) -> target_ptr_t {
+        let value = value as target_ptr_t;
+        if value >= base {
+            value
+        } else {
+            self.bias.wrapping_add(value)
+        }
+    }
+
+    /// Read the name, section index, value and size of symbol `index`
+    fn symbol(&self, cpu: &mut CPUState, index: u64) -> Option<(u64, u64, u64, u64)> {
+        let offset = index.checked_mul(self.syment)?;
+        let addr = self.symtab.checked_add(offset.try_into().ok()?)?;
+        let sym = virtual_memory_read(cpu, addr as target_ulong, self.syment as usize).ok()?;
+
+        if self.is_64 {
+            Some((
+                self.int(sym.get(..4)?),
+                self.int(sym.get(6..8)?),
+                self.int(sym.get(8..0x10)?),
+                self.int(sym.get(0x10..0x18)?),
+            ))
+        } else {
+            Some((
+                self.int(sym.get(..4)?),
+                self.int(sym.get(0xe..0x10)?),
+                self.int(sym.get(4..8)?),
+                self.int(sym.get(8..0xc)?),
+            ))
+        }
+    }
+
+    /// Find the address and size of the defined symbol with the given name
+    fn find_symbol(&self, cpu: &mut CPUState, name: &str) -> Option<(target_ptr_t, target_ptr_t)> {
+        let mut expected = name.as_bytes().to_vec();
+        expected.push(0);
+
+        for i in 0..self.symbol_count.min(MAX_SYMBOLS) {
+            let (st_name, st_shndx, st_value, st_size) = match self.symbol(cpu, i) {
+                Some(sym) => sym,
+                None => continue,
+            };
+
+            // Skip undefined symbols, which are imports from other objects
+            if st_shndx == 0 || st_value == 0 {
+                continue;
+            }
+
+            let name_addr = match st_name
+                .try_into()
+                .ok()
+                .and_then(|st_name| self.strtab.checked_add(st_name))
+            {
+                Some(addr) => addr,
+                None => continue,
+            };
+
+            let sym_name = virtual_memory_read(cpu, name_addr as target_ulong, expected.len());
+            if sym_name.map_or(false, |sym_name| sym_name == expected) {
+                let addr = self.bias.wrapping_add(st_value as target_ptr_t);
+                return Some((addr, st_size.try_into().unwrap_or(target_ptr_t::MAX)));
+            }
+        }
+
+        None
+    }
+}
+
+/// Look up the address and size of a dynamic symbol defined by the shared object whose
+/// ELF header is mapped at `base` in the current process
+pub(crate) fn find_symbol(
+    cpu: &mut CPUState,
+    base: target_ptr_t,
+    name: &str,
+) -> Option<(target_ptr_t, target_ptr_t)> {
+    Elf::load(cpu, base)?.find_symbol(cpu, name)
+}
@@ -1,5 +1,6 @@
 use thiserror::Error;
 use std::os::raw::c_int;
+use std::path::PathBuf;
 
 // Top-level -----------------------------------------------------------------------------------------------------------
 
@@ -11,8 +12,17 @@ pub enum Error {
     #[error("The provided size was not properly page-aligned")]
     UnalignedPageSize,
 
+    #[error("The provided address was not properly page-aligned")]
+    UnalignedAddress,
+
+    #[error("The provided region overlaps the already mapped region {0:?}")]
+    OverlappingRegion(String),
+
+    #[error(transparent)]
+    RecordReplayError(#[from] RrError),
+
     #[error(transparent)]
-    RecordReplayError(#[from] RrError)
+    PluginError(#[from] PluginError)
 }
 
 // Transparent Subclasses ----------------------------------------------------------------------------------------------
@@ -26,6 +36,27 @@ pub enum RrError {
     RrCtrlEPending,
 }
 
+#[derive(Debug, Error)]
+pub enum PluginError {
+    #[error("PANDA_PATH not set and PANDA is not installed globally")]
+    PandaNotFound,
+
+    #[error("Could not find panda plugin dir, consider setting PANDA_PLUGIN_DIR")]
+    PluginDirNotFound,
+
+    #[error("Could not find plugin {name} at {}", .path.display())]
+    PluginNotFound { name: String, path: PathBuf },
+
+    #[error("Failed to load plugin {name}")]
+    LoadFailed { name: String, source: libloading::Error },
+
+    #[error("Could not find symbol {symbol} in plugin {plugin}")]
+    SymbolNotFound { plugin: String, symbol: String, source: libloading::Error },
+
+    #[error("Plugin {0} has been unloaded")]
+    Unloaded(String),
+}
+
 impl RrError {
     pub fn translate_err_code(code: c_int) -> Result<(), Error> {
         match code {
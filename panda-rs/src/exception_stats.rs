@@ -0,0 +1,230 @@
+//! Statistics on CPU exceptions and interrupts
+//!
+//! This module records counts of the interrupts and exceptions the guest handles, broken
+//! down by vector and by process (ASID), along with an optional bounded timeline of the
+//! most recent events. It is built on the [`before_handle_interrupt`] and
+//! [`before_handle_exception`] callbacks, and only does a couple of map updates per event, so it
+//! can be left enabled for long-running analyses of interrupt-heavy firmware.
+//!
+//! [`before_handle_interrupt`]: crate::before_handle_interrupt
+//! [`before_handle_exception`]: crate::before_handle_exception
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::exception_stats;
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     exception_stats::set_timeline_capacity(1000);
+//!     exception_stats::enable();
+//! }
+//!
+//! #[panda::uninit]
+//! fn uninit(_: &mut PluginHandle) {
+//!     let file = std::fs::File::create("exceptions.csv").unwrap();
+//!     exception_stats::write_csv(file).unwrap();
+//! }
+//! ```
+
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+use crate::{current_asid, Callback};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Whether an event was an interrupt or an exception
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EventKind {
+    Interrupt,
+    Exception,
+}
+
+/// A single recorded interrupt or exception
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+
+    /// The exception index as reported by PANDA (for interrupts, the pending interrupt
+    /// request)
+    pub vector: i32,
+
+    /// The address space the guest was executing in when the event was handled
+    pub asid: target_ulong,
+
+    /// The guest instruction count at the time of the event. Only meaningful while
+    /// recording or replaying.
+    pub instr_count: u64,
+}
+
+/// A snapshot of the statistics recorded so far
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    /// Number of events for each kind/vector pair
+    pub counts: BTreeMap<(EventKind, i32), u64>,
+
+    /// Number of events for each asid/kind/vector triple
+    pub per_asid: BTreeMap<(target_ulong, EventKind, i32), u64>,
+
+    /// The most recent events, oldest first. Bounded by [`set_timeline_capacity`].
+    pub timeline: Vec<Event>,
+}
+
+impl Stats {
+    /// Get the number of times a given vector was handled
+    pub fn count(&self, kind: EventKind, vector: i32) -> u64 {
+        self.counts.get(&(kind, vector)).copied().unwrap_or(0)
+    }
+
+    /// Get the number of times a given vector was handled while in a given asid
+    pub fn count_for_asid(&self, asid: target_ulong, kind: EventKind, vector: i32) -> u64 {
+        self.per_asid
+            .get(&(asid, kind, vector))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Get the total number of events of a given kind
+    pub fn total(&self, kind: EventKind) -> u64 {
+        self.counts
+            .iter()
+            .filter(|((event_kind, _), _)| *event_kind == kind)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+#[derive(Default)]
+struct Recorder {
+    stats: Stats,
+    timeline: VecDeque<Event>,
+    timeline_capacity: usize,
+}
+
+impl Recorder {
+    fn record(&mut self, event: Event) {
+        *self
+            .stats
+            .counts
+            .entry((event.kind, event.vector))
+            .or_default() += 1;
+        *self
+            .stats
+            .per_asid
+            .entry((event.asid, event.kind, event.vector))
+            .or_default() += 1;
+
+        if self.timeline_capacity != 0 {
+            if self.timeline.len() == self.timeline_capacity {
+                self.timeline.pop_front();
+            }
+
+            self.timeline.push_back(event);
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Mutex<Recorder> = Mutex::new(Recorder::default());
+    static ref CALLBACKS: (Callback, Callback) = install_callbacks();
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn record(cpu: &mut CPUState, kind: EventKind, vector: i32) {
+    let event = Event {
+        kind,
+        vector,
+        asid: current_asid(cpu),
+        instr_count: rr_get_guest_instr_count(),
+    };
+
+    RECORDER.lock().unwrap().record(event);
+}
+
+fn install_callbacks() -> (Callback, Callback) {
+    let interrupt = Callback::new();
+    let exception = Callback::new();
+
+    interrupt.before_handle_interrupt(|cpu, index| {
+        record(cpu, EventKind::Interrupt, index);
+        index
+    });
+
+    exception.before_handle_exception(|cpu, index| {
+        record(cpu, EventKind::Exception, index);
+        index
+    });
+
+    (interrupt, exception)
+}
+
+/// Start recording interrupt and exception statistics
+pub fn enable() {
+    if !ENABLED.swap(true, Ordering::SeqCst) {
+        let (interrupt, exception) = &*CALLBACKS;
+        interrupt.enable();
+        exception.enable();
+    }
+}
+
+/// Stop recording interrupt and exception statistics. Statistics recorded so far are
+/// kept until [`reset`] is called.
+pub fn disable() {
+    if ENABLED.swap(false, Ordering::SeqCst) {
+        let (interrupt, exception) = &*CALLBACKS;
+        interrupt.disable();
+        exception.disable();
+    }
+}
+
+/// Check whether statistics are currently being recorded
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Set the maximum number of events kept in the timeline. Defaults to 0, meaning no
+/// timeline is kept and only counts are recorded.
+pub fn set_timeline_capacity(capacity: usize) {
+    let mut recorder = RECORDER.lock().unwrap();
+
+    recorder.timeline_capacity = capacity;
+    while recorder.timeline.len() > capacity {
+        recorder.timeline.pop_front();
+    }
+}
+
+/// Get a snapshot of the statistics recorded so far
+pub fn stats() -> Stats {
+    let recorder = RECORDER.lock().unwrap();
+
+    Stats {
+        timeline: recorder.timeline.iter().copied().collect(),
+        ..recorder.stats.clone()
+    }
+}
+
+/// Clear all statistics recorded so far
+pub fn reset() {
+    let mut recorder = RECORDER.lock().unwrap();
+
+    recorder.stats = Stats::default();
+    recorder.timeline.clear();
+}
+
+/// Write the per-process counts recorded so far as CSV, with the columns
+/// `asid,kind,vector,count`.
+pub fn write_csv(mut writer: impl Write) -> io::Result<()> {
+    let stats = stats();
+
+    writeln!(writer, "asid,kind,vector,count")?;
+    for ((asid, kind, vector), count) in &stats.per_asid {
+        writeln!(writer, "{:#x},{:?},{},{}", asid, kind, vector, count)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,388 @@
+//! A report of the changes made to the guest's filesystem
+//!
+//! Once [`start`] is called, every successful syscall which modifies the filesystem
+//! (opening a file for writing, creating, deleting or renaming a file, changing its
+//! permissions or creating a directory) is recorded along with the process which made
+//! it and the guest instruction count at which it returned. The changes can be
+//! retrieved at any time with [`report`], or written to a file when the guest shuts
+//! down (including at the end of a replay) with [`save_report_on_exit`], giving a
+//! sandbox-style summary of a sample's filesystem activity.
+//!
+//! Syscalls are decoded using [`syscalls::decode`](crate::syscalls::decode), and the
+//! process making each change is found using OSI. Paths are recorded as passed to the
+//! syscall, so relative paths are relative to the working directory of the process or
+//! the directory given by `dirfd`.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::fs_activity;
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     fs_activity::start();
+//!     fs_activity::save_report_on_exit("fs_activity.txt");
+//! }
+//! ```
+use crate::plugins::osi;
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+use crate::syscalls::decode::{on_sys_return_decoded, OpenFlags, Syscall};
+use crate::{sanitize, Callback};
+
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The `dirfd` meaning the current working directory
+const AT_FDCWD: i32 = -100;
+
+/// A change made to a file or directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileOp {
+    /// A file was opened with a mode allowing it to be written to or truncated
+    OpenForWrite {
+        flags: OpenFlags,
+    },
+
+    /// A file was created, either by `creat` or by `open` with `O_CREAT|O_EXCL`
+    Create,
+    Delete,
+
+    /// A file was renamed to the given path
+    Rename {
+        to: OsString,
+    },
+    Chmod {
+        mode: u32,
+    },
+    Mkdir,
+}
+
+impl fmt::Display for FileOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileOp::OpenForWrite { flags } => write!(f, "opened for writing ({:?})", flags),
+            FileOp::Create => f.write_str("created"),
+            FileOp::Delete => f.write_str("deleted"),
+            FileOp::Rename { to } => {
+                write!(f, "renamed to {}", sanitize::text(&to.to_string_lossy()))
+            }
+            FileOp::Chmod { mode } => write!(f, "mode changed to {:o}", mode),
+            FileOp::Mkdir => f.write_str("directory created"),
+        }
+    }
+}
+
+/// A single change to the filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    /// The guest instruction count when the syscall returned
+    pub instr_count: u64,
+    pub pid: target_pid_t,
+
+    /// The name of the process which made the change
+    pub process: String,
+
+    /// The path as passed to the syscall, or empty if the syscall was given a file
+    /// descriptor (such as `fchmod`), in which case it is `dirfd`
+    pub path: OsString,
+
+    /// The directory a relative `path` is relative to, if it isn't the working
+    /// directory of the process
+    pub dirfd: Option<i32>,
+    pub op: FileOp,
+}
+
+impl FileChange {
+    /// The path for display, with relative paths shown relative to their `dirfd`
+    pub fn display_path(&self) -> String {
+        let path = self.path.to_string_lossy();
+
+        match self.dirfd {
+            Some(dirfd) if path.is_empty() => format!("<fd {}>", dirfd),
+            Some(dirfd) if !path.starts_with('/') => format!("<fd {}>/{}", dirfd, path),
+            _ => path.into_owned(),
+        }
+    }
+}
+
+/// Every change to the filesystem recorded since [`start`] was called
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActivityReport {
+    /// The changes, in the order they were made
+    pub changes: Vec<FileChange>,
+}
+
+impl ActivityReport {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The changes made to each path, in the order they were made
+    pub fn by_path(&self) -> BTreeMap<&OsStr, Vec<&FileChange>> {
+        let mut paths: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for change in &self.changes {
+            paths
+                .entry(change.path.as_os_str())
+                .or_default()
+                .push(change);
+        }
+
+        paths
+    }
+
+    /// The changes made by each process, keyed by pid
+    pub fn by_process(&self) -> BTreeMap<target_pid_t, Vec<&FileChange>> {
+        let mut processes: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for change in &self.changes {
+            processes.entry(change.pid).or_default().push(change);
+        }
+
+        processes
+    }
+
+    /// Write the report as text, with the changes grouped by process. Names and paths
+    /// from the guest are escaped with [`sanitize::text`].
+    pub fn write_report(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "Filesystem activity: {} changes to {} paths",
+            self.changes.len(),
+            self.by_path().len()
+        )?;
+
+        for (pid, changes) in self.by_process() {
+            writeln!(writer)?;
+            let process = sanitize::text(&changes[0].process);
+            writeln!(writer, "{} (pid {}):", process, pid)?;
+
+            for change in changes {
+                writeln!(
+                    writer,
+                    "  [{}] {}: {}",
+                    change.instr_count,
+                    sanitize::text(&change.display_path()),
+                    change.op
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the report to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_report(&mut writer)?;
+
+        writer.flush()
+    }
+}
+
+impl fmt::Display for ActivityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut report = Vec::new();
+        self.write_report(&mut report).map_err(|_| fmt::Error)?;
+
+        f.write_str(&String::from_utf8_lossy(&report))
+    }
+}
+
+/// Get the path, `dirfd` and operation of a syscall which changes the filesystem
+fn file_op(syscall: &Syscall) -> Option<(OsString, Option<i32>, FileOp)> {
+    let dirfd = |dirfd: &i32| Some(*dirfd).filter(|&dirfd| dirfd != AT_FDCWD);
+    let open = |flags: &OpenFlags| {
+        if flags.contains(OpenFlags::O_CREAT | OpenFlags::O_EXCL) {
+            Some(FileOp::Create)
+        } else if flags.is_write() {
+            Some(FileOp::OpenForWrite { flags: *flags })
+        } else {
+            None
+        }
+    };
+
+    Some(match syscall {
+        Syscall::Open { path, flags, .. } => (path.clone(), None, open(flags)?),
+        Syscall::OpenAt {
+            dirfd: fd,
+            path,
+            flags,
+            ..
+        } => (path.clone(), dirfd(fd), open(flags)?),
+        Syscall::Creat { path, .. } => (path.clone(), None, FileOp::Create),
+        Syscall::Unlink { path } => (path.clone(), None, FileOp::Delete),
+        Syscall::UnlinkAt {
+            dirfd: fd, path, ..
+        } => (path.clone(), dirfd(fd), FileOp::Delete),
+        Syscall::Rename { old_path, new_path } => (
+            old_path.clone(),
+            None,
+            FileOp::Rename {
+                to: new_path.clone(),
+            },
+        ),
+        Syscall::RenameAt {
+            old_dirfd,
+            old_path,
+            new_path,
+            ..
+        } => (
+            old_path.clone(),
+            dirfd(old_dirfd),
+            FileOp::Rename {
+                to: new_path.clone(),
+            },
+        ),
+        Syscall::Chmod { path, mode } => (path.clone(), None, FileOp::Chmod { mode: *mode }),
+        Syscall::FchmodAt {
+            dirfd: fd,
+            path,
+            mode,
+        } => (path.clone(), dirfd(fd), FileOp::Chmod { mode: *mode }),
+        Syscall::Fchmod { fd, mode } => (OsString::new(), Some(*fd), FileOp::Chmod { mode: *mode }),
+        Syscall::Mkdir { path, .. } => (path.clone(), None, FileOp::Mkdir),
+        Syscall::MkdirAt {
+            dirfd: fd, path, ..
+        } => (path.clone(), dirfd(fd), FileOp::Mkdir),
+        _ => return None,
+    })
+}
+
+lazy_static::lazy_static! {
+    static ref REPORT: Mutex<ActivityReport> = Mutex::new(ActivityReport::default());
+    static ref STARTED: () = install_callback();
+}
+
+fn install_callback() {
+    on_sys_return_decoded(|cpu, syscall, result| {
+        if result.is_err() {
+            return;
+        }
+
+        let (path, dirfd, op) = match file_op(syscall) {
+            Some(change) => change,
+            None => return,
+        };
+
+        let (pid, process) = match osi::current_process(cpu) {
+            Some(process) => (process.pid, process.name),
+            None => (0, String::from("<unknown>")),
+        };
+
+        REPORT.lock().unwrap().changes.push(FileChange {
+            instr_count: rr_get_guest_instr_count(),
+            pid,
+            process,
+            path,
+            dirfd,
+            op,
+        });
+    });
+}
+
+/// Start recording changes to the filesystem. Calling this more than once has no
+/// further effect.
+pub fn start() {
+    lazy_static::initialize(&STARTED);
+}
+
+/// Get every change recorded so far
+pub fn report() -> ActivityReport {
+    REPORT.lock().unwrap().clone()
+}
+
+/// Write the report to the given file when the guest shuts down, such as at the end of
+/// a replay
+pub fn save_report_on_exit(path: impl Into<PathBuf>) {
+    let path = path.into();
+
+    Callback::new().pre_shutdown(move || {
+        if let Err(err) = report().save(&path) {
+            eprintln!(
+                "Warning: failed to write filesystem activity report to {}: {}",
+                path.display(),
+                err
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(process: &str, path: &str, op: FileOp) -> FileChange {
+        FileChange {
+            instr_count: 1,
+            pid: 1,
+            process: process.to_owned(),
+            path: OsString::from(path),
+            dirfd: None,
+            op,
+        }
+    }
+
+    #[test]
+    fn test_report_escapes_guest_strings() {
+        let report = ActivityReport {
+            changes: vec![
+                change(
+                    "evil\n2 (pid 2):",
+                    "/tmp/a\n  [0] /etc/passwd: deleted",
+                    FileOp::Delete,
+                ),
+                change(
+                    "evil",
+                    "/tmp/b",
+                    FileOp::Rename {
+                        to: OsString::from("/tmp/c\x1b[2J"),
+                    },
+                ),
+            ],
+        };
+
+        let text = report.to_string();
+        assert_eq!(text.lines().count(), 5);
+        assert!(text.contains("evil\\n2 (pid 2): (pid 1):"));
+        assert!(text.contains("/tmp/a\\n  [0] /etc/passwd: deleted: deleted"));
+        assert!(text.contains("renamed to /tmp/c\\u{1b}[2J"));
+    }
+
+    #[test]
+    fn test_file_op_at_variants() {
+        let mkdirat = Syscall::MkdirAt {
+            dirfd: 3,
+            path: OsString::from("dir"),
+            mode: 0o755,
+        };
+        assert_eq!(
+            file_op(&mkdirat),
+            Some((OsString::from("dir"), Some(3), FileOp::Mkdir))
+        );
+
+        let fchmodat = Syscall::FchmodAt {
+            dirfd: AT_FDCWD,
+            path: OsString::from("file"),
+            mode: 0o600,
+        };
+        assert_eq!(
+            file_op(&fchmodat),
+            Some((OsString::from("file"), None, FileOp::Chmod { mode: 0o600 }))
+        );
+
+        let fchmod = Syscall::Fchmod { fd: 4, mode: 0o644 };
+        let (path, dirfd, op) = file_op(&fchmod).unwrap();
+        let change = FileChange {
+            path,
+            dirfd,
+            ..change("chmod", "", op)
+        };
+        assert_eq!(change.display_path(), "<fd 4>");
+    }
+}
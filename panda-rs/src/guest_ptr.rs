@@ -5,8 +5,13 @@ use std::alloc::Layout;
 use std::ops::Deref;
 
 mod guest_align;
+mod guest_string;
 mod impls;
 
+pub use guest_string::{
+    CStrGuest, GuestStr, GuestString, GuestWString, StringEncoding, Utf16, Utf8,
+};
+
 pub(crate) use guest_align::GuestAlign;
 
 #[derive(Copy, Clone, Debug)]
@@ -17,6 +22,49 @@ pub struct GuestWriteFail;
 
 /// A type which can be converted to and from a guest memory representation, allowing
 /// it to be used with [`GuestPtr`].
+///
+/// `GuestType` can be derived for structs, which are laid out like a `#[repr(C)]`
+/// struct in the guest, and for tagged unions: enums where an integer tag selects the
+/// layout of the payload, such as `struct sockaddr`. Each variant of a tagged union
+/// either holds a single payload or is a unit variant, and is selected by
+/// `#[guest(tag = ...)]`. An optional `#[guest(other)]` variant holds the tag of any
+/// unrecognized variant, otherwise unrecognized tags fail to read.
+///
+/// By default the tag is the first field of every payload, as with `sa_family` in each
+/// kind of `sockaddr`. With `#[guest(prefix)]`, the tag is instead followed by a union
+/// of the payloads.
+///
+/// Pointers which may be NULL can be read as `Option<GuestPtr<T>>`, and inline
+/// NUL-terminated strings as [`CStrGuest`]. The last field of a struct may be
+/// dynamically sized (such as a `CStrGuest`), in which case the struct is too.
+///
+/// ## Example
+///
+/// ```
+/// use panda::GuestType;
+///
+/// #[derive(GuestType)]
+/// struct TimerInfo {
+///     interval: u64,
+///     repeat: u32,
+/// }
+///
+/// #[derive(GuestType)]
+/// #[guest(tag = "u32", prefix)]
+/// enum Event {
+///     #[guest(tag = 0)]
+///     Shutdown,
+///
+///     #[guest(tag = 1)]
+///     Signal(i32),
+///
+///     #[guest(tag = 2)]
+///     Timer(TimerInfo),
+///
+///     #[guest(other)]
+///     Unknown(u32),
+/// }
+/// ```
 pub trait GuestType: Sized {
     fn guest_layout() -> Option<Layout>;
 
@@ -39,6 +87,40 @@ pub trait GuestType: Sized {
     fn write_to_guest_phys(&self, ptr: target_ptr_t) -> Result<(), GuestWriteFail>;
 }
 
+/// Get the layout of a tagged union with the given payload layouts, and the offset of
+/// the payload within it. Used by `#[derive(GuestType)]`.
+#[doc(hidden)]
+pub fn __tagged_union_layout<Tag: GuestType>(
+    payloads: &[Option<Layout>],
+    prefix: bool,
+) -> Option<(Layout, usize)> {
+    let tag = Tag::guest_layout()?;
+    let union = payloads.iter().try_fold(
+        if prefix {
+            Layout::from_size_align(0, 1).ok()?
+        } else {
+            tag
+        },
+        |union, payload| {
+            let payload = (*payload)?;
+
+            Layout::from_size_align(
+                union.size().max(payload.size()),
+                union.align().max(payload.align()),
+            )
+            .ok()
+        },
+    )?;
+
+    if prefix {
+        let (layout, offset) = tag.extend(union).ok()?;
+
+        Some((layout.pad_to_align(), offset))
+    } else {
+        Some((union.pad_to_align(), 0))
+    }
+}
+
 pub struct GuestPtr<T: GuestType> {
     pointer: target_ptr_t,
     guest_type: OnceCell<Box<T>>,
@@ -87,6 +169,11 @@ impl<T: GuestType> GuestPtr<T> {
         self.guest_type = OnceCell::new();
     }
 
+    /// The guest virtual address the pointer points to
+    pub fn addr(&self) -> target_ptr_t {
+        self.pointer
+    }
+
     /// Returns a reference to the cached value if one exists.
     pub fn get_cached(&self) -> Option<&T> {
         self.guest_type.get().map(Box::as_ref)
@@ -0,0 +1,296 @@
+use super::{GuestReadFail, GuestType, GuestWriteFail};
+use crate::enums::MemRWStatus;
+use crate::mem::{
+    physical_memory_write, read_guest_bytes_until_nul, read_guest_bytes_until_nul_phys,
+    read_guest_string_with, virtual_memory_write, Encoding,
+};
+use crate::prelude::*;
+
+use std::alloc::Layout;
+use std::borrow::Cow;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// The encoding of a [`GuestStr`], used to pick between [`GuestString`] and
+/// [`GuestWString`]
+pub trait StringEncoding {
+    const ENCODING: Encoding;
+}
+
+/// NUL-terminated UTF-8 (`char *`)
+pub enum Utf8 {}
+
+/// NUL-terminated UTF-16 (a Windows `wchar_t *`)
+pub enum Utf16 {}
+
+impl StringEncoding for Utf8 {
+    const ENCODING: Encoding = Encoding::Utf8;
+}
+
+impl StringEncoding for Utf16 {
+    const ENCODING: Encoding = Encoding::Utf16Le;
+}
+
+/// A pointer to a NUL-terminated string in the guest, read along with the string it
+/// points to. Allows strings to be used as fields of `#[derive(GuestType)]` structs and
+/// read with [`GuestPtr`](super::GuestPtr).
+///
+/// Writing a `GuestStr` to the guest only writes the pointer, as the string may not
+/// fit in the memory it points to.
+///
+/// ## Example
+///
+/// ```
+/// use panda::{GuestString, GuestType};
+///
+/// #[derive(GuestType)]
+/// struct Passwd {
+///     pw_name: GuestString,
+///     pw_passwd: GuestString,
+///     pw_uid: u32,
+///     pw_gid: u32,
+/// }
+/// ```
+pub struct GuestStr<E: StringEncoding> {
+    /// The address of the string in the guest
+    pub ptr: target_ptr_t,
+
+    /// The string, or `None` if the pointer is NULL or the string couldn't be read
+    pub value: Option<String>,
+    encoding: PhantomData<E>,
+}
+
+/// A pointer to a NUL-terminated UTF-8 string (`char *`). See [`GuestStr`].
+pub type GuestString = GuestStr<Utf8>;
+
+/// A pointer to a NUL-terminated UTF-16 string (a Windows `wchar_t *`). See
+/// [`GuestStr`].
+pub type GuestWString = GuestStr<Utf16>;
+
+impl<E: StringEncoding> GuestStr<E> {
+    fn read_at(cpu: &mut CPUState, ptr: target_ptr_t) -> Self {
+        let value = if ptr == 0 {
+            None
+        } else {
+            read_guest_string_with(cpu, ptr, E::ENCODING).ok()
+        };
+
+        Self {
+            ptr,
+            value,
+            encoding: PhantomData,
+        }
+    }
+
+    /// Get the string, or `None` if the pointer is NULL or the string couldn't be read
+    pub fn as_str(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.ptr == 0
+    }
+}
+
+impl<E: StringEncoding> Clone for GuestStr<E> {
+    fn clone(&self) -> Self {
+        Self {
+            ptr: self.ptr,
+            value: self.value.clone(),
+            encoding: PhantomData,
+        }
+    }
+}
+
+impl<E: StringEncoding> PartialEq for GuestStr<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr && self.value == other.value
+    }
+}
+
+impl<E: StringEncoding> Eq for GuestStr<E> {}
+
+impl<E: StringEncoding> fmt::Debug for GuestStr<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{:?} @ {:#x}", value, self.ptr),
+            None => write!(f, "<unreadable> @ {:#x}", self.ptr),
+        }
+    }
+}
+
+impl<E: StringEncoding> fmt::Display for GuestStr<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => f.write_str(value),
+            None if self.is_null() => f.write_str("(null)"),
+            None => write!(f, "<unreadable {:#x}>", self.ptr),
+        }
+    }
+}
+
+impl<E: StringEncoding> GuestType for GuestStr<E> {
+    fn guest_layout() -> Option<Layout> {
+        target_ptr_t::guest_layout()
+    }
+
+    fn read_from_guest(cpu: &mut CPUState, ptr: target_ptr_t) -> Result<Self, GuestReadFail> {
+        let ptr = target_ptr_t::read_from_guest(cpu, ptr)?;
+
+        Ok(Self::read_at(cpu, ptr))
+    }
+
+    fn write_to_guest(&self, cpu: &mut CPUState, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
+        self.ptr.write_to_guest(cpu, ptr)
+    }
+
+    /// Reads the pointer from physical memory, and the string it points to from the
+    /// virtual address space of the current CPU
+    fn read_from_guest_phys(ptr: target_ptr_t) -> Result<Self, GuestReadFail> {
+        let ptr = target_ptr_t::read_from_guest_phys(ptr)?;
+
+        // the string itself is read through the current address space, which requires
+        // a CPU
+        let cpu = unsafe { crate::sys::get_cpu().as_mut() }.ok_or(GuestReadFail)?;
+
+        Ok(Self::read_at(cpu, ptr))
+    }
+
+    fn write_to_guest_phys(&self, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
+        self.ptr.write_to_guest_phys(ptr)
+    }
+}
+
+/// A NUL-terminated string stored inline in guest memory (a `char[]`), rather than a
+/// pointer to one like [`GuestString`]. The string is dynamically sized, so is read up
+/// to its terminator (or [`MAX_GUEST_STRING_LEN`](crate::mem::MAX_GUEST_STRING_LEN)
+/// bytes), and must be the last field of a `#[derive(GuestType)]` struct, like a C
+/// flexible array member. Deriving fails if it is used for any other field.
+///
+/// Combined with [`GuestPtr`](super::GuestPtr) this also reads a `char *` lazily, and
+/// `Option<GuestPtr<CStrGuest>>` one which may be NULL.
+///
+/// Writing a `CStrGuest` writes the bytes of the string followed by a NUL terminator.
+///
+/// ## Example
+///
+/// ```
+/// use panda::{CStrGuest, GuestPtr, GuestType};
+///
+/// #[derive(GuestType)]
+/// struct LinuxDirent64 {
+///     d_ino: u64,
+///     d_off: i64,
+///     d_reclen: u16,
+///     d_type: u8,
+///     d_name: CStrGuest,
+/// }
+///
+/// /// `struct mntent`, where a NULL pointer reads as `None`
+/// #[derive(GuestType)]
+/// struct Mntent {
+///     mnt_fsname: Option<GuestPtr<CStrGuest>>,
+///     mnt_dir: Option<GuestPtr<CStrGuest>>,
+///     mnt_type: Option<GuestPtr<CStrGuest>>,
+///     mnt_opts: Option<GuestPtr<CStrGuest>>,
+///     mnt_freq: i32,
+///     mnt_passno: i32,
+/// }
+/// ```
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct CStrGuest {
+    bytes: Vec<u8>,
+}
+
+impl CStrGuest {
+    /// The bytes of the string, without the NUL terminator
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The string as UTF-8, or `None` if it isn't valid UTF-8
+    pub fn to_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.bytes).ok()
+    }
+
+    /// The string as UTF-8, replacing invalid UTF-8 with U+FFFD
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.bytes)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn with_nul(&self) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+        bytes.push(0);
+
+        bytes
+    }
+}
+
+impl From<Vec<u8>> for CStrGuest {
+    /// Create a string from bytes, ending at the first NUL byte if there is one
+    fn from(mut bytes: Vec<u8>) -> Self {
+        if let Some(nul) = bytes.iter().position(|&byte| byte == 0) {
+            bytes.truncate(nul);
+        }
+
+        Self { bytes }
+    }
+}
+
+impl From<&str> for CStrGuest {
+    fn from(s: &str) -> Self {
+        Self::from(s.as_bytes().to_vec())
+    }
+}
+
+impl fmt::Debug for CStrGuest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string_lossy(), f)
+    }
+}
+
+impl fmt::Display for CStrGuest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_lossy())
+    }
+}
+
+impl GuestType for CStrGuest {
+    fn guest_layout() -> Option<Layout> {
+        None
+    }
+
+    fn read_from_guest(cpu: &mut CPUState, ptr: target_ptr_t) -> Result<Self, GuestReadFail> {
+        read_guest_bytes_until_nul(cpu, ptr).map(|bytes| Self { bytes })
+    }
+
+    fn write_to_guest(&self, cpu: &mut CPUState, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
+        match virtual_memory_write(cpu, ptr, &self.with_nul()) {
+            MemRWStatus::MemTxOk => Ok(()),
+            _ => Err(GuestWriteFail),
+        }
+    }
+
+    fn read_from_guest_phys(ptr: target_ptr_t) -> Result<Self, GuestReadFail> {
+        read_guest_bytes_until_nul_phys(ptr).map(|bytes| Self { bytes })
+    }
+
+    fn write_to_guest_phys(&self, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
+        match physical_memory_write(ptr, &self.with_nul()) {
+            MemRWStatus::MemTxOk => Ok(()),
+            _ => Err(GuestWriteFail),
+        }
+    }
+}
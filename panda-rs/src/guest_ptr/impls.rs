@@ -1,6 +1,6 @@
 use super::{GuestAlign, GuestPtr, GuestReadFail, GuestWriteFail};
 use crate::prelude::*;
-use crate::{enums::Endian, mem::*, GuestType, ARCH_ENDIAN};
+use crate::{check_endian, data_endian, enums::Endian, mem::*, GuestType};
 
 use std::alloc::Layout;
 
@@ -19,7 +19,7 @@ macro_rules! impl_for_num {
                     let mut bytes = [0u8; core::mem::size_of::<$ty>()];
                     virtual_memory_read_into(cpu, ptr, &mut bytes).or(Err(GuestReadFail))?;
 
-                    Ok(match ARCH_ENDIAN {
+                    Ok(match check_endian(cpu) {
                         Endian::Big => <$ty>::from_be_bytes(bytes),
                         Endian::Little => <$ty>::from_le_bytes(bytes),
                     })
@@ -29,14 +29,14 @@ macro_rules! impl_for_num {
                     let mut bytes = [0u8; core::mem::size_of::<$ty>()];
                     physical_memory_read_into(ptr, &mut bytes).or(Err(GuestReadFail))?;
 
-                    Ok(match ARCH_ENDIAN {
+                    Ok(match data_endian() {
                         Endian::Big => <$ty>::from_be_bytes(bytes),
                         Endian::Little => <$ty>::from_le_bytes(bytes),
                     })
                 }
 
                 fn write_to_guest(&self, cpu: &mut CPUState, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
-                    let bytes = match ARCH_ENDIAN {
+                    let bytes = match check_endian(cpu) {
                         Endian::Big => <$ty>::to_be_bytes(*self),
                         Endian::Little => <$ty>::to_le_bytes(*self),
                     };
@@ -47,7 +47,7 @@ macro_rules! impl_for_num {
                 }
 
                 fn write_to_guest_phys(&self, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
-                    let bytes = match ARCH_ENDIAN {
+                    let bytes = match data_endian() {
                         Endian::Big => <$ty>::to_be_bytes(*self),
                         Endian::Little => <$ty>::to_le_bytes(*self),
                     };
@@ -85,6 +85,35 @@ impl<T: GuestType> GuestType for GuestPtr<T> {
     }
 }
 
+/// A nullable pointer, where NULL is read as `None` and `None` is written as NULL
+impl<T: GuestType> GuestType for Option<GuestPtr<T>> {
+    fn guest_layout() -> Option<Layout> {
+        target_ptr_t::guest_layout()
+    }
+
+    fn read_from_guest(cpu: &mut CPUState, ptr: target_ptr_t) -> Result<Self, GuestReadFail> {
+        target_ptr_t::read_from_guest(cpu, ptr)
+            .map(|pointer| (pointer != 0).then(|| GuestPtr::from(pointer)))
+    }
+
+    fn write_to_guest(&self, cpu: &mut CPUState, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
+        self.as_ref()
+            .map_or(0, GuestPtr::addr)
+            .write_to_guest(cpu, ptr)
+    }
+
+    fn read_from_guest_phys(ptr: target_ptr_t) -> Result<Self, GuestReadFail> {
+        target_ptr_t::read_from_guest_phys(ptr)
+            .map(|pointer| (pointer != 0).then(|| GuestPtr::from(pointer)))
+    }
+
+    fn write_to_guest_phys(&self, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
+        self.as_ref()
+            .map_or(0, GuestPtr::addr)
+            .write_to_guest_phys(ptr)
+    }
+}
+
 fn padding_needed_for(layout: &Layout, align: usize) -> usize {
     let len = layout.size();
 
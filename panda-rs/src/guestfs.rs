@@ -0,0 +1,500 @@
+//! Read-only access to guest filesystems from the host
+//!
+//! This allows reading files out of a guest's disk image (qcow2 or raw) without booting
+//! it, in order to correlate on-disk artifacts with behavior observed at runtime. Since
+//! [`GuestFs`] is independent of the running PANDA instance, it can also be used from a
+//! separate thread while the guest is executing.
+//!
+//! Supported formats:
+//!
+//! * Images: qcow2 (v2 and v3, including zlib-compressed clusters and backing files) and raw
+//! * Partition tables: MBR (including logical partitions) and GPT, or no partition table
+//! * Filesystems: ext2/3/4 and FAT12/16/32
+//!
+//! Only the active disk state is read, internal snapshots are ignored.
+//!
+//! [`GuestFs::block_map`] maps locations on disk back to the files stored there, which
+//! [`disk_activity`](crate::disk_activity) uses to report disk accesses in a replay as
+//! accesses to files.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::guestfs;
+//!
+//! let passwd = guestfs::open("bionic-server-cloudimg-amd64-noaslr-nokaslr.qcow2")?
+//!     .read_to_string("/etc/passwd")?;
+//!
+//! println!("{}", passwd);
+//! # Ok::<(), guestfs::GuestFsError>(())
+//! ```
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+mod block_map;
+mod ext;
+mod fat;
+mod partition;
+mod qcow2;
+
+pub use block_map::{BlockMap, FileBlock};
+pub use partition::{Partition, PartitionKind};
+
+use ext::ExtFs;
+use fat::FatFs;
+use qcow2::Qcow2;
+
+/// The maximum number of symlinks followed while resolving a single path
+const MAX_SYMLINKS: usize = 40;
+
+/// An error encountered while reading a guest filesystem
+#[derive(Debug, thiserror::Error)]
+pub enum GuestFsError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("Unsupported image or filesystem: {0}")]
+    Unsupported(&'static str),
+
+    #[error("The image is corrupt: {0}")]
+    Corrupt(&'static str),
+
+    #[error("No supported filesystem was found in the image")]
+    NoFilesystem,
+
+    #[error("Partition {0} does not exist or does not contain a supported filesystem")]
+    NoSuchPartition(usize),
+
+    #[error("No such file or directory: {0}")]
+    NotFound(String),
+
+    #[error("Not a directory: {0}")]
+    NotADirectory(String),
+
+    #[error("Is a directory: {0}")]
+    IsADirectory(String),
+
+    #[error("Not a symlink: {0}")]
+    NotASymlink(String),
+
+    #[error("Too many levels of symbolic links: {0}")]
+    SymlinkLoop(String),
+}
+
+/// The type of a file in a guest filesystem
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+
+    /// Device nodes, FIFOs, sockets, etc.
+    Other,
+}
+
+/// Information about a file in a guest filesystem
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: FileKind,
+
+    /// Size of the file in bytes
+    pub size: u64,
+
+    /// Unix permission bits, if the filesystem has them
+    pub mode: Option<u16>,
+}
+
+impl Metadata {
+    pub fn is_file(&self) -> bool {
+        self.kind == FileKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Directory
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+}
+
+/// An entry returned by [`GuestFs::read_dir`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+
+    /// Metadata of the entry itself (symlinks are not followed)
+    pub metadata: Metadata,
+}
+
+/// A contiguous part of a file stored on disk
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Extent {
+    /// Offset of the part from the start of the file, in bytes
+    pub file_offset: u64,
+
+    /// Offset of the part from the start of the disk image, in bytes
+    pub disk_offset: u64,
+
+    /// Length of the part in bytes
+    pub len: u64,
+}
+
+/// A read-only handle to a filesystem inside a guest disk image
+pub struct GuestFs {
+    fs: Filesystem,
+}
+
+enum Filesystem {
+    Ext(ExtFs),
+    Fat(FatFs),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $fs:ident => $expr:expr) => {
+        match &mut $self.fs {
+            Filesystem::Ext($fs) => $expr,
+            Filesystem::Fat($fs) => $expr,
+        }
+    };
+}
+
+/// Open the first supported filesystem in a disk image.
+///
+/// If the image has no partition table and contains a filesystem directly, that
+/// filesystem is used.
+pub fn open(image: impl AsRef<Path>) -> Result<GuestFs, GuestFsError> {
+    let mut disk = Disk::open(image.as_ref())?;
+    let len = disk.len();
+
+    if let Some(kind) = FsKind::detect(&mut disk, 0)? {
+        return GuestFs::new(Volume::new(disk, 0, len), kind);
+    }
+
+    for part in partition::read_partitions(&mut disk)? {
+        if let Some(kind) = FsKind::detect(&mut disk, part.start)? {
+            return GuestFs::new(Volume::new(disk, part.start, part.len), kind);
+        }
+    }
+
+    Err(GuestFsError::NoFilesystem)
+}
+
+/// Open the filesystem in a given partition of a disk image. Partitions are numbered
+/// the way Linux numbers them, starting at 1 (`/dev/sda1`), with logical MBR
+/// partitions starting at 5.
+pub fn open_partition(image: impl AsRef<Path>, index: usize) -> Result<GuestFs, GuestFsError> {
+    let mut disk = Disk::open(image.as_ref())?;
+    let part = partition::read_partitions(&mut disk)?
+        .into_iter()
+        .find(|part| part.index == index)
+        .ok_or(GuestFsError::NoSuchPartition(index))?;
+
+    let kind =
+        FsKind::detect(&mut disk, part.start)?.ok_or(GuestFsError::NoSuchPartition(index))?;
+
+    GuestFs::new(Volume::new(disk, part.start, part.len), kind)
+}
+
+/// List the partitions of a disk image
+pub fn partitions(image: impl AsRef<Path>) -> Result<Vec<Partition>, GuestFsError> {
+    let mut disk = Disk::open(image.as_ref())?;
+
+    partition::read_partitions(&mut disk)
+}
+
+impl GuestFs {
+    fn new(vol: Volume, kind: FsKind) -> Result<Self, GuestFsError> {
+        let fs = match kind {
+            FsKind::Ext => Filesystem::Ext(ExtFs::open(vol)?),
+            FsKind::Fat => Filesystem::Fat(FatFs::open(vol)?),
+        };
+
+        Ok(Self { fs })
+    }
+
+    /// Read the entire contents of a file
+    pub fn read(&mut self, path: &str) -> Result<Vec<u8>, GuestFsError> {
+        dispatch!(self, fs => {
+            let node = resolve(fs, path, true)?;
+            if fs.metadata(&node).is_dir() {
+                return Err(GuestFsError::IsADirectory(path.into()));
+            }
+
+            fs.read_file(&node)
+        })
+    }
+
+    /// Read the entire contents of a file as a string, replacing invalid UTF-8
+    pub fn read_to_string(&mut self, path: &str) -> Result<String, GuestFsError> {
+        self.read(path)
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// List the contents of a directory
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, GuestFsError> {
+        dispatch!(self, fs => {
+            let node = resolve(fs, path, true)?;
+            if !fs.metadata(&node).is_dir() {
+                return Err(GuestFsError::NotADirectory(path.into()));
+            }
+
+            let entries = fs.read_dir(&node)?
+                .into_iter()
+                .map(|(name, node)| {
+                    let metadata = fs.metadata(&node);
+
+                    DirEntry { name, metadata }
+                })
+                .collect();
+
+            Ok(entries)
+        })
+    }
+
+    /// Get the metadata of a file, following symlinks
+    pub fn metadata(&mut self, path: &str) -> Result<Metadata, GuestFsError> {
+        dispatch!(self, fs => resolve(fs, path, true).map(|node| fs.metadata(&node)))
+    }
+
+    /// Get the metadata of a file without following a final symlink
+    pub fn symlink_metadata(&mut self, path: &str) -> Result<Metadata, GuestFsError> {
+        dispatch!(self, fs => resolve(fs, path, false).map(|node| fs.metadata(&node)))
+    }
+
+    /// Read the target of a symlink
+    pub fn read_link(&mut self, path: &str) -> Result<String, GuestFsError> {
+        dispatch!(self, fs => {
+            let node = resolve(fs, path, false)?;
+            if !fs.metadata(&node).is_symlink() {
+                return Err(GuestFsError::NotASymlink(path.into()));
+            }
+
+            fs.read_file(&node)
+                .map(|target| String::from_utf8_lossy(&target).into_owned())
+        })
+    }
+
+    /// Check whether a path exists, following symlinks
+    pub fn exists(&mut self, path: &str) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// Get where the contents of a file are stored on disk, following symlinks. Data
+    /// stored inside the file's metadata, such as small files inlined into an ext4
+    /// inode, has no extents.
+    pub fn extents(&mut self, path: &str) -> Result<Vec<Extent>, GuestFsError> {
+        dispatch!(self, fs => {
+            let node = resolve(fs, path, true)?;
+            fs.extents(&node)
+        })
+    }
+
+    /// Build a map of where every file and directory in the filesystem is stored on
+    /// disk, for finding which file a disk access is to. Symlinks are not followed.
+    pub fn block_map(&mut self) -> Result<BlockMap, GuestFsError> {
+        dispatch!(self, fs => BlockMap::build(fs))
+    }
+}
+
+/// The operations each filesystem implementation provides, used for shared path
+/// resolution logic.
+trait Fs {
+    type Node: Clone;
+
+    fn root(&mut self) -> Result<Self::Node, GuestFsError>;
+
+    /// A number identifying the node within the filesystem, such as its inode number,
+    /// which only needs to be unique among directories
+    fn id(&mut self, node: &Self::Node) -> u64;
+
+    fn metadata(&mut self, node: &Self::Node) -> Metadata;
+    fn lookup(&mut self, dir: &Self::Node, name: &str) -> Result<Option<Self::Node>, GuestFsError>;
+    fn read_dir(&mut self, dir: &Self::Node) -> Result<Vec<(String, Self::Node)>, GuestFsError>;
+    fn read_file(&mut self, node: &Self::Node) -> Result<Vec<u8>, GuestFsError>;
+    fn extents(&mut self, node: &Self::Node) -> Result<Vec<Extent>, GuestFsError>;
+}
+
+fn resolve<F: Fs>(fs: &mut F, path: &str, follow_last: bool) -> Result<F::Node, GuestFsError> {
+    let mut stack = vec![fs.root()?];
+    let mut pending: Vec<String> = path.split('/').rev().map(String::from).collect();
+    let mut links_followed = 0;
+
+    while let Some(component) = pending.pop() {
+        match component.as_str() {
+            "" | "." => continue,
+            ".." => {
+                if stack.len() > 1 {
+                    stack.pop();
+                }
+                continue;
+            }
+            _ => (),
+        }
+
+        let dir = stack.last().unwrap().clone();
+        if !fs.metadata(&dir).is_dir() {
+            return Err(GuestFsError::NotADirectory(path.into()));
+        }
+
+        let node = fs
+            .lookup(&dir, &component)?
+            .ok_or_else(|| GuestFsError::NotFound(path.into()))?;
+
+        let is_last = pending.iter().all(|c| c.is_empty() || c == ".");
+        if fs.metadata(&node).is_symlink() && (follow_last || !is_last) {
+            links_followed += 1;
+            if links_followed > MAX_SYMLINKS {
+                return Err(GuestFsError::SymlinkLoop(path.into()));
+            }
+
+            let target = fs.read_file(&node)?;
+            let target = String::from_utf8_lossy(&target);
+            if target.starts_with('/') {
+                stack.truncate(1);
+            }
+
+            pending.extend(target.split('/').rev().map(String::from));
+        } else {
+            stack.push(node);
+        }
+    }
+
+    Ok(stack.pop().unwrap())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FsKind {
+    Ext,
+    Fat,
+}
+
+impl FsKind {
+    fn detect(disk: &mut Disk, start: u64) -> Result<Option<Self>, GuestFsError> {
+        if disk.len() < start + 2048 {
+            return Ok(None);
+        }
+
+        let mut magic = [0u8; 2];
+        disk.read_at(
+            start + ext::SUPERBLOCK_OFFSET + ext::MAGIC_OFFSET,
+            &mut magic,
+        )?;
+        if u16::from_le_bytes(magic) == ext::MAGIC {
+            return Ok(Some(FsKind::Ext));
+        }
+
+        let mut boot_sector = [0u8; 512];
+        disk.read_at(start, &mut boot_sector)?;
+        if fat::is_fat(&boot_sector) {
+            return Ok(Some(FsKind::Fat));
+        }
+
+        Ok(None)
+    }
+}
+
+/// A disk image, either qcow2 or raw
+enum Disk {
+    Raw { file: File, len: u64 },
+    Qcow2(Box<Qcow2>),
+}
+
+impl Disk {
+    fn open(path: &Path) -> Result<Self, GuestFsError> {
+        Self::open_backing(path, &mut Vec::new())
+    }
+
+    /// Open an image as the backing file of the images in `chain`
+    fn open_backing(path: &Path, chain: &mut Vec<PathBuf>) -> Result<Self, GuestFsError> {
+        let canonical = path.canonicalize()?;
+        if chain.contains(&canonical) {
+            return Err(GuestFsError::Corrupt("qcow2 backing file loop"));
+        }
+        chain.push(canonical);
+
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        let mut magic = [0u8; 4];
+        if len >= 4 {
+            file.read_exact_at(&mut magic, 0)?;
+        }
+
+        if &magic == qcow2::MAGIC {
+            Ok(Disk::Qcow2(Box::new(Qcow2::open(path, file, chain)?)))
+        } else {
+            Ok(Disk::Raw { file, len })
+        }
+    }
+
+    fn len(&self) -> u64 {
+        match self {
+            Disk::Raw { len, .. } => *len,
+            Disk::Qcow2(qcow) => qcow.len(),
+        }
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), GuestFsError> {
+        match self {
+            Disk::Raw { file, .. } => Ok(file.read_exact_at(buf, offset)?),
+            Disk::Qcow2(qcow) => qcow.read_at(offset, buf),
+        }
+    }
+}
+
+/// A region of a disk containing a single filesystem
+struct Volume {
+    disk: Disk,
+    start: u64,
+    len: u64,
+}
+
+impl Volume {
+    fn new(disk: Disk, start: u64, len: u64) -> Self {
+        Self { disk, start, len }
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), GuestFsError> {
+        match offset.checked_add(buf.len() as u64) {
+            Some(end) if end <= self.len => self.disk.read_at(self.start + offset, buf),
+            _ => Err(GuestFsError::Corrupt("read past the end of the volume")),
+        }
+    }
+
+    /// Convert an offset into the volume to an offset into the disk
+    fn disk_offset(&self, offset: u64) -> u64 {
+        self.start + offset
+    }
+
+    fn read_vec(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, GuestFsError> {
+        let mut buf = vec![0; len];
+        self.read_at(offset, &mut buf)?;
+
+        Ok(buf)
+    }
+}
+
+fn le16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn le64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn be32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn be64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
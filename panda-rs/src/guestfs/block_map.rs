@@ -0,0 +1,258 @@
+use super::{Extent, FileKind, Fs, GuestFsError};
+
+use std::collections::HashSet;
+
+/// A contiguous part of a file stored on disk, as found by [`BlockMap::lookup`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileBlock<'a> {
+    /// The path of the file, absolute from the root of the filesystem
+    pub path: &'a str,
+
+    /// Offset of the part from the start of the file, in bytes
+    pub file_offset: u64,
+
+    /// Offset of the part from the start of the disk image, in bytes
+    pub disk_offset: u64,
+
+    /// Length of the part in bytes
+    pub len: u64,
+}
+
+/// A map from locations on disk to the files stored there, built by
+/// [`GuestFs::block_map`](super::GuestFs::block_map)
+///
+/// The map is a snapshot of the filesystem as it was on disk when it was built, so it
+/// doesn't reflect files created or moved by the guest afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct BlockMap {
+    paths: Vec<String>,
+
+    /// Extents of every file along with the index of its path, sorted by disk offset
+    extents: Vec<(Extent, usize)>,
+
+    /// The length of the longest extent, bounding how far before a range an extent
+    /// overlapping it can start
+    max_extent_len: u64,
+}
+
+impl BlockMap {
+    /// Find the parts of files stored in the `len` bytes of the disk at `disk_offset`,
+    /// clipped to that range, in order of disk offset
+    ///
+    /// A file with several hard links has a part returned for each of its paths.
+    pub fn lookup(&self, disk_offset: u64, len: u64) -> Vec<FileBlock<'_>> {
+        let end = disk_offset.saturating_add(len);
+
+        // extents can overlap (such as those of hard links), so start from the first
+        // one which could be long enough to reach the range
+        let first = self.extents.partition_point(|(extent, _)| {
+            extent.disk_offset.saturating_add(self.max_extent_len) <= disk_offset
+        });
+
+        self.extents[first..]
+            .iter()
+            .take_while(|(extent, _)| extent.disk_offset < end)
+            .filter(|(extent, _)| extent.disk_offset.saturating_add(extent.len) > disk_offset)
+            .map(|(extent, path)| {
+                let start = extent.disk_offset.max(disk_offset);
+                let stop = extent.disk_offset.saturating_add(extent.len).min(end);
+
+                FileBlock {
+                    path: &self.paths[*path],
+                    file_offset: extent.file_offset + (start - extent.disk_offset),
+                    disk_offset: start,
+                    len: stop - start,
+                }
+            })
+            .collect()
+    }
+
+    /// The number of files in the map
+    pub fn file_count(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub(super) fn build<F: Fs>(fs: &mut F) -> Result<Self, GuestFsError> {
+        let mut map = BlockMap::default();
+        let mut pending = vec![(String::from("/"), fs.root()?)];
+
+        // a corrupt filesystem can link a directory into itself
+        let mut visited_dirs = HashSet::new();
+
+        while let Some((path, node)) = pending.pop() {
+            let kind = fs.metadata(&node).kind;
+            if kind == FileKind::Other {
+                continue;
+            }
+
+            if kind == FileKind::Directory && !visited_dirs.insert(fs.id(&node)) {
+                continue;
+            }
+
+            let index = map.paths.len();
+            map.extents
+                .extend(fs.extents(&node)?.into_iter().map(|extent| (extent, index)));
+
+            if kind == FileKind::Directory {
+                for (name, child) in fs.read_dir(&node)? {
+                    let child_path = match path.as_str() {
+                        "/" => format!("/{}", name),
+                        _ => format!("{}/{}", path, name),
+                    };
+
+                    pending.push((child_path, child));
+                }
+            }
+
+            map.paths.push(path);
+        }
+
+        map.extents.sort_by_key(|(extent, _)| extent.disk_offset);
+        map.max_extent_len = map
+            .extents
+            .iter()
+            .map(|(extent, _)| extent.len)
+            .max()
+            .unwrap_or(0);
+
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Metadata;
+    use super::*;
+
+    use std::collections::HashMap;
+
+    /// A filesystem of numbered nodes, where directories list their children and files
+    /// give their extents
+    #[derive(Default)]
+    struct TestFs {
+        dirs: HashMap<u64, Vec<(&'static str, u64)>>,
+        files: HashMap<u64, Vec<Extent>>,
+    }
+
+    impl Fs for TestFs {
+        type Node = u64;
+
+        fn root(&mut self) -> Result<u64, GuestFsError> {
+            Ok(0)
+        }
+
+        fn id(&mut self, node: &u64) -> u64 {
+            *node
+        }
+
+        fn metadata(&mut self, node: &u64) -> Metadata {
+            Metadata {
+                kind: if self.dirs.contains_key(node) {
+                    FileKind::Directory
+                } else {
+                    FileKind::File
+                },
+                size: 0,
+                mode: None,
+            }
+        }
+
+        fn lookup(&mut self, _: &u64, _: &str) -> Result<Option<u64>, GuestFsError> {
+            unimplemented!()
+        }
+
+        fn read_dir(&mut self, dir: &u64) -> Result<Vec<(String, u64)>, GuestFsError> {
+            Ok(self.dirs[dir]
+                .iter()
+                .map(|&(name, node)| (name.to_owned(), node))
+                .collect())
+        }
+
+        fn read_file(&mut self, _: &u64) -> Result<Vec<u8>, GuestFsError> {
+            unimplemented!()
+        }
+
+        fn extents(&mut self, node: &u64) -> Result<Vec<Extent>, GuestFsError> {
+            Ok(self.files.get(node).cloned().unwrap_or_default())
+        }
+    }
+
+    fn extent(file_offset: u64, disk_offset: u64, len: u64) -> Extent {
+        Extent {
+            file_offset,
+            disk_offset,
+            len,
+        }
+    }
+
+    fn paths<'a>(blocks: &[FileBlock<'a>]) -> Vec<&'a str> {
+        let mut paths: Vec<_> = blocks.iter().map(|block| block.path).collect();
+        paths.sort_unstable();
+
+        paths
+    }
+
+    #[test]
+    fn test_lookup_clips_to_range() {
+        let mut fs = TestFs::default();
+        fs.dirs.insert(0, vec![("a", 1), ("b", 2)]);
+        fs.files.insert(1, vec![extent(0, 0x1000, 0x1000)]);
+        fs.files.insert(
+            2,
+            vec![extent(0, 0x3000, 0x100), extent(0x100, 0x2000, 0x100)],
+        );
+
+        let map = BlockMap::build(&mut fs).unwrap();
+        assert_eq!(map.file_count(), 3);
+
+        assert_eq!(
+            map.lookup(0x1f00, 0x200),
+            [
+                FileBlock {
+                    path: "/a",
+                    file_offset: 0xf00,
+                    disk_offset: 0x1f00,
+                    len: 0x100,
+                },
+                FileBlock {
+                    path: "/b",
+                    file_offset: 0x100,
+                    disk_offset: 0x2000,
+                    len: 0x100,
+                },
+            ]
+        );
+        assert_eq!(map.lookup(0x2100, 0xf00), []);
+    }
+
+    #[test]
+    fn test_hard_links() {
+        let mut fs = TestFs::default();
+        fs.dirs
+            .insert(0, vec![("small", 1), ("big", 2), ("link", 2)]);
+        fs.files.insert(1, vec![extent(0, 0x100, 0x10)]);
+        fs.files.insert(2, vec![extent(0, 0, 0x1000)]);
+
+        let map = BlockMap::build(&mut fs).unwrap();
+
+        // both links to the big file overlap the small file, which starts after them
+        let blocks = map.lookup(0x800, 1);
+        assert_eq!(paths(&blocks), ["/big", "/link"]);
+        assert!(blocks.iter().all(|block| block.file_offset == 0x800));
+
+        assert_eq!(paths(&map.lookup(0x108, 1)), ["/big", "/link", "/small"]);
+    }
+
+    #[test]
+    fn test_directory_cycle() {
+        let mut fs = TestFs::default();
+        fs.dirs.insert(0, vec![("dir", 1)]);
+        fs.dirs
+            .insert(1, vec![("root", 0), ("self", 1), ("file", 2)]);
+        fs.files.insert(2, vec![extent(0, 0x1000, 0x10)]);
+
+        let map = BlockMap::build(&mut fs).unwrap();
+        assert_eq!(map.file_count(), 3);
+        assert_eq!(paths(&map.lookup(0x1000, 1)), ["/dir/file"]);
+    }
+}
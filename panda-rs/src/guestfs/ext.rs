@@ -0,0 +1,388 @@
+use super::{le16, le32, Extent, FileKind, Fs, GuestFsError, Metadata, Volume};
+
+pub(super) const SUPERBLOCK_OFFSET: u64 = 1024;
+pub(super) const MAGIC_OFFSET: u64 = 56;
+pub(super) const MAGIC: u16 = 0xef53;
+
+const ROOT_INODE: u32 = 2;
+
+const INCOMPAT_COMPRESSION: u32 = 0x1;
+const INCOMPAT_META_BG: u32 = 0x10;
+const INCOMPAT_64BIT: u32 = 0x80;
+const UNSUPPORTED_INCOMPAT: u32 = INCOMPAT_COMPRESSION | INCOMPAT_META_BG;
+
+const EXTENTS_FL: u32 = 0x8_0000;
+const INLINE_DATA_FL: u32 = 0x1000_0000;
+
+const EXTENT_MAGIC: u16 = 0xf30a;
+const MAX_EXTENT_DEPTH: usize = 5;
+
+/// Size of `i_block`, the inode field holding block pointers, extents or inline data
+const I_BLOCK_LEN: usize = 60;
+
+/// A (logical block, physical block, block count) mapping of part of a file
+type BlockRun = (u64, u64, u64);
+
+/// A read-only ext2/3/4 filesystem
+pub(super) struct ExtFs {
+    vol: Volume,
+    block_size: u64,
+    inodes_per_group: u32,
+    inode_size: u64,
+    desc_size: u64,
+    gdt_offset: u64,
+}
+
+#[derive(Clone)]
+pub(super) struct Inode {
+    num: u32,
+    mode: u16,
+    size: u64,
+    flags: u32,
+    blocks: u32,
+    file_acl: u32,
+    block: [u8; I_BLOCK_LEN],
+}
+
+impl Inode {
+    fn kind(&self) -> FileKind {
+        match self.mode & 0xf000 {
+            0x4000 => FileKind::Directory,
+            0x8000 => FileKind::File,
+            0xa000 => FileKind::Symlink,
+            _ => FileKind::Other,
+        }
+    }
+
+    /// Whether this is a symlink with its target stored directly in the inode
+    fn is_fast_symlink(&self, block_size: u64) -> bool {
+        let xattr_blocks = if self.file_acl != 0 {
+            (block_size / 512) as u32
+        } else {
+            0
+        };
+
+        self.kind() == FileKind::Symlink
+            && self.size < I_BLOCK_LEN as u64
+            && self.blocks == xattr_blocks
+    }
+}
+
+impl ExtFs {
+    pub(super) fn open(mut vol: Volume) -> Result<Self, GuestFsError> {
+        let superblock = vol.read_vec(SUPERBLOCK_OFFSET, 1024)?;
+        if le16(&superblock, MAGIC_OFFSET as usize) != MAGIC {
+            return Err(GuestFsError::Corrupt("invalid ext superblock"));
+        }
+
+        let log_block_size = le32(&superblock, 24);
+        if log_block_size > 6 {
+            return Err(GuestFsError::Corrupt("invalid ext block size"));
+        }
+        let block_size = 1024 << log_block_size;
+
+        let incompat = le32(&superblock, 96);
+        if incompat & UNSUPPORTED_INCOMPAT != 0 {
+            return Err(GuestFsError::Unsupported("ext incompatible features"));
+        }
+
+        let inode_size = match le32(&superblock, 76) {
+            0 => 128,
+            _ => le16(&superblock, 88) as u64,
+        };
+
+        let desc_size = if incompat & INCOMPAT_64BIT != 0 {
+            (le16(&superblock, 254) as u64).max(32)
+        } else {
+            32
+        };
+
+        let inodes_per_group = le32(&superblock, 40);
+        if inodes_per_group == 0 || inode_size < 128 {
+            return Err(GuestFsError::Corrupt("invalid ext superblock"));
+        }
+
+        let first_data_block = le32(&superblock, 20) as u64;
+
+        Ok(Self {
+            vol,
+            block_size,
+            inodes_per_group,
+            inode_size,
+            desc_size,
+            gdt_offset: (first_data_block + 1) * block_size,
+        })
+    }
+
+    fn read_block(&mut self, block: u64) -> Result<Vec<u8>, GuestFsError> {
+        self.vol
+            .read_vec(block * self.block_size, self.block_size as usize)
+    }
+
+    fn read_inode(&mut self, num: u32) -> Result<Inode, GuestFsError> {
+        if num == 0 {
+            return Err(GuestFsError::Corrupt("reference to inode 0"));
+        }
+
+        let group = ((num - 1) / self.inodes_per_group) as u64;
+        let index = ((num - 1) % self.inodes_per_group) as u64;
+
+        let desc = self.vol.read_vec(
+            self.gdt_offset + (group * self.desc_size),
+            self.desc_size as usize,
+        )?;
+        let mut inode_table = le32(&desc, 8) as u64;
+        if self.desc_size >= 64 {
+            inode_table |= (le32(&desc, 0x28) as u64) << 32;
+        }
+
+        let raw = self.vol.read_vec(
+            (inode_table * self.block_size) + (index * self.inode_size),
+            128,
+        )?;
+
+        let mut block = [0u8; I_BLOCK_LEN];
+        block.copy_from_slice(&raw[40..40 + I_BLOCK_LEN]);
+
+        Ok(Inode {
+            num,
+            mode: le16(&raw, 0),
+            size: le32(&raw, 4) as u64 | ((le32(&raw, 108) as u64) << 32),
+            flags: le32(&raw, 32),
+            blocks: le32(&raw, 28),
+            file_acl: le32(&raw, 104),
+            block,
+        })
+    }
+
+    fn block_map(&mut self, inode: &Inode) -> Result<Vec<BlockRun>, GuestFsError> {
+        let mut runs = Vec::new();
+
+        if inode.flags & EXTENTS_FL != 0 {
+            self.read_extents(&inode.block, 0, &mut runs)?;
+        } else {
+            let pointers: Vec<u64> = inode
+                .block
+                .chunks_exact(4)
+                .map(|ptr| le32(ptr, 0) as u64)
+                .collect();
+
+            for (i, &block) in pointers[..12].iter().enumerate() {
+                if block != 0 {
+                    runs.push((i as u64, block, 1));
+                }
+            }
+
+            let per_block = self.block_size / 4;
+            let mut first_logical = 12;
+            for (level, &block) in pointers[12..].iter().enumerate() {
+                let level = level as u32 + 1;
+                self.read_indirect(block, level, first_logical, &mut runs)?;
+                first_logical += per_block.pow(level);
+            }
+        }
+
+        Ok(runs)
+    }
+
+    fn read_extents(
+        &mut self,
+        node: &[u8],
+        level: usize,
+        runs: &mut Vec<BlockRun>,
+    ) -> Result<(), GuestFsError> {
+        if le16(node, 0) != EXTENT_MAGIC || level > MAX_EXTENT_DEPTH {
+            return Err(GuestFsError::Corrupt("invalid extent tree"));
+        }
+
+        let entries = le16(node, 2) as usize;
+        let depth = le16(node, 6);
+        if node.len() < 12 + (entries * 12) {
+            return Err(GuestFsError::Corrupt("invalid extent tree"));
+        }
+
+        for entry in node[12..12 + (entries * 12)].chunks_exact(12) {
+            if depth == 0 {
+                let logical = le32(entry, 0) as u64;
+                let len = le16(entry, 4) as u64;
+                let physical = ((le16(entry, 6) as u64) << 32) | le32(entry, 8) as u64;
+
+                // Uninitialized extents have their length offset by 32768 and read as zeros
+                if len <= 32768 {
+                    runs.push((logical, physical, len));
+                }
+            } else {
+                let child = ((le16(entry, 8) as u64) << 32) | le32(entry, 4) as u64;
+                let child = self.read_block(child)?;
+
+                self.read_extents(&child, level + 1, runs)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_indirect(
+        &mut self,
+        block: u64,
+        level: u32,
+        first_logical: u64,
+        runs: &mut Vec<BlockRun>,
+    ) -> Result<(), GuestFsError> {
+        if block == 0 {
+            return Ok(());
+        }
+
+        let span = (self.block_size / 4).pow(level - 1);
+        let pointers = self.read_block(block)?;
+
+        for (i, ptr) in pointers.chunks_exact(4).enumerate() {
+            let ptr = le32(ptr, 0) as u64;
+            let logical = first_logical + (i as u64 * span);
+
+            if ptr == 0 {
+                continue;
+            } else if level == 1 {
+                runs.push((logical, ptr, 1));
+            } else {
+                self.read_indirect(ptr, level - 1, logical, runs)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_data(&mut self, inode: &Inode) -> Result<Vec<u8>, GuestFsError> {
+        let size = inode.size as usize;
+
+        if inode.flags & INLINE_DATA_FL != 0 || inode.is_fast_symlink(self.block_size) {
+            if size > I_BLOCK_LEN {
+                return Err(GuestFsError::Unsupported(
+                    "ext inline data stored in xattrs",
+                ));
+            }
+
+            return Ok(inode.block[..size].to_vec());
+        }
+
+        // files can't hold more data than the volume, except sparse files, which are
+        // too large to read into memory anyway
+        if inode.size > self.vol.len {
+            return Err(GuestFsError::Unsupported(
+                "ext file larger than its volume, such as a sparse file",
+            ));
+        }
+
+        let mut data = vec![0u8; size];
+        for (logical, physical, len) in self.block_map(inode)? {
+            let start = logical * self.block_size;
+            if start >= inode.size {
+                continue;
+            }
+
+            let len = (len * self.block_size).min(inode.size - start);
+            let range = start as usize..(start + len) as usize;
+            self.vol
+                .read_at(physical * self.block_size, &mut data[range])?;
+        }
+
+        Ok(data)
+    }
+
+    /// Parse the entries of a directory into (name, inode number) pairs
+    fn dir_entries(&mut self, dir: &Inode) -> Result<Vec<(String, u32)>, GuestFsError> {
+        let data = self.read_data(dir)?;
+
+        // Inline directories start with the inode number of the parent
+        let mut offset = if dir.flags & INLINE_DATA_FL != 0 {
+            4
+        } else {
+            0
+        };
+        let mut entries = Vec::new();
+
+        while offset + 8 <= data.len() {
+            let inode = le32(&data, offset);
+            let rec_len = le16(&data, offset + 4) as usize;
+            let name_len = data[offset + 6] as usize;
+
+            if rec_len < 8 {
+                return Err(GuestFsError::Corrupt("invalid ext directory entry"));
+            }
+
+            let name_end = offset + 8 + name_len;
+            if inode != 0 && name_end <= data.len() {
+                let name = String::from_utf8_lossy(&data[offset + 8..name_end]);
+                if name != "." && name != ".." {
+                    entries.push((name.into_owned(), inode));
+                }
+            }
+
+            offset += rec_len;
+        }
+
+        Ok(entries)
+    }
+}
+
+impl Fs for ExtFs {
+    type Node = Inode;
+
+    fn root(&mut self) -> Result<Inode, GuestFsError> {
+        self.read_inode(ROOT_INODE)
+    }
+
+    fn id(&mut self, node: &Inode) -> u64 {
+        node.num as u64
+    }
+
+    fn metadata(&mut self, node: &Inode) -> Metadata {
+        Metadata {
+            kind: node.kind(),
+            size: node.size,
+            mode: Some(node.mode & 0o7777),
+        }
+    }
+
+    fn lookup(&mut self, dir: &Inode, name: &str) -> Result<Option<Inode>, GuestFsError> {
+        let inode = self
+            .dir_entries(dir)?
+            .into_iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, inode)| inode);
+
+        inode.map(|num| self.read_inode(num)).transpose()
+    }
+
+    fn read_dir(&mut self, dir: &Inode) -> Result<Vec<(String, Inode)>, GuestFsError> {
+        self.dir_entries(dir)?
+            .into_iter()
+            .map(|(name, num)| Ok((name, self.read_inode(num)?)))
+            .collect()
+    }
+
+    fn read_file(&mut self, node: &Inode) -> Result<Vec<u8>, GuestFsError> {
+        self.read_data(node)
+    }
+
+    fn extents(&mut self, node: &Inode) -> Result<Vec<Extent>, GuestFsError> {
+        // data stored in the inode is part of the inode table rather than the file
+        if node.flags & INLINE_DATA_FL != 0 || node.is_fast_symlink(self.block_size) {
+            return Ok(Vec::new());
+        }
+
+        let extents = self
+            .block_map(node)?
+            .into_iter()
+            .map(|(logical, physical, len)| (logical * self.block_size, physical, len))
+            .filter(|&(start, _, _)| start < node.size)
+            .map(|(start, physical, len)| Extent {
+                file_offset: start,
+                disk_offset: self.vol.disk_offset(physical * self.block_size),
+                len: (len * self.block_size).min(node.size - start),
+            })
+            .collect();
+
+        Ok(extents)
+    }
+}
@@ -0,0 +1,373 @@
+use super::{le16, le32, Extent, FileKind, Fs, GuestFsError, Metadata, Volume};
+
+const DIR_ENTRY_SIZE: usize = 32;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xe5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// Check whether a boot sector looks like a FAT volume boot record
+pub(super) fn is_fat(boot_sector: &[u8]) -> bool {
+    let bytes_per_sector = le16(boot_sector, 11);
+    let sectors_per_cluster = boot_sector[13];
+    let num_fats = boot_sector[16];
+
+    boot_sector[510..] == [0x55, 0xaa]
+        && matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+        && sectors_per_cluster.is_power_of_two()
+        && num_fats != 0
+        && (&boot_sector[54..57] == b"FAT" || &boot_sector[82..85] == b"FAT")
+}
+
+/// A read-only FAT12/16/32 filesystem
+pub(super) struct FatFs {
+    vol: Volume,
+    fat_type: FatType,
+    cluster_size: u64,
+    cluster_count: u32,
+    fat_offset: u64,
+    data_offset: u64,
+
+    /// Location and size of the fixed root directory (FAT12/16 only)
+    root_offset: u64,
+    root_len: usize,
+
+    /// First cluster of the root directory (FAT32 only)
+    root_cluster: u32,
+}
+
+#[derive(Clone)]
+pub(super) struct FatNode {
+    cluster: u32,
+    size: u32,
+    is_dir: bool,
+    is_root: bool,
+}
+
+impl FatFs {
+    pub(super) fn open(mut vol: Volume) -> Result<Self, GuestFsError> {
+        let boot_sector = vol.read_vec(0, 512)?;
+        if !is_fat(&boot_sector) {
+            return Err(GuestFsError::Corrupt("invalid FAT boot sector"));
+        }
+
+        let bytes_per_sector = le16(&boot_sector, 11) as u64;
+        let sectors_per_cluster = boot_sector[13] as u64;
+        let reserved_sectors = le16(&boot_sector, 14) as u64;
+        let num_fats = boot_sector[16] as u64;
+        let root_entries = le16(&boot_sector, 17) as u64;
+
+        let total_sectors = match le16(&boot_sector, 19) {
+            0 => le32(&boot_sector, 32) as u64,
+            sectors => sectors as u64,
+        };
+        let fat_sectors = match le16(&boot_sector, 22) {
+            0 => le32(&boot_sector, 36) as u64,
+            sectors => sectors as u64,
+        };
+
+        let root_sectors = (root_entries * DIR_ENTRY_SIZE as u64).div_ceil(bytes_per_sector);
+        let root_sector = reserved_sectors + (num_fats * fat_sectors);
+        let data_sector = root_sector + root_sectors;
+
+        let cluster_count = total_sectors
+            .checked_sub(data_sector)
+            .ok_or(GuestFsError::Corrupt("invalid FAT geometry"))?
+            / sectors_per_cluster;
+
+        let fat_type = match cluster_count {
+            0..=4084 => FatType::Fat12,
+            4085..=65524 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+
+        let root_cluster = match fat_type {
+            FatType::Fat32 => le32(&boot_sector, 44),
+            _ => 0,
+        };
+
+        Ok(Self {
+            vol,
+            fat_type,
+            cluster_size: sectors_per_cluster * bytes_per_sector,
+            cluster_count: cluster_count as u32,
+            fat_offset: reserved_sectors * bytes_per_sector,
+            data_offset: data_sector * bytes_per_sector,
+            root_offset: root_sector * bytes_per_sector,
+            root_len: (root_entries as usize) * DIR_ENTRY_SIZE,
+            root_cluster,
+        })
+    }
+
+    /// Get the cluster following `cluster` in its chain, or `None` at the end of the chain
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, GuestFsError> {
+        let (offset, len, end) = match self.fat_type {
+            FatType::Fat12 => (cluster as u64 + (cluster as u64 / 2), 2, 0xff8),
+            FatType::Fat16 => (cluster as u64 * 2, 2, 0xfff8),
+            FatType::Fat32 => (cluster as u64 * 4, 4, 0x0fff_fff8),
+        };
+
+        let mut raw = [0u8; 4];
+        self.vol
+            .read_at(self.fat_offset + offset, &mut raw[..len])?;
+
+        let next = match self.fat_type {
+            FatType::Fat12 if cluster & 1 != 0 => le32(&raw, 0) >> 4,
+            FatType::Fat12 => le32(&raw, 0) & 0xfff,
+            FatType::Fat16 => le32(&raw, 0),
+            FatType::Fat32 => le32(&raw, 0) & 0x0fff_ffff,
+        };
+
+        if next >= end {
+            Ok(None)
+        } else if next < 2 {
+            Err(GuestFsError::Corrupt("invalid FAT cluster chain"))
+        } else {
+            Ok(Some(next))
+        }
+    }
+
+    /// Read a cluster chain, stopping early once `limit` bytes have been read
+    fn read_chain(&mut self, first: u32, limit: Option<u64>) -> Result<Vec<u8>, GuestFsError> {
+        let mut data = Vec::new();
+        let mut cluster = Some(first).filter(|&cluster| cluster >= 2);
+        let mut remaining_clusters = self.cluster_count;
+
+        while let Some(current) = cluster {
+            if limit.map_or(false, |limit| data.len() as u64 >= limit) {
+                break;
+            }
+
+            if remaining_clusters == 0 {
+                return Err(GuestFsError::Corrupt("cyclic FAT cluster chain"));
+            }
+            remaining_clusters -= 1;
+
+            let offset = self.data_offset + ((current as u64 - 2) * self.cluster_size);
+            data.extend(self.vol.read_vec(offset, self.cluster_size as usize)?);
+
+            cluster = self.next_cluster(current)?;
+        }
+
+        if let Some(limit) = limit {
+            data.truncate(limit as usize);
+        }
+
+        Ok(data)
+    }
+
+    /// Get the location of each run of contiguous clusters in a cluster chain, stopping
+    /// once `limit` bytes are covered
+    fn chain_extents(
+        &mut self,
+        first: u32,
+        limit: Option<u64>,
+    ) -> Result<Vec<Extent>, GuestFsError> {
+        let mut extents: Vec<Extent> = Vec::new();
+        let mut cluster = Some(first).filter(|&cluster| cluster >= 2);
+        let mut remaining_clusters = self.cluster_count;
+        let mut file_offset = 0;
+
+        while let Some(current) = cluster {
+            if limit.map_or(false, |limit| file_offset >= limit) {
+                break;
+            }
+
+            if remaining_clusters == 0 {
+                return Err(GuestFsError::Corrupt("cyclic FAT cluster chain"));
+            }
+            remaining_clusters -= 1;
+
+            let len = limit.map_or(self.cluster_size, |limit| {
+                self.cluster_size.min(limit - file_offset)
+            });
+            let offset = self.data_offset + ((current as u64 - 2) * self.cluster_size);
+            let disk_offset = self.vol.disk_offset(offset);
+
+            match extents.last_mut() {
+                Some(last) if last.disk_offset + last.len == disk_offset => last.len += len,
+                _ => extents.push(Extent {
+                    file_offset,
+                    disk_offset,
+                    len,
+                }),
+            }
+
+            file_offset += len;
+            cluster = self.next_cluster(current)?;
+        }
+
+        Ok(extents)
+    }
+
+    fn dir_entries(&mut self, dir: &FatNode) -> Result<Vec<(String, FatNode)>, GuestFsError> {
+        let data = if dir.is_root && self.fat_type != FatType::Fat32 {
+            self.vol.read_vec(self.root_offset, self.root_len)?
+        } else {
+            self.read_chain(dir.cluster, None)?
+        };
+
+        let mut entries = Vec::new();
+        let mut long_name: Vec<u16> = Vec::new();
+
+        for entry in data.chunks_exact(DIR_ENTRY_SIZE) {
+            let attr = entry[11];
+
+            match entry[0] {
+                ENTRY_END => break,
+                ENTRY_DELETED => {
+                    long_name.clear();
+                    continue;
+                }
+                _ => (),
+            }
+
+            if attr == ATTR_LONG_NAME {
+                // Long name entries are stored last part first
+                let mut part: Vec<u16> = [1..11, 14..26, 28..32]
+                    .iter()
+                    .flat_map(|range| entry[range.clone()].chunks_exact(2))
+                    .map(|c| le16(c, 0))
+                    .collect();
+
+                part.extend(long_name.drain(..));
+                long_name = part;
+                continue;
+            }
+
+            if attr & ATTR_VOLUME_ID != 0 {
+                long_name.clear();
+                continue;
+            }
+
+            let name = if long_name.is_empty() {
+                short_name(entry)
+            } else {
+                let end = long_name
+                    .iter()
+                    .position(|&c| c == 0)
+                    .unwrap_or(long_name.len());
+
+                String::from_utf16_lossy(&long_name[..end])
+            };
+            long_name.clear();
+
+            if name == "." || name == ".." {
+                continue;
+            }
+
+            let cluster = ((le16(entry, 20) as u32) << 16) | le16(entry, 26) as u32;
+            entries.push((
+                name,
+                FatNode {
+                    cluster,
+                    size: le32(entry, 28),
+                    is_dir: attr & ATTR_DIRECTORY != 0,
+                    is_root: false,
+                },
+            ));
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Format the 8.3 name of a directory entry
+fn short_name(entry: &[u8]) -> String {
+    // Flags used by Windows NT to store all-lowercase base names and extensions
+    const LOWER_BASE: u8 = 0x08;
+    const LOWER_EXT: u8 = 0x10;
+
+    let mut base = entry[..8].to_vec();
+    if base[0] == 0x05 {
+        base[0] = ENTRY_DELETED;
+    }
+
+    let mut base = String::from_utf8_lossy(&base).trim_end().to_owned();
+    let mut ext = String::from_utf8_lossy(&entry[8..11]).trim_end().to_owned();
+
+    if entry[12] & LOWER_BASE != 0 {
+        base.make_ascii_lowercase();
+    }
+    if entry[12] & LOWER_EXT != 0 {
+        ext.make_ascii_lowercase();
+    }
+
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+impl Fs for FatFs {
+    type Node = FatNode;
+
+    fn root(&mut self) -> Result<FatNode, GuestFsError> {
+        Ok(FatNode {
+            cluster: self.root_cluster,
+            size: 0,
+            is_dir: true,
+            is_root: true,
+        })
+    }
+
+    fn id(&mut self, node: &FatNode) -> u64 {
+        // every directory starts at its own cluster, other than the FAT12/16 root at 0
+        node.cluster as u64
+    }
+
+    fn metadata(&mut self, node: &FatNode) -> Metadata {
+        Metadata {
+            kind: if node.is_dir {
+                FileKind::Directory
+            } else {
+                FileKind::File
+            },
+            size: node.size as u64,
+            mode: None,
+        }
+    }
+
+    fn lookup(&mut self, dir: &FatNode, name: &str) -> Result<Option<FatNode>, GuestFsError> {
+        let node = self
+            .dir_entries(dir)?
+            .into_iter()
+            .find(|(entry, _)| entry.eq_ignore_ascii_case(name))
+            .map(|(_, node)| node);
+
+        Ok(node)
+    }
+
+    fn read_dir(&mut self, dir: &FatNode) -> Result<Vec<(String, FatNode)>, GuestFsError> {
+        self.dir_entries(dir)
+    }
+
+    fn read_file(&mut self, node: &FatNode) -> Result<Vec<u8>, GuestFsError> {
+        self.read_chain(node.cluster, Some(node.size as u64))
+    }
+
+    fn extents(&mut self, node: &FatNode) -> Result<Vec<Extent>, GuestFsError> {
+        if node.is_root && self.fat_type != FatType::Fat32 {
+            return Ok(vec![Extent {
+                file_offset: 0,
+                disk_offset: self.vol.disk_offset(self.root_offset),
+                len: self.root_len as u64,
+            }]);
+        }
+
+        let limit = Some(node.size as u64).filter(|_| !node.is_dir);
+
+        self.chain_extents(node.cluster, limit)
+    }
+}
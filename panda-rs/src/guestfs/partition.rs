@@ -0,0 +1,272 @@
+use super::{le32, le64, Disk, GuestFsError};
+
+const SECTOR_SIZE: u64 = 512;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+const MBR_TABLE_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_PROTECTIVE_GPT: u8 = 0xee;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// The maximum number of logical partitions followed in an extended partition
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const MAX_GPT_ENTRIES: u32 = 256;
+
+/// A partition of a disk image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// The partition number, as Linux would number it (`/dev/sdaN`)
+    pub index: usize,
+
+    /// Offset of the partition from the start of the disk, in bytes
+    pub start: u64,
+
+    /// Length of the partition in bytes
+    pub len: u64,
+
+    pub kind: PartitionKind,
+}
+
+/// The partition type, as stored in the partition table
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// MBR partition type byte
+    Mbr(u8),
+
+    /// GPT partition type GUID, in on-disk byte order
+    Gpt([u8; 16]),
+}
+
+/// Read the partition table of a disk, returning no partitions if it has none
+pub(super) fn read_partitions(disk: &mut Disk) -> Result<Vec<Partition>, GuestFsError> {
+    if disk.len() < SECTOR_SIZE {
+        return Ok(Vec::new());
+    }
+
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    disk.read_at(0, &mut mbr)?;
+    if mbr[510..] != BOOT_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let mut partitions = Vec::new();
+    for (i, entry) in mbr_entries(&mbr).enumerate() {
+        let (kind, start, len) = entry;
+
+        if kind == MBR_PROTECTIVE_GPT {
+            return read_gpt(disk);
+        } else if MBR_EXTENDED.contains(&kind) {
+            read_logical_partitions(disk, start, &mut partitions)?;
+        } else if kind != 0 {
+            partitions.push(Partition {
+                index: i + 1,
+                start: start * SECTOR_SIZE,
+                len: len * SECTOR_SIZE,
+                kind: PartitionKind::Mbr(kind),
+            });
+        }
+    }
+
+    let disk_len = disk.len();
+    partitions.retain(|part| part.len != 0 && part.start + part.len <= disk_len);
+    partitions.sort_by_key(|part| part.index);
+
+    Ok(partitions)
+}
+
+/// Iterate over the (type, start sector, sector count) of each entry of an MBR or EBR
+fn mbr_entries(sector: &[u8]) -> impl Iterator<Item = (u8, u64, u64)> + '_ {
+    sector[MBR_TABLE_OFFSET..MBR_TABLE_OFFSET + (4 * MBR_ENTRY_SIZE)]
+        .chunks_exact(MBR_ENTRY_SIZE)
+        .map(|entry| (entry[4], le32(entry, 8) as u64, le32(entry, 12) as u64))
+}
+
+/// Follow the chain of EBRs in an extended partition
+fn read_logical_partitions(
+    disk: &mut Disk,
+    extended_start: u64,
+    partitions: &mut Vec<Partition>,
+) -> Result<(), GuestFsError> {
+    let mut ebr_sector = extended_start;
+
+    for index in 5..5 + MAX_LOGICAL_PARTITIONS {
+        if (ebr_sector + 1) * SECTOR_SIZE > disk.len() {
+            break;
+        }
+
+        let mut ebr = [0u8; SECTOR_SIZE as usize];
+        disk.read_at(ebr_sector * SECTOR_SIZE, &mut ebr)?;
+        if ebr[510..] != BOOT_SIGNATURE {
+            break;
+        }
+
+        let mut entries = mbr_entries(&ebr);
+        let (kind, start, len) = entries.next().unwrap();
+        let (next_kind, next_start, _) = entries.next().unwrap();
+
+        if kind != 0 {
+            partitions.push(Partition {
+                index,
+                start: (ebr_sector + start) * SECTOR_SIZE,
+                len: len * SECTOR_SIZE,
+                kind: PartitionKind::Mbr(kind),
+            });
+        }
+
+        if next_kind == 0 || next_start == 0 {
+            break;
+        }
+
+        ebr_sector = extended_start + next_start;
+    }
+
+    Ok(())
+}
+
+fn read_gpt(disk: &mut Disk) -> Result<Vec<Partition>, GuestFsError> {
+    let mut header = [0u8; SECTOR_SIZE as usize];
+    disk.read_at(SECTOR_SIZE, &mut header)?;
+    if &header[..8] != GPT_SIGNATURE {
+        return Err(GuestFsError::Corrupt("invalid GPT header"));
+    }
+
+    let entries_lba = le64(&header, 72);
+    let entry_count = le32(&header, 80).min(MAX_GPT_ENTRIES) as usize;
+    let entry_size = le32(&header, 84) as usize;
+    if entry_size < 128 {
+        return Err(GuestFsError::Corrupt("invalid GPT entry size"));
+    }
+
+    let entries_offset = entries_lba
+        .checked_mul(SECTOR_SIZE)
+        .ok_or(GuestFsError::Corrupt("invalid GPT entries location"))?;
+
+    let mut entries = vec![0u8; entry_count * entry_size];
+    disk.read_at(entries_offset, &mut entries)?;
+
+    let disk_len = disk.len();
+    let partitions = entries
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter_map(|(i, entry)| {
+            let mut type_guid = [0u8; 16];
+            type_guid.copy_from_slice(&entry[..16]);
+
+            let first_lba = le64(entry, 32);
+            let last_lba = le64(entry, 40);
+
+            if type_guid == [0; 16] || last_lba < first_lba {
+                return None;
+            }
+
+            Some(Partition {
+                index: i + 1,
+                start: first_lba.saturating_mul(SECTOR_SIZE),
+                len: (last_lba - first_lba + 1).saturating_mul(SECTOR_SIZE),
+                kind: PartitionKind::Gpt(type_guid),
+            })
+        })
+        .filter(|part| part.start.saturating_add(part.len) <= disk_len)
+        .collect();
+
+    Ok(partitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+
+    const DISK_SECTORS: u64 = 64;
+
+    fn set_entry(sector: &mut [u8], i: usize, kind: u8, start: u32, len: u32) {
+        let entry = &mut sector[MBR_TABLE_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&len.to_le_bytes());
+    }
+
+    fn open_raw(image: &[u8]) -> (tempfile::NamedTempFile, Disk) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), image).unwrap();
+
+        let disk = Disk::Raw {
+            file: File::open(file.path()).unwrap(),
+            len: image.len() as u64,
+        };
+
+        (file, disk)
+    }
+
+    #[test]
+    fn test_no_partition_table() {
+        let (_file, mut disk) = open_raw(&vec![0; (SECTOR_SIZE * DISK_SECTORS) as usize]);
+
+        assert_eq!(read_partitions(&mut disk).unwrap(), []);
+    }
+
+    #[test]
+    fn test_mbr() {
+        let mut image = vec![0; (SECTOR_SIZE * DISK_SECTORS) as usize];
+
+        // a primary partition, an extended partition holding one logical partition,
+        // and a partition past the end of the disk
+        set_entry(&mut image, 0, 0x83, 2, 8);
+        set_entry(&mut image, 1, 0x05, 16, 32);
+        set_entry(&mut image, 2, 0x83, 60, 100);
+        image[510..512].copy_from_slice(&BOOT_SIGNATURE);
+
+        let ebr = &mut image[16 * SECTOR_SIZE as usize..][..SECTOR_SIZE as usize];
+        set_entry(ebr, 0, 0x0b, 1, 15);
+        ebr[510..512].copy_from_slice(&BOOT_SIGNATURE);
+
+        let (_file, mut disk) = open_raw(&image);
+        let partitions = read_partitions(&mut disk).unwrap();
+
+        assert_eq!(
+            partitions,
+            [
+                Partition {
+                    index: 1,
+                    start: 2 * SECTOR_SIZE,
+                    len: 8 * SECTOR_SIZE,
+                    kind: PartitionKind::Mbr(0x83),
+                },
+                Partition {
+                    index: 5,
+                    start: 17 * SECTOR_SIZE,
+                    len: 15 * SECTOR_SIZE,
+                    kind: PartitionKind::Mbr(0x0b),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gpt_out_of_range() {
+        let mut image = vec![0; (SECTOR_SIZE * DISK_SECTORS) as usize];
+        set_entry(
+            &mut image,
+            0,
+            MBR_PROTECTIVE_GPT,
+            1,
+            DISK_SECTORS as u32 - 1,
+        );
+        image[510..512].copy_from_slice(&BOOT_SIGNATURE);
+
+        let header = &mut image[SECTOR_SIZE as usize..][..SECTOR_SIZE as usize];
+        header[..8].copy_from_slice(GPT_SIGNATURE);
+        header[72..80].copy_from_slice(&u64::MAX.to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+
+        let (_file, mut disk) = open_raw(&image);
+        assert!(matches!(
+            read_partitions(&mut disk),
+            Err(GuestFsError::Corrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+use super::{be32, be64, Disk, GuestFsError};
+
+use flate2::read::DeflateDecoder;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+pub(super) const MAGIC: &[u8; 4] = b"QFI\xfb";
+
+const HEADER_V2_LEN: usize = 72;
+const HEADER_V3_LEN: usize = 104;
+
+/// Mask for the host offset in L1 and standard L2 entries
+const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+const COMPRESSED_FLAG: u64 = 1 << 62;
+const ZERO_FLAG: u64 = 1;
+
+/// Incompatible features which don't affect reading the image (dirty, corrupt)
+const KNOWN_INCOMPATIBLE_FEATURES: u64 = 0b11;
+
+/// Number of L2 tables to keep cached before the cache is flushed
+const L2_CACHE_SIZE: usize = 256;
+
+/// The longest backing file name allowed by the qcow2 spec
+const MAX_BACKING_NAME_LEN: usize = 1023;
+
+pub(super) struct Qcow2 {
+    file: File,
+    file_len: u64,
+    cluster_bits: u32,
+    size: u64,
+    l1_table: Vec<u64>,
+    l2_cache: HashMap<u64, Vec<u64>>,
+    cluster_cache: Option<(u64, Vec<u8>)>,
+    backing: Option<Disk>,
+}
+
+impl Qcow2 {
+    /// Open an image, given the canonical paths of the images it is a backing file of
+    /// in order to detect backing files which refer back to themselves
+    pub(super) fn open(
+        path: &Path,
+        file: File,
+        chain: &mut Vec<PathBuf>,
+    ) -> Result<Self, GuestFsError> {
+        let file_len = file.metadata()?.len();
+
+        let mut header = [0u8; HEADER_V3_LEN];
+        file.read_exact_at(&mut header[..HEADER_V2_LEN], 0)?;
+
+        let version = be32(&header, 4);
+        if version != 2 && version != 3 {
+            return Err(GuestFsError::Unsupported("unknown qcow2 version"));
+        }
+
+        if be32(&header, 32) != 0 {
+            return Err(GuestFsError::Unsupported("encrypted qcow2 image"));
+        }
+
+        if version == 3 {
+            file.read_exact_at(&mut header[HEADER_V2_LEN..], HEADER_V2_LEN as u64)?;
+            if be64(&header, 72) & !KNOWN_INCOMPATIBLE_FEATURES != 0 {
+                return Err(GuestFsError::Unsupported("qcow2 incompatible features"));
+            }
+        }
+
+        let cluster_bits = be32(&header, 20);
+        if !(9..=21).contains(&cluster_bits) {
+            return Err(GuestFsError::Corrupt("invalid qcow2 cluster size"));
+        }
+
+        let size = be64(&header, 24);
+        let l1_size = be32(&header, 36) as usize;
+        let l1_offset = be64(&header, 40);
+
+        if l1_size as u64 * 8 > file_len {
+            return Err(GuestFsError::Corrupt(
+                "qcow2 L1 table larger than the image",
+            ));
+        }
+
+        let mut l1_raw = vec![0u8; l1_size * 8];
+        file.read_exact_at(&mut l1_raw, l1_offset)?;
+        let l1_table = l1_raw.chunks_exact(8).map(|entry| be64(entry, 0)).collect();
+
+        let backing_offset = be64(&header, 8);
+        let backing = if backing_offset != 0 {
+            let name_len = be32(&header, 16) as usize;
+            if name_len > MAX_BACKING_NAME_LEN {
+                return Err(GuestFsError::Corrupt("qcow2 backing file name too long"));
+            }
+
+            let mut name = vec![0u8; name_len];
+            file.read_exact_at(&mut name, backing_offset)?;
+
+            let name = String::from_utf8_lossy(&name).into_owned();
+            let backing_path = path
+                .parent()
+                .map(|dir| dir.join(&name))
+                .unwrap_or_else(|| name.into());
+
+            Some(Disk::open_backing(&backing_path, chain)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            file,
+            file_len,
+            cluster_bits,
+            size,
+            l1_table,
+            l2_cache: HashMap::new(),
+            cluster_cache: None,
+            backing,
+        })
+    }
+
+    pub(super) fn len(&self) -> u64 {
+        self.size
+    }
+
+    fn cluster_size(&self) -> u64 {
+        1 << self.cluster_bits
+    }
+
+    pub(super) fn read_at(
+        &mut self,
+        mut offset: u64,
+        mut buf: &mut [u8],
+    ) -> Result<(), GuestFsError> {
+        while !buf.is_empty() {
+            let in_cluster = offset & (self.cluster_size() - 1);
+            let len = (buf.len() as u64).min(self.cluster_size() - in_cluster) as usize;
+            let (chunk, rest) = std::mem::take(&mut buf).split_at_mut(len);
+
+            self.read_in_cluster(offset, chunk)?;
+
+            offset += len as u64;
+            buf = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Read a range which is fully contained by a single guest cluster
+    fn read_in_cluster(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), GuestFsError> {
+        let l2_bits = self.cluster_bits - 3;
+        let l1_index = (offset >> (self.cluster_bits + l2_bits)) as usize;
+        let l2_index = ((offset >> self.cluster_bits) & ((1 << l2_bits) - 1)) as usize;
+        let in_cluster = (offset & (self.cluster_size() - 1)) as usize;
+
+        let l2_offset = self.l1_table.get(l1_index).copied().unwrap_or(0) & OFFSET_MASK;
+        if l2_offset == 0 {
+            return self.read_unallocated(offset, buf);
+        }
+
+        let entry = self.l2_table(l2_offset)?[l2_index];
+        if entry & COMPRESSED_FLAG != 0 {
+            let cluster = self.compressed_cluster(entry)?;
+            buf.copy_from_slice(&cluster[in_cluster..in_cluster + buf.len()]);
+
+            Ok(())
+        } else if entry & ZERO_FLAG != 0 {
+            buf.iter_mut().for_each(|byte| *byte = 0);
+
+            Ok(())
+        } else {
+            match entry & OFFSET_MASK {
+                0 => self.read_unallocated(offset, buf),
+                host => Ok(self.file.read_exact_at(buf, host + in_cluster as u64)?),
+            }
+        }
+    }
+
+    /// Read a range not allocated in this image, falling through to the backing file
+    fn read_unallocated(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), GuestFsError> {
+        buf.iter_mut().for_each(|byte| *byte = 0);
+
+        if let Some(backing) = &mut self.backing {
+            let backing_len = backing.len();
+            if offset < backing_len {
+                let len = (backing_len - offset).min(buf.len() as u64) as usize;
+                backing.read_at(offset, &mut buf[..len])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn l2_table(&mut self, l2_offset: u64) -> Result<&[u64], GuestFsError> {
+        if !self.l2_cache.contains_key(&l2_offset) {
+            if self.l2_cache.len() >= L2_CACHE_SIZE {
+                self.l2_cache.clear();
+            }
+
+            let mut raw = vec![0u8; self.cluster_size() as usize];
+            self.file.read_exact_at(&mut raw, l2_offset)?;
+
+            let table = raw.chunks_exact(8).map(|entry| be64(entry, 0)).collect();
+            self.l2_cache.insert(l2_offset, table);
+        }
+
+        Ok(&self.l2_cache[&l2_offset])
+    }
+
+    fn compressed_cluster(&mut self, entry: u64) -> Result<&[u8], GuestFsError> {
+        let size_shift = 62 - (self.cluster_bits - 8);
+        let host = entry & ((1 << size_shift) - 1);
+
+        let cached = matches!(&self.cluster_cache, Some((offset, _)) if *offset == host);
+        if !cached {
+            let sectors = ((entry >> size_shift) & ((1 << (self.cluster_bits - 8)) - 1)) + 1;
+            let compressed_len =
+                (sectors * 512 - (host & 511)).min(self.file_len.saturating_sub(host)) as usize;
+
+            let mut compressed = vec![0u8; compressed_len];
+            self.file.read_exact_at(&mut compressed, host)?;
+
+            let mut cluster = vec![0u8; self.cluster_size() as usize];
+            DeflateDecoder::new(&compressed[..])
+                .read_exact(&mut cluster)
+                .map_err(|_| GuestFsError::Corrupt("failed to decompress qcow2 cluster"))?;
+
+            self.cluster_cache = Some((host, cluster));
+        }
+
+        Ok(&self.cluster_cache.as_ref().unwrap().1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLUSTER_BITS: u32 = 9;
+    const CLUSTER_SIZE: usize = 1 << CLUSTER_BITS;
+
+    /// Write a qcow2 image of 8 clusters with only the first allocated, holding `data`.
+    /// The header is in the first host cluster, followed by the L1 table, the L2 table
+    /// and the data.
+    fn write_image(path: &Path, backing: Option<&str>, l1_size: u32, data: u8) {
+        let mut image = vec![0u8; CLUSTER_SIZE * 4];
+
+        image[..4].copy_from_slice(MAGIC);
+        image[4..8].copy_from_slice(&2u32.to_be_bytes());
+        if let Some(backing) = backing {
+            image[8..16].copy_from_slice(&(HEADER_V2_LEN as u64).to_be_bytes());
+            image[16..20].copy_from_slice(&(backing.len() as u32).to_be_bytes());
+            image[HEADER_V2_LEN..HEADER_V2_LEN + backing.len()].copy_from_slice(backing.as_bytes());
+        }
+        image[20..24].copy_from_slice(&CLUSTER_BITS.to_be_bytes());
+        image[24..32].copy_from_slice(&(CLUSTER_SIZE as u64 * 8).to_be_bytes());
+        image[36..40].copy_from_slice(&l1_size.to_be_bytes());
+        image[40..48].copy_from_slice(&(CLUSTER_SIZE as u64).to_be_bytes());
+
+        // the copied flag in the top bit is ignored when reading
+        let l1_entry = (CLUSTER_SIZE as u64 * 2) | 1 << 63;
+        image[CLUSTER_SIZE..CLUSTER_SIZE + 8].copy_from_slice(&l1_entry.to_be_bytes());
+
+        let l2_entry = CLUSTER_SIZE as u64 * 3;
+        image[CLUSTER_SIZE * 2..CLUSTER_SIZE * 2 + 8].copy_from_slice(&l2_entry.to_be_bytes());
+
+        image[CLUSTER_SIZE * 3..]
+            .iter_mut()
+            .for_each(|byte| *byte = data);
+
+        std::fs::write(path, image).unwrap();
+    }
+
+    fn read(disk: &mut Disk, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0xff; len];
+        disk.read_at(offset, &mut buf).unwrap();
+
+        buf
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("disk.qcow2");
+        write_image(&path, None, 1, 0x5a);
+
+        let mut disk = Disk::open(&path).unwrap();
+        assert_eq!(disk.len(), CLUSTER_SIZE as u64 * 8);
+        assert_eq!(read(&mut disk, 0, CLUSTER_SIZE), vec![0x5a; CLUSTER_SIZE]);
+
+        // unallocated clusters read as zeros, including across a cluster boundary
+        let mut expected = vec![0x5a; 16];
+        expected.extend(vec![0; 16]);
+        assert_eq!(read(&mut disk, CLUSTER_SIZE as u64 - 16, 32), expected);
+    }
+
+    #[test]
+    fn test_backing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("base.raw"), vec![0xaa; CLUSTER_SIZE * 8]).unwrap();
+
+        let path = dir.path().join("overlay.qcow2");
+        write_image(&path, Some("base.raw"), 1, 0x5a);
+
+        let mut disk = Disk::open(&path).unwrap();
+        assert_eq!(read(&mut disk, 0, 4), [0x5a; 4]);
+        assert_eq!(read(&mut disk, CLUSTER_SIZE as u64, 4), [0xaa; 4]);
+    }
+
+    #[test]
+    fn test_backing_file_loop() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = dir.path().join("self.qcow2");
+        write_image(&path, Some("self.qcow2"), 1, 0);
+        assert!(matches!(Disk::open(&path), Err(GuestFsError::Corrupt(_))));
+
+        let (a, b) = (dir.path().join("a.qcow2"), dir.path().join("b.qcow2"));
+        write_image(&a, Some("b.qcow2"), 1, 0);
+        write_image(&b, Some("a.qcow2"), 1, 0);
+        assert!(matches!(Disk::open(&a), Err(GuestFsError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_oversized_l1_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("disk.qcow2");
+        write_image(&path, None, u32::MAX, 0);
+
+        assert!(matches!(Disk::open(&path), Err(GuestFsError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,222 @@
+//! Loading shared libraries into running guest processes
+//!
+//! [`load_library`] performs a `dlopen` inside of a running Linux process, allowing an
+//! agent library to be injected into a process for instrumentation. It is built out
+//! of pieces which can also be used directly from a
+//! [syscall injector](crate::syscall_injection):
+//!
+//! * [`inject_string`] and [`guest_free`] copy strings into the memory of the process
+//! and free them again
+//! * [`find_symbol`] looks up functions exported by the process's C library
+//! * [`call_function`](crate::syscall_injection::call_function) calls a function in
+//! the process
+//!
+//! Requires OSI to be loaded, and a glibc or musl based guest.
+//!
+//! [`inject_string`]: crate::syscall_injection::inject_string
+//! [`guest_free`]: crate::syscall_injection::guest_free
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::inject;
+//! use panda::prelude::*;
+//!
+//! # let (cpu, pid): (&mut CPUState, target_pid_t) = todo!();
+//! let load = inject::load_library(cpu, pid, "/tmp/agent.so").unwrap();
+//!
+//! // later, once the process has made a system call
+//! match load.result() {
+//!     Some(Ok(handle)) => println!("Loaded agent, handle = {:#x}", handle),
+//!     Some(Err(err)) => eprintln!("Failed to load agent: {}", err),
+//!     None => println!("Agent not loaded yet"),
+//! }
+//! ```
+
+use crate::mem::virtual_memory_read;
+use crate::plugins::osi::OSI;
+use crate::plugins::syscalls2::Syscalls2Callbacks;
+use crate::prelude::*;
+use crate::syscall_injection::{
+    call_function, guest_free, inject_string, run_injector, FunctionCallError, GuestAllocError,
+};
+use crate::{sys, PppCallback};
+
+use once_cell::sync::OnceCell;
+
+use std::ffi::CStr;
+use std::sync::Arc;
+
+/// Resolve all undefined symbols when loading a library
+pub const RTLD_NOW: target_ulong = 2;
+
+/// Makes `__libc_dlopen_mode` behave like `dlopen`
+const RTLD_DLOPEN: target_ulong = 0x8000_0000;
+
+/// The longest `dlerror` message to read
+const MAX_ERROR_LEN: usize = 0x200;
+
+/// An error encountered while injecting into a guest process
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum InjectError {
+    #[error("no process with pid {0}")]
+    NoSuchProcess(target_pid_t),
+
+    #[error("failed to find {0} in the libraries loaded by the process")]
+    SymbolNotFound(&'static str),
+
+    #[error(transparent)]
+    Alloc(#[from] GuestAllocError),
+
+    #[error("dlopen failed: {0}")]
+    DlopenFailed(String),
+
+    #[error("failed to call a function in the process: {0}")]
+    CallFailed(#[from] FunctionCallError),
+}
+
+/// Whether a library is a C library, which `dlopen` and `dlerror` are looked up in
+fn is_libc(name: &str) -> bool {
+    ["libc.", "libc-", "libdl.", "libdl-", "ld-musl-"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Look up a function exported by one of the C libraries (libc and libdl) loaded by
+/// the current process, returning its address
+pub fn find_symbol(cpu: &mut CPUState, name: &str) -> Option<target_ptr_t> {
+    let mut process = *OSI.get_current_process(cpu)?;
+    let mappings = OSI.get_mappings(cpu, &mut process);
+    if mappings.is_null() {
+        return None;
+    }
+
+    // each library is mapped in several pieces, the ELF header is in the lowest
+    let mut libraries: Vec<(String, target_ptr_t)> = Vec::new();
+    for mapping in mappings.iter() {
+        if mapping.name.is_null() {
+            continue;
+        }
+
+        let name = unsafe { CStr::from_ptr(mapping.name) }.to_string_lossy();
+        if !is_libc(&name) {
+            continue;
+        }
+
+        match libraries.iter_mut().find(|(lib, _)| *lib == name) {
+            Some((_, base)) => *base = (*base).min(mapping.base),
+            None => libraries.push((name.into_owned(), mapping.base)),
+        }
+    }
+
+    libraries
+        .into_iter()
+        .find_map(|(_, base)| crate::elf::find_symbol(cpu, base, name))
+        .map(|(addr, _)| addr)
+}
+
+/// Read the message of the last error from `dlerror`, if it can be found
+async fn dlerror(cpu: &mut CPUState) -> String {
+    let msg = match find_symbol(cpu, "dlerror") {
+        Some(dlerror) => call_function(dlerror, &[]).await.unwrap_or(0),
+        None => 0,
+    };
+
+    if msg == 0 {
+        return String::from("unknown error");
+    }
+
+    let bytes = virtual_memory_read(cpu, msg, MAX_ERROR_LEN).unwrap_or_default();
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// Load a shared library into the process being injected into by calling `dlopen`,
+/// returning the handle of the library. Should only be run within a syscall injector.
+///
+/// `path` is a path inside the guest. If the process doesn't have `dlopen` (glibc
+/// before 2.34 without libdl loaded), glibc's internal `__libc_dlopen_mode` is used
+/// instead, in which case errors don't include a message from `dlerror`.
+pub async fn dlopen(path: &str, flags: target_ulong) -> Result<target_ptr_t, InjectError> {
+    let cpu = unsafe { &mut *sys::get_cpu() };
+
+    let (func, flags) = match find_symbol(cpu, "dlopen") {
+        Some(dlopen) => (dlopen, flags),
+        None => match find_symbol(cpu, "__libc_dlopen_mode") {
+            Some(dlopen_mode) => (dlopen_mode, flags | RTLD_DLOPEN),
+            None => return Err(InjectError::SymbolNotFound("dlopen")),
+        },
+    };
+
+    let c_path = inject_string(path).await?;
+    let result = match call_function(func, &[c_path.addr(), flags]).await {
+        Ok(0) => Err(InjectError::DlopenFailed(dlerror(cpu).await)),
+        Ok(handle) => Ok(handle),
+        Err(err) => Err(err.into()),
+    };
+
+    guest_free(c_path).await;
+
+    result
+}
+
+/// The result of a library load started by [`load_library`]
+#[derive(Clone)]
+pub struct PendingLoad(Arc<OnceCell<Result<target_ptr_t, InjectError>>>);
+
+impl PendingLoad {
+    /// Get the handle returned by `dlopen`, or `None` if the library hasn't been loaded
+    /// yet
+    pub fn result(&self) -> Option<&Result<target_ptr_t, InjectError>> {
+        self.0.get()
+    }
+
+    /// Check whether the load has finished, successfully or not
+    pub fn is_done(&self) -> bool {
+        self.0.get().is_some()
+    }
+}
+
+/// Load the shared library at `path` (a path inside the guest) into the running
+/// process `pid`, as if it had called `dlopen(path, RTLD_NOW)`.
+///
+/// The library is loaded the next time the process enters a system call, so the
+/// result is available through the returned [`PendingLoad`] once the guest has run
+/// for a while. See the [module-level docs](self) for an example.
+pub fn load_library(
+    cpu: &mut CPUState,
+    pid: target_pid_t,
+    path: &str,
+) -> Result<PendingLoad, InjectError> {
+    let processes = OSI.get_processes(cpu);
+    if processes.is_null() || !processes.iter().any(|process| process.pid == pid) {
+        return Err(InjectError::NoSuchProcess(pid));
+    }
+
+    let result = Arc::new(OnceCell::new());
+    let pending = PendingLoad(Arc::clone(&result));
+    let path = path.to_owned();
+
+    let next_syscall = PppCallback::new();
+    next_syscall.on_all_sys_enter(move |cpu, pc, _| {
+        let in_process = OSI
+            .get_current_process(cpu)
+            .map(|process| process.pid == pid)
+            .unwrap_or(false);
+
+        if !in_process {
+            return;
+        }
+
+        next_syscall.disable();
+
+        let path = path.clone();
+        let result = Arc::clone(&result);
+        run_injector(pc, async move {
+            let _ = result.set(dlopen(&path, RTLD_NOW).await);
+        });
+    });
+
+    Ok(pending)
+}
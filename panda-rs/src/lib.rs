@@ -12,12 +12,24 @@
 //!
 //! * `libpanda` - enable libpanda mode. This is used to allow for compiling as a binary that links
 //! against libpanda, for pypanda-style use.
+//! * `guestfs` - enable [`guestfs`], for reading files out of guest disk images from the host,
+//! and [`disk_activity`], for reporting disk accesses in replays as accesses to files.
+//! * `plog` - enable [`plog::reader`], for reading pandalog files without PANDA.
+//! * `guest-channels` - enable [`TypedChannel`](plugins::guest_plugin_manager::TypedChannel),
+//! for exchanging serde-serialized messages with guest plugins.
+//! * `coverage-sqlite` - enable writing [`coverage`] to SQLite databases.
+//! * `disas` - enable [`disas`], for disassembling guest code with capstone,
+//! [`insn_callbacks`], for callbacks on classes of instructions, and [`instrument`], for
+//! instrumenting the instructions selected by a policy.
 //!
 //! #### Architecture-specific features
 //!
 //! PANDA supports multiple architectures, but requires plugins to be compiled for each
 //! architecture. In order to target a specific guest arch, use exactly one of the following:
-//! `x86_64`, `i386`, `arm`, `aarch64`, `mips`, `mipsel`, `mips64`, `ppc`
+//! `x86_64`, `i386`, `arm`, `armeb`, `aarch64`, `mips`, `mipsel`, `mips64`, `mips64el`, `ppc`
+//!
+//! `armeb` targets big endian ARM guests. PANDA runs these using its `arm` build, so
+//! `armeb` enables `arm` and only changes [`ARCH_ENDIAN`].
 //!
 //! Typically PANDA plugins forward each of these features in their Cargo.toml:
 //!
@@ -108,15 +120,74 @@ pub use callbacks::*;
 mod init_return;
 pub use init_return::InitReturn;
 
+mod elf;
+
 /// Helpers for getting plugin arguments from panda
 pub mod panda_arg;
 
 #[doc(inline)]
 pub use panda_arg::PandaArgs;
 
+pub mod alloc_sites;
+pub mod analysis;
+pub mod audit;
+pub mod coverage;
+
+#[cfg(not(feature = "ppc"))]
+pub mod crash;
+
+#[cfg(not(feature = "ppc"))]
+pub mod diff;
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "guestfs")))]
+#[cfg(feature = "guestfs")]
+pub mod disk_activity;
 pub mod enums;
+pub mod exception_stats;
+
+#[cfg(not(feature = "ppc"))]
+pub mod fs_activity;
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "guestfs")))]
+#[cfg(feature = "guestfs")]
+pub mod guestfs;
+
+#[cfg_attr(
+    doc_cfg,
+    doc(cfg(all(
+        feature = "syscall-injection",
+        any(feature = "x86_64", feature = "i386", feature = "aarch64")
+    )))
+)]
+#[cfg(all(
+    feature = "syscall-injection",
+    any(feature = "x86_64", feature = "i386", feature = "aarch64")
+))]
+pub mod inject;
+
+pub mod metrics;
+pub mod output;
+pub mod perf_stats;
+pub mod plog;
 pub mod plugins;
+
+#[cfg(not(feature = "ppc"))]
+pub mod procdump;
+
+pub mod reentrancy;
+pub mod sanitize;
+pub mod sdk;
+pub mod sockaddr;
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "spec")))]
+#[cfg(feature = "spec")]
+pub mod spec;
+
+#[cfg(not(feature = "ppc"))]
+pub mod syscalls;
+
 pub mod taint;
+pub mod watch;
 
 #[cfg_attr(doc_cfg, doc(cfg(feature = "syscall-injection")))]
 #[cfg(all(feature = "syscall-injection", not(feature = "ppc")))]
@@ -127,12 +198,21 @@ pub use enums::arch::*;
 /// A set of types PANDA frequently requires but have a low likelihood of clashing with
 /// other types you import, for use as a wildcard import.
 ///
+/// The raw [`CPUState`](sys::CPUState) and [`TranslationBlock`](sys::TranslationBlock)
+/// bindings are still exported here for now, but new code should use the safe methods
+/// of the [`ext`] traits rather than their fields, as they will be replaced by safe
+/// wrappers in a future release. The traits are imported without their names, so
+/// their methods are in scope without clashing with traits of your own.
+///
 /// ## Example
 ///
 /// ```
 /// use panda::prelude::*;
 /// ```
 pub mod prelude {
+    pub use crate::ext::{
+        BlockExt as _, CpuExt as _, MachineExt as _, MonitorExt as _,
+    };
     pub use crate::panda_arg::PandaArgs;
     pub use crate::regs::SyscallPc;
     pub use crate::sys::target_long;
@@ -147,7 +227,21 @@ pub mod prelude {
 }
 
 #[cfg(not(feature = "ppc"))]
-pub use panda_macros::{on_all_sys_enter, on_all_sys_return};
+pub use panda_macros::{
+    on_all_sys_enter, on_all_sys_return, on_all_sys_return_with_args, on_sys_return_with_args,
+};
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "guestfs")))]
+#[cfg(feature = "guestfs")]
+pub use panda_macros::{on_file_block_read, on_file_block_write};
+
+#[cfg_attr(doc_cfg, doc(cfg(any(feature = "arm", feature = "aarch64"))))]
+#[cfg(any(feature = "arm", feature = "aarch64"))]
+pub use panda_macros::{on_smc, on_world_switch};
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "disas")))]
+#[cfg(feature = "disas")]
+pub use panda_macros::{on_call, on_indirect_call, on_indirect_jump, on_privileged, on_ret};
 
 // callbacks
 pub use panda_macros::{
@@ -157,11 +251,12 @@ pub use panda_macros::{
     before_handle_exception, before_handle_interrupt, before_loadvm, before_tcg_codegen,
     cpu_restore_state, during_machine_init, end_block_exec, guest_hypercall, hd_read, hd_write,
     hook, init, insn_exec, insn_translate, main_loop_wait, mmio_after_read, mmio_before_write,
-    monitor, on_mmap_updated, on_process_end, on_process_start, on_rec_auxv, on_thread_end,
-    on_thread_start, phys_mem_after_read, phys_mem_after_write, phys_mem_before_read,
-    phys_mem_before_write, pre_shutdown, replay_after_dma, replay_before_dma, replay_handle_packet,
-    replay_hd_transfer, replay_net_transfer, replay_serial_read, replay_serial_receive,
-    replay_serial_send, replay_serial_write, start_block_exec, top_loop, unassigned_io_read,
-    unassigned_io_write, uninit, virt_mem_after_read, virt_mem_after_write, virt_mem_before_read,
-    virt_mem_before_write, GuestType,
+    monitor, on_mmap_updated, on_process_end, on_process_start, on_rec_auxv, on_replay_progress,
+    on_thread_end, on_thread_start, phys_mem_after_read, phys_mem_after_write,
+    phys_mem_before_read, phys_mem_before_write, pre_shutdown, qmp, replay_after_dma,
+    replay_before_dma, replay_handle_packet, replay_hd_transfer, replay_net_transfer,
+    replay_serial_read, replay_serial_receive, replay_serial_send, replay_serial_write,
+    start_block_exec, top_loop, unassigned_io_read, unassigned_io_write, uninit,
+    virt_mem_after_read, virt_mem_after_write, virt_mem_before_read, virt_mem_before_write,
+    GuestType,
 };
@@ -0,0 +1,499 @@
+//! Driving the guest through its serial console and the QEMU monitor
+//!
+//! When a prompt is set with [`Panda::expect_prompt`], the guest's serial console and
+//! the QEMU monitor are each connected to a unix socket rather than stdio. Commands
+//! can then be typed into either from another thread while [`Panda::run`] is running,
+//! with the prompt used to tell when a command's output is complete.
+#![cfg_attr(not(feature = "libpanda"), allow(dead_code))]
+
+use super::Panda;
+
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long to wait for PANDA to create the console sockets after starting
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for a command to finish when no timeout is given
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How far from the end of the output to look for the prompt
+const MAX_PROMPT_LEN: usize = 1024;
+
+/// The prompt printed by the QEMU monitor
+const MONITOR_PROMPT: &str = r"\(qemu\) ";
+
+/// An error from running a command on the serial console or QEMU monitor
+#[derive(Debug, thiserror::Error)]
+pub enum ConsoleError {
+    #[error("the serial console is only available when a prompt is set with Panda::expect_prompt")]
+    NotConfigured,
+
+    #[error("timed out waiting for the prompt, output so far: {0:?}")]
+    Timeout(String),
+
+    #[error("timed out waiting for the guest to reach instruction count {0}")]
+    InstrCountTimeout(u64),
+
+    #[error("the console was closed by PANDA")]
+    Closed,
+
+    #[error("I/O error on the console: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A prompt, matched against the end of the output with the syntax of the regex crate
+#[derive(Debug, Clone)]
+pub(super) struct Prompt(Regex);
+
+impl Prompt {
+    /// Compile a prompt, with `^` and `$` matching at the start and end of lines
+    pub(super) fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(&format!(r"(?m:{})\z", pattern)).map(Prompt)
+    }
+
+    /// Find where the prompt starts if the output ends with it
+    fn find_at_end(&self, output: &[u8]) -> Option<usize> {
+        // only look near the end, so repeated checks of a growing output stay cheap
+        let window = output.len().saturating_sub(MAX_PROMPT_LEN);
+
+        self.0
+            .find(&output[window..])
+            .map(|found| window + found.start())
+    }
+}
+
+/// Find the first occurrence of `needle` which ends after `from`, returning where it
+/// ends
+fn find_needle_end(output: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+
+    let start = from.saturating_sub(needle.len() - 1);
+
+    output[start..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| start + pos + needle.len())
+}
+
+/// Remove terminal escape sequences (such as those the QEMU monitor uses to redraw its
+/// input line) and carriage returns from console output
+fn clean_output(output: &str) -> String {
+    let mut cleaned = String::with_capacity(output.len());
+    let mut chars = output.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    while let Some(c) = chars.next() {
+                        if c.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\r' => (),
+            c => cleaned.push(c),
+        }
+    }
+
+    cleaned
+}
+
+/// A unix socket connected to either the serial console or the monitor
+struct Connection {
+    path: PathBuf,
+    stream: Option<UnixStream>,
+    prompt: Prompt,
+
+    /// Output read past the end of the last wait, to be searched by the next one
+    buffered: Vec<u8>,
+
+    /// Whether the banner printed when first connecting has been read, for the monitor
+    banner_read: bool,
+}
+
+impl Connection {
+    fn new(path: PathBuf, prompt: Prompt) -> Self {
+        Self {
+            path,
+            stream: None,
+            prompt,
+            buffered: Vec::new(),
+            banner_read: false,
+        }
+    }
+
+    /// Connect to the socket, waiting for PANDA to create it if needed
+    fn stream(&mut self) -> Result<&mut UnixStream, ConsoleError> {
+        if self.stream.is_none() {
+            let start = Instant::now();
+            let stream = loop {
+                match UnixStream::connect(&self.path) {
+                    Ok(stream) => break stream,
+                    Err(err) if start.elapsed() > CONNECT_TIMEOUT => return Err(err.into()),
+                    Err(_) => std::thread::sleep(Duration::from_millis(100)),
+                }
+            };
+
+            self.stream = Some(stream);
+        }
+
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    /// Read until the output ends with the prompt, returning the output before it
+    fn read_until_prompt(&mut self, timeout: Option<Duration>) -> Result<String, ConsoleError> {
+        let prompt = self.prompt.clone();
+
+        self.read_until(timeout, |output, _| prompt.find_at_end(output))
+    }
+
+    /// Read until `end` finds where the output of interest ends, returning the output
+    /// up to that point. Anything read after it is kept for the next read.
+    ///
+    /// `end` is given the output so far and the offset the latest read starts at, so
+    /// that it only needs to search the new output.
+    fn read_until(
+        &mut self,
+        timeout: Option<Duration>,
+        mut end: impl FnMut(&[u8], usize) -> Option<usize>,
+    ) -> Result<String, ConsoleError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut output = std::mem::take(&mut self.buffered);
+        let mut new_from = 0;
+        let mut buf = [0u8; 4096];
+
+        loop {
+            if let Some(end) = end(&output, new_from) {
+                self.buffered = output.split_off(end);
+                return Ok(String::from_utf8_lossy(&output).into_owned());
+            }
+
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => {
+                        let text = clean_output(&String::from_utf8_lossy(&output));
+                        self.buffered = output;
+                        return Err(ConsoleError::Timeout(text));
+                    }
+                },
+                None => None,
+            };
+
+            let stream = self.stream()?;
+            stream.set_read_timeout(remaining)?;
+
+            new_from = output.len();
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    self.stream = None;
+                    return Err(ConsoleError::Closed);
+                }
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Type a command, then return its output with the echoed command and trailing
+    /// prompt removed
+    fn run(&mut self, cmd: &str, timeout: Option<Duration>) -> Result<String, ConsoleError> {
+        // output from before the command isn't part of its output
+        self.buffered.clear();
+        writeln!(self.stream()?, "{}", cmd)?;
+
+        let output = clean_output(&self.read_until_prompt(timeout)?);
+        let output = match output.split_once('\n') {
+            Some((_echo, output)) => output,
+            None => "",
+        };
+
+        Ok(output.trim_end().to_owned())
+    }
+}
+
+/// The serial console and monitor, locked separately so that a command blocked on one
+/// doesn't hold up the other
+struct Console {
+    serial: Mutex<Connection>,
+    monitor: Mutex<Connection>,
+}
+
+lazy_static! {
+    static ref CONSOLE: Mutex<Option<Arc<Console>>> = Mutex::new(None);
+}
+
+/// The sockets to connect the serial console and monitor to, unique to this process
+pub(super) fn socket_paths() -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir();
+    let pid = std::process::id();
+
+    (
+        dir.join(format!("panda_serial_{}", pid)),
+        dir.join(format!("panda_monitor_{}", pid)),
+    )
+}
+
+/// Set up the console for a run with the given prompt, removing any sockets left over
+/// from a previous run. The prompt must already have been checked by
+/// [`Panda::validate`].
+pub(super) fn init(prompt: &str) {
+    let (serial, monitor) = socket_paths();
+    for path in [&serial, &monitor] {
+        let _ = std::fs::remove_file(path);
+    }
+
+    let prompt = Prompt::new(prompt).expect("invalid prompt");
+    let monitor_prompt = Prompt::new(MONITOR_PROMPT).unwrap();
+
+    *CONSOLE.lock().unwrap() = Some(Arc::new(Console {
+        serial: Mutex::new(Connection::new(serial, prompt)),
+        monitor: Mutex::new(Connection::new(monitor, monitor_prompt)),
+    }));
+}
+
+fn console() -> Result<Arc<Console>, ConsoleError> {
+    CONSOLE
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or(ConsoleError::NotConfigured)
+}
+
+fn with_serial<T>(
+    func: impl FnOnce(&mut Connection) -> Result<T, ConsoleError>,
+) -> Result<T, ConsoleError> {
+    let console = console()?;
+    let mut serial = console.serial.lock().unwrap();
+
+    func(&mut serial)
+}
+
+fn with_monitor<T>(
+    func: impl FnOnce(&mut Connection) -> Result<T, ConsoleError>,
+) -> Result<T, ConsoleError> {
+    let console = console()?;
+    let mut monitor = console.monitor.lock().unwrap();
+
+    // skip the banner printed when first connecting
+    if !monitor.banner_read {
+        monitor.read_until_prompt(Some(DEFAULT_TIMEOUT))?;
+        monitor.banner_read = true;
+    }
+
+    func(&mut monitor)
+}
+
+fn socket_arg(path: &Path) -> String {
+    format!("unix:{},server,nowait", path.display())
+}
+
+/// The arguments connecting the serial console and monitor to their sockets
+pub(super) fn args() -> Vec<String> {
+    let (serial, monitor) = socket_paths();
+
+    vec![
+        "-serial".into(),
+        socket_arg(&serial),
+        "-monitor".into(),
+        socket_arg(&monitor),
+    ]
+}
+
+/// Type text into the guest's serial console without waiting for any output
+pub(super) fn type_serial(text: &str) -> Result<(), ConsoleError> {
+    with_serial(|serial| {
+        serial.stream()?.write_all(text.as_bytes())?;
+
+        Ok(())
+    })
+}
+
+/// Wait until the guest prints `needle` to its serial console, returning everything
+/// printed up to and including it
+pub(super) fn wait_for_serial_output(
+    needle: &str,
+    timeout: Option<Duration>,
+) -> Result<String, ConsoleError> {
+    with_serial(|serial| {
+        let output = serial.read_until(timeout, |output, new_from| {
+            find_needle_end(output, needle.as_bytes(), new_from)
+        })?;
+
+        Ok(clean_output(&output))
+    })
+}
+
+impl Panda {
+    /// Type a command into the guest's serial console and wait for the prompt set with
+    /// [`Panda::expect_prompt`] to return, giving the output of the command. Blocks
+    /// until the command has finished, so must be called from a different thread to
+    /// the one calling [`Panda::run`]. Gives up after five minutes, see
+    /// [`Panda::run_serial_cmd_with_timeout`] to wait for a different time.
+    ///
+    /// The guest is expected to be sitting at its prompt when the command is typed,
+    /// such as after reverting to a snapshot with [`Panda::revert_sync`].
+    ///
+    /// ### Example
+    /// ```rust,no_run
+    /// # use panda::prelude::*;
+    /// std::thread::spawn(|| {
+    ///     Panda::revert_sync("root").unwrap();
+    ///     let output = Panda::run_serial_cmd("uname -a").unwrap();
+    ///     println!("{}", output);
+    ///
+    ///     Panda::run_monitor_cmd("quit").ok();
+    /// });
+    ///
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .expect_prompt(r"root@debian-amd64:.*# ")
+    ///     .run();
+    /// ```
+    pub fn run_serial_cmd(cmd: &str) -> Result<String, ConsoleError> {
+        Self::run_serial_cmd_with_timeout(cmd, Some(DEFAULT_TIMEOUT))
+    }
+
+    /// Run a command on the guest's serial console, giving up if the prompt hasn't
+    /// returned within the given time, or waiting forever for `None`. See
+    /// [`Panda::run_serial_cmd`].
+    pub fn run_serial_cmd_with_timeout(
+        cmd: &str,
+        timeout: Option<Duration>,
+    ) -> Result<String, ConsoleError> {
+        with_serial(|serial| serial.run(cmd, timeout))
+    }
+
+    /// Wait until the guest's serial console shows its prompt, such as after booting,
+    /// returning everything printed before it
+    pub fn wait_for_prompt(timeout: Option<Duration>) -> Result<String, ConsoleError> {
+        with_serial(|serial| {
+            let output = serial.read_until_prompt(timeout)?;
+
+            Ok(clean_output(&output))
+        })
+    }
+
+    /// Run a command on the QEMU monitor (such as `info registers` or `savevm`),
+    /// returning its output. Must be called from a different thread to the one calling
+    /// [`Panda::run`], and requires a prompt to be set with [`Panda::expect_prompt`].
+    /// Gives up after five minutes, see [`Panda::run_monitor_cmd_with_timeout`] to
+    /// wait for a different time.
+    pub fn run_monitor_cmd(cmd: &str) -> Result<String, ConsoleError> {
+        Self::run_monitor_cmd_with_timeout(cmd, Some(DEFAULT_TIMEOUT))
+    }
+
+    /// Run a command on the QEMU monitor, giving up if it hasn't finished within the
+    /// given time, or waiting forever for `None`. See [`Panda::run_monitor_cmd`].
+    pub fn run_monitor_cmd_with_timeout(
+        cmd: &str,
+        timeout: Option<Duration>,
+    ) -> Result<String, ConsoleError> {
+        with_monitor(|monitor| monitor.run(cmd, timeout))
+    }
+
+    /// Revert the guest to the given snapshot using the monitor, blocking until the
+    /// snapshot has been loaded. See [`Panda::run_monitor_cmd`]. To revert from within
+    /// a callback, use [`Panda::revert_async`].
+    pub fn revert_sync(snapshot: &str) -> Result<String, ConsoleError> {
+        Self::run_monitor_cmd(&format!("loadvm {}", snapshot))
+    }
+
+    /// Save a snapshot of the guest with the given name using the monitor, blocking
+    /// until it has been saved. See [`Panda::run_monitor_cmd`]. To take a snapshot
+    /// from within a callback, use [`Panda::snap`].
+    pub fn snapshot_sync(snapshot: &str) -> Result<String, ConsoleError> {
+        Self::run_monitor_cmd(&format!("savevm {}", snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt_start(pattern: &str, output: &str) -> Option<usize> {
+        Prompt::new(pattern).unwrap().find_at_end(output.as_bytes())
+    }
+
+    #[test]
+    fn test_prompt_at_end() {
+        assert_eq!(
+            prompt_start(r"root@debian:.*# ", "ls\nroot@debian:~# "),
+            Some(3)
+        );
+        assert_eq!(
+            prompt_start(r"root@debian:.*# ", "root@debian:~# ls\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_prompt_syntax() {
+        assert_eq!(prompt_start(r"[#$] ", "output\n$ "), Some(7));
+        assert_eq!(prompt_start(r"\$ ", "output\n$ "), Some(7));
+        assert_eq!(prompt_start(r"user\d+> ", "output\nuser42> "), Some(7));
+        assert_eq!(prompt_start(r"^# ", "echo # \n# "), Some(8));
+        assert_eq!(
+            prompt_start(MONITOR_PROMPT, "QEMU 2.9.1 monitor\n(qemu) "),
+            Some(19)
+        );
+    }
+
+    #[test]
+    fn test_prompt_only_searches_end() {
+        let output = format!("$ {}", "x".repeat(MAX_PROMPT_LEN * 2));
+        assert_eq!(prompt_start(r"\$ x*", &output), None);
+    }
+
+    #[test]
+    fn test_needle_end() {
+        assert_eq!(
+            find_needle_end(b"booting... login: ", b"login: ", 0),
+            Some(18)
+        );
+        assert_eq!(find_needle_end(b"log", b"login: ", 0), None);
+
+        // the needle may straddle the previous read and the new one
+        assert_eq!(
+            find_needle_end(b"booting... login: ", b"login: ", 14),
+            Some(18)
+        );
+        assert_eq!(find_needle_end(b"login: login: ", b"login: ", 8), Some(14));
+    }
+
+    #[test]
+    fn test_output_after_needle_kept() {
+        let (guest, host) = UnixStream::pair().unwrap();
+        let mut conn = Connection::new(PathBuf::new(), Prompt::new(r"\$ ").unwrap());
+        conn.stream = Some(host);
+
+        (&guest).write_all(b"booting\nlogin: root\n$ ").unwrap();
+
+        let timeout = Some(Duration::from_secs(5));
+        let output = conn
+            .read_until(timeout, |output, new_from| {
+                find_needle_end(output, b"login: ", new_from)
+            })
+            .unwrap();
+
+        assert_eq!(output, "booting\nlogin: ");
+        assert_eq!(conn.read_until_prompt(timeout).unwrap(), "root\n");
+    }
+}
@@ -1,23 +1,35 @@
 #[cfg(feature = "libpanda")]
 mod qcows;
 
+mod console;
+pub use console::ConsoleError;
+
+pub mod record;
+
+mod snapshot;
+pub use snapshot::{PendingSnapshot, SnapshotError};
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "libpanda")))]
+#[cfg(feature = "libpanda")]
+pub mod workers;
+
+use crate::net::{NetConfig, Netdev, PortForward, Protocol};
+use crate::serial::SerialBackend;
+use crate::time::{IcountConfig, IcountShift, MAX_ICOUNT_SHIFT};
 use crate::PandaArgs;
 use std::fmt;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(feature = "libpanda")]
 use std::{ffi::CString, mem::transmute, os::raw::c_char, sync::Mutex};
 
 #[cfg(feature = "libpanda")]
-use crate::{
-    inventory,
-    sys::{self, panda_init, panda_run, panda_set_library_mode},
-    InternalCallback, PPPCallbackSetup,
-};
+use crate::sys::{self, panda_init, panda_run, panda_set_library_mode};
 
 /// Architecture of the guest system
 #[allow(non_camel_case_types)]
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Arch {
     i386,
     x86_64,
@@ -58,7 +70,68 @@ pub struct Panda {
     arch: Option<Arch>,
     extra_args: Vec<String>,
     replay: Option<String>,
+    record: Option<(String, Option<String>)>,
     configurable: bool,
+    serial: Vec<SerialBackend>,
+    net: Option<NetConfig>,
+    icount: Option<IcountConfig>,
+    rtc_clock_vm: bool,
+    machine: Option<String>,
+    kernel: Option<PathBuf>,
+    initrd: Option<PathBuf>,
+    append: Vec<String>,
+    dtb: Option<PathBuf>,
+    script: Option<record::Script>,
+    gdb_port: Option<u16>,
+}
+
+/// An invalid combination of [`Panda`] builder options
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("icount mode cannot be used while replaying, as replays have their own timing")]
+    IcountWithReplay,
+
+    #[error("a recording cannot be made while replaying")]
+    RecordWithReplay,
+
+    #[error("icount shift {0} is larger than the maximum of {}", MAX_ICOUNT_SHIFT)]
+    IcountShiftTooLarge(u8),
+
+    #[error("icount alignment requires sleep to be enabled")]
+    IcountAlignWithoutSleep,
+
+    #[error("icount alignment requires a fixed shift")]
+    IcountAlignWithAutoShift,
+
+    #[error("{0} can only be used when booting a kernel directly")]
+    RequiresKernel(&'static str),
+
+    #[error("device tree blobs are not supported on {0}")]
+    DtbUnsupported(Arch),
+
+    #[error("booting a kernel directly on {0} requires a machine to be set")]
+    MachineRequired(Arch),
+
+    #[error("a machine cannot be set when using the configurable machine")]
+    MachineWithConfigurable,
+
+    #[error("{} does not exist", .0.display())]
+    FileNotFound(PathBuf),
+
+    #[error("a recording script can only be run while recording")]
+    ScriptWithoutRecord,
+
+    #[error("a recording script requires a prompt to be set")]
+    ScriptWithoutPrompt,
+
+    #[error("invalid prompt: {0}")]
+    InvalidPrompt(String),
+
+    #[error("serial ports cannot be added with both Panda::serial and -serial arguments")]
+    SerialWithArgs,
+
+    #[error("only one serial port can be connected to stdio")]
+    SerialStdioReused,
 }
 
 static LIBRARY_STARTED: AtomicBool = AtomicBool::new(false);
@@ -157,6 +230,87 @@ impl Panda {
         self
     }
 
+    /// Set the machine to emulate (e.g. `versatilepb` or `malta`). Equivalent to
+    /// `-M [machine]` from the PANDA command line.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::{prelude::*, Arch};
+    /// Panda::new()
+    ///     .arch(Arch::Arm)
+    ///     .machine("versatilepb")
+    ///     .run();
+    /// ```
+    pub fn machine<S: Into<String>>(&mut self, machine: S) -> &mut Self {
+        self.machine = Some(machine.into());
+
+        self
+    }
+
+    /// Boot the given kernel image directly, rather than through the firmware and
+    /// bootloader of a disk image. Equivalent to `-kernel [path]` from the PANDA command
+    /// line.
+    ///
+    /// Typically used for embedded targets, which also require a [`machine`] to be set
+    /// on ARM. A disk image can still be provided with [`qcow`] for the root filesystem.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::{prelude::*, Arch};
+    /// Panda::new()
+    ///     .arch(Arch::Arm)
+    ///     .machine("versatilepb")
+    ///     .qcow("debian_7.3_arm.qcow")
+    ///     .kernel("vmlinuz-3.2.0-4-versatile")
+    ///     .initrd("initrd.img-3.2.0-4-versatile")
+    ///     .append("root=/dev/sda1")
+    ///     .run();
+    /// ```
+    ///
+    /// [`machine`]: Panda::machine
+    /// [`qcow`]: Panda::qcow
+    pub fn kernel<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.kernel = Some(path.into());
+
+        self
+    }
+
+    /// Load the given initial ramdisk when booting a kernel directly. Equivalent to
+    /// `-initrd [path]` from the PANDA command line.
+    pub fn initrd<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.initrd = Some(path.into());
+
+        self
+    }
+
+    /// Append to the command line of a kernel being booted directly. Can be called
+    /// multiple times, with each addition separated by a space. Equivalent to
+    /// `-append [cmdline]` from the PANDA command line.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::prelude::*;
+    /// Panda::new()
+    ///     .kernel("bzImage")
+    ///     .append("console=ttyS0")
+    ///     .append("root=/dev/sda1")
+    ///     .run();
+    /// ```
+    pub fn append<S: Into<String>>(&mut self, cmdline: S) -> &mut Self {
+        self.append.push(cmdline.into());
+
+        self
+    }
+
+    /// Pass the given device tree blob to a kernel being booted directly. Only
+    /// supported on ARM, AArch64, MIPS and PowerPC. Equivalent to `-dtb [path]` from the
+    /// PANDA command line.
+    pub fn dtb<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.dtb = Some(path.into());
+
+        self
+    }
+
     // Don't pass `-nographic` to QEMU, allowing you use to use a monitor
     ///
     /// ### Example
@@ -172,8 +326,309 @@ impl Panda {
         self
     }
 
+    /// Start QEMU's gdb server before the guest starts running, listening for a debugger
+    /// on the given TCP port. Equivalent to `-gdb tcp::[port]` from the PANDA command
+    /// line.
+    ///
+    /// To control the guest from a plugin rather than an external debugger, see
+    /// [`debug`](crate::debug).
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::prelude::*;
+    /// // attach with `gdb -ex "target remote localhost:1234"`
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .gdb_server(1234)
+    ///     .run();
+    /// ```
+    pub fn gdb_server(&mut self, port: u16) -> &mut Self {
+        self.gdb_port = Some(port);
+
+        self
+    }
+
+    /// Add a serial port (UART) to the guest, connected to the given backend. Can be
+    /// called multiple times to add several ports, for firmware which expects more than
+    /// one. Equivalent to `-serial [backend]` from the PANDA command line.
+    ///
+    /// The first port added replaces the default serial console, unless a prompt is set
+    /// with [`expect_prompt`](Panda::expect_prompt), in which case the console keeps the
+    /// first port and these ports follow it. How many ports can be added depends on the
+    /// machine being emulated.
+    ///
+    /// Ports can't also be added with `-serial` through [`arg`](Panda::arg), as their
+    /// order would be ambiguous, and only one port can be connected to stdio.
+    ///
+    /// ### Example
+    /// ```rust,no_run
+    /// # use panda::prelude::*;
+    /// use panda::serial::SerialBackend;
+    ///
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .serial(SerialBackend::Stdio)
+    ///     .serial(SerialBackend::Pty)
+    ///     .serial(SerialBackend::File("uart2.log".into()))
+    ///     .run();
+    /// ```
+    pub fn serial(&mut self, backend: SerialBackend) -> &mut Self {
+        self.serial.push(backend);
+
+        self
+    }
+
+    /// Forward connections to a TCP port on the host to a port in the guest, using
+    /// user-mode networking. Can be called multiple times to forward several ports.
+    /// Equivalent to `-net user,hostfwd=tcp::[host_port]-:[guest_port]` from the PANDA
+    /// command line.
+    ///
+    /// The forwarded port can be looked up while running with
+    /// [`net::forwarded_port`](crate::net::forwarded_port).
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::prelude::*;
+    /// // SSH into the guest with `ssh -p 2222 localhost`
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .forward_port(2222, 22)
+    ///     .run();
+    /// ```
+    pub fn forward_port(&mut self, host_port: u16, guest_port: u16) -> &mut Self {
+        self.forward(PortForward {
+            protocol: Protocol::Tcp,
+            host_addr: None,
+            host_port,
+            guest_port,
+        })
+    }
+
+    /// Add a port forwarding rule, using user-mode networking. Unlike
+    /// [`forward_port`](Panda::forward_port), this allows forwarding UDP ports and
+    /// restricting which host address is listened on.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::prelude::*;
+    /// use panda::net::{PortForward, Protocol};
+    /// use std::net::Ipv4Addr;
+    ///
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .forward(PortForward {
+    ///         protocol: Protocol::Udp,
+    ///         host_addr: Some(Ipv4Addr::LOCALHOST),
+    ///         host_port: 5353,
+    ///         guest_port: 53,
+    ///     })
+    ///     .run();
+    /// ```
+    pub fn forward(&mut self, forward: PortForward) -> &mut Self {
+        self.net
+            .get_or_insert_with(NetConfig::default)
+            .forward(forward);
+
+        self
+    }
+
+    /// Connect the guest to the given host tap device instead of user-mode networking.
+    /// The device is created if it doesn't exist, and must be configured on the host.
+    /// Equivalent to `-net tap,ifname=[ifname]` from the PANDA command line.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::prelude::*;
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .tap("tap0")
+    ///     .run();
+    /// ```
+    pub fn tap<S: Into<String>>(&mut self, ifname: S) -> &mut Self {
+        self.net.get_or_insert_with(NetConfig::default).netdev = Netdev::Tap {
+            ifname: ifname.into(),
+        };
+
+        self
+    }
+
+    /// Remove the guest's network card entirely. Equivalent to `-net none` from the
+    /// PANDA command line.
+    pub fn no_network(&mut self) -> &mut Self {
+        self.net.get_or_insert_with(NetConfig::default).netdev = Netdev::Disabled;
+
+        self
+    }
+
+    /// Set the model of the guest's network card (e.g. `e1000`, `rtl8139`,
+    /// `virtio-net-pci`). Defaults to the machine's default network card.
+    pub fn nic_model<S: Into<String>>(&mut self, model: S) -> &mut Self {
+        self.net.get_or_insert_with(NetConfig::default).nic_model = Some(model.into());
+
+        self
+    }
+
+    /// Write all of the guest's network traffic to the given pcap file
+    pub fn capture_network<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.net.get_or_insert_with(NetConfig::default).capture = Some(path.into());
+
+        self
+    }
+
+    /// Drive the guest's virtual clock from the number of instructions executed rather
+    /// than the host clock, advancing it 2<sup>shift</sup> ns per instruction. If
+    /// `sleep` is disabled, the virtual clock skips ahead when the guest is idle rather
+    /// than waiting in real time, making guest timing independent of the host.
+    /// Equivalent to `-icount shift=[shift],sleep=[on|off]` from the PANDA command line.
+    ///
+    /// Cannot be combined with [`replay`](Panda::replay).
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::prelude::*;
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .icount(3, false)
+    ///     .deterministic_rtc()
+    ///     .run();
+    /// ```
+    pub fn icount(&mut self, shift: impl Into<IcountShift>, sleep: bool) -> &mut Self {
+        self.icount_config(IcountConfig::new(shift, sleep))
+    }
+
+    /// Enable icount mode with the given configuration. See [`icount`](Panda::icount).
+    pub fn icount_config(&mut self, config: IcountConfig) -> &mut Self {
+        self.icount = Some(config);
+
+        self
+    }
+
+    /// Drive the guest's real time clock from the virtual clock rather than the host
+    /// clock, so that it is frozen along with the virtual clock and advances
+    /// deterministically in icount mode. Equivalent to `-rtc clock=vm` from the PANDA
+    /// command line.
+    pub fn deterministic_rtc(&mut self) -> &mut Self {
+        self.rtc_clock_vm = true;
+
+        self
+    }
+
+    /// Check that the options set on the builder can be used together. Called by
+    /// [`run`](Panda::run), which panics if the options are invalid.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(icount) = &self.icount {
+            if self.replay.is_some() {
+                return Err(ConfigError::IcountWithReplay);
+            }
+
+            match icount.shift {
+                IcountShift::Fixed(shift) if shift > MAX_ICOUNT_SHIFT => {
+                    return Err(ConfigError::IcountShiftTooLarge(shift));
+                }
+                IcountShift::Auto if icount.align => {
+                    return Err(ConfigError::IcountAlignWithAutoShift);
+                }
+                _ => (),
+            }
+
+            if icount.align && !icount.sleep {
+                return Err(ConfigError::IcountAlignWithoutSleep);
+            }
+        }
+
+        if self.configurable && self.machine.is_some() {
+            return Err(ConfigError::MachineWithConfigurable);
+        }
+
+        if self.record.is_some() && self.replay.is_some() {
+            return Err(ConfigError::RecordWithReplay);
+        }
+
+        if let Some(prompt) = &self.expect_prompt {
+            console::Prompt::new(prompt)
+                .map_err(|err| ConfigError::InvalidPrompt(err.to_string()))?;
+        }
+
+        if !self.serial.is_empty() && self.extra_args.iter().any(|arg| arg == "-serial") {
+            return Err(ConfigError::SerialWithArgs);
+        }
+
+        let stdio_ports = self
+            .serial
+            .iter()
+            .filter(|backend| matches!(backend, SerialBackend::Stdio))
+            .count();
+        if stdio_ports > 1 {
+            return Err(ConfigError::SerialStdioReused);
+        }
+
+        if self.script.is_some() {
+            if self.record.is_none() {
+                return Err(ConfigError::ScriptWithoutRecord);
+            }
+
+            if self.expect_prompt.is_none() {
+                return Err(ConfigError::ScriptWithoutPrompt);
+            }
+        }
+
+        self.validate_boot()
+    }
+
+    /// Check the options for booting a kernel directly
+    fn validate_boot(&self) -> Result<(), ConfigError> {
+        let kernel = match &self.kernel {
+            Some(kernel) => kernel,
+            None if self.initrd.is_some() => return Err(ConfigError::RequiresKernel("initrd")),
+            None if !self.append.is_empty() => return Err(ConfigError::RequiresKernel("append")),
+            None if self.dtb.is_some() => return Err(ConfigError::RequiresKernel("dtb")),
+            None => return Ok(()),
+        };
+
+        let arch = self.guest_arch();
+        let has_machine = self.machine.is_some() || self.configurable;
+        if matches!(arch, Arch::Arm | Arch::AArch64) && !has_machine {
+            return Err(ConfigError::MachineRequired(arch));
+        }
+
+        if self.dtb.is_some() && matches!(arch, Arch::i386 | Arch::x86_64) {
+            return Err(ConfigError::DtbUnsupported(arch));
+        }
+
+        let files = [Some(kernel), self.initrd.as_ref(), self.dtb.as_ref()];
+        for path in files.iter().flatten() {
+            if !path.exists() {
+                return Err(ConfigError::FileNotFound(path.to_path_buf()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the architecture of the guest, from either [`arch`](Panda::arch) or the
+    /// generic image being used
+    fn guest_arch(&self) -> Arch {
+        #[cfg(feature = "libpanda")]
+        let generic_arch = self
+            .generic_qcow
+            .as_ref()
+            .map(|generic| qcows::get_supported_image(generic).arch);
+
+        #[cfg(not(feature = "libpanda"))]
+        let generic_arch = None;
+
+        self.arch.or(generic_arch).unwrap_or(Arch::x86_64)
+    }
+
     /// Regular expression describing the prompt exposed by the guest on a serial console. Used in
     /// order to know when running a command has finished with its output.
+    ///
+    /// Setting a prompt connects the guest's first serial port and the QEMU monitor to
+    /// sockets, so that commands can be run with [`Panda::run_serial_cmd`] and
+    /// [`Panda::run_monitor_cmd`] rather than through stdio. The prompt is matched
+    /// against the end of the console's output using the syntax of the
+    /// [regex](https://docs.rs/regex) crate, with `^` and `$` matching at the start and
+    /// end of lines.
     pub fn expect_prompt<S: Into<String>>(&mut self, prompt_regex: S) -> &mut Self {
         self.expect_prompt = Some(prompt_regex.into());
 
@@ -203,6 +658,15 @@ impl Panda {
         self
     }
 
+    /// Use the given qcow (or other disk image) for the run, rather than a generic
+    /// image. Equivalent to passing the path as a positional argument on the PANDA
+    /// command line.
+    pub fn qcow<S: Into<String>>(&mut self, qcow: S) -> &mut Self {
+        self.qcow = Some(qcow.into());
+
+        self
+    }
+
     /// Run the given replay in the PANDA instance. Equivalent to `-replay [name]` from the PANDA
     /// command line.
     ///
@@ -219,6 +683,80 @@ impl Panda {
         self
     }
 
+    /// Start recording as soon as the guest starts running, saving the recording under
+    /// the given name. The recording runs until [`Panda::end_record`] is called (such as
+    /// from a callback) or the guest exits.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::prelude::*;
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .record("boot")
+    ///     .run();
+    /// ```
+    pub fn record<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.record = Some((name.into(), None));
+
+        self
+    }
+
+    /// Restore the given snapshot of the qcow, then start recording from it, saving the
+    /// recording under the given name. See [`Panda::record`].
+    pub fn record_from_snapshot<S1, S2>(&mut self, name: S1, snapshot: S2) -> &mut Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.record = Some((name.into(), Some(snapshot.into())));
+
+        self
+    }
+
+    /// Give the guest the inputs of the given script while recording, such as typing
+    /// commands once the prompt is printed. Requires a prompt to be set with
+    /// [`Panda::expect_prompt`]. See [`record`] for more info.
+    ///
+    /// ### Example
+    /// ```rust
+    /// # use panda::prelude::*;
+    /// use panda::record;
+    ///
+    /// Panda::new()
+    ///     .generic("x86_64")
+    ///     .expect_prompt(r"root@debian-amd64:.*# ")
+    ///     .record("ls")
+    ///     .record_script(
+    ///         record::script()
+    ///             .type_line("ls /")
+    ///             .at_prompt()
+    ///             .end_record()
+    ///             .at_prompt(),
+    ///     )
+    ///     .run();
+    /// ```
+    pub fn record_script(&mut self, script: record::Script) -> &mut Self {
+        self.script = Some(script);
+
+        self
+    }
+
+    /// End the recording currently being made. The recording is finished at the end of
+    /// the current block, so this is safe to call from within a callback.
+    ///
+    /// ### Example
+    /// ```rust
+    /// use panda::prelude::*;
+    ///
+    /// #[panda::on_sys::exit_group_enter]
+    /// fn on_exit(_: &mut CPUState, _: SyscallPc, _: i32) {
+    ///     Panda::end_record().unwrap();
+    /// }
+    /// ```
+    pub fn end_record() -> Result<(), crate::Error> {
+        crate::rr::record_end()
+    }
+
     /// Load a plugin with args provided by a `PandaArgs` struct.
     ///
     /// ### Example
@@ -259,11 +797,6 @@ impl Panda {
                 .map(|generic| qcows::get_generic_path(generic).display().to_string())
         });
 
-        let _arch = self
-            .arch
-            .or_else(|| generic_info.as_ref().map(|x| x.arch))
-            .unwrap_or(Arch::x86_64);
-
         let mem = self
             .mem
             .as_ref()
@@ -294,12 +827,64 @@ impl Panda {
         if self.configurable {
             args.push("-M".into());
             args.push("configurable".into());
+        } else if let Some(machine) = &self.machine {
+            args.push("-M".into());
+            args.push(machine.clone());
+        }
+
+        if let Some(kernel) = &self.kernel {
+            args.push("-kernel".into());
+            args.push(kernel.display().to_string());
+        }
+
+        if let Some(initrd) = &self.initrd {
+            args.push("-initrd".into());
+            args.push(initrd.display().to_string());
+        }
+
+        if !self.append.is_empty() {
+            args.push("-append".into());
+            args.push(self.append.join(" "));
+        }
+
+        if let Some(dtb) = &self.dtb {
+            args.push("-dtb".into());
+            args.push(dtb.display().to_string());
         }
 
         if !self.graphics {
             args.push("-nographic".into());
         }
 
+        // the console takes the first serial port, so it's the guest's default console
+        if self.expect_prompt.is_some() {
+            args.extend(console::args());
+        }
+
+        for backend in &self.serial {
+            args.push("-serial".into());
+            args.push(backend.to_string());
+        }
+
+        if let Some(net) = &self.net {
+            args.extend(net.to_args());
+        }
+
+        if let Some(port) = self.gdb_port {
+            args.push("-gdb".into());
+            args.push(format!("tcp::{}", port));
+        }
+
+        if let Some(icount) = &self.icount {
+            args.push("-icount".into());
+            args.push(icount.to_string());
+        }
+
+        if self.rtc_clock_vm {
+            args.push("-rtc".into());
+            args.push("clock=vm".into());
+        }
+
         if let Some(replay) = &self.replay {
             args.push("-replay".into());
             args.push(replay.clone());
@@ -326,6 +911,10 @@ impl Panda {
         }
         #[cfg(feature = "libpanda")]
         {
+            if let Err(err) = self.validate() {
+                panic!("Invalid PANDA configuration: {}", err);
+            }
+
             let args = self.get_args();
 
             println!("Running with args: {:?}", args);
@@ -335,16 +924,17 @@ impl Panda {
 
             std::env::set_var("PANDA_DIR", std::env::var("PANDA_PATH").unwrap());
 
+            if let Some(prompt) = &self.expect_prompt {
+                console::init(prompt);
+            }
+
+            crate::net::set_active_config(self.net.clone().unwrap_or_default());
+            crate::time::set_icount_config(self.icount);
+
             let x = &mut 0i8;
             let empty = &mut (x as *mut c_char);
             unsafe {
-                for cb in inventory::iter::<InternalCallback> {
-                    sys::panda_register_callback(
-                        self as *mut _ as _,
-                        cb.cb_type,
-                        ::core::mem::transmute(cb.fn_pointer),
-                    );
-                }
+                crate::__internal_register_callbacks(crate::callbacks::get_plugin_ref());
 
                 if LIBRARY_STARTED.swap(true, Ordering::Relaxed) {
                     panic!("libpanda cannot be run twice in the same process");
@@ -352,9 +942,7 @@ impl Panda {
                 panda_set_library_mode(true);
                 panda_init(args_ptrs.len() as i32, transmute(args_ptrs.as_ptr()), empty);
 
-                for cb in inventory::iter::<PPPCallbackSetup> {
-                    cb.0();
-                }
+                crate::__internal_setup_ppp_callbacks();
 
                 let mut init_funcs = Vec::new();
                 core::mem::swap(&mut *AFTER_INIT_FUNCS.lock().unwrap(), &mut init_funcs);
@@ -362,6 +950,15 @@ impl Panda {
                     init_func()
                 }
 
+                if let Some((name, snapshot)) = &self.record {
+                    crate::rr::record_begin(name, snapshot.as_deref())
+                        .expect("Failed to begin recording");
+                }
+
+                if let Some(script) = self.script.take() {
+                    script.start();
+                }
+
                 panda_run();
                 LIBRARY_STARTED.store(false, Ordering::Relaxed);
             }
@@ -0,0 +1,264 @@
+//! Scripted interaction with the guest while making a recording
+//!
+//! A [`Script`] is a list of inputs to give the guest, each waiting for some point in
+//! the guest's execution first, such as its prompt being printed or a guest instruction
+//! count being reached. Running a script with [`Panda::record_script`] allows recordings
+//! to be made without anyone typing into the guest, so that a recording can be made and
+//! then analyzed by the same program.
+//!
+//! Inputs are typed into the serial console or sent as key presses through the QEMU
+//! monitor while recording, so they become part of the recording and a replay of it
+//! sees exactly the same input, without the script being run again.
+//!
+//! Scripts require a prompt to be set with [`Panda::expect_prompt`], which connects the
+//! serial console and monitor to the script.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::record;
+//!
+//! let script = record::script()
+//!     .type_line("./run_test")
+//!     .at_prompt()
+//!     .end_record()
+//!     .at_output("test finished");
+//!
+//! Panda::new()
+//!     .generic("x86_64")
+//!     .expect_prompt(r"root@debian-amd64:.*# ")
+//!     .record("run_test")
+//!     .record_script(script)
+//!     .run();
+//! ```
+#![cfg_attr(not(feature = "libpanda"), allow(dead_code))]
+
+use super::{console, ConsoleError, Panda};
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// What a step of a script waits for before giving its input
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Trigger {
+    /// Give the input as soon as the previous step is done
+    Immediately,
+
+    /// Wait for the guest to print its prompt
+    Prompt,
+
+    /// Wait for the guest to print the given text to its serial console
+    Output(String),
+
+    /// Wait for the guest to execute the given number of instructions
+    InstrCount(u64),
+}
+
+/// The input given by a step of a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    /// Type text into the serial console
+    Type(String),
+
+    /// Press a key (or combination, such as `ctrl-c`) using the monitor's `sendkey`
+    Key(String),
+
+    /// End the recording
+    EndRecord,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    trigger: Trigger,
+
+    /// How long to wait after the trigger before giving the input
+    delay: Option<Duration>,
+    action: Action,
+}
+
+/// A list of inputs to give the guest while recording. See the [module-level
+/// documentation](self) for more info.
+///
+/// Each method adding an input adds a new step, which is given immediately after the
+/// previous step unless one of the `at_*` methods is used to wait for something first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    steps: Vec<Step>,
+    timeout: Option<Duration>,
+}
+
+/// Create a new, empty script
+pub fn script() -> Script {
+    Script::default()
+}
+
+impl Script {
+    fn push(mut self, action: Action) -> Self {
+        self.steps.push(Step {
+            trigger: Trigger::Immediately,
+            delay: None,
+            action,
+        });
+
+        self
+    }
+
+    fn set_trigger(mut self, trigger: Trigger) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.trigger = trigger;
+        }
+
+        self
+    }
+
+    /// Type text into the guest's serial console
+    pub fn type_text(self, text: impl Into<String>) -> Self {
+        self.push(Action::Type(text.into()))
+    }
+
+    /// Type a line into the guest's serial console, followed by a newline
+    pub fn type_line(self, line: impl Into<String>) -> Self {
+        self.push(Action::Type(format!("{}\n", line.into())))
+    }
+
+    /// Press a key using the QEMU monitor, given in the form used by its `sendkey`
+    /// command (such as `ret` or `ctrl-c`)
+    pub fn send_key(self, key: impl Into<String>) -> Self {
+        self.push(Action::Key(key.into()))
+    }
+
+    /// End the recording
+    pub fn end_record(self) -> Self {
+        self.push(Action::EndRecord)
+    }
+
+    /// Wait for the guest to print its prompt before giving the last input
+    pub fn at_prompt(self) -> Self {
+        self.set_trigger(Trigger::Prompt)
+    }
+
+    /// Wait for the guest to print the given text to its serial console before giving
+    /// the last input. Output is only searched from the end of the previous wait.
+    pub fn at_output(self, text: impl Into<String>) -> Self {
+        self.set_trigger(Trigger::Output(text.into()))
+    }
+
+    /// Wait for the guest to have executed the given number of instructions before
+    /// giving the last input. As the script runs alongside the guest, the input is
+    /// given shortly after this point rather than exactly at it.
+    pub fn at_instruction_count(self, instr_count: u64) -> Self {
+        self.set_trigger(Trigger::InstrCount(instr_count))
+    }
+
+    /// Wait for the given time after the last input's trigger before giving it
+    pub fn after(mut self, delay: Duration) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.delay = Some(delay);
+        }
+
+        self
+    }
+
+    /// Give up on the script if a wait for the guest's output or an instruction count
+    /// takes longer than the given time. Defaults to waiting forever.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Start running the script on its own thread, once libpanda has been initialized
+    pub(super) fn start(self) {
+        let gates: Vec<_> = self
+            .steps
+            .iter()
+            .map(|step| match step.trigger {
+                Trigger::InstrCount(instr_count) => Some(Gate::at_instruction_count(instr_count)),
+                _ => None,
+            })
+            .collect();
+
+        std::thread::spawn(move || {
+            for (i, (step, gate)) in self.steps.iter().zip(gates).enumerate() {
+                if let Err(err) = run_step(step, gate, self.timeout) {
+                    eprintln!(
+                        "Warning: recording script failed at step {}: {}",
+                        i + 1,
+                        err
+                    );
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// A flag set once the guest reaches an instruction count
+#[derive(Clone, Default)]
+struct Gate(Arc<(Mutex<bool>, Condvar)>);
+
+impl Gate {
+    fn at_instruction_count(instr_count: u64) -> Self {
+        let gate = Gate::default();
+        let open = gate.clone();
+
+        crate::breakpoint::at_instruction_count(instr_count, move |_| open.open());
+
+        gate
+    }
+
+    fn open(&self) {
+        let (open, condvar) = &*self.0;
+        *open.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    /// Wait for the gate to open, returning whether it opened within the timeout
+    fn wait(&self, timeout: Option<Duration>) -> bool {
+        let (open, condvar) = &*self.0;
+        let open = open.lock().unwrap();
+
+        match timeout {
+            Some(timeout) => {
+                let (open, _) = condvar
+                    .wait_timeout_while(open, timeout, |open| !*open)
+                    .unwrap();
+                *open
+            }
+            None => *condvar.wait_while(open, |open| !*open).unwrap(),
+        }
+    }
+}
+
+fn run_step(
+    step: &Step,
+    gate: Option<Gate>,
+    timeout: Option<Duration>,
+) -> Result<(), ConsoleError> {
+    match &step.trigger {
+        Trigger::Immediately => (),
+        Trigger::Prompt => {
+            Panda::wait_for_prompt(timeout)?;
+        }
+        Trigger::Output(text) => {
+            console::wait_for_serial_output(text, timeout)?;
+        }
+        Trigger::InstrCount(instr_count) => {
+            if let Some(gate) = gate {
+                if !gate.wait(timeout) {
+                    return Err(ConsoleError::InstrCountTimeout(*instr_count));
+                }
+            }
+        }
+    }
+
+    if let Some(delay) = step.delay {
+        std::thread::sleep(delay);
+    }
+
+    match &step.action {
+        Action::Type(text) => console::type_serial(text),
+        Action::Key(key) => Panda::run_monitor_cmd(&format!("sendkey {}", key)).map(drop),
+        Action::EndRecord => Panda::run_monitor_cmd("end_record").map(drop),
+    }
+}
@@ -0,0 +1,143 @@
+//! Taking, reverting to and deleting snapshots of the guest at runtime
+//!
+//! Snapshots can't be taken or loaded in the middle of executing a block, so the
+//! asynchronous methods ([`Panda::snap`], [`Panda::revert_async`] and
+//! [`Panda::delete_snapshot`]) queue the operation to be run from PANDA's main loop as
+//! an [`idle`](crate::idle) task, making them safe to call from init or from within any
+//! callback. They return a [`PendingSnapshot`] through which the outcome can be
+//! checked once the operation has run. The `_sync` methods instead go through the QEMU
+//! monitor and block until the operation has finished, so must be called from a thread
+//! other than the one running PANDA.
+use super::Panda;
+use crate::idle::{self, TaskStatus};
+use crate::sys::{panda_delvm, panda_revert, panda_snap};
+
+use once_cell::sync::OnceCell;
+
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::sync::Arc;
+
+/// An error in taking, reverting to or deleting a snapshot
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    #[error("snapshot name {0:?} contains a null byte")]
+    InvalidName(String),
+
+    #[error("failed to take snapshot {0:?} ({1})")]
+    SnapFailed(String, c_int),
+
+    #[error("failed to revert to snapshot {0:?} ({1})")]
+    RevertFailed(String, c_int),
+}
+
+/// The result of a snapshot operation queued by [`Panda::snap`],
+/// [`Panda::revert_async`] or [`Panda::delete_snapshot`]
+#[derive(Clone)]
+pub struct PendingSnapshot(Arc<OnceCell<Result<(), SnapshotError>>>);
+
+impl PendingSnapshot {
+    /// Get the outcome of the operation, or `None` if it hasn't run yet
+    pub fn result(&self) -> Option<&Result<(), SnapshotError>> {
+        self.0.get()
+    }
+
+    /// Check whether the operation has run, successfully or not
+    pub fn is_done(&self) -> bool {
+        self.0.get().is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SnapshotOp {
+    Snap,
+    Revert,
+    Delete,
+}
+
+impl SnapshotOp {
+    fn run(self, name: &str) -> Result<(), SnapshotError> {
+        let c_name = CString::new(name).map_err(|_| SnapshotError::InvalidName(name.to_owned()))?;
+        let c_name = c_name.into_raw();
+
+        let ret: c_int = unsafe {
+            match self {
+                SnapshotOp::Snap => panda_snap(c_name),
+                SnapshotOp::Revert => panda_revert(c_name),
+                SnapshotOp::Delete => panda_delvm(c_name),
+            }
+        };
+
+        drop(unsafe { CString::from_raw(c_name) });
+
+        match self {
+            SnapshotOp::Snap if ret != 0 => Err(SnapshotError::SnapFailed(name.to_owned(), ret)),
+            SnapshotOp::Revert if ret != 0 => {
+                Err(SnapshotError::RevertFailed(name.to_owned(), ret))
+            }
+
+            // the return value of panda_delvm is not meaningful
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Queue an operation to run from the main loop. Idle tasks are run in the order they
+/// were spawned, so operations run in the order they were queued.
+fn queue(op: SnapshotOp, name: &str) -> PendingSnapshot {
+    let result = Arc::new(OnceCell::new());
+    let pending = PendingSnapshot(Arc::clone(&result));
+    let name = name.to_owned();
+
+    idle::spawn(move |_| {
+        let outcome = op.run(&name);
+        if let Err(err) = &outcome {
+            eprintln!("Warning: {}", err);
+        }
+
+        let _ = result.set(outcome);
+
+        TaskStatus::Done
+    });
+
+    pending
+}
+
+impl Panda {
+    /// Take a snapshot of the guest with the given name, storing it in the qcow. The
+    /// snapshot is taken from the main loop, once the current block has finished
+    /// executing. Any existing snapshot with the same name is replaced.
+    ///
+    /// ### Example
+    /// ```rust,no_run
+    /// use panda::prelude::*;
+    ///
+    /// #[panda::asid_changed]
+    /// fn asid_changed(_: &mut CPUState, _old: target_ulong, _new: target_ulong) -> bool {
+    ///     Panda::snap("last_switch");
+    ///
+    ///     false
+    /// }
+    /// ```
+    pub fn snap(name: &str) -> PendingSnapshot {
+        queue(SnapshotOp::Snap, name)
+    }
+
+    /// Revert the guest to the snapshot with the given name from the main loop, once
+    /// the current block has finished executing. To wait for the revert from another
+    /// thread, use [`Panda::revert_sync`] instead.
+    pub fn revert_async(name: &str) -> PendingSnapshot {
+        queue(SnapshotOp::Revert, name)
+    }
+
+    /// Delete the snapshot with the given name from the qcow, from the main loop
+    pub fn delete_snapshot(name: &str) -> PendingSnapshot {
+        queue(SnapshotOp::Delete, name)
+    }
+
+    /// Delete the snapshot with the given name using the monitor, blocking until it has
+    /// been deleted. See [`Panda::run_monitor_cmd`].
+    pub fn delete_snapshot_sync(name: &str) -> Result<String, super::ConsoleError> {
+        Self::run_monitor_cmd(&format!("delvm {}", name))
+    }
+}
@@ -0,0 +1,361 @@
+//! Running many PANDA instances in parallel, one worker process per instance
+//!
+//! libpanda can only be run once per process, so processing a large corpus of replays
+//! in parallel requires a process for each. A [`WorkerPool`] runs each [`Job`] by
+//! spawning the current executable again as a worker, sending it the job and collecting
+//! the output it produces. Workers are identified by an environment variable, so the
+//! program's `main` must start by calling [`worker_main`], which runs the job and exits
+//! when the process is a worker and returns immediately otherwise.
+//!
+//! Jobs are sent to workers over stdin and the worker's output is returned through a
+//! temporary file, leaving the worker's stdout and stderr (which PANDA writes to) free
+//! and inherited from the parent.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::workers::{self, Job, WorkerPool};
+//!
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//!
+//! static BLOCKS: AtomicU64 = AtomicU64::new(0);
+//!
+//! #[panda::before_block_exec]
+//! fn count_block(_: &mut CPUState, _: &mut TranslationBlock) {
+//!     BLOCKS.fetch_add(1, Ordering::Relaxed);
+//! }
+//!
+//! fn main() {
+//!     workers::worker_main(|job| {
+//!         job.panda().run();
+//!
+//!         BLOCKS.load(Ordering::Relaxed).to_string().into_bytes()
+//!     });
+//!
+//!     let jobs = ["replay1", "replay2", "replay3"]
+//!         .iter()
+//!         .map(|replay| Job::replay(*replay).generic("x86_64"));
+//!
+//!     for result in WorkerPool::new(2).run(jobs) {
+//!         match result {
+//!             Ok(output) => println!("{} blocks", String::from_utf8_lossy(&output)),
+//!             Err(err) => eprintln!("job failed: {}", err),
+//!         }
+//!     }
+//! }
+//! ```
+use super::Panda;
+use crate::PandaArgs;
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// The environment variable marking a process as a worker, holding the path to write
+/// the job's output to
+const WORKER_ENV: &str = "PANDA_RS_WORKER_OUTPUT";
+
+/// An error running a job in a worker process
+#[derive(Debug, thiserror::Error)]
+pub enum WorkerError {
+    #[error("failed to start worker process: {0}")]
+    Spawn(io::Error),
+
+    #[error("failed to communicate with worker process: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("worker process exited unsuccessfully ({0})")]
+    Failed(ExitStatus),
+
+    #[error("job was not run, as the thread running it panicked")]
+    NotRun,
+}
+
+/// A job to be run in a worker process: a replay along with the configuration of the
+/// PANDA instance replaying it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Job {
+    pub replay: String,
+    pub generic: Option<String>,
+    pub qcow: Option<String>,
+
+    /// Extra arguments for PANDA, such as plugins to load
+    pub args: Vec<String>,
+
+    /// Parameters for the worker's own use, in the order they were added
+    pub params: Vec<(String, String)>,
+}
+
+impl Job {
+    /// Create a job running the replay with the given name
+    pub fn replay<S: Into<String>>(replay: S) -> Self {
+        Self {
+            replay: replay.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Use the given generic image for the replay. See [`Panda::generic`].
+    pub fn generic<S: Into<String>>(mut self, generic: S) -> Self {
+        self.generic = Some(generic.into());
+        self
+    }
+
+    /// Use the given qcow for the replay. See [`Panda::qcow`].
+    pub fn qcow<S: Into<String>>(mut self, qcow: S) -> Self {
+        self.qcow = Some(qcow.into());
+        self
+    }
+
+    /// Add an argument for PANDA
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Load a plugin with args provided by a `PandaArgs` struct. See
+    /// [`Panda::plugin_args`].
+    pub fn plugin_args<T: PandaArgs>(self, args: &T) -> Self {
+        self.arg("-panda").arg(args.to_panda_args_str())
+    }
+
+    /// Add a parameter for the worker, retrieved with [`Job::get_param`]
+    pub fn param<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.params.push((key.into(), value.into()));
+        self
+    }
+
+    /// Get the last value of the given parameter
+    pub fn get_param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .rev()
+            .find(|(param, _)| param == key)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Create a [`Panda`] configured to run the job
+    pub fn panda(&self) -> Panda {
+        let mut panda = Panda::new();
+        panda.replay(&self.replay[..]).args(&self.args);
+
+        if let Some(generic) = &self.generic {
+            panda.generic(&generic[..]);
+        }
+
+        if let Some(qcow) = &self.qcow {
+            panda.qcow(&qcow[..]);
+        }
+
+        panda
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut fields = vec![("replay", &self.replay[..])];
+        fields.extend(self.generic.as_deref().map(|generic| ("generic", generic)));
+        fields.extend(self.qcow.as_deref().map(|qcow| ("qcow", qcow)));
+        fields.extend(self.args.iter().map(|arg| ("arg", &arg[..])));
+        for (key, value) in &self.params {
+            fields.push(("param", key));
+            fields.push(("value", value));
+        }
+
+        let mut out = Vec::new();
+        for (tag, value) in fields {
+            for field in [tag, value].iter() {
+                out.extend_from_slice(&(field.len() as u32).to_le_bytes());
+                out.extend_from_slice(field.as_bytes());
+            }
+        }
+
+        out
+    }
+
+    fn decode(mut data: &[u8]) -> Option<Self> {
+        let mut next = || -> Option<String> {
+            let len =
+                u32::from_le_bytes([*data.get(0)?, *data.get(1)?, *data.get(2)?, *data.get(3)?]);
+            let field = data.get(4..4 + len as usize)?;
+            let field = String::from_utf8(field.to_vec()).ok()?;
+            data = &data[4 + len as usize..];
+
+            Some(field)
+        };
+
+        let mut job = Job::default();
+        while let Some(tag) = next() {
+            let value = next()?;
+            match &tag[..] {
+                "replay" => job.replay = value,
+                "generic" => job.generic = Some(value),
+                "qcow" => job.qcow = Some(value),
+                "arg" => job.args.push(value),
+                "param" => job.params.push((value, String::new())),
+                "value" => job.params.last_mut()?.1 = value,
+                _ => return None,
+            }
+        }
+
+        Some(job)
+    }
+}
+
+/// If this process was spawned as a worker by a [`WorkerPool`], run the job it was given
+/// using `run` and exit, sending the returned output back to the pool. Otherwise,
+/// returns immediately.
+///
+/// This should be called at the start of `main`, before anything which shouldn't be run
+/// by every worker.
+pub fn worker_main<F>(run: F)
+where
+    F: FnOnce(Job) -> Vec<u8>,
+{
+    let output_path = match std::env::var_os(WORKER_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => return,
+    };
+
+    // don't pass the marker on to any processes the job spawns
+    std::env::remove_var(WORKER_ENV);
+
+    let mut data = Vec::new();
+    if let Err(err) = io::stdin().read_to_end(&mut data) {
+        eprintln!("Worker failed to read its job: {}", err);
+        std::process::exit(1);
+    }
+
+    let job = match Job::decode(&data) {
+        Some(job) => job,
+        None => {
+            eprintln!("Worker was sent a malformed job");
+            std::process::exit(1);
+        }
+    };
+
+    let output = run(job);
+    if let Err(err) = fs::write(&output_path, output) {
+        eprintln!("Worker failed to write its output: {}", err);
+        std::process::exit(1);
+    }
+
+    std::process::exit(0);
+}
+
+/// A pool running [`Job`]s in parallel, each in its own worker process
+#[derive(Debug, Clone)]
+pub struct WorkerPool {
+    workers: usize,
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+impl WorkerPool {
+    /// Create a pool running up to `workers` jobs at once by spawning the current
+    /// executable as a worker
+    pub fn new(workers: usize) -> Self {
+        Self {
+            workers: workers.max(1),
+            program: std::env::current_exe().unwrap_or_else(|_| PathBuf::from("/proc/self/exe")),
+            args: Vec::new(),
+        }
+    }
+
+    /// Spawn the given program as the worker instead of the current executable. The
+    /// program must call [`worker_main`].
+    pub fn program<P: Into<PathBuf>>(mut self, program: P) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Add a command line argument for the worker processes
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Run each of the jobs, returning their output in the order the jobs were given
+    pub fn run(&self, jobs: impl IntoIterator<Item = Job>) -> Vec<Result<Vec<u8>, WorkerError>> {
+        let jobs: Vec<Job> = jobs.into_iter().collect();
+        let mut results: Vec<_> = (0..jobs.len()).map(|_| Err(WorkerError::NotRun)).collect();
+
+        self.run_with(jobs, |index, _, result| results[index] = result);
+
+        results
+    }
+
+    /// Run each of the jobs, calling `on_done` with the index of each job, the job
+    /// and its output as it finishes
+    pub fn run_with<F>(&self, jobs: impl IntoIterator<Item = Job>, mut on_done: F)
+    where
+        F: FnMut(usize, Job, Result<Vec<u8>, WorkerError>),
+    {
+        let queue: VecDeque<_> = jobs.into_iter().enumerate().collect();
+        let workers = self.workers.min(queue.len());
+        let queue = Arc::new(Mutex::new(queue));
+        let (sender, receiver) = mpsc::channel();
+
+        let threads: Vec<_> = (0..workers)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let sender = sender.clone();
+                let pool = self.clone();
+
+                thread::spawn(move || loop {
+                    let (index, job) = match queue.lock().unwrap().pop_front() {
+                        Some(job) => job,
+                        None => break,
+                    };
+
+                    let result = pool.run_job(&job);
+                    if sender.send((index, job, result)).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+
+        for (index, job, result) in receiver {
+            on_done(index, job, result);
+        }
+
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+
+    /// Run a single job in a new worker process, waiting for it to finish
+    fn run_job(&self, job: &Job) -> Result<Vec<u8>, WorkerError> {
+        // removed when dropped
+        let output = tempfile::NamedTempFile::new()?;
+
+        let mut worker = Command::new(&self.program)
+            .args(&self.args)
+            .env(WORKER_ENV, output.path())
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(WorkerError::Spawn)?;
+
+        // closing stdin once the job is written lets the worker know it has all of it
+        let sent = match worker.stdin.take() {
+            Some(mut stdin) => stdin.write_all(&job.encode()),
+            None => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "worker has no stdin",
+            )),
+        };
+        let status = worker.wait()?;
+        sent?;
+
+        if status.success() {
+            Ok(fs::read(output.path())?)
+        } else {
+            Err(WorkerError::Failed(status))
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Export of analysis metrics in the Prometheus/OpenMetrics text format
+//!
+//! Metrics can be scraped from an embedded HTTP endpoint (see [`serve`]) or pushed to
+//! a Prometheus push gateway (see [`push`]), allowing long-running PANDA-based monitors
+//! to integrate with standard observability stacks. Alongside custom counters and
+//! gauges registered by plugins, built-in metrics for executed blocks and syscalls can
+//! be enabled with [`enable_builtin_metrics`].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::metrics;
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     metrics::enable_builtin_metrics();
+//!     metrics::serve("0.0.0.0:9100").unwrap();
+//!
+//!     let alerts = metrics::counter("my_plugin_alerts", "Number of alerts raised");
+//!     alerts.inc();
+//! }
+//! ```
+
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+use crate::Callback;
+
+#[cfg(not(feature = "ppc"))]
+use crate::{plugins::syscalls2::Syscalls2Callbacks, PppCallback};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A monotonically increasing metric
+#[derive(Debug, Clone)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    /// Increment the counter by one
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Increment the counter by a given amount
+    pub fn add(&self, amount: u64) {
+        self.0.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    /// Get the current value of the counter
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A metric which can be set to an arbitrary value
+#[derive(Debug, Clone)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    /// Set the value of the gauge
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Get the current value of the gauge
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    LabeledCounter(&'static str, Arc<Mutex<BTreeMap<String, u64>>>),
+}
+
+struct Family {
+    help: String,
+    metric: Metric,
+}
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: Mutex<BTreeMap<String, Family>> = Mutex::new(BTreeMap::new());
+}
+
+fn register(name: &str, help: &str, metric: impl FnOnce() -> Metric) -> Metric {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .entry(name.to_owned())
+        .or_insert_with(|| Family {
+            help: help.to_owned(),
+            metric: metric(),
+        })
+        .metric
+        .clone()
+}
+
+/// Register a counter, or get the existing counter if one of the same name has already
+/// been registered.
+///
+/// Following OpenMetrics conventions, the exported sample will have `_total` appended.
+///
+/// **Panics** if a metric of a different type has been registered with the same name.
+pub fn counter(name: &str, help: &str) -> Counter {
+    match register(name, help, || {
+        Metric::Counter(Counter(Arc::new(AtomicU64::new(0))))
+    }) {
+        Metric::Counter(counter) => counter,
+        _ => panic!("Metric {} is already registered and not a counter", name),
+    }
+}
+
+/// Register a gauge, or get the existing gauge if one of the same name has already
+/// been registered.
+///
+/// **Panics** if a metric of a different type has been registered with the same name.
+pub fn gauge(name: &str, help: &str) -> Gauge {
+    match register(name, help, || {
+        Metric::Gauge(Gauge(Arc::new(AtomicU64::new(0f64.to_bits()))))
+    }) {
+        Metric::Gauge(gauge) => gauge,
+        _ => panic!("Metric {} is already registered and not a gauge", name),
+    }
+}
+
+/// Render every registered metric in the OpenMetrics text format
+pub fn render() -> String {
+    update_builtin_gauges();
+
+    let registry = REGISTRY.lock().unwrap();
+    let mut out = String::new();
+
+    for (name, family) in registry.iter() {
+        let help = family.help.replace('\\', "\\\\").replace('\n', "\\n");
+
+        match &family.metric {
+            Metric::Counter(counter) => {
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "{}_total {}", name, counter.get());
+            }
+            Metric::Gauge(gauge) => {
+                let _ = writeln!(out, "# TYPE {} gauge", name);
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "{} {}", name, gauge.get());
+            }
+            Metric::LabeledCounter(label, values) => {
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                for (value, count) in values.lock().unwrap().iter() {
+                    let _ = writeln!(out, "{}_total{{{}=\"{}\"}} {}", name, label, value, count);
+                }
+            }
+        }
+    }
+
+    out.push_str("# EOF\n");
+    out
+}
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// How long a scrape may take to send its request or receive the response
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest request line read from a scrape
+const MAX_REQUEST_LINE: u64 = 8192;
+
+fn handle_scrape(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+
+    let mut request_line = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST_LINE)).read_line(&mut request_line)?;
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let response = if path == "/metrics" || path.starts_with("/metrics?") {
+        let body = render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            CONTENT_TYPE,
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+    };
+
+    stream.write_all(response.as_bytes())
+}
+
+/// Serve metrics over HTTP at `/metrics` on the given address from a background thread,
+/// returning the address being listened on. Each scrape is handled on its own thread,
+/// so a slow client doesn't hold up the others.
+pub fn serve(addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+
+    std::thread::Builder::new()
+        .name("panda-metrics".into())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = std::thread::Builder::new()
+                    .name("panda-metrics-scrape".into())
+                    .spawn(move || handle_scrape(stream));
+            }
+        })?;
+
+    Ok(local_addr)
+}
+
+/// Characters which are escaped in the job name, leaving those which are unreserved in
+/// URLs
+const JOB_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The path the metrics of a job are pushed to
+fn push_path(job: &str) -> String {
+    format!("/metrics/job/{}", utf8_percent_encode(job, JOB_ESCAPE))
+}
+
+/// Push the current metrics to a Prometheus push gateway (such as `localhost:9091`)
+/// under the given job name, replacing any metrics previously pushed for the job.
+pub fn push(gateway: impl ToSocketAddrs, job: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect(gateway)?;
+    let host = stream.peer_addr()?;
+    let body = render();
+
+    write!(
+        stream,
+        "PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        push_path(job),
+        host,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let status = response.split_whitespace().nth(1).unwrap_or("");
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("push gateway responded with status {}", status),
+        ))
+    }
+}
+
+struct Builtins {
+    instructions: Gauge,
+    _blocks: Callback,
+    #[cfg(not(feature = "ppc"))]
+    _syscalls: PppCallback,
+}
+
+lazy_static::lazy_static! {
+    static ref BUILTINS: Mutex<Option<Builtins>> = Mutex::new(None);
+}
+
+fn update_builtin_gauges() {
+    if let Some(builtins) = &*BUILTINS.lock().unwrap() {
+        builtins.instructions.set(rr_get_guest_instr_count() as f64);
+    }
+}
+
+/// Enable the built-in metrics:
+///
+/// * `panda_blocks_executed` - a counter of executed basic blocks
+/// * `panda_guest_instructions` - a gauge of the number of guest instructions executed
+/// * `panda_syscalls` - a counter of syscalls made, labeled by syscall number (not
+/// available on ppc)
+pub fn enable_builtin_metrics() {
+    let mut builtins = BUILTINS.lock().unwrap();
+    if builtins.is_some() {
+        return;
+    }
+
+    let blocks = counter("panda_blocks_executed", "Number of basic blocks executed");
+    let blocks_cb = Callback::new();
+    blocks_cb.before_block_exec(move |_, _| blocks.inc());
+
+    #[cfg(not(feature = "ppc"))]
+    let syscalls_cb = {
+        let syscalls = match register("panda_syscalls", "Number of syscalls made", || {
+            Metric::LabeledCounter("callno", Arc::new(Mutex::new(BTreeMap::new())))
+        }) {
+            Metric::LabeledCounter(_, syscalls) => syscalls,
+            _ => panic!("Metric panda_syscalls is already registered and not a counter"),
+        };
+
+        let syscalls_cb = PppCallback::new();
+        syscalls_cb.on_all_sys_enter(move |_, _, callno: target_ulong| {
+            *syscalls
+                .lock()
+                .unwrap()
+                .entry(callno.to_string())
+                .or_default() += 1;
+        });
+
+        syscalls_cb
+    };
+
+    *builtins = Some(Builtins {
+        instructions: gauge(
+            "panda_guest_instructions",
+            "Number of guest instructions executed",
+        ),
+        _blocks: blocks_cb,
+        #[cfg(not(feature = "ppc"))]
+        _syscalls: syscalls_cb,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_path() {
+        assert_eq!(push_path("panda-rs_1.0~x"), "/metrics/job/panda-rs_1.0~x");
+        assert_eq!(
+            push_path("a job/with?odd#chars"),
+            "/metrics/job/a%20job%2Fwith%3Fodd%23chars"
+        );
+        assert_eq!(push_path("\u{e9}"), "/metrics/job/%C3%A9");
+    }
+
+    #[test]
+    fn test_serve_with_idle_client() {
+        let addr = serve("127.0.0.1:0").unwrap();
+
+        // a client which never sends a request doesn't hold up the next one
+        let _idle = TcpStream::connect(addr).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"GET /other HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
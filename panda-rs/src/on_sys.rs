@@ -4982,3 +4982,783 @@ pub use crate::cbs::{
     on_sys_writev_enter as writev_enter,
     on_sys_writev_return as writev_return,
 };
+#[doc(inline)]
+#[cfg(feature = "mips64el")]
+pub use crate::cbs::{
+    on_sys_accept_enter as accept_enter,
+    on_sys_accept_return as accept_return,
+    on_sys_accept4_enter as accept4_enter,
+    on_sys_accept4_return as accept4_return,
+    on_sys_access_enter as access_enter,
+    on_sys_access_return as access_return,
+    on_sys_acct_enter as acct_enter,
+    on_sys_acct_return as acct_return,
+    on_sys_add_key_enter as add_key_enter,
+    on_sys_add_key_return as add_key_return,
+    on_sys_adjtimex_time32_enter as adjtimex_time32_enter,
+    on_sys_adjtimex_time32_return as adjtimex_time32_return,
+    on_sys_alarm_enter as alarm_enter,
+    on_sys_alarm_return as alarm_return,
+    on_sys_bdflush_enter as bdflush_enter,
+    on_sys_bdflush_return as bdflush_return,
+    on_sys_bind_enter as bind_enter,
+    on_sys_bind_return as bind_return,
+    on_sys_bpf_enter as bpf_enter,
+    on_sys_bpf_return as bpf_return,
+    on_sys_brk_enter as brk_enter,
+    on_sys_brk_return as brk_return,
+    on_sys_cacheflush_enter as cacheflush_enter,
+    on_sys_cacheflush_return as cacheflush_return,
+    on_sys_capget_enter as capget_enter,
+    on_sys_capget_return as capget_return,
+    on_sys_capset_enter as capset_enter,
+    on_sys_capset_return as capset_return,
+    on_sys_chdir_enter as chdir_enter,
+    on_sys_chdir_return as chdir_return,
+    on_sys_chmod_enter as chmod_enter,
+    on_sys_chmod_return as chmod_return,
+    on_sys_chown_enter as chown_enter,
+    on_sys_chown_return as chown_return,
+    on_sys_chroot_enter as chroot_enter,
+    on_sys_chroot_return as chroot_return,
+    on_sys_clock_adjtime_enter as clock_adjtime_enter,
+    on_sys_clock_adjtime_return as clock_adjtime_return,
+    on_sys_clock_adjtime32_enter as clock_adjtime32_enter,
+    on_sys_clock_adjtime32_return as clock_adjtime32_return,
+    on_sys_clock_getres_enter as clock_getres_enter,
+    on_sys_clock_getres_return as clock_getres_return,
+    on_sys_clock_getres_time32_enter as clock_getres_time32_enter,
+    on_sys_clock_getres_time32_return as clock_getres_time32_return,
+    on_sys_clock_gettime_enter as clock_gettime_enter,
+    on_sys_clock_gettime_return as clock_gettime_return,
+    on_sys_clock_gettime32_enter as clock_gettime32_enter,
+    on_sys_clock_gettime32_return as clock_gettime32_return,
+    on_sys_clock_nanosleep_enter as clock_nanosleep_enter,
+    on_sys_clock_nanosleep_return as clock_nanosleep_return,
+    on_sys_clock_nanosleep_time32_enter as clock_nanosleep_time32_enter,
+    on_sys_clock_nanosleep_time32_return as clock_nanosleep_time32_return,
+    on_sys_clock_settime_enter as clock_settime_enter,
+    on_sys_clock_settime_return as clock_settime_return,
+    on_sys_clock_settime32_enter as clock_settime32_enter,
+    on_sys_clock_settime32_return as clock_settime32_return,
+    on_sys_clone_enter as clone_enter,
+    on_sys_clone_return as clone_return,
+    on_sys_close_enter as close_enter,
+    on_sys_close_return as close_return,
+    on_sys_connect_enter as connect_enter,
+    on_sys_connect_return as connect_return,
+    on_sys_copy_file_range_enter as copy_file_range_enter,
+    on_sys_copy_file_range_return as copy_file_range_return,
+    on_sys_creat_enter as creat_enter,
+    on_sys_creat_return as creat_return,
+    on_sys_delete_module_enter as delete_module_enter,
+    on_sys_delete_module_return as delete_module_return,
+    on_sys_dup_enter as dup_enter,
+    on_sys_dup_return as dup_return,
+    on_sys_dup2_enter as dup2_enter,
+    on_sys_dup2_return as dup2_return,
+    on_sys_dup3_enter as dup3_enter,
+    on_sys_dup3_return as dup3_return,
+    on_sys_epoll_create_enter as epoll_create_enter,
+    on_sys_epoll_create_return as epoll_create_return,
+    on_sys_epoll_create1_enter as epoll_create1_enter,
+    on_sys_epoll_create1_return as epoll_create1_return,
+    on_sys_epoll_ctl_enter as epoll_ctl_enter,
+    on_sys_epoll_ctl_return as epoll_ctl_return,
+    on_sys_epoll_pwait_enter as epoll_pwait_enter,
+    on_sys_epoll_pwait_return as epoll_pwait_return,
+    on_sys_epoll_wait_enter as epoll_wait_enter,
+    on_sys_epoll_wait_return as epoll_wait_return,
+    on_sys_eventfd_enter as eventfd_enter,
+    on_sys_eventfd_return as eventfd_return,
+    on_sys_eventfd2_enter as eventfd2_enter,
+    on_sys_eventfd2_return as eventfd2_return,
+    on_sys_execve_enter as execve_enter,
+    on_sys_execve_return as execve_return,
+    on_sys_execveat_enter as execveat_enter,
+    on_sys_execveat_return as execveat_return,
+    on_sys_exit_enter as exit_enter,
+    on_sys_exit_return as exit_return,
+    on_sys_exit_group_enter as exit_group_enter,
+    on_sys_exit_group_return as exit_group_return,
+    on_sys_faccessat_enter as faccessat_enter,
+    on_sys_faccessat_return as faccessat_return,
+    on_sys_faccessat2_enter as faccessat2_enter,
+    on_sys_faccessat2_return as faccessat2_return,
+    on_sys_fadvise64_64_enter as fadvise64_64_enter,
+    on_sys_fadvise64_64_return as fadvise64_64_return,
+    on_sys_fallocate_enter as fallocate_enter,
+    on_sys_fallocate_return as fallocate_return,
+    on_sys_fanotify_init_enter as fanotify_init_enter,
+    on_sys_fanotify_init_return as fanotify_init_return,
+    on_sys_fanotify_mark_enter as fanotify_mark_enter,
+    on_sys_fanotify_mark_return as fanotify_mark_return,
+    on_sys_fchdir_enter as fchdir_enter,
+    on_sys_fchdir_return as fchdir_return,
+    on_sys_fchmod_enter as fchmod_enter,
+    on_sys_fchmod_return as fchmod_return,
+    on_sys_fchmodat_enter as fchmodat_enter,
+    on_sys_fchmodat_return as fchmodat_return,
+    on_sys_fchown_enter as fchown_enter,
+    on_sys_fchown_return as fchown_return,
+    on_sys_fchownat_enter as fchownat_enter,
+    on_sys_fchownat_return as fchownat_return,
+    on_sys_fcntl_enter as fcntl_enter,
+    on_sys_fcntl_return as fcntl_return,
+    on_sys_fcntl64_enter as fcntl64_enter,
+    on_sys_fcntl64_return as fcntl64_return,
+    on_sys_fdatasync_enter as fdatasync_enter,
+    on_sys_fdatasync_return as fdatasync_return,
+    on_sys_fgetxattr_enter as fgetxattr_enter,
+    on_sys_fgetxattr_return as fgetxattr_return,
+    on_sys_finit_module_enter as finit_module_enter,
+    on_sys_finit_module_return as finit_module_return,
+    on_sys_flistxattr_enter as flistxattr_enter,
+    on_sys_flistxattr_return as flistxattr_return,
+    on_sys_flock_enter as flock_enter,
+    on_sys_flock_return as flock_return,
+    on_sys_fork_enter as fork_enter,
+    on_sys_fork_return as fork_return,
+    on_sys_fremovexattr_enter as fremovexattr_enter,
+    on_sys_fremovexattr_return as fremovexattr_return,
+    on_sys_fsconfig_enter as fsconfig_enter,
+    on_sys_fsconfig_return as fsconfig_return,
+    on_sys_fsetxattr_enter as fsetxattr_enter,
+    on_sys_fsetxattr_return as fsetxattr_return,
+    on_sys_fsmount_enter as fsmount_enter,
+    on_sys_fsmount_return as fsmount_return,
+    on_sys_fsopen_enter as fsopen_enter,
+    on_sys_fsopen_return as fsopen_return,
+    on_sys_fspick_enter as fspick_enter,
+    on_sys_fspick_return as fspick_return,
+    on_sys_fstat_enter as fstat_enter,
+    on_sys_fstat_return as fstat_return,
+    on_sys_fstat64_enter as fstat64_enter,
+    on_sys_fstat64_return as fstat64_return,
+    on_sys_fstatat64_enter as fstatat64_enter,
+    on_sys_fstatat64_return as fstatat64_return,
+    on_sys_fstatfs_enter as fstatfs_enter,
+    on_sys_fstatfs_return as fstatfs_return,
+    on_sys_fstatfs64_enter as fstatfs64_enter,
+    on_sys_fstatfs64_return as fstatfs64_return,
+    on_sys_fsync_enter as fsync_enter,
+    on_sys_fsync_return as fsync_return,
+    on_sys_ftruncate_enter as ftruncate_enter,
+    on_sys_ftruncate_return as ftruncate_return,
+    on_sys_ftruncate64_enter as ftruncate64_enter,
+    on_sys_ftruncate64_return as ftruncate64_return,
+    on_sys_futex_enter as futex_enter,
+    on_sys_futex_return as futex_return,
+    on_sys_futex_time32_enter as futex_time32_enter,
+    on_sys_futex_time32_return as futex_time32_return,
+    on_sys_futimesat_time32_enter as futimesat_time32_enter,
+    on_sys_futimesat_time32_return as futimesat_time32_return,
+    on_sys_get_mempolicy_enter as get_mempolicy_enter,
+    on_sys_get_mempolicy_return as get_mempolicy_return,
+    on_sys_get_robust_list_enter as get_robust_list_enter,
+    on_sys_get_robust_list_return as get_robust_list_return,
+    on_sys_getcpu_enter as getcpu_enter,
+    on_sys_getcpu_return as getcpu_return,
+    on_sys_getcwd_enter as getcwd_enter,
+    on_sys_getcwd_return as getcwd_return,
+    on_sys_getdents_enter as getdents_enter,
+    on_sys_getdents_return as getdents_return,
+    on_sys_getdents64_enter as getdents64_enter,
+    on_sys_getdents64_return as getdents64_return,
+    on_sys_getegid_enter as getegid_enter,
+    on_sys_getegid_return as getegid_return,
+    on_sys_geteuid_enter as geteuid_enter,
+    on_sys_geteuid_return as geteuid_return,
+    on_sys_getgid_enter as getgid_enter,
+    on_sys_getgid_return as getgid_return,
+    on_sys_getgroups_enter as getgroups_enter,
+    on_sys_getgroups_return as getgroups_return,
+    on_sys_getitimer_enter as getitimer_enter,
+    on_sys_getitimer_return as getitimer_return,
+    on_sys_getpeername_enter as getpeername_enter,
+    on_sys_getpeername_return as getpeername_return,
+    on_sys_getpgid_enter as getpgid_enter,
+    on_sys_getpgid_return as getpgid_return,
+    on_sys_getpgrp_enter as getpgrp_enter,
+    on_sys_getpgrp_return as getpgrp_return,
+    on_sys_getpid_enter as getpid_enter,
+    on_sys_getpid_return as getpid_return,
+    on_sys_getppid_enter as getppid_enter,
+    on_sys_getppid_return as getppid_return,
+    on_sys_getpriority_enter as getpriority_enter,
+    on_sys_getpriority_return as getpriority_return,
+    on_sys_getrandom_enter as getrandom_enter,
+    on_sys_getrandom_return as getrandom_return,
+    on_sys_getresgid_enter as getresgid_enter,
+    on_sys_getresgid_return as getresgid_return,
+    on_sys_getresuid_enter as getresuid_enter,
+    on_sys_getresuid_return as getresuid_return,
+    on_sys_getrlimit_enter as getrlimit_enter,
+    on_sys_getrlimit_return as getrlimit_return,
+    on_sys_getrusage_enter as getrusage_enter,
+    on_sys_getrusage_return as getrusage_return,
+    on_sys_getsid_enter as getsid_enter,
+    on_sys_getsid_return as getsid_return,
+    on_sys_getsockname_enter as getsockname_enter,
+    on_sys_getsockname_return as getsockname_return,
+    on_sys_getsockopt_enter as getsockopt_enter,
+    on_sys_getsockopt_return as getsockopt_return,
+    on_sys_gettid_enter as gettid_enter,
+    on_sys_gettid_return as gettid_return,
+    on_sys_gettimeofday_enter as gettimeofday_enter,
+    on_sys_gettimeofday_return as gettimeofday_return,
+    on_sys_getuid_enter as getuid_enter,
+    on_sys_getuid_return as getuid_return,
+    on_sys_getxattr_enter as getxattr_enter,
+    on_sys_getxattr_return as getxattr_return,
+    on_sys_idle_enter as idle_enter,
+    on_sys_idle_return as idle_return,
+    on_sys_init_module_enter as init_module_enter,
+    on_sys_init_module_return as init_module_return,
+    on_sys_inotify_add_watch_enter as inotify_add_watch_enter,
+    on_sys_inotify_add_watch_return as inotify_add_watch_return,
+    on_sys_inotify_init_enter as inotify_init_enter,
+    on_sys_inotify_init_return as inotify_init_return,
+    on_sys_inotify_init1_enter as inotify_init1_enter,
+    on_sys_inotify_init1_return as inotify_init1_return,
+    on_sys_inotify_rm_watch_enter as inotify_rm_watch_enter,
+    on_sys_inotify_rm_watch_return as inotify_rm_watch_return,
+    on_sys_io_cancel_enter as io_cancel_enter,
+    on_sys_io_cancel_return as io_cancel_return,
+    on_sys_io_destroy_enter as io_destroy_enter,
+    on_sys_io_destroy_return as io_destroy_return,
+    on_sys_io_getevents_time32_enter as io_getevents_time32_enter,
+    on_sys_io_getevents_time32_return as io_getevents_time32_return,
+    on_sys_io_pgetevents_enter as io_pgetevents_enter,
+    on_sys_io_pgetevents_return as io_pgetevents_return,
+    on_sys_io_pgetevents_time32_enter as io_pgetevents_time32_enter,
+    on_sys_io_pgetevents_time32_return as io_pgetevents_time32_return,
+    on_sys_io_setup_enter as io_setup_enter,
+    on_sys_io_setup_return as io_setup_return,
+    on_sys_io_submit_enter as io_submit_enter,
+    on_sys_io_submit_return as io_submit_return,
+    on_sys_io_uring_enter_enter as io_uring_enter_enter,
+    on_sys_io_uring_enter_return as io_uring_enter_return,
+    on_sys_io_uring_register_enter as io_uring_register_enter,
+    on_sys_io_uring_register_return as io_uring_register_return,
+    on_sys_io_uring_setup_enter as io_uring_setup_enter,
+    on_sys_io_uring_setup_return as io_uring_setup_return,
+    on_sys_ioctl_enter as ioctl_enter,
+    on_sys_ioctl_return as ioctl_return,
+    on_sys_ioperm_enter as ioperm_enter,
+    on_sys_ioperm_return as ioperm_return,
+    on_sys_iopl_enter as iopl_enter,
+    on_sys_iopl_return as iopl_return,
+    on_sys_ioprio_get_enter as ioprio_get_enter,
+    on_sys_ioprio_get_return as ioprio_get_return,
+    on_sys_ioprio_set_enter as ioprio_set_enter,
+    on_sys_ioprio_set_return as ioprio_set_return,
+    on_sys_ipc_enter as ipc_enter,
+    on_sys_ipc_return as ipc_return,
+    on_sys_kcmp_enter as kcmp_enter,
+    on_sys_kcmp_return as kcmp_return,
+    on_sys_kexec_load_enter as kexec_load_enter,
+    on_sys_kexec_load_return as kexec_load_return,
+    on_sys_keyctl_enter as keyctl_enter,
+    on_sys_keyctl_return as keyctl_return,
+    on_sys_kill_enter as kill_enter,
+    on_sys_kill_return as kill_return,
+    on_sys_lchown_enter as lchown_enter,
+    on_sys_lchown_return as lchown_return,
+    on_sys_lgetxattr_enter as lgetxattr_enter,
+    on_sys_lgetxattr_return as lgetxattr_return,
+    on_sys_link_enter as link_enter,
+    on_sys_link_return as link_return,
+    on_sys_linkat_enter as linkat_enter,
+    on_sys_linkat_return as linkat_return,
+    on_sys_listen_enter as listen_enter,
+    on_sys_listen_return as listen_return,
+    on_sys_listxattr_enter as listxattr_enter,
+    on_sys_listxattr_return as listxattr_return,
+    on_sys_llistxattr_enter as llistxattr_enter,
+    on_sys_llistxattr_return as llistxattr_return,
+    on_sys_llseek_enter as llseek_enter,
+    on_sys_llseek_return as llseek_return,
+    on_sys_lookup_dcookie_enter as lookup_dcookie_enter,
+    on_sys_lookup_dcookie_return as lookup_dcookie_return,
+    on_sys_lremovexattr_enter as lremovexattr_enter,
+    on_sys_lremovexattr_return as lremovexattr_return,
+    on_sys_lseek_enter as lseek_enter,
+    on_sys_lseek_return as lseek_return,
+    on_sys_lsetxattr_enter as lsetxattr_enter,
+    on_sys_lsetxattr_return as lsetxattr_return,
+    on_sys_lstat_enter as lstat_enter,
+    on_sys_lstat_return as lstat_return,
+    on_sys_lstat64_enter as lstat64_enter,
+    on_sys_lstat64_return as lstat64_return,
+    on_sys_madvise_enter as madvise_enter,
+    on_sys_madvise_return as madvise_return,
+    on_sys_mbind_enter as mbind_enter,
+    on_sys_mbind_return as mbind_return,
+    on_sys_membarrier_enter as membarrier_enter,
+    on_sys_membarrier_return as membarrier_return,
+    on_sys_memfd_create_enter as memfd_create_enter,
+    on_sys_memfd_create_return as memfd_create_return,
+    on_sys_migrate_pages_enter as migrate_pages_enter,
+    on_sys_migrate_pages_return as migrate_pages_return,
+    on_sys_mincore_enter as mincore_enter,
+    on_sys_mincore_return as mincore_return,
+    on_sys_mkdir_enter as mkdir_enter,
+    on_sys_mkdir_return as mkdir_return,
+    on_sys_mkdirat_enter as mkdirat_enter,
+    on_sys_mkdirat_return as mkdirat_return,
+    on_sys_mknod_enter as mknod_enter,
+    on_sys_mknod_return as mknod_return,
+    on_sys_mknodat_enter as mknodat_enter,
+    on_sys_mknodat_return as mknodat_return,
+    on_sys_mlock_enter as mlock_enter,
+    on_sys_mlock_return as mlock_return,
+    on_sys_mlock2_enter as mlock2_enter,
+    on_sys_mlock2_return as mlock2_return,
+    on_sys_mlockall_enter as mlockall_enter,
+    on_sys_mlockall_return as mlockall_return,
+    on_sys_mmap_enter as mmap_enter,
+    on_sys_mmap_return as mmap_return,
+    on_sys_mount_enter as mount_enter,
+    on_sys_mount_return as mount_return,
+    on_sys_move_mount_enter as move_mount_enter,
+    on_sys_move_mount_return as move_mount_return,
+    on_sys_move_pages_enter as move_pages_enter,
+    on_sys_move_pages_return as move_pages_return,
+    on_sys_mprotect_enter as mprotect_enter,
+    on_sys_mprotect_return as mprotect_return,
+    on_sys_mq_getsetattr_enter as mq_getsetattr_enter,
+    on_sys_mq_getsetattr_return as mq_getsetattr_return,
+    on_sys_mq_notify_enter as mq_notify_enter,
+    on_sys_mq_notify_return as mq_notify_return,
+    on_sys_mq_open_enter as mq_open_enter,
+    on_sys_mq_open_return as mq_open_return,
+    on_sys_mq_timedreceive_enter as mq_timedreceive_enter,
+    on_sys_mq_timedreceive_return as mq_timedreceive_return,
+    on_sys_mq_timedreceive_time32_enter as mq_timedreceive_time32_enter,
+    on_sys_mq_timedreceive_time32_return as mq_timedreceive_time32_return,
+    on_sys_mq_timedsend_enter as mq_timedsend_enter,
+    on_sys_mq_timedsend_return as mq_timedsend_return,
+    on_sys_mq_timedsend_time32_enter as mq_timedsend_time32_enter,
+    on_sys_mq_timedsend_time32_return as mq_timedsend_time32_return,
+    on_sys_mq_unlink_enter as mq_unlink_enter,
+    on_sys_mq_unlink_return as mq_unlink_return,
+    on_sys_mremap_enter as mremap_enter,
+    on_sys_mremap_return as mremap_return,
+    on_sys_msgctl_enter as msgctl_enter,
+    on_sys_msgctl_return as msgctl_return,
+    on_sys_msgget_enter as msgget_enter,
+    on_sys_msgget_return as msgget_return,
+    on_sys_msgrcv_enter as msgrcv_enter,
+    on_sys_msgrcv_return as msgrcv_return,
+    on_sys_msgsnd_enter as msgsnd_enter,
+    on_sys_msgsnd_return as msgsnd_return,
+    on_sys_msync_enter as msync_enter,
+    on_sys_msync_return as msync_return,
+    on_sys_munlock_enter as munlock_enter,
+    on_sys_munlock_return as munlock_return,
+    on_sys_munlockall_enter as munlockall_enter,
+    on_sys_munlockall_return as munlockall_return,
+    on_sys_munmap_enter as munmap_enter,
+    on_sys_munmap_return as munmap_return,
+    on_sys_name_to_handle_at_enter as name_to_handle_at_enter,
+    on_sys_name_to_handle_at_return as name_to_handle_at_return,
+    on_sys_nanosleep_time32_enter as nanosleep_time32_enter,
+    on_sys_nanosleep_time32_return as nanosleep_time32_return,
+    on_sys_newfstat_enter as newfstat_enter,
+    on_sys_newfstat_return as newfstat_return,
+    on_sys_newlstat_enter as newlstat_enter,
+    on_sys_newlstat_return as newlstat_return,
+    on_sys_newstat_enter as newstat_enter,
+    on_sys_newstat_return as newstat_return,
+    on_sys_newuname_enter as newuname_enter,
+    on_sys_newuname_return as newuname_return,
+    on_sys_nfsservctl_enter as nfsservctl_enter,
+    on_sys_nfsservctl_return as nfsservctl_return,
+    on_sys_ni_syscall_enter as ni_syscall_enter,
+    on_sys_ni_syscall_return as ni_syscall_return,
+    on_sys_nice_enter as nice_enter,
+    on_sys_nice_return as nice_return,
+    on_sys_old_readdir_enter as old_readdir_enter,
+    on_sys_old_readdir_return as old_readdir_return,
+    on_sys_oldumount_enter as oldumount_enter,
+    on_sys_oldumount_return as oldumount_return,
+    on_sys_olduname_enter as olduname_enter,
+    on_sys_olduname_return as olduname_return,
+    on_sys_open_enter as open_enter,
+    on_sys_open_return as open_return,
+    on_sys_open_by_handle_at_enter as open_by_handle_at_enter,
+    on_sys_open_by_handle_at_return as open_by_handle_at_return,
+    on_sys_open_tree_enter as open_tree_enter,
+    on_sys_open_tree_return as open_tree_return,
+    on_sys_openat_enter as openat_enter,
+    on_sys_openat_return as openat_return,
+    on_sys_openat2_enter as openat2_enter,
+    on_sys_openat2_return as openat2_return,
+    on_sys_pause_enter as pause_enter,
+    on_sys_pause_return as pause_return,
+    on_sys_perf_event_open_enter as perf_event_open_enter,
+    on_sys_perf_event_open_return as perf_event_open_return,
+    on_sys_personality_enter as personality_enter,
+    on_sys_personality_return as personality_return,
+    on_sys_pidfd_getfd_enter as pidfd_getfd_enter,
+    on_sys_pidfd_getfd_return as pidfd_getfd_return,
+    on_sys_pidfd_open_enter as pidfd_open_enter,
+    on_sys_pidfd_open_return as pidfd_open_return,
+    on_sys_pidfd_send_signal_enter as pidfd_send_signal_enter,
+    on_sys_pidfd_send_signal_return as pidfd_send_signal_return,
+    on_sys_pipe_enter as pipe_enter,
+    on_sys_pipe_return as pipe_return,
+    on_sys_pipe2_enter as pipe2_enter,
+    on_sys_pipe2_return as pipe2_return,
+    on_sys_pivot_root_enter as pivot_root_enter,
+    on_sys_pivot_root_return as pivot_root_return,
+    on_sys_pkey_alloc_enter as pkey_alloc_enter,
+    on_sys_pkey_alloc_return as pkey_alloc_return,
+    on_sys_pkey_free_enter as pkey_free_enter,
+    on_sys_pkey_free_return as pkey_free_return,
+    on_sys_pkey_mprotect_enter as pkey_mprotect_enter,
+    on_sys_pkey_mprotect_return as pkey_mprotect_return,
+    on_sys_poll_enter as poll_enter,
+    on_sys_poll_return as poll_return,
+    on_sys_ppoll_enter as ppoll_enter,
+    on_sys_ppoll_return as ppoll_return,
+    on_sys_ppoll_time32_enter as ppoll_time32_enter,
+    on_sys_ppoll_time32_return as ppoll_time32_return,
+    on_sys_prctl_enter as prctl_enter,
+    on_sys_prctl_return as prctl_return,
+    on_sys_pread64_enter as pread64_enter,
+    on_sys_pread64_return as pread64_return,
+    on_sys_preadv_enter as preadv_enter,
+    on_sys_preadv_return as preadv_return,
+    on_sys_preadv2_enter as preadv2_enter,
+    on_sys_preadv2_return as preadv2_return,
+    on_sys_prlimit64_enter as prlimit64_enter,
+    on_sys_prlimit64_return as prlimit64_return,
+    on_sys_process_vm_readv_enter as process_vm_readv_enter,
+    on_sys_process_vm_readv_return as process_vm_readv_return,
+    on_sys_process_vm_writev_enter as process_vm_writev_enter,
+    on_sys_process_vm_writev_return as process_vm_writev_return,
+    on_sys_pselect6_enter as pselect6_enter,
+    on_sys_pselect6_return as pselect6_return,
+    on_sys_pselect6_time32_enter as pselect6_time32_enter,
+    on_sys_pselect6_time32_return as pselect6_time32_return,
+    on_sys_ptrace_enter as ptrace_enter,
+    on_sys_ptrace_return as ptrace_return,
+    on_sys_pwrite64_enter as pwrite64_enter,
+    on_sys_pwrite64_return as pwrite64_return,
+    on_sys_pwritev_enter as pwritev_enter,
+    on_sys_pwritev_return as pwritev_return,
+    on_sys_pwritev2_enter as pwritev2_enter,
+    on_sys_pwritev2_return as pwritev2_return,
+    on_sys_query_module_enter as query_module_enter,
+    on_sys_query_module_return as query_module_return,
+    on_sys_quotactl_enter as quotactl_enter,
+    on_sys_quotactl_return as quotactl_return,
+    on_sys_read_enter as read_enter,
+    on_sys_read_return as read_return,
+    on_sys_readahead_enter as readahead_enter,
+    on_sys_readahead_return as readahead_return,
+    on_sys_readlink_enter as readlink_enter,
+    on_sys_readlink_return as readlink_return,
+    on_sys_readlinkat_enter as readlinkat_enter,
+    on_sys_readlinkat_return as readlinkat_return,
+    on_sys_readv_enter as readv_enter,
+    on_sys_readv_return as readv_return,
+    on_sys_reboot_enter as reboot_enter,
+    on_sys_reboot_return as reboot_return,
+    on_sys_recv_enter as recv_enter,
+    on_sys_recv_return as recv_return,
+    on_sys_recvfrom_enter as recvfrom_enter,
+    on_sys_recvfrom_return as recvfrom_return,
+    on_sys_recvmmsg_enter as recvmmsg_enter,
+    on_sys_recvmmsg_return as recvmmsg_return,
+    on_sys_recvmmsg_time32_enter as recvmmsg_time32_enter,
+    on_sys_recvmmsg_time32_return as recvmmsg_time32_return,
+    on_sys_recvmsg_enter as recvmsg_enter,
+    on_sys_recvmsg_return as recvmsg_return,
+    on_sys_remap_file_pages_enter as remap_file_pages_enter,
+    on_sys_remap_file_pages_return as remap_file_pages_return,
+    on_sys_removexattr_enter as removexattr_enter,
+    on_sys_removexattr_return as removexattr_return,
+    on_sys_rename_enter as rename_enter,
+    on_sys_rename_return as rename_return,
+    on_sys_renameat_enter as renameat_enter,
+    on_sys_renameat_return as renameat_return,
+    on_sys_renameat2_enter as renameat2_enter,
+    on_sys_renameat2_return as renameat2_return,
+    on_sys_request_key_enter as request_key_enter,
+    on_sys_request_key_return as request_key_return,
+    on_sys_restart_syscall_enter as restart_syscall_enter,
+    on_sys_restart_syscall_return as restart_syscall_return,
+    on_sys_rmdir_enter as rmdir_enter,
+    on_sys_rmdir_return as rmdir_return,
+    on_sys_rseq_enter as rseq_enter,
+    on_sys_rseq_return as rseq_return,
+    on_sys_rt_sigaction_enter as rt_sigaction_enter,
+    on_sys_rt_sigaction_return as rt_sigaction_return,
+    on_sys_rt_sigpending_enter as rt_sigpending_enter,
+    on_sys_rt_sigpending_return as rt_sigpending_return,
+    on_sys_rt_sigprocmask_enter as rt_sigprocmask_enter,
+    on_sys_rt_sigprocmask_return as rt_sigprocmask_return,
+    on_sys_rt_sigqueueinfo_enter as rt_sigqueueinfo_enter,
+    on_sys_rt_sigqueueinfo_return as rt_sigqueueinfo_return,
+    on_sys_rt_sigreturn_enter as rt_sigreturn_enter,
+    on_sys_rt_sigreturn_return as rt_sigreturn_return,
+    on_sys_rt_sigsuspend_enter as rt_sigsuspend_enter,
+    on_sys_rt_sigsuspend_return as rt_sigsuspend_return,
+    on_sys_rt_sigtimedwait_enter as rt_sigtimedwait_enter,
+    on_sys_rt_sigtimedwait_return as rt_sigtimedwait_return,
+    on_sys_rt_sigtimedwait_time32_enter as rt_sigtimedwait_time32_enter,
+    on_sys_rt_sigtimedwait_time32_return as rt_sigtimedwait_time32_return,
+    on_sys_rt_tgsigqueueinfo_enter as rt_tgsigqueueinfo_enter,
+    on_sys_rt_tgsigqueueinfo_return as rt_tgsigqueueinfo_return,
+    on_sys_sched_get_priority_max_enter as sched_get_priority_max_enter,
+    on_sys_sched_get_priority_max_return as sched_get_priority_max_return,
+    on_sys_sched_get_priority_min_enter as sched_get_priority_min_enter,
+    on_sys_sched_get_priority_min_return as sched_get_priority_min_return,
+    on_sys_sched_getaffinity_enter as sched_getaffinity_enter,
+    on_sys_sched_getaffinity_return as sched_getaffinity_return,
+    on_sys_sched_getattr_enter as sched_getattr_enter,
+    on_sys_sched_getattr_return as sched_getattr_return,
+    on_sys_sched_getparam_enter as sched_getparam_enter,
+    on_sys_sched_getparam_return as sched_getparam_return,
+    on_sys_sched_getscheduler_enter as sched_getscheduler_enter,
+    on_sys_sched_getscheduler_return as sched_getscheduler_return,
+    on_sys_sched_rr_get_interval_enter as sched_rr_get_interval_enter,
+    on_sys_sched_rr_get_interval_return as sched_rr_get_interval_return,
+    on_sys_sched_rr_get_interval_time32_enter as sched_rr_get_interval_time32_enter,
+    on_sys_sched_rr_get_interval_time32_return as sched_rr_get_interval_time32_return,
+    on_sys_sched_setaffinity_enter as sched_setaffinity_enter,
+    on_sys_sched_setaffinity_return as sched_setaffinity_return,
+    on_sys_sched_setattr_enter as sched_setattr_enter,
+    on_sys_sched_setattr_return as sched_setattr_return,
+    on_sys_sched_setparam_enter as sched_setparam_enter,
+    on_sys_sched_setparam_return as sched_setparam_return,
+    on_sys_sched_setscheduler_enter as sched_setscheduler_enter,
+    on_sys_sched_setscheduler_return as sched_setscheduler_return,
+    on_sys_sched_yield_enter as sched_yield_enter,
+    on_sys_sched_yield_return as sched_yield_return,
+    on_sys_seccomp_enter as seccomp_enter,
+    on_sys_seccomp_return as seccomp_return,
+    on_sys_select_enter as select_enter,
+    on_sys_select_return as select_return,
+    on_sys_semctl_enter as semctl_enter,
+    on_sys_semctl_return as semctl_return,
+    on_sys_semget_enter as semget_enter,
+    on_sys_semget_return as semget_return,
+    on_sys_semtimedop_enter as semtimedop_enter,
+    on_sys_semtimedop_return as semtimedop_return,
+    on_sys_send_enter as send_enter,
+    on_sys_send_return as send_return,
+    on_sys_sendfile_enter as sendfile_enter,
+    on_sys_sendfile_return as sendfile_return,
+    on_sys_sendfile64_enter as sendfile64_enter,
+    on_sys_sendfile64_return as sendfile64_return,
+    on_sys_sendmmsg_enter as sendmmsg_enter,
+    on_sys_sendmmsg_return as sendmmsg_return,
+    on_sys_sendmsg_enter as sendmsg_enter,
+    on_sys_sendmsg_return as sendmsg_return,
+    on_sys_sendto_enter as sendto_enter,
+    on_sys_sendto_return as sendto_return,
+    on_sys_set_mempolicy_enter as set_mempolicy_enter,
+    on_sys_set_mempolicy_return as set_mempolicy_return,
+    on_sys_set_robust_list_enter as set_robust_list_enter,
+    on_sys_set_robust_list_return as set_robust_list_return,
+    on_sys_set_tid_address_enter as set_tid_address_enter,
+    on_sys_set_tid_address_return as set_tid_address_return,
+    on_sys_setdomainname_enter as setdomainname_enter,
+    on_sys_setdomainname_return as setdomainname_return,
+    on_sys_setfsgid_enter as setfsgid_enter,
+    on_sys_setfsgid_return as setfsgid_return,
+    on_sys_setfsuid_enter as setfsuid_enter,
+    on_sys_setfsuid_return as setfsuid_return,
+    on_sys_setgid_enter as setgid_enter,
+    on_sys_setgid_return as setgid_return,
+    on_sys_setgroups_enter as setgroups_enter,
+    on_sys_setgroups_return as setgroups_return,
+    on_sys_sethostname_enter as sethostname_enter,
+    on_sys_sethostname_return as sethostname_return,
+    on_sys_setitimer_enter as setitimer_enter,
+    on_sys_setitimer_return as setitimer_return,
+    on_sys_setns_enter as setns_enter,
+    on_sys_setns_return as setns_return,
+    on_sys_setpgid_enter as setpgid_enter,
+    on_sys_setpgid_return as setpgid_return,
+    on_sys_setpriority_enter as setpriority_enter,
+    on_sys_setpriority_return as setpriority_return,
+    on_sys_setregid_enter as setregid_enter,
+    on_sys_setregid_return as setregid_return,
+    on_sys_setresgid_enter as setresgid_enter,
+    on_sys_setresgid_return as setresgid_return,
+    on_sys_setresuid_enter as setresuid_enter,
+    on_sys_setresuid_return as setresuid_return,
+    on_sys_setreuid_enter as setreuid_enter,
+    on_sys_setreuid_return as setreuid_return,
+    on_sys_setrlimit_enter as setrlimit_enter,
+    on_sys_setrlimit_return as setrlimit_return,
+    on_sys_setsid_enter as setsid_enter,
+    on_sys_setsid_return as setsid_return,
+    on_sys_setsockopt_enter as setsockopt_enter,
+    on_sys_setsockopt_return as setsockopt_return,
+    on_sys_settimeofday_enter as settimeofday_enter,
+    on_sys_settimeofday_return as settimeofday_return,
+    on_sys_setuid_enter as setuid_enter,
+    on_sys_setuid_return as setuid_return,
+    on_sys_setup_enter as setup_enter,
+    on_sys_setup_return as setup_return,
+    on_sys_setxattr_enter as setxattr_enter,
+    on_sys_setxattr_return as setxattr_return,
+    on_sys_sgetmask_enter as sgetmask_enter,
+    on_sys_sgetmask_return as sgetmask_return,
+    on_sys_shmat_enter as shmat_enter,
+    on_sys_shmat_return as shmat_return,
+    on_sys_shmctl_enter as shmctl_enter,
+    on_sys_shmctl_return as shmctl_return,
+    on_sys_shmdt_enter as shmdt_enter,
+    on_sys_shmdt_return as shmdt_return,
+    on_sys_shmget_enter as shmget_enter,
+    on_sys_shmget_return as shmget_return,
+    on_sys_shutdown_enter as shutdown_enter,
+    on_sys_shutdown_return as shutdown_return,
+    on_sys_sigaction_enter as sigaction_enter,
+    on_sys_sigaction_return as sigaction_return,
+    on_sys_sigaltstack_enter as sigaltstack_enter,
+    on_sys_sigaltstack_return as sigaltstack_return,
+    on_sys_signal_enter as signal_enter,
+    on_sys_signal_return as signal_return,
+    on_sys_signalfd_enter as signalfd_enter,
+    on_sys_signalfd_return as signalfd_return,
+    on_sys_signalfd4_enter as signalfd4_enter,
+    on_sys_signalfd4_return as signalfd4_return,
+    on_sys_sigpending_enter as sigpending_enter,
+    on_sys_sigpending_return as sigpending_return,
+    on_sys_sigprocmask_enter as sigprocmask_enter,
+    on_sys_sigprocmask_return as sigprocmask_return,
+    on_sys_sigreturn_enter as sigreturn_enter,
+    on_sys_sigreturn_return as sigreturn_return,
+    on_sys_sigsuspend_enter as sigsuspend_enter,
+    on_sys_sigsuspend_return as sigsuspend_return,
+    on_sys_socket_enter as socket_enter,
+    on_sys_socket_return as socket_return,
+    on_sys_socketcall_enter as socketcall_enter,
+    on_sys_socketcall_return as socketcall_return,
+    on_sys_socketpair_enter as socketpair_enter,
+    on_sys_socketpair_return as socketpair_return,
+    on_sys_splice_enter as splice_enter,
+    on_sys_splice_return as splice_return,
+    on_sys_ssetmask_enter as ssetmask_enter,
+    on_sys_ssetmask_return as ssetmask_return,
+    on_sys_stat_enter as stat_enter,
+    on_sys_stat_return as stat_return,
+    on_sys_stat64_enter as stat64_enter,
+    on_sys_stat64_return as stat64_return,
+    on_sys_statfs_enter as statfs_enter,
+    on_sys_statfs_return as statfs_return,
+    on_sys_statfs64_enter as statfs64_enter,
+    on_sys_statfs64_return as statfs64_return,
+    on_sys_statx_enter as statx_enter,
+    on_sys_statx_return as statx_return,
+    on_sys_stime32_enter as stime32_enter,
+    on_sys_stime32_return as stime32_return,
+    on_sys_swapoff_enter as swapoff_enter,
+    on_sys_swapoff_return as swapoff_return,
+    on_sys_swapon_enter as swapon_enter,
+    on_sys_swapon_return as swapon_return,
+    on_sys_symlink_enter as symlink_enter,
+    on_sys_symlink_return as symlink_return,
+    on_sys_symlinkat_enter as symlinkat_enter,
+    on_sys_symlinkat_return as symlinkat_return,
+    on_sys_sync_enter as sync_enter,
+    on_sys_sync_return as sync_return,
+    on_sys_sync_file_range_enter as sync_file_range_enter,
+    on_sys_sync_file_range_return as sync_file_range_return,
+    on_sys_syncfs_enter as syncfs_enter,
+    on_sys_syncfs_return as syncfs_return,
+    on_sys_sysctl_enter as sysctl_enter,
+    on_sys_sysctl_return as sysctl_return,
+    on_sys_sysfs_enter as sysfs_enter,
+    on_sys_sysfs_return as sysfs_return,
+    on_sys_sysinfo_enter as sysinfo_enter,
+    on_sys_sysinfo_return as sysinfo_return,
+    on_sys_syslog_enter as syslog_enter,
+    on_sys_syslog_return as syslog_return,
+    on_sys_tee_enter as tee_enter,
+    on_sys_tee_return as tee_return,
+    on_sys_tgkill_enter as tgkill_enter,
+    on_sys_tgkill_return as tgkill_return,
+    on_sys_time32_enter as time32_enter,
+    on_sys_time32_return as time32_return,
+    on_sys_timer_create_enter as timer_create_enter,
+    on_sys_timer_create_return as timer_create_return,
+    on_sys_timer_delete_enter as timer_delete_enter,
+    on_sys_timer_delete_return as timer_delete_return,
+    on_sys_timer_getoverrun_enter as timer_getoverrun_enter,
+    on_sys_timer_getoverrun_return as timer_getoverrun_return,
+    on_sys_timer_gettime_enter as timer_gettime_enter,
+    on_sys_timer_gettime_return as timer_gettime_return,
+    on_sys_timer_gettime32_enter as timer_gettime32_enter,
+    on_sys_timer_gettime32_return as timer_gettime32_return,
+    on_sys_timer_settime_enter as timer_settime_enter,
+    on_sys_timer_settime_return as timer_settime_return,
+    on_sys_timer_settime32_enter as timer_settime32_enter,
+    on_sys_timer_settime32_return as timer_settime32_return,
+    on_sys_timerfd_create_enter as timerfd_create_enter,
+    on_sys_timerfd_create_return as timerfd_create_return,
+    on_sys_timerfd_gettime_enter as timerfd_gettime_enter,
+    on_sys_timerfd_gettime_return as timerfd_gettime_return,
+    on_sys_timerfd_gettime32_enter as timerfd_gettime32_enter,
+    on_sys_timerfd_gettime32_return as timerfd_gettime32_return,
+    on_sys_timerfd_settime_enter as timerfd_settime_enter,
+    on_sys_timerfd_settime_return as timerfd_settime_return,
+    on_sys_timerfd_settime32_enter as timerfd_settime32_enter,
+    on_sys_timerfd_settime32_return as timerfd_settime32_return,
+    on_sys_times_enter as times_enter,
+    on_sys_times_return as times_return,
+    on_sys_tkill_enter as tkill_enter,
+    on_sys_tkill_return as tkill_return,
+    on_sys_truncate_enter as truncate_enter,
+    on_sys_truncate_return as truncate_return,
+    on_sys_truncate64_enter as truncate64_enter,
+    on_sys_truncate64_return as truncate64_return,
+    on_sys_umask_enter as umask_enter,
+    on_sys_umask_return as umask_return,
+    on_sys_umount_enter as umount_enter,
+    on_sys_umount_return as umount_return,
+    on_sys_uname_enter as uname_enter,
+    on_sys_uname_return as uname_return,
+    on_sys_unlink_enter as unlink_enter,
+    on_sys_unlink_return as unlink_return,
+    on_sys_unlinkat_enter as unlinkat_enter,
+    on_sys_unlinkat_return as unlinkat_return,
+    on_sys_unshare_enter as unshare_enter,
+    on_sys_unshare_return as unshare_return,
+    on_sys_uselib_enter as uselib_enter,
+    on_sys_uselib_return as uselib_return,
+    on_sys_userfaultfd_enter as userfaultfd_enter,
+    on_sys_userfaultfd_return as userfaultfd_return,
+    on_sys_ustat_enter as ustat_enter,
+    on_sys_ustat_return as ustat_return,
+    on_sys_utime32_enter as utime32_enter,
+    on_sys_utime32_return as utime32_return,
+    on_sys_utimensat_enter as utimensat_enter,
+    on_sys_utimensat_return as utimensat_return,
+    on_sys_utimensat_time32_enter as utimensat_time32_enter,
+    on_sys_utimensat_time32_return as utimensat_time32_return,
+    on_sys_utimes_time32_enter as utimes_time32_enter,
+    on_sys_utimes_time32_return as utimes_time32_return,
+    on_sys_vhangup_enter as vhangup_enter,
+    on_sys_vhangup_return as vhangup_return,
+    on_sys_vmsplice_enter as vmsplice_enter,
+    on_sys_vmsplice_return as vmsplice_return,
+    on_sys_wait4_enter as wait4_enter,
+    on_sys_wait4_return as wait4_return,
+    on_sys_waitid_enter as waitid_enter,
+    on_sys_waitid_return as waitid_return,
+    on_sys_waitpid_enter as waitpid_enter,
+    on_sys_waitpid_return as waitpid_return,
+    on_sys_write_enter as write_enter,
+    on_sys_write_return as write_return,
+    on_sys_writev_enter as writev_enter,
+    on_sys_writev_return as writev_return,
+};
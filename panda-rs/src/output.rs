@@ -0,0 +1,40 @@
+//! The directory analyses write their results to
+//!
+//! Modules which write files, such as [`procdump`](crate::procdump) and
+//! [`crash`](crate::crash), each write into their own subdirectory of a single shared
+//! output directory, so that one call to [`set_output_dir`] moves all of them. The output
+//! directory defaults to the current directory.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     // dumps are now written to `analysis/procdumps`
+//!     panda::output::set_output_dir("analysis");
+//! }
+//! ```
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref OUTPUT_DIR: Mutex<PathBuf> = Mutex::new(PathBuf::from("."));
+}
+
+/// Set the directory results are written to. Subdirectories are created within it as
+/// they are needed.
+pub fn set_output_dir(dir: impl Into<PathBuf>) {
+    *OUTPUT_DIR.lock().unwrap() = dir.into();
+}
+
+/// Get the directory results are written to
+pub fn output_dir() -> PathBuf {
+    OUTPUT_DIR.lock().unwrap().clone()
+}
+
+/// Get the subdirectory of the output directory with the given name, such as `procdumps`
+pub fn subdir(name: &str) -> PathBuf {
+    output_dir().join(name)
+}
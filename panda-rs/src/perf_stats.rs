@@ -0,0 +1,376 @@
+//! Emulated performance statistics and TLB events
+//!
+//! This module counts the translation blocks and instructions executed by each process
+//! (ASID), along with flushes of QEMU's software TLB and, optionally, TLB misses on
+//! guest data accesses. These can be used for rough microarchitectural analyses such as
+//! working set estimation or measuring the cost of context switches.
+//!
+//! PANDA has no callbacks for TLB events, so they are derived:
+//!
+//! * Flushes are detected from QEMU's flush counter and reported right before the next
+//! block is executed, so several flushes may be reported together.
+//! * Misses are detected by checking QEMU's TLB for each memory access before it is
+//! performed (which requires memory callbacks, see [`enable_tlb_misses`]). As the MMU
+//! mode of an access isn't known, an access only counts as a miss if no MMU mode has
+//! the page cached, so misses may be slightly undercounted.
+//!
+//! The TLB modelled is QEMU's software TLB rather than that of the emulated CPU, so
+//! miss counts reflect the guest's memory access patterns rather than real hardware.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::perf_stats;
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     perf_stats::enable();
+//!     perf_stats::enable_tlb_misses();
+//! }
+//!
+//! #[panda::uninit]
+//! fn uninit(_: &mut PluginHandle) {
+//!     let file = std::fs::File::create("perf.csv").unwrap();
+//!     perf_stats::write_csv(file).unwrap();
+//! }
+//! ```
+
+use crate::prelude::*;
+use crate::mem::page_bits;
+use crate::memcb::{self, MemcbDemand};
+use crate::{cpu_arch_state, current_asid, CPUArchPtr, Callback};
+
+use panda_sys::{tlb_flush_count, CPUTLBEntry};
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The kind of access which missed in the TLB
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TlbAccess {
+    Read,
+    Write,
+}
+
+/// A guest memory access which was not cached in QEMU's TLB, causing a TLB fill
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TlbMiss {
+    pub access: TlbAccess,
+
+    /// The virtual address accessed
+    pub addr: target_ptr_t,
+
+    /// The pc of the instruction making the access
+    pub pc: target_ptr_t,
+
+    /// The address space the access was made in
+    pub asid: target_ulong,
+}
+
+/// Counters for a single process, or for the whole system
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    /// Translation blocks executed
+    pub blocks: u64,
+
+    /// Guest instructions executed, counted per block so blocks exited early are
+    /// counted in full
+    pub instructions: u64,
+
+    /// Data reads which missed in the TLB. Only counted while TLB miss tracking is
+    /// enabled.
+    pub tlb_read_misses: u64,
+
+    /// Data writes which missed in the TLB. Only counted while TLB miss tracking is
+    /// enabled.
+    pub tlb_write_misses: u64,
+}
+
+/// A snapshot of the statistics recorded so far
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    /// Counters for the whole system
+    pub total: Counters,
+
+    /// Counters for each asid
+    pub per_asid: BTreeMap<target_ulong, Counters>,
+
+    /// Number of times QEMU's TLB has been flushed
+    pub tlb_flushes: u64,
+}
+
+impl Stats {
+    /// Get the counters for a given asid
+    pub fn for_asid(&self, asid: target_ulong) -> Counters {
+        self.per_asid.get(&asid).copied().unwrap_or_default()
+    }
+}
+
+type MissCallback = Box<dyn FnMut(&mut CPUState, &TlbMiss) + Send + 'static>;
+type FlushCallback = Box<dyn FnMut(&mut CPUState, u64) + Send + 'static>;
+
+#[derive(Default)]
+struct Recorder {
+    stats: Stats,
+    last_flush_count: c_int,
+    miss_callbacks: Vec<MissCallback>,
+    flush_callbacks: Vec<FlushCallback>,
+}
+
+impl Recorder {
+    /// Count the TLB flushes since the last check, returning how many there were
+    fn check_flush(&mut self) -> u64 {
+        let flush_count = unsafe { tlb_flush_count };
+        let flushes = flush_count.wrapping_sub(self.last_flush_count) as u32 as u64;
+        self.last_flush_count = flush_count;
+        self.stats.tlb_flushes += flushes;
+
+        flushes
+    }
+
+    fn record_block(&mut self, asid: target_ulong, instructions: u64) {
+        for counters in [
+            &mut self.stats.total,
+            self.stats.per_asid.entry(asid).or_default(),
+        ] {
+            counters.blocks += 1;
+            counters.instructions += instructions;
+        }
+    }
+
+    fn record_miss(&mut self, miss: &TlbMiss) {
+        for counters in [
+            &mut self.stats.total,
+            self.stats.per_asid.entry(miss.asid).or_default(),
+        ] {
+            match miss.access {
+                TlbAccess::Read => counters.tlb_read_misses += 1,
+                TlbAccess::Write => counters.tlb_write_misses += 1,
+            }
+        }
+    }
+}
+
+/// Run each callback in the list selected by `list`, without the recorder locked so
+/// that the callbacks can register further callbacks
+fn run_callbacks<C>(list: impl Fn(&mut Recorder) -> &mut Vec<C>, mut run: impl FnMut(&mut C)) {
+    let mut callbacks = std::mem::take(list(&mut RECORDER.lock().unwrap()));
+    for callback in &mut callbacks {
+        run(callback);
+    }
+
+    let mut recorder = RECORDER.lock().unwrap();
+    let list = list(&mut recorder);
+    callbacks.append(list);
+    *list = callbacks;
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Mutex<Recorder> = Mutex::new(Recorder {
+        last_flush_count: unsafe { tlb_flush_count },
+        ..Default::default()
+    });
+    static ref CALLBACKS: Callback = install_callbacks();
+    static ref MISS_CALLBACKS: (Callback, Callback) = install_miss_callbacks();
+
+    /// The memory callbacks demanded while TLB misses are being counted
+    static ref MISS_DEMAND: Mutex<Option<MemcbDemand>> = Mutex::new(None);
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TLB_MISSES_ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn install_callbacks() -> Callback {
+    let block = Callback::new();
+
+    block.before_block_exec(|cpu, tb| {
+        let asid = current_asid(cpu);
+        let flushes = {
+            let mut recorder = RECORDER.lock().unwrap();
+            recorder.record_block(asid, tb.icount as u64);
+            recorder.check_flush()
+        };
+
+        if flushes != 0 {
+            run_callbacks(
+                |recorder| &mut recorder.flush_callbacks,
+                |callback| callback(cpu, flushes),
+            );
+        }
+    });
+
+    block
+}
+
+/// Check whether the page containing `addr` is cached in the TLB for the given access,
+/// mirroring the lookup done by QEMU's softmmu helpers
+fn in_tlb(cpu: &CPUState, addr: target_ptr_t, access: TlbAccess) -> bool {
+    let env = cpu_arch_state!(cpu);
+    let bits = page_bits();
+    let page_mask = !(((1 as target_ulong) << bits) - 1);
+    let invalid_mask = (1 as target_ulong) << (bits - 1);
+    let page = addr as target_ulong & page_mask;
+
+    let matches = |entry: &CPUTLBEntry| {
+        let entry = unsafe { entry.__bindgen_anon_1.__bindgen_anon_1 };
+        let tlb_addr = match access {
+            TlbAccess::Read => entry.addr_read,
+            TlbAccess::Write => entry.addr_write,
+        };
+
+        tlb_addr & (page_mask | invalid_mask) == page
+    };
+
+    unsafe {
+        let tlb_table = &(*env).tlb_table;
+        let index = (page >> bits) as usize & (tlb_table[0].len() - 1);
+
+        tlb_table.iter().any(|table| matches(&table[index]))
+            || (*env).tlb_v_table.iter().flatten().any(matches)
+    }
+}
+
+fn check_access(
+    cpu: &mut CPUState,
+    access: TlbAccess,
+    pc: target_ptr_t,
+    addr: target_ptr_t,
+    size: usize,
+) {
+    let last = addr.wrapping_add(size.saturating_sub(1) as target_ptr_t);
+    let mut pages = vec![addr];
+    if (last >> page_bits()) != (addr >> page_bits()) {
+        pages.push(last);
+    }
+
+    for addr in pages {
+        if !in_tlb(cpu, addr, access) {
+            let miss = TlbMiss {
+                access,
+                addr,
+                pc,
+                asid: current_asid(cpu),
+            };
+
+            RECORDER.lock().unwrap().record_miss(&miss);
+            run_callbacks(
+                |recorder| &mut recorder.miss_callbacks,
+                |callback| callback(cpu, &miss),
+            );
+        }
+    }
+}
+
+fn install_miss_callbacks() -> (Callback, Callback) {
+    let read = Callback::new();
+    let write = Callback::new();
+
+    read.virt_mem_before_read(|cpu, pc, addr, size| {
+        check_access(cpu, TlbAccess::Read, pc, addr, size);
+    });
+
+    write.virt_mem_before_write(|cpu, pc, addr, size, _| {
+        check_access(cpu, TlbAccess::Write, pc, addr, size);
+    });
+
+    (read, write)
+}
+
+/// Start counting executed blocks and instructions and TLB flushes
+pub fn enable() {
+    if !ENABLED.swap(true, Ordering::SeqCst) {
+        CALLBACKS.enable();
+    }
+}
+
+/// Stop recording statistics, including TLB misses. Statistics recorded so far are
+/// kept until [`reset`] is called.
+pub fn disable() {
+    if ENABLED.swap(false, Ordering::SeqCst) {
+        CALLBACKS.disable();
+    }
+
+    if TLB_MISSES_ENABLED.swap(false, Ordering::SeqCst) {
+        let (read, write) = &*MISS_CALLBACKS;
+        read.disable();
+        write.disable();
+
+        MISS_DEMAND.lock().unwrap().take();
+    }
+}
+
+/// Check whether statistics are currently being recorded
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Start counting TLB misses on guest data accesses. This demands memory callbacks
+/// until [`disable`] is called.
+pub fn enable_tlb_misses() {
+    if !TLB_MISSES_ENABLED.swap(true, Ordering::SeqCst) {
+        *MISS_DEMAND.lock().unwrap() = Some(memcb::demand_all());
+
+        let (read, write) = &*MISS_CALLBACKS;
+        read.enable();
+        write.enable();
+    }
+}
+
+/// Register a callback to be run for each TLB miss. Only run while TLB misses are
+/// being counted (see [`enable_tlb_misses`]).
+pub fn on_tlb_miss(callback: impl FnMut(&mut CPUState, &TlbMiss) + Send + 'static) {
+    RECORDER
+        .lock()
+        .unwrap()
+        .miss_callbacks
+        .push(Box::new(callback));
+}
+
+/// Register a callback to be run when the TLB has been flushed, with the number of
+/// flushes since the last time the callback was run. Only run while statistics are
+/// being recorded (see [`enable`]).
+pub fn on_tlb_flush(callback: impl FnMut(&mut CPUState, u64) + Send + 'static) {
+    RECORDER
+        .lock()
+        .unwrap()
+        .flush_callbacks
+        .push(Box::new(callback));
+}
+
+/// Get a snapshot of the statistics recorded so far
+pub fn stats() -> Stats {
+    RECORDER.lock().unwrap().stats.clone()
+}
+
+/// Clear all statistics recorded so far
+pub fn reset() {
+    RECORDER.lock().unwrap().stats = Stats::default();
+}
+
+/// Write the per-process counters recorded so far as CSV, with the columns
+/// `asid,blocks,instructions,tlb_read_misses,tlb_write_misses`.
+pub fn write_csv(mut writer: impl Write) -> io::Result<()> {
+    let stats = stats();
+
+    writeln!(
+        writer,
+        "asid,blocks,instructions,tlb_read_misses,tlb_write_misses"
+    )?;
+    for (asid, counters) in &stats.per_asid {
+        writeln!(
+            writer,
+            "{:#x},{},{},{},{}",
+            asid,
+            counters.blocks,
+            counters.instructions,
+            counters.tlb_read_misses,
+            counters.tlb_write_misses
+        )?;
+    }
+
+    Ok(())
+}
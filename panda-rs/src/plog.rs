@@ -0,0 +1,404 @@
+//! Reading and writing PANDA's pandalog
+//!
+//! The pandalog is PANDA's log of analysis results, a compressed file of protobuf
+//! `LogEntry` messages written by plugins and consumed by tools such as `plog_reader`.
+//! Each entry records the program counter and guest instruction count it was written
+//! at, along with a sub-message from the plugin which wrote it.
+//!
+//! The messages making up `LogEntry` are generated from the `.proto` files of the
+//! plugins PANDA was built with, so field numbers can differ between builds. Rather
+//! than bind a particular build's messages, entries are handled as generic protobuf
+//! [`Message`]s addressed by field number. The field numbers for a build can be found
+//! in its generated `pandalog.proto`.
+//!
+//! Entries are written with [`write`] to the pandalog PANDA was started with (using
+//! `-pandalog <file>`). Pandalogs can be read without PANDA using [`reader::Reader`],
+//! which requires the `plog` feature.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plog::{self, Entry, Message};
+//! use panda::prelude::*;
+//!
+//! /// The field number of `my_plugin_event` in this build's `LogEntry`
+//! const MY_PLUGIN_EVENT: u32 = 60;
+//!
+//! #[panda::before_block_exec]
+//! fn every_block(cpu: &mut CPUState, tb: &mut TranslationBlock) {
+//!     let event = Message::new().uint64(1, tb.pc as u64).string(2, "block");
+//!
+//!     plog::write(cpu, &Entry::new().message(MY_PLUGIN_EVENT, event)).unwrap();
+//! }
+//! ```
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+use crate::sys::{
+    panda__log_entry__free_unpacked, panda__log_entry__unpack, pandalog, pandalog_write_entry,
+};
+
+use std::convert::TryInto;
+use std::ops::{Deref, DerefMut};
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "plog")))]
+#[cfg(feature = "plog")]
+pub mod reader;
+
+/// The field number of the program counter in `LogEntry`
+pub const PC_FIELD: u32 = 1;
+
+/// The field number of the guest instruction count in `LogEntry`
+pub const INSTR_FIELD: u32 = 2;
+
+/// An error encountered while encoding, decoding or writing pandalog entries
+#[derive(Debug, thiserror::Error)]
+pub enum PlogError {
+    #[error("PANDA was not started with a pandalog (-pandalog <file>)")]
+    NotEnabled,
+
+    #[error("the entry is not a valid LogEntry for this build of PANDA")]
+    InvalidEntry,
+
+    #[error("malformed protobuf message")]
+    Malformed,
+
+    #[error("malformed pandalog: {0}")]
+    MalformedLog(&'static str),
+
+    #[error("I/O error reading the pandalog: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The value of a single protobuf field, as it appears on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// Integers, booleans and enums
+    Varint(u64),
+
+    /// `fixed64`, `sfixed64` and `double`
+    Fixed64(u64),
+
+    /// Strings, bytes, sub-messages and packed repeated fields
+    Bytes(Vec<u8>),
+
+    /// `fixed32`, `sfixed32` and `float`
+    Fixed32(u32),
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn get_varint(data: &[u8], pos: &mut usize) -> Result<u64, PlogError> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).ok_or(PlogError::Malformed)?;
+        *pos += 1;
+
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(PlogError::Malformed)
+}
+
+fn get_bytes<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], PlogError> {
+    let end = pos.checked_add(len).ok_or(PlogError::Malformed)?;
+    let bytes = data.get(*pos..end).ok_or(PlogError::Malformed)?;
+    *pos = end;
+
+    Ok(bytes)
+}
+
+/// A protobuf message, as a list of fields in the order they appear. Repeated fields
+/// appear once per value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub fields: Vec<(u32, Value)>,
+}
+
+impl Message {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a field with the given number and value
+    pub fn field(mut self, number: u32, value: Value) -> Self {
+        self.fields.push((number, value));
+        self
+    }
+
+    /// Add a `uint64`, `uint32`, `int64` or enum field
+    pub fn uint64(self, number: u32, value: u64) -> Self {
+        self.field(number, Value::Varint(value))
+    }
+
+    /// Add an `int32` field, which is sign-extended to 64 bits on the wire
+    pub fn int32(self, number: u32, value: i32) -> Self {
+        self.field(number, Value::Varint(value as i64 as u64))
+    }
+
+    pub fn bool(self, number: u32, value: bool) -> Self {
+        self.field(number, Value::Varint(value as u64))
+    }
+
+    pub fn string(self, number: u32, value: &str) -> Self {
+        self.bytes(number, value.as_bytes())
+    }
+
+    pub fn bytes(self, number: u32, value: &[u8]) -> Self {
+        self.field(number, Value::Bytes(value.to_vec()))
+    }
+
+    /// Add a sub-message field
+    pub fn message(self, number: u32, value: Message) -> Self {
+        self.field(number, Value::Bytes(value.encode()))
+    }
+
+    /// Get every value of the given field
+    pub fn all(&self, number: u32) -> impl Iterator<Item = &Value> + '_ {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| *field == number)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the last value of the given field, which is the one protobuf uses for
+    /// non-repeated fields
+    pub fn get(&self, number: u32) -> Option<&Value> {
+        self.all(number).last()
+    }
+
+    /// Get an integer field (or a fixed-width field as an integer)
+    pub fn get_u64(&self, number: u32) -> Option<u64> {
+        match self.get(number)? {
+            Value::Varint(value) | Value::Fixed64(value) => Some(*value),
+            Value::Fixed32(value) => Some(*value as u64),
+            Value::Bytes(_) => None,
+        }
+    }
+
+    pub fn get_bytes(&self, number: u32) -> Option<&[u8]> {
+        match self.get(number)? {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, number: u32) -> Option<&str> {
+        std::str::from_utf8(self.get_bytes(number)?).ok()
+    }
+
+    /// Decode a sub-message field
+    pub fn get_message(&self, number: u32) -> Option<Message> {
+        Message::decode(self.get_bytes(number)?).ok()
+    }
+
+    /// Encode the message in protobuf's wire format
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        for (number, value) in &self.fields {
+            let number = (*number as u64) << 3;
+            match value {
+                Value::Varint(value) => {
+                    put_varint(&mut out, number);
+                    put_varint(&mut out, *value);
+                }
+                Value::Fixed64(value) => {
+                    put_varint(&mut out, number | 1);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                Value::Bytes(bytes) => {
+                    put_varint(&mut out, number | 2);
+                    put_varint(&mut out, bytes.len() as u64);
+                    out.extend_from_slice(bytes);
+                }
+                Value::Fixed32(value) => {
+                    put_varint(&mut out, number | 5);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Decode a message from protobuf's wire format
+    pub fn decode(data: &[u8]) -> Result<Self, PlogError> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let key = get_varint(data, &mut pos)?;
+            let number = (key >> 3) as u32;
+
+            let value = match key & 7 {
+                0 => Value::Varint(get_varint(data, &mut pos)?),
+                1 => {
+                    let bytes = get_bytes(data, &mut pos, 8)?;
+                    Value::Fixed64(u64::from_le_bytes(bytes.try_into().unwrap()))
+                }
+                2 => {
+                    let len = get_varint(data, &mut pos)? as usize;
+                    Value::Bytes(get_bytes(data, &mut pos, len)?.to_vec())
+                }
+                5 => {
+                    let bytes = get_bytes(data, &mut pos, 4)?;
+                    Value::Fixed32(u32::from_le_bytes(bytes.try_into().unwrap()))
+                }
+
+                // groups are deprecated and not used by the pandalog
+                _ => return Err(PlogError::Malformed),
+            };
+
+            fields.push((number, value));
+        }
+
+        Ok(Self { fields })
+    }
+}
+
+/// A pandalog entry (`LogEntry`). Derefs to the underlying [`Message`] for accessing
+/// the fields written by plugins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Entry(pub Message);
+
+impl Entry {
+    /// Create an entry. The program counter and instruction count are filled in when
+    /// the entry is written.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sub-message to the entry, such as the message a plugin logs
+    pub fn message(self, number: u32, value: Message) -> Self {
+        Self(self.0.message(number, value))
+    }
+
+    /// The program counter the entry was written at
+    pub fn pc(&self) -> Option<u64> {
+        self.get_u64(PC_FIELD)
+    }
+
+    /// The guest instruction count the entry was written at
+    pub fn instr(&self) -> Option<u64> {
+        self.get_u64(INSTR_FIELD)
+    }
+}
+
+impl Deref for Entry {
+    type Target = Message;
+
+    fn deref(&self) -> &Message {
+        &self.0
+    }
+}
+
+impl DerefMut for Entry {
+    fn deref_mut(&mut self) -> &mut Message {
+        &mut self.0
+    }
+}
+
+/// Check whether PANDA was started with a pandalog to write entries to
+pub fn enabled() -> bool {
+    unsafe { pandalog != 0 }
+}
+
+/// Write an entry to the pandalog PANDA was started with, stamped with the current
+/// program counter and instruction count
+pub fn write(cpu: &mut CPUState, entry: &Entry) -> Result<(), PlogError> {
+    if !enabled() {
+        return Err(PlogError::NotEnabled);
+    }
+
+    let mut message = Message::new()
+        .uint64(PC_FIELD, crate::current_pc(cpu) as u64)
+        .uint64(INSTR_FIELD, rr_get_guest_instr_count());
+    message.fields.extend(
+        entry
+            .fields
+            .iter()
+            .filter(|(number, _)| *number != PC_FIELD && *number != INSTR_FIELD)
+            .cloned(),
+    );
+
+    // let protobuf-c build its LogEntry from the encoded message, checking that the
+    // fields match this build of PANDA
+    let encoded = message.encode();
+    unsafe {
+        let entry =
+            panda__log_entry__unpack(std::ptr::null_mut(), encoded.len() as _, encoded.as_ptr());
+        if entry.is_null() {
+            return Err(PlogError::InvalidEntry);
+        }
+
+        pandalog_write_entry(entry);
+        panda__log_entry__free_unpacked(entry, std::ptr::null_mut());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let inner = Message::new().string(1, "block").bool(2, true);
+        let message = Message::new()
+            .uint64(1, 0x401000)
+            .int32(2, -1)
+            .bytes(3, &[0, 1, 2])
+            .field(4, Value::Fixed64(u64::MAX))
+            .field(5, Value::Fixed32(7))
+            .message(60, inner.clone())
+            .uint64(1, 0x402000);
+
+        let decoded = Message::decode(&message.encode()).unwrap();
+        assert_eq!(decoded, message);
+
+        // the last value of a repeated field wins
+        assert_eq!(decoded.get_u64(1), Some(0x402000));
+        assert_eq!(decoded.all(1).count(), 2);
+        assert_eq!(decoded.get_u64(2), Some(-1i64 as u64));
+        assert_eq!(decoded.get_bytes(3), Some(&[0, 1, 2][..]));
+        assert_eq!(decoded.get_u64(5), Some(7));
+        assert_eq!(decoded.get_message(60), Some(inner));
+        assert_eq!(
+            decoded.get_message(60).unwrap().get_string(1),
+            Some("block")
+        );
+        assert_eq!(decoded.get(6), None);
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        // truncated varint
+        assert!(Message::decode(&[0x08, 0x80]).is_err());
+
+        // truncated fixed64
+        assert!(Message::decode(&[0x09, 0, 0, 0]).is_err());
+
+        // length longer than the message
+        assert!(Message::decode(&[0x12, 0x05, 1, 2]).is_err());
+
+        // length which overflows when added to the position
+        let mut data = vec![0x12];
+        put_varint(&mut data, u64::MAX);
+        assert!(Message::decode(&data).is_err());
+
+        // groups aren't supported
+        assert!(Message::decode(&[0x0b]).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! Reading pandalog files without PANDA
+//!
+//! A pandalog consists of a header, a series of zlib-compressed chunks each holding a
+//! run of length-prefixed entries, and a directory giving the position, first
+//! instruction count and number of entries of each chunk.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plog::reader::Reader;
+//!
+//! for entry in Reader::open("trace.plog").unwrap() {
+//!     let entry = entry.unwrap();
+//!     println!("{:?} @ {:#x?}: {:?}", entry.instr(), entry.pc(), entry.fields);
+//! }
+//! ```
+use super::{Entry, Message, PlogError};
+
+use flate2::read::ZlibDecoder;
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// The only version of the pandalog format supported
+const PL_VERSION: u32 = 2;
+
+/// The space reserved for the header at the start of the file
+const HEADER_SIZE: u64 = 128;
+
+/// The most chunks or entries to reserve space for up front, as the counts come from
+/// the file and may be corrupt
+const MAX_PREALLOC: usize = 0x1000;
+
+/// A chunk of entries, as listed in the pandalog's directory
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// The instruction count of the first entry in the chunk
+    pub instr: u64,
+
+    /// The offset of the compressed chunk in the file
+    pub pos: u64,
+
+    /// The length of the compressed chunk
+    pub len: u64,
+    pub num_entries: u64,
+}
+
+/// A reader iterating over the entries of a pandalog, in order
+pub struct Reader<R> {
+    reader: R,
+    chunks: Vec<Chunk>,
+    next_chunk: usize,
+    entries: std::vec::IntoIter<Vec<u8>>,
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, PlogError> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, PlogError> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl Reader<BufReader<File>> {
+    /// Open a pandalog file for reading
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PlogError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Reader<R> {
+    /// Read a pandalog from any seekable source
+    pub fn new(mut reader: R) -> Result<Self, PlogError> {
+        // the header is written as a C struct, with padding before dir_pos
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+
+        let version = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let dir_pos = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        if version != PL_VERSION {
+            return Err(PlogError::MalformedLog("unsupported pandalog version"));
+        }
+
+        if dir_pos < HEADER_SIZE {
+            return Err(PlogError::MalformedLog("pandalog was not closed"));
+        }
+
+        reader.seek(SeekFrom::Start(dir_pos))?;
+        let num_chunks = read_u32(&mut reader)?;
+
+        let mut chunks = Vec::with_capacity((num_chunks as usize).min(MAX_PREALLOC));
+        for _ in 0..num_chunks {
+            chunks.push(Chunk {
+                instr: read_u64(&mut reader)?,
+                pos: read_u64(&mut reader)?,
+                len: 0,
+                num_entries: read_u64(&mut reader)?,
+            });
+        }
+
+        // each chunk runs until the next, and the last until the directory
+        let ends: Vec<u64> = chunks
+            .iter()
+            .skip(1)
+            .map(|chunk| chunk.pos)
+            .chain(Some(dir_pos))
+            .collect();
+        for (chunk, end) in chunks.iter_mut().zip(ends) {
+            chunk.len = end
+                .checked_sub(chunk.pos)
+                .ok_or(PlogError::MalformedLog("chunks out of order"))?;
+        }
+
+        Ok(Self {
+            reader,
+            chunks,
+            next_chunk: 0,
+            entries: Vec::new().into_iter(),
+        })
+    }
+
+    /// The chunks making up the pandalog
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Skip ahead or back to the chunk containing the given instruction count. Entries
+    /// before the instruction count within that chunk are still returned.
+    pub fn seek(&mut self, instr: u64) {
+        self.next_chunk = self
+            .chunks
+            .iter()
+            .rposition(|chunk| chunk.instr <= instr)
+            .unwrap_or(0);
+        self.entries = Vec::new().into_iter();
+    }
+
+    fn read_chunk(&mut self, chunk: Chunk) -> Result<Vec<Vec<u8>>, PlogError> {
+        self.reader.seek(SeekFrom::Start(chunk.pos))?;
+
+        let mut data = Vec::new();
+        ZlibDecoder::new((&mut self.reader).take(chunk.len)).read_to_end(&mut data)?;
+
+        let mut entries = Vec::with_capacity((chunk.num_entries as usize).min(MAX_PREALLOC));
+        let mut data = &data[..];
+        while !data.is_empty() {
+            let len = read_u32(&mut data)? as usize;
+            if len > data.len() {
+                return Err(PlogError::MalformedLog("truncated entry"));
+            }
+
+            entries.push(data[..len].to_vec());
+            data = &data[len..];
+        }
+
+        Ok(entries)
+    }
+}
+
+impl<R: Read + Seek> Iterator for Reader<R> {
+    type Item = Result<Entry, PlogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Message::decode(&entry).map(Entry));
+            }
+
+            let chunk = *self.chunks.get(self.next_chunk)?;
+            self.next_chunk += 1;
+
+            match self.read_chunk(chunk) {
+                Ok(entries) => self.entries = entries.into_iter(),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    use std::io::{Cursor, Write};
+
+    fn entry(instr: u64, pc: u64) -> Message {
+        Message::new()
+            .uint64(crate::plog::PC_FIELD, pc)
+            .uint64(crate::plog::INSTR_FIELD, instr)
+    }
+
+    /// Build a pandalog with the given chunks of entries
+    fn pandalog(chunks: &[Vec<Message>]) -> Vec<u8> {
+        let mut file = vec![0; HEADER_SIZE as usize];
+        let mut directory = Vec::new();
+
+        for entries in chunks {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            for entry in entries {
+                let encoded = entry.encode();
+                encoder
+                    .write_all(&(encoded.len() as u32).to_le_bytes())
+                    .unwrap();
+                encoder.write_all(&encoded).unwrap();
+            }
+
+            let instr = entries[0].get_u64(crate::plog::INSTR_FIELD).unwrap();
+            directory.push((instr, file.len() as u64, entries.len() as u64));
+            file.extend(encoder.finish().unwrap());
+        }
+
+        let dir_pos = file.len() as u64;
+        file.extend(&(directory.len() as u32).to_le_bytes());
+        for (instr, pos, num_entries) in directory {
+            file.extend(&instr.to_le_bytes());
+            file.extend(&pos.to_le_bytes());
+            file.extend(&num_entries.to_le_bytes());
+        }
+
+        file[0..4].copy_from_slice(&PL_VERSION.to_le_bytes());
+        file[8..16].copy_from_slice(&dir_pos.to_le_bytes());
+
+        file
+    }
+
+    #[test]
+    fn test_read_roundtrip() {
+        let chunks = vec![
+            vec![entry(0, 0x1000), entry(5, 0x1004)],
+            vec![entry(10, 0x2000), entry(20, 0x2008), entry(30, 0x2010)],
+        ];
+
+        let reader = Reader::new(Cursor::new(pandalog(&chunks))).unwrap();
+        assert_eq!(reader.chunks().len(), 2);
+        assert_eq!(reader.chunks()[1].instr, 10);
+        assert_eq!(reader.chunks()[1].num_entries, 3);
+
+        let entries: Vec<Entry> = reader.map(Result::unwrap).collect();
+        let instrs: Vec<u64> = entries.iter().filter_map(Entry::instr).collect();
+        let pcs: Vec<u64> = entries.iter().filter_map(Entry::pc).collect();
+        assert_eq!(instrs, [0, 5, 10, 20, 30]);
+        assert_eq!(pcs, [0x1000, 0x1004, 0x2000, 0x2008, 0x2010]);
+    }
+
+    #[test]
+    fn test_seek() {
+        let chunks = vec![
+            vec![entry(0, 0x1000), entry(5, 0x1004)],
+            vec![entry(10, 0x2000), entry(20, 0x2008)],
+        ];
+
+        let mut reader = Reader::new(Cursor::new(pandalog(&chunks))).unwrap();
+        reader.seek(15);
+        let instrs: Vec<u64> = reader
+            .map(|entry| entry.unwrap().instr().unwrap())
+            .collect();
+        assert_eq!(instrs, [10, 20]);
+    }
+
+    #[test]
+    fn test_malformed_log() {
+        let mut unsupported = pandalog(&[vec![entry(0, 0)]]);
+        unsupported[0] = 1;
+        assert!(Reader::new(Cursor::new(unsupported)).is_err());
+
+        let mut unclosed = pandalog(&[vec![entry(0, 0)]]);
+        unclosed[8..16].copy_from_slice(&0u64.to_le_bytes());
+        assert!(Reader::new(Cursor::new(unclosed)).is_err());
+
+        // a huge chunk count shouldn't be allocated up front
+        let mut huge = pandalog(&[]);
+        let dir_pos = huge.len() - 4;
+        huge[dir_pos..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Reader::new(Cursor::new(huge)).is_err());
+    }
+
+    #[test]
+    fn test_truncated_entry() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&100u32.to_le_bytes()).unwrap();
+        encoder.write_all(&[0; 10]).unwrap();
+        let chunk = encoder.finish().unwrap();
+
+        let mut file = vec![0; HEADER_SIZE as usize];
+        file.extend(&chunk);
+        let dir_pos = file.len() as u64;
+        file.extend(&1u32.to_le_bytes());
+        file.extend(&0u64.to_le_bytes());
+        file.extend(&HEADER_SIZE.to_le_bytes());
+        file.extend(&u64::MAX.to_le_bytes());
+        file[0..4].copy_from_slice(&PL_VERSION.to_le_bytes());
+        file[8..16].copy_from_slice(&dir_pos.to_le_bytes());
+
+        let mut reader = Reader::new(Cursor::new(file)).unwrap();
+        assert!(reader.next().unwrap().is_err());
+    }
+}
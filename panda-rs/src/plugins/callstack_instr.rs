@@ -0,0 +1,54 @@
+//! Bindings for the PANDA 'callstack_instr' plugin, which keeps a shadow stack of the
+//! calls made by each guest thread.
+//!
+//! Used internally by [`alloc_sites`](crate::alloc_sites) to attribute allocations to
+//! the code which made them.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plugins::callstack_instr;
+//! use panda::prelude::*;
+//!
+//! #[panda::before_block_exec]
+//! fn every_block(cpu: &mut CPUState, tb: &mut TranslationBlock) {
+//!     let callers = callstack_instr::callers(cpu, 4);
+//!     println!("{:#x} called from {:#x?}", tb.pc, callers);
+//! }
+//! ```
+use crate::plugin_import;
+use crate::sys::{target_ulong, CPUState};
+
+plugin_import! {
+    static CALLSTACK_INSTR: CallstackInstr = extern "callstack_instr" {
+        fn get_callers(callers: *mut target_ulong, n: u32, cpu: *mut CPUState) -> u32;
+        fn get_functions(functions: *mut target_ulong, n: u32, cpu: *mut CPUState) -> u32;
+    };
+}
+
+/// Get up to `n` return addresses from the shadow stack of the current thread, starting
+/// with the innermost call
+pub fn callers(cpu: &mut CPUState, n: usize) -> Vec<target_ulong> {
+    let mut callers = vec![0; n];
+    let len = CALLSTACK_INSTR.get_callers(callers.as_mut_ptr(), n as u32, cpu);
+    callers.truncate(len as usize);
+
+    callers
+}
+
+/// Get up to `n` addresses of the functions on the shadow stack of the current thread,
+/// starting with the innermost function
+pub fn functions(cpu: &mut CPUState, n: usize) -> Vec<target_ulong> {
+    let mut functions = vec![0; n];
+    let len = CALLSTACK_INSTR.get_functions(functions.as_mut_ptr(), n as u32, cpu);
+    functions.truncate(len as usize);
+
+    functions
+}
+
+/// Load the plugin, if it isn't already. The shadow stack only includes calls made
+/// after the plugin is loaded, so this should be called during initialization when
+/// callers will be needed later.
+pub fn ensure_loaded() {
+    CALLSTACK_INSTR.ensure_init();
+}
@@ -5,7 +5,8 @@
 //! the Volatility and greater memory forensics communities but in a dynamic analysis
 //! setting.
 //!
-//! See [`OsiType`] and [`osi_static`] for high-level usage.
+//! See [`OsiType`] and [`osi_static`] for high-level usage, [`profile`] for choosing
+//! the profile at runtime, and [`windows`] for helpers specific to Windows guests.
 //!
 //! [`OsiType`]: macro@panda::plugins::cosi::OsiType
 //! [`osi_static`]: panda::plugins::cosi::osi_static
@@ -14,11 +15,23 @@ use crate::plugin_import;
 use crate::prelude::*;
 use crate::GuestReadFail;
 
+use once_cell::sync::OnceCell;
+
 use std::ffi::{CStr, CString};
+use std::fmt;
+use std::ops::{Deref, Range};
 use std::os::raw::c_char;
+use std::sync::{Mutex, RwLockReadGuard};
 
+mod list;
 mod osi_statics;
-pub use osi_statics::*;
+pub use {list::*, osi_statics::*};
+
+pub mod profile;
+
+#[cfg_attr(doc_cfg, doc(cfg(any(feature = "i386", feature = "x86_64"))))]
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+pub mod windows;
 
 #[doc(inline)]
 /// A macro for declaring global kernel data structures accessible via OSI2. The
@@ -81,6 +94,9 @@ pub use panda_macros::osi_static;
 /// | `type_name` |    Struct-Level    |    ✔️     | Sets the name of the type to pull info from within the volatility profile |
 /// |   `rename`  |    Field-Level     |          | By default the name of the field within the volatility profile will be assumed to be identical to the field within the Rust type, the `rename` attribute allows overriding this to have the volatility name and Rust field name be separate.
 /// |  `osi_type` |    Field-Level     |          | Treat as a nested [`OsiType`], not a [`GuestType`]
+/// |    `bits`   |    Field-Level     |          | Decode a range of bits (`#[osi(bits = "0..4")]`, end exclusive, bit 0 being the least significant) of the named field via [`FromBits`]. Multiple Rust fields may share a volatility field using `rename`.
+/// |   `flags`   |    Field-Level     |          | Decode the whole of the named field via [`FromBits`], such as a flags word decoded by a type deriving [`OsiFlags`]
+/// |    `list`   |    Field-Level     |          | Read a linked list head as an [`OsiList`] of entries, given the name of the member linking the entries (`#[osi(list = "sibling")]`). See [`iter_list`] for walking lists directly.
 ///
 /// ## Example
 ///
@@ -93,6 +109,32 @@ pub use panda_macros::osi_static;
 /// }
 /// ```
 ///
+/// Bitfields and flags words can be decoded into semantic values rather than raw words:
+///
+/// ```
+/// use panda::plugins::cosi::{OsiFlags, OsiType};
+///
+/// #[derive(OsiFlags, Debug)]
+/// struct VmFlags {
+///     #[flag(bit = 0)]
+///     read: bool,
+///     #[flag(bit = 1)]
+///     write: bool,
+///     #[flag(bit = 2)]
+///     exec: bool,
+/// }
+///
+/// #[derive(OsiType, Debug)]
+/// #[osi(type_name = "vm_area_struct")]
+/// struct VmAreaStruct {
+///     #[osi(flags)]
+///     vm_flags: VmFlags,
+///
+///     #[osi(rename = "vm_flags", bits = "0..3")]
+///     protection: u8,
+/// }
+/// ```
+///
 /// ## How it works
 ///
 /// OSI 2 is based around a system of using volatility 3 profiles (also known as "Symbol Tables")
@@ -134,6 +176,99 @@ pub use panda_macros::osi_static;
 /// fields of a given type.
 pub use panda_macros::OsiType;
 
+/// A derive macro for decoding a flags word into a struct of booleans, for use with
+/// the `bits` and `flags` attributes of [`OsiType`](macro@OsiType).
+///
+/// Each field must be a `bool` and have exactly one of the following attributes:
+///
+/// * `#[flag(bit = N)]` - the flag is set if bit `N` (0 being the least significant) is set
+/// * `#[flag(mask = M)]` - the flag is set if any bit of the mask `M` is set
+///
+/// ## Example
+///
+/// ```
+/// use panda::plugins::cosi::OsiFlags;
+///
+/// #[derive(OsiFlags, Debug)]
+/// struct TaskFlags {
+///     #[flag(mask = 0x4)]
+///     exiting: bool,
+///     #[flag(bit = 21)]
+///     kthread: bool,
+/// }
+/// ```
+pub use panda_macros::OsiFlags;
+
+/// A type which can be decoded from the bits of a kernel bitfield or flags word. The
+/// bits are shifted down such that the lowest bit of the range is bit 0.
+///
+/// Implemented for `bool` (true if any bit is set), integer types (truncating) and any
+/// type deriving [`OsiFlags`](macro@OsiFlags).
+pub trait FromBits: Sized {
+    fn from_bits(bits: u64) -> Self;
+}
+
+impl FromBits for bool {
+    fn from_bits(bits: u64) -> Self {
+        bits != 0
+    }
+}
+
+macro_rules! impl_from_bits {
+    ($($ty:ty),*) => {
+        $(
+            impl FromBits for $ty {
+                fn from_bits(bits: u64) -> Self {
+                    bits as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_from_bits!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+/// Read the bits of a field of an OSI type, sizing the read using the field's type in
+/// the volatility profile (defaulting to the size of a register if the type is not a
+/// base type).
+#[doc(hidden)]
+pub fn read_field_bits(
+    cpu: &mut CPUState,
+    vol_struct: &VolatilityStruct,
+    field: &str,
+    field_addr: target_ptr_t,
+    bits: Option<Range<u32>>,
+) -> Result<u64, GuestReadFail> {
+    let field_name = CString::new(field).unwrap();
+    let type_ptr = OSI2.type_of_field(vol_struct, field_name.as_ptr());
+
+    let size = if type_ptr.is_null() {
+        None
+    } else {
+        let type_name = unsafe { CStr::from_ptr(type_ptr) }
+            .to_string_lossy()
+            .into_owned();
+        OSI2.free_cosi_str(type_ptr);
+
+        base_type_from_name(&type_name).map(|base_type| base_type.size())
+    };
+
+    let raw = match size.unwrap_or(std::mem::size_of::<target_ulong>() as target_ptr_t) {
+        1 => read_guest_type::<u8>(cpu, field_addr)? as u64,
+        2 => read_guest_type::<u16>(cpu, field_addr)? as u64,
+        4 => read_guest_type::<u32>(cpu, field_addr)? as u64,
+        _ => read_guest_type::<u64>(cpu, field_addr)?,
+    };
+
+    Ok(match bits {
+        Some(bits) if bits.end - bits.start < 64 => {
+            (raw >> bits.start) & ((1 << (bits.end - bits.start)) - 1)
+        }
+        Some(bits) => raw >> bits.start,
+        None => raw,
+    })
+}
+
 plugin_import! {
     /// Raw bindings to the cosi plugin. It is not recommended to use these directly
     static OSI2: Osi2 = extern "cosi" {
@@ -386,36 +521,95 @@ impl VolatilityBaseType {
     }
 }
 
+/// A reference to an object within the profile currently loaded by OSI2, which keeps
+/// the profile from being switched (see [`profile::set_profile`]) while it is held
+pub struct ProfileRef<T: 'static> {
+    value: &'static T,
+    _loaded: RwLockReadGuard<'static, ()>,
+}
+
+impl<T: 'static> ProfileRef<T> {
+    fn new(lookup: impl FnOnce() -> Option<&'static T>) -> Option<Self> {
+        let loaded = profile::hold()?;
+
+        Some(Self {
+            value: lookup()?,
+            _loaded: loaded,
+        })
+    }
+}
+
+impl<T: 'static> Deref for ProfileRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: fmt::Debug + 'static> fmt::Debug for ProfileRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+/// A value looked up from the profile currently loaded by OSI2, cached until the
+/// profile is switched
+#[doc(hidden)]
+pub struct ProfileCache<T>(OnceCell<Mutex<Option<(u64, T)>>>);
+
+impl<T: Copy> ProfileCache<T> {
+    pub const fn new() -> Self {
+        Self(OnceCell::new())
+    }
+
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> T {
+        let generation = profile::generation();
+        let cached = self.0.get_or_init(|| Mutex::new(None));
+
+        if let Some((cached_generation, value)) = *cached.lock().unwrap() {
+            if cached_generation == generation {
+                return value;
+            }
+        }
+
+        let value = init();
+        *cached.lock().unwrap() = Some((generation, value));
+
+        value
+    }
+}
+
 /// Get a reference to an opaque object for accessing information about a given enum based
 /// on the volatility symbols currently loaded by OSI2
-pub fn enum_from_name(name: &str) -> Option<&'static VolatilityEnum> {
+pub fn enum_from_name(name: &str) -> Option<ProfileRef<VolatilityEnum>> {
     let name = CString::new(name).unwrap();
 
-    OSI2.enum_from_name(name.as_ptr())
+    ProfileRef::new(|| OSI2.enum_from_name(name.as_ptr()))
 }
 
 /// Get a reference to an opaque object for accessing information about a given base type
 /// from the volatility symbols currently loaded by OSI2
-pub fn base_type_from_name(name: &str) -> Option<&'static VolatilityBaseType> {
+pub fn base_type_from_name(name: &str) -> Option<ProfileRef<VolatilityBaseType>> {
     let name = CString::new(name).unwrap();
 
-    OSI2.base_type_from_name(name.as_ptr())
+    ProfileRef::new(|| OSI2.base_type_from_name(name.as_ptr()))
 }
 
 /// Get a reference to an opaque object for accessing information about a given symbol
 /// present in the volatility symbols currently loaded by OSI2
-pub fn symbol_from_name(name: &str) -> Option<&'static VolatilitySymbol> {
+pub fn symbol_from_name(name: &str) -> Option<ProfileRef<VolatilitySymbol>> {
     let name = CString::new(name).unwrap();
 
-    OSI2.symbol_from_name(name.as_ptr())
+    ProfileRef::new(|| OSI2.symbol_from_name(name.as_ptr()))
 }
 
 /// Get a reference to an opaque object for accessing information about a given type
 /// present in the volatility symbols currently loaded by OSI2
-pub fn type_from_name(name: &str) -> Option<&'static VolatilityStruct> {
+pub fn type_from_name(name: &str) -> Option<ProfileRef<VolatilityStruct>> {
     let name = CString::new(name).unwrap();
 
-    OSI2.type_from_name(name.as_ptr())
+    ProfileRef::new(|| OSI2.type_from_name(name.as_ptr()))
 }
 
 /// Get the symbol address of a type including the KASLR base offset from the volatility profile
@@ -443,7 +637,21 @@ pub fn kaslr_offset(cpu: &mut CPUState) -> target_ptr_t {
 
 /// Get the current per-CPU offset for kernel data structures such as the current task
 /// struct
+///
+/// On x86_64 the per-CPU offset is the kernel's GS base, which is read in a way that
+/// accounts for `swapgs` (see [`kernel_gs_base`]) so that the offset is correct even
+/// when called while the CPU is in user mode.
+///
+/// [`kernel_gs_base`]: crate::segment::kernel_gs_base
 pub fn current_cpu_offset(cpu: &mut CPUState) -> target_ulong {
+    #[cfg(feature = "x86_64")]
+    {
+        let gs_base = crate::segment::kernel_gs_base(cpu);
+        if gs_base != 0 {
+            return gs_base;
+        }
+    }
+
     OSI2.current_cpu_offset(cpu)
 }
 
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::guest_ptr::GuestReadFail;
+use crate::mem::read_guest_type;
+use crate::prelude::*;
+use crate::GuestType;
+
+use super::{type_from_name, OsiType};
+
+/// The most entries a list is walked for before it is assumed to be corrupt, unless
+/// changed with [`ListAddrs::limit`]
+pub const MAX_LIST_LEN: usize = 0x10000;
+
+/// A kernel doubly-linked list node (`struct list_head` on Linux, `_LIST_ENTRY` on
+/// Windows), embedded within each entry of the list
+#[derive(GuestType, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ListHead {
+    pub next: target_ptr_t,
+    pub prev: target_ptr_t,
+}
+
+/// An iterator over the addresses of the entries of a kernel linked list, created by
+/// [`list_addrs`]
+///
+/// Iteration ends when the list loops back to its head. If the list is corrupt (a
+/// node can't be read, is null, is visited twice or the list is longer than the limit)
+/// a single error is yielded and iteration ends. Null nodes can instead be treated as
+/// the end of the list with [`ListAddrs::null_terminated`].
+pub struct ListAddrs<'a> {
+    cpu: &'a mut CPUState,
+    head: target_ptr_t,
+    next: Result<target_ptr_t, GuestReadFail>,
+    member_offset: target_ptr_t,
+    seen: HashSet<target_ptr_t>,
+    limit: usize,
+    null_terminated: bool,
+    done: bool,
+}
+
+impl<'a> ListAddrs<'a> {
+    /// Set the most entries to walk before the list is assumed to be corrupt
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// End iteration at a null node rather than treating it as corrupt, such as for
+    /// lists which haven't been initialized yet
+    pub fn null_terminated(mut self) -> Self {
+        self.null_terminated = true;
+        self
+    }
+
+    fn fail(&mut self) -> Option<Result<target_ptr_t, GuestReadFail>> {
+        self.done = true;
+
+        Some(Err(GuestReadFail))
+    }
+}
+
+impl Iterator for ListAddrs<'_> {
+    type Item = Result<target_ptr_t, GuestReadFail>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let node = match self.next {
+            Ok(node) if node == self.head || (node == 0 && self.null_terminated) => {
+                self.done = true;
+                return None;
+            }
+            Ok(node) => node,
+            Err(_) => return self.fail(),
+        };
+
+        if node == 0 || self.seen.len() >= self.limit || !self.seen.insert(node) {
+            return self.fail();
+        }
+
+        // `next` is the first field of the node
+        self.next = read_guest_type(self.cpu, node);
+
+        Some(Ok(node.wrapping_sub(self.member_offset)))
+    }
+}
+
+/// Iterate over the addresses of the entries of a kernel linked list
+///
+/// `head_ptr` is the address of the list head, and `offset_of_member` the offset of
+/// the node within each entry. As in the kernel's `list_for_each_entry`, the head
+/// itself is not treated as an entry, so when the head is embedded in an entry of the
+/// same type (such as `init_task.tasks`) that entry is skipped.
+pub fn list_addrs(
+    cpu: &mut CPUState,
+    head_ptr: target_ptr_t,
+    offset_of_member: target_ptr_t,
+) -> ListAddrs<'_> {
+    let next = read_guest_type(cpu, head_ptr);
+
+    ListAddrs {
+        cpu,
+        head: head_ptr,
+        next,
+        member_offset: offset_of_member,
+        seen: HashSet::new(),
+        limit: MAX_LIST_LEN,
+        null_terminated: false,
+        done: false,
+    }
+}
+
+/// An iterator over the entries of a kernel linked list, created by [`iter_list`]
+pub struct ListIter<'a, T: OsiType> {
+    addrs: ListAddrs<'a>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: OsiType> ListIter<'a, T> {
+    /// Set the most entries to walk before the list is assumed to be corrupt
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.addrs = self.addrs.limit(limit);
+        self
+    }
+}
+
+impl<T: OsiType> Iterator for ListIter<'_, T> {
+    type Item = Result<T, GuestReadFail>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let addr = self.addrs.next()?;
+
+        Some(addr.and_then(|addr| T::osi_read(self.addrs.cpu, addr)))
+    }
+}
+
+/// Iterate over the entries of a kernel linked list, reading each as a `T`. See
+/// [`list_addrs`] for the meaning of the arguments and how corrupt lists are handled.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::plugins::cosi::{self, iter_list, OsiType};
+/// use panda::prelude::*;
+///
+/// #[derive(OsiType, Debug)]
+/// #[osi(type_name = "task_struct")]
+/// struct TaskStruct {
+///     comm: [u8; 0x10],
+/// }
+///
+/// # let cpu = unsafe { &mut *panda::sys::get_cpu() };
+/// let init_task = cosi::symbol_addr_from_name("init_task");
+/// let tasks = cosi::type_from_name("task_struct").unwrap().offset_of("tasks") as target_ptr_t;
+///
+/// for task in iter_list::<TaskStruct>(cpu, init_task + tasks, tasks) {
+///     println!("{:?}", task);
+/// }
+/// ```
+pub fn iter_list<T: OsiType>(
+    cpu: &mut CPUState,
+    head_ptr: target_ptr_t,
+    offset_of_member: target_ptr_t,
+) -> ListIter<'_, T> {
+    ListIter {
+        addrs: list_addrs(cpu, head_ptr, offset_of_member),
+        _marker: PhantomData,
+    }
+}
+
+/// A linked list field of an [`OsiType`](macro@super::OsiType), marked with
+/// `#[osi(list = "member")]` where `member` is the name of the node within the entries
+/// of the list.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::plugins::cosi::{OsiList, OsiType};
+///
+/// #[derive(OsiType)]
+/// #[osi(type_name = "task_struct")]
+/// struct TaskStruct {
+///     comm: [u8; 0x10],
+///
+///     // the children of a task are linked by their `sibling` member
+///     #[osi(list = "sibling")]
+///     children: OsiList<TaskStruct>,
+/// }
+///
+/// # let cpu = unsafe { &mut *panda::sys::get_cpu() };
+/// # let task: TaskStruct = todo!();
+/// for child in task.children.iter(cpu) {
+///     println!("{:?}", child.unwrap().comm);
+/// }
+/// ```
+pub struct OsiList<T: OsiType> {
+    /// The address of the list head
+    pub head: target_ptr_t,
+
+    /// The list head itself
+    pub link: ListHead,
+
+    member_offset: target_ptr_t,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: OsiType> OsiList<T> {
+    /// Read the list head at `head`, where `member` is the name of the node within
+    /// each entry. Used by the `OsiType` derive.
+    #[doc(hidden)]
+    pub fn read_at(
+        cpu: &mut CPUState,
+        head: target_ptr_t,
+        member: &str,
+    ) -> Result<Self, GuestReadFail> {
+        let type_name = T::osi_type_name().ok_or(GuestReadFail)?;
+        let entry_type = type_from_name(type_name).ok_or(GuestReadFail)?;
+
+        Ok(Self {
+            head,
+            link: ListHead::read_from_guest(cpu, head)?,
+            member_offset: entry_type.offset_of(member) as target_ptr_t,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Check whether the list has no entries
+    pub fn is_empty(&self) -> bool {
+        self.link.next == self.head
+    }
+
+    /// Iterate over the addresses of the entries of the list
+    pub fn addrs<'a>(&self, cpu: &'a mut CPUState) -> ListAddrs<'a> {
+        list_addrs(cpu, self.head, self.member_offset)
+    }
+
+    /// Iterate over the entries of the list
+    pub fn iter<'a>(&self, cpu: &'a mut CPUState) -> ListIter<'a, T> {
+        iter_list(cpu, self.head, self.member_offset)
+    }
+}
+
+impl<T: OsiType> Clone for OsiList<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: OsiType> Copy for OsiList<T> {}
+
+impl<T: OsiType> fmt::Debug for OsiList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OsiList")
+            .field("head", &format_args!("{:#x}", self.head))
+            .field("next", &format_args!("{:#x}", self.link.next))
+            .field("prev", &format_args!("{:#x}", self.link.prev))
+            .finish()
+    }
+}
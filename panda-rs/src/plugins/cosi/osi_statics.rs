@@ -12,6 +12,12 @@ use super::{find_per_cpu_address, symbol_addr_from_name};
 pub trait OsiType: Sized {
     type MethodDispatcher;
 
+    /// The name of the type within the volatility profile, if it has one. Types with a
+    /// fixed layout do not.
+    fn osi_type_name() -> Option<&'static str> {
+        None
+    }
+
     /// Read the given type out of memory starting at `base_ptr`
     fn osi_read(cpu: &mut CPUState, base_ptr: target_ptr_t) -> Result<Self, GuestReadFail>;
 }
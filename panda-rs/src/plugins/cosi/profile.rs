@@ -0,0 +1,280 @@
+//! Choosing the Volatility profile cosi introspects the guest with
+//!
+//! cosi reads its profile from its `profile` argument when it is loaded. PANDA only
+//! uses the first value given for a plugin argument, so rather than passing the path of
+//! each profile, the profile is placed at a path managed by this module which stays the
+//! same for the life of the process, and switching profiles replaces it and reloads
+//! cosi. This allows tools which analyze guests running different kernels in one
+//! process (such as in libpanda mode) to switch between them.
+//!
+//! cosi must not also be given a `profile` argument by other means, as PANDA would
+//! use that one instead.
+//!
+//! Types and symbols looked up from the profile (such as with
+//! [`type_from_name`](super::type_from_name)) keep it loaded while they are held, so
+//! the profile can't be switched until they have all been dropped.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plugins::cosi::profile::{self, CosiArgs, Profile};
+//! use panda::prelude::*;
+//!
+//! Panda::new()
+//!     .generic("x86_64")
+//!     .replay("ubuntu_boot")
+//!     .plugin_args(&CosiArgs::new(Profile::path("ubuntu-4.15.json.xz")).unwrap())
+//!     .run();
+//!
+//! // later, such as before running a replay of a different guest
+//! profile::set_profile(Profile::path("ubuntu-5.4.json.xz")).unwrap();
+//! ```
+use super::OSI2;
+use crate::prelude::*;
+use crate::sys::panda_add_arg;
+
+use std::ffi::CString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock, RwLockReadGuard};
+
+use tempfile::TempDir;
+
+/// A Volatility profile to load into cosi
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Profile {
+    /// A profile on disk, either JSON or xz-compressed JSON (ending in `.xz`)
+    Path(PathBuf),
+
+    /// The JSON of a profile, such as one generated or downloaded at runtime
+    Json(String),
+}
+
+impl Profile {
+    /// A profile on disk, either JSON or xz-compressed JSON (ending in `.xz`)
+    pub fn path(path: impl Into<PathBuf>) -> Self {
+        Profile::Path(path.into())
+    }
+
+    /// A profile given as JSON
+    pub fn json(json: impl Into<String>) -> Self {
+        Profile::Json(json.into())
+    }
+
+    fn format(&self) -> Format {
+        match self {
+            Profile::Path(path) if path.extension().map_or(false, |ext| ext == "xz") => {
+                Format::JsonXz
+            }
+            _ => Format::Json,
+        }
+    }
+}
+
+impl From<PathBuf> for Profile {
+    fn from(path: PathBuf) -> Self {
+        Profile::Path(path)
+    }
+}
+
+impl From<&Path> for Profile {
+    fn from(path: &Path) -> Self {
+        Profile::Path(path.to_owned())
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Profile::Path(path) => write!(f, "{}", path.display()),
+            Profile::Json(_) => f.write_str("<in-memory profile>"),
+        }
+    }
+}
+
+/// How a profile is stored, which cosi picks between using the extension of its path
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Json,
+    JsonXz,
+}
+
+impl Format {
+    fn file_name(self) -> &'static str {
+        match self {
+            Format::Json => "profile.json",
+            Format::JsonXz => "profile.json.xz",
+        }
+    }
+}
+
+/// An error in loading a profile into cosi
+#[derive(thiserror::Error, Debug)]
+pub enum ProfileError {
+    #[error("profile {0} does not exist")]
+    NotFound(PathBuf),
+
+    #[error(
+        "cosi was first given a {} profile, so can't switch to {profile}",
+        if *.compressed { "compressed" } else { "uncompressed" }
+    )]
+    FormatChanged { compressed: bool, profile: String },
+
+    #[error("types or symbols from the current profile are still in use")]
+    InUse,
+
+    #[error("failed to install profile: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The arguments to load cosi with, giving it a profile. Pass to
+/// [`Panda::plugin_args`](crate::Panda::plugin_args) to load cosi with a profile from
+/// the start in libpanda mode.
+#[derive(PandaArgs)]
+#[name = "cosi"]
+pub struct CosiArgs {
+    profile: String,
+}
+
+impl CosiArgs {
+    /// Arguments loading cosi with the given profile, which can later be switched with
+    /// [`set_profile`]
+    pub fn new(profile: Profile) -> Result<Self, ProfileError> {
+        let path = install(&profile)?;
+        STATE.lock().unwrap().arg_added = true;
+
+        Ok(Self {
+            profile: path.display().to_string(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// The format cosi was first given, which fixes the path it loads from
+    format: Option<Format>,
+
+    /// The profile currently installed
+    current: Option<Profile>,
+
+    /// Whether the `profile` argument has been passed to PANDA
+    arg_added: bool,
+
+    /// The directory profiles are installed to, created on first use
+    dir: Option<TempDir>,
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+
+    /// Held for reading by everything borrowed from the loaded profile, and for writing
+    /// while switching profiles
+    static ref LOADED: RwLock<()> = RwLock::new(());
+}
+
+/// The number of times the profile has been switched
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Keep the current profile loaded until the returned guard is dropped, or return
+/// `None` if it is being switched
+pub(super) fn hold() -> Option<RwLockReadGuard<'static, ()>> {
+    // never wait, as the profile is only switched while nothing is borrowed from it
+    LOADED.try_read().ok()
+}
+
+/// Get the number of times the profile has been switched, which changes whenever
+/// values looked up from the previous profile become invalid
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// Place the profile at the path cosi loads from, returning that path
+fn install(profile: &Profile) -> Result<PathBuf, ProfileError> {
+    let mut state = STATE.lock().unwrap();
+
+    let format = *state.format.get_or_insert(profile.format());
+    if format != profile.format() {
+        return Err(ProfileError::FormatChanged {
+            compressed: format == Format::JsonXz,
+            profile: profile.to_string(),
+        });
+    }
+
+    if state.dir.is_none() {
+        let dir = tempfile::Builder::new()
+            .prefix("panda-rs-cosi-")
+            .tempdir()?;
+        state.dir = Some(dir);
+    }
+
+    let path = state.dir.as_ref().unwrap().path().join(format.file_name());
+    if path.symlink_metadata().is_ok() {
+        std::fs::remove_file(&path)?;
+    }
+
+    match profile {
+        Profile::Path(source) => {
+            let source = source
+                .canonicalize()
+                .map_err(|_| ProfileError::NotFound(source.clone()))?;
+
+            std::os::unix::fs::symlink(source, &path)?;
+        }
+        Profile::Json(json) => std::fs::write(&path, json)?,
+    }
+
+    state.current = Some(profile.clone());
+
+    Ok(path)
+}
+
+/// Load a profile into cosi, loading cosi if it isn't loaded already or reloading it
+/// with the new profile if it is
+///
+/// Returns [`ProfileError::InUse`] if anything borrowed from the current profile, such
+/// as a [`VolatilityStruct`](super::VolatilityStruct), is still held. Values cached
+/// from the previous profile outside of cosi's bindings, such as the
+/// [`kernel_version`](crate::plugins::osi::kernel_version), are not refreshed. This
+/// should be called when the guest isn't being introspected, such as between replays.
+pub fn set_profile(profile: Profile) -> Result<(), ProfileError> {
+    let _switching = LOADED.try_write().map_err(|_| ProfileError::InUse)?;
+    let path = install(&profile)?;
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+
+    let first_load = {
+        let mut state = STATE.lock().unwrap();
+
+        !std::mem::replace(&mut state.arg_added, true)
+    };
+
+    if first_load {
+        let plugin = CString::new(CosiArgs::PLUGIN_NAME).unwrap();
+        let arg = CString::new(format!("profile={}", path.display())).unwrap();
+
+        unsafe {
+            panda_add_arg(plugin.as_ptr(), arg.as_ptr());
+        }
+
+        OSI2.ensure_init();
+    } else {
+        OSI2.reload();
+    }
+
+    Ok(())
+}
+
+/// Load a profile from disk into cosi, see [`set_profile`]
+pub fn set_profile_path(path: impl AsRef<Path>) -> Result<(), ProfileError> {
+    set_profile(Profile::path(path.as_ref()))
+}
+
+/// Load a profile from its JSON into cosi, see [`set_profile`]
+pub fn set_profile_json(json: impl Into<String>) -> Result<(), ProfileError> {
+    set_profile(Profile::json(json))
+}
+
+/// Get the profile most recently given to [`set_profile`] or [`CosiArgs::new`], if any
+pub fn current_profile() -> Option<Profile> {
+    STATE.lock().unwrap().current.clone()
+}
@@ -0,0 +1,352 @@
+//! Helpers for introspecting Windows guests using OSI2
+//!
+//! Windows keeps its per-CPU state in the processor control region (`_KPCR`), which is
+//! found through the GS base on 64-bit Windows and the FS base on 32-bit Windows. From
+//! there the current thread (`_KTHREAD`) and the process it is running in
+//! (`_EPROCESS`) can be found, and the kernel's linked lists can be walked to enumerate
+//! processes, their threads and their loaded modules.
+//!
+//! All types and offsets are pulled from the loaded Volatility profile, so these
+//! helpers require a Windows profile to be loaded into OSI2 (for example one generated
+//! from the guest kernel's PDB).
+//!
+//! [`EProcessFields`] is an [`OsiType`](macro@super::OsiType) for `_EPROCESS`, and can
+//! be used as a starting point for declaring other Windows kernel types.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plugins::cosi::windows;
+//! use panda::prelude::*;
+//!
+//! #[panda::asid_changed]
+//! fn asid_changed(cpu: &mut CPUState, _: target_ulong, _: target_ulong) -> bool {
+//!     if let Ok(process) = windows::current_process(cpu) {
+//!         println!("switched to {} ({})", process.name(), process.pid);
+//!
+//!         for module in windows::modules(cpu, process.addr).unwrap_or_default() {
+//!             println!("    {:#x} {}", module.base, module.name);
+//!         }
+//!     }
+//!
+//!     false
+//! }
+//! ```
+use super::{list_addrs, symbol_addr_from_name, symbol_from_name, type_from_name, OsiType};
+use crate::mem::{read_guest_type, virtual_memory_read};
+use crate::prelude::*;
+use crate::GuestReadFail;
+
+/// The field of `_KPCR` holding the `_KPRCB`
+#[cfg(feature = "x86_64")]
+const KPCR_PRCB: &str = "Prcb";
+
+#[cfg(feature = "i386")]
+const KPCR_PRCB: &str = "PrcbData";
+
+/// The field of `_KPCR` pointing to itself
+#[cfg(feature = "x86_64")]
+const KPCR_SELF: &str = "Self";
+
+#[cfg(feature = "i386")]
+const KPCR_SELF: &str = "SelfPcr";
+
+/// An error encountered while introspecting a Windows guest
+#[derive(thiserror::Error, Debug)]
+pub enum WindowsError {
+    #[error("{0} not found, is cosi loaded with a Windows volatility profile?")]
+    MissingType(&'static str),
+
+    #[error("{0}.{1} not found in the volatility profile")]
+    MissingField(&'static str, &'static str),
+
+    #[error("symbol {0} not found in the volatility profile")]
+    MissingSymbol(&'static str),
+
+    #[error("the processor control region of the current CPU could not be found")]
+    NoKpcr,
+
+    #[error("failed to read from guest memory")]
+    ReadFailed,
+}
+
+impl From<GuestReadFail> for WindowsError {
+    fn from(_: GuestReadFail) -> Self {
+        Self::ReadFailed
+    }
+}
+
+/// The commonly used fields of an `_EPROCESS`, the kernel's representation of a
+/// process. See [`EProcess`] for these along with the address they were read from.
+#[derive(OsiType, Debug, Clone)]
+#[osi(type_name = "_EPROCESS")]
+pub struct EProcessFields {
+    #[osi(rename = "UniqueProcessId")]
+    pub pid: target_ptr_t,
+
+    #[osi(rename = "InheritedFromUniqueProcessId")]
+    pub ppid: target_ptr_t,
+
+    /// The first 15 bytes of the name of the executable, NUL-terminated if shorter
+    #[osi(rename = "ImageFileName")]
+    pub image_file_name: [u8; 15],
+
+    /// The user-mode process environment block (`_PEB`)
+    #[osi(rename = "Peb")]
+    pub peb: target_ptr_t,
+}
+
+/// A process in a Windows guest, along with the address of its `_EPROCESS`
+#[derive(Debug, Clone)]
+pub struct EProcess {
+    /// The address of the `_EPROCESS`
+    pub addr: target_ptr_t,
+    pub pid: target_ptr_t,
+    pub ppid: target_ptr_t,
+    pub image_file_name: [u8; 15],
+    pub peb: target_ptr_t,
+}
+
+impl EProcess {
+    /// Read the `_EPROCESS` at the given address
+    pub fn read(cpu: &mut CPUState, addr: target_ptr_t) -> Result<Self, GuestReadFail> {
+        let fields = EProcessFields::osi_read(cpu, addr)?;
+
+        Ok(Self {
+            addr,
+            pid: fields.pid,
+            ppid: fields.ppid,
+            image_file_name: fields.image_file_name,
+            peb: fields.peb,
+        })
+    }
+
+    /// The name of the process's executable, truncated to 15 bytes by the kernel
+    pub fn name(&self) -> String {
+        let len = self
+            .image_file_name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.image_file_name.len());
+
+        String::from_utf8_lossy(&self.image_file_name[..len]).into_owned()
+    }
+}
+
+/// A thread in a Windows guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EThread {
+    /// The address of the `_ETHREAD`
+    pub addr: target_ptr_t,
+    pub tid: target_ptr_t,
+
+    /// The process the thread belongs to
+    pub pid: target_ptr_t,
+}
+
+/// A module (executable or DLL) loaded by a process, or a driver loaded by the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// The address of the `_LDR_DATA_TABLE_ENTRY`
+    pub addr: target_ptr_t,
+    pub base: target_ptr_t,
+    pub size: target_ptr_t,
+    pub name: String,
+    pub path: String,
+}
+
+/// Get the offset of a field from the loaded profile
+fn offset_of(type_name: &'static str, field: &'static str) -> Result<target_ptr_t, WindowsError> {
+    let ty = type_from_name(type_name).ok_or(WindowsError::MissingType(type_name))?;
+
+    ty.fields()
+        .find(|(name, _)| name == field)
+        .map(|(_, offset)| offset)
+        .ok_or(WindowsError::MissingField(type_name, field))
+}
+
+/// Get the address of a symbol from the loaded profile, including the KASLR offset
+fn symbol_addr(name: &'static str) -> Result<target_ptr_t, WindowsError> {
+    symbol_from_name(name).ok_or(WindowsError::MissingSymbol(name))?;
+
+    Ok(symbol_addr_from_name(name))
+}
+
+fn read_ptr(cpu: &mut CPUState, addr: target_ptr_t) -> Result<target_ptr_t, WindowsError> {
+    Ok(read_guest_type(cpu, addr)?)
+}
+
+/// Get the address of the processor control region (`_KPCR`) of the current CPU
+///
+/// On 64-bit Windows this is the kernel's GS base, accounting for `swapgs` (see
+/// [`kernel_gs_base`](crate::segment::kernel_gs_base)). On 32-bit Windows it is the FS
+/// base while in kernel mode, and is otherwise found through `KiProcessorBlock`.
+pub fn kpcr(cpu: &mut CPUState) -> Result<target_ptr_t, WindowsError> {
+    #[cfg(feature = "x86_64")]
+    let kpcr = crate::segment::kernel_gs_base(cpu) as target_ptr_t;
+
+    #[cfg(feature = "i386")]
+    let kpcr = {
+        use crate::segment::{current_privilege_level, get_segment_base, SegReg};
+
+        if current_privilege_level(cpu) == 0 {
+            get_segment_base(cpu, SegReg::FS) as target_ptr_t
+        } else {
+            let ptr_size = std::mem::size_of::<target_ptr_t>() as target_ptr_t;
+            let blocks = symbol_addr("KiProcessorBlock")?;
+            let prcb = read_ptr(cpu, blocks + cpu.index() as target_ptr_t * ptr_size)?;
+
+            prcb.checked_sub(offset_of("_KPCR", KPCR_PRCB)?)
+                .ok_or(WindowsError::NoKpcr)?
+        }
+    };
+
+    // the self pointer makes sure this is really the KPCR and not, for example, the TEB
+    if kpcr == 0 || read_ptr(cpu, kpcr + offset_of("_KPCR", KPCR_SELF)?)? != kpcr {
+        return Err(WindowsError::NoKpcr);
+    }
+
+    Ok(kpcr)
+}
+
+/// Get the address of the processor control block (`_KPRCB`) of the current CPU
+pub fn kprcb(cpu: &mut CPUState) -> Result<target_ptr_t, WindowsError> {
+    Ok(kpcr(cpu)? + offset_of("_KPCR", KPCR_PRCB)?)
+}
+
+/// Get the address of the `_KTHREAD` (the start of the `_ETHREAD`) of the thread
+/// running on the current CPU
+pub fn current_thread(cpu: &mut CPUState) -> Result<target_ptr_t, WindowsError> {
+    let prcb = kprcb(cpu)?;
+
+    read_ptr(cpu, prcb + offset_of("_KPRCB", "CurrentThread")?)
+}
+
+/// Get the address of the `_EPROCESS` of the process whose address space the current
+/// thread is running in
+///
+/// This is the process the thread is attached to, which is the thread's own process
+/// unless it has temporarily attached to another with `KeStackAttachProcess`.
+pub fn current_eprocess(cpu: &mut CPUState) -> Result<target_ptr_t, WindowsError> {
+    let thread = current_thread(cpu)?;
+    let apc_state = thread + offset_of("_KTHREAD", "ApcState")?;
+
+    read_ptr(cpu, apc_state + offset_of("_KAPC_STATE", "Process")?)
+}
+
+/// Read the process whose address space the current thread is running in
+pub fn current_process(cpu: &mut CPUState) -> Result<EProcess, WindowsError> {
+    let addr = current_eprocess(cpu)?;
+
+    Ok(EProcess::read(cpu, addr)?)
+}
+
+/// Walk a circular `_LIST_ENTRY` list starting at its head, returning the address of
+/// each entry's containing structure, given the offset of the `_LIST_ENTRY` within it.
+/// A null `Flink` ends the list, as lists such as the loader's are zeroed until they
+/// are initialized.
+fn walk_list(
+    cpu: &mut CPUState,
+    head: target_ptr_t,
+    entry_offset: target_ptr_t,
+) -> Result<Vec<target_ptr_t>, GuestReadFail> {
+    list_addrs(cpu, head, entry_offset)
+        .null_terminated()
+        .collect()
+}
+
+/// Iterate over the active processes of the guest, using `PsActiveProcessHead`
+pub fn processes(cpu: &mut CPUState) -> Result<Vec<EProcess>, WindowsError> {
+    let head = symbol_addr("PsActiveProcessHead")?;
+    let links = offset_of("_EPROCESS", "ActiveProcessLinks")?;
+
+    walk_list(cpu, head, links)?
+        .into_iter()
+        .map(|addr| Ok(EProcess::read(cpu, addr)?))
+        .collect()
+}
+
+/// Iterate over the threads of the process whose `_EPROCESS` is at the given address
+pub fn threads(cpu: &mut CPUState, eprocess: target_ptr_t) -> Result<Vec<EThread>, WindowsError> {
+    let head = eprocess + offset_of("_EPROCESS", "ThreadListHead")?;
+    let links = offset_of("_ETHREAD", "ThreadListEntry")?;
+    let cid = offset_of("_ETHREAD", "Cid")?;
+    let unique_process = offset_of("_CLIENT_ID", "UniqueProcess")?;
+    let unique_thread = offset_of("_CLIENT_ID", "UniqueThread")?;
+
+    walk_list(cpu, head, links)?
+        .into_iter()
+        .map(|addr| {
+            Ok(EThread {
+                addr,
+                tid: read_ptr(cpu, addr + cid + unique_thread)?,
+                pid: read_ptr(cpu, addr + cid + unique_process)?,
+            })
+        })
+        .collect()
+}
+
+/// Read a `_UNICODE_STRING`, replacing any invalid UTF-16
+fn read_unicode_string(cpu: &mut CPUState, addr: target_ptr_t) -> Result<String, WindowsError> {
+    let len: u16 = read_guest_type(cpu, addr + offset_of("_UNICODE_STRING", "Length")?)?;
+    let buffer = read_ptr(cpu, addr + offset_of("_UNICODE_STRING", "Buffer")?)?;
+
+    if len == 0 || buffer == 0 {
+        return Ok(String::new());
+    }
+
+    let bytes = virtual_memory_read(cpu, buffer as target_ulong, len as usize)
+        .map_err(|_| WindowsError::ReadFailed)?;
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+
+    Ok(String::from_utf16_lossy(&units))
+}
+
+/// Read the modules of a `_LDR_DATA_TABLE_ENTRY` list linked by `InLoadOrderLinks`
+fn read_modules(cpu: &mut CPUState, head: target_ptr_t) -> Result<Vec<Module>, WindowsError> {
+    let links = offset_of("_LDR_DATA_TABLE_ENTRY", "InLoadOrderLinks")?;
+    let dll_base = offset_of("_LDR_DATA_TABLE_ENTRY", "DllBase")?;
+    let size_of_image = offset_of("_LDR_DATA_TABLE_ENTRY", "SizeOfImage")?;
+    let base_dll_name = offset_of("_LDR_DATA_TABLE_ENTRY", "BaseDllName")?;
+    let full_dll_name = offset_of("_LDR_DATA_TABLE_ENTRY", "FullDllName")?;
+
+    walk_list(cpu, head, links)?
+        .into_iter()
+        .map(|addr| {
+            Ok(Module {
+                addr,
+                base: read_ptr(cpu, addr + dll_base)?,
+                size: read_guest_type::<u32>(cpu, addr + size_of_image)? as target_ptr_t,
+                name: read_unicode_string(cpu, addr + base_dll_name)?,
+                path: read_unicode_string(cpu, addr + full_dll_name)?,
+            })
+        })
+        .collect()
+}
+
+/// Iterate over the modules loaded by the process whose `_EPROCESS` is at the given
+/// address, in load order, using the loader data of its PEB
+///
+/// The PEB lives in user memory, so this only succeeds while the process's address
+/// space is the current one (such as for [`current_eprocess`]).
+pub fn modules(cpu: &mut CPUState, eprocess: target_ptr_t) -> Result<Vec<Module>, WindowsError> {
+    let peb = read_ptr(cpu, eprocess + offset_of("_EPROCESS", "Peb")?)?;
+    if peb == 0 {
+        // system processes have no user-mode modules
+        return Ok(Vec::new());
+    }
+
+    let ldr = read_ptr(cpu, peb + offset_of("_PEB", "Ldr")?)?;
+    let head = ldr + offset_of("_PEB_LDR_DATA", "InLoadOrderModuleList")?;
+
+    read_modules(cpu, head)
+}
+
+/// Iterate over the drivers loaded by the kernel, in load order, using
+/// `PsLoadedModuleList`
+pub fn kernel_modules(cpu: &mut CPUState) -> Result<Vec<Module>, WindowsError> {
+    read_modules(cpu, symbol_addr("PsLoadedModuleList")?)
+}
@@ -3,7 +3,8 @@
 //! The guest plugin manager is a PANDA plugin which manages "guest plugins", or programs
 //! which are injected into the guest and can communicate back to the host.
 //!
-//! See [`load_guest_plugin`] and [`channel_recv`] for more info.
+//! See [`load_guest_plugin`] and [`channel_recv`] for more info, or `TypedChannel` (with
+//! the `guest-channels` feature) for exchanging structured messages.
 use crate::plugin_import;
 
 use std::{
@@ -18,6 +19,13 @@ pub use guest_plugin::{load_guest_plugin, Channel, ChannelCB, ChannelId, GuestPl
 mod from_channel_msg;
 pub use from_channel_msg::FromChannelMessage;
 
+#[cfg(feature = "guest-channels")]
+mod typed_channel;
+
+#[cfg_attr(doc_cfg, doc(cfg(feature = "guest-channels")))]
+#[cfg(feature = "guest-channels")]
+pub use typed_channel::{ChannelError, TypedChannel, MAX_MESSAGE_SIZE};
+
 /// Allows declaring a callback for recieving messages from a channel
 ///
 /// Support functions with the signature `fn(u32, Msg)` where `u32` is the ID of the
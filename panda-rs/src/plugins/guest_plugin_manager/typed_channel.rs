@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{load_guest_plugin, Channel, ChannelId};
+
+/// The largest message which can be sent or received over a [`TypedChannel`]
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The size of the length prefix of each message
+const HEADER_SIZE: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChannelError {
+    #[error("Failed to encode message: {0}")]
+    Encode(#[source] serde_json::Error),
+
+    #[error("Failed to decode message: {0}")]
+    Decode(#[source] serde_json::Error),
+
+    #[error(
+        "Message of {0} bytes is larger than the maximum of {}",
+        MAX_MESSAGE_SIZE
+    )]
+    TooLarge(usize),
+}
+
+type MessageHandler = Box<dyn FnMut(Result<&[u8], ChannelError>) + Send + 'static>;
+
+/// The handler for messages on a typed channel, along with any partial message
+/// received so far
+struct Receiver {
+    buf: Vec<u8>,
+    handler: MessageHandler,
+}
+
+lazy_static::lazy_static! {
+    static ref RECEIVERS: Mutex<HashMap<ChannelId, Receiver>> = Mutex::new(HashMap::new());
+}
+
+/// Receive a write from the guest, splitting the data written so far into messages.
+/// Messages are prefixed with their length as a little endian `u32`.
+extern "C" fn receive(channel: ChannelId, data: *const u8, len: usize) {
+    if data.is_null() {
+        return;
+    }
+
+    let data = unsafe { std::slice::from_raw_parts(data, len) };
+
+    let mut receivers = RECEIVERS.lock().unwrap();
+    let receiver = match receivers.get_mut(&channel) {
+        Some(receiver) => receiver,
+        None => return,
+    };
+
+    receiver.buf.extend_from_slice(data);
+
+    while receiver.buf.len() >= HEADER_SIZE {
+        let mut header = [0; HEADER_SIZE];
+        header.copy_from_slice(&receiver.buf[..HEADER_SIZE]);
+        let msg_len = u32::from_le_bytes(header) as usize;
+
+        if msg_len > MAX_MESSAGE_SIZE {
+            // the stream can't be resynchronized, so drop everything received so far
+            receiver.buf.clear();
+            (receiver.handler)(Err(ChannelError::TooLarge(msg_len)));
+            break;
+        }
+
+        if receiver.buf.len() < HEADER_SIZE + msg_len {
+            break;
+        }
+
+        let msg: Vec<u8> = receiver
+            .buf
+            .drain(..HEADER_SIZE + msg_len)
+            .skip(HEADER_SIZE)
+            .collect();
+        (receiver.handler)(Ok(&msg));
+    }
+}
+
+/// A channel for exchanging messages of type `T` with a guest plugin, serialized as
+/// JSON and framed so that each message is received whole regardless of how the guest
+/// splits up its writes.
+///
+/// Each message is sent as its length, as a little endian `u32`, followed by the JSON
+/// encoding of the message. Guest plugins should use the same framing.
+///
+/// Messages are only received while the `TypedChannel` is alive, so it should be kept
+/// for as long as the guest plugin is in use.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::plugins::guest_plugin_manager::TypedChannel;
+/// use serde::{Deserialize, Serialize};
+///
+/// use std::sync::Mutex;
+///
+/// #[derive(Serialize, Deserialize)]
+/// enum Message {
+///     Ping(u32),
+///     Pong(u32),
+/// }
+///
+/// lazy_static::lazy_static! {
+///     static ref CHANNEL: Mutex<Option<TypedChannel<Message>>> = Mutex::new(None);
+/// }
+///
+/// #[panda::init]
+/// fn init() {
+///     let mut channel = TypedChannel::load_guest_plugin("my_guest_plugin", |msg| match msg {
+///         Ok(Message::Pong(n)) => println!("pong {}", n),
+///         Ok(_) => (),
+///         Err(err) => eprintln!("bad message from guest: {}", err),
+///     });
+///
+///     channel.send(&Message::Ping(1)).unwrap();
+///     *CHANNEL.lock().unwrap() = Some(channel);
+/// }
+/// ```
+pub struct TypedChannel<T> {
+    channel: Channel,
+    _message: PhantomData<fn(T) -> T>,
+}
+
+impl<T: Serialize + DeserializeOwned + 'static> TypedChannel<T> {
+    /// Load a guest plugin, calling `on_message` with each message received from it.
+    ///
+    /// `on_message` must not create or drop channels, as it is run while the channels
+    /// are locked.
+    pub fn load_guest_plugin(
+        name: impl Into<String>,
+        on_message: impl FnMut(Result<T, ChannelError>) + Send + 'static,
+    ) -> Self {
+        Self::register(load_guest_plugin(name, receive), on_message)
+    }
+
+    /// Create a new anonymous channel, calling `on_message` with each message received
+    pub fn new(on_message: impl FnMut(Result<T, ChannelError>) + Send + 'static) -> Self {
+        Self::register(Channel::new(receive), on_message)
+    }
+
+    fn register(
+        channel: Channel,
+        mut on_message: impl FnMut(Result<T, ChannelError>) + Send + 'static,
+    ) -> Self {
+        let handler = move |msg: Result<&[u8], ChannelError>| {
+            on_message(
+                msg.and_then(|msg| serde_json::from_slice(msg).map_err(ChannelError::Decode)),
+            );
+        };
+
+        RECEIVERS.lock().unwrap().insert(
+            channel.id(),
+            Receiver {
+                buf: Vec::new(),
+                handler: Box::new(handler),
+            },
+        );
+
+        TypedChannel {
+            channel,
+            _message: PhantomData,
+        }
+    }
+
+    /// Send a message to the guest, buffered until the guest next reads from the
+    /// channel
+    pub fn send(&mut self, msg: &T) -> Result<(), ChannelError> {
+        let payload = serde_json::to_vec(msg).map_err(ChannelError::Encode)?;
+        let len = u32::try_from(payload.len())
+            .ok()
+            .filter(|&len| len as usize <= MAX_MESSAGE_SIZE)
+            .ok_or(ChannelError::TooLarge(payload.len()))?;
+
+        let mut packet = Vec::with_capacity(HEADER_SIZE + payload.len());
+        packet.extend_from_slice(&len.to_le_bytes());
+        packet.extend_from_slice(&payload);
+        self.channel.write_packet(&packet);
+
+        Ok(())
+    }
+
+    /// Get the raw channel ID of this channel
+    pub fn id(&self) -> ChannelId {
+        self.channel.id()
+    }
+}
+
+impl<T> Drop for TypedChannel<T> {
+    /// Stop receiving messages on the channel, freeing its handler
+    fn drop(&mut self) {
+        RECEIVERS.lock().unwrap().remove(&self.channel.id());
+    }
+}
@@ -24,7 +24,7 @@
 //!     // when a process starts, hook the entrypoint
 //!     entry_hook::hook()
 //!         .after_block_exec()
-//!         .at_addr(auxv.entry)
+//!         .at_addr(auxv.entry);
 //! }
 //!
 //! Panda::new()
@@ -32,12 +32,22 @@
 //!     .replay("test")
 //!     .run();
 //! ```
+use std::any::Any;
+use std::collections::HashSet;
 use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
 
 use crate::plugin_import;
 use crate::prelude::*;
 use crate::sys::{self, panda_cb_type};
 
+mod handle;
+mod kernel_symbol;
+mod process;
+mod symbol;
+
+pub use handle::HookHandle;
+
 plugin_import! {
     static HOOKS: Hooks = extern "hooks" {
         fn add_hook(hook: &Hook);
@@ -142,7 +152,7 @@ pub mod hook {
                             $($arg_ty,)*
                             &mut Hook,
                         ) $( -> $ret_ty )? = unsafe {
-                            std::mem::transmute(hook.context)
+                            std::mem::transmute((*(hook.context as *mut HookContext)).callback)
                         };
 
                         callback($($arg, )* hook)
@@ -161,7 +171,12 @@ pub mod hook {
                             only_kernel: None,
                             enabled: true,
                             asid: None,
-                            context: cb as *mut _ as *mut _,
+                            process: None,
+                            context: HookContext {
+                                callback: cb as *mut _ as *mut _,
+                                state: None,
+                                handle: None,
+                            },
                         }
                     }
                 }
@@ -180,6 +195,30 @@ pub mod hook {
         fn before_block_translate(env: &mut CPUState, pc: target_ptr_t);
         fn before_block_exec_invalidate_opt(env: &mut CPUState, tb: &mut TranslationBlock) -> bool;
     }
+
+    /// Hook the start of a kernel function, looking up its address by name in the
+    /// volatility profile loaded by OSI2 (see [`cosi`](crate::plugins::cosi)).
+    ///
+    /// The symbol is resolved once the guest is executing kernel code, adjusting for
+    /// KASLR, and is resolved again whenever a snapshot is loaded, moving the hook if
+    /// the kernel has moved. The callback is run at the start of the block in kernel
+    /// mode only.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use panda::{hook, prelude::*};
+    ///
+    /// hook::kernel_symbol("do_sys_open", |_, _, _| {
+    ///     println!("do_sys_open called");
+    /// });
+    /// ```
+    pub fn kernel_symbol<CallbackFn>(name: &str, callback: CallbackFn)
+    where
+        CallbackFn: FnMut(&mut CPUState, &mut TranslationBlock, &mut Hook) + 'static,
+    {
+        kernel_symbol::add(name, Box::new(callback));
+    }
 }
 
 #[repr(u32)]
@@ -215,10 +254,86 @@ pub struct Hook {
     /// The symbol of the function to hook
     pub sym: Symbol,
 
-    /// User-provided context variable
+    /// User-provided context variable. For hooks created using [`HookBuilder`] this
+    /// points to data managed by the builder, such as the hook's state, and should not
+    /// be modified.
     pub context: *mut c_void,
 }
 
+impl Hook {
+    /// Get the state attached to this hook using [`HookBuilder::state`] (or the `state`
+    /// argument of [`#[panda::hook]`](macro@crate::hook)), if any. Returns `None` if
+    /// the hook has no state or its state is not of type `S`.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use panda::plugins::hooks::Hook;
+    /// use panda::prelude::*;
+    ///
+    /// #[panda::hook]
+    /// fn count_hits(_: &mut CPUState, _: &mut TranslationBlock, hook: &mut Hook) {
+    ///     let hits = hook.state::<u32>().unwrap();
+    ///     *hits += 1;
+    ///
+    ///     if *hits == 10 {
+    ///         hook.enabled = false;
+    ///     }
+    /// }
+    ///
+    /// count_hits::hook().state(0u32).at_addr(0x5555500ca);
+    /// ```
+    ///
+    /// The context of hooks not installed by a [`HookBuilder`] is never accessed, so
+    /// these hooks have no state.
+    pub fn state<S: 'static>(&mut self) -> Option<&mut S> {
+        // only contexts allocated by a builder are known to point to a `HookContext`
+        if !CONTEXTS.lock().unwrap().contains(&(self.context as usize)) {
+            return None;
+        }
+
+        let context = unsafe { &mut *(self.context as *mut HookContext) };
+
+        context.state.as_mut()?.downcast_mut()
+    }
+}
+
+/// The context of a hook installed by a [`HookBuilder`], holding the closure for hooks
+/// created using the [`hook`](mod@hook) module, any state attached to the hook and the
+/// state shared with its [`HookHandle`].
+struct HookContext {
+    callback: *mut c_void,
+    state: Option<Box<dyn Any>>,
+    handle: Option<Arc<handle::HandleState>>,
+}
+
+impl HookContext {
+    fn new() -> Self {
+        Self {
+            callback: std::ptr::null_mut(),
+            state: None,
+            handle: None,
+        }
+    }
+
+    fn into_raw(self) -> *mut c_void {
+        if self.callback.is_null() && self.state.is_none() && self.handle.is_none() {
+            std::ptr::null_mut()
+        } else {
+            let context = Box::into_raw(Box::new(self)) as *mut c_void;
+            CONTEXTS.lock().unwrap().insert(context as usize);
+
+            context
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    /// The addresses of every [`HookContext`] allocated by a builder, so that the
+    /// context of a [`Hook`] can be checked before being used as one
+    static ref CONTEXTS: Mutex<HashSet<usize>> = Mutex::new(HashSet::new());
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct SymbolHook {
@@ -245,7 +360,8 @@ impl IntoHookBuilder for NormalHookType {
             only_kernel: None,
             enabled: true,
             asid: None,
-            context: std::ptr::null_mut(),
+            process: None,
+            context: HookContext::new(),
         }
     }
 }
@@ -254,7 +370,7 @@ impl IntoHookBuilder for BeforeTranslateHook {
     type BuilderType = HookBuilderCallbackTypeNeeded<Self>;
 
     fn hook(self) -> Self::BuilderType {
-        HookBuilderCallbackTypeNeeded(self)
+        HookBuilderCallbackTypeNeeded(self, HookContext::new())
     }
 }
 
@@ -262,7 +378,7 @@ impl IntoHookBuilder for AfterBlockHook {
     type BuilderType = HookBuilderCallbackTypeNeeded<Self>;
 
     fn hook(self) -> Self::BuilderType {
-        HookBuilderCallbackTypeNeeded(self)
+        HookBuilderCallbackTypeNeeded(self, HookContext::new())
     }
 }
 
@@ -270,7 +386,7 @@ impl IntoHookBuilder for InvalidateOpHook {
     type BuilderType = HookBuilderCallbackTypeNeeded<Self>;
 
     fn hook(self) -> Self::BuilderType {
-        HookBuilderCallbackTypeNeeded(self)
+        HookBuilderCallbackTypeNeeded(self, HookContext::new())
     }
 }
 
@@ -281,7 +397,8 @@ pub struct HookBuilder<T> {
     only_kernel: Option<bool>,
     enabled: bool,
     asid: Option<target_ulong>,
-    context: *mut c_void,
+    process: Option<String>,
+    context: HookContext,
 }
 
 impl<T> HookBuilder<T> {
@@ -304,21 +421,130 @@ impl<T> HookBuilder<T> {
         self
     }
 
-    /// Installs the hook at a given address
-    pub fn at_addr(self, addr: target_ulong) {
-        HOOKS.add_hook(&Hook {
+    /// Only runs the hook while a process with the given name is running, as reported
+    /// by OSI. The process is looked up whenever the guest switches address spaces, so
+    /// this follows the process across restarts and `execve` without needing to know
+    /// its asid up front. Requires the `osi` plugin.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use panda::{hook, prelude::*};
+    ///
+    /// hook::before_block_exec(|_, _, _| {
+    ///     println!("bash hit the hook");
+    /// })
+    /// .for_process_name("bash")
+    /// .at_addr(0x5555500ca);
+    /// ```
+    pub fn for_process_name(mut self, name: impl Into<String>) -> Self {
+        self.process = Some(name.into());
+        self
+    }
+
+    /// Attaches state to the hook, accessible from the callback via [`Hook::state`].
+    /// Each installed hook gets its own state, allowing simple stateful hooks without
+    /// the need for global statics.
+    pub fn state<S: 'static>(mut self, state: S) -> Self {
+        self.context.state = Some(Box::new(state));
+        self
+    }
+
+    /// Installs the hook at a given address, returning a [`HookHandle`] which can be
+    /// used to enable, disable, move or remove the hook later on.
+    ///
+    /// On MIPS, a hook on a branch delay slot is never hit. Use `try_at_addr` to check
+    /// for this.
+    pub fn at_addr(self, addr: target_ulong) -> HookHandle {
+        let hook = Hook {
             addr,
             asid: self.asid.unwrap_or(0),
             enabled: self.enabled,
-            km: match self.only_kernel {
-                Some(true) => KernelMode::KernelOnly,
-                Some(false) => KernelMode::UserOnly,
-                None => KernelMode::Any,
-            },
+            km: self.kernel_mode(),
             cb: self.callback,
             sym: unsafe { std::mem::zeroed() },
-            context: self.context,
-        });
+            context: std::ptr::null_mut(),
+        };
+
+        HookHandle::install(hook, self.process, self.context)
+    }
+
+    /// Installs the hook at a given address like [`at_addr`](Self::at_addr), failing if
+    /// the address is in a branch delay slot, where the hook would never be hit. The
+    /// error gives the address of the branch, which can be hooked instead.
+    ///
+    /// The check reads the code through the current address space, and is skipped if
+    /// the code before `addr` isn't mapped in it.
+    #[cfg_attr(
+        doc_cfg,
+        doc(cfg(any(
+            feature = "mips",
+            feature = "mipsel",
+            feature = "mips64",
+            feature = "mips64el"
+        )))
+    )]
+    #[cfg(any(
+        feature = "mips",
+        feature = "mipsel",
+        feature = "mips64",
+        feature = "mips64el"
+    ))]
+    pub fn try_at_addr(
+        self,
+        addr: target_ulong,
+    ) -> Result<HookHandle, crate::delay_slot::DelaySlotError> {
+        if let Some(cpu) = unsafe { sys::get_cpu().as_mut() } {
+            let hook_addr = crate::delay_slot::hook_addr(cpu, addr);
+            if hook_addr != addr {
+                return Err(crate::delay_slot::DelaySlotError::InDelaySlot {
+                    addr,
+                    branch: hook_addr,
+                });
+            }
+        }
+
+        Ok(self.at_addr(addr))
+    }
+
+    fn kernel_mode(&self) -> KernelMode {
+        match self.only_kernel {
+            Some(true) => KernelMode::KernelOnly,
+            Some(false) => KernelMode::UserOnly,
+            None => KernelMode::Any,
+        }
+    }
+
+    /// Installs the hook at a symbol of a library, such as `malloc` in `libc`. The hook is
+    /// placed once the library is loaded, and again in each process which loads it. The
+    /// library name only needs to be part of the name of the library, so `"libc"` will
+    /// match `libc.so.6`.
+    ///
+    /// Every hook placed for the symbol shares the same state. Any number of hooks can
+    /// be installed on the same symbol, each with its own state and filters.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// use panda::plugins::hooks::Hook;
+    /// use panda::prelude::*;
+    ///
+    /// #[panda::hook]
+    /// fn malloc_hook(_: &mut CPUState, _: &mut TranslationBlock, _: &mut Hook) {
+    ///     println!("malloc called");
+    /// }
+    ///
+    /// malloc_hook::hook().kernel(false).at_symbol("libc", "malloc");
+    /// ```
+    pub fn at_symbol(self, library: &str, symbol: &str) {
+        symbol::install(self, library, symbol::Target::Symbol(symbol.to_owned()));
+    }
+
+    /// Installs the hook at an offset from the start of a library, for hooking functions
+    /// which aren't exported. The library is matched in the same way as
+    /// [`at_symbol`](HookBuilder::at_symbol).
+    pub fn at_offset(self, library: &str, offset: target_ulong) {
+        symbol::install(self, library, symbol::Target::Offset(offset));
     }
 }
 
@@ -349,7 +575,15 @@ impl HookBuilder<NormalHookType> {
     }
 }
 
-pub struct HookBuilderCallbackTypeNeeded<T>(T);
+pub struct HookBuilderCallbackTypeNeeded<T>(T, HookContext);
+
+impl<T> HookBuilderCallbackTypeNeeded<T> {
+    /// Attaches state to the hook, accessible from the callback via [`Hook::state`].
+    pub fn state<S: 'static>(mut self, state: S) -> Self {
+        self.1.state = Some(Box::new(state));
+        self
+    }
+}
 
 impl HookBuilderCallbackTypeNeeded<BeforeTranslateHook> {
     pub fn before_block_translate(self) -> HookBuilder<BeforeTranslateHook> {
@@ -359,7 +593,8 @@ impl HookBuilderCallbackTypeNeeded<BeforeTranslateHook> {
             only_kernel: None,
             enabled: true,
             asid: None,
-            context: std::ptr::null_mut(),
+            process: None,
+            context: self.1,
         }
     }
 }
@@ -372,7 +607,8 @@ impl HookBuilderCallbackTypeNeeded<AfterBlockHook> {
             only_kernel: None,
             enabled: true,
             asid: None,
-            context: std::ptr::null_mut(),
+            process: None,
+            context: self.1,
         }
     }
 }
@@ -385,7 +621,8 @@ impl HookBuilderCallbackTypeNeeded<InvalidateOpHook> {
             only_kernel: None,
             enabled: true,
             asid: None,
-            context: std::ptr::null_mut(),
+            process: None,
+            context: self.1,
         }
     }
 }
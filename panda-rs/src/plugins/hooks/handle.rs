@@ -0,0 +1,232 @@
+//! Handles for enabling, disabling, moving and removing installed hooks
+//!
+//! The hooks plugin keeps its own copy of each hook it's given, and the only way to
+//! change that copy is from within the hook's callback. Hooks installed by a
+//! [`HookBuilder`](super::HookBuilder) are therefore wrapped in a gate which checks the
+//! state shared with the hook's [`HookHandle`] before running the callback, removing
+//! its copy from the plugin (by disabling it) once the handle has removed or moved it.
+use super::{
+    AfterBlockHook, BeforeTranslateHook, Hook, HookContext, HooksPandaCallback, InvalidateOpHook,
+    NormalHookType, HOOKS,
+};
+use crate::prelude::*;
+use crate::sys;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The state of a hook shared between its handle and the copies of it installed in the
+/// hooks plugin
+pub(super) struct HandleState {
+    enabled: AtomicBool,
+    removed: AtomicBool,
+
+    /// The hook as installed, used to install it again when it is moved
+    hook: Mutex<Hook>,
+
+    /// The callback to run once the gate has checked the hook is enabled
+    callback: *const (),
+
+    /// The name of the process the hook is scoped to, if any
+    process: Option<String>,
+}
+
+// the raw pointers are to the leaked context and callback of the hook
+unsafe impl Send for HandleState {}
+unsafe impl Sync for HandleState {}
+
+/// A handle to a hook installed using [`HookBuilder::at_addr`](super::HookBuilder::at_addr),
+/// allowing the hook to be toggled, moved or removed after installation. Dropping the
+/// handle leaves the hook installed.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::{hook, prelude::*};
+///
+/// let handle = hook::before_block_exec(|_, _, _| {
+///     println!("hook hit!");
+/// })
+/// .at_addr(0x5555500ca);
+///
+/// handle.disable();
+/// handle.update_addr(0x5555500f0);
+/// handle.enable();
+///
+/// // ...
+///
+/// handle.remove();
+/// ```
+#[derive(Clone)]
+pub struct HookHandle(Arc<HandleState>);
+
+impl HookHandle {
+    /// Install the hook in the hooks plugin, gated on the state of the returned handle
+    pub(super) fn install(
+        mut hook: Hook,
+        process: Option<String>,
+        mut context: HookContext,
+    ) -> Self {
+        if process.is_some() {
+            super::process::track();
+        }
+
+        let state = Arc::new(HandleState {
+            enabled: AtomicBool::new(hook.enabled),
+            removed: AtomicBool::new(false),
+            hook: Mutex::new(hook),
+            callback: hook.cb.1,
+            process,
+        });
+
+        context.handle = Some(Arc::clone(&state));
+
+        hook.enabled = true;
+        hook.cb = gated(hook.cb);
+        hook.context = context.into_raw();
+        *state.hook.lock().unwrap() = hook;
+
+        HOOKS.add_hook(&hook);
+
+        Self(state)
+    }
+
+    /// Allow the hook's callback to run when the hook is hit
+    pub fn enable(&self) {
+        self.0.enabled.store(true, Ordering::SeqCst);
+    }
+
+    /// Stop the hook's callback from running until the hook is enabled again
+    pub fn disable(&self) {
+        self.0.enabled.store(false, Ordering::SeqCst);
+    }
+
+    /// Whether the hook is enabled. Hooks which disable themselves by setting
+    /// [`Hook::enabled`] to `false` from their callback are reported as disabled and can
+    /// be enabled again through their handle.
+    pub fn is_enabled(&self) -> bool {
+        self.0.enabled.load(Ordering::SeqCst) && !self.is_removed()
+    }
+
+    /// Remove the hook. The hooks plugin's copy of the hook is disabled the next time
+    /// it is hit, without running the callback.
+    pub fn remove(&self) {
+        self.0.removed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.0.removed.load(Ordering::SeqCst)
+    }
+
+    /// The address the hook is installed at
+    pub fn addr(&self) -> target_ulong {
+        self.0.hook.lock().unwrap().addr
+    }
+
+    /// Move the hook to a new address, keeping its callback, state and whether it is
+    /// enabled. Has no effect on removed hooks.
+    pub fn update_addr(&self, addr: target_ulong) {
+        let mut hook = self.0.hook.lock().unwrap();
+        if hook.addr == addr || self.is_removed() {
+            return;
+        }
+
+        // the hook left at the old address removes itself the next time it is hit
+        hook.addr = addr;
+        HOOKS.add_hook(&hook);
+    }
+}
+
+/// Replace a hook's callback with the gate for its callback type
+fn gated(cb: HooksPandaCallback) -> HooksPandaCallback {
+    let gate = match cb.0 {
+        sys::panda_cb_type_PANDA_CB_BEFORE_BLOCK_TRANSLATE => gate_before_translate as *const (),
+        sys::panda_cb_type_PANDA_CB_AFTER_BLOCK_EXEC => gate_after_block as *const (),
+        sys::panda_cb_type_PANDA_CB_BEFORE_BLOCK_EXEC_INVALIDATE_OPT => {
+            gate_invalidate_op as *const ()
+        }
+        _ => gate_normal as *const (),
+    };
+
+    HooksPandaCallback(cb.0, gate)
+}
+
+/// Check whether the callback of a gated hook should be run, returning its state if so
+fn enter(cpu: &mut CPUState, hook: &mut Hook) -> Option<Arc<HandleState>> {
+    let context = unsafe { &*(hook.context as *const HookContext) };
+    let state = Arc::clone(context.handle.as_ref()?);
+
+    if state.removed.load(Ordering::SeqCst) || state.hook.lock().unwrap().addr != hook.addr {
+        hook.enabled = false;
+        return None;
+    }
+
+    if !state.enabled.load(Ordering::SeqCst) {
+        return None;
+    }
+
+    if let Some(name) = &state.process {
+        if !super::process::is_running(cpu, name) {
+            return None;
+        }
+    }
+
+    Some(state)
+}
+
+/// Carry changes made to the hook by its callback over to its handle
+fn leave(hook: &mut Hook, state: &HandleState) {
+    if state.removed.load(Ordering::SeqCst) {
+        hook.enabled = false;
+    } else if !hook.enabled {
+        // keep the plugin's copy enabled so the handle can enable the hook again
+        hook.enabled = true;
+        state.enabled.store(false, Ordering::SeqCst);
+    }
+}
+
+extern "C" fn gate_normal(cpu: &mut CPUState, tb: &mut TranslationBlock, hook: &mut Hook) {
+    if let Some(state) = enter(cpu, hook) {
+        let callback: NormalHookType = unsafe { std::mem::transmute(state.callback) };
+        callback(cpu, tb, hook);
+        leave(hook, &state);
+    }
+}
+
+extern "C" fn gate_before_translate(cpu: &mut CPUState, pc: target_ptr_t, hook: &mut Hook) {
+    if let Some(state) = enter(cpu, hook) {
+        let callback: BeforeTranslateHook = unsafe { std::mem::transmute(state.callback) };
+        callback(cpu, pc, hook);
+        leave(hook, &state);
+    }
+}
+
+extern "C" fn gate_after_block(
+    cpu: &mut CPUState,
+    tb: &mut TranslationBlock,
+    exit_code: u8,
+    hook: &mut Hook,
+) {
+    if let Some(state) = enter(cpu, hook) {
+        let callback: AfterBlockHook = unsafe { std::mem::transmute(state.callback) };
+        callback(cpu, tb, exit_code, hook);
+        leave(hook, &state);
+    }
+}
+
+extern "C" fn gate_invalidate_op(
+    cpu: &mut CPUState,
+    tb: &mut TranslationBlock,
+    hook: &mut Hook,
+) -> bool {
+    match enter(cpu, hook) {
+        Some(state) => {
+            let callback: InvalidateOpHook = unsafe { std::mem::transmute(state.callback) };
+            let invalidate = callback(cpu, tb, hook);
+            leave(hook, &state);
+
+            invalidate
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,170 @@
+//! Hooks on kernel functions, found by name in the volatility profile loaded by OSI2
+
+use super::{Hook, HookContext, HooksPandaCallback, KernelMode, HOOKS};
+use crate::plugins::cosi;
+use crate::prelude::*;
+use crate::Callback;
+
+use lazy_static::lazy_static;
+
+use std::sync::Mutex;
+
+type KernelSymbolCallback = dyn FnMut(&mut CPUState, &mut TranslationBlock, &mut Hook);
+
+/// A hook on a kernel symbol, which is moved whenever the symbol's address changes
+struct KernelSymbolHook {
+    name: String,
+
+    /// The address the hook is currently installed at, `None` until it is resolved
+    addr: Option<target_ptr_t>,
+
+    /// Whether the symbol needs to be looked up again before the hook is installed
+    pending: bool,
+
+    /// The `HookContext` shared by every hook installed for this symbol
+    context: usize,
+}
+
+/// The closure of a kernel symbol hook, pointed to by its `HookContext`
+struct Trampoline {
+    index: usize,
+    callback: Box<KernelSymbolCallback>,
+}
+
+lazy_static! {
+    static ref KERNEL_SYMBOL_HOOKS: Mutex<Vec<KernelSymbolHook>> = Mutex::new(Vec::new());
+    static ref CALLBACKS: [Callback; 2] = install_callbacks();
+}
+
+/// Install the callbacks which resolve pending symbols once the guest is running kernel
+/// code, and mark every symbol as pending after a snapshot is loaded, as the kernel
+/// may have been loaded at a different address. The resolver comes first.
+fn install_callbacks() -> [Callback; 2] {
+    let resolver = Callback::new();
+    resolver.before_block_exec(|cpu, _| {
+        if !crate::in_kernel_mode(cpu) {
+            return;
+        }
+
+        resolve_pending(cpu);
+        CALLBACKS[0].disable();
+    });
+
+    let after_loadvm = Callback::new();
+    after_loadvm.after_loadvm(|_| {
+        for hook in KERNEL_SYMBOL_HOOKS.lock().unwrap().iter_mut() {
+            hook.pending = true;
+        }
+
+        CALLBACKS[0].enable();
+    });
+
+    [resolver, after_loadvm]
+}
+
+/// The offset the kernel has been moved by from the addresses in the volatility profile
+fn kaslr_offset(cpu: &mut CPUState) -> target_ptr_t {
+    // cosi caches the offset it finds, so derive it from the exception handlers when
+    // possible in order to notice the kernel moving after a snapshot is loaded
+    #[cfg(any(feature = "i386", feature = "x86_64"))]
+    {
+        let handler = crate::tables::idt(cpu)
+            .ok()
+            .and_then(|idt| idt.first().map(|entry| entry.handler))
+            .filter(|&handler| handler != 0);
+
+        let symbol = ["asm_exc_divide_error", "divide_error"]
+            .iter()
+            .find_map(|name| cosi::symbol_from_name(name));
+
+        if let (Some(handler), Some(symbol)) = (handler, symbol) {
+            return handler.wrapping_sub(symbol.raw_value());
+        }
+    }
+
+    #[cfg(feature = "aarch64")]
+    {
+        let vectors = crate::tables::vector_base(cpu);
+        if vectors != 0 {
+            if let Some(symbol) = cosi::symbol_from_name("vectors") {
+                return vectors.wrapping_sub(symbol.raw_value());
+            }
+        }
+    }
+
+    cosi::kaslr_offset(cpu)
+}
+
+fn resolve_pending(cpu: &mut CPUState) {
+    let mut hooks = KERNEL_SYMBOL_HOOKS.lock().unwrap();
+    if !hooks.iter().any(|hook| hook.pending) {
+        return;
+    }
+
+    let offset = kaslr_offset(cpu);
+    for hook in hooks.iter_mut().filter(|hook| hook.pending) {
+        hook.pending = false;
+
+        let addr = match cosi::symbol_from_name(&hook.name) {
+            Some(symbol) => symbol.raw_value().wrapping_add(offset),
+            None => {
+                eprintln!("Kernel symbol {} not found, not hooking it", hook.name);
+                continue;
+            }
+        };
+
+        if hook.addr == Some(addr) {
+            continue;
+        }
+
+        hook.addr = Some(addr);
+
+        HOOKS.add_hook(&Hook {
+            addr,
+            asid: 0,
+            enabled: true,
+            km: KernelMode::KernelOnly,
+            cb: HooksPandaCallback::from_start_block_exec(trampoline),
+            sym: unsafe { std::mem::zeroed() },
+            context: hook.context as *mut _,
+        });
+    }
+}
+
+extern "C" fn trampoline(cpu: &mut CPUState, tb: &mut TranslationBlock, hook: &mut Hook) {
+    let trampoline =
+        unsafe { &mut *((*(hook.context as *mut HookContext)).callback as *mut Trampoline) };
+
+    // hooks left behind at a symbol's previous address disable themselves
+    let current_addr = KERNEL_SYMBOL_HOOKS.lock().unwrap()[trampoline.index].addr;
+    if current_addr != Some(hook.addr) {
+        hook.enabled = false;
+        return;
+    }
+
+    (trampoline.callback)(cpu, tb, hook)
+}
+
+pub(super) fn add(name: &str, callback: Box<KernelSymbolCallback>) {
+    let mut hooks = KERNEL_SYMBOL_HOOKS.lock().unwrap();
+    let trampoline = Box::new(Trampoline {
+        index: hooks.len(),
+        callback,
+    });
+
+    let context = HookContext {
+        callback: Box::into_raw(trampoline) as *mut _,
+        state: None,
+        handle: None,
+    };
+
+    hooks.push(KernelSymbolHook {
+        name: name.to_owned(),
+        addr: None,
+        pending: true,
+        context: context.into_raw() as usize,
+    });
+    drop(hooks);
+
+    CALLBACKS[0].enable();
+}
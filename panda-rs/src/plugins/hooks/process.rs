@@ -0,0 +1,48 @@
+//! Tracking of which process each address space belongs to, for hooks scoped to a
+//! process using [`HookBuilder::for_process_name`](super::HookBuilder::for_process_name)
+
+use crate::page_table;
+use crate::plugins::osi;
+use crate::prelude::*;
+
+use lazy_static::lazy_static;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// The name of the process using each address space, as of the last time it was
+    /// switched to
+    static ref PROCESS_NAMES: Mutex<HashMap<target_ulong, Option<String>>> =
+        Mutex::new(HashMap::new());
+    static ref TRACKER: () = install_tracker();
+}
+
+/// Look up the process again each time its address space is switched to, as address
+/// spaces freed by exiting processes get reused and `execve` renames processes
+fn install_tracker() {
+    page_table::on_change(|cpu, change| {
+        let name = change.process(cpu).map(|process| process.name);
+        PROCESS_NAMES.lock().unwrap().insert(change.new, name);
+    });
+}
+
+/// Start tracking the process using each address space
+pub(super) fn track() {
+    lazy_static::initialize(&TRACKER);
+}
+
+/// Check whether the process currently running has the given name
+pub(super) fn is_running(cpu: &mut CPUState, name: &str) -> bool {
+    let asid = crate::current_asid(cpu);
+    if let Some(current) = PROCESS_NAMES.lock().unwrap().get(&asid) {
+        return current.as_deref() == Some(name);
+    }
+
+    // the address space hasn't been switched to since tracking started
+    let current = osi::current_process(cpu).map(|process| process.name);
+    let is_running = current.as_deref() == Some(name);
+    PROCESS_NAMES.lock().unwrap().insert(asid, current);
+
+    is_running
+}
@@ -0,0 +1,264 @@
+//! Hooks on symbols of shared libraries, resolved by the hooks plugin as libraries are
+//! loaded
+//!
+//! The hooks plugin creates a new hook each time a [`SymbolHook`] is resolved, with no
+//! way of passing along a context. Symbol hooks are therefore installed with a dispatch
+//! callback which, the first time each resolved hook is hit, finds the symbol hook it
+//! came from using the symbol the plugin filled in, and attaches its context.
+//!
+//! The hooks resolved from two symbol hooks on the same symbol can't be told apart
+//! this way, so every hook placed on a symbol with the same callback type shares one
+//! symbol hook, which runs the callback of each of them.
+use super::{
+    process, AfterBlockHook, BeforeTranslateHook, Hook, HookBuilder, HooksPandaCallback,
+    InvalidateOpHook, KernelMode, NormalHookType, SymbolHook, HOOKS,
+};
+use crate::prelude::*;
+use crate::sys;
+
+use lazy_static::lazy_static;
+
+use std::ffi::c_void;
+use std::sync::Mutex;
+
+/// The size of the name and section buffers of [`SymbolHook`] and [`Symbol`](super::Symbol)
+const NAME_LEN: usize = 256;
+
+/// Where in a library a symbol hook is placed
+#[derive(PartialEq, Eq)]
+pub(super) enum Target {
+    Symbol(String),
+    Offset(target_ulong),
+}
+
+/// A symbol hook as installed, used to find the contexts of the hooks it resolves to
+struct SymbolHookEntry {
+    library: String,
+    target: Target,
+    cb_type: sys::panda_cb_type,
+
+    /// The `SymbolGroup` shared by every hook this symbol hook resolves to
+    group: usize,
+}
+
+/// The contexts of every hook placed on one symbol with one callback type
+struct SymbolGroup {
+    contexts: Mutex<Vec<&'static SymbolContext>>,
+}
+
+/// The context attached to every hook resolved from a symbol hook
+struct SymbolContext {
+    /// The callback to run once the hook has passed the builder's filters
+    callback: *const (),
+    enabled: bool,
+    asid: Option<target_ulong>,
+    km: KernelMode,
+    process: Option<String>,
+
+    /// The context the callback expects to find in [`Hook::context`]
+    context: *mut c_void,
+}
+
+lazy_static! {
+    static ref SYMBOL_HOOKS: Mutex<Vec<SymbolHookEntry>> = Mutex::new(Vec::new());
+}
+
+/// Copy a string into a fixed-size, nul-terminated buffer, truncating it if needed
+fn to_buffer(s: &str) -> [u8; NAME_LEN] {
+    let mut buf = [0; NAME_LEN];
+    let len = s.len().min(NAME_LEN - 1);
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+
+    buf
+}
+
+/// Read a string out of a fixed-size, nul-terminated buffer
+fn from_buffer(buf: &[u8; NAME_LEN]) -> &str {
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+
+    std::str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+pub(super) fn install<T>(builder: HookBuilder<T>, library: &str, target: Target) {
+    if builder.process.is_some() {
+        process::track();
+    }
+
+    let cb = builder.callback;
+    let context: &'static SymbolContext = Box::leak(Box::new(SymbolContext {
+        callback: cb.1,
+        enabled: builder.enabled,
+        asid: builder.asid,
+        km: builder.kernel_mode(),
+        process: builder.process,
+        context: builder.context.into_raw(),
+    }));
+
+    let mut symbol_hooks = SYMBOL_HOOKS.lock().unwrap();
+    let existing = symbol_hooks
+        .iter()
+        .find(|entry| entry.cb_type == cb.0 && entry.library == library && entry.target == target);
+
+    if let Some(entry) = existing {
+        let group = unsafe { &*(entry.group as *const SymbolGroup) };
+        group.contexts.lock().unwrap().push(context);
+
+        return;
+    }
+
+    let group = Box::new(SymbolGroup {
+        contexts: Mutex::new(vec![context]),
+    });
+
+    let (name, offset, hook_offset) = match &target {
+        Target::Symbol(name) => (to_buffer(name), 0, false),
+        Target::Offset(offset) => ([0; NAME_LEN], *offset, true),
+    };
+
+    symbol_hooks.push(SymbolHookEntry {
+        library: library.to_owned(),
+        target,
+        cb_type: cb.0,
+        group: Box::into_raw(group) as usize,
+    });
+    drop(symbol_hooks);
+
+    HOOKS.add_symbol_hook(&SymbolHook {
+        name,
+        offset,
+        hook_offset,
+        section: to_buffer(library),
+        cb: dispatcher(cb),
+    });
+}
+
+/// Replace a symbol hook's callback with the dispatcher for its callback type
+fn dispatcher(cb: HooksPandaCallback) -> HooksPandaCallback {
+    let dispatch = match cb.0 {
+        sys::panda_cb_type_PANDA_CB_BEFORE_BLOCK_TRANSLATE => {
+            dispatch_before_translate as *const ()
+        }
+        sys::panda_cb_type_PANDA_CB_AFTER_BLOCK_EXEC => dispatch_after_block as *const (),
+        sys::panda_cb_type_PANDA_CB_BEFORE_BLOCK_EXEC_INVALIDATE_OPT => {
+            dispatch_invalidate_op as *const ()
+        }
+        _ => dispatch_normal as *const (),
+    };
+
+    HooksPandaCallback(cb.0, dispatch)
+}
+
+/// Find the contexts of the symbol hook a resolved hook came from
+fn find_group(hook: &Hook) -> Option<*mut SymbolGroup> {
+    let name = from_buffer(&hook.sym.name);
+    let section = from_buffer(&hook.sym.section);
+
+    SYMBOL_HOOKS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|entry| {
+            entry.cb_type == hook.cb.0
+                && section.contains(&entry.library)
+                && match &entry.target {
+                    Target::Symbol(symbol) => symbol == name,
+                    Target::Offset(offset) => hook.addr.wrapping_sub(hook.sym.address) == *offset,
+                }
+        })
+        .map(|entry| entry.group as *mut SymbolGroup)
+}
+
+/// Check whether a hook's callback should be run given its builder's filters
+fn should_run(cpu: &mut CPUState, context: &SymbolContext) -> bool {
+    let asid_matches = context
+        .asid
+        .map_or(true, |asid| crate::current_asid(cpu) == asid);
+    let km_matches = match context.km {
+        KernelMode::Any => true,
+        KernelMode::KernelOnly => crate::in_kernel_mode(cpu),
+        KernelMode::UserOnly => !crate::in_kernel_mode(cpu),
+    };
+
+    let process_matches = context
+        .process
+        .as_ref()
+        .map_or(true, |name| process::is_running(cpu, name));
+
+    context.enabled && asid_matches && km_matches && process_matches
+}
+
+/// Run `run` for each hook on the symbol a resolved hook came from which passes its
+/// filters, swapping in the context its callback expects while it runs
+fn dispatch(
+    cpu: &mut CPUState,
+    hook: &mut Hook,
+    mut run: impl FnMut(&mut CPUState, &mut Hook, &SymbolContext),
+) {
+    if hook.context.is_null() {
+        match find_group(hook) {
+            Some(group) => hook.context = group as *mut c_void,
+            None => {
+                hook.enabled = false;
+                return;
+            }
+        }
+    }
+
+    let group = hook.context;
+
+    // copied out so that callbacks can place hooks on the same symbol
+    let contexts = unsafe { &*(group as *const SymbolGroup) }
+        .contexts
+        .lock()
+        .unwrap()
+        .clone();
+
+    for context in contexts {
+        if should_run(cpu, context) {
+            hook.context = context.context;
+            run(cpu, hook, context);
+        }
+    }
+
+    hook.context = group;
+}
+
+extern "C" fn dispatch_normal(cpu: &mut CPUState, tb: &mut TranslationBlock, hook: &mut Hook) {
+    dispatch(cpu, hook, |cpu, hook, context| {
+        let callback: NormalHookType = unsafe { std::mem::transmute(context.callback) };
+        callback(cpu, tb, hook);
+    });
+}
+
+extern "C" fn dispatch_before_translate(cpu: &mut CPUState, pc: target_ptr_t, hook: &mut Hook) {
+    dispatch(cpu, hook, |cpu, hook, context| {
+        let callback: BeforeTranslateHook = unsafe { std::mem::transmute(context.callback) };
+        callback(cpu, pc, hook);
+    });
+}
+
+extern "C" fn dispatch_after_block(
+    cpu: &mut CPUState,
+    tb: &mut TranslationBlock,
+    exit_code: u8,
+    hook: &mut Hook,
+) {
+    dispatch(cpu, hook, |cpu, hook, context| {
+        let callback: AfterBlockHook = unsafe { std::mem::transmute(context.callback) };
+        callback(cpu, tb, exit_code, hook);
+    });
+}
+
+extern "C" fn dispatch_invalidate_op(
+    cpu: &mut CPUState,
+    tb: &mut TranslationBlock,
+    hook: &mut Hook,
+) -> bool {
+    let mut invalidate = false;
+    dispatch(cpu, hook, |cpu, hook, context| {
+        let callback: InvalidateOpHook = unsafe { std::mem::transmute(context.callback) };
+        invalidate |= callback(cpu, tb, hook);
+    });
+
+    invalidate
+}
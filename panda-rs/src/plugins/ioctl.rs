@@ -0,0 +1,272 @@
+//! Bindings for the PANDA 'ioctl' plugin, which decodes the `ioctl` calls made by
+//! Linux guests and the files they are made on.
+//!
+//! Each call is reported to [`on_ioctl_request`](IoctlCallbacks::on_ioctl_request)
+//! callbacks when the syscall is entered, and to
+//! [`on_ioctl_response`](IoctlCallbacks::on_ioctl_response) callbacks when it returns,
+//! along with its decoded command and a copy of its argument buffer if the command
+//! encodes one.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plugins::ioctl::{IoctlCall, IoctlCallbacks};
+//! use panda::prelude::*;
+//! use panda::PppCallback;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     PppCallback::new().on_ioctl_request(|_, ioctl: &IoctlCall| {
+//!         println!(
+//!             "ioctl {:?} on {:?}",
+//!             ioctl.cmd,
+//!             ioctl.file_name().unwrap_or_default()
+//!         );
+//!     });
+//! }
+//! ```
+use crate::plugin_import;
+use crate::sys::CPUState;
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+plugin_import! {
+    static IOCTL: Ioctl = extern "ioctl" {
+        callbacks {
+            fn on_ioctl_request(cpu: &mut CPUState, ioctl: &IoctlCall);
+            fn on_ioctl_response(cpu: &mut CPUState, ioctl: &IoctlCall);
+        }
+    };
+}
+
+/// The direction data is transferred in by an ioctl, from the point of view of the
+/// guest process (`_IO`, `_IOW`, `_IOR` and `_IOWR`)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum IoctlDirection {
+    /// No argument buffer (`_IO`)
+    None = 0,
+
+    /// The process writes the argument buffer to the driver (`_IOW`)
+    Write = 1,
+
+    /// The process reads the argument buffer from the driver (`_IOR`)
+    Read = 2,
+
+    /// Both (`_IOWR`)
+    ReadWrite = 3,
+}
+
+impl IoctlDirection {
+    /// Convert the plugin's `ioctl_direction_t`, if it is one of the known directions
+    pub fn from_raw(direction: u32) -> Option<Self> {
+        match direction {
+            0 => Some(Self::None),
+            1 => Some(Self::Write),
+            2 => Some(Self::Read),
+            3 => Some(Self::ReadWrite),
+            _ => None,
+        }
+    }
+}
+
+/// An ioctl command number, decoded into its fields
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct IoctlCmd {
+    /// The raw `ioctl_direction_t`, read as an integer as the plugin's value isn't
+    /// guaranteed to be a valid [`IoctlDirection`]
+    direction: u32,
+
+    /// The driver-specific type, typically an ASCII character (`_IOC_TYPE`)
+    pub type_num: u32,
+
+    /// The command number within the type (`_IOC_NR`)
+    pub cmd_num: u32,
+
+    /// The size of the argument buffer in bytes (`_IOC_SIZE`)
+    pub arg_size: u32,
+}
+
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el",
+    feature = "ppc"
+))]
+mod encoding {
+    pub(super) const SIZE_BITS: u32 = 13;
+    pub(super) const READ: u32 = 2;
+    pub(super) const WRITE: u32 = 4;
+}
+
+#[cfg(not(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el",
+    feature = "ppc"
+)))]
+mod encoding {
+    pub(super) const SIZE_BITS: u32 = 14;
+    pub(super) const READ: u32 = 2;
+    pub(super) const WRITE: u32 = 1;
+}
+
+impl IoctlCmd {
+    /// The direction the argument buffer is transferred in, or `None` if the plugin
+    /// reported an unknown direction
+    pub fn direction(&self) -> Option<IoctlDirection> {
+        IoctlDirection::from_raw(self.direction)
+    }
+
+    /// Decode a raw command number using the Linux encoding for the guest
+    /// architecture
+    pub fn decode(cmd: u32) -> Self {
+        let size_shift = 16;
+        let dir_shift = size_shift + encoding::SIZE_BITS;
+
+        let dir = cmd >> dir_shift;
+        let direction = match (dir & encoding::READ != 0, dir & encoding::WRITE != 0) {
+            (true, true) => IoctlDirection::ReadWrite,
+            (true, false) => IoctlDirection::Read,
+            (false, true) => IoctlDirection::Write,
+            (false, false) => IoctlDirection::None,
+        };
+
+        Self {
+            direction: direction as u32,
+            type_num: (cmd >> 8) & 0xff,
+            cmd_num: cmd & 0xff,
+            arg_size: (cmd >> size_shift) & ((1 << encoding::SIZE_BITS) - 1),
+        }
+    }
+}
+
+/// An ioctl call made by the guest, as reported by the plugin
+#[repr(C)]
+#[derive(Debug)]
+pub struct IoctlCall {
+    /// The path of the file the ioctl was made on, or null if it couldn't be found
+    pub file_name: *const c_char,
+    pub cmd: IoctlCmd,
+
+    /// The guest address of the argument
+    pub guest_arg_ptr: u64,
+
+    /// A copy of the argument buffer, `cmd.arg_size` bytes long, or null if the
+    /// command has no buffer or it couldn't be read
+    pub guest_arg_buf: *const u8,
+}
+
+impl IoctlCall {
+    /// The path of the file the ioctl was made on
+    pub fn file_name(&self) -> Option<String> {
+        if self.file_name.is_null() {
+            None
+        } else {
+            let name = unsafe { CStr::from_ptr(self.file_name) };
+
+            Some(name.to_string_lossy().into_owned())
+        }
+    }
+
+    /// The contents of the argument buffer, if the command has one
+    pub fn arg_buf(&self) -> Option<&[u8]> {
+        let has_buf = matches!(
+            self.cmd.direction(),
+            Some(direction) if direction != IoctlDirection::None
+        );
+
+        if self.guest_arg_buf.is_null() || !has_buf {
+            None
+        } else {
+            let len = self.cmd.arg_size as usize;
+
+            Some(unsafe { std::slice::from_raw_parts(self.guest_arg_buf, len) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(direction: IoctlDirection, type_num: u8, cmd_num: u32, arg_size: u32) -> IoctlCmd {
+        IoctlCmd {
+            direction: direction as u32,
+            type_num: type_num as u32,
+            cmd_num,
+            arg_size,
+        }
+    }
+
+    #[test]
+    #[cfg(not(any(
+        feature = "mips",
+        feature = "mipsel",
+        feature = "mips64",
+        feature = "mips64el",
+        feature = "ppc"
+    )))]
+    fn test_decode() {
+        // UI_DEV_CREATE, EVIOCGVERSION, PERF_EVENT_IOC_SET_FILTER and VIDIOC_G_FMT
+        let cases = [
+            (0x5501, cmd(IoctlDirection::None, b'U', 1, 0)),
+            (0x8004_4501, cmd(IoctlDirection::Read, b'E', 1, 4)),
+            (0x4008_2406, cmd(IoctlDirection::Write, b'$', 6, 8)),
+            (0xc0d0_5604, cmd(IoctlDirection::ReadWrite, b'V', 4, 0xd0)),
+        ];
+
+        for (raw, decoded) in cases.iter() {
+            assert_eq!(IoctlCmd::decode(*raw), *decoded);
+        }
+    }
+
+    #[test]
+    #[cfg(any(
+        feature = "mips",
+        feature = "mipsel",
+        feature = "mips64",
+        feature = "mips64el",
+        feature = "ppc"
+    ))]
+    fn test_decode() {
+        let cases = [
+            (0x2000_5501, cmd(IoctlDirection::None, b'U', 1, 0)),
+            (0x4004_4501, cmd(IoctlDirection::Read, b'E', 1, 4)),
+            (0x8008_2406, cmd(IoctlDirection::Write, b'$', 6, 8)),
+            (0xc0d0_5604, cmd(IoctlDirection::ReadWrite, b'V', 4, 0xd0)),
+        ];
+
+        for (raw, decoded) in cases.iter() {
+            assert_eq!(IoctlCmd::decode(*raw), *decoded);
+        }
+    }
+
+    #[test]
+    fn test_unknown_direction() {
+        assert_eq!(IoctlDirection::from_raw(3), Some(IoctlDirection::ReadWrite));
+        assert_eq!(IoctlDirection::from_raw(4), None);
+
+        let buf = [0u8; 4];
+        let mut call = IoctlCall {
+            file_name: std::ptr::null(),
+            cmd: IoctlCmd {
+                direction: 0xff,
+                type_num: 0,
+                cmd_num: 0,
+                arg_size: 4,
+            },
+            guest_arg_ptr: 0,
+            guest_arg_buf: buf.as_ptr(),
+        };
+
+        assert_eq!(call.cmd.direction(), None);
+        assert_eq!(call.arg_buf(), None);
+
+        call.cmd.direction = IoctlDirection::Read as u32;
+        assert_eq!(call.arg_buf(), Some(&buf[..]));
+    }
+}
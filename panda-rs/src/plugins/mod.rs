@@ -1,18 +1,25 @@
 //! Bindings for various built-in PANDA plugins
 
-use crate::{sys::panda_require, ARCH_NAME};
-use libloading::Symbol;
+use crate::{
+    sys::{panda_require, panda_unload_plugin_by_name},
+    Error, PluginError, ARCH_NAME,
+};
 use once_cell::sync::OnceCell;
-use std::ffi::CString;
+use std::collections::HashMap;
+use std::ffi::{c_void, CString};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
 
+pub mod callstack_instr;
 pub mod cosi;
 pub mod glib;
 pub mod guest_plugin_manager;
 pub mod hooks;
 pub mod hooks2;
+pub mod ioctl;
 pub mod osi;
 pub mod proc_start_linux;
+pub mod signal;
 
 #[cfg(not(feature = "ppc"))]
 pub mod syscalls2;
@@ -85,6 +92,51 @@ pub mod syscalls2;
 /// a trait called `ProcStartLinuxCallbacks` which would have a method called `on_rec_auxv`,
 /// which is automatically implemented for [`PppCallback`].
 ///
+/// ### Optional plugins
+///
+/// The static loads the plugin the first time it is used, panicking if the plugin
+/// can't be found. For plugins which are optional, use the `try_new` constructor of the
+/// generated type instead of the static, which returns a [`panda::Error`](crate::Error)
+/// if the plugin can't be loaded. The handle it returns has a `try_` version of each
+/// function, which returns an error rather than panicking if the plugin has since been
+/// unloaded or doesn't export the function:
+///
+/// ```no_run
+/// use panda::plugins::osi::Osi;
+/// use panda::prelude::*;
+///
+/// fn print_process(cpu: &mut CPUState) {
+///     match Osi::try_new() {
+///         Ok(osi) => match osi.try_get_current_process(cpu) {
+///             Ok(process) => println!("{:?}", process.map(|process| process.pid)),
+///             Err(err) => println!("OSI doesn't support this: {}", err),
+///         },
+///         Err(err) => println!("OSI is unavailable: {}", err),
+///     }
+/// }
+/// ```
+///
+/// ### Unloading
+///
+/// The generated type also has `unload`, `reload` and `is_loaded` methods for managing
+/// the lifetime of the plugin (see [`Plugin::unload`]). Calling a function of a plugin
+/// which has been unloaded panics, unless the static is declared `unloadable`, in which
+/// case the plugin is loaded again on demand:
+///
+/// ```no_run
+/// plugin_import! {
+///     static SIGNAL: Signal = extern "signal" unloadable {
+///         fn block_sig(sig: i32);
+///     };
+/// }
+///
+/// SIGNAL.block_sig(9);
+/// SIGNAL.unload();
+///
+/// // loads the plugin again
+/// SIGNAL.block_sig(9);
+/// ```
+///
 /// [`PppCallback`]: crate::PppCallback
 #[macro_export]
 macro_rules! plugin_import {
@@ -92,7 +144,7 @@ macro_rules! plugin_import {
         $(
             #[ $type_meta:meta ]
         )*
-        static $static:ident : $ty:ident = extern $name:literal {
+        static $static:ident : $ty:ident = extern $name:literal $($load_mode:ident)? {
         $(
             $(
                 #[$meta:meta]
@@ -138,17 +190,51 @@ macro_rules! plugin_import {
             /// Create a new handle to this plugin
             pub fn new() -> Self {
                 Self {
-                    plugin: $crate::plugins::Plugin::new($name)
+                    plugin: $crate::plugins::Plugin::new($name) $(.$load_mode())?
                 }
             }
 
+            /// Create a new handle to this plugin, returning an error rather than
+            /// panicking if it can't be loaded
+            pub fn try_new() -> Result<Self, $crate::Error> {
+                Ok(Self {
+                    plugin: $crate::plugins::Plugin::try_new($name)? $(.$load_mode())?
+                })
+            }
+
             /// Load the plugin and initialize it if it hasn't been loaded already.
-            pub fn ensure_init(&self) {}
+            pub fn ensure_init(&self) {
+                self.plugin.ensure_loaded();
+            }
+
+            /// Load the plugin and initialize it if it hasn't been loaded already,
+            /// returning an error rather than panicking if it can't be loaded
+            pub fn try_ensure_init(&self) -> Result<(), $crate::Error> {
+                self.plugin.try_ensure_loaded()
+            }
+
+            /// Unload the plugin. See [`Plugin::unload`]($crate::plugins::Plugin::unload).
+            pub fn unload(&self) {
+                self.plugin.unload();
+            }
+
+            /// Unload the plugin and load it again, resetting its state
+            pub fn reload(&self) {
+                self.plugin.reload();
+            }
+
+            /// Check whether the plugin is currently loaded
+            pub fn is_loaded(&self) -> bool {
+                self.plugin.is_loaded()
+            }
 
             $(
                 $(
                     #[$meta]
                  )*
+                // pointer arguments are passed through to the plugin unchanged, and the
+                // declaration of each function asserts that it is safe to call
+                #[allow(clippy::not_unsafe_ptr_arg_deref)]
                 pub fn $fn_name $(< $($lifetimes),* >)? (&self $(, $arg_name : $arg_ty )*) $(-> $fn_ret)? {
                     unsafe {
                         self.plugin.get::<unsafe extern "C" fn($($arg_ty),*) $(-> $fn_ret)?>(
@@ -160,6 +246,22 @@ macro_rules! plugin_import {
                         )
                     }
                 }
+
+                $crate::paste::paste!{
+                    #[doc = concat!(
+                        "Call `", stringify!($fn_name), "`, returning an error rather than ",
+                        "panicking if the plugin isn't loaded or doesn't export it"
+                    )]
+                    pub fn [<try_ $fn_name>] $(< $($lifetimes),* >)? (
+                        &self $(, $arg_name : $arg_ty )*
+                    ) -> Result<($($fn_ret)?), $crate::Error> {
+                        let func = self.plugin.try_get::<
+                            unsafe extern "C" fn($($arg_ty),*) $(-> $fn_ret)?
+                        >(stringify!($fn_name))?;
+
+                        Ok(unsafe { func($($arg_name),*) })
+                    }
+                }
              )*
 
             $($(
@@ -318,32 +420,35 @@ macro_rules! plugin_import {
                             where CallbackFn: FnMut($($cb_arg_ty),*) $(-> $cb_fn_ret)? + 'static
                         {
                             use std::ffi::c_void;
-                            let closure_ref: *mut c_void = unsafe {
-                                let x: Box<Box<
-                                    dyn FnMut($($cb_arg_ty),*) $(-> $cb_fn_ret)?
-                                >> = Box::new(
-                                    Box::new(callback) as Box<_>
-                                );
-                                core::mem::transmute(x)
-                            };
+                            let closure_ref = Box::into_raw(Box::new(
+                                $crate::reentrancy::ClosureContext::new(
+                                    Box::new(callback) as Box<dyn FnMut($($cb_arg_ty),*) $(-> $cb_fn_ret)?>
+                                )
+                            )) as *mut c_void;
 
                             unsafe extern "C" fn trampoline(
                                 context: *mut c_void, $($cb_arg_name : $cb_arg_ty),*
                             ) $(-> $cb_fn_ret)?
                             {
-                                let closure: &mut &mut (
-                                    dyn FnMut($($cb_arg_ty),*) $(-> $cb_fn_ret)?
-                                ) = core::mem::transmute(
-                                    context
-                                );
+                                let _running = match $crate::reentrancy::__enter(context) {
+                                    Some(running) => running,
+                                    None => return $crate::reentrancy::SkippedReturn::skipped_return(
+                                        ($($cb_arg_name,)*)
+                                    ),
+                                };
+
+                                let context = context as *mut $crate::reentrancy::ClosureContext<
+                                    Box<dyn FnMut($($cb_arg_ty),*) $(-> $cb_fn_ret)?>
+                                >;
+                                let closure = &mut (*context).closure;
 
                                 closure($($cb_arg_name),*)
                             }
 
                             unsafe fn drop_fn(this: *mut c_void) {
-                                let _: Box<Box<
-                                    dyn FnMut($($cb_arg_ty),*) $(-> $cb_fn_ret)?
-                                >> = core::mem::transmute(this);
+                                let _ = Box::from_raw(this as *mut $crate::reentrancy::ClosureContext<
+                                    Box<dyn FnMut($($cb_arg_ty),*) $(-> $cb_fn_ret)?>
+                                >);
                             }
 
                             unsafe fn enable(this: *mut c_void) {
@@ -386,7 +491,18 @@ macro_rules! plugin_import {
 /// A wrapper for a dynamic library loaded as a PANDA plugin. Is used internally by
 /// the [`plugin_import`] macro to manage loading/unloading PANDA plugins lazily.
 pub struct Plugin {
+    name: String,
+
+    /// Whether to load the plugin again when a symbol is requested after unloading
+    reload_on_use: bool,
+    loaded: RwLock<Option<LoadedPlugin>>,
+}
+
+struct LoadedPlugin {
     lib: libloading::Library,
+
+    /// Addresses of the symbols looked up so far, only valid while the library is loaded
+    symbols: Mutex<HashMap<String, usize>>,
 }
 
 const PANDA_GLOBAL_INSTALLS: &[&str] = &["/usr/local/lib/panda", "/usr/lib/panda"];
@@ -445,32 +561,171 @@ fn get_panda_plugin_dir() -> Option<&'static Path> {
 }
 
 impl Plugin {
+    /// Load the plugin with the given name, panicking if it can't be loaded
     pub fn new(name: &str) -> Self {
-        let panda_path =
-            get_panda_path().expect("PANDA_PATH not set and PANDA is not installed globally");
+        Self::try_new(name).unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        unsafe {
-            std::env::set_var("PANDA_DIR", &panda_path);
+    /// Load the plugin with the given name, returning an error if it can't be found or
+    /// loaded
+    pub fn try_new(name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            name: name.to_owned(),
+            reload_on_use: false,
+            loaded: RwLock::new(Some(LoadedPlugin::load(name)?)),
+        })
+    }
 
-            let c_name = CString::new(name).unwrap();
-            panda_require(c_name.as_ptr());
+    /// Load the plugin again on demand when a symbol is requested after it has been
+    /// unloaded, rather than panicking
+    pub fn unloadable(mut self) -> Self {
+        self.reload_on_use = true;
+        self
+    }
+
+    /// The name of the plugin
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Check whether the plugin is currently loaded
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.read().unwrap().is_some()
+    }
+
+    /// Load the plugin if it has been unloaded, panicking if it can't be loaded
+    pub fn ensure_loaded(&self) {
+        self.try_ensure_loaded()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Load the plugin if it has been unloaded, returning an error if it can't be loaded
+    pub fn try_ensure_loaded(&self) -> Result<(), Error> {
+        let mut loaded = self.loaded.write().unwrap();
+        if loaded.is_none() {
+            *loaded = Some(LoadedPlugin::load(&self.name)?);
+        }
+
+        Ok(())
+    }
+
+    /// Unload the plugin from PANDA, invalidating every symbol looked up from it. Has no
+    /// effect if the plugin isn't loaded.
+    ///
+    /// PANDA may defer unloading until the current callback returns. Any callbacks the
+    /// plugin has registered, and any callbacks registered with it, are removed, so
+    /// function pointers and [`PppCallback`](crate::PppCallback)s obtained from it must
+    /// not be used afterwards.
+    pub fn unload(&self) {
+        let loaded = self.loaded.write().unwrap().take();
+
+        if let Some(loaded) = loaded {
+            let c_name = CString::new(self.name.as_str()).unwrap();
+            unsafe {
+                panda_unload_plugin_by_name(c_name.as_ptr());
+            }
+
+            drop(loaded);
+        }
+    }
+
+    /// Unload the plugin and load it again, resetting its state
+    pub fn reload(&self) {
+        self.unload();
+        self.ensure_loaded();
+    }
+
+    /// Look up a symbol exported by the plugin. `T` must be a pointer-sized type, such
+    /// as a function pointer.
+    ///
+    /// The plugin isn't kept loaded by the returned symbol, so that a function called
+    /// through it can unload the plugin. PANDA defers unloading until the current
+    /// callback returns, so the symbol can be used right away, but shouldn't be kept
+    /// around.
+    ///
+    /// Panics if the symbol isn't found, or if the plugin has been unloaded and isn't
+    /// [`unloadable`](Plugin::unloadable). See [`try_get`](Plugin::try_get) for a
+    /// non-panicking version.
+    pub fn get<T: Copy>(&self, sym: &str) -> T {
+        self.try_get(sym).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Look up a symbol exported by the plugin, returning an error if it isn't found or
+    /// the plugin isn't loaded. `T` must be a pointer-sized type, such as a function
+    /// pointer.
+    pub fn try_get<T: Copy>(&self, sym: &str) -> Result<T, Error> {
+        assert_eq!(std::mem::size_of::<T>(), std::mem::size_of::<usize>());
+
+        let addr = match &*self.loaded.read().unwrap() {
+            Some(plugin) => Some(plugin.symbol(&self.name, sym)?),
+            None => None,
+        };
+
+        match addr {
+            // `T` is checked to be pointer-sized above
+            Some(addr) => Ok(unsafe { std::mem::transmute_copy::<usize, T>(&addr) }),
+            None if self.reload_on_use => {
+                self.try_ensure_loaded()?;
+                self.try_get(sym)
+            }
+            None => Err(PluginError::Unloaded(self.name.clone()).into()),
         }
+    }
+}
 
+impl LoadedPlugin {
+    fn load(name: &str) -> Result<Self, PluginError> {
+        let panda_path = get_panda_path().ok_or(PluginError::PandaNotFound)?;
         let path = get_panda_plugin_dir()
-            .expect("Could not find panda plugin dir, consider setting PANDA_PLUGIN_DIR")
+            .ok_or(PluginError::PluginDirNotFound)?
             .join(&format!("panda_{}.so", name));
 
         if !path.exists() {
-            panic!("Could not find plugin {} at {}", name, path.display());
+            return Err(PluginError::PluginNotFound {
+                name: name.to_owned(),
+                path,
+            });
         }
 
-        Self {
-            lib: libloading::Library::new(path).expect("Failed to load plugin"),
+        unsafe {
+            std::env::set_var("PANDA_DIR", &panda_path);
+
+            let c_name = CString::new(name).unwrap();
+            panda_require(c_name.as_ptr());
         }
+
+        let lib = libloading::Library::new(path).map_err(|source| PluginError::LoadFailed {
+            name: name.to_owned(),
+            source,
+        })?;
+
+        Ok(Self {
+            lib,
+            symbols: Mutex::new(HashMap::new()),
+        })
     }
 
-    pub fn get<T>(&self, sym: &str) -> Symbol<T> {
-        let symbol: Vec<_> = sym.bytes().chain(std::iter::once(0)).collect();
-        unsafe { self.lib.get(&symbol).expect("Could not find symbol") }
+    fn symbol(&self, plugin: &str, sym: &str) -> Result<usize, Error> {
+        let mut symbols = self.symbols.lock().unwrap();
+        let addr = match symbols.get(sym) {
+            Some(&addr) => addr,
+            None => {
+                let symbol: Vec<_> = sym.bytes().chain(std::iter::once(0)).collect();
+                let addr = unsafe {
+                    *self.lib.get::<*mut c_void>(&symbol).map_err(|source| {
+                        PluginError::SymbolNotFound {
+                            plugin: plugin.to_owned(),
+                            symbol: sym.to_owned(),
+                            source,
+                        }
+                    })? as usize
+                };
+
+                symbols.insert(sym.to_owned(), addr);
+                addr
+            }
+        };
+
+        Ok(addr)
     }
 }
@@ -1,4 +1,29 @@
 //! Bingings for the OSI (Operating System Introspection) plugin
+//!
+//! The raw plugin API is available through [`OSI`], which returns glib-allocated
+//! structures holding raw pointers. For most uses the safe functions in this module are
+//! easier to work with, as they return owned types ([`Process`], [`Thread`],
+//! [`Module`] and [`Mapping`]) with their names already copied into `String`s.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plugins::osi;
+//! use panda::prelude::*;
+//!
+//! # let cpu: &mut CPUState = todo!();
+//! if let Some(process) = osi::current_process(cpu) {
+//!     println!("Running {} (pid {})", process.name, process.pid);
+//!
+//!     for mapping in process.mappings(cpu) {
+//!         println!("{:#x}: {:?}", mapping.base, mapping.name);
+//!     }
+//! }
+//!
+//! for process in osi::processes(cpu).filter(|process| process.ppid == 1) {
+//!     println!("{} is a child of init", process.name);
+//! }
+//! ```
 use crate::plugin_import;
 use crate::plugins::glib::{GBox, GBoxedSlice};
 use crate::sys::{target_pid_t, target_ptr_t, target_ulong, CPUState};
@@ -8,10 +33,22 @@ use std::ffi::CStr;
 
 use glib_sys::GArray;
 
+mod carve;
+pub use carve::*;
+
+mod kernel;
+pub use kernel::*;
+
+mod owned;
+pub use owned::*;
+
+mod threads;
+pub use threads::*;
+
 plugin_import! {
     static OSI: Osi = extern "osi" {
         fn get_process_handles(cpu: *mut CPUState) -> GBoxedSlice<OsiProcHandle>;
-        fn get_current_thread(cpu: *mut CPUState) -> GBox<OsiThread>;
+        fn get_current_thread(cpu: *mut CPUState) -> Option<GBox<OsiThread>>;
         fn get_modules(cpu: *mut CPUState) -> GBoxedSlice<OsiModule>;
         fn get_mappings(cpu: *mut CPUState, p: *mut OsiProc) -> GBoxedSlice<OsiModule>;
         fn get_processes(cpu: *mut CPUState) -> GBoxedSlice<OsiProc>;
@@ -19,8 +56,8 @@ plugin_import! {
         fn get_one_module(osimodules: *mut GArray, idx: ::std::os::raw::c_uint) -> *mut OsiModule;
         fn get_one_proc(osiprocs: *mut GArray, idx: ::std::os::raw::c_uint) -> *mut OsiProc;
         fn cleanup_garray(g: *mut GArray);
-        fn get_current_process_handle(cpu: *mut CPUState) -> GBox<OsiProcHandle>;
-        fn get_process(cpu: *mut CPUState, h: *const OsiProcHandle) -> GBox<OsiProc>;
+        fn get_current_process_handle(cpu: *mut CPUState) -> Option<GBox<OsiProcHandle>>;
+        fn get_process(cpu: *mut CPUState, h: *const OsiProcHandle) -> Option<GBox<OsiProc>>;
         fn get_process_pid(cpu: *mut CPUState, h: *const OsiProcHandle) -> target_pid_t;
         fn get_process_ppid(cpu: *mut CPUState, h: *const OsiProcHandle) -> target_pid_t;
         fn in_shared_object(cpu: *mut CPUState, h: *const OsiProc) -> bool;
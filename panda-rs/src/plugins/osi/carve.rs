@@ -0,0 +1,402 @@
+use crate::data_endian;
+use crate::enums::{Endian, MemRWStatus};
+use crate::mem::{page_size, ram_ranges};
+use crate::prelude::*;
+
+use std::mem::size_of;
+
+const PTR_SIZE: usize = size_of::<target_ptr_t>();
+
+/// Size of each block of physical memory read at a time while carving
+const CHUNK_SIZE: usize = 0x10_0000;
+
+/// The number of bytes around a signature which need to be readable to validate it.
+/// Chunks overlap by this amount so that structures spanning two chunks are found.
+const WINDOW: usize = 0x1000;
+
+/// The type of kernel structure a carved process was found from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CarvedKind {
+    /// A Windows `EPROCESS`, found via its pool allocation
+    Eprocess,
+
+    /// A Linux `task_struct`, found via its `comm` field
+    TaskStruct,
+}
+
+/// A process found by [`carve_processes`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarvedProcess {
+    pub kind: CarvedKind,
+
+    /// The physical address the structure was found at. For an `EPROCESS` this is the
+    /// start of the structure, for a `task_struct` this is the address of `comm`.
+    pub phys_addr: u64,
+
+    /// The name of the process, as stored in the kernel (typically truncated to 15 bytes)
+    pub name: String,
+
+    /// The pid of the process, if it could be found
+    pub pid: Option<target_pid_t>,
+
+    /// The address space of the process, if it could be found
+    pub asid: Option<target_ulong>,
+}
+
+/// Find processes by scanning guest physical memory for kernel process structures,
+/// without requiring OSI to be configured for the guest.
+///
+/// This is a heuristic fallback for when no OSI profile is available: it may miss
+/// processes, report processes which have exited, and report false positives. Windows
+/// processes (on x86) are found with their name, pid and asid, while Linux processes
+/// are found by name only. Linux threads are reported individually.
+pub fn carve_processes() -> Vec<CarvedProcess> {
+    let mut processes = Vec::new();
+    let mut chunk = vec![0u8; CHUNK_SIZE + WINDOW];
+
+    for ram in ram_ranges() {
+        let mut base = ram.start;
+
+        while base < ram.end {
+            let len = ((ram.end - base) as usize).min(chunk.len());
+            let chunk = &mut chunk[..len];
+            read_chunk(base, chunk);
+
+            // Only report signatures found before the overlap, as anything after will
+            // be found again in the next chunk
+            let scan_len = len.min(CHUNK_SIZE);
+
+            carve_eprocesses(base, chunk, scan_len, &mut processes);
+            carve_task_structs(base, chunk, scan_len, &mut processes);
+
+            base += CHUNK_SIZE as u64;
+        }
+    }
+
+    processes
+}
+
+fn read_phys(addr: u64, buf: &mut [u8]) -> bool {
+    let status: MemRWStatus = unsafe {
+        panda_sys::panda_physical_memory_read_external(addr, buf.as_mut_ptr(), buf.len() as i32)
+            .into()
+    };
+
+    status == MemRWStatus::MemTxOk
+}
+
+/// Read a chunk of physical memory. If the chunk can't be read in one go, each page is
+/// read separately, and pages which can't be read are zeroed, which no signature
+/// matches.
+fn read_chunk(base: u64, chunk: &mut [u8]) {
+    if read_phys(base, chunk) {
+        return;
+    }
+
+    let page_size = page_size() as u64;
+    let mut offset = 0;
+    while offset < chunk.len() {
+        let addr = base + offset as u64;
+        let len = ((page_size - (addr % page_size)) as usize).min(chunk.len() - offset);
+        let page = &mut chunk[offset..offset + len];
+
+        if !read_phys(addr, page) {
+            page.fill(0);
+        }
+
+        offset += len;
+    }
+}
+
+fn read_ptr(bytes: &[u8], offset: usize) -> Option<target_ptr_t> {
+    let mut raw = [0u8; PTR_SIZE];
+    raw.copy_from_slice(bytes.get(offset..offset + PTR_SIZE)?);
+
+    match data_endian() {
+        Endian::Big => Some(target_ptr_t::from_be_bytes(raw)),
+        Endian::Little => Some(target_ptr_t::from_le_bytes(raw)),
+    }
+}
+
+/// Check whether a pointer falls within the upper half of the address space, where
+/// both Windows and Linux map the kernel
+fn is_kernel_ptr(ptr: target_ptr_t) -> bool {
+    ptr >> (target_ptr_t::BITS - 1) != 0
+}
+
+/// Parse a fixed-size, NUL-terminated name field, requiring it to be non-empty and
+/// made up of printable ASCII
+fn parse_name(field: &[u8]) -> Option<(&str, usize)> {
+    let len = field.iter().position(|&byte| byte == 0)?;
+    let name = &field[..len];
+
+    let valid = !name.is_empty()
+        && name[0].is_ascii_graphic()
+        && name
+            .iter()
+            .all(|&byte| byte == b' ' || byte.is_ascii_graphic());
+
+    valid.then(|| (std::str::from_utf8(name).unwrap(), len))
+}
+
+#[cfg(any(feature = "i386", feature = "x86_64"))]
+fn carve_eprocesses(base: u64, chunk: &[u8], scan_len: usize, processes: &mut Vec<CarvedProcess>) {
+    /// `Proc`, and the same tag with the "protected" bit used by older Windows
+    const POOL_TAGS: [&[u8; 4]; 2] = [b"Proc", b"Pro\xe3"];
+    const POOL_ALIGN: usize = 2 * PTR_SIZE;
+    const POOL_TAG_OFFSET: usize = 4;
+
+    /// `DISPATCHER_HEADER.Type` for processes
+    const PROCESS_OBJECT: u8 = 3;
+    const WAIT_LIST_OFFSET: usize = 8;
+    const DTB_OFFSET: usize = if PTR_SIZE == 8 { 0x28 } else { 0x18 };
+
+    /// Range of offsets from the start of the `EPROCESS` searched for fields whose
+    /// offsets vary between Windows versions
+    const PID_SEARCH: std::ops::Range<usize> = 0x80..0x500;
+    const NAME_SEARCH: std::ops::Range<usize> = 0x100..0x800;
+    const NAME_LEN: usize = 15;
+
+    for header in (0..scan_len).step_by(POOL_ALIGN) {
+        let tag = match chunk.get(header + POOL_TAG_OFFSET..header + POOL_TAG_OFFSET + 4) {
+            Some(tag) => tag,
+            None => break,
+        };
+
+        if !POOL_TAGS.iter().any(|pool_tag| tag == &pool_tag[..]) {
+            continue;
+        }
+
+        // The EPROCESS follows the pool header and object header, the size of which
+        // varies, so find the process's dispatcher header.
+        let eprocess = (header + POOL_ALIGN..header + 0x100)
+            .step_by(PTR_SIZE)
+            .find(|&start| {
+                let dtb = read_ptr(chunk, start + DTB_OFFSET).unwrap_or(0);
+                let wait_list = (
+                    read_ptr(chunk, start + WAIT_LIST_OFFSET).unwrap_or(0),
+                    read_ptr(chunk, start + WAIT_LIST_OFFSET + PTR_SIZE).unwrap_or(0),
+                );
+
+                chunk.get(start) == Some(&PROCESS_OBJECT)
+                    && chunk.get(start + 2).map_or(false, |&size| size != 0)
+                    && is_kernel_ptr(wait_list.0)
+                    && is_kernel_ptr(wait_list.1)
+                    && dtb & !0xfff != 0
+                    && (PTR_SIZE == 8 || dtb & 0x1f == 0)
+            });
+
+        let eprocess = match eprocess {
+            Some(eprocess) => eprocess,
+            None => continue,
+        };
+
+        let name = NAME_SEARCH.step_by(4).find_map(|offset| {
+            let field = chunk.get(eprocess + offset..eprocess + offset + NAME_LEN)?;
+            let (name, len) = parse_name(field)?;
+
+            field[len..]
+                .iter()
+                .all(|&byte| byte == 0)
+                .then(|| name.to_owned())
+        });
+
+        let name = match name {
+            Some(name) => name,
+            None => continue,
+        };
+
+        // UniqueProcessId is directly followed by the ActiveProcessLinks list entry
+        let pid = PID_SEARCH.step_by(PTR_SIZE).find_map(|offset| {
+            let pid = read_ptr(chunk, eprocess + offset)?;
+            let links = (
+                read_ptr(chunk, eprocess + offset + PTR_SIZE)?,
+                read_ptr(chunk, eprocess + offset + (2 * PTR_SIZE))?,
+            );
+
+            (pid != 0
+                && pid % 4 == 0
+                && pid < 0x10_0000
+                && is_kernel_ptr(links.0)
+                && is_kernel_ptr(links.1))
+            .then(|| pid as target_pid_t)
+        });
+
+        processes.push(CarvedProcess {
+            kind: CarvedKind::Eprocess,
+            phys_addr: base + eprocess as u64,
+            name,
+            pid,
+            asid: read_ptr(chunk, eprocess + DTB_OFFSET),
+        });
+    }
+}
+
+#[cfg(not(any(feature = "i386", feature = "x86_64")))]
+fn carve_eprocesses(_: u64, _: &[u8], _: usize, _: &mut Vec<CarvedProcess>) {}
+
+fn carve_task_structs(
+    base: u64,
+    chunk: &[u8],
+    scan_len: usize,
+    processes: &mut Vec<CarvedProcess>,
+) {
+    const COMM_LEN: usize = 16;
+
+    // `comm` is preceded by the `real_cred` and `cred` pointers, which are equal for
+    // almost every task, optionally followed by a `cached_requested_key` pointer.
+    for comm in (3 * PTR_SIZE..scan_len).step_by(PTR_SIZE) {
+        let field = match chunk.get(comm..comm + COMM_LEN) {
+            Some(field) => field,
+            None => break,
+        };
+
+        let name = match parse_name(field) {
+            Some((name, _)) => name,
+            None => continue,
+        };
+
+        let creds_match = |cred_offset: usize| {
+            let real_cred = read_ptr(chunk, comm - cred_offset - PTR_SIZE).unwrap_or(0);
+            let cred = read_ptr(chunk, comm - cred_offset).unwrap_or(0);
+
+            is_kernel_ptr(cred) && cred == real_cred
+        };
+
+        let cached_key = read_ptr(chunk, comm - PTR_SIZE).unwrap_or(0);
+        let is_task = creds_match(PTR_SIZE)
+            || ((cached_key == 0 || is_kernel_ptr(cached_key)) && creds_match(2 * PTR_SIZE));
+
+        if is_task {
+            processes.push(CarvedProcess {
+                kind: CarvedKind::TaskStruct,
+                phys_addr: base + comm as u64,
+                name: name.to_owned(),
+                pid: None,
+                asid: None,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KERNEL_PTR: target_ptr_t = !0 << (target_ptr_t::BITS - 4);
+
+    fn write_ptr(chunk: &mut [u8], offset: usize, ptr: target_ptr_t) {
+        let raw = match data_endian() {
+            Endian::Big => ptr.to_be_bytes(),
+            Endian::Little => ptr.to_le_bytes(),
+        };
+
+        chunk[offset..offset + PTR_SIZE].copy_from_slice(&raw);
+    }
+
+    #[test]
+    fn test_parse_name() {
+        assert_eq!(parse_name(b"bash\0\0\0\0"), Some(("bash", 4)));
+        assert_eq!(parse_name(b"kworker/0:1 \0"), Some(("kworker/0:1 ", 12)));
+        assert_eq!(parse_name(b"\0bash\0"), None);
+        assert_eq!(parse_name(b" bash\0"), None);
+        assert_eq!(parse_name(b"ba\x01sh\0"), None);
+        assert_eq!(parse_name(b"no terminator"), None);
+    }
+
+    #[test]
+    fn test_is_kernel_ptr() {
+        assert!(is_kernel_ptr(KERNEL_PTR));
+        assert!(!is_kernel_ptr(0x0804_8000));
+        assert!(!is_kernel_ptr(0));
+    }
+
+    #[test]
+    fn test_carve_task_struct() {
+        let mut chunk = vec![0u8; 0x200];
+        let comm = 0x100;
+        write_ptr(&mut chunk, comm - 2 * PTR_SIZE, KERNEL_PTR + 0x40);
+        write_ptr(&mut chunk, comm - PTR_SIZE, KERNEL_PTR + 0x40);
+        chunk[comm..comm + 5].copy_from_slice(b"sshd\0");
+
+        let mut processes = Vec::new();
+        carve_task_structs(0x1000_0000, &chunk, chunk.len(), &mut processes);
+
+        assert_eq!(
+            processes,
+            [CarvedProcess {
+                kind: CarvedKind::TaskStruct,
+                phys_addr: 0x1000_0000 + comm as u64,
+                name: "sshd".into(),
+                pid: None,
+                asid: None,
+            }]
+        );
+
+        // the comm has to be preceded by matching creds
+        write_ptr(&mut chunk, comm - PTR_SIZE, KERNEL_PTR + 0x80);
+        processes.clear();
+        carve_task_structs(0, &chunk, chunk.len(), &mut processes);
+        assert!(processes.is_empty());
+    }
+
+    #[test]
+    fn test_carve_task_struct_past_scan_len() {
+        let mut chunk = vec![0u8; 0x200];
+        let comm = 0x100;
+        write_ptr(&mut chunk, comm - 2 * PTR_SIZE, KERNEL_PTR);
+        write_ptr(&mut chunk, comm - PTR_SIZE, KERNEL_PTR);
+        chunk[comm..comm + 5].copy_from_slice(b"sshd\0");
+
+        // found in the overlap, so left for the next chunk
+        let mut processes = Vec::new();
+        carve_task_structs(0, &chunk, comm, &mut processes);
+        assert!(processes.is_empty());
+    }
+
+    #[cfg(any(feature = "i386", feature = "x86_64"))]
+    #[test]
+    fn test_carve_eprocess() {
+        const DTB_OFFSET: usize = if PTR_SIZE == 8 { 0x28 } else { 0x18 };
+
+        let mut chunk = vec![0u8; 0x1000];
+        let header = 0x40;
+        chunk[header + 4..header + 8].copy_from_slice(b"Proc");
+
+        let eprocess = header + 2 * PTR_SIZE;
+        chunk[eprocess] = 3;
+        chunk[eprocess + 2] = 0x2c;
+        write_ptr(&mut chunk, eprocess + 8, KERNEL_PTR + 0x100);
+        write_ptr(&mut chunk, eprocess + 8 + PTR_SIZE, KERNEL_PTR + 0x100);
+        write_ptr(&mut chunk, eprocess + DTB_OFFSET, 0x1a_b000);
+
+        write_ptr(&mut chunk, eprocess + 0x80, 0x1f4);
+        write_ptr(&mut chunk, eprocess + 0x80 + PTR_SIZE, KERNEL_PTR + 0x200);
+        write_ptr(
+            &mut chunk,
+            eprocess + 0x80 + 2 * PTR_SIZE,
+            KERNEL_PTR + 0x300,
+        );
+        chunk[eprocess + 0x180..eprocess + 0x18c].copy_from_slice(b"explorer.exe");
+
+        let mut processes = Vec::new();
+        carve_eprocesses(0x2000, &chunk, chunk.len(), &mut processes);
+
+        assert_eq!(
+            processes,
+            [CarvedProcess {
+                kind: CarvedKind::Eprocess,
+                phys_addr: 0x2000 + eprocess as u64,
+                name: "explorer.exe".into(),
+                pid: Some(0x1f4),
+                asid: Some(0x1a_b000),
+            }]
+        );
+
+        // without the process object type, the pool tag alone isn't enough
+        chunk[eprocess] = 0;
+        processes.clear();
+        carve_eprocesses(0x2000, &chunk, chunk.len(), &mut processes);
+        assert!(processes.is_empty());
+    }
+}
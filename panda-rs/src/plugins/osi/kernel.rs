@@ -0,0 +1,234 @@
+use super::threads::field_offset;
+use crate::mem::virtual_memory_read;
+use crate::plugins::cosi;
+use crate::prelude::*;
+
+use once_cell::sync::OnceCell;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Size of each field of `struct new_utsname`, including the nul terminator
+const UTS_FIELD_LEN: usize = 65;
+
+/// The most bytes of `linux_banner` to read when looking for the version
+const BANNER_LEN: usize = 0x200;
+
+/// An error encountered while detecting the version of the guest kernel
+#[derive(thiserror::Error, Debug)]
+pub enum KernelVersionError {
+    #[error("kernel version symbols not found, is cosi loaded with a volatility profile?")]
+    MissingSymbols,
+
+    #[error("failed to read the kernel version from guest memory")]
+    ReadFailed,
+
+    #[error("failed to parse kernel version {0:?}")]
+    InvalidVersion(String),
+}
+
+/// The version of a Linux kernel, as reported by `uname -r`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+
+    /// The full release string, including any distribution suffix (such as
+    /// `5.4.0-42-generic`)
+    pub release: String,
+}
+
+impl KernelVersion {
+    /// Check whether this version is at least `major.minor`
+    pub fn at_least(&self, major: u32, minor: u32) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+}
+
+impl PartialOrd for KernelVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KernelVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch, &self.release).cmp(&(
+            other.major,
+            other.minor,
+            other.patch,
+            &other.release,
+        ))
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.release)
+    }
+}
+
+impl FromStr for KernelVersion {
+    type Err = KernelVersionError;
+
+    /// Parse a release string such as `4.19.0-16-amd64` or `2.6.32`
+    fn from_str(release: &str) -> Result<Self, Self::Err> {
+        let invalid = || KernelVersionError::InvalidVersion(release.to_owned());
+        let release = release.trim();
+
+        let version_len = release
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(release.len());
+        let mut parts = release[..version_len]
+            .split('.')
+            .map(|part| part.parse::<u32>().map_err(|_| invalid()));
+
+        let major = parts.next().ok_or_else(invalid)??;
+        let minor = parts.next().ok_or_else(invalid)??;
+        let patch = parts.next().transpose()?.unwrap_or(0);
+
+        Ok(Self {
+            major,
+            minor,
+            patch,
+            release: release.to_owned(),
+        })
+    }
+}
+
+/// Read a nul-terminated string out of a buffer
+fn c_str(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// Read `init_uts_ns.name.release`
+fn release_from_utsname(cpu: &mut CPUState) -> Option<Result<String, KernelVersionError>> {
+    cosi::symbol_from_name("init_uts_ns")?;
+
+    let uts_namespace = cosi::type_from_name("uts_namespace")?;
+    let new_utsname = cosi::type_from_name("new_utsname")?;
+    let name = field_offset(&uts_namespace, "name")?;
+    let release = field_offset(&new_utsname, "release")?;
+    let addr = cosi::symbol_addr_from_name("init_uts_ns") + name + release;
+
+    Some(
+        virtual_memory_read(cpu, addr, UTS_FIELD_LEN)
+            .map(|bytes| c_str(&bytes))
+            .map_err(|_| KernelVersionError::ReadFailed),
+    )
+}
+
+/// Read `linux_banner` (`Linux version <release> (<builder>) ...`)
+fn release_from_banner(cpu: &mut CPUState) -> Option<Result<String, KernelVersionError>> {
+    cosi::symbol_from_name("linux_banner")?;
+
+    let addr = cosi::symbol_addr_from_name("linux_banner");
+    let banner = virtual_memory_read(cpu, addr, BANNER_LEN)
+        .map(|bytes| c_str(&bytes))
+        .map_err(|_| KernelVersionError::ReadFailed);
+
+    Some(banner.and_then(|banner| {
+        banner
+            .strip_prefix("Linux version ")
+            .and_then(|rest| rest.split_whitespace().next())
+            .map(String::from)
+            .ok_or(KernelVersionError::InvalidVersion(banner))
+    }))
+}
+
+static VERSION: OnceCell<KernelVersion> = OnceCell::new();
+
+/// Get the version of the guest's Linux kernel, read from the kernel's `utsname` (or
+/// its version banner if `utsname` isn't in the profile). Requires cosi to be loaded
+/// with a volatility profile.
+///
+/// The version is cached after it has been read successfully, so this can be called
+/// from hot callbacks.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::plugins::osi;
+/// use panda::prelude::*;
+///
+/// # let cpu: &mut CPUState = todo!();
+/// let version = osi::kernel_version(cpu).unwrap();
+/// let state_field = if version.at_least(5, 14) { "__state" } else { "state" };
+/// ```
+pub fn kernel_version(cpu: &mut CPUState) -> Result<KernelVersion, KernelVersionError> {
+    if let Some(version) = VERSION.get() {
+        return Ok(version.clone());
+    }
+
+    let release = release_from_utsname(cpu)
+        .or_else(|| release_from_banner(cpu))
+        .ok_or(KernelVersionError::MissingSymbols)??;
+    let version: KernelVersion = release.parse()?;
+
+    Ok(VERSION.get_or_init(|| version).clone())
+}
+
+/// How a kernel configuration option shows up in a volatility profile
+enum ConfigEvidence {
+    /// A global symbol only exists if the option is enabled
+    Symbol(&'static str),
+
+    /// A struct only has a field if the option is enabled
+    Field(&'static str, &'static str),
+
+    /// A type only exists if the option is enabled
+    Type(&'static str),
+}
+
+use ConfigEvidence::*;
+
+/// Kernel configuration options which can be derived from a volatility profile
+const CONFIG_EVIDENCE: &[(&str, ConfigEvidence)] = &[
+    ("CONFIG_SMP", Symbol("__per_cpu_offset")),
+    ("CONFIG_MODULES", Symbol("modules")),
+    ("CONFIG_KALLSYMS", Symbol("kallsyms_names")),
+    ("CONFIG_IKCONFIG", Symbol("kernel_config_data")),
+    ("CONFIG_KPROBES", Symbol("kprobe_table")),
+    ("CONFIG_SLUB", Type("kmem_cache_cpu")),
+    ("CONFIG_SLAB", Type("array_cache")),
+    (
+        "CONFIG_THREAD_INFO_IN_TASK",
+        Field("task_struct", "thread_info"),
+    ),
+    ("CONFIG_CGROUPS", Field("task_struct", "cgroups")),
+    ("CONFIG_SECCOMP", Field("task_struct", "seccomp")),
+    ("CONFIG_AUDITSYSCALL", Field("task_struct", "audit_context")),
+    ("CONFIG_SCHED_INFO", Field("task_struct", "sched_info")),
+    ("CONFIG_NUMA", Field("task_struct", "mempolicy")),
+    ("CONFIG_MEMCG", Field("mm_struct", "owner")),
+];
+
+/// Get whether a kernel configuration option (such as `CONFIG_SLUB`) is enabled in the
+/// guest kernel, as derived from the volatility profile loaded by cosi.
+///
+/// Returns `None` if no profile is loaded or the option can't be derived from the
+/// profile. See [`derivable_config_flags`] for the options which are supported.
+pub fn kernel_config_flag(flag: &str) -> Option<bool> {
+    let (_, evidence) = CONFIG_EVIDENCE.iter().find(|(name, _)| *name == flag)?;
+
+    // if the profile doesn't describe task_struct, no profile is loaded
+    cosi::type_from_name("task_struct")?;
+
+    Some(match evidence {
+        Symbol(symbol) => cosi::symbol_from_name(symbol).is_some(),
+        Type(ty) => cosi::type_from_name(ty).is_some(),
+        Field(ty, field) => cosi::type_from_name(ty)
+            .and_then(|ty| field_offset(&ty, field))
+            .is_some(),
+    })
+}
+
+/// Get the kernel configuration options which [`kernel_config_flag`] can derive from a
+/// volatility profile
+pub fn derivable_config_flags() -> impl Iterator<Item = &'static str> {
+    CONFIG_EVIDENCE.iter().map(|(name, _)| *name)
+}
@@ -0,0 +1,251 @@
+use super::{threads, OsiModule, OsiProc, OsiProcHandle, ThreadError, ThreadInfo, OSI};
+use crate::os::users::{self, Credentials, CredentialsError};
+use crate::prelude::*;
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::ptr;
+
+fn string_from_raw(string: *const c_char) -> Option<String> {
+    if string.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(string) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// A process, as reported by OSI
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Process {
+    /// The address of the process's task descriptor (`task_struct` or `EPROCESS`)
+    pub taskd: target_ptr_t,
+    pub pgd: target_ptr_t,
+    pub asid: target_ptr_t,
+    pub pid: target_pid_t,
+    pub ppid: target_pid_t,
+    pub name: String,
+    pub create_time: u64,
+}
+
+impl From<&OsiProc> for Process {
+    fn from(process: &OsiProc) -> Self {
+        Self {
+            taskd: process.taskd,
+            pgd: process.pgd,
+            asid: process.asid,
+            pid: process.pid,
+            ppid: process.ppid,
+            name: string_from_raw(process.name).unwrap_or_default(),
+            create_time: process.create_time,
+        }
+    }
+}
+
+impl Process {
+    /// A raw process for passing back to OSI, which doesn't own its name
+    fn to_raw(&self) -> OsiProc {
+        OsiProc {
+            taskd: self.taskd,
+            pgd: self.pgd,
+            asid: self.asid,
+            pid: self.pid,
+            ppid: self.ppid,
+            name: ptr::null_mut(),
+            pages: ptr::null_mut(),
+            create_time: self.create_time,
+        }
+    }
+
+    /// Get the handle identifying this process
+    pub fn handle(&self) -> ProcessHandle {
+        ProcessHandle {
+            taskd: self.taskd,
+            asid: self.asid,
+        }
+    }
+
+    /// Get the memory mappings (executable and shared libraries) of this process
+    pub fn mappings(&self, cpu: &mut CPUState) -> Vec<Mapping> {
+        let mappings = OSI.get_mappings(cpu, &mut self.to_raw());
+        if mappings.is_null() {
+            return Vec::new();
+        }
+
+        mappings.iter().map(Mapping::from).collect()
+    }
+
+    /// Find the mapping containing `addr` in this process, if any
+    pub fn mapping_containing(&self, cpu: &mut CPUState, addr: target_ptr_t) -> Option<Mapping> {
+        self.mappings(cpu)
+            .into_iter()
+            .find(|mapping| mapping.contains(addr))
+    }
+
+    /// List the threads of this process. See [`threads`] for requirements.
+    pub fn threads(&self, cpu: &mut CPUState) -> Result<Vec<ThreadInfo>, ThreadError> {
+        threads(cpu, self.pid)
+    }
+
+    /// Read the uids and gids of this process. See [`users::credentials`] for
+    /// requirements.
+    pub fn credentials(&self, cpu: &mut CPUState) -> Result<Credentials, CredentialsError> {
+        users::credentials(cpu, self.taskd)
+    }
+
+    /// Check whether the current program counter is within a shared object of this
+    /// process rather than its main executable
+    pub fn in_shared_object(&self, cpu: &mut CPUState) -> bool {
+        OSI.in_shared_object(cpu, &self.to_raw())
+    }
+}
+
+/// A minimal handle identifying a process, which can be used to look up the full
+/// details of the process
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ProcessHandle {
+    pub taskd: target_ptr_t,
+    pub asid: target_ptr_t,
+}
+
+impl From<&OsiProcHandle> for ProcessHandle {
+    fn from(handle: &OsiProcHandle) -> Self {
+        Self {
+            taskd: handle.taskd,
+            asid: handle.asid,
+        }
+    }
+}
+
+impl ProcessHandle {
+    fn to_raw(self) -> OsiProcHandle {
+        OsiProcHandle {
+            taskd: self.taskd,
+            asid: self.asid,
+        }
+    }
+
+    /// Get the full details of the process, or `None` if OSI can't find it
+    pub fn process(&self, cpu: &mut CPUState) -> Option<Process> {
+        OSI.get_process(cpu, &self.to_raw())
+            .map(|process| Process::from(&*process))
+    }
+
+    pub fn pid(&self, cpu: &mut CPUState) -> target_pid_t {
+        OSI.get_process_pid(cpu, &self.to_raw())
+    }
+
+    pub fn ppid(&self, cpu: &mut CPUState) -> target_pid_t {
+        OSI.get_process_ppid(cpu, &self.to_raw())
+    }
+}
+
+/// A thread of a process, as reported by OSI
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Thread {
+    pub pid: target_pid_t,
+    pub tid: target_pid_t,
+}
+
+/// A kernel module or a memory mapping of a process, as reported by OSI
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Module {
+    /// The address of the module's descriptor
+    pub modd: target_ptr_t,
+    pub base: target_ptr_t,
+    pub size: target_ptr_t,
+
+    /// The path of the file backing the module, if any
+    pub file: Option<String>,
+    pub name: Option<String>,
+}
+
+/// A memory mapping of a process, such as its executable or a shared library
+pub type Mapping = Module;
+
+impl From<&OsiModule> for Module {
+    fn from(module: &OsiModule) -> Self {
+        Self {
+            modd: module.modd,
+            base: module.base,
+            size: module.size,
+            file: string_from_raw(module.file),
+            name: string_from_raw(module.name),
+        }
+    }
+}
+
+impl Module {
+    /// The address just past the end of the module
+    pub fn end(&self) -> target_ptr_t {
+        self.base + self.size
+    }
+
+    /// Check whether `addr` lies within the module
+    pub fn contains(&self, addr: target_ptr_t) -> bool {
+        (self.base..self.end()).contains(&addr)
+    }
+}
+
+/// Get the process currently running on the CPU
+pub fn current_process(cpu: &mut CPUState) -> Option<Process> {
+    OSI.get_current_process(cpu)
+        .map(|process| Process::from(&*process))
+}
+
+/// Get the handle of the process currently running on the CPU
+pub fn current_process_handle(cpu: &mut CPUState) -> Option<ProcessHandle> {
+    OSI.get_current_process_handle(cpu)
+        .map(|handle| ProcessHandle::from(&*handle))
+}
+
+/// Get the thread currently running on the CPU
+pub fn current_thread(cpu: &mut CPUState) -> Option<Thread> {
+    OSI.get_current_thread(cpu).map(|thread| Thread {
+        pid: thread.pid,
+        tid: thread.tid,
+    })
+}
+
+/// Iterate over every process running on the system
+pub fn processes(cpu: &mut CPUState) -> impl Iterator<Item = Process> {
+    let processes = OSI.get_processes(cpu);
+    let processes: Vec<_> = if processes.is_null() {
+        Vec::new()
+    } else {
+        processes.iter().map(Process::from).collect()
+    };
+
+    processes.into_iter()
+}
+
+/// Iterate over the handles of every process running on the system, which is cheaper
+/// than [`processes`] when only a few processes are needed
+pub fn process_handles(cpu: &mut CPUState) -> impl Iterator<Item = ProcessHandle> {
+    let handles = OSI.get_process_handles(cpu);
+    let handles: Vec<_> = if handles.is_null() {
+        Vec::new()
+    } else {
+        handles.iter().map(ProcessHandle::from).collect()
+    };
+
+    handles.into_iter()
+}
+
+/// Find the running process with the given pid
+pub fn process_by_pid(cpu: &mut CPUState, pid: target_pid_t) -> Option<Process> {
+    processes(cpu).find(|process| process.pid == pid)
+}
+
+/// Get the kernel modules loaded by the system
+pub fn kernel_modules(cpu: &mut CPUState) -> Vec<Module> {
+    let modules = OSI.get_modules(cpu);
+    if modules.is_null() {
+        return Vec::new();
+    }
+
+    modules.iter().map(Module::from).collect()
+}
@@ -0,0 +1,403 @@
+use super::OSI;
+use crate::mem::read_guest_type;
+use crate::plugins::cosi::{self, VolatilityStruct};
+use crate::prelude::*;
+use crate::GuestReadFail;
+
+use std::mem::size_of;
+
+/// The size of a kernel stack in the default kernel configuration, used when the
+/// volatility profile doesn't include `union thread_union`
+#[cfg(any(
+    feature = "x86_64",
+    feature = "aarch64",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+const DEFAULT_THREAD_SIZE: target_ptr_t = 0x4000;
+#[cfg(any(
+    feature = "i386",
+    feature = "arm",
+    feature = "mips",
+    feature = "mipsel",
+    feature = "ppc"
+))]
+const DEFAULT_THREAD_SIZE: target_ptr_t = 0x2000;
+
+/// The number of bytes left unused between the user-mode registers saved by the kernel
+/// and the top of the kernel stack (`TOP_OF_KERNEL_STACK_PADDING` on x86, the 8 bytes
+/// below `THREAD_START_SP` on ARM and the 32 bytes for argument slots on MIPS)
+#[cfg(any(feature = "x86_64", feature = "aarch64", feature = "ppc"))]
+const STACK_PADDING: target_ptr_t = 0;
+#[cfg(any(feature = "i386", feature = "arm"))]
+const STACK_PADDING: target_ptr_t = 8;
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+const STACK_PADDING: target_ptr_t = 32;
+
+const TASK_INTERRUPTIBLE: target_ulong = 0x1;
+const TASK_UNINTERRUPTIBLE: target_ulong = 0x2;
+const TASK_STOPPED: target_ulong = 0x4;
+const TASK_TRACED: target_ulong = 0x8;
+const EXIT_DEAD: i32 = 0x10;
+const EXIT_ZOMBIE: i32 = 0x20;
+
+/// An error encountered while enumerating threads
+#[derive(thiserror::Error, Debug)]
+pub enum ThreadError {
+    #[error("no process with pid {0} was found")]
+    NoSuchProcess(target_pid_t),
+
+    #[error("{0} not found, is cosi loaded with a volatility profile?")]
+    MissingType(&'static str),
+
+    #[error("{0} not found in the volatility profile")]
+    MissingField(&'static str),
+
+    #[error("failed to read thread list from guest memory")]
+    ReadFailed,
+}
+
+impl From<GuestReadFail> for ThreadError {
+    fn from(_: GuestReadFail) -> Self {
+        Self::ReadFailed
+    }
+}
+
+/// The scheduling state of a thread, as shown by `ps`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Running or waiting to run (`R`)
+    Running,
+
+    /// Sleeping, can be woken by a signal (`S`)
+    Sleeping,
+
+    /// Sleeping, cannot be woken by a signal, typically waiting on IO (`D`)
+    DiskSleep,
+
+    /// Stopped by a signal (`T`)
+    Stopped,
+
+    /// Stopped by a debugger (`t`)
+    Traced,
+
+    /// Exited but not yet reaped by its parent (`Z`)
+    Zombie,
+
+    /// Exited and being removed (`X`)
+    Dead,
+
+    /// Any other state, as the raw value of the task's state field
+    Other(target_ulong),
+}
+
+impl ThreadState {
+    fn from_raw(state: target_ulong, exit_state: i32) -> Self {
+        if exit_state & EXIT_ZOMBIE != 0 {
+            return Self::Zombie;
+        }
+
+        if exit_state & EXIT_DEAD != 0 {
+            return Self::Dead;
+        }
+
+        match state {
+            0 => Self::Running,
+            _ if state & TASK_UNINTERRUPTIBLE != 0 => Self::DiskSleep,
+            _ if state & TASK_INTERRUPTIBLE != 0 => Self::Sleeping,
+            _ if state & TASK_STOPPED != 0 => Self::Stopped,
+            _ if state & TASK_TRACED != 0 => Self::Traced,
+            _ => Self::Other(state),
+        }
+    }
+}
+
+/// A thread of a process, found by [`threads`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ThreadInfo {
+    pub pid: target_pid_t,
+    pub tid: target_pid_t,
+    pub state: ThreadState,
+
+    /// The address of the thread's `task_struct`
+    pub task: target_ptr_t,
+
+    /// The base of the thread's kernel stack
+    pub stack: target_ptr_t,
+
+    /// Whether the thread is the one currently executing on the CPU passed to
+    /// [`threads`]. The registers of the current thread should be read from the CPU
+    /// rather than with [`saved_regs`].
+    pub current: bool,
+}
+
+/// The user-mode registers of a thread, as saved by the kernel (`struct pt_regs`) at
+/// the top of the thread's kernel stack when it entered the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedRegs {
+    /// The address the registers were read from
+    pub addr: target_ptr_t,
+
+    words: Vec<target_ulong>,
+}
+
+impl SavedRegs {
+    /// The raw contents of `struct pt_regs`, one register-sized word at a time
+    pub fn words(&self) -> &[target_ulong] {
+        &self.words
+    }
+
+    /// Get a register by its field name in `struct pt_regs` for the guest kernel (for
+    /// example `"ip"` or `"r12"` on x86_64). For array fields, this is the first
+    /// element.
+    pub fn field(&self, name: &str) -> Option<target_ulong> {
+        let pt_regs = cosi::type_from_name("pt_regs")?;
+        let offset = field_offset(&pt_regs, name)?;
+
+        self.words
+            .get(offset as usize / size_of::<target_ulong>())
+            .copied()
+    }
+
+    fn array_field(&self, name: &str, index: usize) -> Option<target_ulong> {
+        let pt_regs = cosi::type_from_name("pt_regs")?;
+        let offset = field_offset(&pt_regs, name)? as usize;
+
+        self.words
+            .get(offset / size_of::<target_ulong>() + index)
+            .copied()
+    }
+
+    /// The user-mode program counter of the thread
+    pub fn pc(&self) -> Option<target_ulong> {
+        if cfg!(any(feature = "i386", feature = "x86_64")) {
+            self.field("ip")
+        } else if cfg!(feature = "arm") {
+            self.array_field("uregs", 15)
+        } else if cfg!(feature = "aarch64") {
+            self.field("pc")
+        } else if cfg!(feature = "ppc") {
+            self.field("nip")
+        } else {
+            self.field("cp0_epc")
+        }
+    }
+
+    /// The user-mode stack pointer of the thread
+    pub fn sp(&self) -> Option<target_ulong> {
+        if cfg!(any(
+            feature = "i386",
+            feature = "x86_64",
+            feature = "aarch64"
+        )) {
+            self.field("sp")
+        } else if cfg!(feature = "arm") {
+            self.array_field("uregs", 13)
+        } else if cfg!(feature = "ppc") {
+            self.array_field("gpr", 1)
+        } else {
+            self.array_field("regs", 29)
+        }
+    }
+}
+
+/// Get the offset of a field within a struct, if the struct has a field of that name
+pub(super) fn field_offset(vol_struct: &VolatilityStruct, name: &str) -> Option<target_ptr_t> {
+    vol_struct
+        .fields()
+        .find(|(field, _)| field == name)
+        .map(|(_, offset)| offset)
+}
+
+struct TaskLayout {
+    pid: target_ptr_t,
+    tgid: target_ptr_t,
+    state: target_ptr_t,
+    state_is_long: bool,
+    exit_state: target_ptr_t,
+    stack: target_ptr_t,
+    thread_list: ThreadList,
+}
+
+/// How the threads of a process are linked together
+enum ThreadList {
+    /// `task_struct.thread_group`, a circular list through every thread
+    ThreadGroup(target_ptr_t),
+
+    /// `signal_struct.thread_head`, linking each `task_struct.thread_node` (Linux 6.7+)
+    ThreadHead {
+        signal: target_ptr_t,
+        thread_head: target_ptr_t,
+        thread_node: target_ptr_t,
+    },
+}
+
+impl TaskLayout {
+    fn load() -> Result<Self, ThreadError> {
+        let task_struct =
+            cosi::type_from_name("task_struct").ok_or(ThreadError::MissingType("task_struct"))?;
+        let field = |name: &'static str| {
+            field_offset(&task_struct, name).ok_or(ThreadError::MissingField(name))
+        };
+
+        let (state, state_name) = match field_offset(&task_struct, "__state") {
+            Some(offset) => (offset, "__state"),
+            None => (field("state")?, "state"),
+        };
+
+        let thread_list = match field_offset(&task_struct, "thread_group") {
+            Some(thread_group) => ThreadList::ThreadGroup(thread_group),
+            None => {
+                let signal_struct = cosi::type_from_name("signal_struct")
+                    .ok_or(ThreadError::MissingType("signal_struct"))?;
+
+                ThreadList::ThreadHead {
+                    signal: field("signal")?,
+                    thread_head: field_offset(&signal_struct, "thread_head")
+                        .ok_or(ThreadError::MissingField("signal_struct.thread_head"))?,
+                    thread_node: field("thread_node")?,
+                }
+            }
+        };
+
+        Ok(Self {
+            pid: field("pid")?,
+            tgid: field("tgid")?,
+            state,
+            state_is_long: task_struct.type_of(state_name).contains("long"),
+            exit_state: field("exit_state")?,
+            stack: field("stack")?,
+            thread_list,
+        })
+    }
+
+    fn read_thread(
+        &self,
+        cpu: &mut CPUState,
+        task: target_ptr_t,
+        current_tid: Option<target_pid_t>,
+    ) -> Result<ThreadInfo, GuestReadFail> {
+        let state = if self.state_is_long {
+            read_guest_type::<target_ulong>(cpu, task + self.state)?
+        } else {
+            read_guest_type::<u32>(cpu, task + self.state)? as target_ulong
+        };
+        let exit_state = read_guest_type::<i32>(cpu, task + self.exit_state)?;
+        let tid = read_guest_type::<target_pid_t>(cpu, task + self.pid)?;
+
+        Ok(ThreadInfo {
+            pid: read_guest_type(cpu, task + self.tgid)?,
+            tid,
+            state: ThreadState::from_raw(state, exit_state),
+            task,
+            stack: read_guest_type(cpu, task + self.stack)?,
+            current: current_tid == Some(tid),
+        })
+    }
+
+    /// Get the list head the threads of a process are linked from, along with the
+    /// offset of the list node within each `task_struct`
+    fn list(
+        &self,
+        cpu: &mut CPUState,
+        leader: target_ptr_t,
+    ) -> Result<(target_ptr_t, target_ptr_t), GuestReadFail> {
+        match self.thread_list {
+            ThreadList::ThreadGroup(thread_group) => Ok((leader + thread_group, thread_group)),
+            ThreadList::ThreadHead {
+                signal,
+                thread_head,
+                thread_node,
+            } => {
+                let signal: target_ptr_t = read_guest_type(cpu, leader + signal)?;
+
+                Ok((signal + thread_head, thread_node))
+            }
+        }
+    }
+}
+
+/// The maximum number of threads followed in a thread list, to guard against looping
+/// forever on a corrupt list
+const MAX_THREADS: usize = 0x10000;
+
+/// List the threads of the process with the given pid, similar to a debugger's
+/// `info threads`.
+///
+/// Requires a Linux guest, with cosi loaded alongside a volatility profile for the
+/// guest kernel.
+pub fn threads(cpu: &mut CPUState, pid: target_pid_t) -> Result<Vec<ThreadInfo>, ThreadError> {
+    let layout = TaskLayout::load()?;
+
+    let processes = OSI.get_processes(cpu);
+    if processes.is_null() {
+        return Err(ThreadError::NoSuchProcess(pid));
+    }
+
+    let leader = processes
+        .iter()
+        .find(|process| process.pid == pid)
+        .ok_or(ThreadError::NoSuchProcess(pid))?
+        .taskd;
+
+    let current_tid = OSI.get_current_thread(cpu).map(|thread| thread.tid);
+    let (head, node_offset) = layout.list(cpu, leader)?;
+
+    let mut threads = Vec::new();
+
+    // With `thread_group` the leader is itself a node of the list, rather than the
+    // list being headed from outside of any task
+    if let ThreadList::ThreadGroup(_) = layout.thread_list {
+        threads.push(layout.read_thread(cpu, leader, current_tid)?);
+    }
+
+    let mut node: target_ptr_t = read_guest_type(cpu, head)?;
+
+    while node != head && threads.len() < MAX_THREADS {
+        let task = node - node_offset;
+        threads.push(layout.read_thread(cpu, task, current_tid)?);
+
+        node = read_guest_type(cpu, node)?;
+    }
+
+    Ok(threads)
+}
+
+/// The size of a kernel stack (`THREAD_SIZE`), which depends on the kernel's
+/// configuration, such as being larger for kernels built with KASAN. This is the size of
+/// `union thread_union`, which wraps a stack-sized array, if the profile includes it.
+pub fn thread_size() -> target_ptr_t {
+    cosi::type_from_name("thread_union")
+        .map(|thread_union| thread_union.size() as target_ptr_t)
+        .filter(|&size| size != 0)
+        .unwrap_or(DEFAULT_THREAD_SIZE)
+}
+
+/// Read the user-mode registers the kernel saved for a thread when it last entered
+/// the kernel (`task_pt_regs`). For threads which are not currently running, these are
+/// the registers the thread will resume with.
+///
+/// Requires a Linux guest, with cosi loaded alongside a volatility profile for the
+/// guest kernel. The registers are found from the top of the kernel stack, so rely on
+/// [`thread_size`].
+pub fn saved_regs(cpu: &mut CPUState, thread: &ThreadInfo) -> Result<SavedRegs, ThreadError> {
+    let pt_regs = cosi::type_from_name("pt_regs").ok_or(ThreadError::MissingType("pt_regs"))?;
+    let size = pt_regs.size() as target_ptr_t;
+    let addr = thread.stack + thread_size() - STACK_PADDING - size;
+
+    let words = (0..size as usize / size_of::<target_ulong>())
+        .map(|i| {
+            read_guest_type::<target_ulong>(
+                cpu,
+                addr + (i * size_of::<target_ulong>()) as target_ptr_t,
+            )
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(SavedRegs { addr, words })
+}
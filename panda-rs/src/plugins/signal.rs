@@ -0,0 +1,137 @@
+//! Bindings for the PANDA 'signal' plugin, which tracks the signals sent between
+//! processes of Linux guests and can suppress them.
+//!
+//! Each signal sent is reported to [`on_signal`](SignalCallbacks::on_signal)
+//! callbacks, and signals can be blocked from being delivered with [`block`] and
+//! [`block_for_process`], such as to keep a process under analysis from being killed.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plugins::signal::{self, SignalCallbacks, SignalEvent, SIGKILL, SIGTERM};
+//! use panda::prelude::*;
+//! use panda::PppCallback;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     signal::block_for_process(SIGKILL, "target");
+//!     signal::block_for_process(SIGTERM, "target");
+//!
+//!     PppCallback::new().on_signal(|_, event: &SignalEvent| {
+//!         println!("{:?}", event);
+//!     });
+//! }
+//! ```
+use crate::plugin_import;
+use crate::sys::{target_pid_t, CPUState};
+
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::os::raw::c_char;
+
+plugin_import! {
+    static SIGNAL: Signal = extern "signal" {
+        fn block_sig(sig: i32);
+        fn block_sig_by_proc(sig: i32, proc_name: *const c_char);
+        callbacks {
+            fn on_signal(cpu: &mut CPUState, event: &SignalEvent);
+        }
+    };
+}
+
+pub const SIGHUP: i32 = 1;
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGABRT: i32 = 6;
+pub const SIGFPE: i32 = 8;
+pub const SIGKILL: i32 = 9;
+pub const SIGSEGV: i32 = 11;
+pub const SIGPIPE: i32 = 13;
+pub const SIGALRM: i32 = 14;
+pub const SIGTERM: i32 = 15;
+
+#[cfg(not(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+)))]
+pub const SIGBUS: i32 = 7;
+
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+pub const SIGBUS: i32 = 10;
+
+/// A signal sent by a process, as reported by the plugin
+#[repr(C)]
+pub struct SignalEvent {
+    /// The signal number
+    pub sig: i32,
+
+    /// Whether the signal was blocked from being delivered by [`block`] or
+    /// [`block_for_process`]
+    pub suppressed: bool,
+
+    pub src_pid: target_pid_t,
+    pub dst_pid: target_pid_t,
+
+    /// The names of the sending and receiving processes, or null if unknown
+    pub src_name: *const c_char,
+    pub dst_name: *const c_char,
+}
+
+fn string_from_raw(string: *const c_char) -> Option<String> {
+    if string.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(string) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+impl SignalEvent {
+    /// The name of the process which sent the signal
+    pub fn src_name(&self) -> Option<String> {
+        string_from_raw(self.src_name)
+    }
+
+    /// The name of the process the signal was sent to
+    pub fn dst_name(&self) -> Option<String> {
+        string_from_raw(self.dst_name)
+    }
+}
+
+impl fmt::Debug for SignalEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignalEvent")
+            .field("sig", &self.sig)
+            .field("suppressed", &self.suppressed)
+            .field("src_pid", &self.src_pid)
+            .field("dst_pid", &self.dst_pid)
+            .field("src_name", &self.src_name())
+            .field("dst_name", &self.dst_name())
+            .finish()
+    }
+}
+
+/// Block the given signal from being delivered to any process
+pub fn block(sig: i32) {
+    SIGNAL.block_sig(sig);
+}
+
+/// Block the given signal from being delivered to processes with the given name
+pub fn block_for_process(sig: i32, process_name: &str) {
+    let name = CString::new(process_name).unwrap();
+
+    // the plugin keeps the pointer, so the name must outlive it
+    SIGNAL.block_sig_by_proc(sig, name.into_raw());
+}
@@ -9,4 +9,173 @@
 use crate::sys::{target_ptr_t, target_ulong, CPUState};
 use crate::{cbs::generate_syscalls_callbacks, plugin_import, regs::SyscallPc};
 
+use crate::abi::syscall::{SYSCALL_ARGS, SYSCALL_ARGS_LEN, SYSCALL_RET};
+use crate::{current_asid, current_ksp, regs, PppCallback};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
 generate_syscalls_callbacks!();
+
+/// The context of a syscall, with the arguments captured when the syscall was
+/// entered, delivered to [`on_all_sys_return_with_args`] callbacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallContext {
+    callno: target_ulong,
+    pc: target_ulong,
+    asid: target_ulong,
+    args: [target_ulong; SYSCALL_ARGS_LEN],
+    retval: target_ulong,
+}
+
+impl SyscallContext {
+    /// The syscall number
+    pub fn callno(&self) -> target_ulong {
+        self.callno
+    }
+
+    /// The program counter of the syscall instruction
+    pub fn pc(&self) -> target_ulong {
+        self.pc
+    }
+
+    /// The address space the syscall was made from
+    pub fn asid(&self) -> target_ulong {
+        self.asid
+    }
+
+    /// The raw arguments of the syscall, as they were when the syscall was entered
+    pub fn args(&self) -> &[target_ulong] {
+        &self.args
+    }
+
+    /// Get a single raw argument of the syscall, as it was when the syscall was entered
+    pub fn arg(&self, index: usize) -> Option<target_ulong> {
+        self.args.get(index).copied()
+    }
+
+    /// The value returned by the syscall
+    pub fn retval(&self) -> target_ulong {
+        self.retval
+    }
+}
+
+type ReturnCallback = Box<dyn FnMut(&mut CPUState, &SyscallContext) + Send + 'static>;
+
+/// A return callback, along with the syscall number it runs for, or `None` to run for
+/// every syscall
+struct ContextCallback {
+    callno: Option<target_ulong>,
+    callback: ReturnCallback,
+}
+
+/// Syscalls which have been entered but have yet to return, keyed by thread. Threads
+/// are identified by address space and kernel stack, so syscalls which never return
+/// are replaced by the next syscall made by the same thread.
+#[derive(Default)]
+struct PendingSyscalls {
+    pending: HashMap<(target_ulong, target_ulong), SyscallContext>,
+    callbacks: Vec<ContextCallback>,
+}
+
+lazy_static::lazy_static! {
+    static ref PENDING: Mutex<PendingSyscalls> = Mutex::new(PendingSyscalls::default());
+    static ref CONTEXT_CALLBACKS: (PppCallback, PppCallback) = install_context_callbacks();
+}
+
+fn install_context_callbacks() -> (PppCallback, PppCallback) {
+    let enter = PppCallback::new();
+    let exit = PppCallback::new();
+
+    enter.on_all_sys_enter(|cpu, pc, callno| {
+        let asid = current_asid(cpu);
+        let context = SyscallContext {
+            callno,
+            pc: pc.pc(),
+            asid,
+            args: SYSCALL_ARGS.map(|storage| storage.read(cpu)),
+            retval: 0,
+        };
+
+        let thread = (asid, current_ksp(cpu));
+        PENDING.lock().unwrap().pending.insert(thread, context);
+    });
+
+    exit.on_all_sys_return(|cpu, _, callno| {
+        let thread = (current_asid(cpu), current_ksp(cpu));
+
+        let mut pending = PENDING.lock().unwrap();
+        let context = match pending.pending.remove(&thread) {
+            Some(context) if context.callno == callno => context,
+            _ => return,
+        };
+
+        let context = SyscallContext {
+            retval: regs::get_reg(cpu, SYSCALL_RET),
+            ..context
+        };
+
+        let callbacks = pending
+            .callbacks
+            .iter_mut()
+            .filter(|cb| cb.callno.map_or(true, |wanted| wanted == callno));
+
+        for cb in callbacks {
+            (cb.callback)(cpu, &context);
+        }
+    });
+
+    (enter, exit)
+}
+
+/// Add a callback which runs whenever any syscall returns, providing the arguments the
+/// syscall was called with alongside its return value. Unlike reading arguments from
+/// registers in a return callback, the arguments are captured when the syscall is
+/// entered, so they are unaffected by the kernel clobbering argument registers.
+///
+/// The callback must not add further callbacks, as it is run while the pending syscall
+/// tracker is locked.
+///
+/// ### Example
+/// ```rust
+/// use panda::plugins::syscalls2::on_all_sys_return_with_args;
+///
+/// on_all_sys_return_with_args(|_, ctx| {
+///     println!("syscall {}{:x?} = {:#x}", ctx.callno(), ctx.args(), ctx.retval());
+/// });
+/// ```
+pub fn on_all_sys_return_with_args(
+    callback: impl FnMut(&mut CPUState, &SyscallContext) + Send + 'static,
+) {
+    add_context_callback(None, Box::new(callback));
+}
+
+/// Add a callback which runs whenever the syscall with the number `callno` returns,
+/// providing the arguments the syscall was called with alongside its return value. See
+/// [`on_all_sys_return_with_args`] for details.
+///
+/// ### Example
+/// ```rust
+/// use panda::plugins::syscalls2::on_sys_return_with_args;
+///
+/// // read on x86_64
+/// on_sys_return_with_args(0, |_, ctx| {
+///     println!("read{:x?} = {:#x}", ctx.args(), ctx.retval());
+/// });
+/// ```
+pub fn on_sys_return_with_args(
+    callno: target_ulong,
+    callback: impl FnMut(&mut CPUState, &SyscallContext) + Send + 'static,
+) {
+    add_context_callback(Some(callno), Box::new(callback));
+}
+
+fn add_context_callback(callno: Option<target_ulong>, callback: ReturnCallback) {
+    lazy_static::initialize(&CONTEXT_CALLBACKS);
+
+    PENDING
+        .lock()
+        .unwrap()
+        .callbacks
+        .push(ContextCallback { callno, callback });
+}
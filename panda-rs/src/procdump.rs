@@ -0,0 +1,384 @@
+//! Automatic dumping of process memory right before a process exits
+//!
+//! Transient processes in a replay are hard to capture by hand, as their memory is only
+//! around for a short window. This module hooks the `exit` and `exit_group` syscalls of
+//! Linux guests and, for each process matching a [`Target`], writes the contents of all
+//! of its mappings along with some metadata to the `procdumps` subdirectory of the
+//! [output directory](crate::output) while the process is still intact. The dumps can
+//! then be loaded into offline static analysis tools.
+//!
+//! Each dump is written to its own directory, `<name>-<pid>-<instr count>`, containing:
+//!
+//! * `info.txt` - the name, pid, ppid and asid of the process, and when it was dumped
+//! * `maps.txt` - one line per mapping: `start-end readable_bytes name file`
+//! * `<start>-<end>.bin` - the contents of each mapping. Pages which could not be read
+//! (such as those which are paged out) are filled with zeroes.
+//!
+//! Processes which end without making an exit syscall visible to the hooks (such as
+//! those killed by a signal) are caught by [`on_process_end`](crate::on_process_end),
+//! at which point their memory is no longer available, so only `info.txt` is written.
+//!
+//! Requires the `osi`, `syscalls2` and `hooks2` plugins.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::procdump::{self, Target};
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     procdump::dump_on_exit(Target::Name("dropper".into()));
+//! }
+//! ```
+
+use crate::mem::{page_size, virtual_memory_read_into};
+use crate::plugins::hooks2::Hooks2Callbacks;
+use crate::plugins::osi::{OsiProc, OSI};
+use crate::plugins::syscalls2::Syscalls2Callbacks;
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+use crate::{output, sanitize, PppCallback};
+
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// The subdirectory of the [output directory](crate::output) dumps are written to
+const OUTPUT_SUBDIR: &str = "procdumps";
+
+/// A process (or set of processes) to dump before exiting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Every process
+    All,
+
+    /// Processes with the given name, as reported by OSI
+    Name(String),
+
+    /// The process with the given pid
+    Pid(target_pid_t),
+}
+
+impl Target {
+    fn matches(&self, name: &str, pid: target_pid_t) -> bool {
+        match self {
+            Target::All => true,
+            Target::Name(target) => target == name,
+            Target::Pid(target) => *target == pid,
+        }
+    }
+}
+
+/// A single mapping written as part of a [`ProcessDump`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpedRegion {
+    pub start: target_ptr_t,
+    pub size: target_ptr_t,
+    pub name: String,
+    pub file: String,
+
+    /// The number of bytes of the mapping which could be read from the guest
+    pub readable_bytes: usize,
+}
+
+/// A process dump written to the output directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessDump {
+    pub name: String,
+    pub pid: target_pid_t,
+    pub ppid: target_pid_t,
+    pub asid: target_ptr_t,
+
+    /// The guest instruction count at the time of the dump. Only meaningful while
+    /// recording or replaying.
+    pub instr_count: u64,
+
+    /// The directory the dump was written to
+    pub dir: PathBuf,
+
+    /// The mappings of the process. Empty if the memory of the process could not be
+    /// captured.
+    pub regions: Vec<DumpedRegion>,
+
+    /// Whether the memory of the process was captured, as opposed to only its metadata
+    pub memory_captured: bool,
+}
+
+struct DumpState {
+    targets: Vec<Target>,
+    dumped: HashSet<(target_pid_t, target_ptr_t)>,
+    dumps: Vec<ProcessDump>,
+}
+
+impl DumpState {
+    fn is_target(&self, name: &str, pid: target_pid_t) -> bool {
+        self.targets.iter().any(|target| target.matches(name, pid))
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<DumpState> = Mutex::new(DumpState {
+        targets: Vec::new(),
+        dumped: HashSet::new(),
+        dumps: Vec::new(),
+    });
+    static ref CALLBACKS: (PppCallback, PppCallback, PppCallback) = install_callbacks();
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn install_callbacks() -> (PppCallback, PppCallback, PppCallback) {
+    let exit = PppCallback::new();
+    let exit_group = PppCallback::new();
+    let process_end = PppCallback::new();
+
+    exit.on_sys_exit_enter(|cpu, _, _| on_exit(cpu));
+    exit_group.on_sys_exit_group_enter(|cpu, _, _| on_exit(cpu));
+
+    process_end.on_process_end(|_, procname, asid, pid| on_process_end(procname, asid, pid));
+
+    (exit, exit_group, process_end)
+}
+
+fn on_exit(cpu: &mut CPUState) {
+    let process = match OSI.get_current_process(cpu) {
+        Some(process) => *process,
+        None => return,
+    };
+
+    let name = process.get_name().into_owned();
+    let key = (process.pid, process.asid);
+
+    // the process is marked as dumped before writing, so that the lock isn't held
+    // while writing the dump
+    {
+        let mut state = STATE.lock().unwrap();
+        if state.dumped.contains(&key) || !state.is_target(&name, process.pid) {
+            return;
+        }
+
+        state.dumped.insert(key);
+    }
+
+    let result = write_dump(cpu, &process, &output::subdir(OUTPUT_SUBDIR));
+
+    let mut state = STATE.lock().unwrap();
+    match result {
+        Ok(dump) => state.dumps.push(dump),
+        Err(err) => {
+            // allow another attempt, such as at exit_group after a failed exit
+            state.dumped.remove(&key);
+            eprintln!("Failed to dump process {} ({}): {}", name, process.pid, err);
+        }
+    }
+}
+
+fn on_process_end(procname: *const c_char, asid: target_ptr_t, pid: target_pid_t) {
+    let name = if procname.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(procname) }
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    {
+        let mut state = STATE.lock().unwrap();
+
+        // The process is gone, so allow its pid to be dumped again if it is reused
+        if state.dumped.remove(&(pid, asid)) || !state.is_target(&name, pid) {
+            return;
+        }
+    }
+
+    let mut dump = ProcessDump {
+        name,
+        pid,
+        ppid: 0,
+        asid,
+        instr_count: rr_get_guest_instr_count(),
+        dir: PathBuf::new(),
+        regions: Vec::new(),
+        memory_captured: false,
+    };
+
+    dump.dir = dump_dir(&output::subdir(OUTPUT_SUBDIR), &dump);
+    match fs::create_dir_all(&dump.dir).and_then(|_| write_info(&dump)) {
+        Ok(()) => STATE.lock().unwrap().dumps.push(dump),
+        Err(err) => eprintln!(
+            "Failed to dump process {} ({}): {}",
+            sanitize::text(&dump.name),
+            pid,
+            err
+        ),
+    }
+}
+
+fn dump_dir(output_dir: &Path, dump: &ProcessDump) -> PathBuf {
+    let name = sanitize::file_name(&dump.name);
+
+    output_dir.join(format!("{}-{}-{}", name, dump.pid, dump.instr_count))
+}
+
+fn write_info(dump: &ProcessDump) -> io::Result<()> {
+    let mut info = File::create(dump.dir.join("info.txt"))?;
+
+    writeln!(info, "name: {}", sanitize::text(&dump.name))?;
+    writeln!(info, "pid: {}", dump.pid)?;
+    writeln!(info, "ppid: {}", dump.ppid)?;
+    writeln!(info, "asid: {:#x}", dump.asid)?;
+    writeln!(info, "instr_count: {}", dump.instr_count)?;
+    writeln!(info, "memory_captured: {}", dump.memory_captured)?;
+
+    Ok(())
+}
+
+fn write_region(
+    cpu: &mut CPUState,
+    start: target_ptr_t,
+    size: target_ptr_t,
+    path: &Path,
+) -> io::Result<usize> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let page_size = page_size() as usize;
+    let mut page = vec![0u8; page_size];
+    let mut readable_bytes = 0;
+    let mut offset = 0;
+
+    while offset < size {
+        let len = ((size - offset) as usize).min(page_size);
+        let page = &mut page[..len];
+
+        if virtual_memory_read_into(cpu, (start + offset) as target_ulong, page).is_ok() {
+            readable_bytes += len;
+        } else {
+            page.fill(0);
+        }
+
+        file.write_all(page)?;
+        offset += len as target_ptr_t;
+    }
+
+    file.flush()?;
+
+    Ok(readable_bytes)
+}
+
+pub(crate) fn write_dump(
+    cpu: &mut CPUState,
+    process: &OsiProc,
+    output_dir: &Path,
+) -> io::Result<ProcessDump> {
+    let mut dump = ProcessDump {
+        name: process.get_name().into_owned(),
+        pid: process.pid,
+        ppid: process.ppid,
+        asid: process.asid,
+        instr_count: rr_get_guest_instr_count(),
+        dir: PathBuf::new(),
+        regions: Vec::new(),
+        memory_captured: true,
+    };
+
+    dump.dir = dump_dir(output_dir, &dump);
+    fs::create_dir_all(&dump.dir)?;
+
+    let mut process = *process;
+    let mappings = OSI.get_mappings(cpu, &mut process);
+    let mut maps = File::create(dump.dir.join("maps.txt"))?;
+
+    if !mappings.is_null() {
+        for mapping in mappings.iter() {
+            let path = dump.dir.join(format!(
+                "{:x}-{:x}.bin",
+                mapping.base,
+                mapping.base + mapping.size
+            ));
+            let readable_bytes = write_region(cpu, mapping.base, mapping.size, &path)?;
+
+            let region = DumpedRegion {
+                start: mapping.base,
+                size: mapping.size,
+                name: c_string(mapping.name),
+                file: c_string(mapping.file),
+                readable_bytes,
+            };
+
+            writeln!(
+                maps,
+                "{:x}-{:x} {} {} {}",
+                region.start,
+                region.start + region.size,
+                region.readable_bytes,
+                sanitize::text(&region.name),
+                sanitize::text(&region.file)
+            )?;
+
+            dump.regions.push(region);
+        }
+    }
+
+    write_info(&dump)?;
+
+    Ok(dump)
+}
+
+fn c_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Dump processes matching the given target right before they exit. Can be called
+/// multiple times to dump several targets.
+pub fn dump_on_exit(target: Target) {
+    if !ENABLED.swap(true, Ordering::SeqCst) {
+        let (exit, exit_group, process_end) = &*CALLBACKS;
+        exit.enable();
+        exit_group.enable();
+        process_end.enable();
+    }
+
+    STATE.lock().unwrap().targets.push(target);
+}
+
+/// Stop dumping processes on exit and forget all targets. Dumps written so far are
+/// kept.
+pub fn stop() {
+    if ENABLED.swap(false, Ordering::SeqCst) {
+        let (exit, exit_group, process_end) = &*CALLBACKS;
+        exit.disable();
+        exit_group.disable();
+        process_end.disable();
+    }
+
+    STATE.lock().unwrap().targets.clear();
+}
+
+/// Dump the current process immediately, regardless of the configured targets
+pub fn dump_current_process(cpu: &mut CPUState) -> io::Result<ProcessDump> {
+    let process = OSI
+        .get_current_process(cpu)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no current process"))?;
+
+    let dump = write_dump(cpu, &process, &output::subdir(OUTPUT_SUBDIR))?;
+
+    STATE.lock().unwrap().dumps.push(dump.clone());
+
+    Ok(dump)
+}
+
+/// Get the dumps written so far
+pub fn dumps() -> Vec<ProcessDump> {
+    STATE.lock().unwrap().dumps.clone()
+}
@@ -0,0 +1,323 @@
+//! Guards against callbacks being re-entered by API calls made from within them
+//!
+//! Some PANDA APIs trigger callbacks themselves. For example, reading guest memory from
+//! a memory callback can run the same memory callback again, recursing until the stack
+//! overflows, or deadlocking if the callback holds a lock at the time.
+//!
+//! Closure callbacks (both [`Callback`](crate::Callback) and
+//! [`PppCallback`](crate::PppCallback)) can be protected against this in two ways:
+//!
+//! * A callback which opts in with [`Callback::skip_reentrant`] (or
+//! [`PppCallback::skip_reentrant`]) is never run again while it is already running.
+//! Nested runs are skipped.
+//! * [`suppress`] masks all of the plugin's closure callbacks for the duration of a
+//! scope, for API calls which would otherwise trigger a different callback of the
+//! plugin.
+//!
+//! Skipped callbacks return a neutral value: `false` for callbacks returning a `bool`,
+//! and the unmodified exception index for exception and interrupt callbacks. The number
+//! of runs of a callback which were skipped can be checked with
+//! [`Callback::skipped_calls`], and the total for the plugin with [`skipped_calls`].
+//!
+//! Callbacks declared with attribute macros (such as
+//! [`#[panda::before_block_exec]`](crate::before_block_exec)) are not masked.
+//!
+//! [`Callback::skip_reentrant`]: crate::Callback::skip_reentrant
+//! [`PppCallback::skip_reentrant`]: crate::PppCallback::skip_reentrant
+//! [`Callback::skipped_calls`]: crate::Callback::skipped_calls
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::{mem, reentrancy, Callback};
+//!
+//! Callback::new().virt_mem_after_write(|cpu, _pc, addr, _size, _buf| {
+//!     // without suppressing, this write would run the callback again
+//!     reentrancy::suppress(|| mem::virtual_memory_write(cpu, addr, &[0]));
+//! });
+//! ```
+
+use crate::sys::CPUState;
+
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+thread_local! {
+    static SUPPRESS_DEPTH: Cell<usize> = Cell::new(0);
+}
+
+/// The number of [`suppress`] scopes active on any thread, so callbacks only need to
+/// check the current thread's scopes while one is active
+static SUPPRESSING: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of closure callback runs which have been skipped
+static SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Run `func` with all of this plugin's closure callbacks masked on the current thread,
+/// returning its result. Scopes can be nested.
+pub fn suppress<R>(func: impl FnOnce() -> R) -> R {
+    struct Unsuppress;
+
+    impl Drop for Unsuppress {
+        fn drop(&mut self) {
+            SUPPRESS_DEPTH.with(|depth| depth.set(depth.get() - 1));
+            SUPPRESSING.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    SUPPRESSING.fetch_add(1, Ordering::SeqCst);
+    SUPPRESS_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let _unsuppress = Unsuppress;
+
+    func()
+}
+
+/// Check whether this plugin's closure callbacks are currently masked by [`suppress`]
+pub fn is_suppressed() -> bool {
+    SUPPRESSING.load(Ordering::Relaxed) != 0 && SUPPRESS_DEPTH.with(|depth| depth.get() != 0)
+}
+
+/// Get the number of runs of this plugin's closure callbacks which have been skipped,
+/// either because they were masked by [`suppress`] or because they opted out of being
+/// re-entered
+pub fn skipped_calls() -> u64 {
+    SKIPPED.load(Ordering::Relaxed)
+}
+
+/// The state of a closure callback, kept at the start of its context
+#[derive(Default)]
+struct ClosureState {
+    /// The number of runs of the callback in progress
+    depth: AtomicUsize,
+
+    /// Whether nested runs of the callback are skipped
+    skip_reentrant: AtomicBool,
+
+    /// The number of runs of the callback which have been skipped
+    skipped: AtomicU64,
+
+    /// Whether the callback has been removed, and is waiting to be freed
+    retired: AtomicBool,
+
+    /// Frees the callback once its last run returns
+    deferred: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+}
+
+/// The context a closure callback is registered with: the closure along with the state
+/// used to guard it
+#[doc(hidden)]
+#[repr(C)]
+pub struct ClosureContext<F> {
+    state: ClosureState,
+    pub closure: F,
+}
+
+impl<F> ClosureContext<F> {
+    pub fn new(closure: F) -> Self {
+        Self {
+            state: ClosureState::default(),
+            closure,
+        }
+    }
+}
+
+/// Get the state from the context of a closure callback
+///
+/// ## Safety
+///
+/// `context` must point to a live [`ClosureContext`]
+unsafe fn state<'a>(context: *mut c_void) -> &'a ClosureState {
+    // `ClosureContext` is `repr(C)` with the state first, whatever the closure type
+    unsafe { &*(context as *const ClosureState) }
+}
+
+/// Marks a closure callback as running until dropped
+#[doc(hidden)]
+pub struct RunningGuard(*mut c_void);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        let state = unsafe { state(self.0) };
+
+        if state.depth.fetch_sub(1, Ordering::SeqCst) == 1 && state.retired.load(Ordering::SeqCst) {
+            // the context is freed by `func`, so the state can't be used afterwards
+            let deferred = state.deferred.lock().unwrap().take();
+            if let Some(func) = deferred {
+                func();
+            }
+        }
+    }
+}
+
+/// Set whether nested runs of the closure callback with the given context are skipped
+///
+/// ## Safety
+///
+/// `context` must point to a live [`ClosureContext`]
+pub(crate) unsafe fn set_skip_reentrant(context: *mut c_void, skip: bool) {
+    unsafe { state(context) }
+        .skip_reentrant
+        .store(skip, Ordering::Relaxed);
+}
+
+/// Get the number of runs of the closure callback with the given context which were
+/// skipped
+///
+/// ## Safety
+///
+/// `context` must point to a live [`ClosureContext`]
+pub(crate) unsafe fn skipped(context: *mut c_void) -> u64 {
+    unsafe { state(context) }.skipped.load(Ordering::Relaxed)
+}
+
+/// Run `func` once the closure callback with the given context has returned, or
+/// immediately if it isn't running. Used to free closures which remove themselves.
+///
+/// ## Safety
+///
+/// `context` must point to a live [`ClosureContext`], which `func` frees
+pub(crate) unsafe fn after_return(context: *mut c_void, func: impl FnOnce() + Send + 'static) {
+    let state = unsafe { state(context) };
+
+    *state.deferred.lock().unwrap() = Some(Box::new(func));
+    state.retired.store(true, Ordering::SeqCst);
+
+    if state.depth.load(Ordering::SeqCst) == 0 {
+        let deferred = state.deferred.lock().unwrap().take();
+        if let Some(func) = deferred {
+            func();
+        }
+    }
+}
+
+/// Mark the closure callback with the given context as running, returning `None` if
+/// it should be skipped because it is masked, or already running and opted out of
+/// being re-entered
+///
+/// ## Safety
+///
+/// `context` must point to a live [`ClosureContext`]
+#[doc(hidden)]
+pub unsafe fn __enter(context: *mut c_void) -> Option<RunningGuard> {
+    let state = unsafe { state(context) };
+
+    let depth = state.depth.fetch_add(1, Ordering::SeqCst);
+    let running = RunningGuard(context);
+
+    if is_suppressed() || (depth != 0 && state.skip_reentrant.load(Ordering::Relaxed)) {
+        state.skipped.fetch_add(1, Ordering::Relaxed);
+        SKIPPED.fetch_add(1, Ordering::Relaxed);
+
+        return None;
+    }
+
+    Some(running)
+}
+
+/// The value returned by a callback which was skipped, given the callback's arguments
+#[doc(hidden)]
+pub trait SkippedReturn<Args> {
+    fn skipped_return(args: Args) -> Self;
+}
+
+impl<Args> SkippedReturn<Args> for () {
+    fn skipped_return(_: Args) -> Self {}
+}
+
+impl<Args> SkippedReturn<Args> for bool {
+    fn skipped_return(_: Args) -> Self {
+        false
+    }
+}
+
+/// Exception and interrupt callbacks return the exception index to handle
+impl<'a> SkippedReturn<(&'a mut CPUState, i32)> for i32 {
+    fn skipped_return((_, exception_index): (&'a mut CPUState, i32)) -> Self {
+        exception_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    fn context() -> *mut c_void {
+        Box::into_raw(Box::new(ClosureContext::new(()))) as *mut c_void
+    }
+
+    fn free(context: *mut c_void) {
+        let _ = unsafe { Box::from_raw(context as *mut ClosureContext<()>) };
+    }
+
+    #[test]
+    fn test_nested_runs_allowed_by_default() {
+        let context = context();
+
+        unsafe {
+            let outer = __enter(context);
+            assert!(outer.is_some());
+            assert!(__enter(context).is_some());
+            assert_eq!(skipped(context), 0);
+        }
+
+        free(context);
+    }
+
+    #[test]
+    fn test_skip_reentrant_counts_skipped_runs() {
+        let context = context();
+
+        unsafe {
+            set_skip_reentrant(context, true);
+
+            let outer = __enter(context);
+            assert!(outer.is_some());
+            assert!(__enter(context).is_none());
+            assert_eq!(skipped(context), 1);
+
+            drop(outer);
+            assert!(__enter(context).is_some());
+        }
+
+        free(context);
+    }
+
+    #[test]
+    fn test_suppress_masks_all_callbacks() {
+        let context = context();
+
+        unsafe {
+            assert!(suppress(|| __enter(context)).is_none());
+            assert!(__enter(context).is_some());
+            assert_eq!(skipped(context), 1);
+        }
+
+        free(context);
+    }
+
+    #[test]
+    fn test_after_return_waits_for_running_callback() {
+        let context = context();
+        let freed = Arc::new(AtomicBool::new(false));
+
+        unsafe {
+            let running = __enter(context);
+
+            let flag = Arc::clone(&freed);
+            let addr = context as usize;
+            after_return(context, move || {
+                free(addr as *mut c_void);
+                flag.store(true, Ordering::SeqCst);
+            });
+            assert!(!freed.load(Ordering::SeqCst));
+
+            drop(running);
+            assert!(freed.load(Ordering::SeqCst));
+        }
+    }
+}
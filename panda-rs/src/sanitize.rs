@@ -0,0 +1,241 @@
+//! Sanitizing guest-supplied strings before they are used on the host
+//!
+//! Process names, mapping names, file paths and symbols are all read out of guest
+//! memory, so are controlled by whatever is running in the guest. An adversarial guest
+//! can name a process `../../.ssh/authorized_keys`, embed newlines to forge lines in a
+//! report, or hand back megabytes of invalid UTF-8. These helpers make such strings
+//! safe to use on the analysis host:
+//!
+//! * [`decode`] turns raw guest bytes into a string, stopping at the first NUL and
+//!   replacing invalid UTF-8
+//! * [`file_name`] turns a string into a single path component which can't escape the
+//!   directory it's joined to, can't be hidden, and is of limited length
+//! * [`join`] joins a guest-relative path onto a host directory, rejecting paths which
+//!   would escape it rather than rewriting them
+//! * [`text`] makes a string safe to write as a field of a line-based text file, by
+//!   escaping control characters and limiting its length
+//!
+//! The output directories of [`crash`](crate::crash) and [`procdump`](crate::procdump)
+//! use these for everything the guest names, as do the reports of
+//! [`fs_activity`](crate::fs_activity) and [`coverage`](crate::coverage).
+//!
+//! ## Example
+//!
+//! ```
+//! use panda::sanitize;
+//! use std::path::Path;
+//!
+//! assert_eq!(sanitize::file_name("../../etc/passwd"), "_._.._etc_passwd");
+//! assert_eq!(sanitize::text("evil\nname: forged"), "evil\\nname: forged");
+//! assert!(sanitize::join(Path::new("out"), "a/../../b").is_err());
+//! ```
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+
+/// The longest name [`file_name`] will produce
+pub const MAX_FILE_NAME_LEN: usize = 64;
+
+/// The longest path component [`join`] will accept, the usual `NAME_MAX` of Linux
+pub const MAX_COMPONENT_LEN: usize = 255;
+
+/// The longest string [`text`] will produce, not counting the truncation marker
+pub const MAX_TEXT_LEN: usize = 4096;
+
+/// A guest-supplied path which isn't safe to use on the host
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum UnsafePathError {
+    #[error("path is empty")]
+    Empty,
+
+    #[error("path contains a NUL byte")]
+    NulByte,
+
+    #[error("path is absolute")]
+    Absolute,
+
+    #[error("path escapes its directory using `..`")]
+    Traversal,
+
+    #[error(
+        "path component is {0} bytes long, more than the limit of {}",
+        MAX_COMPONENT_LEN
+    )]
+    TooLong(usize),
+}
+
+/// Decode a string read from guest memory, stopping at the first NUL byte and
+/// replacing any invalid UTF-8
+pub fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    String::from_utf8_lossy(&bytes[..len])
+}
+
+/// Turn a guest-supplied name into a file name which is safe to create on the host
+///
+/// Everything other than ASCII letters, digits, `.` and `_` is replaced with `_`,
+/// including path separators and non-ASCII characters (which rules out look-alike
+/// names), as is a leading `.` so the result can't be `.`, `..` or a hidden file. The
+/// result is truncated to [`MAX_FILE_NAME_LEN`], and is never empty.
+pub fn file_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_FILE_NAME_LEN)
+        .collect();
+
+    if sanitized.starts_with('.') {
+        sanitized.replace_range(..1, "_");
+    }
+
+    if sanitized.is_empty() {
+        sanitized.push('_');
+    }
+
+    sanitized
+}
+
+/// Join a guest-supplied relative path onto a host directory, such as to mirror a
+/// guest file under an output directory
+///
+/// Unlike [`file_name`] the path is kept as is rather than rewritten, so paths which
+/// are absolute, contain a NUL byte, use `..` or have an overly long component are
+/// rejected instead. Empty and `.` components are skipped. Symlinks already present
+/// under `base` are not checked, so `base` should be a directory the guest can't
+/// influence.
+pub fn join(base: &Path, relative: &str) -> Result<PathBuf, UnsafePathError> {
+    if relative.contains('\0') {
+        return Err(UnsafePathError::NulByte);
+    }
+
+    let mut path = base.to_path_buf();
+    let mut components = 0;
+
+    for component in Path::new(relative).components() {
+        match component {
+            Component::Normal(name) => {
+                let len = name.len();
+                if len > MAX_COMPONENT_LEN {
+                    return Err(UnsafePathError::TooLong(len));
+                }
+
+                path.push(name);
+                components += 1;
+            }
+            Component::CurDir => (),
+            Component::ParentDir => return Err(UnsafePathError::Traversal),
+            Component::RootDir | Component::Prefix(_) => return Err(UnsafePathError::Absolute),
+        }
+    }
+
+    if components == 0 {
+        return Err(UnsafePathError::Empty);
+    }
+
+    Ok(path)
+}
+
+/// Check whether a character could change how surrounding text is displayed, such as
+/// the bidirectional overrides used to disguise file extensions
+fn is_format_char(c: char) -> bool {
+    matches!(c, '\u{200e}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Make a guest-supplied string safe to write as a field of a line-based text file or
+/// to a terminal
+///
+/// Control characters (including newlines and escape sequences) and bidirectional
+/// formatting characters are escaped as they would be in a Rust string literal, and the
+/// result is truncated to [`MAX_TEXT_LEN`] bytes, with `...` appended if anything was
+/// cut off.
+pub fn text(s: &str) -> Cow<'_, str> {
+    let is_clean = |s: &str| {
+        s.len() <= MAX_TEXT_LEN && !s.chars().any(|c| c.is_control() || is_format_char(c))
+    };
+
+    if is_clean(s) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut escaped = String::with_capacity(s.len().min(MAX_TEXT_LEN) + 3);
+    for c in s.chars() {
+        let start = escaped.len();
+        if c.is_control() || is_format_char(c) {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+
+        if escaped.len() > MAX_TEXT_LEN {
+            // don't split an escape sequence or character
+            escaped.truncate(start);
+            escaped.push_str("...");
+            break;
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"bash\0garbage"), "bash");
+        assert_eq!(decode(b"no nul"), "no nul");
+        assert_eq!(decode(b"bad \xff utf8"), "bad \u{fffd} utf8");
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name(".."), "_.");
+        assert_eq!(file_name(".bashrc"), "_bashrc");
+        assert_eq!(file_name("a/b\\c"), "a_b_c");
+        assert_eq!(file_name(""), "_");
+        assert_eq!(file_name("caf\u{e9}"), "caf_");
+        assert_eq!(file_name(&"x".repeat(100)).len(), MAX_FILE_NAME_LEN);
+    }
+
+    #[test]
+    fn test_join() {
+        let base = Path::new("out");
+
+        assert_eq!(join(base, "a/./b").unwrap(), Path::new("out/a/b"));
+        assert_eq!(join(base, "/etc/passwd"), Err(UnsafePathError::Absolute));
+        assert_eq!(join(base, "a/../b"), Err(UnsafePathError::Traversal));
+        assert_eq!(join(base, "a\0b"), Err(UnsafePathError::NulByte));
+        assert_eq!(join(base, "./"), Err(UnsafePathError::Empty));
+        assert_eq!(
+            join(base, &"x".repeat(MAX_COMPONENT_LEN + 1)),
+            Err(UnsafePathError::TooLong(MAX_COMPONENT_LEN + 1))
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        assert!(matches!(text("/usr/bin/ls"), Cow::Borrowed(_)));
+        assert_eq!(text("a\r\nb"), "a\\r\\nb");
+        assert_eq!(text("\x1b[2J"), "\\u{1b}[2J");
+        assert_eq!(text("evil\u{202e}fdp.exe"), "evil\\u{202e}fdp.exe");
+    }
+
+    #[test]
+    fn test_text_truncated() {
+        let long = text(&"x".repeat(MAX_TEXT_LEN + 1)).into_owned();
+        assert_eq!(long.len(), MAX_TEXT_LEN + 3);
+        assert!(long.ends_with("..."));
+
+        // an escape sequence is dropped rather than split
+        let mut s = "x".repeat(MAX_TEXT_LEN - 1);
+        s.push('\n');
+        assert_eq!(text(&s), format!("{}...", "x".repeat(MAX_TEXT_LEN - 1)));
+    }
+}
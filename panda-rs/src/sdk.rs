@@ -0,0 +1,237 @@
+//! A single-macro facade for declaring plugins
+//!
+//! [`plugin!`](crate::plugin) declares a plugin's metadata, arguments and entry points
+//! in one place, generating the [`init`](crate::init)/[`uninit`](crate::uninit)
+//! functions, argument parsing, logging setup and error handling which would otherwise
+//! be written by hand.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//!
+//! #[derive(PandaArgs)]
+//! #[name = "my_plugin"]
+//! struct Args {
+//!     #[arg(default = "out.txt")]
+//!     output: String,
+//! }
+//!
+//! panda::plugin! {
+//!     name: "my_plugin",
+//!     log: "debug",
+//!     args: Args,
+//!     init: init,
+//!     uninit: uninit,
+//! }
+//!
+//! fn init(_: &mut PluginHandle, args: &Args) -> Result<(), std::io::Error> {
+//!     std::fs::File::create(&args.output)?;
+//!
+//!     Ok(())
+//! }
+//!
+//! fn uninit(_: &mut PluginHandle) {
+//!     println!("Wrote to {}", plugin_args().output);
+//! }
+//! ```
+use std::fmt;
+
+use once_cell::sync::OnceCell;
+
+/// Metadata describing a plugin declared with [`plugin!`](crate::plugin)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PluginInfo {
+    pub name: &'static str,
+
+    /// Defaults to the version of the plugin's crate
+    pub version: &'static str,
+
+    pub description: &'static str,
+}
+
+/// What to do when a plugin's init function returns an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Print the error and fail to load the plugin. This is the default.
+    Abort,
+
+    /// Print the error and load the plugin anyway
+    Continue,
+}
+
+/// A type which can be returned from the init function of a plugin declared with
+/// [`plugin!`](crate::plugin)
+pub trait PluginResult {
+    fn into_plugin_result(self) -> Result<(), String>;
+}
+
+impl PluginResult for () {
+    fn into_plugin_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E: fmt::Debug> PluginResult for Result<(), E> {
+    fn into_plugin_result(self) -> Result<(), String> {
+        self.map_err(|err| format!("{:?}", err))
+    }
+}
+
+static INFO: OnceCell<PluginInfo> = OnceCell::new();
+
+/// Get the metadata of the plugin, if it was declared with [`plugin!`](crate::plugin)
+/// and has been initialized
+pub fn info() -> Option<&'static PluginInfo> {
+    INFO.get()
+}
+
+#[cfg(feature = "log")]
+struct PluginLogger {
+    name: &'static str,
+}
+
+#[cfg(feature = "log")]
+impl log::Log for PluginLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}: {}", self.name, record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Send `log` records to stderr, prefixed with the plugin name. Does nothing if a
+/// logger has already been installed, such as by another plugin in the same process.
+#[cfg(feature = "log")]
+fn init_logging(name: &'static str, level: &str) {
+    let level = level.parse().unwrap_or(log::LevelFilter::Info);
+    let logger = Box::leak(Box::new(PluginLogger { name }));
+
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+#[cfg(not(feature = "log"))]
+fn init_logging(_: &'static str, _: &str) {}
+
+#[doc(hidden)]
+pub fn __init<R: PluginResult>(
+    info: PluginInfo,
+    log_level: &str,
+    policy: ErrorPolicy,
+    init: impl FnOnce() -> R,
+) -> bool {
+    let info = INFO.get_or_init(|| info);
+    init_logging(info.name, log_level);
+
+    match init().into_plugin_result() {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("[{}] Error initializing plugin: {}", info.name, err);
+
+            policy == ErrorPolicy::Continue
+        }
+    }
+}
+
+#[doc(hidden)]
+pub use once_cell::sync::OnceCell as __OnceCell;
+
+/// Declare a plugin: its metadata, arguments and entry points
+///
+/// Fields must be given in the following order, and all but `name` and `init` are
+/// optional:
+///
+/// * `name` - the name of the plugin, which must match the `#[name]` of the args struct
+/// * `version` - defaults to the version of the crate
+/// * `description` - a short description of the plugin
+/// * `log` - the maximum level of `log` records to print to stderr, defaults to
+/// `"info"`. Logging is only set up if the `log` crate is enabled (by the
+/// `syscall-injection` feature).
+/// * `on_error` - the [`ErrorPolicy`] to apply if `init` returns an error, defaults to
+/// `Abort`
+/// * `args` - a type implementing [`PandaArgs`](crate::PandaArgs). The arguments are
+/// parsed once, before `init` is called, and can be accessed anywhere in the plugin
+/// through a generated `plugin_args()` function.
+/// * `init` - called when the plugin is loaded, with the plugin handle and, if `args`
+/// is given, the parsed arguments. Can return `()` or a `Result<(), E>`.
+/// * `uninit` - called with the plugin handle when the plugin is unloaded
+///
+/// See the [`sdk`](crate::sdk) module for an example.
+#[macro_export]
+macro_rules! plugin {
+    (
+        name: $name:literal,
+        $(version: $version:expr,)?
+        $(description: $description:expr,)?
+        $(log: $log:expr,)?
+        $(on_error: $policy:ident,)?
+        $(args: $args:ty,)?
+        init: $init:expr
+        $(, uninit: $uninit:expr)?
+        $(,)?
+    ) => {
+        $(
+            /// Get the arguments this plugin was loaded with
+            #[allow(dead_code)]
+            pub fn plugin_args() -> &'static $args {
+                static ARGS: $crate::sdk::__OnceCell<$args> = $crate::sdk::__OnceCell::new();
+
+                ARGS.get_or_init(<$args as $crate::PandaArgs>::from_panda_args)
+            }
+        )?
+
+        #[$crate::init]
+        fn __panda_plugin_init(plugin: &mut $crate::PluginHandle) -> bool {
+            $crate::sdk::__init(
+                $crate::sdk::PluginInfo {
+                    name: $name,
+                    version: $crate::__plugin_or!(($($version)?) env!("CARGO_PKG_VERSION")),
+                    description: $crate::__plugin_or!(($($description)?) ""),
+                },
+                $crate::__plugin_or!(($($log)?) "info"),
+                $crate::__plugin_or!(
+                    ($($crate::sdk::ErrorPolicy::$policy)?)
+                    $crate::sdk::ErrorPolicy::Abort
+                ),
+                || $crate::__plugin_init_call!(plugin, $init $(, $args)?),
+            )
+        }
+
+        $(
+            #[$crate::uninit]
+            fn __panda_plugin_uninit(plugin: &mut $crate::PluginHandle) {
+                ($uninit)(plugin)
+            }
+        )?
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plugin_or {
+    (() $default:expr) => {
+        $default
+    };
+    (($value:expr) $default:expr) => {
+        $value
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __plugin_init_call {
+    ($plugin:ident, $init:expr) => {
+        ($init)($plugin)
+    };
+    ($plugin:ident, $init:expr, $args:ty) => {
+        ($init)($plugin, plugin_args())
+    };
+}
@@ -0,0 +1,310 @@
+//! Guest socket addresses (`struct sockaddr` and friends), for decoding the arguments
+//! of network syscalls such as `connect`, `bind` and `accept`
+//!
+//! [`SockAddr`] reads any address, using its `sa_family` to select the address type.
+//! Ports and IP addresses are converted from network byte order, and IP addresses
+//! are displayed the same way as [`std::net::SocketAddr`]. Layouts are those used by
+//! Linux guests.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plugins::syscalls2::Syscalls2Callbacks;
+//! use panda::prelude::*;
+//! use panda::sockaddr::SockAddr;
+//! use panda::{GuestPtr, PppCallback};
+//!
+//! PppCallback::new().on_sys_connect_enter(|_cpu, _pc, _fd, addr, _addrlen| {
+//!     let addr: GuestPtr<SockAddr> = (addr as target_ptr_t).into();
+//!
+//!     if let Ok(addr) = addr.read() {
+//!         println!("connect({})", addr);
+//!     }
+//! });
+//! ```
+
+use crate::enums::Endian;
+use crate::mem::{
+    physical_memory_read_into, physical_memory_write, virtual_memory_read_into,
+    virtual_memory_write,
+};
+use crate::prelude::*;
+use crate::{check_endian, data_endian, GuestReadFail, GuestType, GuestWriteFail};
+
+use std::alloc::Layout;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+pub const AF_UNIX: u16 = 1;
+pub const AF_INET: u16 = 2;
+pub const AF_INET6: u16 = 10;
+
+/// Maximum length of the path of a unix socket, including the nul terminator
+const UNIX_PATH_MAX: usize = 108;
+
+/// Implement `GuestType` for a fixed-size type with `decode` and `encode` methods
+/// converting from and to its guest bytes
+macro_rules! impl_guest_type_via_bytes {
+    ($ty:ty, $size:expr, $align:expr) => {
+        impl GuestType for $ty {
+            fn guest_layout() -> Option<Layout> {
+                Layout::from_size_align($size, $align).ok()
+            }
+
+            fn read_from_guest(
+                cpu: &mut CPUState,
+                ptr: target_ptr_t,
+            ) -> Result<Self, GuestReadFail> {
+                let mut bytes = [0u8; $size];
+                virtual_memory_read_into(cpu, ptr, &mut bytes).or(Err(GuestReadFail))?;
+
+                Ok(Self::decode(&bytes, check_endian(cpu)))
+            }
+
+            fn read_from_guest_phys(ptr: target_ptr_t) -> Result<Self, GuestReadFail> {
+                let mut bytes = [0u8; $size];
+                physical_memory_read_into(ptr, &mut bytes).or(Err(GuestReadFail))?;
+
+                Ok(Self::decode(&bytes, data_endian()))
+            }
+
+            fn write_to_guest(
+                &self,
+                cpu: &mut CPUState,
+                ptr: target_ptr_t,
+            ) -> Result<(), GuestWriteFail> {
+                let bytes = self.encode(check_endian(cpu));
+                virtual_memory_write(cpu, ptr, &bytes);
+
+                Ok(())
+            }
+
+            fn write_to_guest_phys(&self, ptr: target_ptr_t) -> Result<(), GuestWriteFail> {
+                physical_memory_write(ptr, &self.encode(data_endian()));
+
+                Ok(())
+            }
+        }
+    };
+}
+
+fn encode_family(family: u16, endian: Endian) -> [u8; 2] {
+    match endian {
+        Endian::Big => family.to_be_bytes(),
+        Endian::Little => family.to_le_bytes(),
+    }
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// An IPv4 socket address (`struct sockaddr_in`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SockAddrIn {
+    pub port: u16,
+    pub addr: Ipv4Addr,
+}
+
+impl SockAddrIn {
+    const SIZE: usize = 16;
+
+    fn decode(bytes: &[u8], _endian: Endian) -> Self {
+        let addr: [u8; 4] = [bytes[4], bytes[5], bytes[6], bytes[7]];
+
+        Self {
+            port: be_u16(&bytes[2..]),
+            addr: addr.into(),
+        }
+    }
+
+    fn encode(&self, endian: Endian) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..2].copy_from_slice(&encode_family(AF_INET, endian));
+        bytes[2..4].copy_from_slice(&self.port.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.addr.octets());
+
+        bytes
+    }
+}
+
+impl_guest_type_via_bytes!(SockAddrIn, SockAddrIn::SIZE, 4);
+
+impl From<SockAddrIn> for SocketAddrV4 {
+    fn from(addr: SockAddrIn) -> Self {
+        SocketAddrV4::new(addr.addr, addr.port)
+    }
+}
+
+impl fmt::Display for SockAddrIn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        SocketAddrV4::from(*self).fmt(f)
+    }
+}
+
+/// An IPv6 socket address (`struct sockaddr_in6`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SockAddrIn6 {
+    pub port: u16,
+    pub flowinfo: u32,
+    pub addr: Ipv6Addr,
+    pub scope_id: u32,
+}
+
+impl SockAddrIn6 {
+    const SIZE: usize = 28;
+
+    fn decode(bytes: &[u8], endian: Endian) -> Self {
+        let mut addr = [0u8; 16];
+        addr.copy_from_slice(&bytes[8..24]);
+
+        let scope_id = [bytes[24], bytes[25], bytes[26], bytes[27]];
+        let scope_id = match endian {
+            Endian::Big => u32::from_be_bytes(scope_id),
+            Endian::Little => u32::from_le_bytes(scope_id),
+        };
+
+        Self {
+            port: be_u16(&bytes[2..]),
+            flowinfo: be_u32(&bytes[4..]),
+            addr: addr.into(),
+            scope_id,
+        }
+    }
+
+    fn encode(&self, endian: Endian) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..2].copy_from_slice(&encode_family(AF_INET6, endian));
+        bytes[2..4].copy_from_slice(&self.port.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.flowinfo.to_be_bytes());
+        bytes[8..24].copy_from_slice(&self.addr.octets());
+        bytes[24..].copy_from_slice(&match endian {
+            Endian::Big => self.scope_id.to_be_bytes(),
+            Endian::Little => self.scope_id.to_le_bytes(),
+        });
+
+        bytes
+    }
+}
+
+impl_guest_type_via_bytes!(SockAddrIn6, SockAddrIn6::SIZE, 4);
+
+impl From<SockAddrIn6> for SocketAddrV6 {
+    fn from(addr: SockAddrIn6) -> Self {
+        SocketAddrV6::new(addr.addr, addr.port, addr.flowinfo, addr.scope_id)
+    }
+}
+
+impl fmt::Display for SockAddrIn6 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        SocketAddrV6::from(*self).fmt(f)
+    }
+}
+
+/// A unix domain socket address (`struct sockaddr_un`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SockAddrUn {
+    /// The raw path, up to its nul terminator. Abstract socket addresses start with a
+    /// nul byte, which is kept.
+    pub path: Vec<u8>,
+}
+
+impl SockAddrUn {
+    const SIZE: usize = 2 + UNIX_PATH_MAX;
+
+    /// Whether this is an address in the abstract namespace rather than a path
+    pub fn is_abstract(&self) -> bool {
+        self.path.first() == Some(&0)
+    }
+
+    fn decode(bytes: &[u8], _endian: Endian) -> Self {
+        let path = &bytes[2..];
+        let len = if path.first() == Some(&0) {
+            // abstract names aren't nul-terminated, so trim trailing padding instead
+            path.iter()
+                .rposition(|&b| b != 0)
+                .map_or(1, |last| last + 1)
+        } else {
+            path.iter().position(|&b| b == 0).unwrap_or(path.len())
+        };
+
+        Self {
+            path: path[..len].to_vec(),
+        }
+    }
+
+    fn encode(&self, endian: Endian) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        let len = self.path.len().min(UNIX_PATH_MAX);
+        bytes[..2].copy_from_slice(&encode_family(AF_UNIX, endian));
+        bytes[2..2 + len].copy_from_slice(&self.path[..len]);
+
+        bytes
+    }
+}
+
+impl_guest_type_via_bytes!(SockAddrUn, SockAddrUn::SIZE, 2);
+
+impl fmt::Display for SockAddrUn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_abstract() {
+            write!(f, "@{}", String::from_utf8_lossy(&self.path[1..]))
+        } else {
+            write!(f, "{}", String::from_utf8_lossy(&self.path))
+        }
+    }
+}
+
+/// Any socket address, selected by its address family (`struct sockaddr`)
+#[derive(GuestType, Debug, Clone, PartialEq, Eq, Hash)]
+#[guest(tag = "u16")]
+pub enum SockAddr {
+    #[guest(tag = 1)]
+    Unix(SockAddrUn),
+
+    #[guest(tag = 2)]
+    Inet(SockAddrIn),
+
+    #[guest(tag = 10)]
+    Inet6(SockAddrIn6),
+
+    /// An address of an unsupported family, holding the family
+    #[guest(other)]
+    Other(u16),
+}
+
+impl SockAddr {
+    /// Get the address family (`AF_*`) of the address
+    pub fn family(&self) -> u16 {
+        match self {
+            SockAddr::Unix(_) => AF_UNIX,
+            SockAddr::Inet(_) => AF_INET,
+            SockAddr::Inet6(_) => AF_INET6,
+            SockAddr::Other(family) => *family,
+        }
+    }
+
+    /// Convert to a [`SocketAddr`], if this is an IP address
+    pub fn to_socket_addr(&self) -> Option<SocketAddr> {
+        match *self {
+            SockAddr::Inet(addr) => Some(SocketAddrV4::from(addr).into()),
+            SockAddr::Inet6(addr) => Some(SocketAddrV6::from(addr).into()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SockAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SockAddr::Unix(addr) => addr.fmt(f),
+            SockAddr::Inet(addr) => addr.fmt(f),
+            SockAddr::Inet6(addr) => addr.fmt(f),
+            SockAddr::Other(family) => write!(f, "<address family {}>", family),
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! Declarative analysis specifications
+//!
+//! A spec is a YAML (or JSON) file describing hooks, memory watches, a syscall filter
+//! and where to write the resulting events, allowing a generic plugin to be driven by
+//! config files rather than Rust code. Events are written as one JSON object per line,
+//! and are flushed when the guest shuts down or when [`InstalledSpec::flush`] is called.
+//!
+//! ## Example
+//!
+//! ```yaml
+//! hooks:
+//!   - name: main
+//!     addr: 0x401000
+//!     regs: [rdi, rsi]
+//!     once: true
+//!
+//! watches:
+//!   - name: config_flag
+//!     addr: 0x601040
+//!     size: 4
+//!     access: write
+//!
+//! syscalls:
+//!   process: target
+//!   include: [0, 1, 2, 59]
+//!
+//! output:
+//!   path: events.jsonl
+//! ```
+//!
+//! ```no_run
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     panda::spec::load("analysis.yaml").unwrap();
+//! }
+//! ```
+//!
+//! Hook addresses, watch addresses and asids can be given either as integers or as
+//! strings (such as `"0x401000"`), as JSON has no hexadecimal integers.
+
+use crate::api::regs::{get_reg, Reg};
+use crate::hook;
+use crate::memcb::{self, MemcbDemand};
+use crate::plugins::hooks::HookHandle;
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+use crate::{current_asid, Callback};
+
+#[cfg(not(feature = "ppc"))]
+use crate::{current_pc, plugins::osi::OSI, plugins::syscalls2::Syscalls2Callbacks, PppCallback};
+
+use serde::de::{Deserializer, Error as _};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpecError {
+    #[error("Failed to read spec: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Failed to parse spec: {0}")]
+    Parse(#[from] serde_yaml::Error),
+
+    #[error("Unknown register {0:?}")]
+    UnknownRegister(String),
+
+    #[error("Syscall filters are not supported on this architecture")]
+    SyscallsUnsupported,
+}
+
+/// A description of an analysis to install
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Spec {
+    pub hooks: Vec<HookSpec>,
+    pub watches: Vec<WatchSpec>,
+    pub syscalls: Option<SyscallFilter>,
+    pub output: OutputSpec,
+}
+
+/// An instruction to hook, logging an event each time it is executed
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookSpec {
+    /// A name to identify the hook in the output
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(deserialize_with = "address")]
+    pub addr: target_ulong,
+
+    /// The address space to hook in, defaulting to any
+    #[serde(default, deserialize_with = "optional_address")]
+    pub asid: Option<target_ulong>,
+
+    /// `true` to only hook in kernel mode, `false` to only hook in user mode
+    #[serde(default)]
+    pub kernel: Option<bool>,
+
+    /// Registers to include in the event, such as `rdi`
+    #[serde(default)]
+    pub regs: Vec<String>,
+
+    /// Disable the hook after it is first hit
+    #[serde(default)]
+    pub once: bool,
+}
+
+/// The kinds of memory access a watch is triggered by
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    #[default]
+    Write,
+    #[serde(alias = "rw")]
+    Both,
+}
+
+/// A range of virtual memory to watch, logging an event for each access
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchSpec {
+    /// A name to identify the watch in the output
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(deserialize_with = "address")]
+    pub addr: target_ulong,
+
+    /// The size of the watched range in bytes. Defaults to 1.
+    #[serde(default = "default_watch_size")]
+    pub size: target_ulong,
+
+    /// The kinds of access to watch for. Defaults to writes.
+    #[serde(default)]
+    pub access: Access,
+}
+
+fn default_watch_size() -> target_ulong {
+    1
+}
+
+/// Which syscalls to log an event for
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyscallFilter {
+    /// Only log syscalls made by processes with this name
+    pub process: Option<String>,
+
+    /// Syscall numbers to log. If empty, every syscall not excluded is logged.
+    pub include: Vec<target_ulong>,
+
+    /// Syscall numbers to never log
+    pub exclude: Vec<target_ulong>,
+}
+
+#[cfg(not(feature = "ppc"))]
+impl SyscallFilter {
+    fn matches_callno(&self, callno: target_ulong) -> bool {
+        (self.include.is_empty() || self.include.contains(&callno))
+            && !self.exclude.contains(&callno)
+    }
+}
+
+/// Where events are written
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputSpec {
+    /// The file to write events to, or stdout if not given
+    pub path: Option<PathBuf>,
+
+    /// Append to the file rather than truncating it
+    pub append: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawAddress {
+    Int(u64),
+    Str(String),
+}
+
+fn parse_address(addr: &str) -> Option<target_ulong> {
+    match addr.strip_prefix("0x").or_else(|| addr.strip_prefix("0X")) {
+        Some(hex) => target_ulong::from_str_radix(hex, 16).ok(),
+        None => addr.parse().ok(),
+    }
+}
+
+fn address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<target_ulong, D::Error> {
+    match RawAddress::deserialize(deserializer)? {
+        RawAddress::Int(addr) => Ok(addr as target_ulong),
+        RawAddress::Str(addr) => parse_address(&addr)
+            .ok_or_else(|| D::Error::custom(format!("invalid address {:?}", addr))),
+    }
+}
+
+fn optional_address<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<target_ulong>, D::Error> {
+    address(deserializer).map(Some)
+}
+
+fn parse_reg(name: &str) -> Result<Reg, SpecError> {
+    name.to_uppercase()
+        .parse()
+        .map_err(|_| SpecError::UnknownRegister(name.to_owned()))
+}
+
+type Output = Arc<Mutex<Box<dyn Write + Send>>>;
+
+fn open_output(spec: &OutputSpec) -> Result<Output, SpecError> {
+    let out: Box<dyn Write + Send> = match &spec.path {
+        Some(path) => {
+            let file = File::options()
+                .create(true)
+                .write(true)
+                .append(spec.append)
+                .truncate(!spec.append)
+                .open(path)?;
+
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(io::stdout()),
+    };
+
+    Ok(Arc::new(Mutex::new(out)))
+}
+
+/// An event written to the output
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum Event<'a> {
+    Hook {
+        name: Option<&'a str>,
+        pc: target_ulong,
+        asid: target_ulong,
+        instr: u64,
+        #[serde(serialize_with = "reg_values")]
+        regs: Vec<(String, target_ulong)>,
+    },
+    Watch {
+        name: Option<&'a str>,
+        access: &'static str,
+        pc: target_ptr_t,
+        asid: target_ulong,
+        instr: u64,
+        addr: target_ptr_t,
+        size: usize,
+        value: String,
+    },
+    #[cfg(not(feature = "ppc"))]
+    Syscall {
+        process: Option<String>,
+        callno: target_ulong,
+        pc: target_ulong,
+        asid: target_ulong,
+        instr: u64,
+    },
+}
+
+/// Serialize register values as an object, keeping the order they were listed in
+fn reg_values<S: Serializer>(
+    regs: &[(String, target_ulong)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(regs.iter().map(|(name, value)| (name, value)))
+}
+
+fn emit(output: &Output, event: &Event) {
+    let mut out = output.lock().unwrap();
+    if serde_json::to_writer(&mut *out, event).is_ok() {
+        let _ = writeln!(out);
+    }
+}
+
+/// The hooks and callbacks installed for a spec, returned by [`Spec::install`].
+/// Dropping this leaves them installed.
+pub struct InstalledSpec {
+    hooks: Vec<HookHandle>,
+    callbacks: Vec<Callback>,
+    #[cfg(not(feature = "ppc"))]
+    syscalls: Option<PppCallback>,
+    memcb: Option<MemcbDemand>,
+    output: Output,
+}
+
+impl InstalledSpec {
+    /// Write out any events which are still buffered
+    pub fn flush(&self) -> io::Result<()> {
+        self.output.lock().unwrap().flush()
+    }
+
+    /// Remove the hooks and callbacks, then flush the output
+    pub fn remove(mut self) -> io::Result<()> {
+        for hook in &self.hooks {
+            hook.remove();
+        }
+
+        for callback in &self.callbacks {
+            callback.remove();
+        }
+
+        #[cfg(not(feature = "ppc"))]
+        if let Some(syscalls) = &self.syscalls {
+            syscalls.disable();
+        }
+
+        // turns memory callbacks back off if nothing else needs them
+        drop(self.memcb.take());
+
+        self.flush()
+    }
+}
+
+impl Drop for InstalledSpec {
+    fn drop(&mut self) {
+        if let Some(memcb) = self.memcb.take() {
+            memcb.forget();
+        }
+    }
+}
+
+impl Spec {
+    /// Parse a spec from YAML or JSON
+    pub fn parse(spec: &str) -> Result<Self, SpecError> {
+        Ok(serde_yaml::from_str(spec)?)
+    }
+
+    /// Read and parse a spec from a YAML or JSON file
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, SpecError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Install the hooks, watches and syscall filter described by the spec
+    pub fn install(&self) -> Result<InstalledSpec, SpecError> {
+        // Validate everything before installing anything
+        let hook_regs = self
+            .hooks
+            .iter()
+            .map(|hook| hook.regs.iter().map(|reg| parse_reg(reg)).collect())
+            .collect::<Result<Vec<Vec<Reg>>, _>>()?;
+
+        #[cfg(feature = "ppc")]
+        if self.syscalls.is_some() {
+            return Err(SpecError::SyscallsUnsupported);
+        }
+
+        let output = open_output(&self.output)?;
+
+        let hooks = self
+            .hooks
+            .iter()
+            .zip(hook_regs)
+            .map(|(spec, regs)| install_hook(spec, regs, output.clone()))
+            .collect();
+
+        let mut callbacks = Vec::new();
+        let mut memcb = None;
+        if !self.watches.is_empty() {
+            memcb = Some(memcb::demand_all());
+
+            for spec in &self.watches {
+                callbacks.extend(install_watch(spec, output.clone()));
+            }
+        }
+
+        let flush = Callback::new();
+        let flush_output = output.clone();
+        flush.pre_shutdown(move || {
+            let _ = flush_output.lock().unwrap().flush();
+        });
+        callbacks.push(flush);
+
+        Ok(InstalledSpec {
+            hooks,
+            callbacks,
+            #[cfg(not(feature = "ppc"))]
+            syscalls: self
+                .syscalls
+                .clone()
+                .map(|filter| install_syscall_filter(filter, output.clone())),
+            memcb,
+            output,
+        })
+    }
+}
+
+/// Read a spec from a YAML or JSON file and install it
+pub fn load(path: impl AsRef<Path>) -> Result<InstalledSpec, SpecError> {
+    Spec::read(path)?.install()
+}
+
+fn install_hook(spec: &HookSpec, regs: Vec<Reg>, output: Output) -> HookHandle {
+    let name = spec.name.clone();
+    let once = spec.once;
+
+    let mut builder = hook::start_block_exec(move |cpu, _, hook| {
+        let regs = regs
+            .iter()
+            .map(|&reg| (reg.to_string().to_lowercase(), get_reg(cpu, reg)))
+            .collect();
+
+        emit(
+            &output,
+            &Event::Hook {
+                name: name.as_deref(),
+                pc: hook.addr,
+                asid: current_asid(cpu),
+                instr: rr_get_guest_instr_count(),
+                regs,
+            },
+        );
+
+        if once {
+            hook.enabled = false;
+        }
+    });
+
+    if let Some(asid) = spec.asid {
+        builder = builder.asid(asid);
+    }
+
+    if let Some(kernel) = spec.kernel {
+        builder = builder.kernel(kernel);
+    }
+
+    builder.at_addr(spec.addr)
+}
+
+fn install_watch(spec: &WatchSpec, output: Output) -> Vec<Callback> {
+    let name = spec.name.clone();
+    let (start, end) = (spec.addr, spec.addr.saturating_add(spec.size));
+
+    let on_access = move |access: &'static str,
+                          cpu: &mut CPUState,
+                          pc: target_ptr_t,
+                          addr: target_ptr_t,
+                          size: usize,
+                          buf: *mut u8| {
+        if addr >= end || addr.saturating_add(size as target_ptr_t) <= start {
+            return;
+        }
+
+        let value = unsafe { std::slice::from_raw_parts(buf, size) }
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        emit(
+            &output,
+            &Event::Watch {
+                name: name.as_deref(),
+                access,
+                pc,
+                asid: current_asid(cpu),
+                instr: rr_get_guest_instr_count(),
+                addr,
+                size,
+                value,
+            },
+        );
+    };
+
+    let mut callbacks = Vec::new();
+
+    if matches!(spec.access, Access::Read | Access::Both) {
+        let on_access = on_access.clone();
+        let callback = Callback::new();
+        callback.virt_mem_after_read(move |cpu, pc, addr, size, buf| {
+            on_access("read", cpu, pc, addr, size, buf)
+        });
+        callbacks.push(callback);
+    }
+
+    if matches!(spec.access, Access::Write | Access::Both) {
+        let callback = Callback::new();
+        callback.virt_mem_after_write(move |cpu, pc, addr, size, buf| {
+            on_access("write", cpu, pc, addr, size, buf)
+        });
+        callbacks.push(callback);
+    }
+
+    callbacks
+}
+
+#[cfg(not(feature = "ppc"))]
+fn install_syscall_filter(filter: SyscallFilter, output: Output) -> PppCallback {
+    let callback = PppCallback::new();
+    callback.on_all_sys_enter(move |cpu, _, callno| {
+        if !filter.matches_callno(callno) {
+            return;
+        }
+
+        let process = OSI
+            .get_current_process(cpu)
+            .map(|process| process.get_name().into_owned());
+
+        if let Some(name) = &filter.process {
+            if process.as_ref() != Some(name) {
+                return;
+            }
+        }
+
+        emit(
+            &output,
+            &Event::Syscall {
+                process,
+                callno,
+                pc: current_pc(cpu),
+                asid: current_asid(cpu),
+                instr: rr_get_guest_instr_count(),
+            },
+        );
+    });
+
+    callback
+}
@@ -64,19 +64,34 @@ use crate::{
 
 mod arch;
 mod conversion;
+pub mod fuzz;
+mod guest_alloc;
+pub mod guest_fs;
+
+#[cfg(any(feature = "x86_64", feature = "i386", feature = "aarch64"))]
+mod function_call;
+
 mod pinned_queue;
+mod scratch;
 mod syscall_future;
 mod syscall_regs;
 mod syscalls;
 
 pub(crate) use crate::abi::set_is_sysenter;
 use {
-    arch::{FORK_IS_CLONE, SYSCALL_RET, VFORK},
+    arch::{CLONE_VFORK, FORK_IS_CLONE, SIGCHLD, SYSCALL_RET, VFORK},
     pinned_queue::PinnedQueue,
-    syscall_future::{INJECTOR_BAIL, WAITING_FOR_SYSCALL},
+    syscall_future::{raw_syscall, INJECTOR_BAIL, WAITING_FOR_SYSCALL},
     syscall_regs::SyscallRegs,
 };
-pub use {conversion::*, syscall_future::*};
+pub use {conversion::*, guest_alloc::*, syscall_future::*};
+
+#[cfg_attr(
+    doc_cfg,
+    doc(cfg(any(feature = "x86_64", feature = "i386", feature = "aarch64")))
+)]
+#[cfg(any(feature = "x86_64", feature = "i386", feature = "aarch64"))]
+pub use function_call::{call_function, FunctionCallError};
 
 type Injector = dyn Future<Output = ()> + 'static;
 
@@ -97,7 +112,9 @@ struct ThreadId {
 impl ThreadId {
     fn current() -> Self {
         let cpu = unsafe { &mut *sys::get_cpu() };
-        let thread = OSI.get_current_thread(cpu);
+        let thread = OSI
+            .get_current_thread(cpu)
+            .expect("OSI could not find the current thread");
 
         let tid = thread.tid as target_ulong;
         let pid = thread.pid as target_ulong;
@@ -123,6 +140,10 @@ lazy_static! {
 
     /// A list of thread ids which have started forking but not returned from the fork
     static ref FORKING_THREADS: DashSet<ThreadId> = DashSet::new();
+
+    /// The syscall enter and return callbacks driving the injectors, while any are
+    /// running
+    static ref ACTIVE_CALLBACKS: Mutex<Option<(PppCallback, PppCallback)>> = Mutex::new(None);
 }
 
 struct ChildInjector((SyscallRegs, Pin<Box<Injector>>));
@@ -141,6 +162,10 @@ static JUST_CLONED: AtomicBool = AtomicBool::new(false);
 ///
 /// Registers will be restored once the child process completes as well, unless the
 /// child injector bails.
+///
+/// Returns the PID of the child process, once the child has exited or exec'd. On
+/// targets without a fork system call (such as aarch64), the fork is made using
+/// `clone(2)` instead.
 pub async fn fork(child_injector: impl Future<Output = ()> + 'static) -> target_ulong {
     // Since all state needs to be copied when forking, we also need to copy *our*
     // state. Since we've backed up the registers to restore once we're done injecting
@@ -163,30 +188,22 @@ pub async fn fork(child_injector: impl Future<Output = ()> + 'static) -> target_
 
     // aarch64 is a new enough Linux target that it deprecates `fork(2)` entirely and
     // replaces it with the `clone(2)`. This means that for certain targets we'll have
-    // our syscall number for it (`FORK`) actually be the syscall number for clone, which
-    // has a different set of arguments. Currently unsupported.
+    // our syscall number for it (`VFORK`) actually be the syscall number for clone, which
+    // has a different set of arguments. Both return twice like `fork(2)` does: the child
+    // is picked up by its parent PID in the syscall return callback, and the parent gets
+    // the PID of the child once the child exits or execs.
     if FORK_IS_CLONE {
-        const CLONE_FILES: target_ulong = 0x00000400;
-        const CLONE_VFORK: target_ulong = 0x00004000;
-        const CLONE_NEWPID: target_ulong = 0x20000000;
-
         const NULL: target_ptr_t = 0;
         const CLONE: target_ulong = VFORK;
 
-        JUST_CLONED.swap(true, Ordering::SeqCst);
+        JUST_CLONED.store(true, Ordering::SeqCst);
 
+        // No new stack, parent/child TID pointers or TLS, so the child continues on a
+        // copy of the parent's stack with the parent's thread pointer. The argument
+        // order differs between architectures, but every one other than the flags is
+        // null, so that doesn't matter here.
         log::debug!("Running clone syscall");
-        syscall(
-            CLONE,
-            (
-                CLONE_VFORK | CLONE_NEWPID | CLONE_FILES,
-                NULL,
-                NULL,
-                NULL,
-                NULL,
-            ),
-        )
-        .await
+        syscall(CLONE, (CLONE_VFORK | SIGCHLD, NULL, NULL, NULL, NULL)).await
     } else {
         syscall(VFORK, ()).await
     }
@@ -262,7 +279,10 @@ pub fn run_injector(pc: SyscallPc, injector: impl Future<Output = ()> + 'static)
     // put it in the PinnedQueue before we poll it the first time
     let is_first = INJECTORS.is_empty();
     let thread_id = ThreadId::current();
-    INJECTORS.entry(thread_id).or_default().push_future(async {
+    INJECTORS.entry(thread_id).or_default().push_future(async move {
+        #[cfg(any(feature = "x86_64", feature = "i386", feature = "aarch64"))]
+        function_call::set_injector_pc(pc);
+
         let backed_up_regs = SyscallRegs::backup();
         set_backed_up_regs(backed_up_regs.clone());
 
@@ -288,8 +308,11 @@ pub fn run_injector(pc: SyscallPc, injector: impl Future<Output = ()> + 'static)
             log::trace!("Disabling callbacks...");
             sys_enter.disable();
             sys_return.disable();
+            ACTIVE_CALLBACKS.lock().take();
         };
 
+        ACTIVE_CALLBACKS.lock().replace((sys_enter, sys_return));
+
         // after the syscall set the return value for the future then jump back to
         // the syscall instruction
         sys_return.on_all_sys_return(move |cpu: &mut CPUState, sys_pc, sys_num| {
@@ -311,6 +334,12 @@ pub fn run_injector(pc: SyscallPc, injector: impl Future<Output = ()> + 'static)
                 );
             }
 
+            // syscalls made by a function being called by an injector are left alone
+            #[cfg(any(feature = "x86_64", feature = "i386", feature = "aarch64"))]
+            if function_call::in_function_call() {
+                return;
+            }
+
             if sys_num == VFORK {
                 log::trace!("ret = {:#x?}", regs::get_reg(cpu, SYSCALL_RET));
             }
@@ -334,6 +363,7 @@ pub fn run_injector(pc: SyscallPc, injector: impl Future<Output = ()> + 'static)
                 } else {
                     log::debug!("Returning from fork {:?}", &thread_id);
                     FORKING_THREADS.remove(&thread_id);
+                    JUST_CLONED.store(false, Ordering::SeqCst);
                 }
             }
 
@@ -404,10 +434,19 @@ pub fn run_injector(pc: SyscallPc, injector: impl Future<Output = ()> + 'static)
                 pc
             );
 
+            #[cfg(any(feature = "x86_64", feature = "i386", feature = "aarch64"))]
+            if function_call::in_function_call()
+                && !function_call::check_function_return(cpu, sys_pc.pc())
+            {
+                return;
+            }
+
             if poll_injectors() {
                 disable_callbacks();
             }
 
+            jump_to_called_function(cpu);
+
             if SHOULD_LOOP_AGAIN.swap(false, Ordering::SeqCst) {
                 restart_syscall(cpu, pc);
             }
@@ -419,6 +458,44 @@ pub fn run_injector(pc: SyscallPc, injector: impl Future<Output = ()> + 'static)
             println!("WARN: Injector seemed to not call any system calls?");
             disable_callbacks();
         }
+
+        jump_to_called_function(unsafe { &mut *sys::get_cpu() });
+    }
+}
+
+/// Drop every running and queued injector without restoring the registers they backed
+/// up, such as before the guest is reverted to a snapshot. Must not be called from
+/// within an injector.
+pub(crate) fn cancel_injectors() {
+    if let Some((sys_enter, sys_return)) = ACTIVE_CALLBACKS.lock().take() {
+        sys_enter.disable();
+        sys_return.disable();
+    }
+
+    INJECTORS.clear();
+    FORKING_THREADS.clear();
+    CHILD_INJECTOR.lock().take();
+    CURRENT_REGS_BACKUP.clear();
+    CURRENT_INJECTOR_THREAD.lock().take();
+    scratch::forget_scratch();
+
+    #[cfg(any(feature = "x86_64", feature = "i386", feature = "aarch64"))]
+    function_call::forget_calls();
+
+    PARENT_PID.store(u64::MAX, Ordering::SeqCst);
+    JUST_CLONED.store(false, Ordering::SeqCst);
+    SHOULD_LOOP_AGAIN.store(false, Ordering::SeqCst);
+    INJECTOR_BAIL.store(false, Ordering::SeqCst);
+    WAITING_FOR_SYSCALL.store(false, Ordering::SeqCst);
+}
+
+/// If the current injector has called a function, start running it
+#[allow(unused_variables)]
+fn jump_to_called_function(cpu: &mut CPUState) {
+    #[cfg(any(feature = "x86_64", feature = "i386", feature = "aarch64"))]
+    if let Some(func) = function_call::take_call_target() {
+        SHOULD_LOOP_AGAIN.store(false, Ordering::SeqCst);
+        restart_syscall(cpu, func);
     }
 }
 
@@ -25,3 +25,144 @@ pub(crate) const FORK_IS_CLONE: bool = cfg!(any(
     feature = "mips",
     feature = "mipsel"
 ));
+
+// Flags for implementing fork with `clone(2)` on targets where `VFORK` is clone. The
+// child gets a copy of the address space and file table like it would with `vfork(2)`,
+// and the parent is suspended until the child exits or execs, like with `vfork(2)`.
+pub(crate) const CLONE_VFORK: target_ulong = 0x00004000;
+
+// The signal sent to the parent when the child exits, which goes in the low byte of
+// the clone flags. Without it the child can't be waited on like a forked process.
+#[cfg(not(any(feature = "mips", feature = "mipsel")))]
+pub(crate) const SIGCHLD: target_ulong = 17;
+
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const SIGCHLD: target_ulong = 18;
+
+// Used to map scratch memory for syscall arguments. 32-bit ARM and x86 use mmap2,
+// which takes its offset in pages.
+#[cfg(feature = "x86_64")]
+pub(crate) const MMAP: target_ulong = 9;
+#[cfg(feature = "x86_64")]
+pub(crate) const MUNMAP: target_ulong = 11;
+
+#[cfg(any(feature = "i386", feature = "arm"))]
+pub(crate) const MMAP: target_ulong = 192;
+#[cfg(any(feature = "i386", feature = "arm"))]
+pub(crate) const MUNMAP: target_ulong = 91;
+
+#[cfg(feature = "aarch64")]
+pub(crate) const MMAP: target_ulong = 222;
+#[cfg(feature = "aarch64")]
+pub(crate) const MUNMAP: target_ulong = 215;
+
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const MMAP: target_ulong = 4210;
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const MUNMAP: target_ulong = 4091;
+
+#[cfg(any(feature = "mips64", feature = "mips64el"))]
+pub(crate) const MMAP: target_ulong = 5009;
+#[cfg(any(feature = "mips64", feature = "mips64el"))]
+pub(crate) const MUNMAP: target_ulong = 5011;
+
+pub(crate) const PROT_READ_WRITE: target_ulong = 0x3;
+
+/// The largest errno a syscall can fail with, as errors are returned as `-errno`
+const MAX_ERRNO: target_ulong = 4095;
+
+/// Get the errno of a syscall's return value, or `None` if it succeeded
+pub(crate) fn errno(ret: target_ulong) -> Option<target_ulong> {
+    (ret > target_ulong::MAX - MAX_ERRNO).then(|| ret.wrapping_neg())
+}
+
+// MAP_PRIVATE | MAP_ANONYMOUS | MAP_POPULATE, populated so the pages can be written
+// to from the host
+#[cfg(not(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+)))]
+pub(crate) const MAP_SCRATCH: target_ulong = 0x8022;
+
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+pub(crate) const MAP_SCRATCH: target_ulong = 0x10802;
+
+// File syscalls used by `guest_fs`. `openat` is used rather than `open` as aarch64
+// doesn't have `open`.
+#[cfg(feature = "x86_64")]
+pub(crate) const READ: target_ulong = 0;
+#[cfg(feature = "x86_64")]
+pub(crate) const WRITE: target_ulong = 1;
+#[cfg(feature = "x86_64")]
+pub(crate) const CLOSE: target_ulong = 3;
+#[cfg(feature = "x86_64")]
+pub(crate) const LSEEK: target_ulong = 8;
+#[cfg(feature = "x86_64")]
+pub(crate) const OPENAT: target_ulong = 257;
+
+#[cfg(any(feature = "i386", feature = "arm"))]
+pub(crate) const READ: target_ulong = 3;
+#[cfg(any(feature = "i386", feature = "arm"))]
+pub(crate) const WRITE: target_ulong = 4;
+#[cfg(any(feature = "i386", feature = "arm"))]
+pub(crate) const CLOSE: target_ulong = 6;
+#[cfg(any(feature = "i386", feature = "arm"))]
+pub(crate) const LSEEK: target_ulong = 19;
+#[cfg(feature = "i386")]
+pub(crate) const OPENAT: target_ulong = 295;
+#[cfg(feature = "arm")]
+pub(crate) const OPENAT: target_ulong = 322;
+
+#[cfg(feature = "aarch64")]
+pub(crate) const READ: target_ulong = 63;
+#[cfg(feature = "aarch64")]
+pub(crate) const WRITE: target_ulong = 64;
+#[cfg(feature = "aarch64")]
+pub(crate) const CLOSE: target_ulong = 57;
+#[cfg(feature = "aarch64")]
+pub(crate) const LSEEK: target_ulong = 62;
+#[cfg(feature = "aarch64")]
+pub(crate) const OPENAT: target_ulong = 56;
+
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const READ: target_ulong = 4003;
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const WRITE: target_ulong = 4004;
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const CLOSE: target_ulong = 4006;
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const LSEEK: target_ulong = 4019;
+#[cfg(any(feature = "mips", feature = "mipsel"))]
+pub(crate) const OPENAT: target_ulong = 4288;
+
+#[cfg(any(feature = "mips64", feature = "mips64el"))]
+pub(crate) const READ: target_ulong = 5000;
+#[cfg(any(feature = "mips64", feature = "mips64el"))]
+pub(crate) const WRITE: target_ulong = 5001;
+#[cfg(any(feature = "mips64", feature = "mips64el"))]
+pub(crate) const CLOSE: target_ulong = 5003;
+#[cfg(any(feature = "mips64", feature = "mips64el"))]
+pub(crate) const LSEEK: target_ulong = 5008;
+#[cfg(any(feature = "mips64", feature = "mips64el"))]
+pub(crate) const OPENAT: target_ulong = 5247;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errno() {
+        assert_eq!(errno(0), None);
+        assert_eq!(errno(0x1000), None);
+        assert_eq!(errno(target_ulong::MAX), Some(1));
+        assert_eq!(errno((2 as target_ulong).wrapping_neg()), Some(2));
+        assert_eq!(errno((MAX_ERRNO + 1).wrapping_neg()), None);
+    }
+}
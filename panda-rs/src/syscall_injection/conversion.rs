@@ -1,4 +1,6 @@
-use crate::sys::target_ulong;
+use super::scratch::alloc_scratch;
+use crate::sys::{get_cpu, target_long, target_ulong};
+use crate::{GuestPtr, GuestType};
 use async_trait::async_trait;
 
 use std::convert::TryInto;
@@ -9,7 +11,9 @@ use super::syscall;
 /// A trait for converting a single value into a syscall argument.
 ///
 /// This trait is asynchronous to allow for system calls to be performed
-/// during the conversion (for example to map memory in the guest).
+/// during the conversion (for example to map memory in the guest). Strings, byte
+/// buffers and [`ByRef`] values are copied into memory mapped in the guest for the
+/// duration of the syscall, and file descriptors can be passed using [`Fd`].
 #[async_trait]
 pub trait IntoSyscallArg {
     async fn into_syscall_arg(self) -> target_ulong;
@@ -30,6 +34,111 @@ macro_rules! impl_for_ints {
 
 impl_for_ints!(u8, u16, u32, u64);
 
+/// A file descriptor argument, which is sign-extended so that negative values such as
+/// `AT_FDCWD` are passed correctly
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Fd(pub i32);
+
+#[async_trait]
+impl IntoSyscallArg for Fd {
+    async fn into_syscall_arg(self) -> target_ulong {
+        self.0 as target_long as target_ulong
+    }
+}
+
+/// Strings are copied into guest memory with a NUL terminator and passed by pointer.
+/// The memory is unmapped once the syscall returns.
+#[async_trait]
+impl<'a> IntoSyscallArg for &'a str {
+    async fn into_syscall_arg(self) -> target_ulong {
+        let mut bytes = Vec::with_capacity(self.len() + 1);
+        bytes.extend_from_slice(self.as_bytes());
+        bytes.push(0);
+
+        alloc_scratch(&bytes).await
+    }
+}
+
+#[async_trait]
+impl IntoSyscallArg for String {
+    async fn into_syscall_arg(self) -> target_ulong {
+        self.as_str().into_syscall_arg().await
+    }
+}
+
+/// Byte buffers are copied into guest memory and passed by pointer. The memory is
+/// unmapped once the syscall returns.
+#[async_trait]
+impl<'a> IntoSyscallArg for &'a [u8] {
+    async fn into_syscall_arg(self) -> target_ulong {
+        alloc_scratch(self).await
+    }
+}
+
+#[async_trait]
+impl IntoSyscallArg for Vec<u8> {
+    async fn into_syscall_arg(self) -> target_ulong {
+        alloc_scratch(&self).await
+    }
+}
+
+/// A value passed to a syscall by pointer, such as a `struct timespec`. The value is
+/// written to guest memory using its [`GuestType`] layout, which is unmapped once the
+/// syscall returns.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::syscall_injection::{syscall, ByRef};
+/// use panda::GuestType;
+///
+/// #[derive(GuestType)]
+/// struct Timespec {
+///     tv_sec: u64,
+///     tv_nsec: u64,
+/// }
+///
+/// const NANOSLEEP: u64 = 35;
+///
+/// async fn sleep(secs: u64) {
+///     let duration = Timespec {
+///         tv_sec: secs,
+///         tv_nsec: 0,
+///     };
+///
+///     syscall(NANOSLEEP, (ByRef(duration), 0u64)).await;
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByRef<T: GuestType>(pub T);
+
+#[async_trait]
+impl<T: GuestType + Send> IntoSyscallArg for ByRef<T> {
+    async fn into_syscall_arg(self) -> target_ulong {
+        let size = T::guest_size().expect("Cannot pass an unsized GuestType by reference");
+        let addr = alloc_scratch(&vec![0; size]).await;
+        if addr == 0 {
+            return 0;
+        }
+
+        let cpu = unsafe { &mut *get_cpu() };
+        if self.0.write_to_guest(cpu, addr).is_err() {
+            log::warn!("Failed to write syscall argument to {:#x}", addr);
+        }
+
+        addr
+    }
+}
+
+/// Guest pointers, such as those returned by [`guest_malloc`](super::guest_malloc), are
+/// passed as their address
+#[async_trait]
+impl<T: GuestType + Send + Sync> IntoSyscallArg for GuestPtr<T> {
+    async fn into_syscall_arg(self) -> target_ulong {
+        self.addr()
+    }
+}
+
 /// A trait for converting a set of values into a full set of arguments for
 /// performing a system call. This trait is primarily used to provide arguments
 /// to the [`syscall`] function.
@@ -54,6 +163,21 @@ pub struct SyscallArgs {
 }
 
 impl SyscallArgs {
+    pub(crate) fn new(args: &[target_ulong]) -> Self {
+        assert!(
+            args.len() <= 6,
+            "Only up to 6 syscall arguments are allowed"
+        );
+
+        let mut regs = [0; 6];
+        regs[..args.len()].copy_from_slice(args);
+
+        Self {
+            regs,
+            regs_used: args.len(),
+        }
+    }
+
     pub fn iter_args(&self) -> impl Iterator<Item = target_ulong> + '_ {
         self.regs.iter().copied().take(self.regs_used)
     }
@@ -0,0 +1,298 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc},
+    task::{Context, Poll},
+};
+
+use super::syscall_future::WAITING_FOR_SYSCALL;
+use super::ThreadId;
+use crate::prelude::*;
+use crate::{cpu_arch_state, regs, CPUArchPtr};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use panda_sys::get_cpu;
+
+/// Bytes below the stack pointer which functions may use without moving the stack
+/// pointer, which must be skipped over before setting up a call
+const RED_ZONE: target_ulong = 128;
+
+#[cfg(feature = "x86_64")]
+const MAX_ARGS: usize = 6;
+
+#[cfg(feature = "i386")]
+const MAX_ARGS: usize = 6;
+
+#[cfg(feature = "aarch64")]
+const MAX_ARGS: usize = 8;
+
+/// An error in calling a function in the guest
+#[derive(thiserror::Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FunctionCallError {
+    #[error("only up to {MAX_ARGS} function arguments are supported, {0} were given")]
+    TooManyArgs(usize),
+
+    #[error("call_function was run outside of an injector")]
+    NotInInjector,
+}
+
+/// The general purpose registers of the thread making a function call, which the
+/// called function is free to clobber
+#[derive(Clone, Copy)]
+struct SavedRegs {
+    #[cfg(any(feature = "x86_64", feature = "i386"))]
+    regs: [target_ulong; panda_sys::CPU_NB_REGS as usize],
+
+    #[cfg(feature = "aarch64")]
+    xregs: [u64; 32],
+}
+
+impl SavedRegs {
+    fn backup(cpu: &mut CPUState) -> Self {
+        let env = unsafe { &*cpu_arch_state!(cpu) };
+
+        Self {
+            #[cfg(any(feature = "x86_64", feature = "i386"))]
+            regs: env.regs,
+
+            #[cfg(feature = "aarch64")]
+            xregs: env.xregs,
+        }
+    }
+
+    fn restore(self, cpu: &mut CPUState) {
+        let env = unsafe { &mut *cpu_arch_state!(cpu) };
+
+        #[cfg(any(feature = "x86_64", feature = "i386"))]
+        {
+            env.regs = self.regs;
+        }
+
+        #[cfg(feature = "aarch64")]
+        {
+            env.xregs = self.xregs;
+        }
+    }
+}
+
+/// A function call which has been set up but hasn't returned yet
+struct PendingCall {
+    regs: SavedRegs,
+    return_pc: target_ulong,
+    return_sp: target_ulong,
+    ret_val: Arc<OnceCell<target_ulong>>,
+}
+
+lazy_static! {
+    static ref PENDING_CALLS: DashMap<ThreadId, PendingCall> = DashMap::new();
+
+    /// Functions to jump to once the injectors have been polled
+    static ref CALL_TARGETS: DashMap<ThreadId, target_ulong> = DashMap::new();
+
+    /// The address of the syscall instruction each thread is being injected into
+    static ref INJECTOR_PCS: DashMap<ThreadId, target_ulong> = DashMap::new();
+}
+
+pub(crate) fn set_injector_pc(pc: target_ulong) {
+    INJECTOR_PCS.insert(ThreadId::current(), pc);
+}
+
+/// Forget the function calls of every thread, for when the injectors making them have
+/// been cancelled
+pub(crate) fn forget_calls() {
+    PENDING_CALLS.clear();
+    CALL_TARGETS.clear();
+    INJECTOR_PCS.clear();
+}
+
+/// Whether the current thread is running a function called by an injector. The
+/// thread's own system calls are left alone while this is the case.
+pub(crate) fn in_function_call() -> bool {
+    PENDING_CALLS.contains_key(&ThreadId::current())
+}
+
+/// Take the address of the function the current thread should jump to, if an injector
+/// has just called one
+pub(crate) fn take_call_target() -> Option<target_ulong> {
+    CALL_TARGETS
+        .remove(&ThreadId::current())
+        .map(|(_, target)| target)
+}
+
+/// Check whether a system call entered by the current thread is the called function
+/// returning to the injected syscall instruction. If so, the function's return value
+/// is passed to the injector and the thread's registers are restored.
+pub(crate) fn check_function_return(cpu: &mut CPUState, sys_pc: target_ulong) -> bool {
+    let thread_id = ThreadId::current();
+    let returned = PENDING_CALLS
+        .get(&thread_id)
+        .map(|call| sys_pc == call.return_pc && stack_pointer(cpu) == call.return_sp)
+        .unwrap_or(false);
+
+    if !returned {
+        return false;
+    }
+
+    if let Some((_, call)) = PENDING_CALLS.remove(&thread_id) {
+        let ret = regs::get_reg(cpu, regs::reg_ret_val()[0]);
+        log::trace!("Injected function call returned {:#x?}", ret);
+
+        call.regs.restore(cpu);
+        let _ = call.ret_val.set(ret);
+    }
+
+    true
+}
+
+fn stack_pointer(cpu: &mut CPUState) -> target_ulong {
+    #[cfg(any(feature = "x86_64", feature = "i386"))]
+    {
+        regs::get_reg(cpu, regs::reg_sp())
+    }
+
+    #[cfg(feature = "aarch64")]
+    {
+        unsafe { (*cpu_arch_state!(cpu)).xregs[31] }
+    }
+}
+
+#[cfg(any(feature = "x86_64", feature = "i386"))]
+fn write_word(cpu: &mut CPUState, addr: target_ptr_t, val: target_ulong) {
+    crate::mem::virtual_memory_write(cpu, addr, &val.to_le_bytes());
+}
+
+/// Set up the registers and stack to call `func` with `args`, returning to `ret_addr`.
+/// Returns the stack pointer expected once the function has returned.
+fn setup_call(cpu: &mut CPUState, args: &[target_ulong], ret_addr: target_ulong) -> target_ulong {
+    #[cfg(feature = "x86_64")]
+    {
+        use regs::Reg::*;
+
+        const ARG_REGS: [regs::Reg; MAX_ARGS] = [RDI, RSI, RDX, RCX, R8, R9];
+
+        let sp = (regs::get_reg(cpu, RSP) - RED_ZONE) & !0xf;
+        let sp = sp - 8;
+        write_word(cpu, sp, ret_addr);
+        regs::set_reg(cpu, RSP, sp);
+
+        for (&reg, &arg) in ARG_REGS.iter().zip(args) {
+            regs::set_reg(cpu, reg, arg);
+        }
+
+        // number of vector registers used, in case the function is variadic
+        regs::set_reg(cpu, RAX, 0);
+
+        sp + 8
+    }
+
+    #[cfg(feature = "i386")]
+    {
+        let args_size = (args.len() * 4) as target_ulong;
+        let args_start = (regs::get_reg(cpu, regs::Reg::ESP) - RED_ZONE - args_size) & !0xf;
+        for (i, &arg) in args.iter().enumerate() {
+            write_word(cpu, args_start + (i * 4) as target_ulong, arg);
+        }
+
+        let sp = args_start - 4;
+        write_word(cpu, sp, ret_addr);
+        regs::set_reg(cpu, regs::Reg::ESP, sp);
+
+        sp + 4
+    }
+
+    #[cfg(feature = "aarch64")]
+    {
+        let env = unsafe { &mut *cpu_arch_state!(cpu) };
+        for (i, &arg) in args.iter().enumerate() {
+            env.xregs[i] = arg;
+        }
+
+        let sp = (env.xregs[31] - RED_ZONE) & !0xf;
+        env.xregs[30] = ret_addr;
+        env.xregs[31] = sp;
+
+        sp
+    }
+}
+
+struct FunctionCallFuture {
+    ret_val: Arc<OnceCell<target_ulong>>,
+}
+
+impl Future for FunctionCallFuture {
+    type Output = target_ulong;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.ret_val.get() {
+            Some(ret_val) => Poll::Ready(*ret_val),
+
+            // keep the runtime from polling again until the function has returned
+            None => {
+                WAITING_FOR_SYSCALL.store(true, Ordering::SeqCst);
+
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Call a function in the guest process being injected into, returning the value it
+/// returns. Should only be run within an injector being run by
+/// [`run_injector`](crate::syscall_injection::run_injector), and fails with
+/// [`FunctionCallError::NotInInjector`] otherwise.
+///
+/// Arguments are passed according to the platform's C calling convention, and only
+/// integer and pointer arguments are supported. The function returns to the system
+/// call instruction being injected into, after which all general purpose registers
+/// are restored. Any system calls made by the function are left alone.
+///
+/// Supported on x86_64, i386 and aarch64, with up to 6, 6 and 8 arguments respectively.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::prelude::*;
+/// use panda::syscall_injection::{call_function, run_injector};
+///
+/// # let (pc, getpid_addr): (SyscallPc, target_ulong) = todo!();
+/// run_injector(pc, async move {
+///     if let Ok(pid) = call_function(getpid_addr, &[]).await {
+///         println!("getpid() = {}", pid);
+///     }
+/// });
+/// ```
+pub async fn call_function(
+    func: target_ulong,
+    args: &[target_ulong],
+) -> Result<target_ulong, FunctionCallError> {
+    if args.len() > MAX_ARGS {
+        return Err(FunctionCallError::TooManyArgs(args.len()));
+    }
+
+    let cpu = unsafe { &mut *get_cpu() };
+    let thread_id = ThreadId::current();
+    let return_pc = *INJECTOR_PCS
+        .get(&thread_id)
+        .ok_or(FunctionCallError::NotInInjector)?;
+
+    log::trace!("Injecting call to {:#x?} with args {:#x?}", func, args);
+
+    let regs = SavedRegs::backup(cpu);
+    let return_sp = setup_call(cpu, args, return_pc);
+    let ret_val = Arc::new(OnceCell::new());
+
+    PENDING_CALLS.insert(
+        thread_id,
+        PendingCall {
+            regs,
+            return_pc,
+            return_sp,
+            ret_val: Arc::clone(&ret_val),
+        },
+    );
+    CALL_TARGETS.insert(thread_id, func);
+
+    Ok(FunctionCallFuture { ret_val }.await)
+}
@@ -0,0 +1,875 @@
+//! A syscall fuzzer built on syscall injection
+//!
+//! Once [`start`]ed, the fuzzer waits for the victim process to make a syscall, then
+//! injects a short random sequence of syscalls into it in its place. Syscalls are
+//! picked from the [prototype table](crate::syscalls) and their arguments are generated
+//! from their types: integers are drawn from values which tend to find edge cases,
+//! strings from a dictionary of paths, pointers point to buffers of random bytes mapped
+//! in the guest, and file descriptors are often the value returned by an earlier
+//! syscall in the sequence.
+//!
+//! Each sequence is run to completion, then the victim is left to make a few more
+//! syscalls of its own (see [`Fuzzer::settle_syscalls`]) before the guest is reverted
+//! to a snapshot, ready for the next sequence. If the victim is sent a fatal signal
+//! (such as `SIGSEGV`) the sequence is reported as a crash, and if it doesn't finish
+//! within [`Fuzzer::hang_timeout`] it is reported as a hang. Every sequence is passed
+//! to [`on_case`] callbacks as a [`FuzzCase`], which records the seed it was generated
+//! from so it can be regenerated with [`Fuzzer::generate`].
+//!
+//! Signals are observed through the `signal` plugin and processes through the `osi`
+//! plugin, and the prototype table requires syscalls2 to be loaded with
+//! `load-info=true` (see [`syscalls`](crate::syscalls)).
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::syscall_injection::fuzz::{self, Fuzzer};
+//!
+//! fn main() {
+//!     fuzz::start(
+//!         Fuzzer::new("victim")
+//!             .snapshot("root")
+//!             .iterations(10_000)
+//!             .deny(&["unlink", "rmdir"]),
+//!     );
+//!
+//!     fuzz::on_case(|case| {
+//!         if case.is_finding() {
+//!             println!("{}", case);
+//!         }
+//!     });
+//!
+//!     Panda::new()
+//!         .generic("x86_64")
+//!         .args(&["-loadvm", "root"])
+//!         .run();
+//! }
+//! ```
+use super::conversion::IntoSyscallArg;
+use super::scratch::alloc_scratch;
+use super::{cancel_injectors, run_injector, syscall};
+use crate::plugins::osi::OSI;
+use crate::plugins::signal::{
+    SignalCallbacks, SignalEvent, SIGABRT, SIGBUS, SIGFPE, SIGILL, SIGSEGV,
+};
+use crate::plugins::syscalls2::Syscalls2Callbacks;
+use crate::prelude::*;
+use crate::syscalls::{self, ArgKind, Prototype, SyscallArg};
+use crate::{Callback, PppCallback};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Syscalls which are never injected unless explicitly allowed, as they end or replace
+/// the victim, block indefinitely or send signals which would be mistaken for crashes
+const DEFAULT_DENY: &[&str] = &[
+    "exit",
+    "exit_group",
+    "execve",
+    "execveat",
+    "fork",
+    "vfork",
+    "clone",
+    "clone3",
+    "reboot",
+    "kill",
+    "tkill",
+    "tgkill",
+    "rt_sigqueueinfo",
+    "rt_tgsigqueueinfo",
+    "rt_sigreturn",
+    "sigreturn",
+    "rt_sigsuspend",
+    "pause",
+    "munmap",
+    "mremap",
+    "mprotect",
+    "brk",
+    "wait4",
+    "waitid",
+    "nanosleep",
+    "clock_nanosleep",
+];
+
+/// Paths used for string arguments by default
+const DEFAULT_STRINGS: &[&str] = &[
+    "/",
+    ".",
+    "/tmp",
+    "/tmp/fuzz",
+    "/dev/null",
+    "/dev/zero",
+    "/proc/self/maps",
+    "/etc/passwd",
+];
+
+/// Integers which tend to find edge cases, before being truncated to the size of the
+/// argument
+const INTERESTING: &[u64] = &[
+    0,
+    1,
+    2,
+    3,
+    4,
+    8,
+    16,
+    0x40,
+    0x7f,
+    0x80,
+    0xff,
+    0x100,
+    0x1000,
+    0x7fff,
+    0xffff,
+    0x7fff_ffff,
+    0x8000_0000,
+    0xffff_ffff,
+    u64::MAX,
+];
+
+/// Sizes of the buffers pointer arguments point to
+const BUFFER_SIZES: &[usize] = &[0, 1, 8, 64, 256, 4096];
+
+/// A small deterministic random number generator (SplitMix64), so that a sequence can
+/// be regenerated from its seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+
+    fn chance(&mut self, one_in: usize) -> bool {
+        self.below(one_in) == 0
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// The configuration of a fuzzing campaign, passed to [`start`]
+#[derive(Debug, Clone)]
+pub struct Fuzzer {
+    target: String,
+    snapshot: String,
+    seed: u64,
+    min_len: usize,
+    max_len: usize,
+    iterations: Option<u64>,
+    hang_timeout: Duration,
+    settle_syscalls: usize,
+    allow: Option<Vec<String>>,
+    deny: Vec<String>,
+    strings: Vec<String>,
+    crash_signals: Vec<i32>,
+}
+
+impl Fuzzer {
+    /// Fuzz the process with the given name
+    pub fn new(target: &str) -> Self {
+        Self {
+            target: target.to_owned(),
+            snapshot: "root".to_owned(),
+            seed: 0,
+            min_len: 1,
+            max_len: 8,
+            iterations: None,
+            hang_timeout: Duration::from_secs(10),
+            settle_syscalls: 16,
+            allow: None,
+            deny: DEFAULT_DENY.iter().map(|&name| name.to_owned()).collect(),
+            strings: DEFAULT_STRINGS
+                .iter()
+                .map(|&path| path.to_owned())
+                .collect(),
+            crash_signals: vec![SIGSEGV, SIGBUS, SIGILL, SIGFPE, SIGABRT],
+        }
+    }
+
+    /// Set the snapshot the guest is reverted to after each sequence, which should be
+    /// one the victim is already running in. Defaults to `root`.
+    pub fn snapshot(mut self, name: &str) -> Self {
+        self.snapshot = name.to_owned();
+        self
+    }
+
+    /// Set the seed which the seed of each sequence is derived from. Defaults to 0.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Set the smallest and largest number of syscalls injected in each sequence.
+    /// Defaults to 1 to 8.
+    pub fn sequence_len(mut self, min: usize, max: usize) -> Self {
+        self.min_len = min.max(1);
+        self.max_len = max.max(self.min_len);
+        self
+    }
+
+    /// Stop after the given number of sequences have been run. Defaults to running
+    /// until [`stop`] is called.
+    pub fn iterations(mut self, iterations: u64) -> Self {
+        self.iterations = Some(iterations);
+        self
+    }
+
+    /// Set how long a sequence can take, including settling, before it is reported as
+    /// a hang. Defaults to 10 seconds.
+    pub fn hang_timeout(mut self, timeout: Duration) -> Self {
+        self.hang_timeout = timeout;
+        self
+    }
+
+    /// Set the number of syscalls the victim makes after a sequence before the guest
+    /// is reverted, which gives crashes caused by the state the sequence left behind a
+    /// chance to happen. Defaults to 16.
+    pub fn settle_syscalls(mut self, count: usize) -> Self {
+        self.settle_syscalls = count;
+        self
+    }
+
+    /// Only inject syscalls with the given names (such as `"openat"`), including any
+    /// which are denied by default
+    pub fn allow(mut self, names: &[&str]) -> Self {
+        self.allow = Some(names.iter().map(|&name| name.to_owned()).collect());
+        self
+    }
+
+    /// Never inject syscalls with the given names, in addition to those which are
+    /// denied by default
+    pub fn deny(mut self, names: &[&str]) -> Self {
+        self.deny.extend(names.iter().map(|&name| name.to_owned()));
+        self
+    }
+
+    /// Add strings to use for string arguments, in addition to a default set of paths
+    pub fn strings(mut self, strings: &[&str]) -> Self {
+        self.strings
+            .extend(strings.iter().map(|&string| string.to_owned()));
+        self
+    }
+
+    /// Set the signals which are reported as crashes when sent to the victim. Defaults
+    /// to `SIGSEGV`, `SIGBUS`, `SIGILL`, `SIGFPE` and `SIGABRT`.
+    pub fn crash_signals(mut self, signals: &[i32]) -> Self {
+        self.crash_signals = signals.to_vec();
+        self
+    }
+
+    fn is_allowed(&self, prototype: &Prototype) -> bool {
+        match &self.allow {
+            Some(allow) => allow.contains(&prototype.name),
+            None => !prototype.noreturn && !self.deny.contains(&prototype.name),
+        }
+    }
+
+    /// The seed the sequence of the given iteration is generated from
+    fn case_seed(&self, iteration: u64) -> u64 {
+        Rng(self.seed ^ iteration.wrapping_mul(0x2545_f491_4f6c_dd1d)).next()
+    }
+
+    /// Generate the sequence of syscalls for the given seed, such as to reproduce a
+    /// [`FuzzCase`]. Returns an empty sequence if no syscalls can be injected.
+    pub fn generate(&self, seed: u64) -> Vec<FuzzCall> {
+        let candidates: Vec<&'static Prototype> = syscalls::prototypes()
+            .filter(|prototype| self.is_allowed(prototype))
+            .collect();
+
+        self.generate_from(&candidates, seed)
+    }
+
+    fn generate_from(&self, candidates: &[&'static Prototype], seed: u64) -> Vec<FuzzCall> {
+        if candidates.is_empty() {
+            return Vec::new();
+        }
+
+        let mut rng = Rng(seed);
+        let len = self.min_len + rng.below(self.max_len - self.min_len + 1);
+
+        (0..len)
+            .map(|index| {
+                let prototype = *rng.pick(candidates);
+                let args = prototype
+                    .args
+                    .iter()
+                    .map(|arg| self.generate_arg(&mut rng, arg, index))
+                    .collect();
+
+                FuzzCall {
+                    prototype,
+                    args,
+                    ret: None,
+                }
+            })
+            .collect()
+    }
+
+    fn generate_arg(&self, rng: &mut Rng, arg: &SyscallArg, index: usize) -> FuzzArg {
+        let is_fd = arg.name == "fd" || arg.name.ends_with("fd");
+
+        match arg.kind {
+            _ if is_fd && index > 0 && !rng.chance(3) => FuzzArg::Result(rng.below(index)),
+            ArgKind::String if !rng.chance(8) => FuzzArg::String(rng.pick(&self.strings).clone()),
+            ArgKind::String | ArgKind::Pointer | ArgKind::StructPointer if !rng.chance(8) => {
+                let len = *rng.pick(BUFFER_SIZES);
+
+                FuzzArg::Buffer((0..len).map(|_| rng.next() as u8).collect())
+            }
+            _ => {
+                let value = match rng.below(4) {
+                    0 => rng.next(),
+                    1 => 1 << rng.below(64),
+                    _ => *rng.pick(INTERESTING),
+                };
+                let bits = (arg.size * 8).clamp(8, 64);
+
+                FuzzArg::Value((value & (u64::MAX >> (64 - bits))) as target_ulong)
+            }
+        }
+    }
+}
+
+/// An argument of an injected syscall
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FuzzArg {
+    Value(target_ulong),
+
+    /// A pointer to a copy of the bytes in guest memory
+    Buffer(Vec<u8>),
+
+    /// A pointer to a NUL-terminated copy of the string in guest memory
+    String(String),
+
+    /// The value returned by the syscall at the given index of the sequence, such as a
+    /// file descriptor
+    Result(usize),
+}
+
+impl fmt::Display for FuzzArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FuzzArg::Value(value) => write!(f, "{:#x}", value),
+            FuzzArg::Buffer(bytes) => write!(f, "<{} bytes>", bytes.len()),
+            FuzzArg::String(string) => write!(f, "{:?}", string),
+            FuzzArg::Result(index) => write!(f, "${}", index),
+        }
+    }
+}
+
+/// A syscall of a fuzzed sequence
+#[derive(Debug, Clone)]
+pub struct FuzzCall {
+    pub prototype: &'static Prototype,
+    pub args: Vec<FuzzArg>,
+
+    /// The value returned by the syscall, if it returned
+    pub ret: Option<target_ulong>,
+}
+
+impl fmt::Display for FuzzCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self
+            .prototype
+            .args
+            .iter()
+            .zip(&self.args)
+            .map(|(arg, value)| format!("{}={}", arg.name, value))
+            .collect();
+
+        write!(f, "{}({})", self.prototype.name, args.join(", "))?;
+
+        match self.ret {
+            Some(ret) => write!(f, " = {:#x}", ret),
+            None => write!(f, " = ?"),
+        }
+    }
+}
+
+/// How a fuzzed sequence ended
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    /// The sequence ran and the victim settled without being sent a crash signal
+    Completed,
+
+    /// The victim was sent the given crash signal
+    Crash { signal: i32 },
+
+    /// The sequence didn't finish within the hang timeout
+    Hang,
+}
+
+/// A sequence of syscalls which has been injected and how it ended
+#[derive(Debug, Clone)]
+pub struct FuzzCase {
+    /// The number of sequences run before this one
+    pub iteration: u64,
+
+    /// The seed the sequence was generated from, see [`Fuzzer::generate`]
+    pub seed: u64,
+
+    pub calls: Vec<FuzzCall>,
+    pub outcome: Outcome,
+}
+
+impl FuzzCase {
+    /// Whether the sequence crashed or hung the victim
+    pub fn is_finding(&self) -> bool {
+        self.outcome != Outcome::Completed
+    }
+}
+
+impl fmt::Display for FuzzCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "case {} (seed {:#x}): {:?}",
+            self.iteration, self.seed, self.outcome
+        )?;
+
+        for (index, call) in self.calls.iter().enumerate() {
+            writeln!(f, "  ${} = {}", index, call)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts of the sequences run so far
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FuzzStats {
+    pub iterations: u64,
+    pub crashes: u64,
+    pub hangs: u64,
+
+    /// The number of injected syscalls which have returned
+    pub syscalls: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the victim to make a syscall to inject into
+    Idle,
+    Injecting,
+
+    /// Waiting for the victim to make the given number of syscalls
+    Settling(usize),
+
+    /// Waiting for the guest to be reverted to the snapshot
+    Reverting,
+    Done,
+}
+
+struct Engine {
+    fuzzer: Fuzzer,
+    phase: Phase,
+    iteration: u64,
+    seed: u64,
+    calls: Vec<FuzzCall>,
+    victim: target_pid_t,
+    stats: FuzzStats,
+}
+
+/// When the current sequence is reported as a hang, in nanoseconds since `EPOCH`. Kept
+/// outside of the engine so that checking it doesn't take the lock on every block.
+static HANG_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+type CaseCallback = Box<dyn FnMut(&FuzzCase) + Send + 'static>;
+
+lazy_static! {
+    static ref EPOCH: Instant = Instant::now();
+    static ref ENGINE: Mutex<Option<Engine>> = Mutex::new(None);
+    static ref CASE_CALLBACKS: Mutex<Vec<CaseCallback>> = Mutex::new(Vec::new());
+    static ref CALLBACKS: (PppCallback, PppCallback, Callback, Callback) = install_callbacks();
+}
+
+fn install_callbacks() -> (PppCallback, PppCallback, Callback, Callback) {
+    let sys_enter = PppCallback::new();
+    let signal = PppCallback::new();
+    let hang_check = Callback::new();
+    let reverted = Callback::new();
+
+    sys_enter.on_all_sys_enter(move |cpu: &mut CPUState, pc, _| {
+        let process = match OSI.get_current_process(cpu) {
+            Some(process) => process,
+            None => return,
+        };
+
+        let mut guard = ENGINE.lock();
+        let engine = match guard.as_mut() {
+            Some(engine) => engine,
+            None => return,
+        };
+
+        match engine.phase {
+            Phase::Idle if process.get_name() == engine.fuzzer.target => {
+                engine.seed = engine.fuzzer.case_seed(engine.iteration);
+                engine.calls = engine.fuzzer.generate(engine.seed);
+                if engine.calls.is_empty() {
+                    eprintln!("Warning: no syscalls to fuzz with, stopping");
+                    engine.phase = Phase::Done;
+                    drop(guard);
+                    disable_callbacks();
+                    return;
+                }
+
+                engine.phase = Phase::Injecting;
+                engine.victim = process.pid;
+
+                let timeout = engine.fuzzer.hang_timeout.as_nanos() as u64;
+                HANG_DEADLINE.store(
+                    (EPOCH.elapsed().as_nanos() as u64).saturating_add(timeout),
+                    Ordering::Relaxed,
+                );
+
+                let calls = engine.calls.clone();
+                hang_check.enable();
+
+                // the injector is polled straight away, so the engine must be unlocked
+                drop(guard);
+                run_injector(pc, inject(calls));
+            }
+            Phase::Settling(remaining) if process.pid == engine.victim => {
+                if remaining <= 1 {
+                    drop(guard);
+                    finish(Outcome::Completed);
+                } else {
+                    engine.phase = Phase::Settling(remaining - 1);
+                }
+            }
+            _ => (),
+        }
+    });
+
+    signal.on_signal(|_, event: &SignalEvent| {
+        let is_crash = ENGINE.lock().as_ref().map_or(false, |engine| {
+            matches!(engine.phase, Phase::Injecting | Phase::Settling(_))
+                && event.dst_pid == engine.victim
+                && engine.fuzzer.crash_signals.contains(&event.sig)
+        });
+
+        if is_crash {
+            finish(Outcome::Crash { signal: event.sig });
+        }
+    });
+
+    // only enabled while a sequence is injecting or settling, and `finish` ignores
+    // sequences which have already ended
+    hang_check.before_block_exec(|_, _| {
+        if EPOCH.elapsed().as_nanos() as u64 > HANG_DEADLINE.load(Ordering::Relaxed) {
+            finish(Outcome::Hang);
+        }
+    });
+
+    reverted.after_loadvm(|_| {
+        if let Some(engine) = ENGINE.lock().as_mut() {
+            if engine.phase == Phase::Reverting {
+                engine.phase = Phase::Idle;
+            }
+        }
+    });
+
+    hang_check.disable();
+
+    (sys_enter, signal, hang_check, reverted)
+}
+
+/// An argument ready to be passed to an injected syscall
+enum InjectArg {
+    Value(target_ulong),
+    Bytes(Vec<u8>),
+}
+
+#[async_trait]
+impl IntoSyscallArg for InjectArg {
+    async fn into_syscall_arg(self) -> target_ulong {
+        match self {
+            InjectArg::Value(value) => value,
+            InjectArg::Bytes(bytes) => alloc_scratch(&bytes).await,
+        }
+    }
+}
+
+async fn inject(calls: Vec<FuzzCall>) {
+    let mut rets: Vec<target_ulong> = Vec::with_capacity(calls.len());
+
+    for (index, call) in calls.into_iter().enumerate() {
+        let mut args = [(); 6].map(|_| InjectArg::Value(0));
+        for (slot, arg) in args.iter_mut().zip(call.args) {
+            *slot = match arg {
+                FuzzArg::Value(value) => InjectArg::Value(value),
+                FuzzArg::Buffer(bytes) => InjectArg::Bytes(bytes),
+                FuzzArg::String(string) => {
+                    let mut bytes = string.into_bytes();
+                    bytes.push(0);
+
+                    InjectArg::Bytes(bytes)
+                }
+                FuzzArg::Result(earlier) => InjectArg::Value(rets[earlier]),
+            };
+        }
+
+        let ret = syscall(call.prototype.no, args).await;
+        rets.push(ret);
+
+        if let Some(engine) = ENGINE.lock().as_mut() {
+            if let Some(call) = engine.calls.get_mut(index) {
+                call.ret = Some(ret);
+            }
+            engine.stats.syscalls += 1;
+        }
+    }
+
+    let settled = ENGINE.lock().as_mut().map_or(false, |engine| {
+        engine.phase = Phase::Settling(engine.fuzzer.settle_syscalls);
+        engine.fuzzer.settle_syscalls == 0
+    });
+
+    if settled {
+        finish(Outcome::Completed);
+    }
+}
+
+/// End the current sequence, report it and revert the guest for the next one
+fn finish(outcome: Outcome) {
+    let (case, was_injecting, snapshot, done) = {
+        let mut engine = ENGINE.lock();
+        let engine = match engine.as_mut() {
+            Some(engine) => engine,
+            None => return,
+        };
+
+        let was_injecting = match engine.phase {
+            Phase::Injecting => true,
+            Phase::Settling(_) => false,
+            _ => return,
+        };
+
+        let case = FuzzCase {
+            iteration: engine.iteration,
+            seed: engine.seed,
+            calls: std::mem::take(&mut engine.calls),
+            outcome,
+        };
+
+        engine.iteration += 1;
+        engine.stats.iterations += 1;
+        match outcome {
+            Outcome::Crash { .. } => engine.stats.crashes += 1,
+            Outcome::Hang => engine.stats.hangs += 1,
+            Outcome::Completed => (),
+        }
+
+        let done = engine
+            .fuzzer
+            .iterations
+            .map_or(false, |iterations| engine.iteration >= iterations);
+
+        engine.phase = if done { Phase::Done } else { Phase::Reverting };
+
+        (case, was_injecting, engine.fuzzer.snapshot.clone(), done)
+    };
+
+    CALLBACKS.2.disable();
+    HANG_DEADLINE.store(u64::MAX, Ordering::Relaxed);
+
+    // a sequence cut short is still waiting on a syscall, so has to be dropped rather
+    // than left to restore the victim's registers
+    if was_injecting {
+        cancel_injectors();
+    }
+
+    Panda::revert_async(&snapshot);
+
+    if done {
+        disable_callbacks();
+    }
+
+    // run the callbacks unlocked so that they can register further callbacks
+    let mut callbacks = std::mem::take(&mut *CASE_CALLBACKS.lock());
+    for callback in &mut callbacks {
+        callback(&case);
+    }
+
+    let mut case_callbacks = CASE_CALLBACKS.lock();
+    callbacks.append(&mut case_callbacks);
+    *case_callbacks = callbacks;
+}
+
+fn disable_callbacks() {
+    CALLBACKS.0.disable();
+    CALLBACKS.1.disable();
+    CALLBACKS.2.disable();
+    CALLBACKS.3.disable();
+}
+
+/// Start fuzzing, replacing any campaign already running. Sequences are injected the
+/// next time the victim makes a syscall.
+pub fn start(fuzzer: Fuzzer) {
+    *ENGINE.lock() = Some(Engine {
+        fuzzer,
+        phase: Phase::Idle,
+        iteration: 0,
+        seed: 0,
+        calls: Vec::new(),
+        victim: 0,
+        stats: FuzzStats::default(),
+    });
+
+    CALLBACKS.0.enable();
+    CALLBACKS.1.enable();
+    CALLBACKS.3.enable();
+}
+
+/// Stop fuzzing once the current sequence has finished
+pub fn stop() {
+    if let Some(engine) = ENGINE.lock().as_mut() {
+        engine.fuzzer.iterations = Some(engine.iteration + 1);
+
+        if matches!(engine.phase, Phase::Idle | Phase::Reverting) {
+            engine.phase = Phase::Done;
+            disable_callbacks();
+        }
+    }
+}
+
+/// Register a callback to be run each time a sequence finishes, whether it completed,
+/// crashed or hung the victim
+pub fn on_case(callback: impl FnMut(&FuzzCase) + Send + 'static) {
+    CASE_CALLBACKS.lock().push(Box::new(callback));
+}
+
+/// Get counts of the sequences run so far by the current campaign
+pub fn stats() -> FuzzStats {
+    ENGINE
+        .lock()
+        .as_ref()
+        .map(|engine| engine.stats)
+        .unwrap_or_default()
+}
+
+/// Whether a campaign is running
+pub fn is_running() -> bool {
+    ENGINE
+        .lock()
+        .as_ref()
+        .map_or(false, |engine| engine.phase != Phase::Done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prototype(no: target_ulong, name: &str, args: &[(&str, ArgKind)]) -> &'static Prototype {
+        let args = args
+            .iter()
+            .map(|&(name, kind)| SyscallArg {
+                name: name.to_owned(),
+                type_name: String::new(),
+                kind,
+                size: std::mem::size_of::<target_ulong>(),
+            })
+            .collect();
+
+        Box::leak(Box::new(Prototype {
+            no,
+            name: name.to_owned(),
+            args,
+            noreturn: false,
+        }))
+    }
+
+    fn prototypes() -> Vec<&'static Prototype> {
+        vec![
+            prototype(
+                2,
+                "open",
+                &[
+                    ("filename", ArgKind::String),
+                    ("flags", ArgKind::Signed),
+                    ("mode", ArgKind::Unsigned),
+                ],
+            ),
+            prototype(
+                0,
+                "read",
+                &[
+                    ("fd", ArgKind::Unsigned),
+                    ("buf", ArgKind::Pointer),
+                    ("count", ArgKind::Unsigned),
+                ],
+            ),
+            prototype(3, "close", &[("fd", ArgKind::Unsigned)]),
+        ]
+    }
+
+    fn summary(calls: &[FuzzCall]) -> Vec<(&str, Vec<FuzzArg>)> {
+        calls
+            .iter()
+            .map(|call| (&call.prototype.name[..], call.args.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rng_deterministic() {
+        // reference outputs of SplitMix64 seeded with 0
+        let mut rng = Rng(0);
+        assert_eq!(rng.next(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(rng.next(), 0x6e78_9e6a_a1b9_65f4);
+        assert_eq!(rng.next(), 0x06c4_5d18_8009_454f);
+
+        let fuzzer = Fuzzer::new("victim");
+        assert_eq!(fuzzer.case_seed(7), fuzzer.case_seed(7));
+        assert_ne!(fuzzer.case_seed(7), fuzzer.case_seed(8));
+        assert_ne!(fuzzer.case_seed(7), fuzzer.clone().seed(1).case_seed(7));
+    }
+
+    #[test]
+    fn test_generate_deterministic() {
+        let fuzzer = Fuzzer::new("victim").sequence_len(2, 6);
+        let candidates = prototypes();
+
+        let mut distinct = std::collections::HashSet::new();
+        for seed in 0..32 {
+            let calls = fuzzer.generate_from(&candidates, seed);
+
+            assert_eq!(
+                summary(&calls),
+                summary(&fuzzer.generate_from(&candidates, seed))
+            );
+            assert!((2..=6).contains(&calls.len()));
+
+            for (index, call) in calls.iter().enumerate() {
+                assert_eq!(call.args.len(), call.prototype.args.len());
+                assert!(call.ret.is_none());
+
+                for arg in &call.args {
+                    if let FuzzArg::Result(earlier) = arg {
+                        assert!(*earlier < index);
+                    }
+                }
+            }
+
+            distinct.insert(format!("{:?}", summary(&calls)));
+        }
+
+        assert!(distinct.len() > 1);
+        assert!(fuzzer.generate_from(&[], 0).is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+use super::scratch::{map_memory, unmap_memory};
+use super::ThreadId;
+use crate::enums::MemRWStatus;
+use crate::mem::{page_align_up, virtual_memory_write};
+use crate::prelude::*;
+use crate::{regs, sys, GuestPtr, GuestType};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+
+/// Bytes below the stack pointer which leaf functions may use without moving it (the
+/// System V red zone), and so must be skipped over when pushing to the stack
+#[cfg(feature = "x86_64")]
+const RED_ZONE: target_ulong = 128;
+
+#[cfg(not(feature = "x86_64"))]
+const RED_ZONE: target_ulong = 0;
+
+/// The alignment kept for the stack pointer, enough for any supported ABI
+const STACK_ALIGN: target_ulong = 16;
+
+/// An error encountered while allocating memory in the guest from an injector
+#[derive(thiserror::Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GuestAllocError {
+    #[error("failed to map memory in the guest (errno {0})")]
+    MapFailed(target_ulong),
+
+    #[error("failed to write to guest memory at {0:#x}")]
+    WriteFailed(target_ptr_t),
+}
+
+lazy_static! {
+    /// The length of each mapping made by `guest_malloc`, keyed by the process it was
+    /// made in and its address
+    static ref ALLOCATIONS: DashMap<(target_ulong, target_ptr_t), target_ulong> = DashMap::new();
+}
+
+fn write_bytes(addr: target_ptr_t, bytes: &[u8]) -> Result<(), GuestAllocError> {
+    let cpu = unsafe { &mut *sys::get_cpu() };
+
+    match virtual_memory_write(cpu, addr, bytes) {
+        MemRWStatus::MemTxOk => Ok(()),
+        _ => Err(GuestAllocError::WriteFailed(addr)),
+    }
+}
+
+/// Map at least `len` bytes of zeroed, readable and writable memory in the process
+/// being injected into. The memory stays mapped after the injector finishes, until it
+/// is freed with [`guest_free`]. Should only be run within a syscall injector.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::prelude::*;
+/// use panda::syscall_injection::{guest_free, guest_malloc, syscall};
+///
+/// const GETCWD: target_ulong = 79;
+///
+/// async fn cwd_len() -> target_ulong {
+///     let buf = guest_malloc(0x1000).await.unwrap();
+///     let len = syscall(GETCWD, (buf.clone(), 0x1000u64)).await;
+///     guest_free(buf).await;
+///
+///     len
+/// }
+/// ```
+pub async fn guest_malloc(len: usize) -> Result<GuestPtr<u8>, GuestAllocError> {
+    let len = page_align_up(len.max(1) as target_ulong);
+    let addr = map_memory(len).await.map_err(GuestAllocError::MapFailed)?;
+
+    ALLOCATIONS.insert((ThreadId::current().pid, addr), len);
+
+    Ok(GuestPtr::from(addr))
+}
+
+/// Unmap memory mapped by [`guest_malloc`] or [`inject_string`]. Should only be run
+/// within a syscall injector, in the same process the memory was mapped in.
+pub async fn guest_free<T: GuestType>(ptr: GuestPtr<T>) {
+    let addr = ptr.addr();
+
+    match ALLOCATIONS.remove(&(ThreadId::current().pid, addr)) {
+        Some((_, len)) => {
+            unmap_memory(addr, len).await;
+        }
+        None => log::warn!("guest_free: {:#x} was not mapped by guest_malloc", addr),
+    }
+}
+
+/// Copy a string into newly mapped memory in the process being injected into, with a
+/// NUL terminator, such as to pass as a path. The memory stays mapped until it is freed
+/// with [`guest_free`]. Should only be run within a syscall injector.
+///
+/// To pass a string to a single syscall, the string can be passed to [`syscall`]
+/// directly instead.
+pub async fn inject_string(string: &str) -> Result<GuestPtr<u8>, GuestAllocError> {
+    let mut bytes = Vec::with_capacity(string.len() + 1);
+    bytes.extend_from_slice(string.as_bytes());
+    bytes.push(0);
+
+    let ptr = guest_malloc(bytes.len()).await?;
+    if let Err(err) = write_bytes(ptr.addr(), &bytes) {
+        guest_free(ptr).await;
+        return Err(err);
+    }
+
+    Ok(ptr)
+}
+
+/// Push bytes onto the stack of the thread being injected into, returning a pointer to
+/// them. The memory is released when the injector finishes and the original stack
+/// pointer is restored, so doesn't need to be freed. Should only be run within a
+/// syscall injector.
+///
+/// Only the pages of the stack which are already mapped can be written to, so large
+/// buffers should be allocated with [`guest_malloc`] instead.
+pub fn write_bytes_to_guest_stack(bytes: &[u8]) -> Result<GuestPtr<u8>, GuestAllocError> {
+    let cpu = unsafe { &mut *sys::get_cpu() };
+    let sp = regs::get_reg(cpu, regs::reg_sp());
+    let addr = sp.wrapping_sub(RED_ZONE + bytes.len() as target_ulong) & !(STACK_ALIGN - 1);
+
+    write_bytes(addr, bytes)?;
+    regs::set_reg(cpu, regs::reg_sp(), addr);
+
+    Ok(GuestPtr::from(addr))
+}
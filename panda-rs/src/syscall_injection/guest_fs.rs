@@ -0,0 +1,222 @@
+//! File I/O in the guest for syscall injectors
+//!
+//! These futures chain together the syscalls needed to work with files in the guest,
+//! using the syscall numbers and flag values of the target architecture, so that
+//! pulling a file out of the guest (or dropping one into it) doesn't require writing
+//! an injector by hand. Like [`syscall`], they can only be awaited within a syscall
+//! injector, and act on the files of the process being injected into.
+//!
+//! Data is moved through memory mapped in the guest with
+//! [`guest_malloc`](super::guest_malloc), which is unmapped again before each future
+//! completes.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::syscall_injection::{guest_fs, run_injector};
+//!
+//! #[panda::on_all_sys_enter]
+//! fn on_sys_enter(_: &mut CPUState, pc: SyscallPc, _: target_ulong) {
+//!     run_injector(pc, async {
+//!         match guest_fs::read_file_to_vec("/etc/passwd").await {
+//!             Ok(passwd) => println!("{}", String::from_utf8_lossy(&passwd)),
+//!             Err(err) => println!("{}", err),
+//!         }
+//!
+//!         guest_fs::write_file("/tmp/marker", b"injected\n").await.unwrap();
+//!     });
+//! }
+//! ```
+use super::arch::{errno, CLOSE, LSEEK, OPENAT, READ, WRITE};
+use super::{guest_free, guest_malloc, syscall, Fd, GuestAllocError};
+use crate::mem::virtual_memory_read;
+use crate::prelude::*;
+use crate::sys;
+
+use std::io::SeekFrom;
+
+/// Resolve paths relative to the current working directory of the process
+const AT_FDCWD: i32 = -100;
+
+/// The size of the buffer used to read whole files
+const READ_CHUNK: usize = 0x10000;
+
+/// Returned when a write can't make progress as the disk is full
+const ENOSPC: target_ulong = 28;
+
+/// The permissions files are created with by [`open`] and [`write_file`]
+const DEFAULT_MODE: target_ulong = 0o644;
+
+pub const O_RDONLY: target_ulong = 0o0;
+pub const O_WRONLY: target_ulong = 0o1;
+pub const O_RDWR: target_ulong = 0o2;
+
+#[cfg(not(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+)))]
+mod flags {
+    use crate::prelude::*;
+
+    pub const O_CREAT: target_ulong = 0o100;
+    pub const O_EXCL: target_ulong = 0o200;
+    pub const O_TRUNC: target_ulong = 0o1000;
+    pub const O_APPEND: target_ulong = 0o2000;
+}
+
+#[cfg(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+))]
+mod flags {
+    use crate::prelude::*;
+
+    pub const O_CREAT: target_ulong = 0x100;
+    pub const O_EXCL: target_ulong = 0x400;
+    pub const O_TRUNC: target_ulong = 0x200;
+    pub const O_APPEND: target_ulong = 0x8;
+}
+
+pub use flags::*;
+
+/// An error encountered while accessing files in the guest
+#[derive(thiserror::Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GuestFileError {
+    #[error("{0} failed in the guest (errno {1})")]
+    Syscall(&'static str, target_ulong),
+
+    #[error(transparent)]
+    Alloc(#[from] GuestAllocError),
+
+    #[error("failed to read guest memory at {0:#x}")]
+    ReadFailed(target_ptr_t),
+}
+
+/// Convert a syscall return value into an error if it is a negated errno
+fn check(name: &'static str, ret: target_ulong) -> Result<target_ulong, GuestFileError> {
+    match errno(ret) {
+        Some(errno) => Err(GuestFileError::Syscall(name, errno)),
+        None => Ok(ret),
+    }
+}
+
+/// Open a file, returning its file descriptor. `flags` is a combination of the `O_*`
+/// constants in this module, and files created with [`O_CREAT`] are given the
+/// permissions `0644`.
+pub async fn open(path: &str, flags: target_ulong) -> Result<Fd, GuestFileError> {
+    open_with_mode(path, flags, DEFAULT_MODE).await
+}
+
+/// Open a file, giving it the permissions `mode` if it is created
+pub async fn open_with_mode(
+    path: &str,
+    flags: target_ulong,
+    mode: target_ulong,
+) -> Result<Fd, GuestFileError> {
+    let fd = syscall(OPENAT, (Fd(AT_FDCWD), path, flags, mode)).await;
+
+    check("openat", fd).map(|fd| Fd(fd as i32))
+}
+
+/// Close a file descriptor
+pub async fn close(fd: Fd) -> Result<(), GuestFileError> {
+    check("close", syscall(CLOSE, (fd,)).await).map(drop)
+}
+
+/// Read into an already mapped guest buffer, returning the bytes read
+async fn read_with_buf(fd: Fd, buf: target_ptr_t, len: usize) -> Result<Vec<u8>, GuestFileError> {
+    let read = check("read", syscall(READ, (fd, buf, len as target_ulong)).await)?;
+    let cpu = unsafe { &mut *sys::get_cpu() };
+
+    virtual_memory_read(cpu, buf, read as usize).map_err(|_| GuestFileError::ReadFailed(buf))
+}
+
+/// Read up to `len` bytes from a file descriptor. Fewer bytes may be returned, and none
+/// are returned at the end of the file.
+pub async fn read(fd: Fd, len: usize) -> Result<Vec<u8>, GuestFileError> {
+    let buf = guest_malloc(len).await?;
+    let bytes = read_with_buf(fd, buf.addr(), len).await;
+    guest_free(buf).await;
+
+    bytes
+}
+
+/// Read from a file descriptor until the end of the file
+pub async fn read_to_end(fd: Fd) -> Result<Vec<u8>, GuestFileError> {
+    let buf = guest_malloc(READ_CHUNK).await?;
+    let mut bytes = Vec::new();
+
+    let result = loop {
+        match read_with_buf(fd, buf.addr(), READ_CHUNK).await {
+            Ok(chunk) if chunk.is_empty() => break Ok(()),
+            Ok(chunk) => bytes.extend_from_slice(&chunk),
+            Err(err) => break Err(err),
+        }
+    };
+
+    guest_free(buf).await;
+
+    result.map(|_| bytes)
+}
+
+/// Write bytes to a file descriptor, returning how many were written. Fewer bytes than
+/// were given may be written.
+pub async fn write(fd: Fd, bytes: &[u8]) -> Result<usize, GuestFileError> {
+    check(
+        "write",
+        syscall(WRITE, (fd, bytes, bytes.len() as target_ulong)).await,
+    )
+    .map(|written| written as usize)
+}
+
+/// Write all of the given bytes to a file descriptor
+pub async fn write_all(fd: Fd, mut bytes: &[u8]) -> Result<(), GuestFileError> {
+    while !bytes.is_empty() {
+        let written = write(fd, bytes).await?;
+        if written == 0 {
+            return Err(GuestFileError::Syscall("write", ENOSPC));
+        }
+
+        bytes = &bytes[written..];
+    }
+
+    Ok(())
+}
+
+/// Move the offset of a file descriptor, returning the new offset from the start of
+/// the file
+pub async fn seek(fd: Fd, pos: SeekFrom) -> Result<u64, GuestFileError> {
+    let (offset, whence) = match pos {
+        SeekFrom::Start(offset) => (offset as target_ulong, 0),
+        SeekFrom::Current(offset) => (offset as target_long as target_ulong, 1),
+        SeekFrom::End(offset) => (offset as target_long as target_ulong, 2),
+    };
+
+    let ret = syscall(LSEEK, (fd, offset, whence as target_ulong)).await;
+
+    check("lseek", ret).map(|offset| offset as u64)
+}
+
+/// Read the whole of a file in the guest
+pub async fn read_file_to_vec(path: &str) -> Result<Vec<u8>, GuestFileError> {
+    let fd = open(path, O_RDONLY).await?;
+    let bytes = read_to_end(fd).await;
+    close(fd).await?;
+
+    bytes
+}
+
+/// Write a file in the guest, creating it if it doesn't exist and replacing its
+/// contents if it does
+pub async fn write_file(path: &str, bytes: &[u8]) -> Result<(), GuestFileError> {
+    let fd = open(path, O_WRONLY | O_CREAT | O_TRUNC).await?;
+    let written = write_all(fd, bytes).await;
+    close(fd).await?;
+
+    written
+}
@@ -0,0 +1,78 @@
+use super::arch::{errno, MAP_SCRATCH, MMAP, MUNMAP, PROT_READ_WRITE};
+use super::{raw_syscall, SyscallArgs, ThreadId};
+use crate::enums::MemRWStatus;
+use crate::mem::{page_align_up, virtual_memory_write};
+use crate::prelude::*;
+use crate::sys::get_cpu;
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// Scratch memory mapped for the arguments of the syscall being injected by each
+    /// thread, as `(addr, len)`
+    static ref SCRATCH: DashMap<ThreadId, Vec<(target_ptr_t, target_ulong)>> = DashMap::new();
+}
+
+/// Map `len` bytes of readable and writable memory in the process being injected into,
+/// returning the errno if it can't be mapped
+pub(crate) async fn map_memory(len: target_ulong) -> Result<target_ptr_t, target_ulong> {
+    let addr = raw_syscall(
+        MMAP,
+        SyscallArgs::new(&[0, len, PROT_READ_WRITE, MAP_SCRATCH, target_ulong::MAX, 0]),
+    )
+    .await;
+
+    errno(addr).map_or(Ok(addr), Err)
+}
+
+/// Unmap memory mapped by [`map_memory`]
+pub(crate) async fn unmap_memory(addr: target_ptr_t, len: target_ulong) {
+    raw_syscall(MUNMAP, SyscallArgs::new(&[addr, len])).await;
+}
+
+/// Map memory in the guest holding `data`, which is unmapped once the syscall being
+/// injected by the current thread returns. Returns NULL if the memory can't be mapped.
+pub(crate) async fn alloc_scratch(data: &[u8]) -> target_ptr_t {
+    let len = page_align_up(data.len().max(1) as target_ulong);
+    let addr = match map_memory(len).await {
+        Ok(addr) => addr,
+        Err(errno) => {
+            log::warn!(
+                "Failed to map scratch memory for syscall argument (errno {})",
+                errno
+            );
+            return 0;
+        }
+    };
+
+    SCRATCH
+        .entry(ThreadId::current())
+        .or_default()
+        .push((addr, len));
+
+    let cpu = unsafe { &mut *get_cpu() };
+    if virtual_memory_write(cpu, addr, data) != MemRWStatus::MemTxOk {
+        log::warn!("Failed to write syscall argument to {:#x}", addr);
+    }
+
+    addr
+}
+
+/// Unmap all the scratch memory mapped for the syscall the current thread just injected
+pub(crate) async fn free_scratch() {
+    let scratch = match SCRATCH.remove(&ThreadId::current()) {
+        Some((_, scratch)) => scratch,
+        None => return,
+    };
+
+    for (addr, len) in scratch {
+        unmap_memory(addr, len).await;
+    }
+}
+
+/// Forget the scratch memory of every thread without unmapping it, for when the
+/// injectors using it have been cancelled
+pub(crate) fn forget_scratch() {
+    SCRATCH.clear();
+}
@@ -9,8 +9,9 @@ use std::{
 };
 
 use super::arch::{SYSCALL_ARGS, SYSCALL_NUM_REG, SYSCALL_RET};
+use super::scratch::free_scratch;
 use super::{IntoSyscallArgs, SyscallArgs, ThreadId};
-use crate::regs;
+use crate::{audit, regs};
 
 use dashmap::DashMap;
 use lazy_static::lazy_static;
@@ -52,18 +53,39 @@ fn set_syscall_num(cpu: &mut CPUState, num: target_ulong) {
 
 /// Perform a system call in the guest. Should only be run within an injector being
 /// run by [`run_injector`](crate::syscall_injection::run_injector)
+///
+/// Any guest memory allocated to pass the arguments (such as for strings or
+/// [`ByRef`](super::ByRef) values) is unmapped once the system call returns.
 pub async fn syscall(num: target_ulong, args: impl IntoSyscallArgs) -> target_ulong {
-    log::trace!("Injecting syscall {}", num);
-    let cpu = unsafe { &mut *get_cpu() };
+    let args = args.into_syscall_args().await;
+    let ret = raw_syscall(num, args).await;
 
-    let saved_sp = regs::get_reg(cpu, regs::reg_sp());
+    free_scratch().await;
+
+    ret
+}
+
+/// Perform a system call with already-converted arguments, without freeing any
+/// scratch memory
+pub(crate) async fn raw_syscall(num: target_ulong, args: SyscallArgs) -> target_ulong {
+    log::trace!("Injecting syscall {}", num);
 
     #[cfg(feature = "i386")]
-    let saved_bp = regs::get_reg(cpu, regs::Reg::EBP);
+    let saved_bp = regs::get_reg(unsafe { &mut *get_cpu() }, regs::Reg::EBP);
 
-    // Setup the system call
-    set_syscall_num(cpu, num);
-    set_syscall_args(cpu, args.into_syscall_args().await);
+    // the CPU isn't held across the await, so argument conversions can inject
+    // syscalls from within a `Send` future
+    let (saved_sp, audit_args) = {
+        let cpu = unsafe { &mut *get_cpu() };
+        let saved_sp = regs::get_reg(cpu, regs::reg_sp());
+
+        // Setup the system call
+        let audit_args: Vec<_> = args.iter_args().collect();
+        set_syscall_num(cpu, num);
+        set_syscall_args(cpu, args);
+
+        (saved_sp, audit_args)
+    };
 
     // Wait until the system call has returned to get the return value
     let ret = Pin::new(&mut SyscallFuture {
@@ -73,6 +95,13 @@ pub async fn syscall(num: target_ulong, args: impl IntoSyscallArgs) -> target_ul
 
     log::trace!("Injected syscall {} returned {}", num, ret);
 
+    let cpu = unsafe { &mut *get_cpu() };
+    audit::record(Some(&mut *cpu), || audit::Mutation::Syscall {
+        num,
+        args: audit_args,
+        ret: Some(ret),
+    });
+
     regs::set_reg(cpu, regs::reg_sp(), saved_sp);
 
     #[cfg(feature = "i386")]
@@ -89,8 +118,14 @@ pub async fn syscall_no_return(num: target_ulong, args: impl IntoSyscallArgs) ->
     let cpu = unsafe { &mut *get_cpu() };
 
     // Setup the system call
+    let args = args.into_syscall_args().await;
     set_syscall_num(cpu, num);
-    set_syscall_args(cpu, args.into_syscall_args().await);
+    audit::record(Some(&mut *cpu), || audit::Mutation::Syscall {
+        num,
+        args: args.iter_args().collect(),
+        ret: None,
+    });
+    set_syscall_args(cpu, args);
 
     bail_no_restore_regs().await
 }
@@ -0,0 +1,283 @@
+//! A runtime table of syscall prototypes
+//!
+//! The [`on_sys`](crate::on_sys) callbacks give each syscall typed arguments, but a
+//! tracer handling every syscall from [`on_all_sys_enter`] only has the syscall number
+//! and raw argument registers. This module exposes the prototype of each syscall
+//! (its name and the name, type and size of each argument) as known to syscalls2 for
+//! the guest OS, so arguments can be decoded and printed without a match over every
+//! syscall.
+//!
+//! The prototypes are read from syscalls2, which only loads them when given the
+//! `load-info=true` argument. If syscalls2 hasn't been loaded yet when the table is
+//! first used, it is loaded with that argument, otherwise it must be passed when
+//! loading syscalls2 (such as `-panda syscalls2:load-info=true`). Without it, every
+//! lookup returns `None`.
+//!
+//! [`on_all_sys_enter`]: macro@crate::on_all_sys_enter
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::plugins::syscalls2::{on_all_sys_return_with_args, SyscallContext};
+//! use panda::prelude::*;
+//! use panda::syscalls;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     on_all_sys_return_with_args(|cpu, ctx: &SyscallContext| {
+//!         if let Some(prototype) = syscalls::prototype(ctx.callno()) {
+//!             // openat(dfd=-100, filename="/etc/passwd", flags=0, mode=0x0) = 0x3
+//!             println!("{} = {:#x}", prototype.format(cpu, ctx.args()), ctx.retval());
+//!         }
+//!     });
+//! }
+//! ```
+pub mod decode;
+
+use crate::mem::read_guest_string;
+use crate::plugin_import;
+use crate::prelude::*;
+use crate::sys::panda_add_arg;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uint};
+
+/// The metadata syscalls2 keeps for a single syscall (`syscall_info_t`)
+#[repr(C)]
+pub struct SyscallInfo {
+    pub no: c_int,
+    pub name: *const c_char,
+    pub nargs: c_int,
+    pub argt: *const c_uint,
+    pub argsz: *const u8,
+    pub argn: *const *const c_char,
+    pub argtn: *const *const c_char,
+    pub noreturn: bool,
+}
+
+/// The bounds of syscalls2's syscall metadata (`syscall_meta_t`)
+#[repr(C)]
+pub struct SyscallMeta {
+    pub max: c_uint,
+    pub max_generic: c_uint,
+    pub max_args: c_uint,
+}
+
+plugin_import! {
+    /// Direct access to the syscall metadata API of syscalls2
+    static SYSCALLS2_INFO: Syscalls2Info = extern "syscalls2" {
+        fn get_syscall_info(callno: u32) -> *const SyscallInfo;
+        fn get_syscall_meta() -> *const SyscallMeta;
+    };
+}
+
+/// How a syscall argument should be interpreted
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ArgKind {
+    Unsigned,
+    Signed,
+    Pointer,
+
+    /// A pointer to a NUL-terminated string
+    String,
+
+    /// A pointer to a struct
+    StructPointer,
+
+    /// A struct or array passed by value
+    Aggregate,
+}
+
+/// `SYSCALL_ARG_BUF_PTR`, a pointer to a buffer
+const SYSCALL_ARG_BUF_PTR: c_uint = 0x20;
+
+/// `SYSCALL_ARG_STRUCT_PTR`, a pointer to a struct
+const SYSCALL_ARG_STRUCT_PTR: c_uint = 0x21;
+
+/// `SYSCALL_ARG_STR_PTR`, a pointer to a NUL-terminated string
+const SYSCALL_ARG_STR_PTR: c_uint = 0x22;
+
+impl ArgKind {
+    /// Classify an argument from its `syscall_argtype_t`. Integers are classified by
+    /// the category of their type alone, as their sizes are given separately.
+    fn classify(argtype: c_uint) -> Self {
+        match argtype {
+            SYSCALL_ARG_STR_PTR => Self::String,
+            SYSCALL_ARG_STRUCT_PTR => Self::StructPointer,
+            SYSCALL_ARG_BUF_PTR => Self::Pointer,
+            _ => match argtype >> 4 {
+                0 => Self::Unsigned,
+                1 => Self::Signed,
+                2 => Self::Pointer,
+                _ => Self::Aggregate,
+            },
+        }
+    }
+}
+
+unsafe fn string_from_raw(string: *const c_char) -> String {
+    if string.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(string).to_string_lossy().into_owned()
+    }
+}
+
+/// A single argument of a syscall
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyscallArg {
+    pub name: String,
+
+    /// The C type of the argument, as written in the kernel's prototype
+    pub type_name: String,
+    pub kind: ArgKind,
+
+    /// The size of the argument in bytes
+    pub size: usize,
+}
+
+impl SyscallArg {
+    /// Format a raw argument value according to its type, reading strings from guest
+    /// memory
+    pub fn format(&self, cpu: &mut CPUState, value: target_ulong) -> String {
+        let bits = (self.size * 8).clamp(8, 64) as u32;
+        let value = value as u64;
+
+        match self.kind {
+            ArgKind::Signed => {
+                let shift = 64 - bits;
+                (((value << shift) as i64) >> shift).to_string()
+            }
+            ArgKind::Unsigned => format!("{:#x}", value & (u64::MAX >> (64 - bits))),
+            ArgKind::String => match read_guest_string(cpu, value as target_ptr_t) {
+                Ok(string) => format!("{:?}", string),
+                Err(_) => format!("{:#x}", value),
+            },
+            ArgKind::Pointer | ArgKind::StructPointer | ArgKind::Aggregate => {
+                format!("{:#x}", value)
+            }
+        }
+    }
+}
+
+/// The prototype of a syscall
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Prototype {
+    pub no: target_ulong,
+
+    /// The name of the syscall, without the `sys_` prefix of its kernel entry point
+    /// (such as `openat`)
+    pub name: String,
+    pub args: Vec<SyscallArg>,
+
+    /// Whether the syscall never returns (such as `exit`)
+    pub noreturn: bool,
+}
+
+impl Prototype {
+    /// Format a call to this syscall with the given raw arguments, such as
+    /// `read(fd=0x3, buf=0x7ffc1000, count=0x100)`. Extra arguments are ignored.
+    pub fn format(&self, cpu: &mut CPUState, args: &[target_ulong]) -> String {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .zip(args)
+            .map(|(arg, &value)| format!("{}={}", arg.name, arg.format(cpu, value)))
+            .collect();
+
+        format!("{}({})", self.name, args.join(", "))
+    }
+
+    unsafe fn from_raw(info: &SyscallInfo) -> Option<Self> {
+        if info.name.is_null() {
+            return None;
+        }
+
+        let args = (0..info.nargs.max(0) as usize)
+            .map(|i| SyscallArg {
+                name: string_from_raw(*info.argn.add(i)),
+                type_name: string_from_raw(*info.argtn.add(i)),
+                kind: ArgKind::classify(*info.argt.add(i)),
+                size: *info.argsz.add(i) as usize,
+            })
+            .collect();
+
+        // syscalls2 names syscalls after their kernel entry points (`sys_openat`)
+        let name = string_from_raw(info.name);
+        let name = name.strip_prefix("sys_").unwrap_or(&name).to_owned();
+
+        Some(Self {
+            no: info.no as target_ulong,
+            name,
+            args,
+            noreturn: info.noreturn,
+        })
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PROTOTYPES: Vec<Option<Prototype>> = load_prototypes();
+}
+
+fn load_prototypes() -> Vec<Option<Prototype>> {
+    // only takes effect if syscalls2 hasn't been loaded yet
+    let plugin = CString::new("syscalls2").unwrap();
+    let arg = CString::new("load-info=true").unwrap();
+    unsafe {
+        panda_add_arg(plugin.as_ptr(), arg.as_ptr());
+    }
+
+    let meta = SYSCALLS2_INFO.get_syscall_meta();
+    if meta.is_null() {
+        eprintln!(
+            "Warning: syscalls2 was loaded without load-info=true, no syscall prototypes available"
+        );
+        return Vec::new();
+    }
+
+    (0..unsafe { (*meta).max })
+        .map(|callno| {
+            let info = SYSCALLS2_INFO.get_syscall_info(callno);
+
+            unsafe { info.as_ref().and_then(|info| Prototype::from_raw(info)) }
+        })
+        .collect()
+}
+
+/// Get the prototype of the syscall with the given number, if it is known
+pub fn prototype(callno: target_ulong) -> Option<&'static Prototype> {
+    PROTOTYPES.get(callno as usize)?.as_ref()
+}
+
+/// Get the prototype of the syscall with the given name, such as `"openat"`
+pub fn prototype_by_name(name: &str) -> Option<&'static Prototype> {
+    prototypes().find(|prototype| prototype.name == name)
+}
+
+/// Iterate over the prototypes of every known syscall, in order of syscall number
+pub fn prototypes() -> impl Iterator<Item = &'static Prototype> {
+    PROTOTYPES.iter().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        // SYSCALL_ARG_U32, SYSCALL_ARG_S64
+        assert_eq!(ArgKind::classify(0x01), ArgKind::Unsigned);
+        assert_eq!(ArgKind::classify(0x10), ArgKind::Signed);
+
+        assert_eq!(ArgKind::classify(SYSCALL_ARG_BUF_PTR), ArgKind::Pointer);
+        assert_eq!(
+            ArgKind::classify(SYSCALL_ARG_STRUCT_PTR),
+            ArgKind::StructPointer
+        );
+        assert_eq!(ArgKind::classify(SYSCALL_ARG_STR_PTR), ArgKind::String);
+
+        // SYSCALL_ARG_STRUCT, SYSCALL_ARG_ARR
+        assert_eq!(ArgKind::classify(0x30), ArgKind::Aggregate);
+        assert_eq!(ArgKind::classify(0x31), ArgKind::Aggregate);
+    }
+}
@@ -0,0 +1,533 @@
+//! Syscalls decoded into typed arguments
+//!
+//! The [`on_sys`](crate::on_sys) callbacks give every argument as the raw value of its
+//! register, leaving pointers to strings to be read from guest memory by hand. This
+//! module decodes the common file, process and memory syscalls into a [`Syscall`], with
+//! paths read from guest memory as [`OsString`]s, file descriptors as `i32` and flags
+//! as [`bitflags`] flag sets. Any other syscall is given as [`Syscall::Other`].
+//!
+//! Syscalls are identified by name using the [prototype table](super), so syscalls2
+//! must be able to provide its syscall info (see [`super::prototype`]).
+//!
+//! A string argument which can't be read from guest memory (such as a path in a page
+//! of the program which hasn't been faulted in yet) causes the syscall to be given as
+//! [`Syscall::Other`]. By the time a syscall returns, the kernel has faulted in its
+//! arguments, so [`on_sys_return_decoded`] is more reliable than
+//! [`on_sys_enter_decoded`] for syscalls which don't replace the address space.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::syscalls::decode::{on_sys_return_decoded, OpenFlags, Syscall};
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     on_sys_return_decoded(|_, syscall, result| match syscall {
+//!         Syscall::Open { path, flags, .. } | Syscall::OpenAt { path, flags, .. }
+//!             if flags.contains(OpenFlags::O_CREAT) =>
+//!         {
+//!             println!("created {:?} (fd {:?})", path, result);
+//!         }
+//!         _ => (),
+//!     });
+//! }
+//! ```
+use super::{prototype, Prototype};
+use crate::abi::syscall::SYSCALL_ARGS;
+use crate::mem::{read_guest_bytes_until_nul, read_guest_type};
+use crate::plugins::syscalls2::{on_all_sys_return_with_args, Syscalls2Callbacks};
+use crate::prelude::*;
+use crate::{GuestReadFail, PppCallback};
+
+use bitflags::bitflags;
+
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+
+/// The most entries of `argv` read for an `execve`
+const MAX_ARGV: usize = 256;
+
+/// The size of the pages `mmap2` gives its offset in
+const MMAP2_PAGE_SIZE: u64 = 0x1000;
+
+const IS_MIPS: bool = cfg!(any(
+    feature = "mips",
+    feature = "mipsel",
+    feature = "mips64",
+    feature = "mips64el"
+));
+
+const IS_ARM: bool = cfg!(any(feature = "arm", feature = "aarch64"));
+
+bitflags! {
+    /// The flags passed to `open` and `openat`. The access mode (`O_RDONLY`, `O_WRONLY`
+    /// or `O_RDWR`) is given by [`OpenFlags::access_mode`].
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+    pub struct OpenFlags: u32 {
+        const O_WRONLY = 0o1;
+        const O_RDWR = 0o2;
+        const O_CREAT = if IS_MIPS { 0x100 } else { 0o100 };
+        const O_EXCL = if IS_MIPS { 0x400 } else { 0o200 };
+        const O_NOCTTY = if IS_MIPS { 0x800 } else { 0o400 };
+        const O_TRUNC = if IS_MIPS { 0x200 } else { 0o1000 };
+        const O_APPEND = if IS_MIPS { 0x8 } else { 0o2000 };
+        const O_NONBLOCK = if IS_MIPS { 0x80 } else { 0o4000 };
+        const O_DIRECTORY = if IS_ARM { 0o40000 } else { 0o200000 };
+        const O_NOFOLLOW = if IS_ARM { 0o100000 } else { 0o400000 };
+        const O_CLOEXEC = 0o2000000;
+    }
+}
+
+/// How a file is opened, from the lowest bits of [`OpenFlags`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AccessMode {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+impl OpenFlags {
+    pub fn access_mode(self) -> AccessMode {
+        match self.bits() & 0o3 {
+            0 => AccessMode::ReadOnly,
+            1 => AccessMode::WriteOnly,
+            _ => AccessMode::ReadWrite,
+        }
+    }
+
+    /// Whether the file may be written to or created
+    pub fn is_write(self) -> bool {
+        self.access_mode() != AccessMode::ReadOnly
+            || self.contains(Self::O_CREAT)
+            || self.contains(Self::O_TRUNC)
+    }
+}
+
+bitflags! {
+    /// The memory protection passed to `mmap` and `mprotect`
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+    pub struct ProtFlags: u32 {
+        const PROT_READ = 0x1;
+        const PROT_WRITE = 0x2;
+        const PROT_EXEC = 0x4;
+    }
+}
+
+bitflags! {
+    /// The flags passed to `mmap`
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+    pub struct MapFlags: u32 {
+        const MAP_SHARED = 0x1;
+        const MAP_PRIVATE = 0x2;
+        const MAP_FIXED = 0x10;
+        const MAP_ANONYMOUS = if IS_MIPS { 0x800 } else { 0x20 };
+    }
+}
+
+/// A syscall with its arguments decoded. Syscalls with several variants (such as
+/// `dup2` and `dup3`, or `mmap` and `mmap2`) are decoded into the same variant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Syscall {
+    Open {
+        path: OsString,
+        flags: OpenFlags,
+        mode: u32,
+    },
+    OpenAt {
+        dirfd: i32,
+        path: OsString,
+        flags: OpenFlags,
+        mode: u32,
+    },
+    Creat {
+        path: OsString,
+        mode: u32,
+    },
+    Close {
+        fd: i32,
+    },
+    Read {
+        fd: i32,
+        buf: target_ptr_t,
+        count: usize,
+    },
+    Write {
+        fd: i32,
+        buf: target_ptr_t,
+        count: usize,
+    },
+    Dup {
+        oldfd: i32,
+    },
+    Dup2 {
+        oldfd: i32,
+        newfd: i32,
+    },
+    Unlink {
+        path: OsString,
+    },
+    UnlinkAt {
+        dirfd: i32,
+        path: OsString,
+        flags: i32,
+    },
+    Rename {
+        old_path: OsString,
+        new_path: OsString,
+    },
+    RenameAt {
+        old_dirfd: i32,
+        old_path: OsString,
+        new_dirfd: i32,
+        new_path: OsString,
+    },
+    Mkdir {
+        path: OsString,
+        mode: u32,
+    },
+    MkdirAt {
+        dirfd: i32,
+        path: OsString,
+        mode: u32,
+    },
+    Chdir {
+        path: OsString,
+    },
+    Chmod {
+        path: OsString,
+        mode: u32,
+    },
+    Fchmod {
+        fd: i32,
+        mode: u32,
+    },
+    FchmodAt {
+        dirfd: i32,
+        path: OsString,
+        mode: u32,
+    },
+    Execve {
+        path: OsString,
+        argv: Vec<OsString>,
+    },
+    ExecveAt {
+        dirfd: i32,
+        path: OsString,
+        argv: Vec<OsString>,
+    },
+    Fork,
+    Vfork,
+    Clone {
+        flags: u64,
+    },
+    Exit {
+        status: i32,
+    },
+    ExitGroup {
+        status: i32,
+    },
+    Kill {
+        pid: i32,
+        signal: i32,
+    },
+    Mmap {
+        addr: target_ptr_t,
+        len: usize,
+        prot: ProtFlags,
+        flags: MapFlags,
+        fd: i32,
+        offset: u64,
+    },
+    Mprotect {
+        addr: target_ptr_t,
+        len: usize,
+        prot: ProtFlags,
+    },
+    Munmap {
+        addr: target_ptr_t,
+        len: usize,
+    },
+    Brk {
+        addr: target_ptr_t,
+    },
+
+    /// Any syscall not decoded by this module, with its raw arguments
+    Other {
+        callno: target_ulong,
+        args: Vec<target_ulong>,
+    },
+}
+
+fn read_path(cpu: &mut CPUState, addr: target_ulong) -> Result<OsString, GuestReadFail> {
+    read_guest_bytes_until_nul(cpu, addr as target_ptr_t).map(OsString::from_vec)
+}
+
+/// Read a NULL-terminated array of string pointers, each `ptr_size` bytes as given by
+/// the guest ABI
+fn read_argv(
+    cpu: &mut CPUState,
+    argv: target_ulong,
+    ptr_size: usize,
+) -> Result<Vec<OsString>, GuestReadFail> {
+    let mut args = Vec::new();
+    let mut addr = argv as target_ptr_t;
+
+    while args.len() < MAX_ARGV {
+        let arg = match ptr_size {
+            4 => read_guest_type::<u32>(cpu, addr)? as target_ptr_t,
+            _ => read_guest_type::<target_ptr_t>(cpu, addr)?,
+        };
+        if arg == 0 {
+            break;
+        }
+
+        args.push(read_path(cpu, arg as target_ulong)?);
+        addr += ptr_size as target_ptr_t;
+    }
+
+    Ok(args)
+}
+
+/// Why a syscall couldn't be decoded
+enum DecodeError {
+    /// The syscall isn't one this module decodes
+    NotDecoded,
+
+    /// A string argument couldn't be read from guest memory
+    ReadFail,
+}
+
+impl From<GuestReadFail> for DecodeError {
+    fn from(_: GuestReadFail) -> Self {
+        DecodeError::ReadFail
+    }
+}
+
+impl Syscall {
+    /// Decode a syscall from its number and raw arguments, reading any strings from
+    /// guest memory
+    pub fn decode(cpu: &mut CPUState, callno: target_ulong, args: &[target_ulong]) -> Self {
+        prototype(callno)
+            .and_then(|prototype| Self::decode_with(cpu, prototype, args).ok())
+            .unwrap_or_else(|| Syscall::Other {
+                callno,
+                args: args.to_vec(),
+            })
+    }
+
+    fn decode_with(
+        cpu: &mut CPUState,
+        prototype: &Prototype,
+        args: &[target_ulong],
+    ) -> Result<Self, DecodeError> {
+        let name = prototype.name.as_str();
+        let arg = |i: usize| args.get(i).copied().unwrap_or(0);
+        let int = |i: usize| arg(i) as i32;
+        let ptr = |i: usize| arg(i) as target_ptr_t;
+        let size = |i: usize| arg(i) as usize;
+
+        // pointers are the size the guest ABI gives them, not necessarily the size of
+        // a target pointer
+        let ptr_size = |i: usize| match prototype.args.get(i) {
+            Some(arg) if arg.size != 0 => arg.size,
+            _ => std::mem::size_of::<target_ptr_t>(),
+        };
+
+        Ok(match name {
+            "open" => Syscall::Open {
+                path: read_path(cpu, arg(0))?,
+                flags: OpenFlags::from_bits_retain(arg(1) as u32),
+                mode: arg(2) as u32,
+            },
+            "openat" => Syscall::OpenAt {
+                dirfd: int(0),
+                path: read_path(cpu, arg(1))?,
+                flags: OpenFlags::from_bits_retain(arg(2) as u32),
+                mode: arg(3) as u32,
+            },
+            "creat" => Syscall::Creat {
+                path: read_path(cpu, arg(0))?,
+                mode: arg(1) as u32,
+            },
+            "close" => Syscall::Close { fd: int(0) },
+            "read" => Syscall::Read {
+                fd: int(0),
+                buf: ptr(1),
+                count: size(2),
+            },
+            "write" => Syscall::Write {
+                fd: int(0),
+                buf: ptr(1),
+                count: size(2),
+            },
+            "dup" => Syscall::Dup { oldfd: int(0) },
+            "dup2" | "dup3" => Syscall::Dup2 {
+                oldfd: int(0),
+                newfd: int(1),
+            },
+            "unlink" => Syscall::Unlink {
+                path: read_path(cpu, arg(0))?,
+            },
+            "unlinkat" => Syscall::UnlinkAt {
+                dirfd: int(0),
+                path: read_path(cpu, arg(1))?,
+                flags: int(2),
+            },
+            "rename" => Syscall::Rename {
+                old_path: read_path(cpu, arg(0))?,
+                new_path: read_path(cpu, arg(1))?,
+            },
+            "renameat" | "renameat2" => Syscall::RenameAt {
+                old_dirfd: int(0),
+                old_path: read_path(cpu, arg(1))?,
+                new_dirfd: int(2),
+                new_path: read_path(cpu, arg(3))?,
+            },
+            "mkdir" => Syscall::Mkdir {
+                path: read_path(cpu, arg(0))?,
+                mode: arg(1) as u32,
+            },
+            "mkdirat" => Syscall::MkdirAt {
+                dirfd: int(0),
+                path: read_path(cpu, arg(1))?,
+                mode: arg(2) as u32,
+            },
+            "chdir" => Syscall::Chdir {
+                path: read_path(cpu, arg(0))?,
+            },
+            "chmod" => Syscall::Chmod {
+                path: read_path(cpu, arg(0))?,
+                mode: arg(1) as u32,
+            },
+            "fchmod" => Syscall::Fchmod {
+                fd: int(0),
+                mode: arg(1) as u32,
+            },
+            "fchmodat" => Syscall::FchmodAt {
+                dirfd: int(0),
+                path: read_path(cpu, arg(1))?,
+                mode: arg(2) as u32,
+            },
+            "execve" => Syscall::Execve {
+                path: read_path(cpu, arg(0))?,
+                argv: read_argv(cpu, arg(1), ptr_size(1))?,
+            },
+            "execveat" => Syscall::ExecveAt {
+                dirfd: int(0),
+                path: read_path(cpu, arg(1))?,
+                argv: read_argv(cpu, arg(2), ptr_size(2))?,
+            },
+            "fork" => Syscall::Fork,
+            "vfork" => Syscall::Vfork,
+            "clone" => Syscall::Clone {
+                flags: arg(0) as u64,
+            },
+            "exit" => Syscall::Exit { status: int(0) },
+            "exit_group" => Syscall::ExitGroup { status: int(0) },
+            "kill" => Syscall::Kill {
+                pid: int(0),
+                signal: int(1),
+            },
+            "mmap" | "mmap2" => Syscall::Mmap {
+                addr: ptr(0),
+                len: size(1),
+                prot: ProtFlags::from_bits_retain(arg(2) as u32),
+                flags: MapFlags::from_bits_retain(arg(3) as u32),
+                fd: int(4),
+                offset: if name == "mmap2" {
+                    arg(5) as u64 * MMAP2_PAGE_SIZE
+                } else {
+                    arg(5) as u64
+                },
+            },
+            "mprotect" => Syscall::Mprotect {
+                addr: ptr(0),
+                len: size(1),
+                prot: ProtFlags::from_bits_retain(arg(2) as u32),
+            },
+            "munmap" => Syscall::Munmap {
+                addr: ptr(0),
+                len: size(1),
+            },
+            "brk" => Syscall::Brk { addr: ptr(0) },
+            _ => return Err(DecodeError::NotDecoded),
+        })
+    }
+
+    /// The path the syscall operates on, if it takes one
+    pub fn path(&self) -> Option<&OsString> {
+        match self {
+            Syscall::Open { path, .. }
+            | Syscall::OpenAt { path, .. }
+            | Syscall::Creat { path, .. }
+            | Syscall::Unlink { path }
+            | Syscall::UnlinkAt { path, .. }
+            | Syscall::Mkdir { path, .. }
+            | Syscall::MkdirAt { path, .. }
+            | Syscall::Chdir { path }
+            | Syscall::Chmod { path, .. }
+            | Syscall::FchmodAt { path, .. }
+            | Syscall::Execve { path, .. }
+            | Syscall::ExecveAt { path, .. } => Some(path),
+            Syscall::Rename { old_path, .. } | Syscall::RenameAt { old_path, .. } => Some(old_path),
+            _ => None,
+        }
+    }
+}
+
+/// Split a raw syscall return value into either its result or the error number, using
+/// the Linux convention of returning `-errno` for values in `-4095..0`
+pub fn syscall_result(retval: target_ulong) -> Result<target_ulong, i32> {
+    let signed = retval as target_long as i64;
+
+    if (-4095..0).contains(&signed) {
+        Err(-signed as i32)
+    } else {
+        Ok(retval)
+    }
+}
+
+/// Add a callback which runs whenever any syscall is entered, with its arguments
+/// decoded
+pub fn on_sys_enter_decoded(mut callback: impl FnMut(&mut CPUState, &Syscall) + Send + 'static) {
+    PppCallback::new().on_all_sys_enter(move |cpu, _, callno| {
+        let args = SYSCALL_ARGS.map(|storage| storage.read(cpu));
+        let syscall = Syscall::decode(cpu, callno, &args);
+
+        callback(cpu, &syscall);
+    });
+}
+
+/// Add a callback which runs whenever any syscall returns, with the arguments it was
+/// called with decoded and its result split using [`syscall_result`]
+///
+/// The callback must not add further callbacks, see
+/// [`on_all_sys_return_with_args`].
+pub fn on_sys_return_decoded(
+    mut callback: impl FnMut(&mut CPUState, &Syscall, Result<target_ulong, i32>) + Send + 'static,
+) {
+    on_all_sys_return_with_args(move |cpu, ctx| {
+        let syscall = Syscall::decode(cpu, ctx.callno(), ctx.args());
+
+        callback(cpu, &syscall, syscall_result(ctx.retval()));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_flags() {
+        let flags = OpenFlags::from_bits_retain(1 | OpenFlags::O_CREAT.bits() | 0x4000_0000);
+
+        assert_eq!(flags.access_mode(), AccessMode::WriteOnly);
+        assert!(flags.contains(OpenFlags::O_CREAT));
+        assert!(!flags.contains(OpenFlags::O_CREAT | OpenFlags::O_EXCL));
+        assert_eq!(flags.bits() & 0x4000_0000, 0x4000_0000);
+
+        assert!(!OpenFlags::empty().is_write());
+        assert!(OpenFlags::O_TRUNC.is_write());
+    }
+}
@@ -36,10 +36,16 @@ use crate::sys::{target_ptr_t, CPUState};
 
 use std::collections::HashSet;
 use std::ops::Range;
-use std::os::raw::{c_int, c_long, c_void};
+use std::os::raw::{c_char, c_int, c_long, c_void};
 use std::ptr;
 use std::sync::Once;
 
+pub mod differential;
+pub mod export;
+pub mod symbolic;
+
+pub use differential::{differential, LabelError, TaintLabels};
+
 plugin_import! {
     /// Direct access to the taint2 C API when direct use is needed
     static TAINT: Taint = extern "taint2" {
@@ -101,6 +107,12 @@ plugin_import! {
         fn taint2_query_laddr_full(reg_num: u64, offset: u64, qr: &mut QueryResult);
         fn taint2_query_reg_full(reg_num: u32, offset: u32, qr: &mut QueryResult);
         fn taint2_query_ram_full(addr: u64, qr: &mut QueryResult);
+
+        fn taint2_enable_sym();
+        fn taint2_sym_label_ram(ram_offset: u64, label: u32);
+        fn taint2_sym_label_reg(reg_num: c_int, offset: c_int, label: u32);
+        fn taint2_sym_query_ram(ram_offset: u64) -> *mut c_char;
+        fn taint2_sym_query_reg(reg_num: u32, offset: u32) -> *mut c_char;
     };
 }
 
@@ -544,6 +556,94 @@ pub fn check_laddr(addr: u64, offset: u64) -> bool {
     TAINT_ENABLE.is_completed() && TAINT.taint2_query_laddr(addr, offset) > 0
 }
 
+/// Get the taint compute number (TCN) of a byte of RAM: how many computations the
+/// tainted data has been through since being labeled. A TCN of 0 means the byte is a
+/// direct copy of labeled data, while higher values mean it is less directly
+/// controlled by the labeled input.
+///
+/// Returns 0 for untainted bytes, so should be used together with [`check_ram`].
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::taint;
+///
+/// let addr = 0xffff_0034;
+/// if taint::check_ram(addr) && taint::tcn_ram(addr) == 0 {
+///     println!("{:#x} is copied directly from the input", addr);
+/// }
+/// ```
+pub fn tcn_ram(addr: target_ptr_t) -> u32 {
+    if TAINT_ENABLE.is_completed() {
+        TAINT.taint2_query_tcn_ram(addr as u64)
+    } else {
+        0
+    }
+}
+
+/// Get the highest taint compute number of any byte of a register. See [`tcn_ram`].
+pub fn tcn_reg(reg: impl Into<Reg>) -> u32 {
+    let reg_num = reg.into() as c_int;
+
+    (0..std::mem::size_of::<target_ptr_t>())
+        .map(|offset| tcn_reg_num_byte(reg_num, offset))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Get the taint compute number of a specific byte of a register. See [`tcn_ram`].
+///
+/// ## Panics
+///
+/// This function panics if `byte_offset` is greater than or equal to the size of the register.
+pub fn tcn_reg_byte(reg: impl Into<Reg>, byte_offset: usize) -> u32 {
+    assert!(byte_offset < std::mem::size_of::<target_ptr_t>());
+    tcn_reg_num_byte(reg.into() as c_int, byte_offset)
+}
+
+fn tcn_reg_num_byte(reg_num: c_int, byte_offset: usize) -> u32 {
+    if TAINT_ENABLE.is_completed() {
+        TAINT.taint2_query_tcn_reg(reg_num, byte_offset as c_int)
+    } else {
+        0
+    }
+}
+
+/// Get a mask of the bits of a value in RAM which are controlled by tainted input: the
+/// bits which can take on different values depending on the labeled data, as opposed
+/// to bits which are tainted but fixed (such as by masking). Bit `n` of the mask is bit
+/// `n % 8` of the byte at `addr + n / 8`.
+///
+/// ## Panics
+///
+/// This function panics if `size` is greater than 8.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::taint;
+///
+/// // how much of a 4 byte length field can the input control?
+/// let controlled = taint::controlled_bits(0xffff_0034, 4).count_ones();
+/// println!("{} of 32 bits are controlled", controlled);
+/// ```
+pub fn controlled_bits(addr: target_ptr_t, size: usize) -> u64 {
+    assert!(size <= 8, "controlled bits can only be queried for up to 8 bytes");
+
+    if !TAINT_ENABLE.is_completed() {
+        return 0;
+    }
+
+    let addr = Addr {
+        typ: AddrType::MADDR,
+        val: ValueUnion { ma: addr as u64 },
+        off: 0,
+        flag: AddrFlag::IRRELEVANT,
+    };
+
+    TAINT.taint2_query_cb_mask(addr, size as u8)
+}
+
 /// Get a list of all taint labels applied to a register, excluding duplicates across bytes
 pub fn get_reg(reg: impl Into<Reg>) -> Vec<u32> {
     let labels: HashSet<u32> = iter_reg_labels(reg).collect();
@@ -691,5 +791,3 @@ impl Iterator for LabelIter {
         }
     }
 }
-
-// TODO: sym_enable, sym_label_ram, sym_label_reg
@@ -0,0 +1,822 @@
+//! Differential taint analysis between two labelings of the same execution
+//!
+//! Running the same execution twice with different inputs labeled shows which values
+//! each input influences, and where that influence differs. [`differential`] automates
+//! this for live execution: the guest is reverted to a snapshot, the first set of
+//! [`TaintLabels`] is applied and the taint of the observed registers and memory is
+//! recorded each time an observed pc is executed, then the same is done from the same
+//! snapshot with the second set of labels. The two recordings are matched up by pc and
+//! the number of times it has been executed, and every location whose labels differ is
+//! reported in a [`TaintDiffReport`].
+//!
+//! Replays can't be restarted within the same run of PANDA, so for replays each
+//! labeling is recorded in its own run using [`record`], with the [`TaintTrace`] saved
+//! at the end of the run. The saved traces are then compared with [`compare`].
+//!
+//! Labels are stored in taint2 with the index of the run in their top byte, so that
+//! taint left over from the first run isn't attributed to the second, and so must be
+//! below `0x0100_0000`. Larger labels are rejected with [`LabelError`].
+//!
+//! Requires the `taint2` plugin.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::regs::Reg;
+//! use panda::taint::{self, TaintLabels};
+//!
+//! #[panda::init]
+//! fn init(_: &mut panda::PluginHandle) {
+//!     let input = 0x1000;
+//!
+//!     taint::differential(
+//!         TaintLabels::new().ram(input..input + 4, 1).unwrap(),
+//!         TaintLabels::new().ram(input + 4..input + 8, 1).unwrap(),
+//!     )
+//!     .snapshot("root")
+//!     .observe_pc(0x401234)
+//!     .regs(&[Reg::RAX, Reg::RDX])
+//!     .until_pc(0x401300)
+//!     .run(|report| print!("{}", report));
+//! }
+//! ```
+use super::{enable, get_ram, get_reg_byte, label_ram_range, label_reg};
+use crate::api::regs::Reg;
+use crate::prelude::*;
+use crate::Callback;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Labels of each run are offset by the run index shifted by this much
+const RUN_LABEL_SHIFT: u32 = 24;
+
+/// An error from adding a label to [`TaintLabels`]
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum LabelError {
+    /// The label overlaps the top byte, which holds the index of the run
+    #[error("taint label {0:#x} is not below 0x0100_0000")]
+    TooLarge(u32),
+}
+
+fn check_label(label: u32) -> Result<u32, LabelError> {
+    if label >> RUN_LABEL_SHIFT == 0 {
+        Ok(label)
+    } else {
+        Err(LabelError::TooLarge(label))
+    }
+}
+
+/// Labels applied at the start of a run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaintLabels {
+    regs: Vec<(Reg, u32)>,
+    ram: Vec<(Range<target_ptr_t>, u32)>,
+}
+
+impl TaintLabels {
+    /// No labels, to be added to using the other methods
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Label every byte of a register. The label must be below `0x0100_0000`.
+    pub fn reg(mut self, reg: impl Into<Reg>, label: u32) -> Result<Self, LabelError> {
+        self.regs.push((reg.into(), check_label(label)?));
+        Ok(self)
+    }
+
+    /// Label every byte of a range of RAM. The label must be below `0x0100_0000`.
+    pub fn ram(mut self, range: Range<target_ptr_t>, label: u32) -> Result<Self, LabelError> {
+        self.ram.push((range, check_label(label)?));
+        Ok(self)
+    }
+
+    fn apply(&self, run: u32) {
+        let base = run << RUN_LABEL_SHIFT;
+
+        for &(reg, label) in &self.regs {
+            label_reg(reg, base | label);
+        }
+
+        for (range, label) in &self.ram {
+            label_ram_range(range.clone(), base | label);
+        }
+    }
+}
+
+/// Where and when taint is recorded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Observe {
+    pcs: BTreeSet<target_ulong>,
+    regs: Vec<Reg>,
+    ram: Vec<Range<target_ptr_t>>,
+    until_pc: Option<target_ulong>,
+    max_points: Option<usize>,
+}
+
+impl Observe {
+    /// Observe nothing, to be added to using the other methods
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record taint each time the block containing `pc` starts executing
+    pub fn pc(mut self, pc: target_ulong) -> Self {
+        self.pcs.insert(pc);
+        self
+    }
+
+    /// Record the taint of each byte of the given registers
+    pub fn regs(mut self, regs: &[Reg]) -> Self {
+        self.regs.extend_from_slice(regs);
+        self
+    }
+
+    /// Record the taint of each byte of a range of RAM
+    pub fn ram(mut self, range: Range<target_ptr_t>) -> Self {
+        self.ram.push(range);
+        self
+    }
+
+    /// End the run the first time the block containing `pc` is executed
+    pub fn until_pc(mut self, pc: target_ulong) -> Self {
+        self.until_pc = Some(pc);
+        self
+    }
+
+    /// End the run once taint has been recorded the given number of times
+    pub fn max_points(mut self, points: usize) -> Self {
+        self.max_points = Some(points);
+        self
+    }
+}
+
+/// A register byte or byte of RAM
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Location {
+    /// A byte of a register, by register name
+    Reg(String, usize),
+    Ram(target_ptr_t),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Reg(name, byte) => write!(f, "{}[{}]", name, byte),
+            Location::Ram(addr) => write!(f, "ram {:#x}", addr),
+        }
+    }
+}
+
+/// The taint recorded when an observed pc was executed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaintPoint {
+    pub pc: target_ulong,
+
+    /// The number of times the pc had been executed before
+    pub hit: u64,
+
+    /// The labels of each tainted location. Untainted locations are left out.
+    pub taint: BTreeMap<Location, BTreeSet<u32>>,
+}
+
+/// The taint recorded during a single run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaintTrace {
+    pub points: Vec<TaintPoint>,
+}
+
+fn invalid_data(line: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line + 1, msg),
+    )
+}
+
+fn parse_hex(field: Option<&str>) -> Option<u64> {
+    u64::from_str_radix(field?.trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_labels(field: Option<&str>) -> Option<BTreeSet<u32>> {
+    field?.split(',').map(|label| label.parse().ok()).collect()
+}
+
+fn fmt_labels(labels: &BTreeSet<u32>) -> String {
+    let labels: Vec<String> = labels.iter().map(u32::to_string).collect();
+
+    labels.join(",")
+}
+
+impl TaintTrace {
+    /// Write the trace in a line-based text format, with a line for each point followed
+    /// by a line for each tainted location
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        for point in &self.points {
+            writeln!(writer, "point {:#x} {:#x}", point.pc, point.hit)?;
+
+            for (location, labels) in &point.taint {
+                match location {
+                    Location::Reg(name, byte) => write!(writer, "reg {} {:#x} ", name, byte)?,
+                    Location::Ram(addr) => write!(writer, "ram {:#x} ", addr)?,
+                }
+
+                writeln!(writer, "{}", fmt_labels(labels))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a trace written by [`TaintTrace::write`]
+    pub fn read(reader: impl BufRead) -> io::Result<Self> {
+        let mut trace = Self::default();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+
+            let parsed = match fields.next() {
+                Some("point") => (|| {
+                    trace.points.push(TaintPoint {
+                        pc: parse_hex(fields.next())? as target_ulong,
+                        hit: parse_hex(fields.next())?,
+                        taint: BTreeMap::new(),
+                    });
+
+                    Some(())
+                })(),
+                Some(kind) if kind == "reg" || kind == "ram" => (|| {
+                    let location = if kind == "reg" {
+                        let name = fields.next()?.to_owned();
+                        Location::Reg(name, parse_hex(fields.next())? as usize)
+                    } else {
+                        Location::Ram(parse_hex(fields.next())? as target_ptr_t)
+                    };
+                    let labels = parse_labels(fields.next())?;
+
+                    trace.points.last_mut()?.taint.insert(location, labels);
+
+                    Some(())
+                })(),
+                None => Some(()),
+                Some(_) => return Err(invalid_data(i, "unknown record kind")),
+            };
+
+            if parsed.is_none() {
+                return Err(invalid_data(i, "malformed record"));
+            }
+        }
+
+        Ok(trace)
+    }
+
+    /// Save the trace to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+
+        writer.flush()
+    }
+
+    /// Load a trace saved by [`TaintTrace::save`]
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+}
+
+/// A location whose labels differ between the runs at a matched point
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaintDiff {
+    pub pc: target_ulong,
+    pub hit: u64,
+    pub location: Location,
+    pub a: BTreeSet<u32>,
+    pub b: BTreeSet<u32>,
+}
+
+/// The differences between the taint of two runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaintDiffReport {
+    pub diffs: Vec<TaintDiff>,
+
+    /// Points (pc and hit) only reached by one of the runs
+    pub only_in_a: Vec<(target_ulong, u64)>,
+    pub only_in_b: Vec<(target_ulong, u64)>,
+}
+
+impl TaintDiffReport {
+    /// Check whether no differences were found
+    pub fn is_empty(&self) -> bool {
+        self.diffs.is_empty() && self.only_in_a.is_empty() && self.only_in_b.is_empty()
+    }
+
+    /// The first point at which the taint of the runs differs, if any
+    pub fn first_divergence(&self) -> Option<(target_ulong, u64)> {
+        self.diffs.first().map(|diff| (diff.pc, diff.hit))
+    }
+
+    /// Write the report as text
+    pub fn write_report(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "{}", self)
+    }
+}
+
+impl fmt::Display for TaintDiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "points: {} only in a, {} only in b",
+            self.only_in_a.len(),
+            self.only_in_b.len()
+        )?;
+
+        for (pc, hit) in &self.only_in_a {
+            writeln!(f, "  a: {:#x} #{}", pc, hit)?;
+        }
+
+        for (pc, hit) in &self.only_in_b {
+            writeln!(f, "  b: {:#x} #{}", pc, hit)?;
+        }
+
+        writeln!(f, "taint: {} differences", self.diffs.len())?;
+        for diff in &self.diffs {
+            writeln!(
+                f,
+                "  {:#x} #{} {}: {{{}}} -> {{{}}}",
+                diff.pc,
+                diff.hit,
+                diff.location,
+                fmt_labels(&diff.a),
+                fmt_labels(&diff.b)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compare the taint recorded by two runs, matching points by pc and hit count
+pub fn compare(a: &TaintTrace, b: &TaintTrace) -> TaintDiffReport {
+    let index = |trace: &TaintTrace| -> BTreeMap<(target_ulong, u64), usize> {
+        trace
+            .points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| ((point.pc, point.hit), i))
+            .collect()
+    };
+
+    let (points_a, points_b) = (index(a), index(b));
+    let mut report = TaintDiffReport::default();
+    let empty = BTreeSet::new();
+
+    for (&key, &i) in &points_a {
+        let point_a = &a.points[i];
+        let point_b = match points_b.get(&key) {
+            Some(&j) => &b.points[j],
+            None => {
+                report.only_in_a.push(key);
+                continue;
+            }
+        };
+
+        let locations: BTreeSet<&Location> =
+            point_a.taint.keys().chain(point_b.taint.keys()).collect();
+
+        for location in locations {
+            let labels_a = point_a.taint.get(location).unwrap_or(&empty);
+            let labels_b = point_b.taint.get(location).unwrap_or(&empty);
+
+            if labels_a != labels_b {
+                report.diffs.push(TaintDiff {
+                    pc: key.0,
+                    hit: key.1,
+                    location: location.clone(),
+                    a: labels_a.clone(),
+                    b: labels_b.clone(),
+                });
+            }
+        }
+    }
+
+    report.only_in_b = points_b
+        .keys()
+        .filter(|key| !points_a.contains_key(key))
+        .copied()
+        .collect();
+
+    // order by when the points were first reached in the first run
+    report
+        .diffs
+        .sort_by_key(|diff| points_a[&(diff.pc, diff.hit)]);
+
+    report
+}
+
+type ReportCallback = Box<dyn FnOnce(&TaintDiffReport) + Send>;
+
+enum Phase {
+    /// Waiting for the guest to be reverted before starting a run
+    Reverting,
+    Running,
+    Done,
+}
+
+struct Recorder {
+    observe: Observe,
+    run: u32,
+    phase: Phase,
+    hits: BTreeMap<target_ulong, u64>,
+    trace: TaintTrace,
+
+    /// The state of a differential run, if recording is driven by `differential`
+    differential: Option<DifferentialState>,
+}
+
+struct DifferentialState {
+    snapshot: String,
+
+    /// The labels applied at the start of each run
+    labels: [TaintLabels; 2],
+    trace_a: Option<TaintTrace>,
+    on_report: Option<ReportCallback>,
+}
+
+impl Recorder {
+    fn new(observe: Observe, differential: Option<DifferentialState>) -> Self {
+        Self {
+            observe,
+            run: 0,
+            phase: Phase::Running,
+            hits: BTreeMap::new(),
+            trace: TaintTrace::default(),
+            differential,
+        }
+    }
+
+    fn record_point(&mut self, pc: target_ulong) {
+        let hit = self.hits.entry(pc).or_default();
+        let mut point = TaintPoint {
+            pc,
+            hit: *hit,
+            taint: BTreeMap::new(),
+        };
+        *hit += 1;
+
+        let run = self.run;
+        let mut insert = |location: Location, labels: Vec<u32>| {
+            // only keep labels applied by this run, without the run index
+            let labels: BTreeSet<u32> = labels
+                .into_iter()
+                .filter(|label| label >> RUN_LABEL_SHIFT == run)
+                .map(|label| label & ((1 << RUN_LABEL_SHIFT) - 1))
+                .collect();
+
+            if !labels.is_empty() {
+                point.taint.insert(location, labels);
+            }
+        };
+
+        for &reg in &self.observe.regs {
+            for byte in 0..std::mem::size_of::<target_ptr_t>() {
+                insert(
+                    Location::Reg(reg.to_string(), byte),
+                    get_reg_byte(reg, byte),
+                );
+            }
+        }
+
+        for range in &self.observe.ram {
+            for addr in range.clone() {
+                insert(Location::Ram(addr), get_ram(addr));
+            }
+        }
+
+        self.trace.points.push(point);
+    }
+
+    fn is_finished(&self, tb: &TranslationBlock) -> bool {
+        let reached_end = self
+            .observe
+            .until_pc
+            .map_or(false, |pc| block_contains(tb, pc));
+        let enough_points = self
+            .observe
+            .max_points
+            .map_or(false, |max| self.trace.points.len() >= max);
+
+        reached_end || enough_points
+    }
+}
+
+fn block_contains(tb: &TranslationBlock, pc: target_ulong) -> bool {
+    (tb.pc..tb.pc + tb.size as target_ulong).contains(&pc)
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+    static ref CALLBACKS: (Callback, Callback) = install_callbacks();
+}
+
+fn install_callbacks() -> (Callback, Callback) {
+    let block = Callback::new();
+    let reverted = Callback::new();
+
+    block.before_block_exec(|_, tb| {
+        let mut recorder_lock = RECORDER.lock().unwrap();
+        let recorder = match recorder_lock.as_mut() {
+            Some(recorder) if matches!(recorder.phase, Phase::Running) => recorder,
+            _ => return,
+        };
+
+        let observed: Vec<target_ulong> = recorder
+            .observe
+            .pcs
+            .iter()
+            .copied()
+            .filter(|&pc| block_contains(tb, pc))
+            .collect();
+
+        for pc in observed {
+            recorder.record_point(pc);
+        }
+
+        if !recorder.is_finished(tb) {
+            return;
+        }
+
+        let report = finish_run(recorder);
+        if let Phase::Done = recorder.phase {
+            CALLBACKS.0.disable();
+            CALLBACKS.1.disable();
+        }
+
+        // the report callback may use the recorder, such as through `trace`
+        drop(recorder_lock);
+        if let Some((on_report, report)) = report {
+            on_report(&report);
+        }
+    });
+
+    reverted.after_loadvm(|_| {
+        if let Some(recorder) = RECORDER.lock().unwrap().as_mut() {
+            if let Phase::Reverting = recorder.phase {
+                if let Some(state) = &recorder.differential {
+                    state.labels[recorder.run as usize].apply(recorder.run);
+                    recorder.phase = Phase::Running;
+                }
+            }
+        }
+    });
+
+    (block, reverted)
+}
+
+/// End the current run, either starting the second run of a differential or returning
+/// the report callback along with its results
+fn finish_run(recorder: &mut Recorder) -> Option<(ReportCallback, TaintDiffReport)> {
+    let state = match &mut recorder.differential {
+        Some(state) => state,
+        None => {
+            recorder.phase = Phase::Done;
+            return None;
+        }
+    };
+
+    if recorder.run == 0 {
+        state.trace_a = Some(std::mem::take(&mut recorder.trace));
+        recorder.run = 1;
+        recorder.hits.clear();
+        recorder.phase = Phase::Reverting;
+
+        Panda::revert_async(&state.snapshot);
+
+        None
+    } else {
+        recorder.phase = Phase::Done;
+
+        let trace_a = state.trace_a.take().unwrap_or_default();
+        let report = compare(&trace_a, &recorder.trace);
+
+        state.on_report.take().map(|on_report| (on_report, report))
+    }
+}
+
+/// Apply labels and start recording taint at the observed points of the current run,
+/// such as a replay. The recording ends at the end point of `observe`, if any, after
+/// which the callbacks are disabled. It can be retrieved with [`trace`].
+pub fn record(labels: TaintLabels, observe: Observe) {
+    enable();
+    labels.apply(0);
+
+    *RECORDER.lock().unwrap() = Some(Recorder::new(observe, None));
+    CALLBACKS.0.enable();
+}
+
+/// Get the taint recorded so far by [`record`]
+pub fn trace() -> TaintTrace {
+    RECORDER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|recorder| recorder.trace.clone())
+        .unwrap_or_default()
+}
+
+/// A differential taint run, started with [`Differential::run`]
+pub struct Differential {
+    labels_a: TaintLabels,
+    labels_b: TaintLabels,
+    observe: Observe,
+    snapshot: String,
+}
+
+/// Set up a differential taint run between two sets of labels (see the
+/// [module documentation](self))
+pub fn differential(run_a_labels: TaintLabels, run_b_labels: TaintLabels) -> Differential {
+    Differential {
+        labels_a: run_a_labels,
+        labels_b: run_b_labels,
+        observe: Observe::new(),
+        snapshot: String::from("root"),
+    }
+}
+
+impl Differential {
+    /// Set the snapshot both runs start from. Defaults to `root`.
+    pub fn snapshot(mut self, name: &str) -> Self {
+        self.snapshot = name.to_owned();
+        self
+    }
+
+    /// Record taint each time the block containing `pc` starts executing
+    pub fn observe_pc(mut self, pc: target_ulong) -> Self {
+        self.observe = self.observe.pc(pc);
+        self
+    }
+
+    /// Record the taint of each byte of the given registers
+    pub fn regs(mut self, regs: &[Reg]) -> Self {
+        self.observe = self.observe.regs(regs);
+        self
+    }
+
+    /// Record the taint of each byte of a range of RAM
+    pub fn ram(mut self, range: Range<target_ptr_t>) -> Self {
+        self.observe = self.observe.ram(range);
+        self
+    }
+
+    /// End each run the first time the block containing `pc` is executed
+    pub fn until_pc(mut self, pc: target_ulong) -> Self {
+        self.observe = self.observe.until_pc(pc);
+        self
+    }
+
+    /// End each run once taint has been recorded the given number of times
+    pub fn max_points(mut self, points: usize) -> Self {
+        self.observe = self.observe.max_points(points);
+        self
+    }
+
+    /// Revert to the snapshot and start the first run, calling `on_report` with the
+    /// differences once the second run has ended. A run without an end point (see
+    /// [`until_pc`](Self::until_pc) and [`max_points`](Self::max_points)) never ends.
+    pub fn run(self, on_report: impl FnOnce(&TaintDiffReport) + Send + 'static) {
+        enable();
+
+        let mut recorder = Recorder::new(
+            self.observe,
+            Some(DifferentialState {
+                snapshot: self.snapshot.clone(),
+                labels: [self.labels_a, self.labels_b],
+                trace_a: None,
+                on_report: Some(Box::new(on_report)),
+            }),
+        );
+        recorder.phase = Phase::Reverting;
+
+        *RECORDER.lock().unwrap() = Some(recorder);
+        CALLBACKS.0.enable();
+        CALLBACKS.1.enable();
+
+        // the first run's labels are applied once the revert has happened
+        Panda::revert_async(&self.snapshot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(pc: target_ulong, hit: u64, taint: &[(Location, &[u32])]) -> TaintPoint {
+        TaintPoint {
+            pc,
+            hit,
+            taint: taint
+                .iter()
+                .map(|(location, labels)| (location.clone(), labels.iter().copied().collect()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_labels_reject_run_byte() {
+        assert!(TaintLabels::new().ram(0..4, 0xff_ffff).is_ok());
+        assert_eq!(
+            TaintLabels::new().ram(0..4, 0x0100_0000),
+            Err(LabelError::TooLarge(0x0100_0000))
+        );
+        assert_eq!(
+            TaintLabels::new().reg(crate::regs::reg_sp(), u32::MAX),
+            Err(LabelError::TooLarge(u32::MAX))
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let reg = Location::Reg(String::from("EAX"), 0);
+        let a = TaintTrace {
+            points: vec![
+                point(0x20, 0, &[(Location::Ram(0x100), &[1])]),
+                point(0x10, 0, &[(reg.clone(), &[1, 2])]),
+                point(0x30, 0, &[]),
+            ],
+        };
+        let b = TaintTrace {
+            points: vec![
+                point(0x10, 0, &[(reg.clone(), &[2])]),
+                point(
+                    0x20,
+                    0,
+                    &[(Location::Ram(0x100), &[1]), (Location::Ram(0x101), &[3])],
+                ),
+                point(0x10, 1, &[]),
+            ],
+        };
+
+        let report = compare(&a, &b);
+
+        assert_eq!(report.only_in_a, vec![(0x30, 0)]);
+        assert_eq!(report.only_in_b, vec![(0x10, 1)]);
+
+        // ordered by when the point was reached in the first run
+        let diffs: Vec<_> = report
+            .diffs
+            .iter()
+            .map(|diff| (diff.pc, diff.location.clone(), diff.a.len(), diff.b.len()))
+            .collect();
+        assert_eq!(
+            diffs,
+            vec![(0x20, Location::Ram(0x101), 0, 1), (0x10, reg, 2, 1)]
+        );
+        assert_eq!(report.first_divergence(), Some((0x20, 0)));
+
+        assert!(compare(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn test_trace_round_trip() {
+        let trace = TaintTrace {
+            points: vec![
+                point(
+                    0x401234,
+                    3,
+                    &[
+                        (Location::Reg(String::from("RAX"), 7), &[1, 0xff_ffff]),
+                        (Location::Ram(0x1000), &[2]),
+                    ],
+                ),
+                point(0x401300, 0, &[]),
+            ],
+        };
+
+        let mut written = Vec::new();
+        trace.write(&mut written).unwrap();
+
+        assert_eq!(TaintTrace::read(&written[..]).unwrap(), trace);
+    }
+
+    #[test]
+    fn test_trace_read_errors() {
+        for text in &[
+            "bogus 0x1 0x0\n",
+            "point 0x1\n",
+            "ram 0x1000 1\n",
+            "point 0x1 0x0\nram 0x1000 a\n",
+        ] {
+            let err = TaintTrace::read(text.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let err = TaintTrace::read(&b"point 0x1 0x0\n\nreg\n"[..]).unwrap_err();
+        assert!(err.to_string().starts_with("line 3:"));
+    }
+}
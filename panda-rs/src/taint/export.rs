@@ -0,0 +1,272 @@
+//! Export of taint query results for downstream tooling
+//!
+//! Taint queries can be written either as JSON, in the same shape as the
+//! `tainted_instr` pandalog entries produced by `ida_taint2` (as read by `plog_reader`),
+//! or as CSV with one row per tainted byte. Both are consumed by the existing IDA and
+//! Ghidra taint visualization scripts. Each query also includes the controlled bits of
+//! the byte (`cbMask`), which those scripts ignore.
+//!
+//! [`TaintWriter`] streams records as they are produced, so whole-replay taint dumps
+//! never need to be held in memory.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::prelude::*;
+//! use panda::taint::export::{Format, TaintQuery, TaintRecord, TaintWriter};
+//!
+//! # let cpu: &mut CPUState = todo!();
+//! # let (pc, buf) = (0, 0x1000);
+//! let file = std::fs::File::create("taint.json").unwrap();
+//! let mut writer = TaintWriter::new(file, Format::Json);
+//!
+//! let queries = TaintQuery::ram_range(buf..buf + 0x10);
+//! if !queries.is_empty() {
+//!     writer.write(&TaintRecord::new(cpu, pc, queries)).unwrap();
+//! }
+//!
+//! writer.finish().unwrap();
+//! ```
+use super::{check_ram, check_reg_num_byte, LabelIter, QueryResult, TAINT};
+use crate::api::regs::Reg;
+use crate::current_asid;
+use crate::prelude::*;
+use crate::rr::rr_get_guest_instr_count;
+
+use std::io::{self, Write};
+use std::ops::Range;
+use std::os::raw::c_int;
+
+/// The taint on a single byte
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaintQuery {
+    /// The address of the byte (a RAM offset, or a register number for registers)
+    pub addr: u64,
+
+    /// The offset of the byte within the queried value
+    pub offset: u32,
+
+    /// The taint compute number: how many computations the tainted data has been
+    /// through since being labeled
+    pub tcn: u32,
+
+    /// The bits of the byte which are controlled by the tainted input (see
+    /// [`controlled_bits`](super::controlled_bits))
+    pub cb_mask: u8,
+
+    /// An identifier for the label set, shared by every byte with identical labels
+    pub label_set: u64,
+
+    /// The labels applied to the byte
+    pub labels: Vec<u32>,
+}
+
+impl TaintQuery {
+    fn from_result(addr: u64, offset: u32, query_result: QueryResult) -> Self {
+        let tcn = query_result.tcn;
+        let cb_mask = query_result.cb_mask;
+        let label_set = query_result.ls as u64;
+        let labels = LabelIter {
+            done: query_result.is_empty_or_invalid(),
+            query_result,
+        }
+        .collect();
+
+        Self {
+            addr,
+            offset,
+            tcn,
+            cb_mask,
+            label_set,
+            labels,
+        }
+    }
+
+    /// Query the taint on a byte of RAM, returning `None` if it is untainted
+    pub fn ram(addr: target_ptr_t) -> Option<Self> {
+        Self::ram_with_offset(addr, 0)
+    }
+
+    fn ram_with_offset(addr: target_ptr_t, offset: u32) -> Option<Self> {
+        if !check_ram(addr) {
+            return None;
+        }
+
+        let mut query_result = QueryResult::empty();
+        TAINT.taint2_query_ram_full(addr as u64, &mut query_result);
+
+        Some(Self::from_result(addr as u64, offset, query_result))
+    }
+
+    /// Query the taint on each byte of a range of RAM, skipping untainted bytes
+    pub fn ram_range(addr_range: Range<target_ptr_t>) -> Vec<Self> {
+        let start = addr_range.start;
+
+        addr_range
+            .filter_map(|addr| Self::ram_with_offset(addr, (addr - start) as u32))
+            .collect()
+    }
+
+    /// Query the taint on each byte of a register, skipping untainted bytes
+    pub fn reg(reg: impl Into<Reg>) -> Vec<Self> {
+        let reg = reg.into();
+
+        (0..std::mem::size_of::<target_ptr_t>())
+            .filter(|&offset| check_reg_num_byte(reg as c_int, offset))
+            .map(|offset| {
+                let mut query_result = QueryResult::empty();
+                TAINT.taint2_query_reg_full(reg as u32, offset as u32, &mut query_result);
+
+                Self::from_result(reg as u64, offset as u32, query_result)
+            })
+            .collect()
+    }
+}
+
+/// A set of taint queries made at a given instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaintRecord {
+    /// The program counter of the instruction
+    pub pc: target_ulong,
+
+    /// The address space the instruction was executed in
+    pub asid: target_ulong,
+
+    /// The guest instruction count at the time of the queries
+    pub instr: u64,
+
+    pub queries: Vec<TaintQuery>,
+}
+
+impl TaintRecord {
+    /// Create a record of queries at `pc`, taking the address space and instruction
+    /// count from the current state of the guest
+    pub fn new(cpu: &mut CPUState, pc: target_ulong, queries: Vec<TaintQuery>) -> Self {
+        Self {
+            pc,
+            asid: current_asid(cpu),
+            instr: rr_get_guest_instr_count(),
+            queries,
+        }
+    }
+}
+
+/// The format taint records are written in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// A JSON array of `tainted_instr` entries, as produced by `plog_reader`
+    Json,
+
+    /// CSV with a header of `pc,asid,instr,addr,offset,tcn,labels,cb_mask`, with one row per
+    /// tainted byte and labels separated by spaces
+    Csv,
+}
+
+/// A streaming writer of taint records
+pub struct TaintWriter<W: Write> {
+    out: W,
+    format: Format,
+    records: usize,
+}
+
+impl<W: Write> TaintWriter<W> {
+    pub fn new(out: W, format: Format) -> Self {
+        Self {
+            out,
+            format,
+            records: 0,
+        }
+    }
+
+    /// The number of records written so far
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Write a single record
+    pub fn write(&mut self, record: &TaintRecord) -> io::Result<()> {
+        match self.format {
+            Format::Json => self.write_json(record)?,
+            Format::Csv => self.write_csv(record)?,
+        }
+
+        self.records += 1;
+
+        Ok(())
+    }
+
+    fn write_json(&mut self, record: &TaintRecord) -> io::Result<()> {
+        let separator = if self.records == 0 { "[\n" } else { ",\n" };
+
+        write!(
+            self.out,
+            "{}{{\"pc\": \"{}\", \"instr\": \"{}\", \"asid\": \"{}\", \"taintedInstr\": {{\"taintQuery\": [",
+            separator, record.pc, record.instr, record.asid
+        )?;
+
+        for (i, query) in record.queries.iter().enumerate() {
+            let labels = query
+                .labels
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            write!(
+                self.out,
+                "{}{{\"ptr\": \"{}\", \"tcn\": {}, \"cbMask\": {}, \"offset\": {}, \"uniqueLabelSet\": {{\"ptr\": \"{}\", \"label\": [{}]}}}}",
+                if i == 0 { "" } else { ", " },
+                query.addr,
+                query.tcn,
+                query.cb_mask,
+                query.offset,
+                query.label_set,
+                labels
+            )?;
+        }
+
+        write!(self.out, "]}}}}")
+    }
+
+    fn write_csv(&mut self, record: &TaintRecord) -> io::Result<()> {
+        if self.records == 0 {
+            writeln!(self.out, "pc,asid,instr,addr,offset,tcn,labels,cb_mask")?;
+        }
+
+        for query in &record.queries {
+            let labels = query
+                .labels
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            writeln!(
+                self.out,
+                "{:#x},{:#x},{},{:#x},{},{},{},{:#04x}",
+                record.pc,
+                record.asid,
+                record.instr,
+                query.addr,
+                query.offset,
+                query.tcn,
+                labels,
+                query.cb_mask
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Finish writing, completing the output and returning the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == Format::Json {
+            let closing = if self.records == 0 { "[]\n" } else { "\n]\n" };
+            self.out.write_all(closing.as_bytes())?;
+        }
+
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,170 @@
+//! Symbolic taint analysis
+//!
+//! When symbolic tracking is enabled, bytes labeled using this module are treated as
+//! symbolic variables, and taint2 tracks the value of every byte derived from them as
+//! a symbolic (z3) expression over those variables. Each byte labeled becomes its own
+//! variable, named after its label.
+//!
+//! Expressions are returned in z3's textual (SMT-LIB) form, ready to be parsed by a
+//! solver.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::regs::Reg;
+//! use panda::taint::symbolic;
+//!
+//! // make each byte of RAX its own symbolic variable, labeled 0 to 7
+//! symbolic::label_reg(Reg::RAX, 0);
+//!
+//! // ...
+//!
+//! for (i, expr) in symbolic::query_reg(Reg::RBX).iter().enumerate() {
+//!     if let Some(expr) = expr {
+//!         println!("RBX[{}] = {}", i, expr);
+//!     }
+//! }
+//! ```
+use super::TAINT;
+use crate::api::regs::Reg;
+use crate::prelude::*;
+
+use std::ffi::CStr;
+use std::ops::Range;
+use std::os::raw::{c_char, c_int};
+use std::sync::Once;
+
+static SYM_ENABLE: Once = Once::new();
+
+/// Ensure symbolic tracking is enabled, enabling taint as well if needed
+///
+/// Note: the labeling functions in this module call this internally, so this only needs
+/// to be called directly in order to enable symbolic tracking earlier.
+pub fn enable() {
+    SYM_ENABLE.call_once(|| {
+        super::enable();
+        TAINT.taint2_enable_sym();
+    })
+}
+
+/// Check if symbolic tracking has been enabled by this plugin
+pub fn is_enabled() -> bool {
+    SYM_ENABLE.is_completed()
+}
+
+/// Take ownership of an expression string returned by taint2
+fn expr_from_raw(expr: *mut c_char) -> Option<String> {
+    if expr.is_null() {
+        return None;
+    }
+
+    let string = unsafe { CStr::from_ptr(expr) }
+        .to_string_lossy()
+        .into_owned();
+
+    unsafe {
+        glib_sys::g_free(expr as _);
+    }
+
+    Some(string)
+}
+
+/// Make a byte in RAM a symbolic variable, named after `label`.
+///
+/// **Note**: This will enable symbolic tracking if not already enabled.
+pub fn label_ram(addr: target_ptr_t, label: u32) {
+    enable();
+    TAINT.taint2_sym_label_ram(addr as u64, label);
+}
+
+/// Make each byte in a range of RAM a symbolic variable, labeled sequentially starting
+/// from `start_label`.
+///
+/// ## Example
+///
+/// ```no_run
+/// use panda::taint::symbolic;
+///
+/// // 4 symbolic bytes, labeled 10, 11, 12 and 13
+/// symbolic::label_ram_range(0xffff_0034..0xffff_0038, 10);
+/// ```
+///
+/// **Note**: This will enable symbolic tracking if not already enabled.
+pub fn label_ram_range(addr_range: Range<target_ptr_t>, start_label: u32) {
+    enable();
+    for (i, addr) in addr_range.enumerate() {
+        TAINT.taint2_sym_label_ram(addr as u64, start_label + i as u32);
+    }
+}
+
+/// Make each byte of a register a symbolic variable, labeled sequentially starting from
+/// `start_label` with the least significant byte.
+///
+/// **Note**: This will enable symbolic tracking if not already enabled.
+pub fn label_reg(register: impl Into<Reg>, start_label: u32) {
+    let reg = register.into() as c_int;
+    enable();
+    for i in 0..std::mem::size_of::<target_ptr_t>() {
+        TAINT.taint2_sym_label_reg(reg, i as c_int, start_label + i as u32);
+    }
+}
+
+/// Make a specific byte of a register a symbolic variable, named after `label`.
+///
+/// ## Panics
+///
+/// This function panics if `byte_offset` is greater than or equal to the size of the register.
+///
+/// **Note**: This will enable symbolic tracking if not already enabled.
+pub fn label_reg_byte(register: impl Into<Reg>, byte_offset: usize, label: u32) {
+    assert!(byte_offset < std::mem::size_of::<target_ptr_t>());
+
+    let reg = register.into() as c_int;
+    enable();
+    TAINT.taint2_sym_label_reg(reg, byte_offset as c_int, label);
+}
+
+/// Get the symbolic expression for a byte in RAM, or `None` if the byte isn't symbolic.
+///
+/// **Note:** If symbolic tracking has not been enabled by **your** plugin, this will
+/// return `None`
+pub fn query_ram(addr: target_ptr_t) -> Option<String> {
+    if !is_enabled() {
+        return None;
+    }
+
+    expr_from_raw(TAINT.taint2_sym_query_ram(addr as u64))
+}
+
+/// Get the symbolic expression for each byte in a range of RAM, with `None` for bytes
+/// which aren't symbolic.
+pub fn query_ram_range(addr_range: Range<target_ptr_t>) -> Vec<Option<String>> {
+    addr_range.map(query_ram).collect()
+}
+
+/// Get the symbolic expression for each byte of a register, starting from the least
+/// significant byte, with `None` for bytes which aren't symbolic.
+pub fn query_reg(register: impl Into<Reg>) -> Vec<Option<String>> {
+    let reg = register.into();
+
+    (0..std::mem::size_of::<target_ptr_t>())
+        .map(|i| query_reg_byte(reg, i))
+        .collect()
+}
+
+/// Get the symbolic expression for a specific byte of a register, or `None` if the byte
+/// isn't symbolic.
+///
+/// ## Panics
+///
+/// This function panics if `byte_offset` is greater than or equal to the size of the register.
+pub fn query_reg_byte(register: impl Into<Reg>, byte_offset: usize) -> Option<String> {
+    assert!(byte_offset < std::mem::size_of::<target_ptr_t>());
+
+    if !is_enabled() {
+        return None;
+    }
+
+    let reg = register.into() as u32;
+    expr_from_raw(TAINT.taint2_sym_query_reg(reg, byte_offset as u32))
+}
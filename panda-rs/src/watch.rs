@@ -0,0 +1,412 @@
+//! Memory watches on symbols in shared libraries
+//!
+//! A watch is described by a library-relative expression rather than an address, such
+//! as `libssl.so:ssl_session`, and is resolved separately in every process which maps
+//! the library. Watches are re-armed whenever a library is mapped, so they keep working
+//! across ASLR, process restarts and libraries loaded after the watch was added.
+//!
+//! Expressions take the form `library:location`, where `library` matches a mapping
+//! whose file name is either exactly `library` or starts with `library.` (so
+//! `libssl.so` matches `libssl.so.1.1`), and `location` is one of:
+//!
+//! * `symbol` - a symbol exported by the library, watched over its whole size
+//! * `symbol+0x10` - an offset from an exported symbol
+//! * `0x1234` - an offset from the start of the library, for symbols which are not
+//! exported
+//!
+//! Exported symbols are looked up in the dynamic symbol table of the library as
+//! loaded in guest memory, so the library must be an ELF shared object.
+//!
+//! Requires the `osi` and `hooks2` plugins. Enabling a watch turns on memory
+//! callbacks, which slows down execution.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use panda::watch;
+//! use panda::PluginHandle;
+//!
+//! #[panda::init]
+//! fn init(_: &mut PluginHandle) {
+//!     watch::on_symbol_write("libssl.so:ssl_session", |_, access| {
+//!         println!(
+//!             "asid {:#x} wrote {:02x?} to ssl_session+{:#x}",
+//!             access.asid, access.data, access.offset
+//!         );
+//!     })
+//!     .unwrap();
+//! }
+//! ```
+
+use crate::plugins::hooks2::Hooks2Callbacks;
+use crate::plugins::osi::OSI;
+use crate::prelude::*;
+use crate::{current_asid, memcb, Callback, PppCallback};
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::c_char;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, thiserror::Error)]
+pub enum WatchError {
+    #[error("Invalid watch expression {0:?}, expected `library:symbol` or `library:offset`")]
+    InvalidExpression(String),
+}
+
+/// Where in a library a watch is placed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// An offset from an exported symbol
+    Symbol { name: String, offset: target_ulong },
+
+    /// An offset from the start of the library
+    Offset(target_ulong),
+}
+
+/// A parsed watch expression, such as `libssl.so:ssl_session`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolExpr {
+    pub library: String,
+    pub location: Location,
+}
+
+impl SymbolExpr {
+    fn matches_library(&self, file_name: &str) -> bool {
+        file_name == self.library
+            || (file_name.starts_with(&self.library)
+                && file_name[self.library.len()..].starts_with('.'))
+    }
+}
+
+fn parse_offset(offset: &str) -> Option<target_ulong> {
+    match offset.strip_prefix("0x") {
+        Some(hex) => target_ulong::from_str_radix(hex, 16).ok(),
+        None => offset.parse().ok(),
+    }
+}
+
+impl FromStr for SymbolExpr {
+    type Err = WatchError;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let invalid = || WatchError::InvalidExpression(expr.to_owned());
+
+        let (library, location) = expr.rsplit_once(':').ok_or_else(invalid)?;
+        if library.is_empty() || location.is_empty() {
+            return Err(invalid());
+        }
+
+        let location = if location.starts_with(|c: char| c.is_ascii_digit()) {
+            Location::Offset(parse_offset(location).ok_or_else(invalid)?)
+        } else {
+            let (name, offset) = match location.split_once('+') {
+                Some((name, offset)) => (name, parse_offset(offset).ok_or_else(invalid)?),
+                None => (location, 0),
+            };
+
+            Location::Symbol {
+                name: name.to_owned(),
+                offset,
+            }
+        };
+
+        Ok(SymbolExpr {
+            library: library.to_owned(),
+            location,
+        })
+    }
+}
+
+impl fmt::Display for SymbolExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Location::Symbol { name, offset: 0 } => write!(f, "{}:{}", self.library, name),
+            Location::Symbol { name, offset } => {
+                write!(f, "{}:{}+{:#x}", self.library, name, offset)
+            }
+            Location::Offset(offset) => write!(f, "{}:{:#x}", self.library, offset),
+        }
+    }
+}
+
+/// An access to a watched symbol
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolAccess<'a> {
+    /// The watch expression which was triggered
+    pub expr: &'a SymbolExpr,
+
+    /// The address space the access was made in
+    pub asid: target_ulong,
+
+    /// The pc of the instruction making the access
+    pub pc: target_ptr_t,
+
+    /// The address accessed
+    pub addr: target_ptr_t,
+
+    /// The offset of the access from the start of the watched range
+    pub offset: target_ptr_t,
+
+    /// The data read or written
+    pub data: &'a [u8],
+}
+
+type WatchCallback = Box<dyn FnMut(&mut CPUState, &SymbolAccess) + Send + 'static>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+struct Watch {
+    expr: Arc<SymbolExpr>,
+    access: Access,
+    callback: Arc<Mutex<WatchCallback>>,
+}
+
+/// An access matching a watch, whose callback is run once the watch state is unlocked
+struct Hit {
+    expr: Arc<SymbolExpr>,
+    callback: Arc<Mutex<WatchCallback>>,
+    offset: target_ptr_t,
+}
+
+/// A watch resolved to an address range within a single address space
+struct Armed {
+    start: target_ptr_t,
+    end: target_ptr_t,
+    watch: usize,
+}
+
+#[derive(Default)]
+struct WatchState {
+    watches: Vec<Watch>,
+
+    /// Watches resolved in each address space seen so far. Address spaces missing from
+    /// this map are resolved on their next memory access.
+    armed: HashMap<target_ulong, Vec<Armed>>,
+}
+
+impl WatchState {
+    fn arm(&mut self, cpu: &mut CPUState, asid: target_ulong) {
+        let mut armed = Vec::new();
+
+        if let Some(process) = OSI.get_current_process(cpu) {
+            let mut process = *process;
+            let mappings = OSI.get_mappings(cpu, &mut process);
+            let mappings: Vec<_> = if mappings.is_null() {
+                Vec::new()
+            } else {
+                mappings
+                    .iter()
+                    .map(|mapping| (mapping.base, c_string(mapping.name)))
+                    .collect()
+            };
+
+            for (i, watch) in self.watches.iter().enumerate() {
+                let base = mappings
+                    .iter()
+                    .filter(|(_, name)| watch.expr.matches_library(name))
+                    .map(|(base, _)| *base)
+                    .min();
+
+                let range = base.and_then(|base| resolve(cpu, base, &watch.expr.location));
+                if let Some((start, size)) = range {
+                    armed.push(Armed {
+                        start,
+                        end: start.saturating_add(size),
+                        watch: i,
+                    });
+                }
+            }
+        }
+
+        self.armed.insert(asid, armed);
+    }
+
+    /// Find the watches matching an access of `size` bytes at `addr`
+    fn hits(
+        &mut self,
+        cpu: &mut CPUState,
+        asid: target_ulong,
+        access: Access,
+        addr: target_ptr_t,
+        size: usize,
+    ) -> Vec<Hit> {
+        if !self.armed.contains_key(&asid) {
+            self.arm(cpu, asid);
+        }
+
+        let end = addr.saturating_add(size as target_ptr_t);
+
+        self.armed[&asid]
+            .iter()
+            .filter(|range| addr < range.end && end > range.start)
+            .map(|range| (&self.watches[range.watch], range.start))
+            .filter(|(watch, _)| watch.access == access)
+            .map(|(watch, start)| Hit {
+                expr: Arc::clone(&watch.expr),
+                callback: Arc::clone(&watch.callback),
+                offset: addr.wrapping_sub(start),
+            })
+            .collect()
+    }
+}
+
+fn on_access(
+    cpu: &mut CPUState,
+    access: Access,
+    pc: target_ptr_t,
+    addr: target_ptr_t,
+    size: usize,
+    buf: *mut u8,
+) {
+    let asid = current_asid(cpu);
+    let hits = STATE.lock().unwrap().hits(cpu, asid, access, addr, size);
+
+    for hit in hits {
+        let data = unsafe { std::slice::from_raw_parts(buf, size) };
+        let event = SymbolAccess {
+            expr: &hit.expr,
+            asid,
+            pc,
+            addr,
+            offset: hit.offset,
+            data,
+        };
+
+        (hit.callback.lock().unwrap())(cpu, &event);
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<WatchState> = Mutex::new(WatchState::default());
+    static ref CALLBACKS: (Callback, Callback, PppCallback, PppCallback) = install_callbacks();
+}
+
+fn install_callbacks() -> (Callback, Callback, PppCallback, PppCallback) {
+    // the watchpoint callbacks may access guest memory themselves
+    let read = Callback::new().skip_reentrant();
+    let write = Callback::new().skip_reentrant();
+    let mmap_updated = PppCallback::new();
+    let process_end = PppCallback::new();
+
+    memcb::demand_all().forget();
+
+    read.virt_mem_after_read(|cpu, pc, addr, size, buf| {
+        on_access(cpu, Access::Read, pc, addr, size, buf);
+    });
+
+    write.virt_mem_after_write(|cpu, pc, addr, size, buf| {
+        on_access(cpu, Access::Write, pc, addr, size, buf);
+    });
+
+    // A library was (re)mapped, so resolve the watches of this address space again on
+    // its next memory access
+    mmap_updated.on_mmap_updated(|cpu, _, _, _| {
+        let asid = current_asid(cpu);
+        STATE.lock().unwrap().armed.remove(&asid);
+    });
+
+    process_end.on_process_end(|_, _, asid, _| {
+        STATE.lock().unwrap().armed.remove(&asid);
+    });
+
+    (read, write, mmap_updated, process_end)
+}
+
+fn add_watch(
+    expr: &str,
+    access: Access,
+    callback: impl FnMut(&mut CPUState, &SymbolAccess) + Send + 'static,
+) -> Result<(), WatchError> {
+    let expr = expr.parse()?;
+
+    lazy_static::initialize(&CALLBACKS);
+
+    let mut state = STATE.lock().unwrap();
+    state.watches.push(Watch {
+        expr: Arc::new(expr),
+        access,
+        callback: Arc::new(Mutex::new(Box::new(callback))),
+    });
+
+    // Resolve the new watch in every address space
+    state.armed.clear();
+
+    Ok(())
+}
+
+/// Register a callback to be run whenever the given symbol is written to, in any
+/// process which maps its library. See the [module-level docs](self) for the format of
+/// the expression.
+pub fn on_symbol_write(
+    expr: &str,
+    callback: impl FnMut(&mut CPUState, &SymbolAccess) + Send + 'static,
+) -> Result<(), WatchError> {
+    add_watch(expr, Access::Write, callback)
+}
+
+/// Register a callback to be run whenever the given symbol is read from, in any process
+/// which maps its library. See the [module-level docs](self) for the format of the
+/// expression.
+pub fn on_symbol_read(
+    expr: &str,
+    callback: impl FnMut(&mut CPUState, &SymbolAccess) + Send + 'static,
+) -> Result<(), WatchError> {
+    add_watch(expr, Access::Read, callback)
+}
+
+/// Get the address ranges the watches are currently armed at in the given address
+/// space, along with their expressions. Address spaces are only resolved once they
+/// make a memory access.
+pub fn armed(asid: target_ulong) -> Vec<(SymbolExpr, std::ops::Range<target_ptr_t>)> {
+    let state = STATE.lock().unwrap();
+
+    state
+        .armed
+        .get(&asid)
+        .into_iter()
+        .flatten()
+        .map(|armed| {
+            (
+                SymbolExpr::clone(&state.watches[armed.watch].expr),
+                armed.start..armed.end,
+            )
+        })
+        .collect()
+}
+
+fn c_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Resolve a location within the library loaded at `base` to an address range
+fn resolve(
+    cpu: &mut CPUState,
+    base: target_ptr_t,
+    location: &Location,
+) -> Option<(target_ptr_t, target_ptr_t)> {
+    match location {
+        Location::Offset(offset) => Some((base.wrapping_add(*offset as target_ptr_t), 1)),
+        Location::Symbol { name, offset } => {
+            let (addr, size) = crate::elf::find_symbol(cpu, base, name)?;
+
+            if *offset == 0 {
+                Some((addr, size.max(1)))
+            } else {
+                Some((addr.wrapping_add(*offset as target_ptr_t), 1))
+            }
+        }
+    }
+}
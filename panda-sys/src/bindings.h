@@ -2,3 +2,11 @@
 #include "panda/common.h"
 #include "panda/plog.h"
 #include "panda/panda_api.h"
+#include "monitor/monitor.h"
+#include "qemu/main-loop.h"
+#include "qemu/timer.h"
+#include "sysemu/cpus.h"
+#include "exec/ioport.h"
+#include "panda/checkpoint.h"
+#include "exec/cputlb.h"
+#include "exec/gdbstub.h"
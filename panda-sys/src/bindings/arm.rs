@@ -38112,6 +38112,57 @@ extern "C" {
         flags: ::std::os::raw::c_int,
     );
 }
+extern "C" {
+    pub fn monitor_printf(mon: *mut Monitor, fmt: *const ::std::os::raw::c_char, ...);
+}
+extern "C" {
+    pub static mut use_icount: ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn cpu_enable_ticks();
+}
+extern "C" {
+    pub fn cpu_disable_ticks();
+}
+extern "C" {
+    pub fn qemu_mutex_iothread_locked() -> bool;
+}
+extern "C" {
+    pub fn qemu_mutex_lock_iothread();
+}
+extern "C" {
+    pub fn qemu_mutex_unlock_iothread();
+}
+extern "C" {
+    pub fn cpu_outb(addr: u32, val: u8);
+}
+extern "C" {
+    pub fn cpu_inb(addr: u32) -> u8;
+}
+pub const QEMUClockType_QEMU_CLOCK_REALTIME: QEMUClockType = 0;
+pub const QEMUClockType_QEMU_CLOCK_VIRTUAL: QEMUClockType = 1;
+pub const QEMUClockType_QEMU_CLOCK_HOST: QEMUClockType = 2;
+pub const QEMUClockType_QEMU_CLOCK_VIRTUAL_RT: QEMUClockType = 3;
+pub const QEMUClockType_QEMU_CLOCK_MAX: QEMUClockType = 4;
+pub type QEMUClockType = ::std::os::raw::c_uint;
+extern "C" {
+    pub fn qemu_clock_get_ns(type_: QEMUClockType) -> i64;
+}
+extern "C" {
+    pub fn panda_checkpoint() -> *mut ::std::os::raw::c_void;
+}
+extern "C" {
+    pub fn panda_restore(opaque: *mut ::std::os::raw::c_void);
+}
+extern "C" {
+    pub fn get_num_checkpoints() -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn gdbserver_start(port: *const ::std::os::raw::c_char) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub static mut tlb_flush_count: ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn lookup_symbol(orig_addr: target_ulong) -> *const ::std::os::raw::c_char;
 }
@@ -8,6 +8,9 @@ use std::mem::{size_of, transmute, MaybeUninit};
 const READ_CHUNK_SIZE: target_ptr_t = 0x10;
 
 impl CPUState {
+    #[deprecated(
+        note = "use `panda::ext::CpuExt::read_mem`, which returns an error rather than panicking"
+    )]
     pub fn mem_read(&mut self, addr: target_ulong, len: usize) -> Vec<u8> {
         let mut temp = vec![0; len];
 
@@ -26,6 +29,9 @@ impl CPUState {
         temp
     }
 
+    #[deprecated(
+        note = "use `panda::ext::CpuExt::write_mem`, which returns an error rather than panicking"
+    )]
     pub fn mem_write(&mut self, addr: target_ulong, data: &[u8]) {
         unsafe {
             if panda_virtual_memory_write_external(
@@ -40,6 +46,7 @@ impl CPUState {
         }
     }
 
+    #[deprecated(note = "use `panda::ext::CpuExt::read_mem`")]
     pub fn try_mem_read(&mut self, addr: target_ulong, len: usize) -> Option<Vec<u8>> {
         let mut temp = vec![0; len];
 
@@ -59,6 +66,7 @@ impl CPUState {
         }
     }
 
+    #[deprecated(note = "use `panda::ext::PhysAddr::read`")]
     pub fn try_mem_read_phys(&mut self, addr: target_ptr_t, len: usize) -> Option<Vec<u8>> {
         let mut temp = vec![0; len];
 
@@ -71,6 +79,9 @@ impl CPUState {
         }
     }
 
+    #[deprecated(
+        note = "use `panda::mem::read_guest_type`, which returns an error rather than panicking"
+    )]
     pub fn mem_read_val<T: Sized>(&mut self, addr: target_ulong) -> T {
         let mut temp = MaybeUninit::uninit();
 
@@ -89,6 +100,7 @@ impl CPUState {
         }
     }
 
+    #[deprecated(note = "use `panda::ext::CpuExt::read_string`, which stops at unreadable memory")]
     pub fn mem_read_string(&mut self, mut addr: target_ptr_t) -> String {
         let mut buf = vec![];
         let mut temp = [0; READ_CHUNK_SIZE as usize];
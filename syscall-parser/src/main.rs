@@ -145,6 +145,7 @@ const ARCHES: &[(&str, &str)] = &[
     ("mips", "mips"),
     ("mipsel", "mips"),
     ("mips64", "mips"),
+    ("mips64el", "mips"),
 ];
 
 fn main() {